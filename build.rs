@@ -1,26 +1,81 @@
+use std::env;
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-const GLSLC_PATH: &str = "C:/VulkanSDK/1.3.250.0/Bin/glslc.exe";
+// Compiler of the Vulkan SDK, looked up in `VULKAN_SDK` first and in `PATH` otherwise
+const GLSLC: &str = if cfg!(windows) { "glslc.exe" } else { "glslc" };
+
+fn find_glslc() -> Option<PathBuf> {
+    if let Some(sdk) = env::var_os("VULKAN_SDK") {
+        let path = Path::new(&sdk).join("bin").join(GLSLC);
+        if path.is_file() {
+            return Some(path);
+        }
+    }
+    Command::new(GLSLC)
+        .arg("--version")
+        .output()
+        .ok()
+        .map(|_| PathBuf::from(GLSLC))
+}
 
 fn main() {
+    println!("cargo:rerun-if-changed=src/shaders");
+    println!("cargo:rerun-if-env-changed=VULKAN_SDK");
+
+    let glslc = find_glslc();
+    if glslc.is_none() {
+        println!(
+            "cargo:warning=glslc wasn't found, the shaders aren't recompiled and the SPIR-V of \
+             src/spirv is used as it is"
+        );
+    }
+
     let paths = fs::read_dir("./src/shaders").unwrap();
     for shader in paths {
         let path = shader.unwrap().path();
+        // Code shared by several shaders, included by them rather than compiled on its own
+        if path.extension().and_then(|ext| ext.to_str()) == Some("glsl") {
+            continue;
+        }
         let file_name = path.file_stem().unwrap();
         let output_path: String = format!("./src/spirv/{}.spv", file_name.to_str().unwrap());
 
-        let output = Command::new(GLSLC_PATH)
-            .arg(path)
+        let Some(glslc) = &glslc else {
+            // The compiled shaders are committed along with their source
+            if !Path::new(&output_path).is_file() {
+                panic!(
+                    "{output_path} is missing and glslc wasn't found to compile {}, install the \
+                     Vulkan SDK and set VULKAN_SDK or add glslc to PATH",
+                    path.display()
+                );
+            }
+            continue;
+        };
+
+        let mut command = Command::new(glslc);
+        // Task, mesh, ray tracing and ray query shaders need SPIR-V 1.4, part of Vulkan 1.3
+        if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("task" | "mesh" | "rgen" | "rmiss" | "rchit")
+        ) || file_name.to_str().unwrap().ends_with("_ray_query")
+        {
+            command.arg("--target-env=vulkan1.3");
+        }
+        let output = command
+            .arg(&path)
             .arg("-o")
             .arg(output_path)
             .output()
-            .unwrap();
+            .unwrap_or_else(|err| panic!("running {}: {err}", glslc.display()));
 
-        if !output.stderr.is_empty() {
-            println!("############## SHADER COMPILATION FAILED ##############");
-            println!("{}", String::from_utf8(output.stderr).unwrap());
-            println!("#######################################################");
+        if !output.status.success() {
+            panic!(
+                "compiling {}:\n{}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            );
         }
     }
 }
@@ -0,0 +1,408 @@
+use ash::{khr::acceleration_structure, vk, Device, Instance};
+
+use crate::{
+    garbage_collector::Garbage, handle_registry, AppResult, Application, BufferHolder, SyncPool,
+    Vertex,
+};
+
+/// Acceleration structure along with the buffer holding it
+pub(crate) struct Accel {
+    pub(crate) handle: vk::AccelerationStructureKHR,
+    pub(crate) buffer: BufferHolder,
+    /// Referenced by the instances of a top-level acceleration structure
+    pub(crate) address: vk::DeviceAddress,
+    ty: vk::AccelerationStructureTypeKHR,
+    flags: vk::BuildAccelerationStructureFlagsKHR,
+}
+
+/// Scratch memory of the builds and refits of an acceleration structure
+pub(crate) struct Scratch {
+    buffer: BufferHolder,
+    address: vk::DeviceAddress,
+}
+
+impl Scratch {
+    pub(crate) fn into_garbage(self) -> Garbage {
+        Garbage::Buffer(self.buffer.buffer, self.buffer.memory)
+    }
+}
+
+/// Creates, builds, refits and compacts acceleration structures.
+///
+/// A structure is sized for a geometry and a primitive count, then built from any geometry of
+/// the same kind with at most as many primitives. A structure created with `ALLOW_UPDATE` can be
+/// refitted in place instead, much faster than a build but keeping the primitive count and
+/// tracing slower as the primitives move away from where they were built.
+pub(crate) struct AccelBuilder {
+    loader: acceleration_structure::Device,
+    scratch_alignment: u64,
+}
+
+impl AccelBuilder {
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+    ) -> Self {
+        let mut accel_properties = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
+        let mut properties2 =
+            vk::PhysicalDeviceProperties2::default().push_next(&mut accel_properties);
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+
+        Self {
+            loader: acceleration_structure::Device::new(instance, device),
+            scratch_alignment: accel_properties.min_acceleration_structure_scratch_offset_alignment
+                as u64,
+        }
+    }
+
+    /// Creates an acceleration structure sized for `primitive_count` primitives of `geometry`,
+    /// along with the scratch memory of its builds and, with `ALLOW_UPDATE`, of its refits
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn create(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        ty: vk::AccelerationStructureTypeKHR,
+        flags: vk::BuildAccelerationStructureFlagsKHR,
+        geometry: &vk::AccelerationStructureGeometryKHR,
+        primitive_count: u32,
+    ) -> AppResult<(Accel, Scratch)> {
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR {
+            ty,
+            flags,
+            mode: vk::BuildAccelerationStructureModeKHR::BUILD,
+            geometry_count: 1,
+            p_geometries: geometry,
+            ..Default::default()
+        };
+        let mut sizes = vk::AccelerationStructureBuildSizesInfoKHR::default();
+        unsafe {
+            self.loader.get_acceleration_structure_build_sizes(
+                vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                &build_info,
+                &[primitive_count],
+                &mut sizes,
+            )
+        };
+
+        let accel = self.create_storage(
+            instance,
+            device,
+            physical_device,
+            ty,
+            flags,
+            sizes.acceleration_structure_size,
+        )?;
+
+        let scratch_size = if flags.contains(vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE) {
+            sizes.build_scratch_size.max(sizes.update_scratch_size)
+        } else {
+            sizes.build_scratch_size
+        };
+        let buffer = Application::create_buffer(
+            instance,
+            device,
+            physical_device,
+            scratch_size + self.scratch_alignment,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let address = align_up(
+            buffer_device_address(device, buffer.buffer),
+            self.scratch_alignment,
+        );
+
+        Ok((accel, Scratch { buffer, address }))
+    }
+
+    fn create_storage(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        ty: vk::AccelerationStructureTypeKHR,
+        flags: vk::BuildAccelerationStructureFlagsKHR,
+        size: u64,
+    ) -> AppResult<Accel> {
+        let buffer = Application::create_buffer(
+            instance,
+            device,
+            physical_device,
+            size,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let create_info = vk::AccelerationStructureCreateInfoKHR {
+            buffer: buffer.buffer,
+            size,
+            ty,
+            ..Default::default()
+        };
+        let handle = unsafe {
+            self.loader
+                .create_acceleration_structure(&create_info, None)?
+        };
+        handle_registry::register(handle);
+
+        let address = unsafe {
+            self.loader.get_acceleration_structure_device_address(
+                &vk::AccelerationStructureDeviceAddressInfoKHR {
+                    acceleration_structure: handle,
+                    ..Default::default()
+                },
+            )
+        };
+
+        Ok(Accel {
+            handle,
+            buffer,
+            address,
+            ty,
+            flags,
+        })
+    }
+
+    /// Records a build of `accel` from the first `primitive_count` primitives of `geometry`, or
+    /// with `refit` an update of its last build, which must have had as many primitives. The
+    /// following uses of the structure must wait for the build.
+    pub(crate) fn record_build(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        accel: &Accel,
+        scratch: &Scratch,
+        geometry: &vk::AccelerationStructureGeometryKHR,
+        primitive_count: u32,
+        refit: bool,
+    ) {
+        let (mode, src_acceleration_structure) = if refit {
+            (vk::BuildAccelerationStructureModeKHR::UPDATE, accel.handle)
+        } else {
+            (
+                vk::BuildAccelerationStructureModeKHR::BUILD,
+                vk::AccelerationStructureKHR::null(),
+            )
+        };
+        let build_info = vk::AccelerationStructureBuildGeometryInfoKHR {
+            ty: accel.ty,
+            flags: accel.flags,
+            mode,
+            src_acceleration_structure,
+            dst_acceleration_structure: accel.handle,
+            geometry_count: 1,
+            p_geometries: geometry,
+            scratch_data: vk::DeviceOrHostAddressKHR {
+                device_address: scratch.address,
+            },
+            ..Default::default()
+        };
+        let range = vk::AccelerationStructureBuildRangeInfoKHR {
+            primitive_count,
+            ..Default::default()
+        };
+
+        unsafe {
+            self.loader.cmd_build_acceleration_structures(
+                command_buffer,
+                &[build_info],
+                &[&[range]],
+            )
+        };
+    }
+
+    /// Builds a bottom-level acceleration structure from `geometry` then copies it to a
+    /// compacted one, waiting for both. The compacted structure can't be refitted.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn build_compacted(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        geometry: &vk::AccelerationStructureGeometryKHR,
+        primitive_count: u32,
+    ) -> AppResult<Accel> {
+        let (accel, scratch) = self.create(
+            instance,
+            device,
+            physical_device,
+            vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE
+                | vk::BuildAccelerationStructureFlagsKHR::ALLOW_COMPACTION,
+            geometry,
+            primitive_count,
+        )?;
+
+        let pool_info = vk::QueryPoolCreateInfo {
+            query_type: vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+            query_count: 1,
+            ..Default::default()
+        };
+        let query_pool = unsafe { device.create_query_pool(&pool_info, None)? };
+        handle_registry::register(query_pool);
+
+        // The compacted size is only known once the build is done
+        let build_barrier = vk::MemoryBarrier {
+            src_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+            dst_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+            ..Default::default()
+        };
+        let command_buffer = Application::begin_singe_time_command(device, command_pool)?;
+        unsafe {
+            device.cmd_reset_query_pool(command_buffer, query_pool, 0, 1);
+            self.record_build(
+                command_buffer,
+                &accel,
+                &scratch,
+                geometry,
+                primitive_count,
+                false,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::DependencyFlags::empty(),
+                &[build_barrier],
+                &[],
+                &[],
+            );
+            self.loader.cmd_write_acceleration_structures_properties(
+                command_buffer,
+                &[accel.handle],
+                vk::QueryType::ACCELERATION_STRUCTURE_COMPACTED_SIZE_KHR,
+                query_pool,
+                0,
+            );
+        }
+        Application::end_single_time_command(
+            device,
+            queue,
+            command_pool,
+            sync_pool,
+            command_buffer,
+        )?;
+
+        let mut compacted_size = [0u64];
+        let result = unsafe {
+            device.get_query_pool_results(
+                query_pool,
+                0,
+                &mut compacted_size,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )
+        };
+        unsafe {
+            handle_registry::unregister(query_pool);
+            device.destroy_query_pool(query_pool, None);
+            scratch.into_garbage().destroy(device);
+        }
+        result?;
+
+        let compacted = self.create_storage(
+            instance,
+            device,
+            physical_device,
+            accel.ty,
+            accel.flags,
+            compacted_size[0],
+        )?;
+        let copy_info = vk::CopyAccelerationStructureInfoKHR {
+            src: accel.handle,
+            dst: compacted.handle,
+            mode: vk::CopyAccelerationStructureModeKHR::COMPACT,
+            ..Default::default()
+        };
+        let command_buffer = Application::begin_singe_time_command(device, command_pool)?;
+        unsafe {
+            self.loader
+                .cmd_copy_acceleration_structure(command_buffer, &copy_info)
+        };
+        Application::end_single_time_command(
+            device,
+            queue,
+            command_pool,
+            sync_pool,
+            command_buffer,
+        )?;
+        for garbage in self.garbage(accel) {
+            unsafe { garbage.destroy(device) };
+        }
+
+        Ok(compacted)
+    }
+
+    /// Resources of an acceleration structure, to be retired or destroyed
+    pub(crate) fn garbage(&self, accel: Accel) -> [Garbage; 2] {
+        [
+            Garbage::AccelerationStructure(self.loader.clone(), accel.handle),
+            Garbage::Buffer(accel.buffer.buffer, accel.buffer.memory),
+        ]
+    }
+}
+
+/// Triangles of a mesh, whose meshes lie in the XY plane so the positions are read as 2D.
+/// `vertex_count` bounds the vertices the indices can reference.
+pub(crate) fn triangles_geometry(
+    vertices: vk::DeviceAddress,
+    vertex_count: usize,
+    indices: vk::DeviceAddress,
+) -> vk::AccelerationStructureGeometryKHR<'static> {
+    vk::AccelerationStructureGeometryKHR {
+        geometry_type: vk::GeometryTypeKHR::TRIANGLES,
+        geometry: vk::AccelerationStructureGeometryDataKHR {
+            triangles: vk::AccelerationStructureGeometryTrianglesDataKHR {
+                vertex_format: vk::Format::R32G32_SFLOAT,
+                vertex_data: vk::DeviceOrHostAddressConstKHR {
+                    device_address: vertices,
+                },
+                vertex_stride: Vertex::STRIDE as u64,
+                max_vertex: vertex_count.saturating_sub(1) as u32,
+                index_type: vk::IndexType::UINT32,
+                index_data: vk::DeviceOrHostAddressConstKHR {
+                    device_address: indices,
+                },
+                ..Default::default()
+            },
+        },
+        flags: vk::GeometryFlagsKHR::OPAQUE,
+        ..Default::default()
+    }
+}
+
+/// Geometry of a top-level acceleration structure, made of the instances at `address`
+pub(crate) fn instances_geometry(
+    address: vk::DeviceAddress,
+) -> vk::AccelerationStructureGeometryKHR<'static> {
+    vk::AccelerationStructureGeometryKHR {
+        geometry_type: vk::GeometryTypeKHR::INSTANCES,
+        geometry: vk::AccelerationStructureGeometryDataKHR {
+            instances: vk::AccelerationStructureGeometryInstancesDataKHR {
+                array_of_pointers: vk::FALSE,
+                data: vk::DeviceOrHostAddressConstKHR {
+                    device_address: address,
+                },
+                ..Default::default()
+            },
+        },
+        flags: vk::GeometryFlagsKHR::OPAQUE,
+        ..Default::default()
+    }
+}
+
+pub(crate) fn buffer_device_address(device: &Device, buffer: vk::Buffer) -> vk::DeviceAddress {
+    unsafe {
+        device.get_buffer_device_address(&vk::BufferDeviceAddressInfo {
+            buffer,
+            ..Default::default()
+        })
+    }
+}
+
+pub(crate) fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
@@ -0,0 +1,411 @@
+use cgmath::{InnerSpace, VectorSpace};
+
+use crate::{
+    draw_list::{DrawItemId, DrawList, MeshHandle},
+    geometry::{Mat4, Quat, Vec3},
+    morph_targets::MorphTargets,
+};
+
+/// Transform of an animated node, applied as its scale, then its rotation, then its translation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NodeTransform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for NodeTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Quat::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl NodeTransform {
+    /// Interpolates linearly towards `other`, the rotation along the shortest arc
+    pub fn blend(&self, other: &NodeTransform, amount: f32) -> NodeTransform {
+        NodeTransform {
+            translation: self.translation.lerp(other.translation, amount),
+            rotation: self.rotation.slerp(other.rotation, amount).normalize(),
+            scale: self.scale.lerp(other.scale, amount),
+        }
+    }
+
+    pub fn to_matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.translation)
+            * Mat4::from(self.rotation)
+            * Mat4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+/// Transform of a node `time` seconds into a clip
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub transform: NodeTransform,
+}
+
+/// Weights of the morph targets of a mesh `time` seconds into a clip, see `MorphTarget`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WeightKeyframe {
+    pub time: f32,
+    pub weights: Vec<f32>,
+}
+
+/// Keyframed animation of a set of nodes, with a track of keyframes sorted by time per node.
+/// A node is interpolated linearly between the keyframes around the sampled time, and holds its
+/// first and last keyframes before and after them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AnimationClip {
+    pub tracks: Vec<Vec<Keyframe>>,
+    /// Keyframes of the morph target weights, sorted by time and interpolated like the tracks
+    pub weights: Vec<WeightKeyframe>,
+}
+
+impl AnimationClip {
+    /// Time of the last keyframe of the clip
+    pub fn duration(&self) -> f32 {
+        self.tracks
+            .iter()
+            .filter_map(|track| track.last())
+            .map(|keyframe| keyframe.time)
+            .chain(self.weights.last().map(|keyframe| keyframe.time))
+            .fold(0.0, f32::max)
+    }
+
+    /// Transform of `node` at `time` seconds into the clip, or `None` without a track or
+    /// keyframe for the node
+    pub fn sample(&self, node: usize, time: f32) -> Option<NodeTransform> {
+        let track = self.tracks.get(node)?;
+        let next = track.partition_point(|keyframe| keyframe.time <= time);
+        let transform = match (
+            next.checked_sub(1).map(|previous| &track[previous]),
+            track.get(next),
+        ) {
+            (Some(previous), Some(next)) => {
+                let amount = (time - previous.time) / (next.time - previous.time);
+                previous.transform.blend(&next.transform, amount)
+            }
+            (Some(keyframe), None) | (None, Some(keyframe)) => keyframe.transform,
+            (None, None) => return None,
+        };
+
+        Some(transform)
+    }
+
+    /// Morph target weights at `time` seconds into the clip, or `None` without weight keyframes
+    pub fn sample_weights(&self, time: f32) -> Option<Vec<f32>> {
+        let next = self
+            .weights
+            .partition_point(|keyframe| keyframe.time <= time);
+        let weights = match (
+            next.checked_sub(1).map(|previous| &self.weights[previous]),
+            self.weights.get(next),
+        ) {
+            (Some(previous), Some(next)) => {
+                let amount = (time - previous.time) / (next.time - previous.time);
+                blend_weights(&previous.weights, &next.weights, amount)
+            }
+            (Some(keyframe), None) | (None, Some(keyframe)) => keyframe.weights.clone(),
+            (None, None) => return None,
+        };
+
+        Some(weights)
+    }
+}
+
+// Interpolates the weights linearly, the missing ones being zero
+fn blend_weights(from: &[f32], to: &[f32], amount: f32) -> Vec<f32> {
+    (0..from.len().max(to.len()))
+        .map(|index| {
+            let from = from.get(index).copied().unwrap_or(0.0);
+            let to = to.get(index).copied().unwrap_or(0.0);
+            from + (to - from) * amount
+        })
+        .collect()
+}
+
+/// How a clip is played
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Playback {
+    /// Whether the clip starts over once finished, or holds its last pose
+    pub looping: bool,
+    /// Multiplies the elapsed time, a negative speed playing the clip backwards
+    pub speed: f32,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            looping: true,
+            speed: 1.0,
+        }
+    }
+}
+
+/// Identifies an animation so it can be controlled or removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AnimationId(u64);
+
+#[derive(Clone, Copy, Debug)]
+struct ClipState {
+    clip: usize,
+    time: f32,
+    playback: Playback,
+}
+
+impl ClipState {
+    fn advance(&mut self, delta_time: f32, duration: f32) {
+        self.time += delta_time * self.playback.speed;
+        self.time = if self.playback.looping && duration > 0.0 {
+            self.time.rem_euclid(duration)
+        } else {
+            self.time.clamp(0.0, duration)
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CrossFade {
+    // Clip faded out
+    from: ClipState,
+    elapsed: f32,
+    duration: f32,
+}
+
+/// Plays clips on draw items, the first track of the clips animating the first item and so on.
+/// The transforms of the items are replaced every frame the animations aren't paused.
+///
+/// Switching clips either cuts to the next one or cross-fades to it, blending the poses of both
+/// clips linearly over the fade while both keep playing.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    clips: Vec<AnimationClip>,
+    items: Vec<DrawItemId>,
+    morph_mesh: Option<MeshHandle>,
+    current: ClipState,
+    fade: Option<CrossFade>,
+}
+
+impl Animation {
+    /// Animation of `items` by `clips`, starting with the first clip looping at normal speed
+    pub fn new(clips: Vec<AnimationClip>, items: Vec<DrawItemId>) -> Self {
+        Self {
+            clips,
+            items,
+            morph_mesh: None,
+            current: ClipState {
+                clip: 0,
+                time: 0.0,
+                playback: Playback::default(),
+            },
+            fade: None,
+        }
+    }
+
+    /// Cuts to the start of the clip of index `clip`, ignored if there is no such clip
+    pub fn play(&mut self, clip: usize, playback: Playback) {
+        if clip < self.clips.len() {
+            self.current = ClipState {
+                clip,
+                time: 0.0,
+                playback,
+            };
+            self.fade = None;
+        }
+    }
+
+    /// Fades from the current pose to the start of the clip of index `clip` over `duration`
+    /// seconds, ignored if there is no such clip. A fade in progress is replaced, starting from
+    /// the clip it was fading to.
+    pub fn cross_fade(&mut self, clip: usize, playback: Playback, duration: f32) {
+        if clip >= self.clips.len() {
+            return;
+        }
+        if duration <= 0.0 {
+            self.play(clip, playback);
+            return;
+        }
+
+        self.fade = Some(CrossFade {
+            from: self.current,
+            elapsed: 0.0,
+            duration,
+        });
+        self.current = ClipState {
+            clip,
+            time: 0.0,
+            playback,
+        };
+    }
+
+    /// Index of the clip playing, or being faded to
+    pub fn current_clip(&self) -> usize {
+        self.current.clip
+    }
+
+    /// Time into the current clip, in seconds
+    pub fn time(&self) -> f32 {
+        self.current.time
+    }
+
+    /// Jumps to `time` seconds into the current clip, within its duration
+    pub fn seek(&mut self, time: f32) {
+        self.current.time = time;
+        self.current.advance(0.0, self.duration(self.current.clip));
+    }
+
+    pub fn playback(&self) -> Playback {
+        self.current.playback
+    }
+
+    /// Changes the looping and speed of the current clip, keeping its time
+    pub fn set_playback(&mut self, playback: Playback) {
+        self.current.playback = playback;
+    }
+
+    /// Whether a clip not looping reached its end, or its start when played backwards
+    pub fn is_finished(&self) -> bool {
+        let ClipState {
+            clip,
+            time,
+            playback,
+        } = self.current;
+        !playback.looping
+            && if playback.speed < 0.0 {
+                time <= 0.0
+            } else {
+                time >= self.duration(clip)
+            }
+    }
+
+    pub fn items(&self) -> &[DrawItemId] {
+        &self.items
+    }
+
+    /// Sets the morph mesh whose target weights are driven by the weight keyframes of the clips,
+    /// `None` to leave the weights alone
+    pub fn set_morph_mesh(&mut self, mesh: Option<MeshHandle>) {
+        self.morph_mesh = mesh;
+    }
+
+    pub fn morph_mesh(&self) -> Option<MeshHandle> {
+        self.morph_mesh
+    }
+
+    /// Transform of `node` for the current time, blended with the clip faded out during a
+    /// cross-fade
+    pub fn pose(&self, node: usize) -> Option<NodeTransform> {
+        let current = self
+            .clips
+            .get(self.current.clip)?
+            .sample(node, self.current.time);
+        let Some(fade) = &self.fade else {
+            return current;
+        };
+
+        let from = self.clips[fade.from.clip].sample(node, fade.from.time);
+        match (from, current) {
+            (Some(from), Some(current)) => Some(from.blend(&current, fade.elapsed / fade.duration)),
+            _ => current.or(from),
+        }
+    }
+
+    /// Morph target weights for the current time, blended with the clip faded out during a
+    /// cross-fade
+    pub fn morph_weights(&self) -> Option<Vec<f32>> {
+        let current = self
+            .clips
+            .get(self.current.clip)?
+            .sample_weights(self.current.time);
+        let Some(fade) = &self.fade else {
+            return current;
+        };
+
+        let from = self.clips[fade.from.clip].sample_weights(fade.from.time);
+        match (from, current) {
+            (Some(from), Some(current)) => {
+                Some(blend_weights(&from, &current, fade.elapsed / fade.duration))
+            }
+            (from, current) => current.or(from),
+        }
+    }
+
+    fn duration(&self, clip: usize) -> f32 {
+        self.clips.get(clip).map_or(0.0, AnimationClip::duration)
+    }
+
+    fn update(&mut self, delta_time: f32) {
+        let duration = self.duration(self.current.clip);
+        self.current.advance(delta_time, duration);
+
+        if let Some(fade) = &mut self.fade {
+            let duration = self.clips[fade.from.clip].duration();
+            fade.from.advance(delta_time, duration);
+            fade.elapsed += delta_time;
+            if fade.elapsed >= fade.duration {
+                self.fade = None;
+            }
+        }
+    }
+}
+
+/// Animations of the draw items, advanced along with the scene
+#[derive(Default)]
+pub(crate) struct Animations {
+    animations: Vec<(AnimationId, Animation)>,
+    next_id: u64,
+}
+
+impl Animations {
+    pub(crate) fn add(&mut self, animation: Animation) -> AnimationId {
+        let id = AnimationId(self.next_id);
+        self.next_id += 1;
+        self.animations.push((id, animation));
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: AnimationId) -> Option<Animation> {
+        let index = self
+            .animations
+            .iter()
+            .position(|(animation_id, _)| *animation_id == id)?;
+        Some(self.animations.remove(index).1)
+    }
+
+    pub(crate) fn get_mut(&mut self, id: AnimationId) -> Option<&mut Animation> {
+        self.animations
+            .iter_mut()
+            .find(|(animation_id, _)| *animation_id == id)
+            .map(|(_, animation)| animation)
+    }
+
+    /// Advances the animations by `delta_time` seconds, poses their items and sets the weights
+    /// of their morph meshes, the removed items and meshes being skipped
+    pub(crate) fn update(
+        &mut self,
+        delta_time: f32,
+        draw_list: &mut DrawList,
+        mut morph_targets: Option<&mut MorphTargets>,
+    ) {
+        for (_, animation) in self.animations.iter_mut() {
+            animation.update(delta_time);
+
+            for (node, &id) in animation.items.iter().enumerate() {
+                if let (Some(pose), Some(item)) = (animation.pose(node), draw_list.get_mut(id)) {
+                    item.transform = pose.to_matrix();
+                }
+            }
+            if let (Some(mesh), Some(morph_targets)) =
+                (animation.morph_mesh, morph_targets.as_deref_mut())
+            {
+                if let Some(weights) = animation.morph_weights() {
+                    morph_targets.set_weights(mesh.0, &weights);
+                }
+            }
+        }
+    }
+}
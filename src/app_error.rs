@@ -1,92 +1,243 @@
+use std::{borrow::Cow, error::Error, fmt, sync::Arc};
+
 use ash::vk;
+#[cfg(feature = "windowing")]
 use raw_window_handle::HandleError;
 
-#[derive(Debug, Clone)]
+use crate::AppResult;
+
+/// Error of the application. The errors of the libraries it comes from are kept as its `source`,
+/// for the callers walking the chain of errors.
+///
+/// The steps the error went through are added with `ErrorContext`, and displayed from the
+/// outermost one, e.g. "creating the application: creating the swapchain: ...".
+#[derive(Debug, Clone, thiserror::Error)]
 pub struct AppError {
     pub error_type: AppErrorType,
     pub message: String,
+    #[source]
+    source: Option<Arc<dyn Error + Send + Sync>>,
+    // The innermost step first
+    context: Vec<Cow<'static, str>>,
 }
 
-impl std::fmt::Display for AppError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "Application error `{:?}`: {}",
-            self.error_type, self.message
-        )
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Application error `{:?}`: ", self.error_type)?;
+        for context in self.context.iter().rev() {
+            write!(f, "{}: ", context)?;
+        }
+        write!(f, "{}", self.message)
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Kind of failure of an `AppError`, displayed as the default message of the error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
 pub enum AppErrorType {
+    #[error("{0}")]
     VulkanError(vk::Result),
+    #[error("Couldn't load the Vulkan library.")]
     VulkanLoadingError,
+    #[error("No suitable physical device is avaible.")]
     NoSuitableDevice,
+    #[error("Failed to find suitable memory type.")]
     NoSuitableMemType,
+    #[error("An io error occured.")]
     IoError,
+    #[error("An error occured while retreiving an handle.")]
     HandleError,
+    #[error("The swapchain format can't be captured as a screenshot.")]
+    UnsupportedScreenshotFormat,
+    #[error("No frame has been presented yet.")]
+    NothingPresented,
+    #[error("A post effect with the same name is already part of the chain.")]
+    DuplicatePostEffect,
+    #[error("Failed to compile a shader override.")]
+    ShaderCompilationFailed,
+    #[error("A shader override is not a valid SPIR-V module.")]
+    InvalidShader,
+    #[error("The draw list can't hold more items.")]
+    DrawListFull,
+    #[error("A mesh needs at least one vertex and one index.")]
+    EmptyMesh,
+    #[error("The mesh doesn't exist or isn't dynamic.")]
+    NotADynamicMesh,
+    #[error("The data exceeds the capacity of the dynamic mesh.")]
+    MeshCapacityExceeded,
+    #[error("The device doesn't support the sparse residency needed by virtual textures.")]
+    SparseResidencyUnsupported,
+    #[error("The maximum number of reflection probes is already placed.")]
+    TooManyReflectionProbes,
+    #[error("A lightmap can't be baked with more than MAX_BAKED_LIGHTS lights.")]
+    TooManyBakedLights,
+    #[error("A texture array needs at least one layer, all of the same size and format.")]
+    MismatchedArrayLayers,
+    #[error("Failed to compile a script.")]
+    ScriptCompilationFailed,
+    #[error("A texture file is malformed.")]
+    InvalidTextureFile,
+    #[error("A texture file holds a format or a layout which can't be loaded.")]
+    UnsupportedTextureFormat,
+    #[error("A cubemap needs six square faces of the same size and format.")]
+    InvalidCubemapFaces,
+    #[error("The PBR pipeline can't hold more than MAX_PBR_MATERIALS materials.")]
+    TooManyPbrMaterials,
+    #[error(
+        "Too many lights for the PBR materials, see MAX_PBR_LIGHTS and MAX_DIRECTIONAL_PBR_LIGHTS."
+    )]
+    TooManyPbrLights,
+    #[error("A buffer can only be written within its size, when host visible and coherent.")]
+    InvalidBufferWrite,
+    #[error("The Vulkan instance doesn't support VK_EXT_headless_surface.")]
+    HeadlessSurfaceUnsupported,
+    #[error("The parameters of a shader material must keep the size they were created with.")]
+    InvalidMaterialParameters,
+    #[error("The clipboard couldn't be written.")]
+    ClipboardUnavailable,
+    #[error("No more than MAX_SHADOWED_POINT_LIGHTS point lights can cast shadows.")]
+    TooManyShadowedLights,
+    #[error("The passes of the render graph depend on each other in a cycle.")]
+    RenderGraphCycle,
+    #[error("A font file is malformed.")]
+    InvalidFont,
+    #[error("The device doesn't support the fill_mode_non_solid feature needed by the wireframe.")]
+    WireframeUnsupported,
+    #[error("The splat map and the layers of a terrain must be loaded 2D textures.")]
+    InvalidTerrainTextures,
+    #[error("The device doesn't support the VK_EXT_mesh_shader extension needed by the meshlets.")]
+    MeshShaderUnsupported,
+    #[error("The device doesn't support the VK_KHR_ray_tracing_pipeline extension.")]
+    RayTracingUnsupported,
+    #[error("The device doesn't support the VK_KHR_ray_query extension.")]
+    RayQueryUnsupported,
+    #[error("A morph mesh needs at least one target, with an offset for every vertex.")]
+    InvalidMorphTargets,
+    #[error("An image of the glTF file doesn't match its size.")]
+    InvalidGltfImage,
+    #[error("At least one frame must be in flight.")]
+    NoFramesInFlight,
+    #[error("The MSAA sample count must be a power of two up to 64.")]
+    InvalidMsaaSamples,
+    #[error("The swapchain couldn't be created, acquired or presented: {0}")]
+    SwapchainError(vk::Result),
+    #[error("The device or the host ran out of memory: {0}")]
+    AllocationFailed(vk::Result),
+    #[error("An asset couldn't be decoded.")]
+    AssetLoadingFailed,
 }
 
 impl AppErrorType {
-    const MSG_VULKAN_LOADING_ERROR: &'static str = "Couldn't load the Vulkan library.";
-    const MSG_NO_SUITABLE_DEVICE: &'static str = "No suitable physical device is avaible.";
-    const MSG_NO_SUITABLE_MEM_TYPE: &'static str = "Failed to find suitable memory type.";
-    const MSG_IO_ERROR: &'static str = "An io error occured.";
-    const MSG_HANDLE_ERROR: &'static str = "An error occured while retreiving an handle.";
+    /// Kind of the failures reported by Vulkan, telling apart the swapchain and the allocation
+    /// failures from the other results
+    fn from_vk_result(result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_OUT_OF_DATE_KHR
+            | vk::Result::SUBOPTIMAL_KHR
+            | vk::Result::ERROR_SURFACE_LOST_KHR
+            | vk::Result::ERROR_NATIVE_WINDOW_IN_USE_KHR => AppErrorType::SwapchainError(result),
+            vk::Result::ERROR_OUT_OF_HOST_MEMORY
+            | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY
+            | vk::Result::ERROR_OUT_OF_POOL_MEMORY
+            | vk::Result::ERROR_FRAGMENTED_POOL
+            | vk::Result::ERROR_FRAGMENTATION => AppErrorType::AllocationFailed(result),
+            _ => AppErrorType::VulkanError(result),
+        }
+    }
 }
 
 impl AppError {
     pub fn new(error_type: AppErrorType) -> Self {
-        let message = match error_type {
-            AppErrorType::VulkanError(vk_result) => vk_result.to_string(),
-            AppErrorType::VulkanLoadingError => {
-                String::from(AppErrorType::MSG_VULKAN_LOADING_ERROR)
-            }
-            AppErrorType::NoSuitableDevice => String::from(AppErrorType::MSG_NO_SUITABLE_DEVICE),
-            AppErrorType::NoSuitableMemType => String::from(AppErrorType::MSG_NO_SUITABLE_MEM_TYPE),
-            AppErrorType::IoError => String::from(AppErrorType::MSG_IO_ERROR),
-            AppErrorType::HandleError => String::from(AppErrorType::MSG_HANDLE_ERROR),
-        };
+        Self::with_message(error_type, error_type.to_string())
+    }
 
+    /// Error of `error_type` with a message telling more than the default one
+    pub fn with_message(error_type: AppErrorType, message: impl Into<String>) -> Self {
         Self {
             error_type,
-            message,
+            message: message.into(),
+            source: None,
+            context: Vec::new(),
         }
     }
+
+    /// Error of `error_type` caused by `source`, whose message is the one of the error
+    pub fn with_source(
+        error_type: AppErrorType,
+        source: impl Error + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            error_type,
+            message: source.to_string(),
+            source: Some(Arc::new(source)),
+            context: Vec::new(),
+        }
+    }
+
+    /// Steps the error went through, from the outermost one
+    pub fn context(&self) -> impl Iterator<Item = &str> {
+        self.context.iter().rev().map(|context| context.as_ref())
+    }
+
+    /// Records that the error happened while doing `context`, e.g. "creating the texture image"
+    pub fn add_context(mut self, context: impl Into<Cow<'static, str>>) -> Self {
+        self.context.push(context.into());
+        self
+    }
+}
+
+/// Adds the step an error happened in to the errors of a result, converting them to `AppError`,
+/// e.g. `.context("creating the texture image")?`
+pub trait ErrorContext<T> {
+    fn context(self, context: &'static str) -> AppResult<T>;
+
+    /// Same as `context`, with a context only built on errors
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> AppResult<T>;
+}
+
+impl<T, E: Into<AppError>> ErrorContext<T> for Result<T, E> {
+    fn context(self, context: &'static str) -> AppResult<T> {
+        self.map_err(|err| err.into().add_context(context))
+    }
+
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> AppResult<T> {
+        self.map_err(|err| err.into().add_context(context()))
+    }
 }
 
 impl From<vk::Result> for AppError {
     fn from(value: vk::Result) -> Self {
-        AppError {
-            error_type: AppErrorType::VulkanError(value),
-            message: value.to_string(),
-        }
+        AppError::with_source(AppErrorType::from_vk_result(value), value)
     }
 }
 
 impl From<std::io::Error> for AppError {
     fn from(value: std::io::Error) -> Self {
-        AppError {
-            error_type: AppErrorType::IoError,
-            message: value.to_string(),
-        }
+        AppError::with_source(AppErrorType::IoError, value)
     }
 }
 
 impl From<image::ImageError> for AppError {
     fn from(value: image::ImageError) -> Self {
-        AppError {
-            error_type: AppErrorType::IoError,
-            message: value.to_string(),
-        }
+        AppError::with_source(AppErrorType::AssetLoadingFailed, value)
     }
 }
 
+#[cfg(feature = "windowing")]
 impl From<HandleError> for AppError {
     fn from(value: HandleError) -> Self {
-        AppError {
-            error_type: AppErrorType::HandleError,
-            message: value.to_string(),
-        }
+        AppError::with_source(AppErrorType::HandleError, value)
+    }
+}
+
+#[cfg(feature = "gltf")]
+impl From<gltf::Error> for AppError {
+    fn from(value: gltf::Error) -> Self {
+        AppError::with_source(AppErrorType::AssetLoadingFailed, value)
+    }
+}
+
+impl From<png::EncodingError> for AppError {
+    fn from(value: png::EncodingError) -> Self {
+        AppError::with_source(AppErrorType::IoError, value)
     }
 }
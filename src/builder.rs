@@ -0,0 +1,166 @@
+use std::path::{Path, PathBuf};
+
+use ash::vk;
+use dpi::PhysicalSize;
+#[cfg(feature = "windowing")]
+use winit::{event_loop::ActiveEventLoop, window::Window};
+
+use crate::{
+    hot_reload::MeshImporter, AppError, AppErrorType, AppResult, Application, SoftwareRendering,
+    SurfaceSize, Vertex,
+};
+
+/// Frames recorded while the GPU renders the previous ones unless set otherwise, see
+/// `ApplicationBuilder::frames_in_flight`
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+/// Least severe validation messages printed unless set otherwise, see
+/// `ApplicationBuilder::validation_severity`
+#[cfg(feature = "vlayers")]
+pub const DEFAULT_VALIDATION_SEVERITY: vk::DebugUtilsMessageSeverityFlagsEXT =
+    vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE;
+
+/// Options the application is created with, returned by `Application::builder`. Every option has
+/// the default `Application::create` uses.
+pub struct ApplicationBuilder {
+    pub(crate) app_name: String,
+    pub(crate) software_rendering: SoftwareRendering,
+    pub(crate) preferred_gpu: Option<String>,
+    pub(crate) present_mode: Option<vk::PresentModeKHR>,
+    pub(crate) msaa_samples: u32,
+    pub(crate) frames_in_flight: usize,
+    #[cfg(feature = "vlayers")]
+    pub(crate) validation_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    texture_path: Option<PathBuf>,
+    model: Option<(PathBuf, MeshImporter)>,
+}
+
+impl Default for ApplicationBuilder {
+    fn default() -> Self {
+        Self {
+            app_name: String::from("Vulkan Tutorial"),
+            software_rendering: SoftwareRendering::from_env(),
+            preferred_gpu: None,
+            present_mode: None,
+            msaa_samples: 1,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            #[cfg(feature = "vlayers")]
+            validation_severity: DEFAULT_VALIDATION_SEVERITY,
+            texture_path: None,
+            model: None,
+        }
+    }
+}
+
+impl ApplicationBuilder {
+    /// Name given to the driver along with the instance, "Vulkan Tutorial" by default
+    pub fn app_name(mut self, name: &str) -> Self {
+        self.app_name = String::from(name);
+        self
+    }
+
+    /// Whether a CPU implementation of Vulkan may be picked, read from
+    /// `VULKAN_TUTORIAL_SOFTWARE_RENDERING` by default
+    pub fn software_rendering(mut self, software_rendering: SoftwareRendering) -> Self {
+        self.software_rendering = software_rendering;
+        self
+    }
+
+    /// Picks the first suitable device whose name contains `name`, ignoring the case. The usual
+    /// device is picked when none matches.
+    pub fn preferred_gpu(mut self, name: &str) -> Self {
+        self.preferred_gpu = Some(name.to_lowercase());
+        self
+    }
+
+    /// Presents with `present_mode` when the surface supports it, falling back to FIFO. Mailbox
+    /// is preferred by default.
+    pub fn present_mode(mut self, present_mode: vk::PresentModeKHR) -> Self {
+        self.present_mode = Some(present_mode);
+        self
+    }
+
+    /// Samples per pixel of the scene, a single one by default. Clamped to the most the device
+    /// supports, see `Application::msaa_samples`. The scene is resolved before the post effects
+    /// and the overlays, which are drawn with a single sample.
+    pub fn msaa_samples(mut self, samples: u32) -> Self {
+        self.msaa_samples = samples;
+        self
+    }
+
+    /// Frames recorded while the GPU renders the previous ones, `DEFAULT_FRAMES_IN_FLIGHT` by
+    /// default. More frames hide the stalls of the CPU at the cost of latency and memory.
+    pub fn frames_in_flight(mut self, frames_in_flight: usize) -> Self {
+        self.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    /// Least severe messages of the validation layers printed, `DEFAULT_VALIDATION_SEVERITY` by
+    /// default
+    #[cfg(feature = "vlayers")]
+    pub fn validation_severity(mut self, severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        self.validation_severity = severity;
+        self
+    }
+
+    /// Texture of the initial quad, which samples a placeholder without one
+    pub fn texture<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.texture_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Mesh imported from `path` with `import` drawn instead of the initial quad, see
+    /// `Application::load_mesh`
+    pub fn model<P, F>(mut self, path: P, import: F) -> Self
+    where
+        P: AsRef<Path>,
+        F: Fn(&Path) -> AppResult<(Vec<Vertex>, Vec<u32>)> + 'static,
+    {
+        self.model = Some((path.as_ref().to_path_buf(), Box::new(import)));
+        self
+    }
+
+    /// Creates the application presenting to `window`
+    #[cfg(feature = "windowing")]
+    pub fn build(self, event_loop: &ActiveEventLoop, window: &Window) -> AppResult<Application> {
+        self.check()?;
+        let application = Application::create_windowed(event_loop, window, &self)?;
+        self.load_assets(application)
+    }
+
+    /// Creates the application rendering to a headless surface of `size` physical pixels, see
+    /// `Application::create_headless`
+    pub fn build_headless(self, size: PhysicalSize<u32>) -> AppResult<Application> {
+        self.check()?;
+        let application =
+            Application::create_with_headless_surface(SurfaceSize::new(size, 1.0), &self)?;
+        self.load_assets(application)
+    }
+
+    fn check(&self) -> AppResult<()> {
+        if !self.msaa_samples.is_power_of_two() || self.msaa_samples > 64 {
+            return AppResult::Err(AppError::new(AppErrorType::InvalidMsaaSamples));
+        }
+        if self.frames_in_flight == 0 {
+            return AppResult::Err(AppError::new(AppErrorType::NoFramesInFlight));
+        }
+
+        Ok(())
+    }
+
+    fn load_assets(self, mut application: Application) -> AppResult<Application> {
+        let item = application.mesh_item_id();
+        if let Some(path) = &self.texture_path {
+            let texture = application.load_texture(path)?;
+            // The texture was just loaded, it is neither freed nor an array
+            let material = application.create_material(texture).unwrap();
+            application.draw_item_mut(item).unwrap().material = material;
+        }
+        if let Some((path, import)) = self.model {
+            let mesh = application.load_mesh(path, import)?;
+            application.draw_item_mut(item).unwrap().mesh = mesh;
+        }
+
+        Ok(application)
+    }
+}
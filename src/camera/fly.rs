@@ -0,0 +1,122 @@
+use cgmath::{Deg, InnerSpace, Rad};
+
+use super::CameraController;
+use crate::{input::actions, InputState, Mat4, Point3, Vec3};
+
+const DEFAULT_SPEED: f32 = 2.0;
+const DEFAULT_SENSITIVITY: f32 = 0.003;
+// Rotation speed of the look actions (arrows or stick), in radians per second
+const LOOK_SPEED: f32 = 2.0;
+// Avoids the view direction becoming parallel to the up vector
+const MAX_PITCH: Deg<f32> = Deg(89.0);
+
+/// First-person camera, the world up axis being +Z.
+///
+/// The `move_*` actions move the camera (WASD, E and Q or the left stick and triggers by default),
+/// moving the mouse while the `look` action is held or the `look_*` actions look around.
+#[derive(Debug, Clone)]
+pub struct FlyCamera {
+    pub position: Point3,
+    /// Rotation around the up axis, from +X
+    pub yaw: Rad<f32>,
+    /// Elevation above the XY plane
+    pub pitch: Rad<f32>,
+    /// Movement speed, in units per second
+    pub speed: f32,
+    /// Rotation per unit of mouse movement, in radians
+    pub sensitivity: f32,
+}
+
+impl Default for FlyCamera {
+    /// Looks at the origin from (2, 2, 2)
+    fn default() -> Self {
+        Self::new(
+            Point3::new(2.0, 2.0, 2.0),
+            Deg(-135.0).into(),
+            Rad(-(1.0f32 / 3.0f32.sqrt()).asin()),
+        )
+    }
+}
+
+impl FlyCamera {
+    pub fn new(position: Point3, yaw: Rad<f32>, pitch: Rad<f32>) -> Self {
+        let mut camera = Self {
+            position,
+            yaw,
+            pitch,
+            speed: DEFAULT_SPEED,
+            sensitivity: DEFAULT_SENSITIVITY,
+        };
+        camera.turn(Rad(0.0), Rad(0.0));
+        camera
+    }
+
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn with_sensitivity(mut self, sensitivity: f32) -> Self {
+        self.sensitivity = sensitivity;
+        self
+    }
+
+    /// Direction the camera is looking at
+    pub fn forward(&self) -> Vec3 {
+        Vec3::new(
+            self.pitch.0.cos() * self.yaw.0.cos(),
+            self.pitch.0.cos() * self.yaw.0.sin(),
+            self.pitch.0.sin(),
+        )
+    }
+
+    /// Direction pointing to the right of the screen.
+    ///
+    /// The view matrix uses a flipped up vector, so this is computed against it rather than +Z.
+    pub fn right(&self) -> Vec3 {
+        self.forward().cross(Vec3::new(0.0, 0.0, -1.0)).normalize()
+    }
+
+    /// Rotates the view by a mouse movement of `dx`, `dy`
+    pub fn look(&mut self, dx: f32, dy: f32) {
+        self.turn(Rad(dx * self.sensitivity), Rad(-dy * self.sensitivity));
+    }
+
+    /// Rotates the view, a positive `yaw` turning right and a positive `pitch` looking up
+    pub fn turn(&mut self, yaw: Rad<f32>, pitch: Rad<f32>) {
+        let max_pitch: Rad<f32> = MAX_PITCH.into();
+        self.yaw += yaw;
+        self.pitch = Rad((self.pitch + pitch).0.clamp(-max_pitch.0, max_pitch.0));
+    }
+}
+
+impl CameraController for FlyCamera {
+    fn view_matrix(&self) -> Mat4 {
+        // The up vector is flipped to account for the Vulkan clip space Y axis pointing down
+        Mat4::look_to_rh(self.position, self.forward(), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    fn update(&mut self, input: &InputState, delta_time: f32) {
+        if input.action_down(actions::LOOK) {
+            let (dx, dy) = input.mouse_delta();
+            self.look(dx as f32, dy as f32);
+        }
+
+        let look_speed = LOOK_SPEED * delta_time;
+        self.turn(
+            Rad(input.axis(actions::LOOK_LEFT, actions::LOOK_RIGHT) * look_speed),
+            Rad(input.axis(actions::LOOK_DOWN, actions::LOOK_UP) * look_speed),
+        );
+
+        let mut direction = self.forward()
+            * input.axis(actions::MOVE_BACKWARD, actions::MOVE_FORWARD)
+            + self.right() * input.axis(actions::MOVE_LEFT, actions::MOVE_RIGHT)
+            + Vec3::new(0.0, 0.0, 1.0) * input.axis(actions::MOVE_DOWN, actions::MOVE_UP);
+
+        // Partially deflected sticks move slower, but diagonals don't move faster
+        if direction.magnitude2() > 1.0 {
+            direction = direction.normalize();
+        }
+        self.position += direction * self.speed * delta_time;
+    }
+}
@@ -0,0 +1,16 @@
+mod fly;
+mod orbit;
+
+pub use fly::FlyCamera;
+pub use orbit::OrbitCamera;
+
+use crate::{InputState, Mat4};
+
+/// Drives the view matrix of the scene from the user input
+pub trait CameraController {
+    fn view_matrix(&self) -> Mat4;
+
+    /// Updates the camera from the input of the frame, `delta_time` being in seconds. Called once
+    /// per frame before the view matrix is read.
+    fn update(&mut self, input: &InputState, delta_time: f32);
+}
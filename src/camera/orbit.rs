@@ -0,0 +1,117 @@
+use super::CameraController;
+use crate::{input::actions, InputState, Mat4, Point3, Vec3};
+use cgmath::{Deg, InnerSpace, Rad};
+
+// Radians per unit of mouse movement
+const ROTATE_SPEED: f32 = 0.005;
+// Distance factor per scroll line
+const ZOOM_SPEED: f32 = 0.9;
+// Rotation speed of the look actions (arrows or stick), in radians per second
+const LOOK_SPEED: f32 = 2.0;
+// Scroll lines per second of the zoom actions
+const ZOOM_ACTION_SPEED: f32 = 4.0;
+
+const MIN_DISTANCE: f32 = 0.5;
+const MAX_DISTANCE: f32 = 8.0;
+// Avoids the view direction becoming parallel to the up vector
+const MAX_PITCH: Deg<f32> = Deg(89.0);
+
+/// Camera rotating around a target, the world up axis being +Z.
+///
+/// Moving the mouse while the `orbit` action is held or the `look_*` actions orbit around the
+/// target, scrolling or the `zoom_*` actions move closer or further from it.
+#[derive(Debug, Clone)]
+pub struct OrbitCamera {
+    pub target: Point3,
+    /// Rotation around the up axis, from +X
+    pub yaw: Rad<f32>,
+    /// Elevation above the XY plane
+    pub pitch: Rad<f32>,
+    pub distance: f32,
+}
+
+impl Default for OrbitCamera {
+    /// Looks at the origin from (2, 2, 2)
+    fn default() -> Self {
+        Self::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Deg(45.0).into(),
+            Rad((1.0f32 / 3.0f32.sqrt()).asin()),
+            12.0f32.sqrt(),
+        )
+    }
+}
+
+impl OrbitCamera {
+    pub fn new(target: Point3, yaw: Rad<f32>, pitch: Rad<f32>, distance: f32) -> Self {
+        let mut camera = Self {
+            target,
+            yaw,
+            pitch,
+            distance,
+        };
+        camera.clamp();
+        camera
+    }
+
+    /// Position of the camera
+    pub fn eye(&self) -> Point3 {
+        let direction = Vec3::new(
+            self.pitch.0.cos() * self.yaw.0.cos(),
+            self.pitch.0.cos() * self.yaw.0.sin(),
+            self.pitch.0.sin(),
+        );
+        self.target + direction.normalize() * self.distance
+    }
+
+    /// Orbits around the target by a mouse movement of `dx`, `dy`
+    pub fn rotate(&mut self, dx: f32, dy: f32) {
+        self.orbit(Rad(-dx * ROTATE_SPEED), Rad(dy * ROTATE_SPEED));
+    }
+
+    /// Moves around the target, a positive `pitch` moving above it
+    pub fn orbit(&mut self, yaw: Rad<f32>, pitch: Rad<f32>) {
+        self.yaw += yaw;
+        self.pitch += pitch;
+        self.clamp();
+    }
+
+    /// Moves closer to the target for positive `lines`, further for negative ones
+    pub fn zoom(&mut self, lines: f32) {
+        self.distance *= ZOOM_SPEED.powf(lines);
+        self.clamp();
+    }
+
+    fn clamp(&mut self) {
+        let max_pitch: Rad<f32> = MAX_PITCH.into();
+        self.pitch = Rad(self.pitch.0.clamp(-max_pitch.0, max_pitch.0));
+        self.distance = self.distance.clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+}
+
+impl CameraController for OrbitCamera {
+    fn view_matrix(&self) -> Mat4 {
+        // The up vector is flipped to account for the Vulkan clip space Y axis pointing down
+        Mat4::look_at_rh(self.eye(), self.target, Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    fn update(&mut self, input: &InputState, delta_time: f32) {
+        if input.action_down(actions::ORBIT) {
+            let (dx, dy) = input.mouse_delta();
+            self.rotate(dx as f32, dy as f32);
+        }
+
+        // Same direction as a mouse drag
+        let look_speed = LOOK_SPEED * delta_time;
+        self.orbit(
+            Rad(-input.axis(actions::LOOK_LEFT, actions::LOOK_RIGHT) * look_speed),
+            Rad(-input.axis(actions::LOOK_DOWN, actions::LOOK_UP) * look_speed),
+        );
+
+        let zoom = input.wheel()
+            + input.axis(actions::ZOOM_OUT, actions::ZOOM_IN) * ZOOM_ACTION_SPEED * delta_time;
+        if zoom != 0.0 {
+            self.zoom(zoom);
+        }
+    }
+}
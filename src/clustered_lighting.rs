@@ -0,0 +1,161 @@
+use ash::{vk, Device, Instance};
+
+use crate::{
+    handle_registry, load_shader, pbr::GpuLight, AppResult, Application, BufferHolder,
+    MemoryMappedBuffer, MAX_PBR_LIGHTS,
+};
+
+/// Clusters the view frustum is divided into along the width and height of the screen, and along
+/// the depth with slices growing exponentially from the near plane to the far one
+pub const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+/// Point lights shading the fragments of a cluster at most, the further ones being left out
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 64;
+
+const CLUSTER_COUNT: u32 = CLUSTER_GRID[0] * CLUSTER_GRID[1] * CLUSTER_GRID[2];
+// Light count of a cluster followed by the indices of its lights
+const CLUSTER_STRIDE: u64 = (1 + MAX_LIGHTS_PER_CLUSTER as u64) * 4;
+
+/// Lists of the point lights reaching each cluster of the view frustum, built every frame by a
+/// compute pass so the fragments only loop over the lights close to them.
+///
+/// The culling pass binds the lighting set of the PBR pipeline as set 0, reading the lighting
+/// uniforms and the point lights, and writing the cluster lists.
+pub(crate) struct ClusteredLighting {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    // One of each per frame in flight
+    light_buffers: Vec<MemoryMappedBuffer>,
+    cluster_buffers: Vec<BufferHolder>,
+}
+
+impl ClusteredLighting {
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        lighting_set_layout: vk::DescriptorSetLayout,
+        frame_count: usize,
+    ) -> AppResult<Self> {
+        let pipeline_layout =
+            Application::create_pipeline_layout(device, &[lighting_set_layout], &[])?;
+        let pipeline = Application::create_compute_pipeline(
+            device,
+            pipeline_layout,
+            &load_shader(
+                "light_culling.comp",
+                include_bytes!("spirv/light_culling.spv"),
+            )?,
+        )?;
+
+        let light_buffers = Application::create_host_visible_buffers(
+            instance,
+            device,
+            physical_device,
+            (MAX_PBR_LIGHTS * std::mem::size_of::<GpuLight>()) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            frame_count,
+        )?;
+        let cluster_buffers = (0..frame_count)
+            .map(|_| {
+                Application::create_buffer(
+                    instance,
+                    device,
+                    physical_device,
+                    CLUSTER_COUNT as u64 * CLUSTER_STRIDE,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            light_buffers,
+            cluster_buffers,
+        })
+    }
+
+    /// Point lights and cluster lists of `frame`, to bind at bindings 2 and 3 of the lighting set
+    pub(crate) fn buffer_infos(&self, frame: usize) -> [vk::DescriptorBufferInfo; 2] {
+        [
+            vk::DescriptorBufferInfo {
+                buffer: self.light_buffers[frame].buffer,
+                offset: 0,
+                range: vk::WHOLE_SIZE,
+            },
+            vk::DescriptorBufferInfo {
+                buffer: self.cluster_buffers[frame].buffer,
+                offset: 0,
+                range: vk::WHOLE_SIZE,
+            },
+        ]
+    }
+
+    /// Cluster lists of `frame`
+    pub(crate) fn cluster_buffer(&self, frame: usize) -> vk::Buffer {
+        self.cluster_buffers[frame].buffer
+    }
+
+    /// Writes the point lights of `frame`, which must not be in use by the GPU
+    pub(crate) fn write_lights(&self, frame: usize, lights: &[GpuLight]) {
+        let count = lights.len().min(MAX_PBR_LIGHTS);
+        unsafe {
+            std::ptr::copy(
+                lights.as_ptr(),
+                self.light_buffers[frame].memory_map as *mut GpuLight,
+                count,
+            );
+        }
+    }
+
+    /// Records the culling of the lights against the clusters, before the scene
+    /// render pass whose fragment shaders read the lists. The barrier between the two is left
+    /// to the render graph of the frame.
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        lighting_set: vk::DescriptorSet,
+    ) {
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[lighting_set],
+                &[],
+            );
+            // A workgroup per depth slice, an invocation per cluster of the slice
+            device.cmd_dispatch(command_buffer, 1, 1, CLUSTER_GRID[2]);
+        }
+    }
+
+    /// Destroys the buffers and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for buffer in self.light_buffers.iter() {
+                handle_registry::unregister(buffer.buffer);
+                device.destroy_buffer(buffer.buffer, None);
+                handle_registry::unregister(buffer.memory);
+                device.free_memory(buffer.memory, None);
+            }
+            for buffer in self.cluster_buffers.iter() {
+                handle_registry::unregister(buffer.buffer);
+                device.destroy_buffer(buffer.buffer, None);
+                handle_registry::unregister(buffer.memory);
+                device.free_memory(buffer.memory, None);
+            }
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,920 @@
+use std::{
+    collections::HashSet,
+    ffi::{CStr, CString},
+};
+
+#[cfg(feature = "vlayers")]
+use ash::ext::debug_utils;
+use ash::{
+    ext::mesh_shader,
+    khr::{
+        self, acceleration_structure, deferred_host_operations, ray_query, ray_tracing_pipeline,
+        surface,
+    },
+    prelude::VkResult,
+    vk, Device, Entry, Instance,
+};
+use tracing::warn;
+
+use crate::{
+    creation_guard::CreationGuard, handle_registry, queue_families::QueueFamilyIndice,
+    software_rendering, AppError, AppErrorType, AppResult, Application, ApplicationBuilder,
+    DeviceFeatures, ErrorContext, EventLog, PortabilitySubset, RendererEvent, SoftwareRendering,
+    SurfaceHodlder, SwapChainDetails, DEVICE_EXTENSIONS, EXTENSIONS, MAX_API_VERSION,
+    OPTIONAL_EXTENSIONS,
+};
+#[cfg(feature = "vlayers")]
+use crate::{DebugMessengerHolder, VALIDATION_LAYERS};
+
+/// Instance, surface and device of the application, along with the debug messenger of the
+/// validation layers. Destroyed last, once every object created from them is.
+pub(crate) struct Context {
+    // Keeps the Vulkan library loaded
+    _entry: Entry,
+    pub instance: Instance,
+    pub surface: SurfaceHodlder,
+    pub physical_device: vk::PhysicalDevice,
+    pub queue_family_indices: QueueFamilyIndice,
+    pub device: Device,
+    pub graphics_queue: vk::Queue,
+    pub present_queue: vk::Queue,
+    pub api_version: u32,
+    pub device_features: DeviceFeatures,
+    // `None` when the validation layers aren't installed
+    #[cfg(feature = "vlayers")]
+    pub debug_messenger: Option<DebugMessengerHolder>,
+    destroyed: bool,
+}
+
+impl Context {
+    /// Creates the instance presenting to the surface returned by `create_surface`, with
+    /// `surface_extensions` enabled, then the device
+    pub(crate) fn new(
+        entry: Entry,
+        surface_extensions: &[&CStr],
+        create_surface: impl FnOnce(&Entry, &Instance) -> VkResult<vk::SurfaceKHR>,
+        builder: &ApplicationBuilder,
+        event_log: &EventLog,
+    ) -> AppResult<Self> {
+        // The validation layers are skipped when they aren't installed, along with the
+        // extensions of EXTENSIONS which are only needed by them
+        #[cfg(feature = "vlayers")]
+        let validation_active =
+            Self::validation_supported(&entry).context("checking the validation layers")?;
+        #[cfg(not(feature = "vlayers"))]
+        let validation_active = false;
+
+        // Getting every requested extension names as an iterator of valid CStr
+        let extension_names = EXTENSIONS
+            .iter()
+            .copied()
+            .filter(|_| validation_active)
+            .chain(surface_extensions.iter().copied());
+
+        // Getting every requested validation layers names as an iterator of valid CStr
+        #[cfg(feature = "vlayers")]
+        let layer_names = VALIDATION_LAYERS
+            .iter()
+            .copied()
+            .filter(|_| validation_active);
+
+        // Creating the VkInstance with the highest version supported by both the loader and the
+        // application
+        let instance_version =
+            Self::negotiate_instance_version(&entry).context("negotiating the instance version")?;
+        #[cfg(feature = "vlayers")]
+        let instance = Self::create_instance(
+            &entry,
+            builder,
+            instance_version,
+            extension_names,
+            layer_names,
+        )
+        .context("creating the instance")?;
+        #[cfg(not(feature = "vlayers"))]
+        let instance = Self::create_instance(&entry, builder, instance_version, extension_names)
+            .context("creating the instance")?;
+        let mut creation_guard = CreationGuard::new(&instance);
+
+        // Setting up the VkDebugUtilsMessengerEXT for the validation layers
+        #[cfg(feature = "vlayers")]
+        let debug_messenger = if validation_active {
+            Self::setup_debug_messenger(&entry, &instance, builder.validation_severity)
+        } else {
+            None
+        };
+        #[cfg(feature = "vlayers")]
+        if let Some(debug_messenger) = &debug_messenger {
+            creation_guard.set_debug_messenger(
+                &debug_messenger.debug_util_ext,
+                debug_messenger.debug_messenger,
+            );
+        }
+
+        let surface = Self::create_surface(&entry, &instance, create_surface)
+            .context("creating the surface")?;
+        creation_guard.set_surface(&surface.surface_ext, surface.surface);
+
+        // Choosing the VkPhisicalDevice, create the VkDevice and the graphics queue
+        let (physical_device, queue_family_indices) =
+            Self::pick_physical_device(&instance, &surface, builder)
+                .context("picking the physical device")?;
+        let api_version =
+            Self::negotiate_device_version(&instance, physical_device, instance_version);
+
+        let proprieties = unsafe { instance.get_physical_device_properties(physical_device) };
+        event_log.push(RendererEvent::DeviceChosen {
+            name: proprieties.device_name_as_c_str().map_or_else(
+                |_| String::new(),
+                |name| name.to_string_lossy().into_owned(),
+            ),
+            api_version,
+        });
+        let (device, graphics_queue, present_queue, device_features) = Self::create_logical_device(
+            &entry,
+            &instance,
+            physical_device,
+            queue_family_indices,
+            api_version,
+        )
+        .context("creating the logical device")?;
+
+        // The device is the last object of the context, nothing can fail once it is created
+        creation_guard.release();
+        Ok(Self {
+            _entry: entry,
+            instance,
+            surface,
+            physical_device,
+            queue_family_indices,
+            device,
+            graphics_queue,
+            present_queue,
+            api_version,
+            device_features,
+            #[cfg(feature = "vlayers")]
+            debug_messenger,
+            destroyed: false,
+        })
+    }
+
+    /// Most samples per pixel up to `requested` the device supports for both the color and the
+    /// depth attachments
+    pub(crate) fn supported_samples(&self, requested: u32) -> vk::SampleCountFlags {
+        let limits = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+                .limits
+        };
+        let supported =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+
+        // The flag of a sample count is the count itself
+        let mut samples = requested.max(1);
+        while samples > 1 && !supported.contains(vk::SampleCountFlags::from_raw(samples)) {
+            samples /= 2;
+        }
+        vk::SampleCountFlags::from_raw(samples)
+    }
+
+    /// Destroys the device, the surface and the instance, does nothing if they are already
+    /// destroyed. The objects created from them must be destroyed beforehand.
+    pub(crate) fn destroy(&mut self) {
+        if self.destroyed {
+            return;
+        }
+        self.destroyed = true;
+
+        unsafe {
+            handle_registry::unregister(self.device.handle());
+            self.device.destroy_device(None);
+
+            #[cfg(feature = "vlayers")]
+            if let Some(debug_messenger) = &self.debug_messenger {
+                handle_registry::unregister(debug_messenger.debug_messenger);
+                debug_messenger
+                    .debug_util_ext
+                    .destroy_debug_utils_messenger(debug_messenger.debug_messenger, None);
+            }
+
+            handle_registry::unregister(self.surface.surface);
+            self.surface
+                .surface_ext
+                .destroy_surface(self.surface.surface, None);
+
+            handle_registry::unregister(self.instance.handle());
+            self.instance.destroy_instance(None);
+        }
+    }
+
+    pub(crate) fn load_entry() -> AppResult<Entry> {
+        unsafe {
+            Entry::load().or(AppResult::Err(AppError::new(
+                AppErrorType::VulkanLoadingError,
+            )))
+        }
+    }
+
+    /// Creates the VkInstance with the requested extension names and validation layers name
+    pub(crate) fn create_instance<'a, 'b>(
+        entry: &Entry,
+        builder: &ApplicationBuilder,
+        api_version: u32,
+        extension_names: impl IntoIterator<Item = &'a CStr>,
+        #[cfg(feature = "vlayers")] layer_names: impl IntoIterator<Item = &'b CStr>,
+    ) -> AppResult<Instance> {
+        // Define the vulkan application info
+        // The name is cut at its first nul character
+        let app_name = CString::new(builder.app_name.split('\0').next().unwrap()).unwrap();
+        let engine_name = CString::new("No Engine").unwrap();
+        let app_info = vk::ApplicationInfo {
+            p_application_name: app_name.as_ptr(),
+            application_version: vk::make_api_version(1, 0, 0, 0),
+            p_engine_name: engine_name.as_ptr(),
+            engine_version: vk::make_api_version(1, 0, 0, 0),
+            api_version,
+            ..Default::default()
+        };
+
+        // Filter out the the layers unsupported by the vulkan instance
+        #[cfg(feature = "vlayers")]
+        let layers: Vec<&CStr> = {
+            let avaible_layers = unsafe { entry.enumerate_instance_layer_properties()? };
+
+            layer_names
+                .into_iter()
+                .filter(|&lay| {
+                    avaible_layers
+                        .iter()
+                        .find(|&a_lay| unsafe { CStr::from_ptr(a_lay.layer_name.as_ptr()) } == lay)
+                        .or_else(|| {
+                            warn!(layer = ?lay, "layer unsupported");
+                            None
+                        })
+                        .is_some()
+                })
+                .collect()
+        };
+
+        // Filter out the the extensions unsupported by the vulkan instance, the enabled layers
+        // may provide their own extensions
+        #[allow(unused_mut)]
+        let mut avaible_extensions =
+            unsafe { entry.enumerate_instance_extension_properties(None)? };
+        #[cfg(feature = "vlayers")]
+        for &lay in layers.iter() {
+            avaible_extensions
+                .extend(unsafe { entry.enumerate_instance_extension_properties(Some(lay))? });
+        }
+        let mut extensions: Vec<*const i8> = extension_names
+            .into_iter()
+            .filter(|&ext| {
+                avaible_extensions
+                    .iter()
+                    .find(|&a_ext| unsafe { CStr::from_ptr(a_ext.extension_name.as_ptr()) } == ext)
+                    .or_else(|| {
+                        warn!(extension = ?ext, "extension unsupported");
+                        None
+                    })
+                    .is_some()
+            })
+            .map(|ext| ext.as_ptr())
+            .collect();
+
+        // Silently enable the optional extensions the vulkan instance supports
+        let mut flags = vk::InstanceCreateFlags::empty();
+        for &ext in OPTIONAL_EXTENSIONS {
+            let is_avaible = avaible_extensions
+                .iter()
+                .any(|a_ext| unsafe { CStr::from_ptr(a_ext.extension_name.as_ptr()) } == ext);
+            if !is_avaible {
+                continue;
+            }
+
+            // Lists the portability implementations along with the conformant devices
+            if ext == khr::portability_enumeration::NAME {
+                flags |= vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
+            }
+            extensions.push(ext.as_ptr());
+        }
+
+        #[cfg(feature = "vlayers")]
+        let layers: Vec<*const i8> = layers.iter().map(|lay| lay.as_ptr()).collect();
+
+        #[allow(unused_mut)]
+        let mut create_info = vk::InstanceCreateInfo {
+            flags,
+            p_application_info: &app_info as *const _,
+            enabled_extension_count: extensions.len() as u32,
+            pp_enabled_extension_names: extensions.as_ptr(),
+            ..Default::default()
+        };
+
+        #[cfg(feature = "vlayers")]
+        let debug_messenger_create_info =
+            Self::debug_messenger_create_info(builder.validation_severity);
+        // The messenger chained to the instance also reports the instance creation and destruction
+        #[cfg(feature = "vlayers")]
+        if !layers.is_empty() {
+            let debug_messenger_create_info_ptr =
+                &debug_messenger_create_info as *const vk::DebugUtilsMessengerCreateInfoEXT;
+            create_info.p_next = debug_messenger_create_info_ptr as *const _;
+            create_info.enabled_layer_count = layers.len() as u32;
+            create_info.pp_enabled_layer_names = layers.as_ptr();
+        }
+
+        // Create the instance
+        // Safety: The instance is the last destroyed object
+        let instance = unsafe {
+            entry
+                .create_instance(&create_info, None)
+                .or_else(|r| AppResult::Err(r.into()))?
+        };
+        handle_registry::register(instance.handle());
+
+        Ok(instance)
+    }
+
+    pub(crate) fn create_surface(
+        entry: &Entry,
+        instance: &Instance,
+        create_surface: impl FnOnce(&Entry, &Instance) -> VkResult<vk::SurfaceKHR>,
+    ) -> AppResult<SurfaceHodlder> {
+        let surface_ext = surface::Instance::new(entry, instance);
+
+        let surface = create_surface(entry, instance)?;
+        handle_registry::register(surface);
+
+        Ok(SurfaceHodlder {
+            surface_ext,
+            surface,
+        })
+    }
+
+    /// Chooses the first avaible physical device that suits the needs of the application
+    pub(crate) fn pick_physical_device(
+        instance: &Instance,
+        surface: &SurfaceHodlder,
+        builder: &ApplicationBuilder,
+    ) -> AppResult<(vk::PhysicalDevice, QueueFamilyIndice)> {
+        let software_rendering = builder.software_rendering;
+        let physical_devices = unsafe { instance.enumerate_physical_devices()? };
+        let suitable = physical_devices.into_iter().filter_map(|device| {
+            let software = software_rendering::is_software_device(instance, device);
+            if !software_rendering.accepts(software) {
+                return None;
+            }
+            Self::is_device_suitable(instance, device, surface)
+                .ok()?
+                .map(|indices| (device, indices, software))
+        });
+
+        // The device named by the builder is preferred, then a GPU over a CPU implementation
+        // listed before it
+        let suitable: Vec<_> = suitable.collect();
+        let preferred = builder.preferred_gpu.as_ref().and_then(|name| {
+            suitable.iter().copied().find(|&(device, _, _)| {
+                let properties = unsafe { instance.get_physical_device_properties(device) };
+                properties.device_name_as_c_str().is_ok_and(|device_name| {
+                    device_name.to_string_lossy().to_lowercase().contains(name)
+                })
+            })
+        });
+        let picked = preferred
+            .or_else(|| suitable.iter().copied().find(|(_, _, software)| !software))
+            .or_else(|| suitable.first().copied());
+
+        let Some((device, indices, software)) = picked else {
+            return match software_rendering {
+                SoftwareRendering::Require => Err(AppError::with_message(
+                    AppErrorType::NoSuitableDevice,
+                    "No suitable CPU implementation of Vulkan, e.g. lavapipe or SwiftShader",
+                )),
+                _ => Err(AppError::new(AppErrorType::NoSuitableDevice)),
+            };
+        };
+
+        if software && software_rendering == SoftwareRendering::Fallback {
+            let properties = unsafe { instance.get_physical_device_properties(device) };
+            warn!(
+                device = %properties
+                    .device_name_as_c_str()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                "no GPU is suitable, rendering on the CPU will be slow"
+            );
+        }
+
+        Ok((device, indices))
+    }
+
+    /// Checks if the physical device meets the application's requirements
+    pub(crate) fn is_device_suitable(
+        instance: &Instance,
+        device: vk::PhysicalDevice,
+        surface: &SurfaceHodlder,
+    ) -> AppResult<Option<QueueFamilyIndice>> {
+        let indices = Self::find_queue_families(instance, device, surface)?;
+        if !indices.is_complete() {
+            return Ok(None);
+        }
+
+        let extensions_supported = Self::check_device_extensions_support(instance, device)?;
+        if !extensions_supported {
+            return Ok(None);
+        }
+
+        let swapchain_details = Self::query_swapchain_support(device, surface)?;
+        let swapchain_adequate =
+            !swapchain_details.formats.is_empty() && !swapchain_details.present_modes.is_empty();
+        if !swapchain_adequate {
+            return Ok(None);
+        }
+
+        let supported_features = unsafe { instance.get_physical_device_features(device) };
+        if supported_features.sampler_anisotropy == vk::FALSE {
+            return Ok(None);
+        }
+
+        Ok(Some(indices))
+    }
+
+    /// Finds the needed queue families from the physical device
+    pub(crate) fn find_queue_families(
+        instance: &Instance,
+        device: vk::PhysicalDevice,
+        surface: &SurfaceHodlder,
+    ) -> AppResult<QueueFamilyIndice> {
+        let queue_families =
+            unsafe { instance.get_physical_device_queue_family_properties(device) };
+
+        let mut indices = QueueFamilyIndice::default();
+        for (i, family) in queue_families
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (i as u32, f))
+        {
+            if indices.is_complete() {
+                break;
+            }
+
+            if family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
+                indices.graphics_family = Some(i)
+            }
+
+            if unsafe {
+                surface.surface_ext.get_physical_device_surface_support(
+                    device,
+                    i,
+                    surface.surface,
+                )?
+            } {
+                indices.present_family = Some(i)
+            }
+        }
+
+        Ok(indices)
+    }
+
+    pub(crate) fn check_device_extensions_support(
+        instance: &Instance,
+        device: vk::PhysicalDevice,
+    ) -> AppResult<bool> {
+        let avaible_extensions = unsafe { instance.enumerate_device_extension_properties(device)? };
+
+        let mut avaible_extensions_set = HashSet::new();
+        for a_ext in avaible_extensions.into_iter() {
+            avaible_extensions_set.insert(unsafe { CStr::from_ptr(a_ext.extension_name.as_ptr()) });
+        }
+
+        for &ext in DEVICE_EXTENSIONS.iter() {
+            if !avaible_extensions_set.insert(ext) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub(crate) fn check_device_extension_support(
+        instance: &Instance,
+        device: vk::PhysicalDevice,
+        extension: &CStr,
+    ) -> AppResult<bool> {
+        let avaible_extensions = unsafe { instance.enumerate_device_extension_properties(device)? };
+
+        Ok(avaible_extensions
+            .iter()
+            .any(|a_ext| unsafe { CStr::from_ptr(a_ext.extension_name.as_ptr()) } == extension))
+    }
+
+    pub(crate) fn query_swapchain_support(
+        device: vk::PhysicalDevice,
+        surface: &SurfaceHodlder,
+    ) -> AppResult<SwapChainDetails> {
+        let capabilities = unsafe {
+            surface
+                .surface_ext
+                .get_physical_device_surface_capabilities(device, surface.surface)?
+        };
+
+        let formats = unsafe {
+            surface
+                .surface_ext
+                .get_physical_device_surface_formats(device, surface.surface)?
+        };
+
+        let present_modes = unsafe {
+            surface
+                .surface_ext
+                .get_physical_device_surface_present_modes(device, surface.surface)?
+        };
+
+        Ok(SwapChainDetails {
+            capabilities,
+            formats,
+            present_modes,
+        })
+    }
+
+    /// Returns the features of the `VK_KHR_portability_subset` implementation, or `None` if the
+    /// physical device is fully conformant
+    pub(crate) fn query_portability_subset(
+        entry: &Entry,
+        instance: &Instance,
+        device: vk::PhysicalDevice,
+    ) -> AppResult<Option<PortabilitySubset>> {
+        let avaible_extensions = unsafe { instance.enumerate_device_extension_properties(device)? };
+        let is_portability = avaible_extensions.iter().any(|a_ext| {
+            let name = unsafe { CStr::from_ptr(a_ext.extension_name.as_ptr()) };
+            name == khr::portability_subset::NAME
+        });
+        if !is_portability {
+            return Ok(None);
+        }
+
+        // The portability subset requires VK_KHR_get_physical_device_properties2, which is
+        // always enabled when avaible
+        let properties2_ext = khr::get_physical_device_properties2::Instance::new(entry, instance);
+        let mut portability_features = vk::PhysicalDevicePortabilitySubsetFeaturesKHR::default();
+        let mut features2 =
+            vk::PhysicalDeviceFeatures2::default().push_next(&mut portability_features);
+        unsafe { properties2_ext.get_physical_device_features2(device, &mut features2) };
+
+        Ok(Some(portability_features.into()))
+    }
+
+    /// Returns the highest instance version supported by both the loader and the application
+    pub(crate) fn negotiate_instance_version(entry: &Entry) -> AppResult<u32> {
+        // Vulkan 1.0 loaders don't expose vkEnumerateInstanceVersion
+        let loader_version =
+            unsafe { entry.try_enumerate_instance_version()? }.unwrap_or(vk::API_VERSION_1_0);
+
+        Ok(Self::strip_patch_version(
+            loader_version.min(MAX_API_VERSION),
+        ))
+    }
+
+    /// Returns the version usable with the physical device, which may be lower than the instance
+    /// version
+    pub(crate) fn negotiate_device_version(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        instance_version: u32,
+    ) -> u32 {
+        let proprieties = unsafe { instance.get_physical_device_properties(physical_device) };
+        Self::strip_patch_version(proprieties.api_version.min(instance_version))
+    }
+
+    pub(crate) fn strip_patch_version(version: u32) -> u32 {
+        vk::make_api_version(
+            vk::api_version_variant(version),
+            vk::api_version_major(version),
+            vk::api_version_minor(version),
+            0,
+        )
+    }
+
+    /// Creates the VkDevice
+    pub(crate) fn create_logical_device(
+        entry: &Entry,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        indices: QueueFamilyIndice,
+        api_version: u32,
+    ) -> AppResult<(Device, vk::Queue, vk::Queue, DeviceFeatures)> {
+        let unique_families = indices.get_unique_families();
+
+        let queue_priorities = [1.0f32];
+        let mut queue_create_infos = Vec::with_capacity(unique_families.len());
+        for &queue_family in unique_families.iter() {
+            queue_create_infos.push(vk::DeviceQueueCreateInfo {
+                queue_family_index: queue_family,
+                queue_count: 1,
+                p_queue_priorities: &queue_priorities as *const _,
+                ..Default::default()
+            })
+        }
+
+        // Virtual textures are sparse images bound on the graphics queue, whose fragment shader
+        // writes the pages it needs to a storage buffer
+        let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+        let graphics_family_flags = unsafe {
+            instance.get_physical_device_queue_family_properties(physical_device)
+                [indices.graphics_family.unwrap() as usize]
+                .queue_flags
+        };
+        let sparse_residency = supported_features.sparse_binding == vk::TRUE
+            && supported_features.sparse_residency_image2_d == vk::TRUE
+            && supported_features.fragment_stores_and_atomics == vk::TRUE
+            && graphics_family_flags.contains(vk::QueueFlags::SPARSE_BINDING);
+
+        let texture_compression_bc = supported_features.texture_compression_bc == vk::TRUE;
+        let fill_mode_non_solid = supported_features.fill_mode_non_solid == vk::TRUE;
+
+        let device_features = vk::PhysicalDeviceFeatures::default()
+            .sampler_anisotropy(true)
+            .texture_compression_bc(texture_compression_bc)
+            .fill_mode_non_solid(fill_mode_non_solid)
+            .sparse_binding(sparse_residency)
+            .sparse_residency_image2_d(sparse_residency)
+            .fragment_stores_and_atomics(sparse_residency);
+        let mut device_extensions = DEVICE_EXTENSIONS
+            .iter()
+            .map(|&ext| ext.as_ptr())
+            .collect::<Vec<*const i8>>();
+
+        // A portability implementation must have its extension enabled, along with the subset
+        // features the application relies on
+        let portability_subset = Self::query_portability_subset(entry, instance, physical_device)?;
+        let mut portability_features = portability_subset.map(PortabilitySubset::to_vk);
+        if portability_subset.is_some() {
+            device_extensions.push(khr::portability_subset::NAME.as_ptr());
+        }
+
+        // Enables the Vulkan 1.3 paths when both the instance and the device support them
+        let mut vulkan13_features = vk::PhysicalDeviceVulkan13Features::default();
+        if api_version >= vk::API_VERSION_1_3 {
+            let mut supported_features13 = vk::PhysicalDeviceVulkan13Features::default();
+            let mut features2 =
+                vk::PhysicalDeviceFeatures2::default().push_next(&mut supported_features13);
+            unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+            vulkan13_features.synchronization2 = supported_features13.synchronization2;
+            vulkan13_features.dynamic_rendering = supported_features13.dynamic_rendering;
+        }
+
+        // The mesh shaders are compiled to SPIR-V 1.4, which needs Vulkan 1.3
+        let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+        if api_version >= vk::API_VERSION_1_3
+            && Self::check_device_extension_support(instance, physical_device, mesh_shader::NAME)?
+        {
+            let mut supported_mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+            let mut features2 =
+                vk::PhysicalDeviceFeatures2::default().push_next(&mut supported_mesh_shader);
+            unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+            if supported_mesh_shader.task_shader == vk::TRUE
+                && supported_mesh_shader.mesh_shader == vk::TRUE
+            {
+                mesh_shader_features.task_shader = vk::TRUE;
+                mesh_shader_features.mesh_shader = vk::TRUE;
+                device_extensions.push(mesh_shader::NAME.as_ptr());
+            }
+        }
+
+        // The acceleration structures are built from buffer device addresses, and traced by the
+        // ray tracing pipelines or by the ray queries of the fragment shaders
+        let accel_extensions = [acceleration_structure::NAME, deferred_host_operations::NAME];
+        let mut accel_features = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+        let mut ray_tracing_features = vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default();
+        let mut ray_query_features = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+        let mut address_features = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+        let mut accel_supported = api_version >= vk::API_VERSION_1_3;
+        for extension in accel_extensions {
+            accel_supported = accel_supported
+                && Self::check_device_extension_support(instance, physical_device, extension)?;
+        }
+        let ray_tracing_supported = accel_supported
+            && Self::check_device_extension_support(
+                instance,
+                physical_device,
+                ray_tracing_pipeline::NAME,
+            )?;
+        let ray_query_supported = accel_supported
+            && Self::check_device_extension_support(instance, physical_device, ray_query::NAME)?;
+        if ray_tracing_supported || ray_query_supported {
+            let mut supported_accel = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+            let mut supported_ray_tracing =
+                vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default();
+            let mut supported_ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+            let mut supported_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+            let mut features2 = vk::PhysicalDeviceFeatures2::default()
+                .push_next(&mut supported_accel)
+                .push_next(&mut supported_ray_tracing)
+                .push_next(&mut supported_ray_query)
+                .push_next(&mut supported_address);
+            unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+            if supported_accel.acceleration_structure == vk::TRUE
+                && supported_address.buffer_device_address == vk::TRUE
+            {
+                if ray_tracing_supported && supported_ray_tracing.ray_tracing_pipeline == vk::TRUE {
+                    ray_tracing_features.ray_tracing_pipeline = vk::TRUE;
+                    device_extensions.push(ray_tracing_pipeline::NAME.as_ptr());
+                }
+                if ray_query_supported && supported_ray_query.ray_query == vk::TRUE {
+                    ray_query_features.ray_query = vk::TRUE;
+                    device_extensions.push(ray_query::NAME.as_ptr());
+                }
+                if ray_tracing_features.ray_tracing_pipeline == vk::TRUE
+                    || ray_query_features.ray_query == vk::TRUE
+                {
+                    accel_features.acceleration_structure = vk::TRUE;
+                    address_features.buffer_device_address = vk::TRUE;
+                    device_extensions.extend(accel_extensions.map(CStr::as_ptr));
+                }
+            }
+        }
+
+        let device_features_info = DeviceFeatures {
+            synchronization2: vulkan13_features.synchronization2 == vk::TRUE,
+            dynamic_rendering: vulkan13_features.dynamic_rendering == vk::TRUE,
+            sparse_residency,
+            texture_compression_bc,
+            fill_mode_non_solid,
+            mesh_shader: mesh_shader_features.mesh_shader == vk::TRUE,
+            ray_tracing: ray_tracing_features.ray_tracing_pipeline == vk::TRUE,
+            ray_query: ray_query_features.ray_query == vk::TRUE,
+            portability_subset,
+        };
+
+        let mut create_info = vk::DeviceCreateInfo {
+            queue_create_info_count: queue_create_infos.len() as u32,
+            p_queue_create_infos: queue_create_infos.as_ptr(),
+            enabled_extension_count: device_extensions.len() as u32,
+            pp_enabled_extension_names: device_extensions.as_ptr(),
+            p_enabled_features: &device_features as *const _,
+            ..Default::default()
+        };
+        if let Some(portability_features) = portability_features.as_mut() {
+            create_info = create_info.push_next(portability_features);
+        }
+        if api_version >= vk::API_VERSION_1_3 {
+            create_info = create_info.push_next(&mut vulkan13_features);
+        }
+        if mesh_shader_features.mesh_shader == vk::TRUE {
+            create_info = create_info.push_next(&mut mesh_shader_features);
+        }
+        if accel_features.acceleration_structure == vk::TRUE {
+            create_info = create_info
+                .push_next(&mut accel_features)
+                .push_next(&mut address_features);
+        }
+        if ray_tracing_features.ray_tracing_pipeline == vk::TRUE {
+            create_info = create_info.push_next(&mut ray_tracing_features);
+        }
+        if ray_query_features.ray_query == vk::TRUE {
+            create_info = create_info.push_next(&mut ray_query_features);
+        }
+
+        // Safety: The Device is destroyed befor the parent Instance, see Application::cleanup()
+        let device = unsafe { instance.create_device(physical_device, &create_info, None)? };
+        handle_registry::register(device.handle());
+
+        let graphics_queue =
+            unsafe { device.get_device_queue(indices.graphics_family.unwrap(), 0) };
+        let present_queue = unsafe { device.get_device_queue(indices.present_family.unwrap(), 0) };
+
+        Ok((device, graphics_queue, present_queue, device_features_info))
+    }
+
+    /// Returns whether the validation layers and the debug utils extension are installed, logging
+    /// what is missing otherwise
+    #[cfg(feature = "vlayers")]
+    pub(crate) fn validation_supported(entry: &Entry) -> AppResult<bool> {
+        let avaible_layers = unsafe { entry.enumerate_instance_layer_properties()? };
+        let mut avaible_extensions =
+            unsafe { entry.enumerate_instance_extension_properties(None)? };
+
+        for &lay in VALIDATION_LAYERS {
+            let is_avaible = avaible_layers
+                .iter()
+                .any(|a_lay| unsafe { CStr::from_ptr(a_lay.layer_name.as_ptr()) } == lay);
+            if !is_avaible {
+                warn!(layer = ?lay, "validation layer not installed, running without validation");
+                return Ok(false);
+            }
+            avaible_extensions
+                .extend(unsafe { entry.enumerate_instance_extension_properties(Some(lay))? });
+        }
+
+        let has_debug_utils = avaible_extensions.iter().any(
+            |a_ext| unsafe { CStr::from_ptr(a_ext.extension_name.as_ptr()) } == debug_utils::NAME,
+        );
+        if !has_debug_utils {
+            warn!(
+                extension = ?debug_utils::NAME,
+                "validation extension unsupported, running without validation"
+            );
+        }
+
+        Ok(has_debug_utils)
+    }
+
+    /// Sets up the debug messenger for the validation layers, `None` if it can't be created in
+    /// which case the application runs without it
+    #[cfg(feature = "vlayers")]
+    pub(crate) fn setup_debug_messenger(
+        entry: &Entry,
+        instance: &Instance,
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    ) -> Option<DebugMessengerHolder> {
+        let debug_util_ext = debug_utils::Instance::new(entry, instance);
+
+        let create_info = Self::debug_messenger_create_info(severity);
+
+        let debug_messenger =
+            match unsafe { debug_util_ext.create_debug_utils_messenger(&create_info, None) } {
+                Ok(debug_messenger) => debug_messenger,
+                Err(err) => {
+                    warn!(%err, "failed to create the debug messenger, running without it");
+                    return None;
+                }
+            };
+        handle_registry::register(debug_messenger);
+
+        Some(DebugMessengerHolder {
+            debug_util_ext,
+            debug_messenger,
+        })
+    }
+
+    /// Creates the VkDebugUtilsMessengerCreateInfoEXT for the debug messenger, reporting the
+    /// messages at least as severe as `severity`
+    #[cfg(feature = "vlayers")]
+    pub(crate) fn debug_messenger_create_info(
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    ) -> vk::DebugUtilsMessengerCreateInfoEXT<'static> {
+        let message_severity = [
+            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        ]
+        .into_iter()
+        .filter(|&flag| flag >= severity)
+        .fold(
+            vk::DebugUtilsMessageSeverityFlagsEXT::empty(),
+            |flags, flag| flags | flag,
+        );
+
+        vk::DebugUtilsMessengerCreateInfoEXT {
+            message_severity,
+            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            pfn_user_callback: Some(Application::debug_callback),
+            ..Default::default()
+        }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        self.destroy();
+    }
+}
+
+impl Application {
+    /// Returns the features missing from the physical device when running on a portability
+    /// implementation like MoltenVK, or `None` if the device is fully conformant
+    pub fn portability_subset(&self) -> Option<PortabilitySubset> {
+        self.context.device_features.portability_subset
+    }
+
+    /// Returns whether the device is a CPU implementation of Vulkan, see `SoftwareRendering`
+    pub fn is_software_rendering(&self) -> bool {
+        software_rendering::is_software_device(&self.context.instance, self.context.physical_device)
+    }
+
+    /// Returns whether the validation layers report their messages, which is never the case
+    /// without the `vlayers` feature or when the layers aren't installed
+    pub fn validation_active(&self) -> bool {
+        #[cfg(feature = "vlayers")]
+        return self.context.debug_messenger.is_some();
+        #[cfg(not(feature = "vlayers"))]
+        false
+    }
+
+    /// Returns the Vulkan version used by the application, the highest version supported by the
+    /// loader, the physical device and the application
+    pub fn api_version(&self) -> u32 {
+        self.context.api_version
+    }
+
+    /// Returns the optional features enabled on the device
+    pub fn device_features(&self) -> DeviceFeatures {
+        self.context.device_features
+    }
+}
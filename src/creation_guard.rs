@@ -0,0 +1,121 @@
+use std::ops::{Deref, DerefMut};
+
+#[cfg(feature = "vlayers")]
+use ash::ext::debug_utils;
+use ash::{khr::surface, vk, Device, Instance};
+
+use crate::handle_registry;
+
+/// Destroys the instance, along with the debug messenger and the surface once they are created,
+/// when the context fails to be created. The device is the last object of the context, which
+/// destroys it along with the others once created.
+pub(crate) struct CreationGuard {
+    instance: Instance,
+    #[cfg(feature = "vlayers")]
+    debug_messenger: Option<(debug_utils::Instance, vk::DebugUtilsMessengerEXT)>,
+    surface: Option<(surface::Instance, vk::SurfaceKHR)>,
+    // Set once the context owns the objects
+    released: bool,
+}
+
+impl CreationGuard {
+    pub(crate) fn new(instance: &Instance) -> Self {
+        Self {
+            instance: instance.clone(),
+            #[cfg(feature = "vlayers")]
+            debug_messenger: None,
+            surface: None,
+            released: false,
+        }
+    }
+
+    #[cfg(feature = "vlayers")]
+    pub(crate) fn set_debug_messenger(
+        &mut self,
+        debug_util_ext: &debug_utils::Instance,
+        debug_messenger: vk::DebugUtilsMessengerEXT,
+    ) {
+        self.debug_messenger = Some((debug_util_ext.clone(), debug_messenger));
+    }
+
+    pub(crate) fn set_surface(&mut self, surface_ext: &surface::Instance, surface: vk::SurfaceKHR) {
+        self.surface = Some((surface_ext.clone(), surface));
+    }
+
+    /// Leaves the objects to the created context
+    pub(crate) fn release(mut self) {
+        self.released = true;
+    }
+}
+
+impl Drop for CreationGuard {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+
+        unsafe {
+            if let Some((surface_ext, surface)) = &self.surface {
+                handle_registry::unregister(*surface);
+                surface_ext.destroy_surface(*surface, None);
+            }
+            #[cfg(feature = "vlayers")]
+            if let Some((debug_util_ext, debug_messenger)) = &self.debug_messenger {
+                handle_registry::unregister(*debug_messenger);
+                debug_util_ext.destroy_debug_utils_messenger(*debug_messenger, None);
+            }
+            handle_registry::unregister(self.instance.handle());
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// Destroys an object created on the device with `destroy` when the application fails to be
+/// created. The guards are dropped in the reverse order of their declaration, the objects are
+/// destroyed in the reverse order of their creation and before the context they were created
+/// from.
+pub(crate) struct DeviceObjectGuard<T> {
+    device: Device,
+    // `None` once released
+    object: Option<T>,
+    destroy: fn(&mut T, &Device),
+}
+
+impl<T> DeviceObjectGuard<T> {
+    pub(crate) fn new(device: &Device, object: T, destroy: fn(&mut T, &Device)) -> Self {
+        Self {
+            device: device.clone(),
+            object: Some(object),
+            destroy,
+        }
+    }
+
+    /// Leaves the object to its owner once created
+    pub(crate) fn release(mut self) -> T {
+        self.object.take().unwrap()
+    }
+}
+
+impl<T> Deref for DeviceObjectGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.object.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for DeviceObjectGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.object.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for DeviceObjectGuard<T> {
+    fn drop(&mut self) {
+        if let Some(object) = &mut self.object {
+            // The creation error is the one reported, a failed wait is ignored
+            let _ = unsafe { self.device.device_wait_idle() };
+            (self.destroy)(object, &self.device);
+        }
+    }
+}
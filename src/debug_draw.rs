@@ -0,0 +1,272 @@
+use ash::{vk, Device, Instance};
+use cgmath::{EuclideanSpace, Transform};
+
+use crate::{
+    garbage_collector::{Garbage, GarbageCollector},
+    handle_registry, load_shader,
+    overlay::{create_overlay_pipeline, OverlayBlend},
+    Aabb, AppResult, Application, Mat4, MemoryMappedBuffer, PerFrame, Point3, Vec3,
+};
+
+// Segments of each of the three circles drawing a sphere
+const SPHERE_SEGMENTS: usize = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+// Vertex buffer of a frame in flight, grown when the lines outgrow it
+#[derive(Default)]
+struct LineBuffer {
+    vertex_buffer: Option<MemoryMappedBuffer>,
+    vertex_capacity: usize,
+    vertex_count: u32,
+}
+
+/// Draws the lines queued with the `Application::debug_draw_*` calls over the next frame, in the
+/// composite render pass. The lines are in world space, drawn over the scene without depth test.
+pub(crate) struct DebugDraw {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    buffers: PerFrame<LineBuffer>,
+
+    // Line list of the next frame
+    vertices: Vec<LineVertex>,
+}
+
+impl DebugDraw {
+    pub(crate) fn new(
+        device: &Device,
+        render_pass: vk::RenderPass,
+        frame_count: usize,
+    ) -> AppResult<Self> {
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<Mat4>() as u32,
+            }],
+        )?;
+        let pipeline = create_overlay_pipeline(
+            device,
+            render_pass,
+            vk::SampleCountFlags::TYPE_1,
+            pipeline_layout,
+            &load_shader("debug_line.vert", include_bytes!("spirv/debug_line.spv"))?,
+            &load_shader(
+                "debug_line_color.frag",
+                include_bytes!("spirv/debug_line_color.spv"),
+            )?,
+            &[vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: std::mem::size_of::<LineVertex>() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }],
+            &[
+                vk::VertexInputAttributeDescription {
+                    location: 0,
+                    binding: 0,
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: 0,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 1,
+                    binding: 0,
+                    format: vk::Format::R32G32B32A32_SFLOAT,
+                    offset: 12,
+                },
+            ],
+            vk::PrimitiveTopology::LINE_LIST,
+            OverlayBlend::Alpha,
+        )?;
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            buffers: PerFrame::new(frame_count, |_| Ok(LineBuffer::default()))?,
+            vertices: Vec::new(),
+        })
+    }
+
+    pub(crate) fn line(&mut self, from: Point3, to: Point3, color: [f32; 4]) {
+        self.vertices.extend([from, to].map(|position| LineVertex {
+            position: position.into(),
+            color,
+        }));
+    }
+
+    /// The twelve edges of `bounds` moved by `transform`
+    pub(crate) fn aabb(&mut self, bounds: Aabb, transform: Mat4, color: [f32; 4]) {
+        let corners: Vec<_> = (0..8)
+            .map(|i| {
+                let corner = |axis: usize| match i & (1 << axis) {
+                    0 => bounds.min[axis],
+                    _ => bounds.max[axis],
+                };
+                transform.transform_point(Point3::new(corner(0), corner(1), corner(2)))
+            })
+            .collect();
+
+        // The corners differing by a single bit share an edge
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], color);
+                }
+            }
+        }
+    }
+
+    /// A circle around each axis
+    pub(crate) fn sphere(&mut self, center: Point3, radius: f32, color: [f32; 4]) {
+        let axes = [
+            (Vec3::unit_x(), Vec3::unit_y()),
+            (Vec3::unit_y(), Vec3::unit_z()),
+            (Vec3::unit_z(), Vec3::unit_x()),
+        ];
+        for (u, v) in axes {
+            let point = |segment: usize| {
+                let angle = segment as f32 / SPHERE_SEGMENTS as f32 * std::f32::consts::TAU;
+                center + (u * angle.cos() + v * angle.sin()) * radius
+            };
+            for segment in 0..SPHERE_SEGMENTS {
+                self.line(point(segment), point(segment + 1), color);
+            }
+        }
+    }
+
+    /// The X, Y and Z axes of `transform` in red, green and blue, `size` long
+    pub(crate) fn axes(&mut self, transform: Mat4, size: f32) {
+        let origin = transform.transform_point(Point3::origin());
+        let axes = [
+            (Vec3::unit_x(), [1.0, 0.0, 0.0, 1.0]),
+            (Vec3::unit_y(), [0.0, 1.0, 0.0, 1.0]),
+            (Vec3::unit_z(), [0.0, 0.0, 1.0, 1.0]),
+        ];
+        for (axis, color) in axes {
+            let end = transform.transform_point(Point3::from_vec(axis * size));
+            self.line(origin, end, color);
+        }
+    }
+
+    /// Copies the queued lines to the vertex buffer of `frame`, which must not be in use by the
+    /// GPU, growing it if needed. The queue is emptied for the following frame.
+    pub(crate) fn upload(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        garbage_collector: &mut GarbageCollector,
+        frame: usize,
+    ) -> AppResult<()> {
+        let frame_count = self.buffers.len();
+        let buffers = &mut self.buffers[frame];
+        buffers.vertex_count = self.vertices.len() as u32;
+        if self.vertices.is_empty() {
+            return Ok(());
+        }
+
+        if buffers.vertex_capacity < self.vertices.len() {
+            let capacity = self.vertices.len().next_power_of_two();
+            let buffer = Application::create_host_visible_buffers(
+                instance,
+                device,
+                physical_device,
+                (capacity * std::mem::size_of::<LineVertex>()) as u64,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                1,
+            )?
+            .pop()
+            .unwrap();
+            if let Some(old) = buffers.vertex_buffer.replace(buffer) {
+                garbage_collector.retire(Garbage::Buffer(old.buffer, old.memory), frame_count);
+            }
+            buffers.vertex_capacity = capacity;
+        }
+
+        unsafe {
+            std::ptr::copy(
+                self.vertices.as_ptr(),
+                buffers.vertex_buffer.as_ref().unwrap().memory_map as *mut LineVertex,
+                self.vertices.len(),
+            );
+        }
+        self.vertices.clear();
+
+        Ok(())
+    }
+
+    /// Records the lines of `frame` seen through `view_proj`, in the composite render pass
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        frame: usize,
+        view_proj: Mat4,
+    ) {
+        let buffers = &self.buffers[frame];
+        let Some(vertex_buffer) = &buffers.vertex_buffer else {
+            return;
+        };
+        if buffers.vertex_count == 0 {
+            return;
+        }
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        }];
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                std::slice::from_raw_parts(
+                    &view_proj as *const Mat4 as *const u8,
+                    std::mem::size_of::<Mat4>(),
+                ),
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_draw(command_buffer, buffers.vertex_count, 1, 0, 0);
+        }
+    }
+
+    /// Destroys the buffers and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for buffers in self.buffers.iter_mut() {
+                if let Some(buffer) = buffers.vertex_buffer.take() {
+                    Garbage::Buffer(buffer.buffer, buffer.memory).destroy(device);
+                }
+            }
+
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,606 @@
+use std::time::{Duration, Instant};
+
+use ash::{vk, Device, Instance};
+use image::RgbaImage;
+
+use crate::{
+    handle_registry, load_shader,
+    overlay::{self, create_overlay_pipeline, OverlayBlend, OverlayVertex},
+    sync_pool::SyncPool,
+    texture_loader::DecodedTexture,
+    AppResult, Application, MemoryMappedBuffer, PerFrame, TextureHolder,
+};
+
+/// Period the frame statistics of the debug HUD are averaged over
+pub const HUD_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+// Characters drawn by the HUD at most, the following ones being left out
+const MAX_HUD_CHARS: usize = 512;
+// Screen pixels per pixel of the font
+const HUD_SCALE: f32 = 2.0;
+// Distance of the text to the corner of the screen and to the edges of its background
+const HUD_MARGIN: f32 = 8.0;
+
+// Glyphs of the built-in font, 3 pixels wide and 5 high, one row per byte with the left pixel as
+// the highest bit. The lowercase letters are drawn as the uppercase ones.
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+const CHARSET: &str = " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ.:-_/()%?";
+#[rustfmt::skip]
+const GLYPHS: [[u8; GLYPH_HEIGHT as usize]; 46] = [
+    [0b000, 0b000, 0b000, 0b000, 0b000], // ' '
+    [0b111, 0b101, 0b101, 0b101, 0b111], // 0
+    [0b010, 0b110, 0b010, 0b010, 0b111], // 1
+    [0b111, 0b001, 0b111, 0b100, 0b111], // 2
+    [0b111, 0b001, 0b111, 0b001, 0b111], // 3
+    [0b101, 0b101, 0b111, 0b001, 0b001], // 4
+    [0b111, 0b100, 0b111, 0b001, 0b111], // 5
+    [0b111, 0b100, 0b111, 0b101, 0b111], // 6
+    [0b111, 0b001, 0b001, 0b001, 0b001], // 7
+    [0b111, 0b101, 0b111, 0b101, 0b111], // 8
+    [0b111, 0b101, 0b111, 0b001, 0b111], // 9
+    [0b010, 0b101, 0b111, 0b101, 0b101], // A
+    [0b110, 0b101, 0b110, 0b101, 0b110], // B
+    [0b011, 0b100, 0b100, 0b100, 0b011], // C
+    [0b110, 0b101, 0b101, 0b101, 0b110], // D
+    [0b111, 0b100, 0b110, 0b100, 0b111], // E
+    [0b111, 0b100, 0b110, 0b100, 0b100], // F
+    [0b011, 0b100, 0b101, 0b101, 0b011], // G
+    [0b101, 0b101, 0b111, 0b101, 0b101], // H
+    [0b111, 0b010, 0b010, 0b010, 0b111], // I
+    [0b001, 0b001, 0b001, 0b101, 0b010], // J
+    [0b101, 0b101, 0b110, 0b101, 0b101], // K
+    [0b100, 0b100, 0b100, 0b100, 0b111], // L
+    [0b101, 0b111, 0b111, 0b101, 0b101], // M
+    [0b110, 0b101, 0b101, 0b101, 0b101], // N
+    [0b010, 0b101, 0b101, 0b101, 0b010], // O
+    [0b110, 0b101, 0b110, 0b100, 0b100], // P
+    [0b010, 0b101, 0b101, 0b110, 0b011], // Q
+    [0b110, 0b101, 0b110, 0b101, 0b101], // R
+    [0b011, 0b100, 0b010, 0b001, 0b110], // S
+    [0b111, 0b010, 0b010, 0b010, 0b010], // T
+    [0b101, 0b101, 0b101, 0b101, 0b111], // U
+    [0b101, 0b101, 0b101, 0b101, 0b010], // V
+    [0b101, 0b101, 0b111, 0b111, 0b101], // W
+    [0b101, 0b101, 0b010, 0b101, 0b101], // X
+    [0b101, 0b101, 0b010, 0b010, 0b010], // Y
+    [0b111, 0b001, 0b010, 0b100, 0b111], // Z
+    [0b000, 0b000, 0b000, 0b000, 0b010], // .
+    [0b000, 0b010, 0b000, 0b010, 0b000], // :
+    [0b000, 0b000, 0b111, 0b000, 0b000], // -
+    [0b000, 0b000, 0b000, 0b000, 0b111], // _
+    [0b001, 0b001, 0b010, 0b100, 0b100], // /
+    [0b010, 0b100, 0b100, 0b100, 0b010], // (
+    [0b010, 0b001, 0b001, 0b001, 0b010], // )
+    [0b101, 0b001, 0b010, 0b100, 0b101], // %
+    [0b111, 0b001, 0b010, 0b000, 0b010], // ?
+];
+// Cells of the glyphs in the atlas, padded so the neighbours don't bleed. A last, fully opaque,
+// cell is sampled by the background.
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: u32 = GLYPH_HEIGHT + 1;
+const SOLID_CELL: usize = GLYPHS.len();
+
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BACKGROUND_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+
+/// Frame statistics shown by the debug HUD, averaged over `HUD_REFRESH_INTERVAL`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    pub fps: f32,
+    /// Time spent preparing and submitting a frame on the CPU, in milliseconds
+    pub cpu_frame_time: f32,
+    /// Time the GPU spent executing a frame, in milliseconds. `None` when the graphics queue
+    /// doesn't support timestamps or before the first measures.
+    pub gpu_frame_time: Option<f32>,
+    /// Draw calls of the scene render pass in the last frame
+    pub draw_calls: u32,
+}
+
+/// Swapchain properties listed by the HUD
+pub(crate) struct SwapchainInfo {
+    pub(crate) extent: vk::Extent2D,
+    pub(crate) format: vk::Format,
+    pub(crate) present_mode: vk::PresentModeKHR,
+    pub(crate) image_count: usize,
+}
+
+/// Overlay listing the frame statistics and the swapchain properties in the top left corner of
+/// the screen, drawn with a built-in bitmap font so it doesn't depend on any UI integration.
+///
+/// The GPU frame time is measured with a pair of timestamps around the command buffer of each
+/// frame, read back once the frame is done.
+pub(crate) struct DebugHud {
+    visible: bool,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    sampler: vk::Sampler,
+    font_atlas: TextureHolder,
+    vertex_buffers: PerFrame<MemoryMappedBuffer>,
+    vertex_counts: Vec<u32>,
+
+    // Two timestamps per frame in flight, null when the graphics queue doesn't support them
+    query_pool: vk::QueryPool,
+    // Nanoseconds per timestamp tick
+    timestamp_period: f32,
+    // Whether the timestamps of each frame were written since the HUD was shown
+    timestamps_written: Vec<bool>,
+
+    last_frame: Option<Instant>,
+    elapsed: Duration,
+    frame_count: u32,
+    cpu_time: Duration,
+    gpu_time: f32,
+    gpu_frame_count: u32,
+    draw_calls: u32,
+    stats: FrameStats,
+}
+
+impl DebugHud {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        queue: vk::Queue,
+        queue_family: u32,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        render_pass: vk::RenderPass,
+        frame_count: usize,
+    ) -> AppResult<Self> {
+        let font_atlas = Application::upload_textures(
+            instance,
+            device,
+            queue,
+            physical_device,
+            command_pool,
+            sync_pool,
+            &mut [DecodedTexture::from_rgba(vec![Self::font_atlas()])],
+        )?
+        .pop()
+        .unwrap();
+
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+        handle_registry::register(sampler);
+
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        }];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: 1,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &set_layout,
+            ..Default::default()
+        };
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+        let image_info = vk::DescriptorImageInfo {
+            sampler,
+            image_view: font_atlas.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: &image_info,
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<[f32; 2]>() as u32,
+            }],
+        )?;
+        let pipeline = create_overlay_pipeline(
+            device,
+            render_pass,
+            vk::SampleCountFlags::TYPE_1,
+            pipeline_layout,
+            &load_shader("overlay.vert", include_bytes!("spirv/overlay.spv"))?,
+            &load_shader("hud_text.frag", include_bytes!("spirv/hud_text.spv"))?,
+            &OverlayVertex::bindings(),
+            &OverlayVertex::attributes(),
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            OverlayBlend::Alpha,
+        )?;
+
+        // The text and its background
+        let vertex_capacity = 6 * (MAX_HUD_CHARS + 1);
+        let vertex_buffers = Application::create_host_visible_buffers(
+            instance,
+            device,
+            physical_device,
+            (vertex_capacity * std::mem::size_of::<OverlayVertex>()) as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            frame_count,
+        )?
+        .into_iter()
+        .collect();
+
+        let (query_pool, timestamp_period) =
+            Self::create_query_pool(instance, device, physical_device, queue_family, frame_count)?;
+
+        Ok(Self {
+            visible: false,
+            pipeline,
+            pipeline_layout,
+            set_layout,
+            descriptor_pool,
+            descriptor_set,
+            sampler,
+            font_atlas,
+            vertex_buffers,
+            vertex_counts: vec![0; frame_count],
+            query_pool,
+            timestamp_period,
+            timestamps_written: vec![false; frame_count],
+            last_frame: None,
+            elapsed: Duration::ZERO,
+            frame_count: 0,
+            cpu_time: Duration::ZERO,
+            gpu_time: 0.0,
+            gpu_frame_count: 0,
+            draw_calls: 0,
+            stats: FrameStats::default(),
+        })
+    }
+
+    pub(crate) fn set_visible(&mut self, visible: bool) {
+        if !visible {
+            self.timestamps_written.fill(false);
+        }
+        self.visible = visible;
+    }
+
+    pub(crate) fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub(crate) fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// Reads the GPU time of the previous use of `frame`, once its fence is waited on
+    pub(crate) fn read_gpu_time(&mut self, device: &Device, frame: usize) {
+        if !self.timestamps_written[frame] {
+            return;
+        }
+        self.timestamps_written[frame] = false;
+
+        let mut timestamps = [0u64; 2];
+        let result = unsafe {
+            device.get_query_pool_results(
+                self.query_pool,
+                2 * frame as u32,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        // Not ready when the frame was never submitted, the measure is skipped
+        if result.is_ok() {
+            let ticks = timestamps[1].saturating_sub(timestamps[0]);
+            self.gpu_time += ticks as f32 * self.timestamp_period / 1_000_000.0;
+            self.gpu_frame_count += 1;
+        }
+    }
+
+    /// Records the timestamp starting the measure of `frame`, at the beginning of its command
+    /// buffer
+    pub(crate) fn begin_timing(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        if !self.visible || self.query_pool == vk::QueryPool::null() {
+            return;
+        }
+
+        unsafe {
+            device.cmd_reset_query_pool(command_buffer, self.query_pool, 2 * frame as u32, 2);
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                2 * frame as u32,
+            );
+        }
+    }
+
+    /// Records the timestamp ending the measure of `frame`, at the end of its command buffer
+    pub(crate) fn end_timing(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        if !self.visible || self.query_pool == vk::QueryPool::null() {
+            return;
+        }
+
+        unsafe {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                2 * frame as u32 + 1,
+            );
+        }
+        self.timestamps_written[frame] = true;
+    }
+
+    /// Accounts for a frame submitted after `cpu_time` of work, the scene render pass having
+    /// recorded `draw_calls` draws
+    pub(crate) fn end_frame(&mut self, cpu_time: Duration, draw_calls: u32) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            self.elapsed += now - last_frame;
+            self.frame_count += 1;
+            self.cpu_time += cpu_time;
+        }
+        self.draw_calls = draw_calls;
+
+        if self.elapsed < HUD_REFRESH_INTERVAL || self.frame_count == 0 {
+            return;
+        }
+
+        self.stats = FrameStats {
+            fps: self.frame_count as f32 / self.elapsed.as_secs_f32(),
+            cpu_frame_time: self.cpu_time.as_secs_f32() * 1000.0 / self.frame_count as f32,
+            gpu_frame_time: (self.gpu_frame_count > 0)
+                .then(|| self.gpu_time / self.gpu_frame_count as f32)
+                .or(self.stats.gpu_frame_time),
+            draw_calls,
+        };
+        self.elapsed = Duration::ZERO;
+        self.frame_count = 0;
+        self.cpu_time = Duration::ZERO;
+        self.gpu_time = 0.0;
+        self.gpu_frame_count = 0;
+    }
+
+    /// Writes the text of the HUD to the vertex buffer of `frame`, which must not be in use by
+    /// the GPU
+    pub(crate) fn upload(&mut self, frame: usize, swapchain: &SwapchainInfo) {
+        if !self.visible {
+            self.vertex_counts[frame] = 0;
+            return;
+        }
+
+        let gpu_frame_time = match self.stats.gpu_frame_time {
+            Some(time) => format!("{:.2} MS", time),
+            None => String::from("N/A"),
+        };
+        let lines = [
+            format!("FPS {:.1}", self.stats.fps),
+            format!("CPU {:.2} MS", self.stats.cpu_frame_time),
+            format!("GPU {}", gpu_frame_time),
+            format!("DRAWS {}", self.draw_calls),
+            format!(
+                "{}X{} {:?}",
+                swapchain.extent.width, swapchain.extent.height, swapchain.format
+            ),
+            format!(
+                "{:?} {} IMAGES",
+                swapchain.present_mode, swapchain.image_count
+            ),
+        ];
+
+        let advance = CELL_WIDTH as f32 * HUD_SCALE;
+        let line_height = (CELL_HEIGHT + 1) as f32 * HUD_SCALE;
+        let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+
+        let mut vertices = Vec::with_capacity(6 * (MAX_HUD_CHARS + 1));
+        Self::push_cell(
+            &mut vertices,
+            [0.0, 0.0],
+            [
+                2.0 * HUD_MARGIN + columns as f32 * advance,
+                2.0 * HUD_MARGIN + lines.len() as f32 * line_height,
+            ],
+            SOLID_CELL,
+            BACKGROUND_COLOR,
+        );
+        let chars = lines.iter().enumerate().flat_map(|(row, line)| {
+            line.chars()
+                .enumerate()
+                .map(move |(column, c)| (row, column, c))
+        });
+        for (row, column, c) in chars.take(MAX_HUD_CHARS) {
+            let glyph = CHARSET
+                .find(c.to_ascii_uppercase())
+                .unwrap_or(CHARSET.len() - 1);
+            if glyph == 0 {
+                continue;
+            }
+
+            let position = [
+                HUD_MARGIN + column as f32 * advance,
+                HUD_MARGIN + row as f32 * line_height,
+            ];
+            let size = [
+                GLYPH_WIDTH as f32 * HUD_SCALE,
+                GLYPH_HEIGHT as f32 * HUD_SCALE,
+            ];
+            Self::push_cell(&mut vertices, position, size, glyph, TEXT_COLOR);
+        }
+
+        unsafe {
+            std::ptr::copy(
+                vertices.as_ptr(),
+                self.vertex_buffers[frame].memory_map as *mut OverlayVertex,
+                vertices.len(),
+            );
+        }
+        self.vertex_counts[frame] = vertices.len() as u32;
+    }
+
+    /// Records the HUD of `frame`, in the composite render pass
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        frame: usize,
+    ) {
+        if !self.visible || self.vertex_counts[frame] == 0 {
+            return;
+        }
+
+        overlay::bind_overlay_pipeline(
+            device,
+            command_buffer,
+            self.pipeline,
+            self.pipeline_layout,
+            self.descriptor_set,
+            extent,
+        );
+        unsafe {
+            device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[self.vertex_buffers[frame].buffer],
+                &[0],
+            );
+            device.cmd_draw(command_buffer, self.vertex_counts[frame], 1, 0, 0);
+        }
+    }
+
+    /// Destroys the buffers, the queries, the font atlas and the pipeline, the device must be
+    /// idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for buffer in self.vertex_buffers.iter() {
+                handle_registry::unregister(buffer.buffer);
+                device.destroy_buffer(buffer.buffer, None);
+                handle_registry::unregister(buffer.memory);
+                device.free_memory(buffer.memory, None);
+            }
+            if self.query_pool != vk::QueryPool::null() {
+                handle_registry::unregister(self.query_pool);
+                device.destroy_query_pool(self.query_pool, None);
+            }
+
+            handle_registry::unregister(self.font_atlas.view);
+            device.destroy_image_view(self.font_atlas.view, None);
+            handle_registry::unregister(self.font_atlas.image.image);
+            device.destroy_image(self.font_atlas.image.image, None);
+            handle_registry::unregister(self.font_atlas.image.memory);
+            device.free_memory(self.font_atlas.image.memory, None);
+            handle_registry::unregister(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.descriptor_pool);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            handle_registry::unregister(self.set_layout);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+
+    /// White glyphs on a transparent background, one cell per glyph then the solid cell
+    fn font_atlas() -> RgbaImage {
+        let width = CELL_WIDTH * (GLYPHS.len() as u32 + 1);
+        RgbaImage::from_fn(width, CELL_HEIGHT, |x, y| {
+            let cell = (x / CELL_WIDTH) as usize;
+            let (column, row) = (x % CELL_WIDTH, y);
+            let lit = match GLYPHS.get(cell) {
+                Some(glyph) => {
+                    column < GLYPH_WIDTH
+                        && row < GLYPH_HEIGHT
+                        && glyph[row as usize] & (1 << (GLYPH_WIDTH - 1 - column)) != 0
+                }
+                None => true,
+            };
+            image::Rgba([255, 255, 255, if lit { 255 } else { 0 }])
+        })
+    }
+
+    // Quad covering `size` pixels from `position`, sampling the glyph of `cell`
+    fn push_cell(
+        vertices: &mut Vec<OverlayVertex>,
+        position: [f32; 2],
+        size: [f32; 2],
+        cell: usize,
+        color: [f32; 4],
+    ) {
+        let atlas_width = (CELL_WIDTH * (GLYPHS.len() as u32 + 1)) as f32;
+        let u0 = (cell as u32 * CELL_WIDTH) as f32 / atlas_width;
+        let u1 = (cell as u32 * CELL_WIDTH + GLYPH_WIDTH) as f32 / atlas_width;
+        let v1 = GLYPH_HEIGHT as f32 / CELL_HEIGHT as f32;
+        overlay::push_quad(vertices, position, size, [u0, 0.0], [u1, v1], color);
+    }
+
+    fn create_query_pool(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        queue_family: u32,
+        frame_count: usize,
+    ) -> AppResult<(vk::QueryPool, f32)> {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let queue_families =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+        if queue_families[queue_family as usize].timestamp_valid_bits == 0 {
+            return Ok((vk::QueryPool::null(), 0.0));
+        }
+
+        let pool_info = vk::QueryPoolCreateInfo {
+            query_type: vk::QueryType::TIMESTAMP,
+            query_count: 2 * frame_count as u32,
+            ..Default::default()
+        };
+        let query_pool = unsafe { device.create_query_pool(&pool_info, None)? };
+        handle_registry::register(query_pool);
+
+        Ok((query_pool, properties.limits.timestamp_period))
+    }
+}
@@ -0,0 +1,169 @@
+use ash::{vk, Device};
+
+use crate::{handle_registry, AppResult};
+
+/// Usage of the descriptor pools of the scene sets, see `Application::descriptor_pool_stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DescriptorPoolStats {
+    pub pool_count: usize,
+    /// Sets the pools can hold
+    pub capacity: u32,
+    /// Sets allocated from the pools, kept until the application is destroyed
+    pub allocated: u32,
+    /// Highest number of sets used by the drawn materials at once, over the frames in flight
+    pub peak_used: u32,
+    /// Sets released by their owner, reused by the next allocations once no frame in flight
+    /// reads them
+    pub recycled: u32,
+}
+
+/// Descriptor pools of a single set layout, a new pool doubling the capacity being created
+/// whenever the sets don't fit anymore. A pool running out of descriptors before running out of
+/// sets, e.g. when fragmented, is left for a new one as well.
+///
+/// The sets released by their owner with `retire` are recycled once the frames in flight are
+/// done with them, the other ones being destroyed with the pools.
+pub(crate) struct DescriptorAllocator {
+    // Descriptors of each type a set of the layout holds
+    set_sizes: Vec<vk::DescriptorPoolSize>,
+    // Pools along with the sets they can still hold, the last one being allocated from
+    pools: Vec<(vk::DescriptorPool, u32)>,
+    // Released sets along with the number of frames left before they can be reused
+    retired: Vec<(vk::DescriptorSet, usize)>,
+    free_sets: Vec<vk::DescriptorSet>,
+    stats: DescriptorPoolStats,
+}
+
+impl DescriptorAllocator {
+    pub(crate) fn new(set_sizes: Vec<vk::DescriptorPoolSize>) -> Self {
+        Self {
+            set_sizes,
+            pools: Vec::new(),
+            retired: Vec::new(),
+            free_sets: Vec::new(),
+            stats: DescriptorPoolStats::default(),
+        }
+    }
+
+    /// Allocates `count` sets of `layout`, reusing the recycled sets first and creating a pool
+    /// for the others when the last one is full. The recycled sets keep their previous
+    /// descriptors until written.
+    pub(crate) fn allocate(
+        &mut self,
+        device: &Device,
+        layout: vk::DescriptorSetLayout,
+        count: u32,
+    ) -> AppResult<Vec<vk::DescriptorSet>> {
+        let reused = self.free_sets.len().min(count as usize);
+        let mut sets = self.free_sets.split_off(self.free_sets.len() - reused);
+        self.stats.recycled -= reused as u32;
+        let count = count - reused as u32;
+        if count == 0 {
+            return Ok(sets);
+        }
+        if self.pools.last().is_none_or(|&(_, free)| free < count) {
+            self.add_pool(device, count.max(self.stats.capacity))?;
+        }
+
+        let layouts = vec![layout; count as usize];
+        let allocated = match self.allocate_from_last_pool(device, &layouts) {
+            // The pool ran out of descriptors, the sets go to a new one
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
+                self.add_pool(device, count.max(self.stats.capacity))?;
+                self.allocate_from_last_pool(device, &layouts)?
+            }
+            result => result?,
+        };
+        self.stats.allocated += count;
+        sets.extend(allocated);
+
+        Ok(sets)
+    }
+
+    fn allocate_from_last_pool(
+        &mut self,
+        device: &Device,
+        layouts: &[vk::DescriptorSetLayout],
+    ) -> Result<Vec<vk::DescriptorSet>, vk::Result> {
+        let (pool, free) = self.pools.last_mut().unwrap();
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool: *pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+        let sets = unsafe { device.allocate_descriptor_sets(&alloc_info)? };
+        *free -= layouts.len() as u32;
+
+        Ok(sets)
+    }
+
+    /// Releases `sets`, to be reused once `frame_count` frames are collected
+    pub(crate) fn retire(&mut self, sets: &[vk::DescriptorSet], frame_count: usize) {
+        self.retired
+            .extend(sets.iter().map(|&set| (set, frame_count)));
+    }
+
+    /// Recycles the retired sets no frame in flight can read anymore. To call once per frame
+    /// after waiting for the previous use of its resources.
+    pub(crate) fn collect(&mut self) {
+        for (_, frames_left) in self.retired.iter_mut() {
+            *frames_left = frames_left.saturating_sub(1);
+        }
+        let free_sets = &mut self.free_sets;
+        self.retired.retain(|&(set, frames_left)| {
+            if frames_left > 0 {
+                return true;
+            }
+            free_sets.push(set);
+            false
+        });
+        self.stats.recycled = self.free_sets.len() as u32;
+    }
+
+    fn add_pool(&mut self, device: &Device, set_count: u32) -> AppResult<()> {
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = self
+            .set_sizes
+            .iter()
+            .map(|size| vk::DescriptorPoolSize {
+                ty: size.ty,
+                descriptor_count: size.descriptor_count * set_count,
+            })
+            .collect();
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: set_count,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        self.pools
+            .push((handle_registry::register(pool), set_count));
+        self.stats.pool_count += 1;
+        self.stats.capacity += set_count;
+
+        Ok(())
+    }
+
+    /// Records that `count` sets are in use, for the peak of `stats`
+    pub(crate) fn record_usage(&mut self, count: u32) {
+        self.stats.peak_used = self.stats.peak_used.max(count);
+    }
+
+    pub(crate) fn stats(&self) -> DescriptorPoolStats {
+        self.stats
+    }
+
+    /// Destroys the pools along with their sets, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        for (pool, _) in self.pools.drain(..) {
+            unsafe {
+                handle_registry::unregister(pool);
+                device.destroy_descriptor_pool(pool, None);
+            }
+        }
+        self.retired.clear();
+        self.free_sets.clear();
+        self.stats = DescriptorPoolStats::default();
+    }
+}
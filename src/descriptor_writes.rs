@@ -0,0 +1,210 @@
+use std::ops::Range;
+
+use ash::{vk, Device};
+
+use crate::{handle_registry, AppResult};
+
+enum WriteInfos {
+    Images(Range<usize>),
+    Buffers(Range<usize>),
+}
+
+struct PendingWrite {
+    dst_set: vk::DescriptorSet,
+    dst_binding: u32,
+    descriptor_type: vk::DescriptorType,
+    infos: WriteInfos,
+}
+
+/// Descriptor writes gathered from any number of sets and applied with a single
+/// `vkUpdateDescriptorSets`.
+///
+/// The infos are copied in lists kept from one flush to the next, and the writes only point to
+/// them once no more infos can be added, so they can't be moved while referenced.
+#[derive(Default)]
+pub(crate) struct DescriptorWriteBatch {
+    pending: Vec<PendingWrite>,
+    image_infos: Vec<vk::DescriptorImageInfo>,
+    buffer_infos: Vec<vk::DescriptorBufferInfo>,
+    writes: Vec<vk::WriteDescriptorSet<'static>>,
+}
+
+impl DescriptorWriteBatch {
+    /// Writes `image_infos` to the consecutive array elements of `dst_binding` and the
+    /// following bindings of `dst_set`
+    pub(crate) fn write_images(
+        &mut self,
+        dst_set: vk::DescriptorSet,
+        dst_binding: u32,
+        descriptor_type: vk::DescriptorType,
+        image_infos: impl IntoIterator<Item = vk::DescriptorImageInfo>,
+    ) {
+        let start = self.image_infos.len();
+        self.image_infos.extend(image_infos);
+        self.pending.push(PendingWrite {
+            dst_set,
+            dst_binding,
+            descriptor_type,
+            infos: WriteInfos::Images(start..self.image_infos.len()),
+        });
+    }
+
+    /// Writes `buffer_infos` to the consecutive array elements of `dst_binding` and the
+    /// following bindings of `dst_set`
+    pub(crate) fn write_buffers(
+        &mut self,
+        dst_set: vk::DescriptorSet,
+        dst_binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer_infos: impl IntoIterator<Item = vk::DescriptorBufferInfo>,
+    ) {
+        let start = self.buffer_infos.len();
+        self.buffer_infos.extend(buffer_infos);
+        self.pending.push(PendingWrite {
+            dst_set,
+            dst_binding,
+            descriptor_type,
+            infos: WriteInfos::Buffers(start..self.buffer_infos.len()),
+        });
+    }
+
+    /// Applies the gathered writes, the sets written must not be in use by the GPU
+    pub(crate) fn flush(&mut self, device: &Device) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        for write in self.pending.drain(..) {
+            let (descriptor_count, p_image_info, p_buffer_info) = match write.infos {
+                WriteInfos::Images(range) => (
+                    range.len(),
+                    self.image_infos[range].as_ptr(),
+                    std::ptr::null(),
+                ),
+                WriteInfos::Buffers(range) => (
+                    range.len(),
+                    std::ptr::null(),
+                    self.buffer_infos[range].as_ptr(),
+                ),
+            };
+            self.writes.push(vk::WriteDescriptorSet {
+                dst_set: write.dst_set,
+                dst_binding: write.dst_binding,
+                dst_array_element: 0,
+                descriptor_count: descriptor_count as u32,
+                descriptor_type: write.descriptor_type,
+                p_image_info,
+                p_buffer_info,
+                ..Default::default()
+            });
+        }
+
+        unsafe { device.update_descriptor_sets(&self.writes, &[]) };
+
+        self.writes.clear();
+        self.image_infos.clear();
+        self.buffer_infos.clear();
+    }
+}
+
+/// Descriptors of a scene set, laid out as the entries of its update template
+#[repr(C)]
+pub(crate) struct SceneSetData {
+    pub(crate) uniform_buffer: vk::DescriptorBufferInfo,
+    // The texture and the lightmap
+    pub(crate) images: [vk::DescriptorImageInfo; 2],
+}
+
+/// Writes every binding of a scene set at once through a descriptor update template, instead of
+/// building a `vk::WriteDescriptorSet` per binding.
+///
+/// The templates are core since Vulkan 1.1, the sets of the older devices are written through a
+/// `DescriptorWriteBatch` instead.
+pub(crate) struct SceneSetWriter {
+    template: Option<vk::DescriptorUpdateTemplate>,
+}
+
+impl SceneSetWriter {
+    pub(crate) fn new(
+        device: &Device,
+        set_layout: vk::DescriptorSetLayout,
+        api_version: u32,
+    ) -> AppResult<Self> {
+        if api_version < vk::API_VERSION_1_1 {
+            return Ok(Self { template: None });
+        }
+
+        let entries = [
+            vk::DescriptorUpdateTemplateEntry {
+                dst_binding: 0,
+                dst_array_element: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                offset: std::mem::offset_of!(SceneSetData, uniform_buffer),
+                stride: std::mem::size_of::<vk::DescriptorBufferInfo>(),
+            },
+            // The texture and the lightmap are consecutive bindings, written by a single entry
+            vk::DescriptorUpdateTemplateEntry {
+                dst_binding: 1,
+                dst_array_element: 0,
+                descriptor_count: 2,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                offset: std::mem::offset_of!(SceneSetData, images),
+                stride: std::mem::size_of::<vk::DescriptorImageInfo>(),
+            },
+        ];
+        let template_info = vk::DescriptorUpdateTemplateCreateInfo {
+            descriptor_update_entry_count: entries.len() as u32,
+            p_descriptor_update_entries: entries.as_ptr(),
+            template_type: vk::DescriptorUpdateTemplateType::DESCRIPTOR_SET,
+            descriptor_set_layout: set_layout,
+            ..Default::default()
+        };
+        let template = unsafe { device.create_descriptor_update_template(&template_info, None)? };
+
+        Ok(Self {
+            template: Some(handle_registry::register(template)),
+        })
+    }
+
+    /// Writes `data` to `descriptor_set` right away, or once `descriptor_writes` is flushed
+    /// without a template. The set must not be in use by the GPU.
+    pub(crate) fn write(
+        &self,
+        device: &Device,
+        descriptor_writes: &mut DescriptorWriteBatch,
+        descriptor_set: vk::DescriptorSet,
+        data: &SceneSetData,
+    ) {
+        match self.template {
+            Some(template) => unsafe {
+                device.update_descriptor_set_with_template(
+                    descriptor_set,
+                    template,
+                    (data as *const SceneSetData).cast(),
+                );
+            },
+            None => {
+                descriptor_writes.write_buffers(
+                    descriptor_set,
+                    0,
+                    vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                    [data.uniform_buffer],
+                );
+                descriptor_writes.write_images(
+                    descriptor_set,
+                    1,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    data.images,
+                );
+            }
+        }
+    }
+
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        if let Some(template) = self.template.take() {
+            handle_registry::unregister(template);
+            unsafe { device.destroy_descriptor_update_template(template, None) };
+        }
+    }
+}
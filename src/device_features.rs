@@ -0,0 +1,28 @@
+use crate::PortabilitySubset;
+
+/// Optional features enabled on the logical device.
+///
+/// The Vulkan 1.3 features are only enabled when both the instance and the physical device
+/// support Vulkan 1.3.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceFeatures {
+    pub synchronization2: bool,
+    pub dynamic_rendering: bool,
+    /// Sparse residency of 2D images on the graphics queue, needed by virtual textures
+    pub sparse_residency: bool,
+    /// Sampling of the BC1 to BC7 block-compressed textures, e.g. loaded from DDS files
+    pub texture_compression_bc: bool,
+    /// Line polygon mode, needed by the wireframe rendering
+    pub fill_mode_non_solid: bool,
+    /// Task and mesh shaders of `VK_EXT_mesh_shader`, needed by the meshlet rendering
+    pub mesh_shader: bool,
+    /// Acceleration structures and ray tracing pipelines of `VK_KHR_ray_tracing_pipeline`,
+    /// needed by the ray traced image of the scene
+    pub ray_tracing: bool,
+    /// Acceleration structures and ray queries of `VK_KHR_ray_query`, needed by the ray traced
+    /// shadows of the PBR materials
+    pub ray_query: bool,
+    /// Features missing on a portability implementation like MoltenVK, `None` when the device is
+    /// fully conformant
+    pub portability_subset: Option<PortabilitySubset>,
+}
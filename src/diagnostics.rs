@@ -0,0 +1,330 @@
+use std::ffi::{c_char, CStr, CString};
+
+use ash::{ext::headless_surface, khr, khr::surface, vk, Entry, Instance};
+
+use crate::{
+    context::Context, handle_registry, texture_loader, AppResult, SurfaceHodlder, DEVICE_EXTENSIONS,
+};
+
+// Texture formats whose support is reported, the ones the loaders upload
+const REPORTED_FORMATS: &[vk::Format] = &[
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::BC1_RGBA_SRGB_BLOCK,
+    vk::Format::BC3_SRGB_BLOCK,
+    vk::Format::BC5_UNORM_BLOCK,
+    vk::Format::BC6H_UFLOAT_BLOCK,
+    vk::Format::BC7_SRGB_BLOCK,
+    vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+    vk::Format::ASTC_4X4_SRGB_BLOCK,
+];
+
+/// A queue family of a physical device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueueFamilyReport {
+    pub index: u32,
+    pub flags: vk::QueueFlags,
+    pub queue_count: u32,
+    /// Whether the family can present, `None` when no surface could be created to ask
+    pub present: Option<bool>,
+}
+
+/// What the application needs to know about a physical device to pick it
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceReport {
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub api_version: u32,
+    pub driver_version: u32,
+    pub queue_families: Vec<QueueFamilyReport>,
+    /// Device extensions required by the application which the device lacks
+    pub missing_extensions: Vec<String>,
+    pub sampler_anisotropy: bool,
+    /// Support of the texture formats the application can upload
+    pub texture_formats: Vec<(vk::Format, bool)>,
+    /// Formats and present modes of a headless surface, empty when none could be created
+    pub surface_formats: Vec<vk::SurfaceFormatKHR>,
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    /// Whether `Application::create` would accept the device, `None` when no surface could be
+    /// created to check the presentation support
+    pub suitable: Option<bool>,
+}
+
+/// Report of the Vulkan environment built by `Application::diagnose`, printable as text
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostics {
+    pub loader_version: u32,
+    pub layers: Vec<(String, u32)>,
+    pub extensions: Vec<String>,
+    /// Whether a headless surface could be created to query the presentation support
+    pub headless_surface: bool,
+    pub devices: Vec<DeviceReport>,
+}
+
+fn name_to_string(name: &[c_char]) -> String {
+    unsafe { CStr::from_ptr(name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn format_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+/// Enumerates the instance and the physical devices with an instance of its own, without
+/// opening a window. A headless surface stands in for the window surface when avaible.
+pub(crate) fn collect(entry: &Entry) -> AppResult<Diagnostics> {
+    let loader_version =
+        unsafe { entry.try_enumerate_instance_version()? }.unwrap_or(vk::API_VERSION_1_0);
+    let layers = unsafe { entry.enumerate_instance_layer_properties()? }
+        .iter()
+        .map(|layer| (name_to_string(&layer.layer_name), layer.spec_version))
+        .collect();
+    let avaible_extensions = unsafe { entry.enumerate_instance_extension_properties(None)? };
+    let extensions: Vec<String> = avaible_extensions
+        .iter()
+        .map(|ext| name_to_string(&ext.extension_name))
+        .collect();
+    let has_extension = |name: &CStr| {
+        extensions
+            .iter()
+            .any(|ext| ext.as_bytes() == name.to_bytes())
+    };
+
+    // The headless surface needs the surface extension, the portability enumeration lists the
+    // portability implementations
+    let headless_surface = has_extension(headless_surface::NAME) && has_extension(surface::NAME);
+    let mut enabled_extensions = vec![];
+    let mut flags = vk::InstanceCreateFlags::empty();
+    if headless_surface {
+        enabled_extensions.push(surface::NAME.as_ptr());
+        enabled_extensions.push(headless_surface::NAME.as_ptr());
+    }
+    if has_extension(khr::portability_enumeration::NAME) {
+        enabled_extensions.push(khr::portability_enumeration::NAME.as_ptr());
+        flags |= vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
+    }
+
+    let app_name = CString::new("Vulkan Tutorial diagnostics").unwrap();
+    let app_info = vk::ApplicationInfo {
+        p_application_name: app_name.as_ptr(),
+        api_version: Context::negotiate_instance_version(entry)?,
+        ..Default::default()
+    };
+    let create_info = vk::InstanceCreateInfo {
+        flags,
+        p_application_info: &app_info as *const _,
+        enabled_extension_count: enabled_extensions.len() as u32,
+        pp_enabled_extension_names: enabled_extensions.as_ptr(),
+        ..Default::default()
+    };
+    let instance = unsafe { entry.create_instance(&create_info, None)? };
+    handle_registry::register(instance.handle());
+
+    let devices = collect_devices(entry, &instance, headless_surface);
+
+    unsafe {
+        handle_registry::unregister(instance.handle());
+        instance.destroy_instance(None);
+    }
+
+    Ok(Diagnostics {
+        loader_version,
+        layers,
+        extensions,
+        headless_surface,
+        devices: devices?,
+    })
+}
+
+fn collect_devices(
+    entry: &Entry,
+    instance: &Instance,
+    headless_surface: bool,
+) -> AppResult<Vec<DeviceReport>> {
+    let surface = if headless_surface {
+        let headless_ext = headless_surface::Instance::new(entry, instance);
+        let create_info = vk::HeadlessSurfaceCreateInfoEXT::default();
+        let surface = unsafe { headless_ext.create_headless_surface(&create_info, None)? };
+        handle_registry::register(surface);
+        Some(SurfaceHodlder {
+            surface_ext: surface::Instance::new(entry, instance),
+            surface,
+        })
+    } else {
+        None
+    };
+
+    let devices = unsafe { instance.enumerate_physical_devices() }
+        .map_err(Into::into)
+        .and_then(|devices| {
+            devices
+                .into_iter()
+                .map(|device| collect_device(instance, device, surface.as_ref()))
+                .collect()
+        });
+
+    if let Some(surface) = surface {
+        unsafe {
+            handle_registry::unregister(surface.surface);
+            surface.surface_ext.destroy_surface(surface.surface, None);
+        }
+    }
+
+    devices
+}
+
+fn collect_device(
+    instance: &Instance,
+    device: vk::PhysicalDevice,
+    surface: Option<&SurfaceHodlder>,
+) -> AppResult<DeviceReport> {
+    let properties = unsafe { instance.get_physical_device_properties(device) };
+    let features = unsafe { instance.get_physical_device_features(device) };
+
+    let mut queue_families = vec![];
+    let families = unsafe { instance.get_physical_device_queue_family_properties(device) };
+    for (index, family) in families.iter().enumerate() {
+        let present = match surface {
+            Some(surface) => Some(unsafe {
+                surface.surface_ext.get_physical_device_surface_support(
+                    device,
+                    index as u32,
+                    surface.surface,
+                )?
+            }),
+            None => None,
+        };
+        queue_families.push(QueueFamilyReport {
+            index: index as u32,
+            flags: family.queue_flags,
+            queue_count: family.queue_count,
+            present,
+        });
+    }
+
+    let avaible_extensions = unsafe { instance.enumerate_device_extension_properties(device)? };
+    let missing_extensions = DEVICE_EXTENSIONS
+        .iter()
+        .filter(|&&ext| {
+            !avaible_extensions
+                .iter()
+                .any(|a_ext| unsafe { CStr::from_ptr(a_ext.extension_name.as_ptr()) } == ext)
+        })
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .collect();
+
+    let texture_formats = REPORTED_FORMATS
+        .iter()
+        .map(|&format| {
+            let supported = texture_loader::is_format_supported(instance, device, format);
+            (format, supported)
+        })
+        .collect();
+
+    let (surface_formats, present_modes, suitable) = match surface {
+        Some(surface) => {
+            let details = Context::query_swapchain_support(device, surface)?;
+            let suitable = Context::is_device_suitable(instance, device, surface)?.is_some();
+            (details.formats, details.present_modes, Some(suitable))
+        }
+        None => (vec![], vec![], None),
+    };
+
+    Ok(DeviceReport {
+        name: name_to_string(&properties.device_name),
+        device_type: properties.device_type,
+        api_version: properties.api_version,
+        driver_version: properties.driver_version,
+        queue_families,
+        missing_extensions,
+        sampler_anisotropy: features.sampler_anisotropy == vk::TRUE,
+        texture_formats,
+        surface_formats,
+        present_modes,
+        suitable,
+    })
+}
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Vulkan loader: {}", format_version(self.loader_version))?;
+
+        writeln!(f, "Instance layers ({}):", self.layers.len())?;
+        for (name, spec_version) in self.layers.iter() {
+            writeln!(f, "  {} {}", name, format_version(*spec_version))?;
+        }
+
+        writeln!(f, "Instance extensions ({}):", self.extensions.len())?;
+        for name in self.extensions.iter() {
+            writeln!(f, "  {}", name)?;
+        }
+
+        if !self.headless_surface {
+            writeln!(
+                f,
+                "No headless surface: the presentation support can't be checked"
+            )?;
+        }
+
+        writeln!(f, "Physical devices ({}):", self.devices.len())?;
+        for (i, device) in self.devices.iter().enumerate() {
+            writeln!(f, "  {}: {} ({:?})", i, device.name, device.device_type)?;
+            writeln!(
+                f,
+                "    Vulkan {}, driver version {:#x}",
+                format_version(device.api_version),
+                device.driver_version
+            )?;
+            let suitable = match device.suitable {
+                Some(true) => "yes",
+                Some(false) => "no",
+                None => "unknown",
+            };
+            writeln!(f, "    Suitable: {}", suitable)?;
+            if !device.missing_extensions.is_empty() {
+                writeln!(
+                    f,
+                    "    Missing extensions: {}",
+                    device.missing_extensions.join(", ")
+                )?;
+            }
+            if !device.sampler_anisotropy {
+                writeln!(f, "    Missing feature: samplerAnisotropy")?;
+            }
+
+            writeln!(f, "    Queue families:")?;
+            for family in device.queue_families.iter() {
+                let present = match family.present {
+                    Some(true) => ", present",
+                    _ => "",
+                };
+                writeln!(
+                    f,
+                    "      {}: {:?}, {} queues{}",
+                    family.index, family.flags, family.queue_count, present
+                )?;
+            }
+
+            writeln!(f, "    Texture formats:")?;
+            for (format, supported) in device.texture_formats.iter() {
+                let support = if *supported { "sampled" } else { "unsupported" };
+                writeln!(f, "      {:?}: {}", format, support)?;
+            }
+
+            if self.headless_surface {
+                writeln!(f, "    Surface formats:")?;
+                for format in device.surface_formats.iter() {
+                    writeln!(f, "      {:?} {:?}", format.format, format.color_space)?;
+                }
+                writeln!(f, "    Present modes: {:?}", device.present_modes)?;
+            }
+        }
+
+        Ok(())
+    }
+}
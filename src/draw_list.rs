@@ -0,0 +1,70 @@
+use crate::geometry::Mat4;
+
+/// Identifies a mesh uploaded to the GPU
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshHandle(pub(crate) usize);
+
+/// Identifies a material, i.e. the texture an object is sampled with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(pub(crate) usize);
+
+/// Identifies an item of the draw list so it can be updated or removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DrawItemId(u64);
+
+/// Object drawn every frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawItem {
+    pub mesh: MeshHandle,
+    pub material: MaterialHandle,
+    /// Model matrix of the object
+    pub transform: Mat4,
+}
+
+/// Items drawn by the scene pass, in insertion order
+#[derive(Default)]
+pub(crate) struct DrawList {
+    items: Vec<(DrawItemId, DrawItem)>,
+    next_id: u64,
+}
+
+impl DrawList {
+    pub(crate) fn add(&mut self, item: DrawItem) -> DrawItemId {
+        let id = DrawItemId(self.next_id);
+        self.next_id += 1;
+        self.items.push((id, item));
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: DrawItemId) -> Option<DrawItem> {
+        let index = self.items.iter().position(|(item_id, _)| *item_id == id)?;
+        Some(self.items.remove(index).1)
+    }
+
+    pub(crate) fn get(&self, id: DrawItemId) -> Option<&DrawItem> {
+        self.items
+            .iter()
+            .find(|(item_id, _)| *item_id == id)
+            .map(|(_, item)| item)
+    }
+
+    pub(crate) fn get_mut(&mut self, id: DrawItemId) -> Option<&mut DrawItem> {
+        self.items
+            .iter_mut()
+            .find(|(item_id, _)| *item_id == id)
+            .map(|(_, item)| item)
+    }
+
+    /// Index of the item in the list, i.e. its uniform buffer slot
+    pub(crate) fn slot(&self, id: DrawItemId) -> Option<usize> {
+        self.items.iter().position(|(item_id, _)| *item_id == id)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &DrawItem> {
+        self.items.iter().map(|(_, item)| item)
+    }
+}
@@ -0,0 +1,316 @@
+use std::path::Path;
+
+use ash::{vk, Device, Instance};
+use image::ImageReader;
+
+use crate::{
+    handle_registry, load_shader,
+    post::{create_fullscreen_pipeline, SCENE_COLOR_FORMAT},
+    texture_loader::{DecodedTexture, TextureUploadPath},
+    AppResult, Application, ImageHolder, SyncPool, TextureHolder, CUBEMAP_FACES,
+};
+
+/// Decodes an equirectangular panorama without clamping its texels, as a single level
+fn decode_equirect(path: &Path) -> AppResult<DecodedTexture> {
+    let image = ImageReader::open(path)?.decode()?.into_rgba32f();
+    Ok(DecodedTexture::from_rgba32f(vec![image]))
+}
+
+/// Projects the equirectangular panorama at `path` into a cubemap of `face_size` texels wide
+/// faces, in the scene color format. Each face is rendered with a single sample render pass of
+/// the scene format, which leaves it ready to be sampled, and the work is waited for before
+/// returning. The world up is +Z, the top row of the panorama being straight up.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn equirect_to_cubemap(
+    instance: &Instance,
+    device: &Device,
+    queue: vk::Queue,
+    physical_device: vk::PhysicalDevice,
+    command_pool: vk::CommandPool,
+    sync_pool: &SyncPool,
+    path: &Path,
+    face_size: u32,
+) -> AppResult<TextureHolder> {
+    let source = Application::upload_textures(
+        instance,
+        device,
+        queue,
+        physical_device,
+        command_pool,
+        sync_pool,
+        &mut [decode_equirect(path)?],
+    )?
+    .pop()
+    .unwrap();
+
+    let bindings = [vk::DescriptorSetLayoutBinding {
+        binding: 0,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        descriptor_count: 1,
+        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+        ..Default::default()
+    }];
+    let layout_info = vk::DescriptorSetLayoutCreateInfo {
+        binding_count: bindings.len() as u32,
+        p_bindings: bindings.as_ptr(),
+        ..Default::default()
+    };
+    let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+    handle_registry::register(set_layout);
+
+    let pipeline_layout = Application::create_pipeline_layout(
+        device,
+        &[set_layout],
+        &[vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: std::mem::size_of::<u32>() as u32,
+        }],
+    )?;
+    let render_pass = Application::create_render_pass(
+        device,
+        SCENE_COLOR_FORMAT,
+        vk::AttachmentLoadOp::CLEAR,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    )?;
+    let pipeline = create_fullscreen_pipeline(
+        device,
+        render_pass,
+        pipeline_layout,
+        &load_shader(
+            "equirect_to_cube.frag",
+            include_bytes!("spirv/equirect_to_cube.spv"),
+        )?,
+    )?;
+    handle_registry::register(pipeline);
+
+    // The longitude wraps around, the latitude stops at the poles
+    let sampler_info = vk::SamplerCreateInfo {
+        mag_filter: vk::Filter::LINEAR,
+        min_filter: vk::Filter::LINEAR,
+        address_mode_u: vk::SamplerAddressMode::REPEAT,
+        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        ..Default::default()
+    };
+    let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+    handle_registry::register(sampler);
+
+    let pool_sizes = [vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        descriptor_count: 1,
+    }];
+    let pool_info = vk::DescriptorPoolCreateInfo {
+        max_sets: 1,
+        pool_size_count: pool_sizes.len() as u32,
+        p_pool_sizes: pool_sizes.as_ptr(),
+        ..Default::default()
+    };
+    let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+    handle_registry::register(descriptor_pool);
+
+    let alloc_info = vk::DescriptorSetAllocateInfo {
+        descriptor_pool,
+        descriptor_set_count: 1,
+        p_set_layouts: &set_layout as *const _,
+        ..Default::default()
+    };
+    let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+    let image_info = vk::DescriptorImageInfo {
+        sampler,
+        image_view: source.view,
+        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    };
+    let descriptor_write = vk::WriteDescriptorSet {
+        dst_set: descriptor_set,
+        dst_binding: 0,
+        descriptor_count: 1,
+        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        p_image_info: &image_info as *const _,
+        ..Default::default()
+    };
+    unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+    let image_info = vk::ImageCreateInfo {
+        flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        image_type: vk::ImageType::TYPE_2D,
+        format: SCENE_COLOR_FORMAT,
+        extent: vk::Extent3D {
+            width: face_size,
+            height: face_size,
+            depth: 1,
+        },
+        mip_levels: 1,
+        array_layers: CUBEMAP_FACES as u32,
+        samples: vk::SampleCountFlags::TYPE_1,
+        tiling: vk::ImageTiling::OPTIMAL,
+        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        initial_layout: vk::ImageLayout::UNDEFINED,
+        ..Default::default()
+    };
+    let image = unsafe { device.create_image(&image_info, None)? };
+    handle_registry::register(image);
+
+    let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
+    let alloc_info = vk::MemoryAllocateInfo {
+        allocation_size: mem_requirements.size,
+        memory_type_index: Application::find_memory_type(
+            instance,
+            physical_device,
+            mem_requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?,
+        ..Default::default()
+    };
+    let memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+    handle_registry::register(memory);
+    unsafe { device.bind_image_memory(image, memory, 0)? };
+
+    let cube_view = create_view(device, image, vk::ImageViewType::CUBE, 0, CUBEMAP_FACES)?;
+    let mut face_views = Vec::with_capacity(CUBEMAP_FACES);
+    let mut framebuffers = Vec::with_capacity(CUBEMAP_FACES);
+    for face in 0..CUBEMAP_FACES {
+        let view = create_view(device, image, vk::ImageViewType::TYPE_2D, face, 1)?;
+        let attachments = [view];
+        let framebuffer_info = vk::FramebufferCreateInfo {
+            render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: face_size,
+            height: face_size,
+            layers: 1,
+            ..Default::default()
+        };
+        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None)? };
+        handle_registry::register(framebuffer);
+
+        face_views.push(view);
+        framebuffers.push(framebuffer);
+    }
+
+    let command_buffer = Application::begin_singe_time_command(device, command_pool)?;
+    let render_area = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent: vk::Extent2D {
+            width: face_size,
+            height: face_size,
+        },
+    };
+    let clear_values = [vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 1.0],
+        },
+    }];
+    let viewports = [vk::Viewport {
+        x: 0.0,
+        y: 0.0,
+        width: face_size as f32,
+        height: face_size as f32,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    }];
+    for (face, &framebuffer) in framebuffers.iter().enumerate() {
+        let render_pass_info = vk::RenderPassBeginInfo {
+            render_pass,
+            framebuffer,
+            render_area,
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+            ..Default::default()
+        };
+        let face = face as u32;
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &face.to_ne_bytes(),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            device.cmd_end_render_pass(command_buffer);
+        }
+    }
+    Application::end_single_time_command(device, queue, command_pool, sync_pool, command_buffer)?;
+
+    // Only the cubemap outlives the projection
+    unsafe {
+        for (&framebuffer, &view) in framebuffers.iter().zip(&face_views) {
+            handle_registry::unregister(framebuffer);
+            device.destroy_framebuffer(framebuffer, None);
+            handle_registry::unregister(view);
+            device.destroy_image_view(view, None);
+        }
+        handle_registry::unregister(descriptor_pool);
+        device.destroy_descriptor_pool(descriptor_pool, None);
+        handle_registry::unregister(sampler);
+        device.destroy_sampler(sampler, None);
+        handle_registry::unregister(pipeline);
+        device.destroy_pipeline(pipeline, None);
+        handle_registry::unregister(pipeline_layout);
+        device.destroy_pipeline_layout(pipeline_layout, None);
+        handle_registry::unregister(render_pass);
+        device.destroy_render_pass(render_pass, None);
+        handle_registry::unregister(set_layout);
+        device.destroy_descriptor_set_layout(set_layout, None);
+        handle_registry::unregister(source.view);
+        device.destroy_image_view(source.view, None);
+        handle_registry::unregister(source.image.image);
+        device.destroy_image(source.image.image, None);
+        handle_registry::unregister(source.image.memory);
+        device.free_memory(source.image.memory, None);
+    }
+
+    Ok(TextureHolder {
+        image: ImageHolder::new(image, memory),
+        view: cube_view,
+        array_layers: Some(CUBEMAP_FACES as u32),
+        upload_path: TextureUploadPath::Native,
+    })
+}
+
+fn create_view(
+    device: &Device,
+    image: vk::Image,
+    view_type: vk::ImageViewType,
+    base_array_layer: usize,
+    layer_count: usize,
+) -> AppResult<vk::ImageView> {
+    let create_info = vk::ImageViewCreateInfo {
+        image,
+        view_type,
+        format: SCENE_COLOR_FORMAT,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: base_array_layer as u32,
+            layer_count: layer_count as u32,
+        },
+        ..Default::default()
+    };
+
+    unsafe {
+        Ok(handle_registry::register(
+            device.create_image_view(&create_info, None)?,
+        ))
+    }
+}
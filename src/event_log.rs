@@ -0,0 +1,140 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use ash::vk;
+
+// Number of events kept, the oldest ones are dropped first
+const EVENT_LOG_CAPACITY: usize = 256;
+
+/// Something that happened in the renderer
+#[derive(Clone, Debug, PartialEq)]
+pub enum RendererEvent {
+    DeviceChosen {
+        name: String,
+        api_version: u32,
+    },
+    SwapchainCreated {
+        extent: vk::Extent2D,
+        format: vk::Format,
+        color_space: vk::ColorSpaceKHR,
+        image_count: usize,
+    },
+    PipelineBuilt {
+        name: String,
+    },
+    PostEffectInserted {
+        name: String,
+    },
+    PostEffectRemoved {
+        name: String,
+    },
+    /// `size` is the size of the memory allocation, in bytes
+    ResourceCreated {
+        name: String,
+        size: u64,
+    },
+    ResourceDestroyed {
+        name: String,
+        size: u64,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl std::fmt::Display for RendererEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DeviceChosen { name, api_version } => write!(
+                f,
+                "Device chosen: {} (Vulkan {}.{})",
+                name,
+                vk::api_version_major(*api_version),
+                vk::api_version_minor(*api_version)
+            ),
+            Self::SwapchainCreated {
+                extent,
+                format,
+                color_space,
+                image_count,
+            } => write!(
+                f,
+                "Swapchain created: {}x{} {:?} {:?}, {} images",
+                extent.width, extent.height, format, color_space, image_count
+            ),
+            Self::PipelineBuilt { name } => write!(f, "Pipeline built: {}", name),
+            Self::PostEffectInserted { name } => write!(f, "Post effect inserted: {}", name),
+            Self::PostEffectRemoved { name } => write!(f, "Post effect removed: {}", name),
+            Self::ResourceCreated { name, size } => {
+                write!(f, "Resource created: {} ({} bytes)", name, size)
+            }
+            Self::ResourceDestroyed { name, size } => {
+                write!(f, "Resource destroyed: {} ({} bytes)", name, size)
+            }
+            Self::Error { message } => write!(f, "Error: {}", message),
+        }
+    }
+}
+
+/// An event and the time it happened at, since the creation of the application
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoggedEvent {
+    pub time: Duration,
+    pub event: RendererEvent,
+}
+
+/// Ring buffer of the last renderer events, meant to be attached to bug reports
+pub struct EventLog {
+    start: Instant,
+    events: Mutex<VecDeque<LoggedEvent>>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            events: Mutex::new(VecDeque::with_capacity(EVENT_LOG_CAPACITY)),
+        }
+    }
+}
+
+impl EventLog {
+    pub fn push(&self, event: RendererEvent) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() == EVENT_LOG_CAPACITY {
+            events.pop_front();
+        }
+        events.push_back(LoggedEvent {
+            time: self.start.elapsed(),
+            event,
+        });
+    }
+
+    /// Returns the logged events, oldest first
+    pub fn events(&self) -> Vec<LoggedEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Formats the logged events, one per line
+    pub fn dump(&self) -> String {
+        let mut dump = String::new();
+        for logged in self.events.lock().unwrap().iter() {
+            writeln!(
+                dump,
+                "[{:>10.3}s] {}",
+                logged.time.as_secs_f64(),
+                logged.event
+            )
+            .unwrap();
+        }
+        dump
+    }
+
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+}
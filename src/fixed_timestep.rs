@@ -0,0 +1,65 @@
+/// Ticks per second of the simulation unless set otherwise, see `Application::set_tick_rate`
+pub const DEFAULT_TICK_RATE: u32 = 60;
+// Ticks run by a single frame at most, the time of a longer frame is dropped rather than
+// simulated in a burst making the next frames longer still
+const MAX_TICKS_PER_FRAME: u32 = 8;
+
+/// State of the simulation given to the fixed update, see `Application::set_fixed_update`
+pub struct FixedUpdateContext {
+    /// Duration of the tick in seconds, the same for every tick
+    pub tick: f32,
+    /// Simulated time at the end of the tick, in seconds
+    pub time: f32,
+}
+
+pub type FixedUpdateFn = Box<dyn FnMut(&FixedUpdateContext)>;
+
+/// Splits the elapsed time in ticks of a fixed duration, so that the simulation advances the
+/// same whatever the frame rate. The time left over by a frame, less than a tick, is kept for
+/// the next frames and given as `alpha` to interpolate between the last two ticks.
+pub(crate) struct FixedTimestep {
+    tick: f32,
+    accumulator: f32,
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self {
+            tick: 1.0 / DEFAULT_TICK_RATE as f32,
+            accumulator: 0.0,
+        }
+    }
+}
+
+impl FixedTimestep {
+    pub(crate) fn set_tick_rate(&mut self, tick_rate: u32) {
+        self.tick = 1.0 / tick_rate.max(1) as f32;
+        self.accumulator = self.accumulator.min(self.tick);
+    }
+
+    pub(crate) fn tick_rate(&self) -> u32 {
+        (1.0 / self.tick).round() as u32
+    }
+
+    pub(crate) fn tick(&self) -> f32 {
+        self.tick
+    }
+
+    /// Accumulates `delta_time` seconds, returns the number of ticks to run
+    pub(crate) fn advance(&mut self, delta_time: f32) -> u32 {
+        self.accumulator += delta_time;
+        let ticks = (self.accumulator / self.tick) as u32;
+        if ticks > MAX_TICKS_PER_FRAME {
+            self.accumulator = 0.0;
+            return MAX_TICKS_PER_FRAME;
+        }
+
+        self.accumulator -= ticks as f32 * self.tick;
+        ticks
+    }
+
+    /// Fraction of a tick elapsed since the last one, from 0 to 1
+    pub(crate) fn alpha(&self) -> f32 {
+        (self.accumulator / self.tick).clamp(0.0, 1.0)
+    }
+}
@@ -0,0 +1,26 @@
+use bumpalo::{collections::Vec as BumpVec, Bump};
+
+/// Bump allocator for the transient lists built while updating and recording a frame (barriers,
+/// copies, descriptor writes, sparse binds). It is reset once the previous use of the frame is
+/// done, so after the first frames it reuses its chunks instead of allocating.
+#[derive(Default)]
+pub(crate) struct FrameArena {
+    bump: Bump,
+}
+
+impl FrameArena {
+    /// Returns an empty list allocated in the arena, freed on the next reset
+    pub(crate) fn vec<T>(&self) -> BumpVec<'_, T> {
+        BumpVec::new_in(&self.bump)
+    }
+
+    /// Collects `iter` in the arena
+    pub(crate) fn collect<T>(&self, iter: impl IntoIterator<Item = T>) -> BumpVec<'_, T> {
+        BumpVec::from_iter_in(iter, &self.bump)
+    }
+
+    /// Frees every list of the previous frame, keeping the largest chunk
+    pub(crate) fn reset(&mut self) {
+        self.bump.reset();
+    }
+}
@@ -0,0 +1,68 @@
+use ash::{vk, Device};
+
+use crate::AppResult;
+
+/// Debug checks of the writes to the per frame resources mapped by the CPU (uniform buffers,
+/// dynamic meshes, descriptor sets), see `Application::set_frame_validation`.
+///
+/// The resources of a frame may only be written between the wait on its fence and the
+/// submission of its command buffer. Once enabled, the writes made outside of that window, or to
+/// the resources of another frame, panic with the name of the resource.
+#[derive(Default)]
+pub(crate) struct FrameGuard {
+    enabled: bool,
+    // Frame whose resources can be written, if any
+    writable_frame: Option<usize>,
+}
+
+impl FrameGuard {
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Opens the writes to the resources of `frame`, once `fence` is waited on. Checks that the
+    /// fence is signaled, i.e. the GPU is done with the previous use of the frame.
+    pub(crate) fn begin_writes(
+        &mut self,
+        device: &Device,
+        frame: usize,
+        fence: vk::Fence,
+    ) -> AppResult<()> {
+        if self.enabled {
+            let signaled = unsafe { device.get_fence_status(fence)? };
+            assert!(
+                signaled,
+                "the resources of frame {} are opened for writes while it is in flight",
+                frame
+            );
+        }
+        self.writable_frame = Some(frame);
+
+        Ok(())
+    }
+
+    /// Closes the writes, once the frame is submitted
+    pub(crate) fn end_writes(&mut self) {
+        self.writable_frame = None;
+    }
+
+    /// Checks that `resource` of `frame` can be written
+    pub(crate) fn check(&self, frame: usize, resource: &str) {
+        if !self.enabled {
+            return;
+        }
+        match self.writable_frame {
+            Some(writable_frame) => assert!(
+                writable_frame == frame,
+                "{} of frame {} written while frame {} is being prepared",
+                resource,
+                frame,
+                writable_frame
+            ),
+            None => panic!(
+                "{} of frame {} written while no frame is being prepared",
+                resource, frame
+            ),
+        }
+    }
+}
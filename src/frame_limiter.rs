@@ -0,0 +1,54 @@
+use std::time::{Duration, Instant};
+
+// Slept for, the end of the wait being spun on as the sleeps may overshoot by about a millisecond
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+/// Paces the frames to a maximum frame rate whatever the present mode, see
+/// `Application::set_fps_limit`.
+///
+/// The frames start on a grid of frame periods rather than a period after the previous frame,
+/// so that the time lost by one wait is caught up by the next ones. A frame late by more than a
+/// period restarts the grid instead of being followed by a burst of frames.
+#[derive(Default)]
+pub(crate) struct FrameLimiter {
+    fps: Option<u32>,
+    next_frame: Option<Instant>,
+}
+
+impl FrameLimiter {
+    /// Limits the frame rate to `fps` frames per second, `None` or 0 lifting the limit
+    pub(crate) fn set_limit(&mut self, fps: Option<u32>) {
+        self.fps = fps.filter(|&fps| fps > 0);
+        self.next_frame = None;
+    }
+
+    pub(crate) fn limit(&self) -> Option<u32> {
+        self.fps
+    }
+
+    /// Waits until the next frame may start, returns at once without a limit
+    pub(crate) fn wait(&mut self) {
+        let Some(fps) = self.fps else {
+            return;
+        };
+        let period = Duration::from_secs(1) / fps;
+
+        let now = Instant::now();
+        let deadline = match self.next_frame {
+            Some(deadline) if deadline + period > now => deadline,
+            _ => now,
+        };
+
+        if let Some(sleep) = deadline
+            .checked_duration_since(now)
+            .and_then(|remaining| remaining.checked_sub(SPIN_MARGIN))
+        {
+            std::thread::sleep(sleep);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+
+        self.next_frame = Some(deadline + period);
+    }
+}
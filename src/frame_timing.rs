@@ -0,0 +1,62 @@
+use std::time::{Duration, Instant};
+
+/// Timing of the last frame prepared by `draw_frame`, see `Application::frame_timing`. Unlike the
+/// `FrameStats` of the debug HUD, nothing is averaged.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTiming {
+    /// Seconds elapsed since the start of the previous frame, whether the scene is paused or not
+    pub delta_time: f32,
+    /// Seconds elapsed since the application was created
+    pub total_time: f64,
+    /// Index of the frame among the frames drawn since the creation, starting at 0
+    pub frame_index: u64,
+    /// Seconds the CPU spent preparing and submitting the frame, the waits on the GPU and on the
+    /// frame rate limit aside
+    pub cpu_time: f32,
+}
+
+/// Clock of the frames, keeping the `FrameTiming` up to date
+pub(crate) struct FrameClock {
+    creation_time: Instant,
+    last_frame_time: Instant,
+    timing: FrameTiming,
+    // Set once the first frame started, for its index to be 0
+    started: bool,
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            creation_time: now,
+            last_frame_time: now,
+            timing: FrameTiming::default(),
+            started: false,
+        }
+    }
+}
+
+impl FrameClock {
+    /// Starts a frame, returns the time elapsed since the previous one in seconds
+    pub(crate) fn start_frame(&mut self) -> f32 {
+        let now = Instant::now();
+        self.timing.delta_time = now.duration_since(self.last_frame_time).as_secs_f32();
+        self.timing.total_time = now.duration_since(self.creation_time).as_secs_f64();
+        if self.started {
+            self.timing.frame_index += 1;
+        }
+        self.started = true;
+        self.last_frame_time = now;
+
+        self.timing.delta_time
+    }
+
+    /// Records the CPU time of the frame, once submitted
+    pub(crate) fn end_frame(&mut self, cpu_time: Duration) {
+        self.timing.cpu_time = cpu_time.as_secs_f32();
+    }
+
+    pub(crate) fn timing(&self) -> FrameTiming {
+        self.timing
+    }
+}
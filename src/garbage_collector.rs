@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+
+use ash::{khr::acceleration_structure, vk, Device};
+
+use crate::{handle_registry, TextureHolder};
+
+/// Objects destroyed by the garbage collector every frame by default, see
+/// `Application::set_gc_budget`
+pub const DEFAULT_GC_BUDGET: usize = 16;
+
+/// Resource released by the application, destroyed by the garbage collector
+pub(crate) enum Garbage {
+    Texture(TextureHolder),
+    Buffer(vk::Buffer, vk::DeviceMemory),
+    /// Acceleration structure along with the loader destroying it, its buffer being retired
+    /// on its own
+    AccelerationStructure(acceleration_structure::Device, vk::AccelerationStructureKHR),
+}
+
+impl Garbage {
+    // Vulkan objects destroyed along with the resource, counted against the budget
+    fn object_count(&self) -> usize {
+        match self {
+            Garbage::Texture(_) => 3,
+            Garbage::Buffer(..) => 2,
+            Garbage::AccelerationStructure(..) => 1,
+        }
+    }
+
+    /// Destroys the resource right away, the GPU must be done with it
+    pub(crate) unsafe fn destroy(self, device: &Device) {
+        match self {
+            Garbage::Texture(texture) => {
+                handle_registry::unregister(texture.view);
+                device.destroy_image_view(texture.view, None);
+                handle_registry::unregister(texture.image.image);
+                device.destroy_image(texture.image.image, None);
+                handle_registry::unregister(texture.image.memory);
+                device.free_memory(texture.image.memory, None);
+            }
+            Garbage::Buffer(buffer, memory) => {
+                handle_registry::unregister(buffer);
+                device.destroy_buffer(buffer, None);
+                handle_registry::unregister(memory);
+                device.free_memory(memory, None);
+            }
+            Garbage::AccelerationStructure(loader, accel) => {
+                handle_registry::unregister(accel);
+                loader.destroy_acceleration_structure(accel, None);
+            }
+        }
+    }
+}
+
+/// Deferred destruction queue of the released resources.
+///
+/// A resource is retired for the frames in flight that may still use it, then destroyed by the
+/// collections of the following frames, each destroying at most a budget of Vulkan objects so
+/// unloading a large scene at once doesn't stall a single frame.
+pub(crate) struct GarbageCollector {
+    // Retired resources along with the number of frames left before they can be destroyed, in
+    // retirement order
+    retired: VecDeque<(Garbage, usize)>,
+    budget: usize,
+}
+
+impl Default for GarbageCollector {
+    fn default() -> Self {
+        Self {
+            retired: VecDeque::new(),
+            budget: DEFAULT_GC_BUDGET,
+        }
+    }
+}
+
+impl GarbageCollector {
+    /// Queues `garbage` for destruction once `frame_count` frames are collected
+    pub(crate) fn retire(&mut self, garbage: Garbage, frame_count: usize) {
+        self.retired.push_back((garbage, frame_count));
+    }
+
+    /// Sets the objects destroyed at most by a collection, at least one resource always being
+    /// destroyed when one can be
+    pub(crate) fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+    }
+
+    /// Resources retired and not destroyed yet
+    pub(crate) fn pending(&self) -> usize {
+        self.retired.len()
+    }
+
+    /// Destroys the oldest retired resources no frame in flight can use anymore, within the
+    /// budget. To call once per frame after waiting for the previous use of its resources.
+    pub(crate) fn collect(&mut self, device: &Device) {
+        for (_, frames_left) in self.retired.iter_mut() {
+            *frames_left = frames_left.saturating_sub(1);
+        }
+
+        let mut destroyed = 0;
+        let mut i = 0;
+        while i < self.retired.len() {
+            if self.retired[i].1 > 0 {
+                i += 1;
+                continue;
+            }
+
+            let object_count = self.retired[i].0.object_count();
+            if destroyed > 0 && destroyed + object_count > self.budget {
+                break;
+            }
+            let (garbage, _) = self.retired.remove(i).unwrap();
+            unsafe { garbage.destroy(device) };
+            destroyed += object_count;
+        }
+    }
+
+    /// Destroys every retired resource, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        for (garbage, _) in self.retired.drain(..) {
+            unsafe { garbage.destroy(device) };
+        }
+    }
+}
@@ -1,26 +1,45 @@
 use std::mem;
 
 use ash::vk;
+use cgmath::InnerSpace;
 
 pub type Point2 = cgmath::Point2<f32>;
 pub type Point3 = cgmath::Point3<f32>;
 
 pub type Vec2 = cgmath::Vector2<f32>;
 pub type Vec3 = cgmath::Vector3<f32>;
+pub type Vec4 = cgmath::Vector4<f32>;
 
 pub type Mat4 = cgmath::Matrix4<f32>;
+pub type Quat = cgmath::Quaternion<f32>;
 
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq)]
-pub struct ModelViewProj {
+pub(crate) struct ModelViewProj {
     model: Mat4,
     view: Mat4,
     proj: Mat4,
+    // Without the jitter of `proj`, for this frame and the previous one, read by the velocity
+    // prepass
+    model_view_proj: Mat4,
+    previous_model_view_proj: Mat4,
 }
 
 impl ModelViewProj {
-    pub fn new(model: Mat4, view: Mat4, proj: Mat4) -> Self {
-        Self { model, view, proj }
+    pub(crate) fn new(
+        model: Mat4,
+        view: Mat4,
+        proj: Mat4,
+        model_view_proj: Mat4,
+        previous_model_view_proj: Mat4,
+    ) -> Self {
+        Self {
+            model,
+            view,
+            proj,
+            model_view_proj,
+            previous_model_view_proj,
+        }
     }
 }
 
@@ -30,6 +49,10 @@ pub struct Vertex {
     position: Vec2,
     color: Vec3,
     uv: Vec2,
+    // Where the vertex lies in the lightmap of the mesh, unique for every triangle
+    lightmap_uv: Vec2,
+    // Direction of increasing u in the XY plane of the mesh, w being the sign of the bitangent
+    tangent: Vec4,
 }
 
 impl Vertex {
@@ -61,16 +84,63 @@ impl Vertex {
             format: vk::Format::R32G32_SFLOAT,
             offset: mem::size_of::<Vec2>() as u32 + mem::size_of::<Vec3>() as u32,
         },
+        vk::VertexInputAttributeDescription {
+            binding: 0,
+            location: 3,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 2 * mem::size_of::<Vec2>() as u32 + mem::size_of::<Vec3>() as u32,
+        },
+        vk::VertexInputAttributeDescription {
+            binding: 0,
+            location: 4,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+            offset: 3 * mem::size_of::<Vec2>() as u32 + mem::size_of::<Vec3>() as u32,
+        },
     ];
 
+    /// Creates a vertex whose lightmap coordinates are its texture coordinates, see
+    /// `with_lightmap_uv`, and whose tangent is +X, see `with_tangent`
     pub const fn new(position: Vec2, color: Vec3, uv: Vec2) -> Self {
         Self {
             position,
             color,
             uv,
+            lightmap_uv: uv,
+            tangent: Vec4::new(1.0, 0.0, 0.0, 1.0),
         }
     }
 
+    /// Sets the coordinates of the vertex in the lightmap of its mesh. A baked mesh needs its
+    /// triangles not to overlap in the lightmap, which texture coordinates often do, e.g. tiling.
+    pub const fn with_lightmap_uv(mut self, lightmap_uv: Vec2) -> Self {
+        self.lightmap_uv = lightmap_uv;
+        self
+    }
+
+    /// Sets the tangent the normal maps are sampled along: the direction of increasing u in the
+    /// XY plane of the mesh, `handedness` being 1 when the direction of increasing v is +90
+    /// degrees from it around +Z and -1 otherwise. See `compute_tangents`.
+    pub const fn with_tangent(mut self, tangent: Vec2, handedness: f32) -> Self {
+        self.tangent = Vec4::new(tangent.x, tangent.y, 0.0, handedness);
+        self
+    }
+
+    pub const fn position(&self) -> Vec2 {
+        self.position
+    }
+
+    pub const fn lightmap_uv(&self) -> Vec2 {
+        self.lightmap_uv
+    }
+
+    pub const fn uv(&self) -> Vec2 {
+        self.uv
+    }
+
+    pub const fn tangent(&self) -> Vec4 {
+        self.tangent
+    }
+
     #[allow(dead_code)]
     pub const fn zero() -> Self {
         Self::new(
@@ -80,3 +150,49 @@ impl Vertex {
         )
     }
 }
+
+/// Sets the tangent of every vertex from the texture coordinates of the triangles of `indices`
+/// sharing it, averaged. The vertices without a triangle with a valid mapping keep their tangent.
+pub fn compute_tangents(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut tangents = vec![Vec2::new(0.0, 0.0); vertices.len()];
+    let mut bitangents = vec![Vec2::new(0.0, 0.0); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+        if [a, b, c].iter().any(|&i| i >= vertices.len()) {
+            continue;
+        }
+
+        let edge1 = vertices[b].position - vertices[a].position;
+        let edge2 = vertices[c].position - vertices[a].position;
+        let delta1 = vertices[b].uv - vertices[a].uv;
+        let delta2 = vertices[c].uv - vertices[a].uv;
+        let determinant = delta1.x * delta2.y - delta2.x * delta1.y;
+        if determinant.abs() <= f32::EPSILON {
+            continue;
+        }
+
+        let tangent = (edge1 * delta2.y - edge2 * delta1.y) / determinant;
+        let bitangent = (edge2 * delta1.x - edge1 * delta2.x) / determinant;
+        for i in [a, b, c] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let length = tangent.magnitude();
+        if length <= f32::EPSILON {
+            continue;
+        }
+
+        // The bitangent is the tangent turned around +Z, flipped for mirrored mappings
+        let turned = Vec2::new(-tangent.y, tangent.x);
+        let handedness = if turned.dot(bitangent) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        *vertex = vertex.clone().with_tangent(tangent / length, handedness);
+    }
+}
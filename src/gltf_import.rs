@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use gltf::{
+    image::{Data, Format},
+    material::AlphaMode as GltfAlphaMode,
+    texture::Texture,
+};
+use image::{DynamicImage, ImageBuffer, RgbaImage};
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    geometry::{Vec3, Vec4},
+    pbr::{AlphaMode, PbrMaterial},
+    texture_loader::{self, DecodedTexture},
+    AppResult,
+};
+
+/// Reads the metallic-roughness materials of the glTF file at `path`, in the order of the file,
+/// decoding the images they sample whether they are embedded or external. Every map samples the
+/// first texture coordinates of the vertices, the samplers and texture transforms of the file
+/// being ignored.
+pub(crate) fn import_materials(path: &Path) -> AppResult<Vec<PbrMaterial<DecodedTexture>>> {
+    let (document, _, images) = gltf::import(path)?;
+    let texture = |texture: Texture| rgba_image(&images[texture.source().index()]);
+
+    document
+        .materials()
+        .map(|material| {
+            let pbr = material.pbr_metallic_roughness();
+            let albedo = match pbr.base_color_texture() {
+                Some(info) => texture(info.texture())?,
+                None => RgbaImage::from_pixel(1, 1, image::Rgba([u8::MAX; 4])),
+            };
+            let normal = material
+                .normal_texture()
+                .map(|info| texture(info.texture()));
+            let metallic_roughness = pbr
+                .metallic_roughness_texture()
+                .map(|info| texture(info.texture()));
+            let occlusion = material
+                .occlusion_texture()
+                .map(|info| texture(info.texture()));
+            let emissive = material
+                .emissive_texture()
+                .map(|info| texture(info.texture()));
+            let decode = |image: Option<AppResult<RgbaImage>>| {
+                image
+                    .transpose()
+                    .map(|image| image.map(texture_loader::texture_from_rgba))
+            };
+
+            Ok(PbrMaterial {
+                albedo: texture_loader::texture_from_rgba(albedo),
+                normal: decode(normal)?,
+                metallic_roughness: decode(metallic_roughness)?,
+                occlusion: decode(occlusion)?,
+                emissive: decode(emissive)?,
+                base_color: Vec4::from(pbr.base_color_factor()),
+                metallic: pbr.metallic_factor(),
+                roughness: pbr.roughness_factor(),
+                emissive_factor: Vec3::from(material.emissive_factor()),
+                alpha_mode: match material.alpha_mode() {
+                    GltfAlphaMode::Opaque => AlphaMode::Opaque,
+                    GltfAlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5)),
+                    GltfAlphaMode::Blend => AlphaMode::Blend,
+                },
+                double_sided: material.double_sided(),
+            })
+        })
+        .collect()
+}
+
+/// Converts an image of the file to RGBA8, the one and two channel images being luminance and
+/// luminance with alpha as decoded by the `gltf` crate
+fn rgba_image(data: &Data) -> AppResult<RgbaImage> {
+    let (width, height) = (data.width, data.height);
+    let bytes = || data.pixels.clone();
+    let words = || -> Vec<u16> {
+        data.pixels
+            .chunks_exact(2)
+            .map(|word| u16::from_ne_bytes([word[0], word[1]]))
+            .collect()
+    };
+    let floats = || -> Vec<f32> {
+        data.pixels
+            .chunks_exact(4)
+            .map(|float| f32::from_ne_bytes([float[0], float[1], float[2], float[3]]))
+            .collect()
+    };
+
+    let image = match data.format {
+        Format::R8 => ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageLuma8),
+        Format::R8G8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageLumaA8)
+        }
+        Format::R8G8B8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageRgb8)
+        }
+        Format::R8G8B8A8 => {
+            ImageBuffer::from_raw(width, height, bytes()).map(DynamicImage::ImageRgba8)
+        }
+        Format::R16 => ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageLuma16),
+        Format::R16G16 => {
+            ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageLumaA16)
+        }
+        Format::R16G16B16 => {
+            ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageRgb16)
+        }
+        Format::R16G16B16A16 => {
+            ImageBuffer::from_raw(width, height, words()).map(DynamicImage::ImageRgba16)
+        }
+        Format::R32G32B32FLOAT => {
+            ImageBuffer::from_raw(width, height, floats()).map(DynamicImage::ImageRgb32F)
+        }
+        Format::R32G32B32A32FLOAT => {
+            ImageBuffer::from_raw(width, height, floats()).map(DynamicImage::ImageRgba32F)
+        }
+    };
+
+    image
+        .map(DynamicImage::into_rgba8)
+        .ok_or_else(|| AppError::new(AppErrorType::InvalidGltfImage))
+}
@@ -0,0 +1,500 @@
+use ash::{vk, Device, Instance};
+
+use crate::{
+    handle_registry, load_shader,
+    overlay::{create_overlay_pipeline, OverlayBlend},
+    AppResult, Application, BufferHolder, Mat4, ParticleEmitter,
+};
+
+// Invocations of a workgroup of the update shader
+const WORKGROUP_SIZE: u32 = 64;
+
+// Two triangles per particle, built by the vertex shader from the vertex index
+const VERTICES_PER_PARTICLE: u32 = 6;
+
+// Position and age followed by the velocity
+const PARTICLE_SIZE: u64 = 2 * 16;
+
+// Arguments of `vkCmdDrawIndirect` followed by the particles spawned during the update
+const DRAW_BUFFER_SIZE: u64 = std::mem::size_of::<vk::DrawIndirectCommand>() as u64 + 4;
+
+/// Identifies a GPU particle emitter so it can be updated or removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GpuParticleEmitterId(u64);
+
+#[repr(C)]
+struct SimulationConstants {
+    // w: seconds since the previous update
+    emitter_position: [f32; 4],
+    // w: spread of the velocity of a new particle
+    velocity: [f32; 4],
+    // w: lifetime of the particles
+    acceleration: [f32; 4],
+    spawn_count: u32,
+    seed: u32,
+    capacity: u32,
+}
+
+#[repr(C)]
+struct DrawConstants {
+    view_proj: Mat4,
+    // Camera axes in world space, w: width of the quads for the right axis, lifetime of the
+    // particles for the up one
+    right: [f32; 4],
+    up: [f32; 4],
+    start_color: [f32; 4],
+    end_color: [f32; 4],
+}
+
+struct Emitter {
+    id: GpuParticleEmitterId,
+    desc: ParticleEmitter,
+    capacity: u32,
+    particles: BufferHolder,
+    draw: BufferHolder,
+    alive: BufferHolder,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    // Fraction of a particle left to emit from the previous updates
+    pending: f32,
+    // Particles to bring back to life during the next update
+    spawn_count: u32,
+    // The particles are all marked dead by the first update
+    cleared: bool,
+}
+
+/// Emitters whose particles live in storage buffers, updated every frame by a compute pass and
+/// drawn in the scene render pass with an indirect draw, without ever being read back.
+///
+/// The update ages and moves the live particles, brings the dead ones back to life at the
+/// emitter up to the spawn count of the frame, and appends the index of every live particle to
+/// the alive list, counting them in the instance count of the indirect draw.
+pub(crate) struct GpuParticles {
+    set_layout: vk::DescriptorSetLayout,
+    update_pipeline: vk::Pipeline,
+    update_pipeline_layout: vk::PipelineLayout,
+    draw_pipeline: vk::Pipeline,
+    draw_pipeline_layout: vk::PipelineLayout,
+    emitters: Vec<Emitter>,
+    next_id: u64,
+    // Changes every update so the spawned particles differ from one frame to the next
+    seed: u32,
+    delta_time: f32,
+}
+
+impl GpuParticles {
+    pub(crate) fn new(
+        device: &Device,
+        scene_render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+    ) -> AppResult<Self> {
+        // The particles, the draw arguments and the alive list, the bindings sharing their
+        // stages so the three are written at once
+        let bindings = [0, 1, 2].map(|binding| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::VERTEX,
+            ..Default::default()
+        });
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        let update_pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: std::mem::size_of::<SimulationConstants>() as u32,
+            }],
+        )?;
+        let update_pipeline = Application::create_compute_pipeline(
+            device,
+            update_pipeline_layout,
+            &load_shader(
+                "gpu_particle_update.comp",
+                include_bytes!("spirv/gpu_particle_update.spv"),
+            )?,
+        )?;
+
+        let draw_pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<DrawConstants>() as u32,
+            }],
+        )?;
+        let draw_pipeline = create_overlay_pipeline(
+            device,
+            scene_render_pass,
+            samples,
+            draw_pipeline_layout,
+            &load_shader(
+                "gpu_particle.vert",
+                include_bytes!("spirv/gpu_particle.spv"),
+            )?,
+            &load_shader(
+                "particle_sprite.frag",
+                include_bytes!("spirv/particle_sprite.spv"),
+            )?,
+            &[],
+            &[],
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            OverlayBlend::Additive,
+        )?;
+
+        Ok(Self {
+            set_layout,
+            update_pipeline,
+            update_pipeline_layout,
+            draw_pipeline,
+            draw_pipeline_layout,
+            emitters: Vec::new(),
+            next_id: 0,
+            seed: 0,
+            delta_time: 0.0,
+        })
+    }
+
+    /// Adds an emitter with room for `capacity` particles alive at once
+    pub(crate) fn add(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        desc: ParticleEmitter,
+        capacity: u32,
+    ) -> AppResult<GpuParticleEmitterId> {
+        let capacity = capacity.max(1);
+        let create_buffer = |size: u64, usage: vk::BufferUsageFlags| {
+            Application::create_buffer(
+                instance,
+                device,
+                physical_device,
+                size,
+                usage,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+        };
+        let particles = create_buffer(
+            capacity as u64 * PARTICLE_SIZE,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        )?;
+        let draw = create_buffer(
+            DRAW_BUFFER_SIZE,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+        )?;
+        let alive = create_buffer(capacity as u64 * 4, vk::BufferUsageFlags::STORAGE_BUFFER)?;
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 3,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: 1,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &self.set_layout,
+            ..Default::default()
+        };
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+
+        let buffer_infos = [&particles, &draw, &alive].map(|holder| vk::DescriptorBufferInfo {
+            buffer: holder.buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        });
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: buffer_infos.len() as u32,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            p_buffer_info: buffer_infos.as_ptr(),
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        let id = GpuParticleEmitterId(self.next_id);
+        self.next_id += 1;
+        self.emitters.push(Emitter {
+            id,
+            desc,
+            capacity,
+            particles,
+            draw,
+            alive,
+            descriptor_pool,
+            descriptor_set,
+            pending: 0.0,
+            spawn_count: 0,
+            cleared: false,
+        });
+        Ok(id)
+    }
+
+    /// Removes the emitter along with its particles, the device must be idle
+    pub(crate) fn remove(
+        &mut self,
+        device: &Device,
+        id: GpuParticleEmitterId,
+    ) -> Option<ParticleEmitter> {
+        let index = self.emitters.iter().position(|emitter| emitter.id == id)?;
+        let emitter = self.emitters.remove(index);
+        Self::destroy_emitter(device, &emitter);
+        Some(emitter.desc)
+    }
+
+    pub(crate) fn get_mut(&mut self, id: GpuParticleEmitterId) -> Option<&mut ParticleEmitter> {
+        self.emitters
+            .iter_mut()
+            .find(|emitter| emitter.id == id)
+            .map(|emitter| &mut emitter.desc)
+    }
+
+    /// Particle, alive list and draw argument buffers of each emitter
+    pub(crate) fn buffers(&self) -> Vec<[vk::Buffer; 3]> {
+        self.emitters
+            .iter()
+            .map(|emitter| {
+                [
+                    emitter.particles.buffer,
+                    emitter.alive.buffer,
+                    emitter.draw.buffer,
+                ]
+            })
+            .collect()
+    }
+
+    /// Sets the time the next simulation pass moves the particles by, and the particles each
+    /// emitter brings back to life
+    pub(crate) fn update(&mut self, delta_time: f32) {
+        self.delta_time = delta_time;
+        self.seed = self.seed.wrapping_add(1);
+        for emitter in self.emitters.iter_mut() {
+            emitter.pending += emitter.desc.rate * delta_time;
+            emitter.spawn_count = emitter.pending as u32;
+            emitter.pending -= emitter.spawn_count as f32;
+        }
+    }
+
+    /// Records the update of the particles of every emitter, before the scene render pass. The
+    /// barrier between the two is left to the render graph of the frame.
+    pub(crate) fn record_simulation(&mut self, device: &Device, command_buffer: vk::CommandBuffer) {
+        if self.emitters.is_empty() {
+            return;
+        }
+
+        let reset_barrier = vk::MemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            ..Default::default()
+        };
+        // The draw arguments start from no particle alive nor spawned
+        let reset: [u32; 5] = [VERTICES_PER_PARTICLE, 0, 0, 0, 0];
+
+        unsafe {
+            // The buffers are shared by the frames in flight, the previous frame must be done
+            // drawing the particles before they are rewritten
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::DRAW_INDIRECT,
+                vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[],
+            );
+            for emitter in self.emitters.iter_mut() {
+                // Every word set to the largest float, the age making each particle dead
+                if !emitter.cleared {
+                    device.cmd_fill_buffer(
+                        command_buffer,
+                        emitter.particles.buffer,
+                        0,
+                        vk::WHOLE_SIZE,
+                        f32::MAX.to_bits(),
+                    );
+                    emitter.cleared = true;
+                }
+                device.cmd_update_buffer(
+                    command_buffer,
+                    emitter.draw.buffer,
+                    0,
+                    std::slice::from_raw_parts(
+                        reset.as_ptr() as *const u8,
+                        std::mem::size_of_val(&reset),
+                    ),
+                );
+            }
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[reset_barrier],
+                &[],
+                &[],
+            );
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.update_pipeline,
+            );
+            for emitter in &self.emitters {
+                let desc = &emitter.desc;
+                let constants = SimulationConstants {
+                    emitter_position: [
+                        desc.position.x,
+                        desc.position.y,
+                        desc.position.z,
+                        self.delta_time,
+                    ],
+                    velocity: desc.velocity.extend(desc.velocity_spread).into(),
+                    acceleration: desc.acceleration.extend(desc.lifetime).into(),
+                    spawn_count: emitter.spawn_count,
+                    seed: self.seed,
+                    capacity: emitter.capacity,
+                };
+
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.update_pipeline_layout,
+                    0,
+                    &[emitter.descriptor_set],
+                    &[],
+                );
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.update_pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    std::slice::from_raw_parts(
+                        &constants as *const SimulationConstants as *const u8,
+                        std::mem::size_of::<SimulationConstants>(),
+                    ),
+                );
+                device.cmd_dispatch(
+                    command_buffer,
+                    emitter.capacity.div_ceil(WORKGROUP_SIZE),
+                    1,
+                    1,
+                );
+            }
+        }
+    }
+
+    /// Records the particles seen from the camera of `view` through `view_proj`, in the scene
+    /// render pass. Returns the number of draw calls.
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        view: Mat4,
+        view_proj: Mat4,
+    ) -> u32 {
+        if self.emitters.is_empty() {
+            return 0;
+        }
+
+        // The viewport and scissor of the scene pass are already set
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.draw_pipeline,
+            );
+            for emitter in &self.emitters {
+                let desc = &emitter.desc;
+                // The rows of the rotation of the view are the camera axes in world space
+                let constants = DrawConstants {
+                    view_proj,
+                    right: [view.x.x, view.y.x, view.z.x, desc.size],
+                    up: [view.x.y, view.y.y, view.z.y, desc.lifetime],
+                    start_color: desc.start_color.into(),
+                    end_color: desc.end_color.into(),
+                };
+
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.draw_pipeline_layout,
+                    0,
+                    &[emitter.descriptor_set],
+                    &[],
+                );
+                device.cmd_push_constants(
+                    command_buffer,
+                    self.draw_pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    std::slice::from_raw_parts(
+                        &constants as *const DrawConstants as *const u8,
+                        std::mem::size_of::<DrawConstants>(),
+                    ),
+                );
+                device.cmd_draw_indirect(
+                    command_buffer,
+                    emitter.draw.buffer,
+                    0,
+                    1,
+                    std::mem::size_of::<vk::DrawIndirectCommand>() as u32,
+                );
+            }
+        }
+        self.emitters.len() as u32
+    }
+
+    fn destroy_emitter(device: &Device, emitter: &Emitter) {
+        unsafe {
+            for holder in [&emitter.particles, &emitter.draw, &emitter.alive] {
+                handle_registry::unregister(holder.buffer);
+                device.destroy_buffer(holder.buffer, None);
+                handle_registry::unregister(holder.memory);
+                device.free_memory(holder.memory, None);
+            }
+            handle_registry::unregister(emitter.descriptor_pool);
+            device.destroy_descriptor_pool(emitter.descriptor_pool, None);
+        }
+    }
+
+    /// Destroys the emitters and the pipelines, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        for emitter in self.emitters.drain(..) {
+            Self::destroy_emitter(device, &emitter);
+        }
+
+        unsafe {
+            handle_registry::unregister(self.update_pipeline);
+            device.destroy_pipeline(self.update_pipeline, None);
+            handle_registry::unregister(self.update_pipeline_layout);
+            device.destroy_pipeline_layout(self.update_pipeline_layout, None);
+            handle_registry::unregister(self.draw_pipeline);
+            device.destroy_pipeline(self.draw_pipeline, None);
+            handle_registry::unregister(self.draw_pipeline_layout);
+            device.destroy_pipeline_layout(self.draw_pipeline_layout, None);
+            handle_registry::unregister(self.set_layout);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use ash::{vk, Device, Instance};
+
+use crate::{handle_registry, AppResult, Application};
+
+/// Buffer bound to its own memory, created with `Application::create_gpu_buffer` and destroyed
+/// with `Application::destroy_gpu_buffer`
+#[derive(Debug)]
+pub struct GpuBuffer {
+    pub(crate) buffer: vk::Buffer,
+    pub(crate) memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    memory_size: vk::DeviceSize,
+    memory_properties: vk::MemoryPropertyFlags,
+}
+
+impl GpuBuffer {
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_properties: vk::MemoryPropertyFlags,
+    ) -> AppResult<Self> {
+        let holder = Application::create_buffer(
+            instance,
+            device,
+            physical_device,
+            size,
+            usage,
+            memory_properties,
+        )?;
+        let memory_size = unsafe { device.get_buffer_memory_requirements(holder.buffer).size };
+
+        Ok(Self {
+            buffer: holder.buffer,
+            memory: holder.memory,
+            size,
+            memory_size,
+            memory_properties,
+        })
+    }
+
+    /// Size the buffer was created with
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// Size of the memory bound to the buffer
+    pub fn memory_size(&self) -> vk::DeviceSize {
+        self.memory_size
+    }
+
+    pub fn memory_properties(&self) -> vk::MemoryPropertyFlags {
+        self.memory_properties
+    }
+
+    /// Raw handle of the buffer, e.g. to bind or copy it in a render hook.
+    ///
+    /// # Safety
+    /// The handle must not be destroyed, nor used once the buffer is destroyed.
+    pub unsafe fn raw(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    /// Raw handle of the memory bound to the buffer.
+    ///
+    /// # Safety
+    /// The memory must not be freed or rebound, nor used once the buffer is destroyed.
+    pub unsafe fn raw_memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+
+    /// Copies `data` at `offset` through a temporary mapping, the buffer must be host visible and
+    /// coherent and the range must not be in use by the GPU
+    pub(crate) fn write(
+        &self,
+        device: &Device,
+        offset: vk::DeviceSize,
+        data: &[u8],
+    ) -> AppResult<()> {
+        unsafe {
+            let dst = device.map_memory(
+                self.memory,
+                offset,
+                data.len() as u64,
+                vk::MemoryMapFlags::empty(),
+            )?;
+            std::ptr::copy(data.as_ptr(), dst as *mut u8, data.len());
+            device.unmap_memory(self.memory);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn destroy(self, device: &Device) {
+        unsafe {
+            handle_registry::unregister(self.buffer);
+            device.destroy_buffer(self.buffer, None);
+            handle_registry::unregister(self.memory);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// Device local 2D image with a view of all its levels, created with
+/// `Application::create_gpu_image` and destroyed with `Application::destroy_gpu_image`. It is
+/// created in the `UNDEFINED` layout, its transitions are left to the user.
+#[derive(Debug)]
+pub struct GpuImage {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    extent: vk::Extent2D,
+    mip_levels: u32,
+    format: vk::Format,
+    memory_size: vk::DeviceSize,
+}
+
+impl GpuImage {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        mip_levels: u32,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> AppResult<Self> {
+        let holder = Application::create_image(
+            instance,
+            device,
+            physical_device,
+            extent.width,
+            extent.height,
+            mip_levels,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let view = Application::create_image_view(device, holder.image, format, mip_levels)?;
+        let memory_size = unsafe { device.get_image_memory_requirements(holder.image).size };
+
+        Ok(Self {
+            image: holder.image,
+            memory: holder.memory,
+            view,
+            extent,
+            mip_levels,
+            format,
+            memory_size,
+        })
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    /// Size of the memory bound to the image
+    pub fn memory_size(&self) -> vk::DeviceSize {
+        self.memory_size
+    }
+
+    /// Raw handle of the image, e.g. to transition or render to it in a render hook.
+    ///
+    /// # Safety
+    /// The handle must not be destroyed, nor used once the image is destroyed.
+    pub unsafe fn raw(&self) -> vk::Image {
+        self.image
+    }
+
+    /// Raw handle of the view of every level of the image.
+    ///
+    /// # Safety
+    /// The handle must not be destroyed, nor used once the image is destroyed.
+    pub unsafe fn raw_view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    /// Raw handle of the memory bound to the image.
+    ///
+    /// # Safety
+    /// The memory must not be freed or rebound, nor used once the image is destroyed.
+    pub unsafe fn raw_memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+
+    pub(crate) fn destroy(self, device: &Device) {
+        unsafe {
+            handle_registry::unregister(self.view);
+            device.destroy_image_view(self.view, None);
+            handle_registry::unregister(self.image);
+            device.destroy_image(self.image, None);
+            handle_registry::unregister(self.memory);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// Sampler created with `Application::create_gpu_sampler` and destroyed with
+/// `Application::destroy_gpu_sampler`
+#[derive(Debug)]
+pub struct GpuSampler {
+    sampler: vk::Sampler,
+}
+
+impl GpuSampler {
+    /// Samples every level with `filter`, addressing the three coordinates with `address_mode`
+    pub(crate) fn new(
+        device: &Device,
+        filter: vk::Filter,
+        address_mode: vk::SamplerAddressMode,
+    ) -> AppResult<Self> {
+        let mipmap_mode = match filter {
+            vk::Filter::NEAREST => vk::SamplerMipmapMode::NEAREST,
+            _ => vk::SamplerMipmapMode::LINEAR,
+        };
+        let create_info = vk::SamplerCreateInfo {
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_mode,
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+            max_lod: vk::LOD_CLAMP_NONE,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.create_sampler(&create_info, None)? };
+
+        Ok(Self {
+            sampler: handle_registry::register(sampler),
+        })
+    }
+
+    /// Raw handle of the sampler, e.g. to write it to a descriptor set.
+    ///
+    /// # Safety
+    /// The handle must not be destroyed, nor used once the sampler is destroyed.
+    pub unsafe fn raw(&self) -> vk::Sampler {
+        self.sampler
+    }
+
+    pub(crate) fn destroy(self, device: &Device) {
+        unsafe {
+            handle_registry::unregister(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+#[cfg(debug_assertions)]
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    collections::BTreeMap,
+    sync::Mutex,
+};
+
+use ash::vk::Handle;
+#[cfg(debug_assertions)]
+use ash::vk::ObjectType;
+#[cfg(debug_assertions)]
+use tracing::warn;
+
+// Every Vulkan handle created by the renderer in debug builds, with where it was created. The
+// non-dispatchable handles aren't unique, identical objects may share a handle.
+#[cfg(debug_assertions)]
+static LIVE_HANDLES: Mutex<BTreeMap<(i32, u64), Vec<Backtrace>>> = Mutex::new(BTreeMap::new());
+
+/// Records that `handle` has been created, returns it unchanged. Does nothing in release builds.
+#[cfg_attr(not(debug_assertions), inline(always))]
+pub(crate) fn register<H: Handle + Copy>(handle: H) -> H {
+    #[cfg(debug_assertions)]
+    LIVE_HANDLES
+        .lock()
+        .unwrap()
+        .entry((H::TYPE.as_raw(), handle.as_raw()))
+        .or_default()
+        .push(Backtrace::capture());
+
+    handle
+}
+
+/// Records that `handle` has been destroyed. Does nothing in release builds.
+#[cfg_attr(not(debug_assertions), inline(always))]
+pub(crate) fn unregister<H: Handle>(handle: H) {
+    #[cfg(debug_assertions)]
+    {
+        let mut live_handles = LIVE_HANDLES.lock().unwrap();
+        let key = (H::TYPE.as_raw(), handle.as_raw());
+        if let Some(backtraces) = live_handles.get_mut(&key) {
+            backtraces.pop();
+            if backtraces.is_empty() {
+                live_handles.remove(&key);
+            }
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    let _ = handle;
+}
+
+/// Prints the registered handles that haven't been destroyed, along with where they were created.
+/// Does nothing in release builds.
+pub(crate) fn report_leaks() {
+    #[cfg(debug_assertions)]
+    {
+        let live_handles = LIVE_HANDLES.lock().unwrap();
+        let mut captured = true;
+        for (&(object_type, raw), backtraces) in live_handles.iter() {
+            for backtrace in backtraces {
+                warn!(
+                    object_type = ?ObjectType::from_raw(object_type),
+                    handle = format_args!("{:#x}", raw),
+                    "Vulkan handle leaked, created at:\n{}",
+                    backtrace
+                );
+                captured &= backtrace.status() == BacktraceStatus::Captured;
+            }
+        }
+
+        if !captured {
+            warn!("run with `RUST_BACKTRACE=1` to know where the leaked handles were created");
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use ash::{vk, Device};
+
+/// Points of the frame where user commands can be recorded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RenderHook {
+    /// Inside the scene render pass, before the scene is drawn
+    BeforeOpaque,
+    /// Inside the scene render pass, after the opaque items and before the transparent ones
+    AfterOpaque,
+    /// Outside of any render pass, once the scene target is ready to be sampled by the post chain
+    BeforePost,
+    /// Inside the swapchain render pass, after the composition and the UI
+    AfterUi,
+}
+
+/// Identifies a registered hook so it can be removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RenderHookId(u64);
+
+/// State of the frame available to a hook
+pub struct RenderHookContext<'a> {
+    pub device: &'a Device,
+    pub command_buffer: vk::CommandBuffer,
+    pub frame_index: usize,
+    pub extent: vk::Extent2D,
+    /// Render pass currently begun, null for `RenderHook::BeforePost`
+    pub render_pass: vk::RenderPass,
+    /// Samples per pixel of `render_pass`, which the pipelines drawing in it must be created with
+    pub samples: vk::SampleCountFlags,
+    /// Color target of the hook point: the scene target, resolved at the end of the scene pass
+    /// when multisampled, or the swapchain image for `RenderHook::AfterUi`
+    pub color_image: vk::Image,
+    pub color_view: vk::ImageView,
+    /// Descriptor set of the frame (uniform buffer and texture), and a pipeline layout it can be
+    /// bound with at set 0. The uniform buffer is dynamic, the set is bound with
+    /// `frame_uniform_offset`, the offset of the first draw item.
+    pub frame_descriptor_set: vk::DescriptorSet,
+    pub frame_uniform_offset: u32,
+    pub frame_pipeline_layout: vk::PipelineLayout,
+}
+
+pub type RenderHookFn = Box<dyn FnMut(&RenderHookContext)>;
+
+/// Hooks registered on the application, run in registration order
+#[derive(Default)]
+pub(crate) struct RenderHooks {
+    hooks: Vec<(RenderHookId, RenderHook, RenderHookFn)>,
+    next_id: u64,
+}
+
+impl RenderHooks {
+    pub(crate) fn add(&mut self, point: RenderHook, hook: RenderHookFn) -> RenderHookId {
+        let id = RenderHookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push((id, point, hook));
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: RenderHookId) -> Option<RenderHookFn> {
+        let index = self
+            .hooks
+            .iter()
+            .position(|(hook_id, _, _)| *hook_id == id)?;
+        Some(self.hooks.remove(index).2)
+    }
+
+    /// Runs every hook registered at `point`
+    pub(crate) fn run(&mut self, point: RenderHook, context: &RenderHookContext) {
+        for (_, hook_point, hook) in self.hooks.iter_mut() {
+            if *hook_point == point {
+                hook(context);
+            }
+        }
+    }
+}
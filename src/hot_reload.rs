@@ -0,0 +1,162 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    load_mesh_cached, texture_loader::DecodedTexture, AppResult, MaterialHandle, MeshData,
+    MeshHandle, PbrMaterial, TextureHandle, Vertex,
+};
+
+/// Time between two checks of the watched files when hot reloading is enabled, see
+/// `Application::set_hot_reload`
+pub const HOT_RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Imports the vertices and indices of a mesh file, called again whenever the file changes
+pub(crate) type MeshImporter = Box<dyn Fn(&Path) -> AppResult<(Vec<Vertex>, Vec<u32>)>>;
+
+/// Decodes PBR materials again from their files, called whenever one of the files changes
+pub(crate) type PbrImporter = Rc<dyn Fn() -> AppResult<Vec<PbrMaterial<DecodedTexture>>>>;
+
+/// Asset loaded from a watched file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum WatchedAsset {
+    Texture(TextureHandle),
+    Mesh(MeshHandle),
+    /// PBR materials with consecutive handles, starting at `first`
+    PbrMaterials {
+        first: MaterialHandle,
+        count: usize,
+    },
+}
+
+enum Importer {
+    Mesh(MeshImporter),
+    // Shared by the files of the materials
+    Pbr(PbrImporter),
+}
+
+struct WatchedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    asset: WatchedAsset,
+    // Not set for the textures
+    importer: Option<Importer>,
+}
+
+/// Files the textures and meshes were loaded from, whose modification time is polled to reload
+/// the assets they changed
+#[derive(Default)]
+pub(crate) struct AssetWatcher {
+    enabled: bool,
+    files: Vec<WatchedFile>,
+    last_poll: Option<Instant>,
+}
+
+impl AssetWatcher {
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Watches the texture loaded from `path`
+    pub(crate) fn watch_texture(&mut self, path: &Path, texture: TextureHandle) {
+        self.watch(path, WatchedAsset::Texture(texture), None);
+    }
+
+    /// Watches the mesh imported from `path` with `importer`
+    pub(crate) fn watch_mesh(&mut self, path: &Path, mesh: MeshHandle, importer: MeshImporter) {
+        self.watch(
+            path,
+            WatchedAsset::Mesh(mesh),
+            Some(Importer::Mesh(importer)),
+        );
+    }
+
+    /// Watches the files `materials` were decoded from with `importer`, any of them changing
+    /// reloading every material
+    pub(crate) fn watch_pbr_materials(
+        &mut self,
+        paths: &[&Path],
+        materials: WatchedAsset,
+        importer: PbrImporter,
+    ) {
+        for path in paths {
+            self.watch(path, materials, Some(Importer::Pbr(importer.clone())));
+        }
+    }
+
+    fn watch(&mut self, path: &Path, asset: WatchedAsset, importer: Option<Importer>) {
+        self.files.push(WatchedFile {
+            path: path.to_path_buf(),
+            modified: Self::modified(path),
+            asset,
+            importer,
+        });
+    }
+
+    /// Stops watching the file of `asset`, e.g. once it is freed
+    pub(crate) fn unwatch(&mut self, asset: WatchedAsset) {
+        self.files.retain(|file| file.asset != asset);
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Returns the assets whose file changed since the last poll along with their path, polling
+    /// at most once per `HOT_RELOAD_INTERVAL` while enabled
+    pub(crate) fn poll(&mut self, now: Instant) -> Vec<(WatchedAsset, PathBuf)> {
+        if !self.enabled
+            || self
+                .last_poll
+                .is_some_and(|last| now.duration_since(last) < HOT_RELOAD_INTERVAL)
+        {
+            return vec![];
+        }
+        self.last_poll = Some(now);
+
+        let mut changed = vec![];
+        for file in self.files.iter_mut() {
+            // A file being rewritten can briefly be missing, it is reloaded once it is back
+            let modified = Self::modified(&file.path);
+            if modified.is_some() && modified != file.modified {
+                file.modified = modified;
+                // The materials are reloaded once however many of their files changed
+                if !changed.iter().any(|(asset, _)| *asset == file.asset) {
+                    changed.push((file.asset, file.path.clone()));
+                }
+            }
+        }
+        changed
+    }
+
+    /// Imports the mesh again from its changed file, refreshing its cache
+    pub(crate) fn import_mesh(&self, mesh: MeshHandle) -> Option<AppResult<MeshData>> {
+        let file = self
+            .files
+            .iter()
+            .find(|file| file.asset == WatchedAsset::Mesh(mesh))?;
+        let Some(Importer::Mesh(importer)) = &file.importer else {
+            return None;
+        };
+
+        Some(load_mesh_cached(&file.path, importer))
+    }
+
+    /// Decodes the materials again from their changed files
+    pub(crate) fn import_pbr_materials(
+        &self,
+        materials: WatchedAsset,
+    ) -> Option<AppResult<Vec<PbrMaterial<DecodedTexture>>>> {
+        let file = self.files.iter().find(|file| file.asset == materials)?;
+        let Some(Importer::Pbr(importer)) = &file.importer else {
+            return None;
+        };
+
+        Some(importer())
+    }
+}
@@ -0,0 +1,533 @@
+use ash::{vk, Device, Instance};
+
+use crate::{
+    handle_registry, load_shader,
+    post::{create_fullscreen_pipeline, SCENE_COLOR_FORMAT},
+    AppResult, Application, ImageHolder, SyncPool, CUBEMAP_FACES,
+};
+
+/// Size of the faces of the diffuse irradiance cubemap, which has no high frequencies
+pub const IRRADIANCE_RESOLUTION: u32 = 32;
+/// Size of the faces of the prefiltered specular cubemap
+pub const SPECULAR_RESOLUTION: u32 = 128;
+/// Levels of the specular cubemap, prefiltered from a mirror-like surface at level 0 to a fully
+/// rough one at the last level
+pub const SPECULAR_MIP_LEVELS: u32 = 5;
+pub const BRDF_LUT_RESOLUTION: u32 = 256;
+
+/// Views of the image based lighting maps, in the shader read only layout. They are meant to be
+/// sampled with linear filtering and clamped coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IblViews {
+    /// Cosine weighted irradiance of the environment around a normal
+    pub irradiance: vk::ImageView,
+    /// Environment prefiltered with the GGX distribution, the roughness of its level `i` being
+    /// `i / (SPECULAR_MIP_LEVELS - 1)`
+    pub specular: vk::ImageView,
+    /// Scale (red) and bias (green) of the Fresnel reflectance at normal incidence, for the
+    /// cosine of the view angle along u and the roughness along v
+    pub brdf_lut: vk::ImageView,
+}
+
+/// Maps of the split sum approximation of the ambient lighting of an environment cubemap,
+/// rendered once from it with a single sample render pass of the scene format
+pub(crate) struct ImageBasedLighting {
+    irradiance: ImageHolder,
+    specular: ImageHolder,
+    brdf_lut: ImageHolder,
+    views: IblViews,
+}
+
+// Pipelines and descriptors used while baking only
+struct BakePipelines {
+    render_pass: vk::RenderPass,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    irradiance: vk::Pipeline,
+    specular: vk::Pipeline,
+    brdf_lut: vk::Pipeline,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl ImageBasedLighting {
+    /// Renders the maps of the cubemap `environment` and waits for them. The environment is
+    /// sampled from its level 0, so it should be at least `SPECULAR_RESOLUTION` texels wide.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn bake(
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        environment: vk::ImageView,
+    ) -> AppResult<Self> {
+        let pipelines = BakePipelines::new(device, environment)?;
+
+        let cube_flags = vk::ImageCreateFlags::CUBE_COMPATIBLE;
+        let irradiance = Self::create_image(
+            instance,
+            device,
+            physical_device,
+            cube_flags,
+            IRRADIANCE_RESOLUTION,
+            1,
+            CUBEMAP_FACES as u32,
+        )?;
+        let specular = Self::create_image(
+            instance,
+            device,
+            physical_device,
+            cube_flags,
+            SPECULAR_RESOLUTION,
+            SPECULAR_MIP_LEVELS,
+            CUBEMAP_FACES as u32,
+        )?;
+        let brdf_lut = Self::create_image(
+            instance,
+            device,
+            physical_device,
+            vk::ImageCreateFlags::empty(),
+            BRDF_LUT_RESOLUTION,
+            1,
+            1,
+        )?;
+
+        let faces = CUBEMAP_FACES as u32;
+        let cube = vk::ImageViewType::CUBE;
+        let views = IblViews {
+            irradiance: Self::create_view(device, irradiance.image, cube, 0, 1, 0, faces)?,
+            specular: Self::create_view(
+                device,
+                specular.image,
+                cube,
+                0,
+                SPECULAR_MIP_LEVELS,
+                0,
+                faces,
+            )?,
+            brdf_lut: Self::create_view(
+                device,
+                brdf_lut.image,
+                vk::ImageViewType::TYPE_2D,
+                0,
+                1,
+                0,
+                1,
+            )?,
+        };
+
+        // The face views and framebuffers are destroyed once the passes are done
+        let mut targets = vec![];
+        let command_buffer = Application::begin_singe_time_command(device, command_pool)?;
+        for face in 0..faces {
+            unsafe {
+                Self::record_pass(
+                    device,
+                    command_buffer,
+                    &pipelines,
+                    pipelines.irradiance,
+                    &face.to_ne_bytes(),
+                    (irradiance.image, 0, face),
+                    IRRADIANCE_RESOLUTION,
+                    &mut targets,
+                )?;
+            }
+        }
+        for level in 0..SPECULAR_MIP_LEVELS {
+            let roughness = level as f32 / (SPECULAR_MIP_LEVELS - 1) as f32;
+            for face in 0..faces {
+                let constants = [face, roughness.to_bits()];
+                unsafe {
+                    Self::record_pass(
+                        device,
+                        command_buffer,
+                        &pipelines,
+                        pipelines.specular,
+                        std::slice::from_raw_parts(constants.as_ptr() as *const u8, 8),
+                        (specular.image, level, face),
+                        SPECULAR_RESOLUTION >> level,
+                        &mut targets,
+                    )?;
+                }
+            }
+        }
+        unsafe {
+            Self::record_pass(
+                device,
+                command_buffer,
+                &pipelines,
+                pipelines.brdf_lut,
+                &[],
+                (brdf_lut.image, 0, 0),
+                BRDF_LUT_RESOLUTION,
+                &mut targets,
+            )?;
+        }
+        let submitted = Application::end_single_time_command(
+            device,
+            queue,
+            command_pool,
+            sync_pool,
+            command_buffer,
+        );
+
+        unsafe {
+            for (framebuffer, view) in targets {
+                handle_registry::unregister(framebuffer);
+                device.destroy_framebuffer(framebuffer, None);
+                handle_registry::unregister(view);
+                device.destroy_image_view(view, None);
+            }
+            pipelines.destroy(device);
+        }
+        submitted?;
+
+        Ok(Self {
+            irradiance,
+            specular,
+            brdf_lut,
+            views,
+        })
+    }
+
+    pub(crate) fn views(&self) -> IblViews {
+        self.views
+    }
+
+    pub(crate) fn memory_size(&self, device: &Device) -> u64 {
+        [&self.irradiance, &self.specular, &self.brdf_lut]
+            .iter()
+            .map(|image| unsafe { device.get_image_memory_requirements(image.image).size })
+            .sum()
+    }
+
+    /// Renders a full-screen triangle with `pipeline` in a level and layer of `image`, whose
+    /// view and framebuffer are pushed to `targets`
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn record_pass(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipelines: &BakePipelines,
+        pipeline: vk::Pipeline,
+        constants: &[u8],
+        (image, level, layer): (vk::Image, u32, u32),
+        size: u32,
+        targets: &mut Vec<(vk::Framebuffer, vk::ImageView)>,
+    ) -> AppResult<()> {
+        let view = Self::create_view(
+            device,
+            image,
+            vk::ImageViewType::TYPE_2D,
+            level,
+            1,
+            layer,
+            1,
+        )?;
+        let attachments = [view];
+        let framebuffer_info = vk::FramebufferCreateInfo {
+            render_pass: pipelines.render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: size,
+            height: size,
+            layers: 1,
+            ..Default::default()
+        };
+        let framebuffer =
+            handle_registry::register(device.create_framebuffer(&framebuffer_info, None)?);
+        targets.push((framebuffer, view));
+
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vk::Extent2D {
+                width: size,
+                height: size,
+            },
+        };
+        let clear_values = [vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 1.0],
+            },
+        }];
+        let render_pass_info = vk::RenderPassBeginInfo {
+            render_pass: pipelines.render_pass,
+            framebuffer,
+            render_area,
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+            ..Default::default()
+        };
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &render_pass_info,
+            vk::SubpassContents::INLINE,
+        );
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: size as f32,
+            height: size as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        device.cmd_set_viewport(command_buffer, 0, &viewports);
+        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipelines.pipeline_layout,
+            0,
+            &[pipelines.descriptor_set],
+            &[],
+        );
+        if !constants.is_empty() {
+            device.cmd_push_constants(
+                command_buffer,
+                pipelines.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                constants,
+            );
+        }
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        device.cmd_end_render_pass(command_buffer);
+
+        Ok(())
+    }
+
+    fn create_image(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        flags: vk::ImageCreateFlags,
+        size: u32,
+        mip_levels: u32,
+        array_layers: u32,
+    ) -> AppResult<ImageHolder> {
+        let image_info = vk::ImageCreateInfo {
+            flags,
+            image_type: vk::ImageType::TYPE_2D,
+            format: SCENE_COLOR_FORMAT,
+            extent: vk::Extent3D {
+                width: size,
+                height: size,
+                depth: 1,
+            },
+            mip_levels,
+            array_layers,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            ..Default::default()
+        };
+        let image = unsafe { device.create_image(&image_info, None)? };
+        handle_registry::register(image);
+
+        let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
+        let alloc_info = vk::MemoryAllocateInfo {
+            allocation_size: mem_requirements.size,
+            memory_type_index: Application::find_memory_type(
+                instance,
+                physical_device,
+                mem_requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?,
+            ..Default::default()
+        };
+        let memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+        handle_registry::register(memory);
+        unsafe { device.bind_image_memory(image, memory, 0)? };
+
+        Ok(ImageHolder::new(image, memory))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_view(
+        device: &Device,
+        image: vk::Image,
+        view_type: vk::ImageViewType,
+        base_mip_level: u32,
+        level_count: u32,
+        base_array_layer: u32,
+        layer_count: u32,
+    ) -> AppResult<vk::ImageView> {
+        let create_info = vk::ImageViewCreateInfo {
+            image,
+            view_type,
+            format: SCENE_COLOR_FORMAT,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level,
+                level_count,
+                base_array_layer,
+                layer_count,
+            },
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_image_view(&create_info, None)?,
+            ))
+        }
+    }
+
+    /// Destroys the maps, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for view in [
+                self.views.irradiance,
+                self.views.specular,
+                self.views.brdf_lut,
+            ] {
+                handle_registry::unregister(view);
+                device.destroy_image_view(view, None);
+            }
+            for image in [&self.irradiance, &self.specular, &self.brdf_lut] {
+                handle_registry::unregister(image.image);
+                device.destroy_image(image.image, None);
+                handle_registry::unregister(image.memory);
+                device.free_memory(image.memory, None);
+            }
+        }
+    }
+}
+
+impl BakePipelines {
+    fn new(device: &Device, environment: vk::ImageView) -> AppResult<Self> {
+        let render_pass = Application::create_render_pass(
+            device,
+            SCENE_COLOR_FORMAT,
+            vk::AttachmentLoadOp::CLEAR,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        }];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        // Shared by the three passes, the specular one pushing the face and the roughness
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: 8,
+            }],
+        )?;
+        let create_pipeline = |name: &str, bytes: &[u8]| -> AppResult<vk::Pipeline> {
+            let pipeline = create_fullscreen_pipeline(
+                device,
+                render_pass,
+                pipeline_layout,
+                &load_shader(name, bytes)?,
+            )?;
+            Ok(handle_registry::register(pipeline))
+        };
+        let irradiance = create_pipeline(
+            "ibl_irradiance.frag",
+            include_bytes!("spirv/ibl_irradiance.spv"),
+        )?;
+        // Same prefiltering as the reflection probes
+        let specular = create_pipeline(
+            "probe_prefilter.frag",
+            include_bytes!("spirv/probe_prefilter.spv"),
+        )?;
+        let brdf_lut = create_pipeline("brdf_lut.frag", include_bytes!("spirv/brdf_lut.spv"))?;
+
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+        handle_registry::register(sampler);
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: 1,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &set_layout as *const _,
+            ..Default::default()
+        };
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+        let image_info = vk::DescriptorImageInfo {
+            sampler,
+            image_view: environment,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: &image_info as *const _,
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        Ok(Self {
+            render_pass,
+            set_layout,
+            pipeline_layout,
+            irradiance,
+            specular,
+            brdf_lut,
+            sampler,
+            descriptor_pool,
+            descriptor_set,
+        })
+    }
+
+    unsafe fn destroy(self, device: &Device) {
+        handle_registry::unregister(self.descriptor_pool);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        handle_registry::unregister(self.sampler);
+        device.destroy_sampler(self.sampler, None);
+        for pipeline in [self.irradiance, self.specular, self.brdf_lut] {
+            handle_registry::unregister(pipeline);
+            device.destroy_pipeline(pipeline, None);
+        }
+        handle_registry::unregister(self.pipeline_layout);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        handle_registry::unregister(self.set_layout);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+        handle_registry::unregister(self.render_pass);
+        device.destroy_render_pass(self.render_pass, None);
+    }
+}
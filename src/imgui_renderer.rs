@@ -0,0 +1,414 @@
+use ash::{vk, Device, Instance};
+use imgui::{DrawCmd, DrawCmdParams, DrawData, DrawIdx, DrawVert};
+
+use crate::{
+    garbage_collector::{Garbage, GarbageCollector},
+    handle_registry, load_shader,
+    overlay::{create_overlay_pipeline, OverlayBlend},
+    AppResult, Application, MemoryMappedBuffer, PerFrame, TextureHolder,
+};
+
+// Push constants of imgui.vert
+#[repr(C)]
+struct ImguiConstants {
+    scale: [f32; 2],
+    translate: [f32; 2],
+}
+
+// An indexed draw of the translated draw data, clipped to `clip_rect`
+struct ImguiCommand {
+    clip_rect: vk::Rect2D,
+    index_count: u32,
+    first_index: u32,
+    vertex_offset: i32,
+}
+
+// Vertex and index buffers of a frame in flight, grown when the draw data outgrows them
+#[derive(Default)]
+struct ImguiBuffers {
+    vertex_buffer: Option<MemoryMappedBuffer>,
+    vertex_capacity: usize,
+    index_buffer: Option<MemoryMappedBuffer>,
+    index_capacity: usize,
+}
+
+/// Draws the Dear ImGui draw data over the swapchain image, in the composite render pass.
+///
+/// Only the font atlas is bound, every texture id of the draw data sampling it.
+pub(crate) struct ImguiRenderer {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    font_atlas: TextureHolder,
+    buffers: PerFrame<ImguiBuffers>,
+
+    // Draw data of the next frame, translated to the buffer layout
+    vertices: Vec<DrawVert>,
+    indices: Vec<DrawIdx>,
+    commands: Vec<ImguiCommand>,
+    constants: ImguiConstants,
+}
+
+impl ImguiRenderer {
+    /// Creates the renderer drawing in `render_pass`, sampling `font_atlas` with `sampler`
+    pub(crate) fn new(
+        device: &Device,
+        render_pass: vk::RenderPass,
+        font_atlas: TextureHolder,
+        sampler: vk::Sampler,
+        frame_count: usize,
+    ) -> AppResult<Self> {
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        }];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: 1,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &set_layout,
+            ..Default::default()
+        };
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+        let image_info = vk::DescriptorImageInfo {
+            sampler,
+            image_view: font_atlas.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: &image_info,
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<ImguiConstants>() as u32,
+            }],
+        )?;
+        let pipeline = create_overlay_pipeline(
+            device,
+            render_pass,
+            vk::SampleCountFlags::TYPE_1,
+            pipeline_layout,
+            &load_shader("imgui.vert", include_bytes!("spirv/imgui.spv"))?,
+            &load_shader("imgui_atlas.frag", include_bytes!("spirv/imgui_atlas.spv"))?,
+            &[vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: std::mem::size_of::<DrawVert>() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }],
+            &[
+                vk::VertexInputAttributeDescription {
+                    location: 0,
+                    binding: 0,
+                    format: vk::Format::R32G32_SFLOAT,
+                    offset: 0,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 1,
+                    binding: 0,
+                    format: vk::Format::R32G32_SFLOAT,
+                    offset: 8,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 2,
+                    binding: 0,
+                    format: vk::Format::R8G8B8A8_UNORM,
+                    offset: 16,
+                },
+            ],
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            OverlayBlend::Alpha,
+        )?;
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            set_layout,
+            descriptor_pool,
+            descriptor_set,
+            font_atlas,
+            buffers: PerFrame::new(frame_count, |_| Ok(ImguiBuffers::default()))?,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            commands: Vec::new(),
+            constants: ImguiConstants {
+                scale: [0.0; 2],
+                translate: [0.0; 2],
+            },
+        })
+    }
+
+    /// Translates `draw_data` into the vertices, indices and draws of the next frame, replacing
+    /// the previous ones
+    pub(crate) fn set_draw_data(&mut self, draw_data: &DrawData) {
+        self.vertices.clear();
+        self.indices.clear();
+        self.commands.clear();
+
+        let [x, y] = draw_data.display_pos;
+        let [width, height] = draw_data.display_size;
+        if width <= 0.0 || height <= 0.0 {
+            return;
+        }
+        self.constants = ImguiConstants {
+            scale: [2.0 / width, 2.0 / height],
+            translate: [-1.0 - x * 2.0 / width, -1.0 - y * 2.0 / height],
+        };
+
+        let [scale_x, scale_y] = draw_data.framebuffer_scale;
+        for draw_list in draw_data.draw_lists() {
+            let vertex_offset = self.vertices.len();
+            let index_offset = self.indices.len();
+            self.vertices.extend_from_slice(draw_list.vtx_buffer());
+            self.indices.extend_from_slice(draw_list.idx_buffer());
+
+            for command in draw_list.commands() {
+                // The callbacks and the render state resets are left to the caller
+                let DrawCmd::Elements {
+                    count,
+                    cmd_params:
+                        DrawCmdParams {
+                            clip_rect,
+                            vtx_offset,
+                            idx_offset,
+                            ..
+                        },
+                } = command
+                else {
+                    continue;
+                };
+
+                let min_x = ((clip_rect[0] - x) * scale_x).max(0.0);
+                let min_y = ((clip_rect[1] - y) * scale_y).max(0.0);
+                let max_x = (clip_rect[2] - x) * scale_x;
+                let max_y = (clip_rect[3] - y) * scale_y;
+                if max_x <= min_x || max_y <= min_y {
+                    continue;
+                }
+
+                self.commands.push(ImguiCommand {
+                    clip_rect: vk::Rect2D {
+                        offset: vk::Offset2D {
+                            x: min_x as i32,
+                            y: min_y as i32,
+                        },
+                        extent: vk::Extent2D {
+                            width: (max_x - min_x) as u32,
+                            height: (max_y - min_y) as u32,
+                        },
+                    },
+                    index_count: count as u32,
+                    first_index: (index_offset + idx_offset) as u32,
+                    vertex_offset: (vertex_offset + vtx_offset) as i32,
+                });
+            }
+        }
+    }
+
+    /// Copies the translated draw data to the buffers of `frame`, which must not be in use by the
+    /// GPU, growing them if needed. The outgrown buffers are retired to `garbage_collector`.
+    pub(crate) fn upload(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        garbage_collector: &mut GarbageCollector,
+        frame: usize,
+    ) -> AppResult<()> {
+        if self.commands.is_empty() {
+            return Ok(());
+        }
+
+        let frame_count = self.buffers.len();
+        let buffers = &mut self.buffers[frame];
+        if buffers.vertex_capacity < self.vertices.len() {
+            let capacity = self.vertices.len().next_power_of_two();
+            let buffer = Application::create_host_visible_buffers(
+                instance,
+                device,
+                physical_device,
+                (capacity * std::mem::size_of::<DrawVert>()) as u64,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                1,
+            )?
+            .pop()
+            .unwrap();
+            if let Some(old) = buffers.vertex_buffer.replace(buffer) {
+                garbage_collector.retire(Garbage::Buffer(old.buffer, old.memory), frame_count);
+            }
+            buffers.vertex_capacity = capacity;
+        }
+        if buffers.index_capacity < self.indices.len() {
+            let capacity = self.indices.len().next_power_of_two();
+            let buffer = Application::create_host_visible_buffers(
+                instance,
+                device,
+                physical_device,
+                (capacity * std::mem::size_of::<DrawIdx>()) as u64,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                1,
+            )?
+            .pop()
+            .unwrap();
+            if let Some(old) = buffers.index_buffer.replace(buffer) {
+                garbage_collector.retire(Garbage::Buffer(old.buffer, old.memory), frame_count);
+            }
+            buffers.index_capacity = capacity;
+        }
+
+        unsafe {
+            std::ptr::copy(
+                self.vertices.as_ptr(),
+                buffers.vertex_buffer.as_ref().unwrap().memory_map as *mut DrawVert,
+                self.vertices.len(),
+            );
+            std::ptr::copy(
+                self.indices.as_ptr(),
+                buffers.index_buffer.as_ref().unwrap().memory_map as *mut DrawIdx,
+                self.indices.len(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Records the draws of the draw data uploaded for `frame`, in the composite render pass
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        frame: usize,
+    ) {
+        let buffers = &self.buffers[frame];
+        let (Some(vertex_buffer), Some(index_buffer)) =
+            (&buffers.vertex_buffer, &buffers.index_buffer)
+        else {
+            return;
+        };
+        if self.commands.is_empty() {
+            return;
+        }
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                std::slice::from_raw_parts(
+                    &self.constants as *const ImguiConstants as *const u8,
+                    std::mem::size_of::<ImguiConstants>(),
+                ),
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                index_buffer.buffer,
+                0,
+                vk::IndexType::UINT16,
+            );
+
+            for command in &self.commands {
+                device.cmd_set_scissor(command_buffer, 0, &[command.clip_rect]);
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    command.index_count,
+                    1,
+                    command.first_index,
+                    command.vertex_offset,
+                    0,
+                );
+            }
+        }
+    }
+
+    /// Destroys the buffers, the font atlas and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for buffers in self.buffers.iter_mut() {
+                for buffer in [buffers.vertex_buffer.take(), buffers.index_buffer.take()]
+                    .into_iter()
+                    .flatten()
+                {
+                    Garbage::Buffer(buffer.buffer, buffer.memory).destroy(device);
+                }
+            }
+
+            handle_registry::unregister(self.font_atlas.view);
+            device.destroy_image_view(self.font_atlas.view, None);
+            handle_registry::unregister(self.font_atlas.image.image);
+            device.destroy_image(self.font_atlas.image.image, None);
+            handle_registry::unregister(self.font_atlas.image.memory);
+            device.free_memory(self.font_atlas.image.memory, None);
+
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.descriptor_pool);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            handle_registry::unregister(self.set_layout);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use gilrs::{Axis, Button, EventType, Gilrs};
+use tracing::warn;
+
+// Stick deflection under which the axis is considered centered
+const DEAD_ZONE: f32 = 0.15;
+
+/// Direction of a gamepad axis an action can be bound to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+/// Change of a gamepad input, reported to the input state
+pub(super) enum GamepadChange {
+    Button(Button, bool),
+    Axis(Axis, f32),
+    /// A gamepad has been disconnected, every gamepad input is released
+    Reset,
+}
+
+/// Connected gamepads, polled once per frame
+pub(super) struct Gamepads {
+    gilrs: Option<Gilrs>,
+}
+
+impl std::fmt::Debug for Gamepads {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Gamepads")
+            .field("available", &self.gilrs.is_some())
+            .finish()
+    }
+}
+
+impl Default for Gamepads {
+    fn default() -> Self {
+        // Gamepads are optional, the application can still be used without them
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(err) => {
+                warn!(%err, "gamepads unavailable");
+                None
+            }
+        };
+
+        Self { gilrs }
+    }
+}
+
+impl Gamepads {
+    /// Returns the changes reported since the last poll
+    pub(super) fn poll(&mut self) -> Vec<GamepadChange> {
+        let mut changes = vec![];
+        let Some(gilrs) = self.gilrs.as_mut() else {
+            return changes;
+        };
+
+        while let Some(event) = gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    changes.push(GamepadChange::Button(button, true))
+                }
+                EventType::ButtonReleased(button, _) => {
+                    changes.push(GamepadChange::Button(button, false))
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    let value = if value.abs() < DEAD_ZONE { 0.0 } else { value };
+                    changes.push(GamepadChange::Axis(axis, value));
+                }
+                // The held inputs of the gamepad won't be released anymore
+                EventType::Disconnected => changes.push(GamepadChange::Reset),
+                _ => (),
+            }
+        }
+
+        changes
+    }
+}
+
+/// Value of an axis in `direction`, between 0 and 1
+pub(super) fn axis_value(axes: &HashMap<Axis, f32>, axis: Axis, direction: AxisDirection) -> f32 {
+    let value = axes.get(&axis).copied().unwrap_or(0.0);
+    match direction {
+        AxisDirection::Positive => value.max(0.0),
+        AxisDirection::Negative => (-value).max(0.0),
+    }
+}
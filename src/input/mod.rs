@@ -0,0 +1,428 @@
+// Without the windowing and gamepad features no binding can drive the state
+#![cfg_attr(
+    not(any(feature = "windowing", feature = "gamepad")),
+    allow(unused_mut, unreachable_patterns, dead_code)
+)]
+
+#[cfg(feature = "gamepad")]
+mod gamepad;
+
+#[cfg(feature = "gamepad")]
+pub use gamepad::AxisDirection;
+
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "gamepad")]
+use gamepad::{GamepadChange, Gamepads};
+#[cfg(feature = "gamepad")]
+use gilrs::{Axis, Button};
+
+use dpi::PhysicalPosition;
+#[cfg(feature = "windowing")]
+use winit::{
+    event::{DeviceEvent, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+// Pixels per scroll line for touchpads reporting pixel deltas
+#[cfg(feature = "windowing")]
+const PIXELS_PER_LINE: f64 = 40.0;
+// Deflection from which a gamepad axis is considered held
+#[cfg(feature = "gamepad")]
+const AXIS_PRESS_THRESHOLD: f32 = 0.5;
+
+/// Names of the actions bound by default
+pub mod actions {
+    pub const MOVE_FORWARD: &str = "move_forward";
+    pub const MOVE_BACKWARD: &str = "move_backward";
+    pub const MOVE_LEFT: &str = "move_left";
+    pub const MOVE_RIGHT: &str = "move_right";
+    pub const MOVE_UP: &str = "move_up";
+    pub const MOVE_DOWN: &str = "move_down";
+    /// Held to orbit around the target with the mouse
+    pub const ORBIT: &str = "orbit";
+    /// Held to look around with the mouse
+    pub const LOOK: &str = "look";
+    /// Look or orbit around at a constant speed, e.g. from a stick
+    pub const LOOK_LEFT: &str = "look_left";
+    pub const LOOK_RIGHT: &str = "look_right";
+    pub const LOOK_UP: &str = "look_up";
+    pub const LOOK_DOWN: &str = "look_down";
+    pub const ZOOM_IN: &str = "zoom_in";
+    pub const ZOOM_OUT: &str = "zoom_out";
+    pub const PAUSE: &str = "pause";
+    /// Reports the object under the cursor
+    pub const PICK: &str = "pick";
+    pub const SCREENSHOT: &str = "screenshot";
+    /// Copies the last frame to the clipboard
+    pub const COPY_SCREENSHOT: &str = "copy_screenshot";
+    /// Held to report the values of the pixel under the cursor
+    pub const INSPECT_PIXEL: &str = "inspect_pixel";
+    pub const SWITCH_CAMERA: &str = "switch_camera";
+    pub const SWITCH_SWAPCHAIN_SHARING: &str = "switch_swapchain_sharing";
+    /// Enables or disables the ambient occlusion
+    pub const TOGGLE_SSAO: &str = "toggle_ssao";
+    pub const SWITCH_TONEMAP_OPERATOR: &str = "switch_tonemap_operator";
+    /// Enables or disables the anti-aliasing
+    pub const TOGGLE_ANTI_ALIASING: &str = "toggle_anti_aliasing";
+    /// Enables or disables the temporal anti-aliasing
+    pub const TOGGLE_TAA: &str = "toggle_taa";
+    /// Shows or hides the debug HUD
+    pub const TOGGLE_HUD: &str = "toggle_hud";
+    /// Switches between the wireframe and the shaded scene
+    pub const TOGGLE_WIREFRAME: &str = "toggle_wireframe";
+    /// Outlines the textured quad, or stops outlining it
+    pub const TOGGLE_OUTLINE: &str = "toggle_outline";
+}
+
+/// A physical input an action can be bound to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    #[cfg(feature = "windowing")]
+    Key(KeyCode),
+    #[cfg(feature = "windowing")]
+    Mouse(MouseButton),
+    /// A button of any connected gamepad
+    #[cfg(feature = "gamepad")]
+    GamepadButton(Button),
+    /// One direction of an axis of any connected gamepad, the action value following the
+    /// deflection
+    #[cfg(feature = "gamepad")]
+    GamepadAxis(Axis, AxisDirection),
+}
+
+/// Input state aggregated from the winit events (with the `windowing` feature) and the gamepads
+/// over a frame, with a mapping of named actions to bindings.
+///
+/// The per-frame values (presses, releases, mouse delta and wheel) are reset by `end_frame`,
+/// which also polls the gamepads for the next frame.
+#[derive(Debug)]
+pub struct InputState {
+    down: HashSet<Binding>,
+    pressed: HashSet<Binding>,
+    released: HashSet<Binding>,
+    mouse_delta: (f64, f64),
+    wheel: f32,
+    cursor: Option<PhysicalPosition<f64>>,
+    #[cfg(feature = "windowing")]
+    focused: bool,
+    #[cfg(feature = "gamepad")]
+    gamepads: Gamepads,
+    #[cfg(feature = "gamepad")]
+    axes: HashMap<Axis, f32>,
+
+    bindings: HashMap<String, Vec<Binding>>,
+}
+
+impl Default for InputState {
+    fn default() -> Self {
+        let mut input = Self {
+            down: HashSet::new(),
+            pressed: HashSet::new(),
+            released: HashSet::new(),
+            mouse_delta: (0.0, 0.0),
+            wheel: 0.0,
+            cursor: None,
+            #[cfg(feature = "windowing")]
+            focused: true,
+            #[cfg(feature = "gamepad")]
+            gamepads: Gamepads::default(),
+            #[cfg(feature = "gamepad")]
+            axes: HashMap::new(),
+            bindings: HashMap::new(),
+        };
+
+        #[cfg(feature = "windowing")]
+        {
+            input.bind(actions::MOVE_FORWARD, Binding::Key(KeyCode::KeyW));
+            input.bind(actions::MOVE_BACKWARD, Binding::Key(KeyCode::KeyS));
+            input.bind(actions::MOVE_LEFT, Binding::Key(KeyCode::KeyA));
+            input.bind(actions::MOVE_RIGHT, Binding::Key(KeyCode::KeyD));
+            input.bind(actions::MOVE_UP, Binding::Key(KeyCode::KeyE));
+            input.bind(actions::MOVE_DOWN, Binding::Key(KeyCode::KeyQ));
+            input.bind(actions::ORBIT, Binding::Mouse(MouseButton::Left));
+            input.bind(actions::LOOK, Binding::Mouse(MouseButton::Right));
+            input.bind(actions::PICK, Binding::Mouse(MouseButton::Middle));
+            input.bind(actions::SCREENSHOT, Binding::Key(KeyCode::F12));
+            input.bind(actions::COPY_SCREENSHOT, Binding::Key(KeyCode::F11));
+            input.bind(actions::INSPECT_PIXEL, Binding::Key(KeyCode::KeyI));
+            input.bind(actions::SWITCH_CAMERA, Binding::Key(KeyCode::KeyC));
+            input.bind(actions::LOOK_LEFT, Binding::Key(KeyCode::ArrowLeft));
+            input.bind(actions::LOOK_RIGHT, Binding::Key(KeyCode::ArrowRight));
+            input.bind(actions::LOOK_UP, Binding::Key(KeyCode::ArrowUp));
+            input.bind(actions::LOOK_DOWN, Binding::Key(KeyCode::ArrowDown));
+            input.bind(actions::SWITCH_SWAPCHAIN_SHARING, Binding::Key(KeyCode::F5));
+            input.bind(actions::PAUSE, Binding::Key(KeyCode::KeyP));
+            input.bind(actions::TOGGLE_SSAO, Binding::Key(KeyCode::KeyO));
+            input.bind(
+                actions::SWITCH_TONEMAP_OPERATOR,
+                Binding::Key(KeyCode::KeyT),
+            );
+            input.bind(actions::TOGGLE_ANTI_ALIASING, Binding::Key(KeyCode::KeyX));
+            input.bind(actions::TOGGLE_TAA, Binding::Key(KeyCode::KeyG));
+            input.bind(actions::TOGGLE_HUD, Binding::Key(KeyCode::F3));
+            input.bind(actions::TOGGLE_WIREFRAME, Binding::Key(KeyCode::KeyF));
+            input.bind(actions::TOGGLE_OUTLINE, Binding::Key(KeyCode::KeyL));
+        }
+
+        #[cfg(feature = "gamepad")]
+        {
+            use AxisDirection::{Negative, Positive};
+
+            input.bind(
+                actions::MOVE_FORWARD,
+                Binding::GamepadAxis(Axis::LeftStickY, Positive),
+            );
+            input.bind(
+                actions::MOVE_BACKWARD,
+                Binding::GamepadAxis(Axis::LeftStickY, Negative),
+            );
+            input.bind(
+                actions::MOVE_LEFT,
+                Binding::GamepadAxis(Axis::LeftStickX, Negative),
+            );
+            input.bind(
+                actions::MOVE_RIGHT,
+                Binding::GamepadAxis(Axis::LeftStickX, Positive),
+            );
+            input.bind(
+                actions::MOVE_UP,
+                Binding::GamepadButton(Button::RightTrigger2),
+            );
+            input.bind(
+                actions::MOVE_DOWN,
+                Binding::GamepadButton(Button::LeftTrigger2),
+            );
+            input.bind(
+                actions::LOOK_LEFT,
+                Binding::GamepadAxis(Axis::RightStickX, Negative),
+            );
+            input.bind(
+                actions::LOOK_RIGHT,
+                Binding::GamepadAxis(Axis::RightStickX, Positive),
+            );
+            input.bind(
+                actions::LOOK_UP,
+                Binding::GamepadAxis(Axis::RightStickY, Positive),
+            );
+            input.bind(
+                actions::LOOK_DOWN,
+                Binding::GamepadAxis(Axis::RightStickY, Negative),
+            );
+            input.bind(
+                actions::ZOOM_IN,
+                Binding::GamepadButton(Button::RightTrigger2),
+            );
+            input.bind(
+                actions::ZOOM_OUT,
+                Binding::GamepadButton(Button::LeftTrigger2),
+            );
+            input.bind(actions::SCREENSHOT, Binding::GamepadButton(Button::Select));
+            input.bind(
+                actions::SWITCH_CAMERA,
+                Binding::GamepadButton(Button::North),
+            );
+            input.bind(actions::PAUSE, Binding::GamepadButton(Button::Start));
+        }
+
+        input
+    }
+}
+
+impl InputState {
+    /// Adds a binding to `action`, an action can have several bindings
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.bindings.entry(action.to_owned()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    /// Removes every binding of `action`
+    pub fn unbind(&mut self, action: &str) {
+        self.bindings.remove(action);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.bindings.get(action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether a binding of `action` is held
+    pub fn action_down(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|b| self.down.contains(b))
+    }
+
+    /// Whether a binding of `action` has been pressed during the frame
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.bindings(action)
+            .iter()
+            .any(|b| self.pressed.contains(b))
+    }
+
+    /// Whether a binding of `action` has been released during the frame
+    pub fn action_released(&self, action: &str) -> bool {
+        self.bindings(action)
+            .iter()
+            .any(|b| self.released.contains(b))
+    }
+
+    /// Value of `action` between 0 and 1, following the deflection of the gamepad axes and 1 for
+    /// the held digital bindings
+    pub fn action_value(&self, action: &str) -> f32 {
+        self.bindings(action)
+            .iter()
+            .map(|&binding| self.value(binding))
+            .fold(0.0, f32::max)
+    }
+
+    /// Returns the value of `positive` minus the value of `negative`, between -1 and 1
+    pub fn axis(&self, negative: &str, positive: &str) -> f32 {
+        self.action_value(positive) - self.action_value(negative)
+    }
+
+    pub fn is_down(&self, binding: Binding) -> bool {
+        self.down.contains(&binding)
+    }
+
+    /// Value of `binding` between 0 and 1
+    pub fn value(&self, binding: Binding) -> f32 {
+        match binding {
+            #[cfg(feature = "gamepad")]
+            Binding::GamepadAxis(axis, direction) => {
+                gamepad::axis_value(&self.axes, axis, direction)
+            }
+            _ => self.is_down(binding) as i32 as f32,
+        }
+    }
+
+    /// Mouse movement during the frame, in unaccelerated device units
+    pub fn mouse_delta(&self) -> (f64, f64) {
+        self.mouse_delta
+    }
+
+    /// Scrolled lines during the frame, positive when scrolling up
+    pub fn wheel(&self) -> f32 {
+        self.wheel
+    }
+
+    /// Position of the cursor in the window, `None` when it is outside
+    pub fn cursor(&self) -> Option<PhysicalPosition<f64>> {
+        self.cursor
+    }
+
+    #[cfg(feature = "windowing")]
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key),
+                        state,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => self.set_state(Binding::Key(*key), state.is_pressed()),
+
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.set_state(Binding::Mouse(*button), state.is_pressed())
+            }
+
+            WindowEvent::CursorMoved { position, .. } => self.cursor = Some(*position),
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.wheel += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => (position.y / PIXELS_PER_LINE) as f32,
+                };
+            }
+
+            // The keyboard and mouse releases won't be reported while the window isn't focused
+            WindowEvent::Focused(focused) => {
+                self.focused = *focused;
+                if !focused {
+                    let (released, kept) = self.down.drain().partition(|binding| {
+                        matches!(binding, Binding::Key(_) | Binding::Mouse(_))
+                    });
+                    self.released.extend::<HashSet<Binding>>(released);
+                    self.down = kept;
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    #[cfg(feature = "windowing")]
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+            if self.focused {
+                self.mouse_delta.0 += dx;
+                self.mouse_delta.1 += dy;
+            }
+        }
+    }
+
+    /// Resets the per-frame state, called once the frame has been processed
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.mouse_delta = (0.0, 0.0);
+        self.wheel = 0.0;
+
+        #[cfg(feature = "gamepad")]
+        self.poll_gamepads();
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn poll_gamepads(&mut self) {
+        use AxisDirection::{Negative, Positive};
+
+        for change in self.gamepads.poll() {
+            match change {
+                GamepadChange::Button(button, pressed) => {
+                    self.set_state(Binding::GamepadButton(button), pressed);
+                }
+
+                // Each direction of the axis is held past the threshold
+                GamepadChange::Axis(axis, value) => {
+                    self.axes.insert(axis, value);
+                    for direction in [Positive, Negative] {
+                        let pressed = gamepad::axis_value(&self.axes, axis, direction)
+                            >= AXIS_PRESS_THRESHOLD;
+                        self.set_state(Binding::GamepadAxis(axis, direction), pressed);
+                    }
+                }
+
+                GamepadChange::Reset => {
+                    self.axes.clear();
+                    let gamepad_bindings: Vec<Binding> = self
+                        .down
+                        .iter()
+                        .copied()
+                        .filter(|binding| {
+                            matches!(
+                                binding,
+                                Binding::GamepadButton(_) | Binding::GamepadAxis(..)
+                            )
+                        })
+                        .collect();
+                    for binding in gamepad_bindings {
+                        self.set_state(binding, false);
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_state(&mut self, binding: Binding, pressed: bool) {
+        if pressed {
+            if self.down.insert(binding) {
+                self.pressed.insert(binding);
+            }
+        } else if self.down.remove(&binding) {
+            self.released.insert(binding);
+        }
+    }
+}
@@ -1,28 +1,228 @@
+mod accel;
+mod animation;
 mod app_error;
+mod builder;
+mod camera;
+mod clustered_lighting;
+mod context;
+mod creation_guard;
+mod debug_draw;
+mod debug_hud;
+mod descriptor_allocator;
+mod descriptor_writes;
+mod device_features;
+mod diagnostics;
+mod draw_list;
+mod equirect;
+mod event_log;
+mod fixed_timestep;
+mod frame_arena;
+mod frame_guard;
+mod frame_limiter;
+mod frame_timing;
+mod garbage_collector;
 #[allow(dead_code)]
 mod geometry;
+#[cfg(feature = "gltf")]
+mod gltf_import;
+mod gpu_particles;
+mod gpu_resources;
+mod handle_registry;
+mod hooks;
+mod hot_reload;
+mod image_based_lighting;
+#[cfg(feature = "imgui")]
+mod imgui_renderer;
+mod input;
+mod lightmap;
+mod mesh_cache;
+mod meshlets;
+mod morph_targets;
+mod outline;
+mod overlay;
+mod particles;
+mod pbr;
+mod per_frame;
+mod picking;
+mod pipeline;
+mod pipeline_manager;
+mod pixel_inspector;
+mod point_shadows;
+mod portability;
+mod post;
+/// Types most applications need, to import at once with `use vulkan_tutorial::prelude::*`.
+///
+/// Everything here is also available from the crate root, along with the constants and the lower
+/// level types (GPU resources, render hooks, post effects) left out of the prelude.
+pub mod prelude;
+mod present_transfer;
 mod queue_families;
-
-use app_error::{AppError, AppErrorType};
+mod ray_tracing;
+mod reflection_probes;
+mod render_graph;
+mod renderer;
+mod resources;
+mod sampler_cache;
+mod screenshot;
+#[cfg(feature = "scripting")]
+mod scripting;
+mod shader_loader;
+mod shader_material;
+mod skybox;
+mod software_rendering;
+mod submit;
+mod surface_size;
+mod swapchain;
+mod sync_pool;
+mod terrain;
+mod text;
+mod texture_array;
+mod texture_loader;
+mod texture_manager;
+mod texture_stream;
+mod virtual_texture;
+
+use animation::Animations;
+use context::Context;
+use creation_guard::DeviceObjectGuard;
+use debug_draw::DebugDraw;
+use debug_hud::DebugHud;
+use descriptor_allocator::DescriptorAllocator;
+use descriptor_writes::{DescriptorWriteBatch, SceneSetWriter};
+use draw_list::DrawList;
+use fixed_timestep::FixedTimestep;
+use frame_arena::FrameArena;
+use frame_guard::FrameGuard;
+use frame_limiter::FrameLimiter;
+use frame_timing::FrameClock;
+use garbage_collector::Garbage;
 use geometry::*;
-use queue_families::QueueFamilyIndice;
+use gpu_particles::GpuParticles;
+use hooks::RenderHooks;
+use hot_reload::{AssetWatcher, WatchedAsset};
+use image_based_lighting::ImageBasedLighting;
+#[cfg(feature = "imgui")]
+use imgui_renderer::ImguiRenderer;
+use meshlets::MeshShading;
+use morph_targets::MorphTargets;
+use outline::Outlines;
+use particles::ParticleSystem;
+use pbr::PbrPipeline;
+use pipeline_manager::PipelineManager;
+use pixel_inspector::PixelInspector;
+use post::{Fxaa, PostChain, Ssao, SsaoBlur, Taa, FXAA_NAME, SSAO_BLUR_NAME, SSAO_NAME, TAA_NAME};
+use present_transfer::PresentTransfer;
+use ray_tracing::{RayTracing, SceneAccel};
+use reflection_probes::ReflectionProbes;
+use resources::Resources;
+use screenshot::RawScreenshot;
+#[cfg(feature = "scripting")]
+use scripting::{ScriptCommand, ScriptHost};
+use shader_material::ShaderMaterials;
+use skybox::Skybox;
+use submit::SubmitScheduler;
+use swapchain::Swapchain;
+use terrain::Terrain;
+use text::{SdfFont, TextRenderer};
+use texture_array::TextureArrayPipeline;
+use texture_loader::DecodedTexture;
+use texture_manager::TextureManager;
+use virtual_texture::VirtualTexturing;
+
+pub use animation::{
+    Animation, AnimationClip, AnimationId, Keyframe, NodeTransform, Playback, WeightKeyframe,
+};
+pub use app_error::{AppError, AppErrorType, ErrorContext};
+#[cfg(feature = "vlayers")]
+pub use builder::DEFAULT_VALIDATION_SEVERITY;
+pub use builder::{ApplicationBuilder, DEFAULT_FRAMES_IN_FLIGHT};
+pub use camera::{CameraController, FlyCamera, OrbitCamera};
+pub use clustered_lighting::{CLUSTER_GRID, MAX_LIGHTS_PER_CLUSTER};
+pub use debug_hud::{FrameStats, HUD_REFRESH_INTERVAL};
+pub use descriptor_allocator::DescriptorPoolStats;
+pub use device_features::DeviceFeatures;
+pub use diagnostics::{DeviceReport, Diagnostics, QueueFamilyReport};
+pub use draw_list::{DrawItem, DrawItemId, MaterialHandle, MeshHandle};
+pub use event_log::{EventLog, LoggedEvent, RendererEvent};
+pub use fixed_timestep::{FixedUpdateContext, FixedUpdateFn, DEFAULT_TICK_RATE};
+pub use frame_timing::FrameTiming;
+pub use garbage_collector::DEFAULT_GC_BUDGET;
+pub use geometry::{compute_tangents, Mat4, Point2, Point3, Quat, Vec2, Vec3, Vec4, Vertex};
+pub use gpu_particles::GpuParticleEmitterId;
+pub use gpu_resources::{GpuBuffer, GpuImage, GpuSampler};
+pub use hooks::{RenderHook, RenderHookContext, RenderHookFn, RenderHookId};
+pub use hot_reload::HOT_RELOAD_INTERVAL;
+pub use image_based_lighting::{
+    IblViews, BRDF_LUT_RESOLUTION, IRRADIANCE_RESOLUTION, SPECULAR_MIP_LEVELS, SPECULAR_RESOLUTION,
+};
+#[cfg(feature = "gamepad")]
+pub use input::AxisDirection;
+pub use input::{actions, Binding, InputState};
+pub use lightmap::{LightmapSettings, PointLight, LIGHTMAP_CACHE_EXTENSION, MAX_BAKED_LIGHTS};
+pub use mesh_cache::{load_mesh_cached, MeshData, MESH_CACHE_EXTENSION};
+pub use meshlets::{MAX_MESHLET_TRIANGLES, MAX_MESHLET_VERTICES};
+pub use morph_targets::MorphTarget;
+pub use outline::OUTLINE_SCALE;
+pub use particles::{ParticleEmitter, ParticleEmitterId, MAX_PARTICLES};
+pub use pbr::{
+    AlphaMode, LightAttenuation, PbrLight, PbrLighting, PbrMaterial, MAX_DIRECTIONAL_PBR_LIGHTS,
+    MAX_PBR_LIGHTS, MAX_PBR_MATERIALS,
+};
+pub use per_frame::PerFrame;
+pub use picking::{Aabb, PickHit, PickId, Pickables, Ray};
+pub use pixel_inspector::{PixelSample, PIXEL_INSPECTIONS_PER_SECOND};
+pub use point_shadows::{ShadowCaster, MAX_SHADOWED_POINT_LIGHTS, POINT_SHADOW_RESOLUTION};
+pub use portability::PortabilitySubset;
+pub use post::{
+    create_fullscreen_pipeline, PostEffect, PostInput, PostPass, PostSetup, TonemapOperator,
+    Tonemapping, GEOMETRY_FORMAT, POST_COLOR_FORMAT,
+};
+pub use present_transfer::SwapchainSharing;
+pub use ray_tracing::{RayTracingMode, MAX_RAY_TRACED_INSTANCES};
+pub use reflection_probes::{
+    ReflectionProbeId, MAX_REFLECTION_PROBES, PROBE_MIP_LEVELS, PROBE_RESOLUTION,
+};
+pub use sampler_cache::{TextureFiltering, MAX_ANISOTROPY};
+#[cfg(feature = "clipboard")]
+pub use screenshot::ClipboardCopy;
+pub use shader_loader::{load_shader, GLSLC_ENV, SHADER_DIR_ENV};
+pub use shader_material::ShaderMaterialDesc;
+pub use skybox::CUBEMAP_FACES;
+pub use software_rendering::{SoftwareRendering, SOFTWARE_RENDERING_ENV};
+pub use submit::WorkType;
+pub use surface_size::SurfaceSize;
+pub use sync_pool::SyncPool;
+pub use terrain::{TerrainSettings, TERRAIN_LAYERS};
+pub use text::SDF_BAKE_SIZE;
+pub use texture_loader::{TextureUploadPath, DDS_EXTENSION, HDR_EXTENSION, KTX2_EXTENSION};
+pub use texture_manager::TextureHandle;
+pub use texture_stream::DEFAULT_STREAM_BUDGET;
+pub use virtual_texture::{PageSource, VIRTUAL_TEXTURE_FORMAT, VIRTUAL_TEXTURE_POOL_PAGES};
 
 use std::{
-    collections::HashSet,
-    ffi::{c_void, CStr, CString},
+    ffi::{c_void, CStr},
     path::Path,
+    rc::Rc,
+    sync::Arc,
     time::Instant,
 };
 
 #[cfg(feature = "vlayers")]
 use ash::ext::debug_utils;
 use ash::{
-    khr::{self, surface, swapchain},
+    ext::headless_surface,
+    khr::{self, surface},
+    prelude::VkResult,
     vk, Device, Entry, Instance,
 };
-use colored::Colorize;
-use image::io::Reader;
+use cgmath::SquareMatrix;
+use dpi::{PhysicalPosition, PhysicalSize};
+#[cfg(feature = "imgui")]
+use image::RgbaImage;
+#[cfg(feature = "windowing")]
 use raw_window_handle::{DisplayHandle, HasDisplayHandle, HasWindowHandle, WindowHandle};
+use tracing::error;
+#[cfg(feature = "windowing")]
 use winit::{event_loop::ActiveEventLoop, window::Window};
 
 // Mesh
@@ -50,7 +250,28 @@ const VERTICES: [Vertex; 4] = [
 ];
 const INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
 
-const MAX_FRAMES_IN_FLIGHT: usize = 2;
+// Each item of the draw list uses a slot of the uniform buffers and a descriptor set, the slots
+// being doubled whenever the draw list outgrows them
+const INITIAL_DRAW_ITEMS: usize = 64;
+// Order of the ambient occlusion effects in the post chain, ahead of the other effects as they
+// darken the scene as rendered
+const SSAO_ORDER: i32 = i32::MIN;
+// Order of the anti-aliasing effect in the post chain, after the other effects so it smooths
+// the edges they may sharpen or add
+const FXAA_ORDER: i32 = i32::MAX;
+// Order of the temporal anti-aliasing in the post chain, right after the ambient occlusion so it
+// resolves the scene before the other effects
+const TAA_ORDER: i32 = i32::MIN + 1;
+// Frames after which the sub-pixel jitter of the projection repeats
+const TAA_JITTER_PERIOD: u32 = 8;
+// Staging memory used by a single texture upload submission
+const TEXTURE_UPLOAD_BUDGET: u64 = 64 * 1024 * 1024;
+// Size of the checkerboard sampled in place of the missing textures, and of its squares
+const PLACEHOLDER_SIZE: u32 = 64;
+const PLACEHOLDER_CHECKER: u32 = 8;
+
+// Highest Vulkan version the application knows how to use
+const MAX_API_VERSION: u32 = vk::API_VERSION_1_3;
 
 const DEVICE_EXTENSIONS: &[&CStr] = &[khr::swapchain::NAME];
 #[cfg(feature = "vlayers")]
@@ -58,6 +279,13 @@ const EXTENSIONS: &[&CStr] = &[debug_utils::NAME];
 #[cfg(not(feature = "vlayers"))]
 const EXTENSIONS: &[&CStr] = &[];
 
+// Instance extensions enabled only when avaible, needed to run on portability implementations
+// like MoltenVK
+const OPTIONAL_EXTENSIONS: &[&CStr] = &[
+    khr::portability_enumeration::NAME,
+    khr::get_physical_device_properties2::NAME,
+];
+
 #[cfg(feature = "vlayers")]
 const VALIDATION_LAYERS: &[&CStr] = unsafe {
     &[CStr::from_bytes_with_nul_unchecked(
@@ -65,21 +293,8 @@ const VALIDATION_LAYERS: &[&CStr] = unsafe {
     )]
 };
 
-#[cfg(feature = "vlayers")]
-const LAYER_SEVERITY: vk::DebugUtilsMessageSeverityFlagsEXT =
-    vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE;
-
 pub type AppResult<T> = Result<T, AppError>;
 
-struct SwapChainHolder {
-    swapchain_ext: swapchain::Device,
-    swapchain: vk::SwapchainKHR,
-    swapchain_images: Vec<vk::Image>,
-    swapchain_image_views: Vec<vk::ImageView>,
-    image_format: vk::Format,
-    extent: vk::Extent2D,
-}
-
 struct SwapChainDetails {
     pub capabilities: vk::SurfaceCapabilitiesKHR,
     pub formats: Vec<vk::SurfaceFormatKHR>,
@@ -93,11 +308,64 @@ struct SurfaceHodlder {
 
 struct GraphicsPipelineHolder {
     renderpass: vk::RenderPass,
+    // Samples per pixel of the render pass, which every pipeline drawing in it is created with
+    samples: vk::SampleCountFlags,
+    // Owns the pipelines below and the ones of the shader materials
+    manager: PipelineManager,
     pipeline: vk::Pipeline,
+    // Alpha blends the transparent materials
+    transparent_pipeline: vk::Pipeline,
+    // `None` when the device doesn't support the `fill_mode_non_solid` feature
+    wireframe_pipeline: Option<vk::Pipeline>,
     pipeline_layout: vk::PipelineLayout,
     descriptor_set_layout: vk::DescriptorSetLayout,
 }
 
+/// Attachments of the render pass a mesh pipeline draws into
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum MeshAttachments {
+    /// A color attachment without depth, as the scene render passes
+    Color,
+    /// A color attachment without depth the fragments are alpha blended into, for the
+    /// transparent items of the scene render passes
+    BlendedColor,
+    /// A depth attachment alone
+    Depth,
+    /// A color attachment tested against a depth attachment
+    ColorDepth,
+    /// The stencil of a color and stencil attachment pair, the color being left untouched
+    Stencil(StencilState),
+    /// A color attachment whose fragments are kept by the stencil test of a stencil attachment
+    ColorStencil(StencilState),
+}
+
+/// Stencil test of a mesh pipeline, the same for both faces. The depth isn't tested.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct StencilState {
+    /// Compares `reference` to the stencil value of the fragments
+    compare_op: vk::CompareOp,
+    /// Updates the stencil value of the fragments passing the test
+    pass_op: vk::StencilOp,
+    reference: u32,
+}
+
+/// Passes of a frame, ordered by the render graph from the resources they use
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FramePass {
+    LightCulling,
+    Shadows,
+    ProbeCaptures,
+    ParticleSimulation,
+    AccelerationStructures,
+    RayTracing,
+    Prepasses,
+    Scene,
+    Terrain,
+    Outlines,
+    PostEffects,
+    Composite,
+}
+
 struct BufferHolder {
     buffer: vk::Buffer,
     memory: vk::DeviceMemory,
@@ -125,6 +393,130 @@ impl MemoryMappedBuffer {
     }
 }
 
+// Resources of a frame in flight, see `PerFrame`
+struct FrameResources {
+    command_buffer: vk::CommandBuffer,
+    image_available: vk::Semaphore,
+    render_done: vk::Semaphore,
+    in_flight: vk::Fence,
+    uniform_buffer: MemoryMappedBuffer,
+    // One descriptor set per material, shared by the draw items of the material which bind it with
+    // the dynamic offset of their slot of the uniform buffer. Along with the material each set
+    // was written with, `None` if it must be written again.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    descriptor_materials: Vec<Option<MaterialHandle>>,
+}
+
+struct MeshHolder {
+    vertex_buffer: BufferHolder,
+    index_buffer: BufferHolder,
+    index_count: u32,
+    index_type: vk::IndexType,
+}
+
+// Host visible buffers rewritten by the CPU, one pair per frame in flight so the buffers of a frame
+// the GPU is still drawing are never written
+struct DynamicMeshHolder {
+    vertex_buffers: Vec<MemoryMappedBuffer>,
+    index_buffers: Vec<MemoryMappedBuffer>,
+    index_counts: Vec<u32>,
+    // Frames whose buffers don't hold the latest data yet
+    stale_frames: Vec<bool>,
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    vertex_capacity: usize,
+    index_capacity: usize,
+}
+
+enum MeshStorage {
+    Static(MeshHolder),
+    Dynamic(DynamicMeshHolder),
+}
+
+impl MeshStorage {
+    /// Returns the vertex and index buffers to draw `frame` with, and the index count
+    fn frame_buffers(&self, frame: usize) -> (vk::Buffer, vk::Buffer, u32, vk::IndexType) {
+        match self {
+            Self::Static(mesh) => (
+                mesh.vertex_buffer.buffer,
+                mesh.index_buffer.buffer,
+                mesh.index_count,
+                mesh.index_type,
+            ),
+            Self::Dynamic(mesh) => (
+                mesh.vertex_buffers[frame].buffer,
+                mesh.index_buffers[frame].buffer,
+                mesh.index_counts[frame],
+                vk::IndexType::UINT32,
+            ),
+        }
+    }
+}
+
+struct TextureHolder {
+    image: ImageHolder,
+    view: vk::ImageView,
+    // Layer count of a 2D array texture or a cubemap, `None` for a 2D texture
+    array_layers: Option<u32>,
+    upload_path: TextureUploadPath,
+}
+
+struct MaterialHolder {
+    texture_view: vk::ImageView,
+    sampler: vk::Sampler,
+    // Multiplies the texture, a white texel when the material isn't baked
+    lightmap_view: vk::ImageView,
+    kind: MaterialKind,
+}
+
+/// Pipeline a material is drawn with, every pipeline binding the scene set as set 0
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MaterialKind {
+    /// Samples `texture_view` with the scene pipeline
+    Scene,
+    /// Samples the virtual texture of this index instead of `texture_view`
+    VirtualTexture(usize),
+    /// Samples this layer of the 2D array `texture_view`
+    ArrayLayer(u32),
+    /// Shades the PBR material of `index`, whose albedo is `texture_view`. A blended material is
+    /// drawn along with the transparent items.
+    Pbr { index: usize, blended: bool },
+    /// Blends `texture_view` over the scene with its alpha, the items being drawn from back to
+    /// front after the opaque ones
+    Transparent,
+    /// Draws with the shaders, textures and parameters of the shader material of this index
+    Shader(usize),
+}
+
+impl MaterialKind {
+    /// Whether the items are blended over the scene, drawn from back to front after the opaque
+    /// ones
+    fn is_transparent(self) -> bool {
+        matches!(
+            self,
+            MaterialKind::Transparent | MaterialKind::Pbr { blended: true, .. }
+        )
+    }
+}
+
+impl MaterialHolder {
+    /// Infos of the texture and lightmap bindings, which follow each other
+    fn image_infos(&self) -> [vk::DescriptorImageInfo; 2] {
+        [
+            vk::DescriptorImageInfo {
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                image_view: self.texture_view,
+                sampler: self.sampler,
+            },
+            vk::DescriptorImageInfo {
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                image_view: self.lightmap_view,
+                sampler: self.sampler,
+            },
+        ]
+    }
+}
+
 struct ImageHolder {
     image: vk::Image,
     memory: vk::DeviceMemory,
@@ -143,1949 +535,3535 @@ struct DebugMessengerHolder {
 }
 
 pub struct Application {
-    _entry: Entry,
-
-    instance: Instance,
-    surface: SurfaceHodlder,
-    physical_device: vk::PhysicalDevice,
-    device: Device,
-    graphics_queue: vk::Queue,
-    present_queue: vk::Queue,
-    swapchain: SwapChainHolder,
+    // Owners of the instance, the device and the scene resources, destroyed by `cleanup` after
+    // the subsystems created from them
+    context: Context,
+    swapchain: Swapchain,
+    resources: Resources,
+
+    swapchain_sharing: SwapchainSharing,
     pipeline: GraphicsPipelineHolder,
-    swapchain_frame_buffers: Vec<vk::Framebuffer>,
-    command_pool: vk::CommandPool,
-    command_buffers: Vec<vk::CommandBuffer>,
+    post_chain: PostChain,
+    render_hooks: RenderHooks,
+    scheduler: SubmitScheduler,
+    event_log: EventLog,
+    frame_arena: FrameArena,
+    descriptor_writes: DescriptorWriteBatch,
+    scene_set_writer: SceneSetWriter,
     current_frame: usize,
-    vertex_buffer: BufferHolder,
-    index_buffer: BufferHolder,
-    uniform_buffers: Vec<MemoryMappedBuffer>,
-    texture_image: ImageHolder,
-    texture_image_view: vk::ImageView,
+    materials: Vec<MaterialHolder>,
+    asset_watcher: AssetWatcher,
+    // Created along with the first virtual texture
+    virtual_texturing: Option<VirtualTexturing>,
+    // Created along with the first reflection probe
+    reflection_probes: Option<ReflectionProbes>,
+    // Created along with the first outline
+    outlines: Option<Outlines>,
+    // Created along with the first GPU particle emitter
+    gpu_particles: Option<GpuParticles>,
+    // Created when the mesh shading is first enabled
+    mesh_shading: Option<MeshShading>,
+    // Created along with the first morph mesh
+    morph_targets: Option<MorphTargets>,
+    // Created when the ray tracing or the ray traced shadows are first enabled
+    scene_accel: Option<SceneAccel>,
+    // Created when the ray tracing is first enabled
+    ray_tracing: Option<RayTracing>,
+    ray_query_shadows: bool,
+    // Created along with the first texture array
+    texture_arrays: Option<TextureArrayPipeline>,
+    // Created along with the first PBR material
+    pbr_pipeline: Option<PbrPipeline>,
+    pbr_lighting: PbrLighting,
+    shader_materials: ShaderMaterials,
+    skybox: Option<Skybox>,
+    // Baked from the skybox along with it
+    image_based_lighting: Option<ImageBasedLighting>,
+    terrain: Option<Terrain>,
+    draw_list: DrawList,
+    // Draw item slots of the uniform buffers and descriptor sets
+    draw_capacity: usize,
+    uniform_stride: u64,
+    // Sampler of the materials, from the sampler cache
     texture_sampler: vk::Sampler,
-    descriptor_pool: vk::DescriptorPool,
-    descriptor_sets: Vec<vk::DescriptorSet>,
-
-    image_avaible_semaphores: Vec<vk::Semaphore>,
-    render_done_semaphores: Vec<vk::Semaphore>,
-    in_flight_fences: Vec<vk::Fence>,
-
-    start_time: Instant,
+    texture_filtering: TextureFiltering,
+    descriptor_allocator: DescriptorAllocator,
+
+    frames: PerFrame<FrameResources>,
+    frames_in_flight: usize,
+    // Preferred to mailbox when recreating the swapchain, see `ApplicationBuilder::present_mode`
+    present_mode: Option<vk::PresentModeKHR>,
+    frame_guard: FrameGuard,
+    frame_limiter: FrameLimiter,
+
+    input: InputState,
+    camera: Box<dyn CameraController>,
+    view_matrix: Mat4,
+    // Set by the caller in place of the camera and of the rotation of the scene
+    view_override: Option<Mat4>,
+    scene_transform: Option<Mat4>,
+    // Without the jitter of the temporal anti-aliasing
+    proj_matrix: Mat4,
+    // Unjittered transform of each draw item slot in the previous frame, for the velocity prepass
+    previous_model_view_projs: Vec<Mat4>,
+    // Index of the frame in the jitter sequence
+    jitter_index: u32,
+    // Slots of the transparent draw items, from the farthest to the closest to the camera
+    transparent_order: Vec<usize>,
+    pickables: Pickables,
+    mesh_pick_id: PickId,
+    pixel_inspector: PixelInspector,
+    debug_hud: DebugHud,
+    debug_draw: DebugDraw,
+    particles: ParticleSystem,
+    animations: Animations,
+    text_renderer: TextRenderer,
+    mesh_item_id: DrawItemId,
+    // Simulated time of the last two ticks, the rendered time being interpolated between them
+    animation_time: f32,
+    previous_animation_time: f32,
+    fixed_timestep: FixedTimestep,
+    fixed_update: Option<FixedUpdateFn>,
+    paused: bool,
+    time_scale: f32,
+    wireframe: bool,
+    frame_clock: FrameClock,
     resize_flag: bool,
-
-    #[cfg(feature = "vlayers")]
-    debug_messenger: DebugMessengerHolder,
+    surface_size: SurfaceSize,
+    presented_image: Option<u32>,
+
+    #[cfg(feature = "scripting")]
+    script_host: ScriptHost,
+    // Created by `Application::enable_imgui`
+    #[cfg(feature = "imgui")]
+    imgui_renderer: Option<ImguiRenderer>,
 }
 
 impl Application {
-    /// Creates the application and initialize the Vulkan working environment
+    /// Creates the application and initialize the Vulkan working environment, the use of a CPU
+    /// implementation being set by `VULKAN_TUTORIAL_SOFTWARE_RENDERING`
+    #[cfg(feature = "windowing")]
     pub fn create(event_loop: &ActiveEventLoop, window: &Window) -> AppResult<Self> {
-        let entry = unsafe {
-            Entry::load().or(AppResult::Err(AppError::new(
-                AppErrorType::VulkanLoadingError,
-            )))?
-        };
+        Self::builder().build(event_loop, window)
+    }
+
+    /// Creates the application, picking a CPU implementation of Vulkan as allowed by
+    /// `software_rendering`
+    #[cfg(feature = "windowing")]
+    pub fn create_with_software_rendering(
+        event_loop: &ActiveEventLoop,
+        window: &Window,
+        software_rendering: SoftwareRendering,
+    ) -> AppResult<Self> {
+        Self::builder()
+            .software_rendering(software_rendering)
+            .build(event_loop, window)
+    }
+
+    /// Options to create the application with, replacing the defaults of `create`
+    pub fn builder() -> ApplicationBuilder {
+        ApplicationBuilder::default()
+    }
+
+    #[cfg(feature = "windowing")]
+    pub(crate) fn create_windowed(
+        event_loop: &ActiveEventLoop,
+        window: &Window,
+        builder: &ApplicationBuilder,
+    ) -> AppResult<Self> {
+        let entry = Context::load_entry()?;
 
-        // Getting every requested extension names as an iterator of valid CStr
         let display_handle: DisplayHandle = event_loop
             .display_handle()
             .or_else(|r| AppResult::Err(r.into()))?;
-
+        let window_handle: WindowHandle = window
+            .window_handle()
+            .or_else(|r| AppResult::Err(r.into()))?;
         let winit_extension_names =
-            ash_window::enumerate_required_extensions(display_handle.as_raw())?;
-        let extension_names = EXTENSIONS.iter().copied().chain(
-            winit_extension_names
+            ash_window::enumerate_required_extensions(display_handle.as_raw())?
                 .iter()
-                .map(|&ext| unsafe { CStr::from_ptr(ext) }),
-        );
+                .map(|&ext| unsafe { CStr::from_ptr(ext) })
+                .collect::<Vec<_>>();
+
+        Self::create_with_surface(
+            entry,
+            &winit_extension_names,
+            |entry, instance| unsafe {
+                ash_window::create_surface(
+                    entry,
+                    instance,
+                    display_handle.as_raw(),
+                    window_handle.as_raw(),
+                    None,
+                )
+            },
+            SurfaceSize::new(window.inner_size(), window.scale_factor()),
+            builder,
+        )
+    }
+
+    /// Creates the application rendering to a headless surface of `size` physical pixels instead
+    /// of a window, e.g. for servers and test tools reading the frames back with
+    /// `save_screenshot`. The instance needs `VK_EXT_headless_surface`, which the CPU
+    /// implementations support among others.
+    pub fn create_headless(
+        size: PhysicalSize<u32>,
+        software_rendering: SoftwareRendering,
+    ) -> AppResult<Self> {
+        Self::builder()
+            .software_rendering(software_rendering)
+            .build_headless(size)
+    }
 
-        // Getting every requested validation layers names as an iterator of valid CStr
-        #[cfg(feature = "vlayers")]
-        let layer_names = VALIDATION_LAYERS.iter().copied();
+    pub(crate) fn create_with_headless_surface(
+        surface_size: SurfaceSize,
+        builder: &ApplicationBuilder,
+    ) -> AppResult<Self> {
+        let entry = Context::load_entry()?;
 
-        // Creating the VkInstance
-        #[cfg(feature = "vlayers")]
-        let instance = Self::create_instance(&entry, extension_names, layer_names)?;
-        #[cfg(not(feature = "vlayers"))]
-        let instance = Self::create_instance(&entry, extension_names)?;
+        let avaible_extensions = unsafe { entry.enumerate_instance_extension_properties(None)? };
+        let headless_supported = avaible_extensions.iter().any(|ext| {
+            ext.extension_name_as_c_str()
+                .is_ok_and(|name| name == headless_surface::NAME)
+        });
+        if !headless_supported {
+            return AppResult::Err(AppError::new(AppErrorType::HeadlessSurfaceUnsupported));
+        }
 
-        // Setting up the VkDebugUtilsMessengerEXT for the validation layers
-        #[cfg(feature = "vlayers")]
-        let debug_messenger = Self::setup_debug_messenger(&entry, &instance)?;
+        Self::create_with_surface(
+            entry,
+            &[surface::NAME, headless_surface::NAME],
+            |entry, instance| unsafe {
+                headless_surface::Instance::new(entry, instance)
+                    .create_headless_surface(&vk::HeadlessSurfaceCreateInfoEXT::default(), None)
+            },
+            surface_size,
+            builder,
+        )
+    }
 
-        let surface = Self::create_surface(&entry, &instance, event_loop, window)?;
+    /// Creates the application presenting to the surface returned by `create_surface`, with
+    /// `surface_extensions` enabled on the instance
+    #[tracing::instrument(name = "initialization", skip_all)]
+    fn create_with_surface(
+        entry: Entry,
+        surface_extensions: &[&CStr],
+        create_surface: impl FnOnce(&Entry, &Instance) -> VkResult<vk::SurfaceKHR>,
+        surface_size: SurfaceSize,
+        builder: &ApplicationBuilder,
+    ) -> AppResult<Self> {
+        let frames_in_flight = builder.frames_in_flight;
+        let event_log = EventLog::default();
+        let context = Context::new(
+            entry,
+            surface_extensions,
+            create_surface,
+            builder,
+            &event_log,
+        )?;
+        let (instance, device) = (&context.instance, &context.device);
+        let physical_device = context.physical_device;
+        let queue_family_indices = context.queue_family_indices;
+        let graphics_queue = context.graphics_queue;
+
+        // The owners are guarded too, the guards destroy them before the context
+        let swapchain = Swapchain::new(
+            &context,
+            queue_family_indices,
+            surface_size,
+            SwapchainSharing::default(),
+            builder.present_mode,
+        )
+        .context("creating the swapchain")?;
+        let mut swapchain = DeviceObjectGuard::new(device, swapchain, Swapchain::destroy);
+        Self::log_swapchain_created(&event_log, &swapchain);
+        swapchain.present_transfer = PresentTransfer::new(
+            device,
+            queue_family_indices,
+            SwapchainSharing::default(),
+            &swapchain.swapchain_images,
+            frames_in_flight as u32,
+        )
+        .context("creating the present transfer")?;
 
-        // Choosing the VkPhisicalDevice, create the VkDevice and the graphics queue
-        let (physical_device, queue_family_indices) =
-            Self::pick_physical_device(&instance, &surface)?;
-        let (device, graphics_queue, present_queue) =
-            Self::create_logical_device(&instance, physical_device, queue_family_indices)?;
+        let pipeline = Self::create_graphics_pipeline(
+            device,
+            context.device_features.fill_mode_non_solid,
+            context.supported_samples(builder.msaa_samples),
+        )
+        .context("creating the scene pipeline")?;
+        let pipeline = DeviceObjectGuard::new(device, pipeline, GraphicsPipelineHolder::destroy);
+        event_log.push(RendererEvent::PipelineBuilt {
+            name: String::from("scene"),
+        });
 
-        let swapchain = Self::create_swapchain(
-            &instance,
-            &device,
+        let post_chain = PostChain::new(
+            instance,
+            device,
             physical_device,
-            &surface,
-            queue_family_indices,
-        )?;
+            pipeline.renderpass,
+            pipeline.samples,
+            pipeline.descriptor_set_layout,
+            swapchain.image_format,
+            swapchain.extent,
+        )
+        .context("creating the post-processing chain")?;
+        let post_chain = DeviceObjectGuard::new(device, post_chain, PostChain::destroy);
 
-        let pipeline = Self::create_graphics_pipeline(&device, &swapchain)?;
+        event_log.push(RendererEvent::PipelineBuilt {
+            name: String::from("composite"),
+        });
 
-        let swapchain_frame_buffers = Self::create_frame_buffers(&device, &pipeline, &swapchain)?;
+        swapchain
+            .create_frame_buffers(device, post_chain.composite_render_pass())
+            .context("creating the swapchain framebuffers")?;
 
-        let command_pool = Self::create_command_pool(&device, queue_family_indices)?;
+        let resources = Resources::new(&context, frames_in_flight)?;
+        let mut resources = DeviceObjectGuard::new(device, resources, Resources::destroy);
 
         let command_buffers =
-            Self::create_command_buffers(&device, command_pool, MAX_FRAMES_IN_FLIGHT as u32)?;
-
-        let texture_image = Self::create_texture_image(
-            &instance,
-            &device,
-            graphics_queue,
+            Self::create_command_buffers(device, resources.command_pool, frames_in_flight as u32)
+                .context("creating the command buffers")?;
+
+        let texture_sampler = resources
+            .sampler_cache
+            .get(device, TextureFiltering::default())
+            .context("creating the texture sampler")?;
+        let debug_hud = DebugHud::new(
+            instance,
+            device,
             physical_device,
-            command_pool,
-            "src/texture.jpg",
-        )?;
-
-        let texture_image_view = Self::create_texture_image_view(&device, texture_image.image)?;
-        let texture_sampler = Self::create_texture_sampler(&instance, &device, physical_device)?;
+            graphics_queue,
+            queue_family_indices.graphics_family.unwrap(),
+            resources.command_pool,
+            &resources.sync_pool,
+            post_chain.composite_render_pass(),
+            frames_in_flight,
+        )
+        .context("creating the debug HUD")?;
+        let debug_hud = DeviceObjectGuard::new(device, debug_hud, DebugHud::destroy);
+        let debug_draw =
+            DebugDraw::new(device, post_chain.composite_render_pass(), frames_in_flight)
+                .context("creating the debug draw")?;
+        let debug_draw = DeviceObjectGuard::new(device, debug_draw, DebugDraw::destroy);
+        let particles = ParticleSystem::new(
+            device,
+            pipeline.renderpass,
+            pipeline.samples,
+            frames_in_flight,
+        )
+        .context("creating the particle system")?;
+        let particles = DeviceObjectGuard::new(device, particles, ParticleSystem::destroy);
+        let text_renderer =
+            TextRenderer::new(device, post_chain.composite_render_pass(), frames_in_flight)
+                .context("creating the text renderer")?;
+        let text_renderer = DeviceObjectGuard::new(device, text_renderer, TextRenderer::destroy);
 
         let vertex_buffer = Self::create_vertex_buffer(
-            &instance,
-            &device,
+            instance,
+            device,
             graphics_queue,
             physical_device,
             &VERTICES,
-            command_pool,
-        )?;
+            resources.command_pool,
+            &resources.sync_pool,
+        )
+        .context("creating the vertex buffer")?;
+        let destroy_buffer: fn(&mut BufferHolder, &Device) =
+            |buffer, device| unsafe { buffer.destroy(device) };
+        let vertex_buffer = DeviceObjectGuard::new(device, vertex_buffer, destroy_buffer);
 
         let index_buffer = Self::create_index_buffer(
-            &instance,
-            &device,
+            instance,
+            device,
             graphics_queue,
             physical_device,
             &INDICES,
-            command_pool,
-        )?;
+            resources.command_pool,
+            &resources.sync_pool,
+        )
+        .context("creating the index buffer")?;
 
+        resources.meshes.push(Some(MeshStorage::Static(MeshHolder {
+            vertex_buffer: vertex_buffer.release(),
+            index_buffer,
+            index_count: INDICES.len() as u32,
+            index_type: vk::IndexType::UINT16,
+        })));
+        let materials = vec![MaterialHolder {
+            texture_view: resources.placeholder_texture.view,
+            sampler: texture_sampler,
+            lightmap_view: resources.white_lightmap.view,
+            kind: MaterialKind::Scene,
+        }];
+
+        let mut draw_list = DrawList::default();
+        let mesh_item_id = draw_list.add(DrawItem {
+            mesh: MeshHandle(0),
+            material: MaterialHandle(0),
+            transform: Mat4::identity(),
+        });
+
+        let uniform_stride = Self::uniform_stride(instance, physical_device);
         let uniform_buffers = Self::create_uniform_buffers(
-            &instance,
-            &device,
+            instance,
+            device,
             physical_device,
-            MAX_FRAMES_IN_FLIGHT,
-        )?;
+            uniform_stride * INITIAL_DRAW_ITEMS as u64,
+            frames_in_flight,
+        )
+        .context("creating the uniform buffers")?;
+        let uniform_buffers =
+            DeviceObjectGuard::new(device, uniform_buffers, |uniform_buffers, device| {
+                for uniform_buffer in uniform_buffers.iter() {
+                    unsafe { uniform_buffer.destroy(device) };
+                }
+            });
 
-        let descriptor_pool = Self::create_descriptor_pool(&device, MAX_FRAMES_IN_FLIGHT as u32)?;
-        let descriptor_sets = Self::create_descriptor_sets(
-            &device,
-            &uniform_buffers,
-            texture_image_view,
-            texture_sampler,
-            pipeline.descriptor_set_layout,
-            descriptor_pool,
-            MAX_FRAMES_IN_FLIGHT as u32,
-        )?;
+        let resource_sizes = Self::resource_sizes(
+            device,
+            &resources.placeholder_texture,
+            &resources.meshes,
+            uniform_buffers.iter(),
+        );
+        for (name, size) in resource_sizes {
+            event_log.push(RendererEvent::ResourceCreated { name, size });
+        }
+
+        // The uniform buffer, then the texture and the lightmap
+        let descriptor_allocator = DescriptorAllocator::new(vec![
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                descriptor_count: 1,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 2,
+            },
+        ]);
+        let mut descriptor_allocator =
+            DeviceObjectGuard::new(device, descriptor_allocator, DescriptorAllocator::destroy);
+        let mut descriptor_writes = DescriptorWriteBatch::default();
+        let scene_set_writer =
+            SceneSetWriter::new(device, pipeline.descriptor_set_layout, context.api_version)
+                .context("creating the descriptor update template")?;
+        let scene_set_writer =
+            DeviceObjectGuard::new(device, scene_set_writer, SceneSetWriter::destroy);
+        let mut descriptor_sets = Vec::with_capacity(frames_in_flight);
+        for uniform_buffer in uniform_buffers.iter() {
+            descriptor_sets.push(
+                Self::create_descriptor_sets(
+                    device,
+                    &scene_set_writer,
+                    &mut descriptor_writes,
+                    uniform_buffer,
+                    &materials,
+                    pipeline.descriptor_set_layout,
+                    &mut descriptor_allocator,
+                )
+                .context("creating the descriptor sets")?,
+            );
+        }
+        descriptor_writes.flush(device);
 
         let (image_avaible_semaphores, render_done_semaphores, in_flight_fences) =
-            Self::create_sync_objects(&device, MAX_FRAMES_IN_FLIGHT as u32)?;
+            Self::create_sync_objects(device, frames_in_flight as u32)
+                .context("creating the synchronization objects")?;
+        let frames = command_buffers
+            .into_iter()
+            .zip(image_avaible_semaphores)
+            .zip(render_done_semaphores.into_iter().zip(in_flight_fences))
+            .zip(uniform_buffers.release().into_iter().zip(descriptor_sets))
+            .map(
+                |(
+                    ((command_buffer, image_available), (render_done, in_flight)),
+                    (uniform_buffer, descriptor_sets),
+                )| FrameResources {
+                    command_buffer,
+                    image_available,
+                    render_done,
+                    in_flight,
+                    uniform_buffer,
+                    descriptor_sets,
+                    descriptor_materials: vec![Some(MaterialHandle(0))],
+                },
+            )
+            .collect();
+
+        // The mesh is flat, its bounding box has no thickness
+        let mut pickables = Pickables::default();
+        let mesh_bounds = Aabb::from_points(VERTICES.iter().map(|vertex| {
+            let position = vertex.position();
+            Point3::new(position.x, position.y, 0.0)
+        }))
+        .unwrap();
+        let mesh_pick_id = pickables.add(mesh_bounds, Mat4::identity());
 
         Ok(Self {
-            _entry: entry,
-
-            instance,
-            surface,
-            physical_device,
-            device,
-            graphics_queue,
-            present_queue,
-            swapchain,
-            pipeline,
-            swapchain_frame_buffers,
-            command_pool,
-            command_buffers,
+            context,
+            swapchain: swapchain.release(),
+            resources: resources.release(),
+            swapchain_sharing: SwapchainSharing::default(),
+            pipeline: pipeline.release(),
+            post_chain: post_chain.release(),
+            render_hooks: RenderHooks::default(),
+            scheduler: SubmitScheduler::default(),
+            event_log,
+            frame_arena: FrameArena::default(),
+            descriptor_writes,
+            scene_set_writer: scene_set_writer.release(),
             current_frame: 0,
-            vertex_buffer,
-            index_buffer,
-            uniform_buffers,
-            texture_image,
-            texture_image_view,
+            materials,
+            asset_watcher: AssetWatcher::default(),
+            virtual_texturing: None,
+            reflection_probes: None,
+            outlines: None,
+            gpu_particles: None,
+            mesh_shading: None,
+            morph_targets: None,
+            scene_accel: None,
+            ray_tracing: None,
+            ray_query_shadows: false,
+            texture_arrays: None,
+            pbr_pipeline: None,
+            pbr_lighting: PbrLighting::default(),
+            shader_materials: ShaderMaterials::default(),
+            skybox: None,
+            terrain: None,
+            image_based_lighting: None,
+            draw_list,
+            draw_capacity: INITIAL_DRAW_ITEMS,
+            uniform_stride,
             texture_sampler,
-            descriptor_pool,
-            descriptor_sets,
-
-            image_avaible_semaphores,
-            render_done_semaphores,
-            in_flight_fences,
-
-            start_time: Instant::now(),
+            texture_filtering: TextureFiltering::default(),
+            descriptor_allocator: descriptor_allocator.release(),
+
+            frames,
+            frames_in_flight,
+            present_mode: builder.present_mode,
+            frame_guard: FrameGuard::default(),
+            frame_limiter: FrameLimiter::default(),
+
+            input: InputState::default(),
+            camera: Box::new(OrbitCamera::default()),
+            view_matrix: Mat4::identity(),
+            view_override: None,
+            scene_transform: None,
+            proj_matrix: Mat4::identity(),
+            previous_model_view_projs: Vec::new(),
+            jitter_index: 0,
+            transparent_order: Vec::new(),
+            pickables,
+            mesh_pick_id,
+            pixel_inspector: PixelInspector::default(),
+            debug_hud: debug_hud.release(),
+            debug_draw: debug_draw.release(),
+            particles: particles.release(),
+            animations: Animations::default(),
+            text_renderer: text_renderer.release(),
+            mesh_item_id,
+            animation_time: 0.0,
+            previous_animation_time: 0.0,
+            fixed_timestep: FixedTimestep::default(),
+            fixed_update: None,
+            paused: false,
+            time_scale: 1.0,
+            wireframe: false,
+            frame_clock: FrameClock::default(),
             resize_flag: false,
+            surface_size,
+            presented_image: None,
 
-            #[cfg(feature = "vlayers")]
-            debug_messenger,
+            #[cfg(feature = "scripting")]
+            script_host: ScriptHost::new(),
+            #[cfg(feature = "imgui")]
+            imgui_renderer: None,
         })
     }
 
-    pub fn draw_frame(&mut self) -> AppResult<()> {
-        unsafe {
-            self.device.wait_for_fences(
-                &[self.in_flight_fences[self.current_frame]],
-                true,
-                std::u64::MAX,
-            )?;
+    /// Reports the Vulkan environment without opening a window: the loader version, layers and
+    /// extensions, then the physical devices along with what `create` checks to pick one. The
+    /// first thing to look at when no suitable device is found.
+    pub fn diagnose() -> AppResult<Diagnostics> {
+        diagnostics::collect(&Context::load_entry()?)
+    }
 
-            let result = self.swapchain.swapchain_ext.acquire_next_image(
-                self.swapchain.swapchain,
-                std::u64::MAX,
-                self.image_avaible_semaphores[self.current_frame],
-                vk::Fence::null(),
-            );
+    /// Notifies the application that the window has been resized to `size` physical pixels
+    pub fn request_resize(&mut self, size: PhysicalSize<u32>) {
+        self.surface_size.physical = size;
+        self.resize_flag = true;
+    }
 
-            let image_index = match result {
-                Ok((v, _)) => v,
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                    self.recreate_swapchain()?;
-                    return Ok(());
-                }
-                Err(res) => return AppResult::Err(res.into()),
-            };
+    /// Notifies the application that the window moved to a monitor with a different scale factor.
+    /// The new physical size is reported by the following resize.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.surface_size.scale_factor = scale_factor;
+        self.resize_flag = true;
+    }
 
-            self.device
-                .reset_fences(&[self.in_flight_fences[self.current_frame]])?;
+    /// Pauses or resumes the animation of the scene, the camera can still be moved while paused
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
 
-            self.device.reset_command_buffer(
-                self.command_buffers[self.current_frame],
-                vk::CommandBufferResetFlags::empty(),
-            )?;
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
 
-            self.update_uniform_buffer();
-
-            self.record_command_buffer(image_index)?;
-
-            let wait_semaphores = [self.image_avaible_semaphores[self.current_frame]];
-            let command_buffers = [self.command_buffers[self.current_frame]];
-            let signal_semaphores = [self.render_done_semaphores[self.current_frame]];
-            let wait_dst_stage_mask = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-            let submit_infos = [vk::SubmitInfo {
-                wait_semaphore_count: wait_semaphores.len() as u32,
-                p_wait_semaphores: &wait_semaphores as *const _,
-                p_wait_dst_stage_mask: &wait_dst_stage_mask as *const _,
-                command_buffer_count: command_buffers.len() as u32,
-                p_command_buffers: &command_buffers as *const _,
-                signal_semaphore_count: signal_semaphores.len() as u32,
-                p_signal_semaphores: &signal_semaphores as *const _,
-                ..Default::default()
-            }];
-
-            self.device.queue_submit(
-                self.graphics_queue,
-                &submit_infos,
-                self.in_flight_fences[self.current_frame],
-            )?;
+    /// Scales the time the scene is animated with, e.g. 0.5 for slow motion. The rotation, the
+    /// particles, the animations and the fixed update follow the scaled time, the camera the real
+    /// one. Negative scales are clamped to 0, which freezes the scene like a pause.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.max(0.0);
+    }
 
-            let wait_semaphores = signal_semaphores;
-            let swapchains = [self.swapchain.swapchain];
-            let image_indices = [image_index];
-            let present_info = vk::PresentInfoKHR {
-                wait_semaphore_count: wait_semaphores.len() as u32,
-                p_wait_semaphores: &wait_semaphores as *const _,
-                swapchain_count: swapchains.len() as u32,
-                p_swapchains: &swapchains as *const _,
-                p_image_indices: &image_indices as *const _,
-                ..Default::default()
-            };
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
 
-            let result = self
-                .swapchain
-                .swapchain_ext
-                .queue_present(self.present_queue, &present_info);
+    /// Transforms the whole scene with `transform` instead of the built-in rotation, applied
+    /// before the transform of every draw item. `None` restores the rotation. The transform is
+    /// kept until replaced, the caller sets it before every `draw_frame` to animate the scene.
+    pub fn set_scene_transform(&mut self, transform: Option<Mat4>) {
+        self.scene_transform = transform;
+    }
 
-            match result {
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) | Ok(true) | Ok(_) if self.resize_flag => {
-                    self.resize_flag = false;
-                    self.recreate_swapchain()?;
-                    return Ok(());
-                }
-                Err(res) => return AppResult::Err(res.into()),
-                _ => (),
-            };
-        }
+    /// Renders with `view` as the view matrix instead of the one of the camera, which is still
+    /// updated by the input. `None` gives the view back to the camera. Like the scene
+    /// transform, the matrix is kept until replaced.
+    pub fn set_view_matrix(&mut self, view: Option<Mat4>) {
+        self.view_override = view;
+    }
 
-        self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
+    /// Caps the frame rate to `fps` frames per second, `None` lifting the cap. `draw_frame` sleeps
+    /// then spins until the next frame may start, which paces the frames whatever the present
+    /// mode, e.g. to spare the GPU with `MAILBOX` or `IMMEDIATE`.
+    pub fn set_fps_limit(&mut self, fps: Option<u32>) {
+        self.frame_limiter.set_limit(fps);
+    }
 
-        Ok(())
+    pub fn fps_limit(&self) -> Option<u32> {
+        self.frame_limiter.limit()
     }
 
-    fn record_command_buffer(&mut self, image_index: u32) -> AppResult<()> {
-        let begin_info = vk::CommandBufferBeginInfo::default();
+    /// Sets the ticks per second of the simulation, `DEFAULT_TICK_RATE` by default. The rotation
+    /// of the scene, the particles and the fixed update advance by whole ticks whatever the
+    /// frame rate, the rotation being interpolated between the last two ticks when rendered.
+    pub fn set_tick_rate(&mut self, tick_rate: u32) {
+        self.fixed_timestep.set_tick_rate(tick_rate);
+    }
 
-        unsafe {
-            self.device
-                .begin_command_buffer(self.command_buffers[self.current_frame], &begin_info)?;
-        }
+    pub fn tick_rate(&self) -> u32 {
+        self.fixed_timestep.tick_rate()
+    }
 
-        let clear_color = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 0.0, 1.0],
-            },
-        };
+    /// Runs `update` once per tick of the simulation, before the frame is rendered, replacing the
+    /// previous one. Like the rest of the simulation, it isn't run while paused.
+    pub fn set_fixed_update(&mut self, update: Option<FixedUpdateFn>) -> Option<FixedUpdateFn> {
+        std::mem::replace(&mut self.fixed_update, update)
+    }
 
-        let offset = vk::Offset2D { x: 0, y: 0 };
-        let render_area = vk::Rect2D {
-            offset,
-            extent: self.swapchain.extent,
-        };
+    /// Draws the edges of the scene meshes instead of their surfaces, every item with the same
+    /// flat color whatever its material. Fails to enable if the device doesn't support the
+    /// `fill_mode_non_solid` feature, see `DeviceFeatures`.
+    pub fn set_wireframe(&mut self, enabled: bool) -> AppResult<()> {
+        if enabled && self.pipeline.wireframe_pipeline.is_none() {
+            return AppResult::Err(AppError::new(AppErrorType::WireframeUnsupported));
+        }
+        self.wireframe = enabled;
 
-        let clear_values = [clear_color];
-        let render_pass_info = vk::RenderPassBeginInfo {
-            render_pass: self.pipeline.renderpass,
-            framebuffer: self.swapchain_frame_buffers[image_index as usize],
-            render_area,
-            clear_value_count: clear_values.len() as u32,
-            p_clear_values: &clear_values as *const _,
-            ..Default::default()
-        };
+        Ok(())
+    }
 
-        let viewports = [vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: self.swapchain.extent.width as f32,
-            height: self.swapchain.extent.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0,
-        }];
+    pub fn wireframe_enabled(&self) -> bool {
+        self.wireframe
+    }
 
-        let offset = vk::Offset2D { x: 0, y: 0 };
-        let scissors = [vk::Rect2D {
-            offset,
-            extent: self.swapchain.extent,
-        }];
+    /// Returns the closest object under `cursor`, in physical pixels from the top left corner of
+    /// the window, as of the last rendered frame
+    pub fn pick(&self, cursor: PhysicalPosition<f64>) -> Option<PickHit> {
+        let extent = self.swapchain.extent;
+        let ray = Ray::from_cursor(
+            cursor,
+            extent.width,
+            extent.height,
+            self.view_matrix,
+            self.proj_matrix,
+        )?;
+        self.pickables.pick(&ray)
+    }
 
-        let command_buffer = self.command_buffers[self.current_frame];
-        unsafe {
-            self.device.cmd_begin_render_pass(
-                command_buffer,
-                &render_pass_info,
-                vk::SubpassContents::INLINE,
-            );
+    /// Inspects the pixel under `cursor`, in physical pixels from the top left corner of the
+    /// window, as of the last rendered frame: the color of the scene target and the closest
+    /// pickable object. The readback waits for the device to be idle, so the inspections are
+    /// throttled to `PIXEL_INSPECTIONS_PER_SECOND`. Returns `None` when throttled, when the
+    /// cursor is outside of the surface or when no frame has been rendered.
+    pub fn inspect_pixel(
+        &mut self,
+        cursor: PhysicalPosition<f64>,
+    ) -> AppResult<Option<PixelSample>> {
+        let extent = self.swapchain.extent;
+        let (x, y) = (cursor.x.floor(), cursor.y.floor());
+        let inside =
+            (0.0..extent.width as f64).contains(&x) && (0.0..extent.height as f64).contains(&y);
+        if !inside || self.presented_image.is_none() {
+            return Ok(None);
+        }
+        if !self.pixel_inspector.try_query(Instant::now()) {
+            return Ok(None);
+        }
 
-            self.device.cmd_bind_pipeline(
-                command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline.pipeline,
-            );
+        // The scene target is shared by the frames in flight
+        unsafe { self.context.device.device_wait_idle()? };
+        let (scene_image, _) = self.post_chain.scene_target();
+        let (x, y) = (x as u32, y as u32);
+        let color = PixelInspector::read_texel(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            scene_image,
+            x,
+            y,
+        )?;
 
-            let vertex_buffers = [self.vertex_buffer.buffer];
-            let offsets = [0];
-            self.device
-                .cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
+        Ok(Some(PixelSample {
+            x,
+            y,
+            color,
+            hit: self.pick(cursor),
+        }))
+    }
 
-            self.device.cmd_bind_index_buffer(
-                command_buffer,
-                self.index_buffer.buffer,
-                0,
-                vk::IndexType::UINT16,
-            );
+    /// Returns the samples per pixel the scene is drawn with, the count asked with
+    /// `ApplicationBuilder::msaa_samples` clamped to what the device supports. The pipelines
+    /// drawn by the render hooks in the scene pass must be created with it.
+    pub fn msaa_samples(&self) -> vk::SampleCountFlags {
+        self.pipeline.samples
+    }
 
-            self.device.cmd_set_viewport(command_buffer, 0, &viewports);
-            self.device.cmd_set_scissor(command_buffer, 0, &scissors);
+    /// Returns the id of the rendered mesh in the pickable objects
+    pub fn mesh_pick_id(&self) -> PickId {
+        self.mesh_pick_id
+    }
 
-            self.device.cmd_bind_descriptor_sets(
-                command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline.pipeline_layout,
-                0,
-                &[self.descriptor_sets[self.current_frame]],
-                &[],
-            );
+    /// Uploads a mesh made of triangles, which can then be drawn by adding a `DrawItem` using the
+    /// returned handle. Fails if there is no vertex or index.
+    pub fn create_mesh(&mut self, vertices: &[Vertex], indices: &[u32]) -> AppResult<MeshHandle> {
+        let mesh = self.upload_mesh(vertices, indices)?;
+        let handle = self.add_mesh(MeshStorage::Static(mesh));
+        let result = self
+            .update_meshlets(handle, vertices, indices)
+            .and_then(|()| self.update_blas(handle, vertices, indices));
+        if let Err(err) = result {
+            // Nobody gets the handle, the mesh is destroyed along with what was built for it
+            self.destroy_mesh(handle)?;
+            return Err(err);
+        }
 
-            self.device
-                .cmd_draw_indexed(command_buffer, INDICES.len() as u32, 1, 0, 0, 0);
+        Ok(handle)
+    }
 
-            self.device.cmd_end_render_pass(command_buffer);
+    /// Creates a static mesh drawn with `targets` added to its vertices, each scaled by its
+    /// weight, see `set_morph_weights`. Every target needs an offset for each vertex. The items
+    /// with the scene material are morphed in the vertex shader, the others are drawn with the
+    /// vertices as given, as are the shadows and the ray traced scene. The mesh isn't split into
+    /// meshlets.
+    pub fn create_morph_mesh(
+        &mut self,
+        vertices: &[Vertex],
+        indices: &[u32],
+        targets: &[MorphTarget],
+    ) -> AppResult<MeshHandle> {
+        if self.morph_targets.is_none() {
+            self.morph_targets = Some(MorphTargets::new(
+                &self.context.device,
+                self.pipeline.renderpass,
+                self.pipeline.samples,
+                self.pipeline.descriptor_set_layout,
+            )?);
+        }
 
-            self.device.end_command_buffer(command_buffer)?;
+        morph_targets::check_targets(vertices.len(), targets)?;
+        let mesh = self.upload_mesh(vertices, indices)?;
+        let handle = self.add_mesh(MeshStorage::Static(mesh));
+        let result = self
+            .morph_targets
+            .as_mut()
+            .unwrap()
+            .add_mesh(
+                &self.context.instance,
+                &self.context.device,
+                self.context.graphics_queue,
+                self.context.physical_device,
+                self.resources.command_pool,
+                &self.resources.sync_pool,
+                self.frames_in_flight,
+                handle.0,
+                vertices.len(),
+                targets,
+            )
+            .and_then(|()| self.update_blas(handle, vertices, indices));
+        if let Err(err) = result {
+            self.destroy_mesh(handle)?;
+            return Err(err);
         }
 
-        Ok(())
+        Ok(handle)
     }
 
-    fn update_uniform_buffer(&mut self) {
-        let time = self.start_time.elapsed().as_secs_f32();
-
-        // Rotates 90 degres every 4 seconds
-        let model = Mat4::from_angle_z(cgmath::Rad(std::f32::consts::PI / 8.0) * time);
+    /// Sets the weights of the targets of a morph mesh, in the order of the targets, from the
+    /// next frame. The missing weights are zero and the extra ones are ignored. Returns `false`
+    /// if the mesh has no targets. Overwritten every frame by an animation morphing the mesh.
+    pub fn set_morph_weights(&mut self, mesh: MeshHandle, weights: &[f32]) -> bool {
+        self.morph_targets
+            .as_mut()
+            .is_some_and(|morph_targets| morph_targets.set_weights(mesh.0, weights))
+    }
 
-        let view = Mat4::look_at_rh(
-            Point3::new(2.0, 2.0, 2.0),
-            Point3::new(0.0, 0.0, 0.0),
-            Vec3::new(0.0, 0.0, -1.0),
-        );
+    /// Weights of the targets of a morph mesh, `None` if it has no targets
+    pub fn morph_weights(&self, mesh: MeshHandle) -> Option<&[f32]> {
+        self.morph_targets.as_ref()?.weights(mesh.0)
+    }
 
-        let aspect_ratio = self.swapchain.extent.width as f32 / self.swapchain.extent.height as f32;
-        let proj = cgmath::perspective(cgmath::Deg(45.0), aspect_ratio, 0.1, 10.0);
+    /// Imports the mesh at `path` with `import` through the mesh cache, see `load_mesh_cached`.
+    /// The mesh is imported and uploaded again whenever the file changes while hot reloading is
+    /// enabled.
+    pub fn load_mesh<P, F>(&mut self, path: P, import: F) -> AppResult<MeshHandle>
+    where
+        P: AsRef<Path>,
+        F: Fn(&Path) -> AppResult<(Vec<Vertex>, Vec<u32>)> + 'static,
+    {
+        let path = path.as_ref();
+        let data = load_mesh_cached(path, &import)?;
+        let handle = self.create_mesh(&data.vertices, &data.indices)?;
+        self.asset_watcher
+            .watch_mesh(path, handle, Box::new(import));
+
+        Ok(handle)
+    }
 
-        let ubo = ModelViewProj::new(model, view, proj);
+    fn upload_mesh(&self, vertices: &[Vertex], indices: &[u32]) -> AppResult<MeshHolder> {
+        if vertices.is_empty() || indices.is_empty() {
+            return AppResult::Err(AppError::new(AppErrorType::EmptyMesh));
+        }
 
-        let src_ptr = &ubo as *const ModelViewProj;
-        let dst_ptr = self.uniform_buffers[self.current_frame].memory_map as *mut ModelViewProj;
-        unsafe { std::ptr::copy(src_ptr, dst_ptr, 1) };
-    }
+        let vertex_buffer = Self::create_vertex_buffer(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            vertices,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+        )?;
+        // Destroyed if the index buffer fails to be created
+        let vertex_buffer = DeviceObjectGuard::new(
+            &self.context.device,
+            vertex_buffer,
+            |buffer, device| unsafe { buffer.destroy(device) },
+        );
+        let index_buffer = Self::create_index_buffer(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            indices,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+        )?;
 
-    pub fn request_resize(&mut self) {
-        self.resize_flag = true;
+        Ok(MeshHolder {
+            vertex_buffer: vertex_buffer.release(),
+            index_buffer,
+            index_count: indices.len() as u32,
+            index_type: vk::IndexType::UINT32,
+        })
     }
 
-    pub fn recreate_swapchain(&mut self) -> AppResult<()> {
-        unsafe {
-            self.device.device_wait_idle()?;
+    /// Creates a mesh whose vertices and indices can be replaced every frame with
+    /// `update_dynamic_mesh`, e.g. for geometry animated by the CPU. The mesh is empty until the
+    /// first update. While the ray tracing or the ray traced shadows are enabled, its
+    /// acceleration structures are refitted every update keeping the triangle count, and rebuilt
+    /// otherwise.
+    pub fn create_dynamic_mesh(
+        &mut self,
+        vertex_capacity: usize,
+        index_capacity: usize,
+    ) -> AppResult<MeshHandle> {
+        if vertex_capacity == 0 || index_capacity == 0 {
+            return AppResult::Err(AppError::new(AppErrorType::EmptyMesh));
         }
 
-        self.cleanup_swapchain();
-
-        let queue_families =
-            Self::find_queue_families(&self.instance, self.physical_device, &self.surface)?;
-        self.swapchain = Self::create_swapchain(
-            &self.instance,
-            &self.device,
-            self.physical_device,
-            &self.surface,
-            queue_families,
+        // The acceleration structures are built straight from the buffers of the frames
+        let accel_usage = if self.accel_in_use() {
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+        } else {
+            vk::BufferUsageFlags::empty()
+        };
+        // The buffers are destroyed if the mesh fails to be created before owning them
+        let destroy_buffers: fn(&mut Vec<MemoryMappedBuffer>, &Device) = |buffers, device| {
+            for buffer in buffers.iter() {
+                unsafe { buffer.destroy(device) };
+            }
+        };
+        let vertex_buffers = Self::create_host_visible_buffers(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            (vertex_capacity * std::mem::size_of::<Vertex>()) as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER | accel_usage,
+            self.frames_in_flight,
         )?;
+        let vertex_buffers =
+            DeviceObjectGuard::new(&self.context.device, vertex_buffers, destroy_buffers);
+        let index_buffers = Self::create_host_visible_buffers(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            (index_capacity * std::mem::size_of::<u32>()) as u64,
+            vk::BufferUsageFlags::INDEX_BUFFER | accel_usage,
+            self.frames_in_flight,
+        )?;
+        let index_buffers =
+            DeviceObjectGuard::new(&self.context.device, index_buffers, destroy_buffers);
+
+        if let (Some(scene_accel), false) = (&mut self.scene_accel, accel_usage.is_empty()) {
+            let previous = scene_accel.add_dynamic_mesh(
+                &self.context.instance,
+                &self.context.device,
+                self.context.physical_device,
+                self.resources.meshes.len(),
+                vertex_buffers
+                    .iter()
+                    .zip(index_buffers.iter())
+                    .map(|(vertices, indices)| (vertices.buffer, indices.buffer)),
+                vertex_capacity,
+                index_capacity,
+            )?;
+            for garbage in previous {
+                self.resources
+                    .garbage_collector
+                    .retire(garbage, self.frames_in_flight);
+            }
+        }
 
-        self.swapchain_frame_buffers =
-            Self::create_frame_buffers(&self.device, &self.pipeline, &self.swapchain)?;
-
-        Ok(())
+        Ok(self.add_mesh(MeshStorage::Dynamic(DynamicMeshHolder {
+            vertex_buffers: vertex_buffers.release(),
+            index_buffers: index_buffers.release(),
+            index_counts: vec![0; self.frames_in_flight],
+            stale_frames: vec![false; self.frames_in_flight],
+            vertices: Vec::with_capacity(vertex_capacity),
+            indices: Vec::with_capacity(index_capacity),
+            vertex_capacity,
+            index_capacity,
+        })))
     }
 
-    /// Creates the VkInstance with the requested extension names and validation layers name
-    fn create_instance<'a, 'b>(
-        entry: &Entry,
-        extension_names: impl IntoIterator<Item = &'a CStr>,
-        #[cfg(feature = "vlayers")] layer_names: impl IntoIterator<Item = &'b CStr>,
-    ) -> AppResult<Instance> {
-        // Define the vulkan application info
-        let app_name = CString::new("Vulkan Tutorial").unwrap();
-        let engine_name = CString::new("No Engine").unwrap();
-        let app_info = vk::ApplicationInfo {
-            p_application_name: app_name.as_ptr(),
-            application_version: vk::make_api_version(1, 0, 0, 0),
-            p_engine_name: engine_name.as_ptr(),
-            engine_version: vk::make_api_version(1, 0, 0, 0),
-            api_version: vk::API_VERSION_1_0,
-            ..Default::default()
+    /// Replaces the content of a dynamic mesh, starting from the next rendered frame. Fails if the
+    /// data exceeds the capacities the mesh was created with, or if `handle` isn't a dynamic mesh.
+    pub fn update_dynamic_mesh(
+        &mut self,
+        handle: MeshHandle,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> AppResult<()> {
+        let Some(Some(MeshStorage::Dynamic(mesh))) = self.resources.meshes.get_mut(handle.0) else {
+            return AppResult::Err(AppError::new(AppErrorType::NotADynamicMesh));
         };
+        if vertices.len() > mesh.vertex_capacity || indices.len() > mesh.index_capacity {
+            return AppResult::Err(AppError::new(AppErrorType::MeshCapacityExceeded));
+        }
 
-        // Filter out the the extensions unsupported by the vulkan instance
-        let avaible_extensions = unsafe { entry.enumerate_instance_extension_properties(None)? };
-        let extensions: Vec<*const i8> =
-            extension_names
-                .into_iter()
-                .filter(|&ext| {
-                    avaible_extensions
-                    .iter()
-                    .find(|&a_ext| unsafe { CStr::from_ptr(a_ext.extension_name.as_ptr()) } == ext)
-                    .or_else(|| {
-                        println!("{} {:?} ", "Extension unsupported:".truecolor(255, 172, 28), ext);
-                        None
-                    })
-                    .is_some()
-                })
-                .map(|ext| ext.as_ptr())
-                .collect();
-
-        // Filter out the the layers unsupported by the vulkan instance
-        #[cfg(feature = "vlayers")]
-        let layers: Vec<*const i8> =
-            {
-                let avaible_layers = unsafe { entry.enumerate_instance_layer_properties()? };
-
-                layer_names
-                    .into_iter()
-                    .filter(|&lay| {
-                        avaible_layers
-                        .iter()
-                        .find(|&a_lay| unsafe { CStr::from_ptr(a_lay.layer_name.as_ptr()) } == lay)
-                        .or_else(|| {
-                            println!("{} {:?} ","Layer unsupported:".truecolor(255, 172, 28), lay);
-                            None
-                        })
-                        .is_some()
-                    })
-                    .map(|lay| lay.as_ptr())
-                    .collect()
-            };
+        mesh.vertices.clear();
+        mesh.vertices.extend_from_slice(vertices);
+        mesh.indices.clear();
+        mesh.indices.extend_from_slice(indices);
+        mesh.stale_frames.fill(true);
 
-        #[allow(unused_mut)]
-        let mut create_info = vk::InstanceCreateInfo {
-            p_application_info: &app_info as *const _,
-            enabled_extension_count: extensions.len() as u32,
-            pp_enabled_extension_names: extensions.as_ptr(),
-            ..Default::default()
-        };
+        Ok(())
+    }
 
-        #[cfg(feature = "vlayers")]
-        let debug_messenger_create_info = Self::debug_messenger_create_info();
-        #[cfg(feature = "vlayers")]
-        let debug_messenger_create_info_ptr =
-            &debug_messenger_create_info as *const vk::DebugUtilsMessengerCreateInfoEXT;
-        {
-            create_info.p_next = debug_messenger_create_info_ptr as *const _;
-            create_info.enabled_layer_count = layers.len() as u32;
-            create_info.pp_enabled_layer_names = layers.as_ptr();
+    fn add_mesh(&mut self, mesh: MeshStorage) -> MeshHandle {
+        let handle = MeshHandle(self.resources.meshes.len());
+        for (name, size) in Self::mesh_sizes(&self.context.device, handle.0, &mesh) {
+            self.event_log
+                .push(RendererEvent::ResourceCreated { name, size });
         }
+        self.resources.meshes.push(Some(mesh));
 
-        // Create the instance
-        // Safety: The instance is the last destroyed object
-        unsafe {
-            entry
-                .create_instance(&create_info, None)
-                .or_else(|r| AppResult::Err(r.into()))
-        }
+        handle
     }
 
-    fn create_surface(
-        entry: &Entry,
-        instance: &Instance,
-        event_loop: &ActiveEventLoop,
-        window: &Window,
-    ) -> AppResult<SurfaceHodlder> {
-        let surface_ext = surface::Instance::new(entry, instance);
-
-        let display_handle: DisplayHandle = event_loop
-            .display_handle()
-            .or_else(|r| AppResult::Err(r.into()))?;
-
-        let window_handle: WindowHandle = window
-            .window_handle()
-            .or_else(|r| AppResult::Err(r.into()))?;
-
-        let surface = unsafe {
-            ash_window::create_surface(
-                entry,
-                instance,
-                display_handle.as_raw(),
-                window_handle.as_raw(),
-                None,
-            )?
+    /// Destroys the buffers of a mesh once the GPU is done with them, spread over the following
+    /// frames by the garbage collector. The draw items using it are kept but not drawn anymore.
+    /// Does nothing if the mesh is already destroyed.
+    pub fn destroy_mesh(&mut self, handle: MeshHandle) -> AppResult<()> {
+        let Some(Some(mesh)) = self.resources.meshes.get_mut(handle.0).map(Option::take) else {
+            return Ok(());
         };
 
-        Ok(SurfaceHodlder {
-            surface_ext,
-            surface,
-        })
-    }
+        self.asset_watcher.unwatch(WatchedAsset::Mesh(handle));
+        self.retire_mesh(handle, mesh);
 
-    /// Chooses the first avaible physical device that suits the needs of the application
-    fn pick_physical_device(
-        instance: &Instance,
-        surface: &SurfaceHodlder,
-    ) -> AppResult<(vk::PhysicalDevice, QueueFamilyIndice)> {
-        let physical_devices = unsafe { instance.enumerate_physical_devices()? };
-        physical_devices
-            .into_iter()
-            .find_map(|device| {
-                Self::is_device_suitable(instance, device, surface)
-                    .ok()?
-                    .map(|indices| (device, indices))
-            })
-            .ok_or_else(|| AppError::new(AppErrorType::NoSuitableDevice))
+        Ok(())
     }
 
-    /// Checks if the physical device meets the application's requirements
-    fn is_device_suitable(
-        instance: &Instance,
-        device: vk::PhysicalDevice,
-        surface: &SurfaceHodlder,
-    ) -> AppResult<Option<QueueFamilyIndice>> {
-        let indices = Self::find_queue_families(instance, device, surface)?;
-        if !indices.is_complete() {
-            return Ok(None);
-        }
-
-        let extensions_supported = Self::check_device_extensions_support(instance, device)?;
-        if !extensions_supported {
-            return Ok(None);
+    // Hands the buffers of a mesh no longer drawn to the garbage collector
+    fn retire_mesh(&mut self, handle: MeshHandle, mesh: MeshStorage) {
+        for (name, size) in Self::mesh_sizes(&self.context.device, handle.0, &mesh) {
+            self.event_log
+                .push(RendererEvent::ResourceDestroyed { name, size });
         }
-
-        let swapchain_details = Self::query_swapchain_support(device, surface)?;
-        let swapchain_adequate =
-            !swapchain_details.formats.is_empty() && !swapchain_details.present_modes.is_empty();
-        if !swapchain_adequate {
-            return Ok(None);
-        }
-
-        let supported_features = unsafe { instance.get_physical_device_features(device) };
-        if supported_features.sampler_anisotropy == vk::FALSE {
-            return Ok(None);
+        let buffers = match mesh {
+            MeshStorage::Static(mesh) => vec![
+                (mesh.vertex_buffer.buffer, mesh.vertex_buffer.memory),
+                (mesh.index_buffer.buffer, mesh.index_buffer.memory),
+            ],
+            MeshStorage::Dynamic(mesh) => mesh
+                .vertex_buffers
+                .iter()
+                .chain(&mesh.index_buffers)
+                .map(|buffer| (buffer.buffer, buffer.memory))
+                .collect(),
+        };
+        for (buffer, memory) in buffers {
+            self.resources
+                .garbage_collector
+                .retire(Garbage::Buffer(buffer, memory), self.frames_in_flight);
         }
-
-        Ok(Some(indices))
-    }
-
-    /// Finds the needed queue families from the physical device
-    fn find_queue_families(
-        instance: &Instance,
-        device: vk::PhysicalDevice,
-        surface: &SurfaceHodlder,
-    ) -> AppResult<QueueFamilyIndice> {
-        let queue_families =
-            unsafe { instance.get_physical_device_queue_family_properties(device) };
-
-        let mut indices = QueueFamilyIndice::default();
-        for (i, family) in queue_families
-            .iter()
-            .enumerate()
-            .map(|(i, f)| (i as u32, f))
+        if let Some(meshlets) = self
+            .mesh_shading
+            .as_mut()
+            .and_then(|mesh_shading| mesh_shading.remove_mesh(handle.0))
         {
-            if indices.is_complete() {
-                break;
-            }
-
-            if family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                indices.graphics_family = Some(i)
+            self.retire_meshlets(meshlets);
+        }
+        if let Some(scene_accel) = &mut self.scene_accel {
+            for garbage in scene_accel.remove_mesh(handle.0) {
+                self.resources
+                    .garbage_collector
+                    .retire(garbage, self.frames_in_flight);
             }
-
-            if unsafe {
-                surface.surface_ext.get_physical_device_surface_support(
-                    device,
-                    i,
-                    surface.surface,
-                )?
-            } {
-                indices.present_family = Some(i)
+        }
+        if let Some(morph_targets) = &mut self.morph_targets {
+            for garbage in morph_targets.remove_mesh(handle.0) {
+                self.resources
+                    .garbage_collector
+                    .retire(garbage, self.frames_in_flight);
             }
         }
-
-        Ok(indices)
     }
 
-    fn check_device_extensions_support(
-        instance: &Instance,
-        device: vk::PhysicalDevice,
-    ) -> AppResult<bool> {
-        let avaible_extensions = unsafe { instance.enumerate_device_extension_properties(device)? };
-
-        let mut avaible_extensions_set = HashSet::new();
-        for a_ext in avaible_extensions.into_iter() {
-            avaible_extensions_set.insert(unsafe { CStr::from_ptr(a_ext.extension_name.as_ptr()) });
+    /// Draws the meshes with the scene material from meshlets, with a task and a mesh shader,
+    /// instead of their vertex and index buffers. Only the meshes created or reloaded while it
+    /// is enabled are split into meshlets, the others keep being drawn from their buffers.
+    /// Fails to enable if the device doesn't support `VK_EXT_mesh_shader`, see
+    /// `DeviceFeatures`. Disabled by default.
+    pub fn set_mesh_shading(&mut self, enabled: bool) -> AppResult<()> {
+        if !enabled {
+            if let Some(mesh_shading) = &mut self.mesh_shading {
+                mesh_shading.set_enabled(false);
+            }
+            return Ok(());
+        }
+        if !self.context.device_features.mesh_shader {
+            return AppResult::Err(AppError::new(AppErrorType::MeshShaderUnsupported));
         }
 
-        for &ext in DEVICE_EXTENSIONS.iter() {
-            if !avaible_extensions_set.insert(ext) {
-                return Ok(false);
+        match &mut self.mesh_shading {
+            Some(mesh_shading) => mesh_shading.set_enabled(true),
+            None => {
+                self.mesh_shading = Some(MeshShading::new(
+                    &self.context.instance,
+                    &self.context.device,
+                    self.pipeline.renderpass,
+                    self.pipeline.samples,
+                    self.pipeline.descriptor_set_layout,
+                    self.frames_in_flight,
+                )?);
+                self.event_log.push(RendererEvent::PipelineBuilt {
+                    name: String::from("meshlet"),
+                });
             }
         }
 
-        Ok(true)
+        Ok(())
     }
 
-    fn query_swapchain_support(
-        device: vk::PhysicalDevice,
-        surface: &SurfaceHodlder,
-    ) -> AppResult<SwapChainDetails> {
-        let capabilities = unsafe {
-            surface
-                .surface_ext
-                .get_physical_device_surface_capabilities(device, surface.surface)?
-        };
+    pub fn mesh_shading_enabled(&self) -> bool {
+        self.mesh_shading
+            .as_ref()
+            .is_some_and(|mesh_shading| mesh_shading.enabled())
+    }
 
-        let formats = unsafe {
-            surface
-                .surface_ext
-                .get_physical_device_surface_formats(device, surface.surface)?
+    // Splits a static mesh into meshlets while the mesh shading is enabled, or forgets its
+    // previous meshlets otherwise
+    fn update_meshlets(
+        &mut self,
+        handle: MeshHandle,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> AppResult<()> {
+        let Some(mesh_shading) = &mut self.mesh_shading else {
+            return Ok(());
         };
 
-        let present_modes = unsafe {
-            surface
-                .surface_ext
-                .get_physical_device_surface_present_modes(device, surface.surface)?
+        let previous = if mesh_shading.enabled() {
+            mesh_shading.add_mesh(
+                &self.context.instance,
+                &self.context.device,
+                self.context.graphics_queue,
+                self.context.physical_device,
+                self.resources.command_pool,
+                &self.resources.sync_pool,
+                handle.0,
+                vertices,
+                indices,
+            )?
+        } else {
+            mesh_shading.remove_mesh(handle.0)
         };
+        if let Some(meshlets) = previous {
+            self.retire_meshlets(meshlets);
+        }
 
-        Ok(SwapChainDetails {
-            capabilities,
-            formats,
-            present_modes,
-        })
+        Ok(())
     }
 
-    /// Creates the VkDevice
-    fn create_logical_device(
-        instance: &Instance,
-        physical_device: vk::PhysicalDevice,
-        indices: QueueFamilyIndice,
-    ) -> AppResult<(Device, vk::Queue, vk::Queue)> {
-        let unique_families = indices.get_unique_families();
-
-        let queue_priorities = [1.0f32];
-        let mut queue_create_infos = Vec::with_capacity(unique_families.len());
-        for &queue_family in unique_families.iter() {
-            queue_create_infos.push(vk::DeviceQueueCreateInfo {
-                queue_family_index: queue_family,
-                queue_count: 1,
-                p_queue_priorities: &queue_priorities as *const _,
-                ..Default::default()
-            })
+    /// Traces the rays of the scene through acceleration structures of its meshes, the ray
+    /// traced image replacing or being blended over the rasterized meshes depending on `mode`.
+    /// Only the meshes created or reloaded while it is enabled are ray traced, shaded with their
+    /// vertex colors, at most `MAX_RAY_TRACED_INSTANCES` draw items. Fails to enable if the
+    /// device doesn't support `VK_KHR_ray_tracing_pipeline`, see `DeviceFeatures`. Disabled by
+    /// default.
+    pub fn set_ray_tracing(&mut self, mode: RayTracingMode) -> AppResult<()> {
+        if mode != RayTracingMode::Disabled && !self.context.device_features.ray_tracing {
+            return AppResult::Err(AppError::new(AppErrorType::RayTracingUnsupported));
         }
 
-        let device_features = vk::PhysicalDeviceFeatures::default().sampler_anisotropy(true);
-        let device_extensions = DEVICE_EXTENSIONS
-            .iter()
-            .map(|&ext| ext.as_ptr())
-            .collect::<Vec<*const i8>>();
-
-        let create_info = vk::DeviceCreateInfo {
-            queue_create_info_count: queue_create_infos.len() as u32,
-            p_queue_create_infos: queue_create_infos.as_ptr(),
-            enabled_extension_count: device_extensions.len() as u32,
-            pp_enabled_extension_names: device_extensions.as_ptr(),
-            p_enabled_features: &device_features as *const _,
-            ..Default::default()
-        };
+        match &mut self.ray_tracing {
+            Some(ray_tracing) => ray_tracing.set_mode(mode),
+            None if mode == RayTracingMode::Disabled => (),
+            None => {
+                self.create_scene_accel()?;
+                self.ray_tracing = Some(RayTracing::new(
+                    &self.context.instance,
+                    &self.context.device,
+                    self.context.physical_device,
+                    self.pipeline.renderpass,
+                    self.pipeline.samples,
+                    self.scene_accel.as_ref().unwrap(),
+                    mode,
+                )?);
+                self.event_log.push(RendererEvent::PipelineBuilt {
+                    name: String::from("ray tracing"),
+                });
+                self.create_scene_framebuffers()?;
+            }
+        }
 
-        // Safety: The Device is destroyed befor the parent Instance, see Application::cleanup()
-        let device = unsafe { instance.create_device(physical_device, &create_info, None)? };
+        Ok(())
+    }
 
-        let graphics_queue =
-            unsafe { device.get_device_queue(indices.graphics_family.unwrap(), 0) };
-        let present_queue = unsafe { device.get_device_queue(indices.present_family.unwrap(), 0) };
+    pub fn ray_tracing_mode(&self) -> RayTracingMode {
+        self.ray_tracing
+            .as_ref()
+            .map_or(RayTracingMode::Disabled, RayTracing::mode)
+    }
 
-        Ok((device, graphics_queue, present_queue))
+    fn active_ray_tracing(&self) -> Option<&RayTracing> {
+        self.ray_tracing
+            .as_ref()
+            .filter(|ray_tracing| ray_tracing.mode() != RayTracingMode::Disabled)
     }
 
-    fn create_swapchain(
-        instance: &Instance,
-        device: &Device,
-        physical_device: vk::PhysicalDevice,
-        surface: &SurfaceHodlder,
-        indices: QueueFamilyIndice,
-    ) -> AppResult<SwapChainHolder> {
-        let swapchain_support = Self::query_swapchain_support(physical_device, surface)?;
-
-        let surface_format = Self::choose_swap_surface_format(&swapchain_support.formats);
-        let present_mode = Self::choose_swap_present_mode(&swapchain_support.present_modes);
-        let extent = Self::choose_swap_extent(swapchain_support.capabilities);
-
-        let mut image_count = swapchain_support.capabilities.min_image_count + 1;
-        if swapchain_support.capabilities.max_image_count != 0 {
-            image_count = image_count.clamp(
-                swapchain_support.capabilities.min_image_count,
-                swapchain_support.capabilities.min_image_count,
-            );
+    /// Traces the shadows of the PBR materials with ray queries against the meshes of the frame
+    /// instead of sampling the shadow cubemaps, for pixel-accurate hard shadows. The directional
+    /// lights cast shadows too, along with the point lights having a shadow range. Only the
+    /// meshes created or reloaded while it is enabled cast ray traced shadows, at most
+    /// `MAX_RAY_TRACED_INSTANCES` draw items. Fails to enable if the device doesn't support
+    /// `VK_KHR_ray_query`, see `DeviceFeatures`. Disabled by default.
+    pub fn set_ray_query_shadows(&mut self, enabled: bool) -> AppResult<()> {
+        if enabled && !self.context.device_features.ray_query {
+            return AppResult::Err(AppError::new(AppErrorType::RayQueryUnsupported));
         }
 
-        let mut create_info = vk::SwapchainCreateInfoKHR {
-            surface: surface.surface,
-            min_image_count: image_count,
-            image_format: surface_format.format,
-            image_color_space: surface_format.color_space,
-            image_extent: extent,
-            image_array_layers: 1,
-            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
-            image_sharing_mode: vk::SharingMode::EXCLUSIVE,
-            pre_transform: swapchain_support.capabilities.current_transform,
-            composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
-            present_mode,
-            clipped: true.into(),
-            ..Default::default()
-        };
-
-        let graphics = indices.graphics_family.unwrap();
-        let present = indices.present_family.unwrap();
-        let indices = [graphics, present];
-        if graphics != present {
-            create_info.image_sharing_mode = vk::SharingMode::CONCURRENT;
-            create_info.queue_family_index_count = indices.len() as u32;
-            create_info.p_queue_family_indices = &indices as *const _;
+        if enabled {
+            self.create_scene_accel()?;
         }
+        self.ray_query_shadows = enabled;
+        self.update_pbr_ray_queries()
+    }
 
-        let swapchain_ext = swapchain::Device::new(instance, device);
-        let swapchain = unsafe { swapchain_ext.create_swapchain(&create_info, None)? };
+    pub fn ray_query_shadows(&self) -> bool {
+        self.ray_query_shadows
+    }
 
-        let swapchain_images = unsafe { swapchain_ext.get_swapchain_images(swapchain)? };
-        let swapchain_image_views =
-            Self::create_image_views(device, &swapchain_images, surface_format.format)?;
+    // Switches the PBR pipeline to the ray traced shadows or back to the shadow cubemaps
+    fn update_pbr_ray_queries(&mut self) -> AppResult<()> {
+        let Some(pbr_pipeline) = &mut self.pbr_pipeline else {
+            return Ok(());
+        };
 
-        Ok(SwapChainHolder {
-            swapchain_ext,
-            swapchain,
-            swapchain_images,
-            swapchain_image_views,
-            image_format: surface_format.format,
-            extent,
-        })
+        let tlases = self.scene_accel.as_ref().map(|scene_accel| {
+            (0..self.frames_in_flight)
+                .map(|frame| scene_accel.tlas(frame))
+                .collect::<Vec<_>>()
+        });
+        pbr_pipeline.set_ray_query_shadows(
+            &self.context.device,
+            self.pipeline.renderpass,
+            self.pipeline.descriptor_set_layout,
+            tlases.as_deref().filter(|_| self.ray_query_shadows),
+        )
     }
 
-    /// Chooses the best surface format avaible for the swapchains.
-    ///
-    /// # Panic
-    /// Panics if `avaible_format` is empty.
-    fn choose_swap_surface_format(
-        avaible_formats: &Vec<vk::SurfaceFormatKHR>,
-    ) -> vk::SurfaceFormatKHR {
-        assert!(!avaible_formats.is_empty());
-
-        for &format in avaible_formats {
-            if format.format == vk::Format::B8G8R8A8_SRGB
-                && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-            {
-                return format;
-            }
+    fn create_scene_accel(&mut self) -> AppResult<()> {
+        if self.scene_accel.is_none() {
+            self.scene_accel = Some(SceneAccel::new(
+                &self.context.instance,
+                &self.context.device,
+                self.context.physical_device,
+                self.frames_in_flight,
+            )?);
         }
 
-        avaible_formats[0]
+        Ok(())
     }
 
-    fn choose_swap_present_mode(
-        avaible_present_modes: &Vec<vk::PresentModeKHR>,
-    ) -> vk::PresentModeKHR {
-        for &present_mode in avaible_present_modes {
-            if present_mode == vk::PresentModeKHR::MAILBOX {
-                return present_mode;
-            }
+    // Whether the acceleration structures of the scene are traced by the ray tracing or the
+    // shadows
+    fn accel_in_use(&self) -> bool {
+        self.active_ray_tracing().is_some() || self.ray_query_shadows
+    }
+
+    // Builds the acceleration structure of a static mesh while the ray tracing or the ray traced
+    // shadows are enabled, or forgets its previous one otherwise
+    fn update_blas(
+        &mut self,
+        handle: MeshHandle,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> AppResult<()> {
+        let in_use = self.accel_in_use();
+        let Some(scene_accel) = &mut self.scene_accel else {
+            return Ok(());
+        };
+
+        let previous = if in_use {
+            scene_accel.add_mesh(
+                &self.context.instance,
+                &self.context.device,
+                self.context.graphics_queue,
+                self.context.physical_device,
+                self.resources.command_pool,
+                &self.resources.sync_pool,
+                handle.0,
+                vertices,
+                indices,
+            )?
+        } else {
+            scene_accel.remove_mesh(handle.0)
+        };
+        for garbage in previous {
+            self.resources
+                .garbage_collector
+                .retire(garbage, self.frames_in_flight);
         }
 
-        vk::PresentModeKHR::FIFO
+        Ok(())
     }
 
-    fn choose_swap_extent(capabilities: vk::SurfaceCapabilitiesKHR) -> vk::Extent2D {
-        if capabilities.current_extent.width != std::u32::MAX {
-            return capabilities.current_extent;
+    fn retire_meshlets(&mut self, buffers: [BufferHolder; 4]) {
+        for buffer in buffers {
+            self.resources.garbage_collector.retire(
+                Garbage::Buffer(buffer.buffer, buffer.memory),
+                self.frames_in_flight,
+            );
         }
-
-        todo!()
     }
 
-    fn create_image_views(
-        device: &Device,
-        images: &Vec<vk::Image>,
-        image_format: vk::Format,
-    ) -> AppResult<Vec<vk::ImageView>> {
-        let mut image_views = Vec::with_capacity(images.len());
+    /// Enables or disables the hot reloading of the textures loaded by `load_texture_handles`, the
+    /// meshes loaded by `load_mesh` and the PBR materials created by `create_pbr_material` or
+    /// `load_gltf_materials`. While enabled, their files are checked every `HOT_RELOAD_INTERVAL`
+    /// and the changed ones are loaded and uploaded again, keeping their handles. The PBR
+    /// materials wait for the device to be idle, their sets being shared by the frames. The
+    /// external images of a glTF file aren't watched, only the file itself.
+    pub fn set_hot_reload(&mut self, enabled: bool) {
+        self.asset_watcher.set_enabled(enabled);
+    }
 
-        for &image in images {
-            image_views.push(Self::create_image_view(device, image, image_format)?);
+    // Reloads the assets whose file changed, the ones failing to load keeping their previous
+    // version and being reported to the event log
+    fn reload_changed_assets(&mut self) {
+        for (asset, path) in self.asset_watcher.poll(Instant::now()) {
+            let result = match asset {
+                WatchedAsset::Texture(texture) => self.reload_texture(texture, &path),
+                WatchedAsset::Mesh(mesh) => self.reload_mesh(mesh),
+                WatchedAsset::PbrMaterials { .. } => self.reload_pbr_materials(asset),
+            };
+            if let Err(err) = result {
+                self.event_log.push(RendererEvent::Error {
+                    message: format!("{:?}: {}", path, err),
+                });
+            }
         }
-
-        Ok(image_views)
     }
 
-    fn create_graphics_pipeline(
-        device: &Device,
-        swapchain: &SwapChainHolder,
-    ) -> AppResult<GraphicsPipelineHolder> {
-        let renderpass = Self::create_render_pass(device, swapchain)?;
+    fn reload_texture(&mut self, texture: TextureHandle, path: &Path) -> AppResult<()> {
+        if self.resources.texture_manager.get(texture).is_none() {
+            return Ok(());
+        }
 
-        let vert_shader_u8 = include_bytes!("spirv/vertex.spv");
-        let frag_shader_u8 = include_bytes!("spirv/fragment.spv");
+        let mut decoded = texture_loader::decode_textures(&[path])?;
+        let replacement = Self::upload_textures(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            &mut decoded,
+        )?
+        .pop()
+        .unwrap();
+        let view = replacement.view;
+        let size = unsafe {
+            self.context
+                .device
+                .get_image_memory_requirements(replacement.image.image)
+                .size
+        };
 
-        let vert_shader_code = Self::make_spirv_raw(vert_shader_u8);
-        let frag_shader_code = Self::make_spirv_raw(frag_shader_u8);
+        let previous = self
+            .resources
+            .texture_manager
+            .replace(texture, replacement)
+            .unwrap();
+        self.event_log.push(RendererEvent::ResourceDestroyed {
+            name: format!("texture {}", texture.0),
+            size: unsafe {
+                self.context
+                    .device
+                    .get_image_memory_requirements(previous.image.image)
+                    .size
+            },
+        });
+        self.event_log.push(RendererEvent::ResourceCreated {
+            name: format!("texture {}", texture.0),
+            size,
+        });
+        self.replace_material_views(previous.view, view, view);
+        self.resources
+            .garbage_collector
+            .retire(Garbage::Texture(previous), self.frames_in_flight);
 
-        let vert_module = Self::create_shader_module(device, &vert_shader_code)?;
-        let frag_module = Self::create_shader_module(device, &frag_shader_code)?;
+        Ok(())
+    }
 
-        let entry_point = CString::new("main").unwrap();
-        let vert_shader_stage_info = vk::PipelineShaderStageCreateInfo {
-            stage: vk::ShaderStageFlags::VERTEX,
-            module: vert_module,
-            p_name: entry_point.as_ptr(),
-            ..Default::default()
-        };
-        let frag_shader_stage_info = vk::PipelineShaderStageCreateInfo {
-            stage: vk::ShaderStageFlags::FRAGMENT,
-            module: frag_module,
-            p_name: entry_point.as_ptr(),
-            ..Default::default()
+    fn reload_mesh(&mut self, handle: MeshHandle) -> AppResult<()> {
+        let Some(data) = self.asset_watcher.import_mesh(handle) else {
+            return Ok(());
         };
+        let data = data?;
+        let mesh = MeshStorage::Static(self.upload_mesh(&data.vertices, &data.indices)?);
 
-        let shader_stages_infos = [vert_shader_stage_info, frag_shader_stage_info];
-
-        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-        let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo {
-            dynamic_state_count: dynamic_states.len() as u32,
-            p_dynamic_states: &dynamic_states as *const _,
-            ..Default::default()
-        };
+        for (name, size) in Self::mesh_sizes(&self.context.device, handle.0, &mesh) {
+            self.event_log
+                .push(RendererEvent::ResourceCreated { name, size });
+        }
+        if let Some(previous) = self.resources.meshes[handle.0].replace(mesh) {
+            self.retire_mesh(handle, previous);
+        }
+        self.update_meshlets(handle, &data.vertices, &data.indices)?;
+        self.update_blas(handle, &data.vertices, &data.indices)
+    }
 
-        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo {
-            vertex_binding_description_count: Vertex::BINDING_DESCRIPTIONS.len() as u32,
-            p_vertex_binding_descriptions: Vertex::BINDING_DESCRIPTIONS.as_ptr(),
-            vertex_attribute_description_count: Vertex::ATTRIBUTE_DESCRIPTIONS.len() as u32,
-            p_vertex_attribute_descriptions: Vertex::ATTRIBUTE_DESCRIPTIONS.as_ptr(),
-            ..Default::default()
+    fn reload_pbr_materials(&mut self, asset: WatchedAsset) -> AppResult<()> {
+        let WatchedAsset::PbrMaterials { first, count } = asset else {
+            return Ok(());
         };
-
-        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo {
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-            primitive_restart_enable: false.into(),
-            ..Default::default()
+        let Some(materials) = self.asset_watcher.import_pbr_materials(asset) else {
+            return Ok(());
         };
+        let materials = materials?;
+        // The sets of the PBR materials are shared by the frames in flight
+        unsafe {
+            self.context.device.device_wait_idle()?;
+        }
 
-        let viewport_state = vk::PipelineViewportStateCreateInfo {
-            viewport_count: 1,
-            scissor_count: 1,
-            ..Default::default()
-        };
+        for (handle, material) in (first.0..first.0 + count).zip(materials) {
+            let MaterialKind::Pbr { index, .. } = self.materials[handle].kind else {
+                continue;
+            };
+            let pbr_pipeline = self.pbr_pipeline.as_mut().unwrap();
+            let previous_size = pbr_pipeline.memory_size(&self.context.device, index);
+            let (albedo_view, previous) = pbr_pipeline.replace_material(
+                &self.context.instance,
+                &self.context.device,
+                self.context.graphics_queue,
+                self.context.physical_device,
+                self.resources.command_pool,
+                &self.resources.sync_pool,
+                index,
+                material,
+            )?;
 
-        let rasterizer = vk::PipelineRasterizationStateCreateInfo {
-            depth_clamp_enable: false.into(),
-            rasterizer_discard_enable: false.into(),
-            polygon_mode: vk::PolygonMode::FILL,
-            cull_mode: vk::CullModeFlags::BACK,
-            front_face: vk::FrontFace::CLOCKWISE,
-            depth_bias_enable: false.into(),
-            depth_bias_constant_factor: 0.0,
-            depth_bias_clamp: 0.0,
-            depth_bias_slope_factor: 0.0,
-            line_width: 1.0,
-            ..Default::default()
-        };
+            self.event_log.push(RendererEvent::ResourceDestroyed {
+                name: format!("pbr material {}", index),
+                size: previous_size,
+            });
+            self.event_log.push(RendererEvent::ResourceCreated {
+                name: format!("pbr material {}", index),
+                size: pbr_pipeline.memory_size(&self.context.device, index),
+            });
+            self.materials[handle].texture_view = albedo_view;
+            self.materials[handle].kind = MaterialKind::Pbr {
+                index,
+                blended: pbr_pipeline.is_blended(index),
+            };
+            for written_material in self
+                .frames
+                .iter_mut()
+                .flat_map(|frame| frame.descriptor_materials.iter_mut())
+            {
+                if *written_material == Some(MaterialHandle(handle)) {
+                    *written_material = None;
+                }
+            }
+            for map in previous {
+                self.resources
+                    .garbage_collector
+                    .retire(Garbage::Texture(map), self.frames_in_flight);
+            }
+        }
 
-        let multisampling = vk::PipelineMultisampleStateCreateInfo {
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
-            sample_shading_enable: false.into(),
-            min_sample_shading: 1.0,
-            alpha_to_coverage_enable: false.into(),
-            alpha_to_one_enable: false.into(),
-            ..Default::default()
-        };
+        Ok(())
+    }
 
-        let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
-            blend_enable: false.into(),
-            color_write_mask: vk::ColorComponentFlags::RGBA,
-            ..Default::default()
-        };
+    /// Changes the filtering of the material textures from the next frame, clamping the anisotropy
+    /// and the mip LOD bias to the device limits. The samplers are cached, and every frame in
+    /// flight rewrites its descriptor sets before using the new one. The PBR materials, whose
+    /// sets are shared by the frames, wait for the device to be idle.
+    pub fn set_texture_filtering(&mut self, filtering: TextureFiltering) -> AppResult<()> {
+        let filtering = self.resources.sampler_cache.clamp(filtering);
+        let sampler = self
+            .resources
+            .sampler_cache
+            .get(&self.context.device, filtering)?;
+        self.texture_filtering = filtering;
+        let previous = std::mem::replace(&mut self.texture_sampler, sampler);
+        if sampler == previous {
+            return Ok(());
+        }
 
-        let color_blend_attachments = [color_blend_attachment];
-        let color_blending = vk::PipelineColorBlendStateCreateInfo {
-            logic_op_enable: false.into(),
-            logic_op: vk::LogicOp::COPY,
-            attachment_count: color_blend_attachments.len() as u32,
-            p_attachments: &color_blend_attachment as *const _,
-            blend_constants: [0.0; 4],
-            ..Default::default()
-        };
+        for material in self.materials.iter_mut() {
+            if material.sampler == previous {
+                material.sampler = sampler;
+            }
+        }
+        for written_material in self
+            .frames
+            .iter_mut()
+            .flat_map(|frame| frame.descriptor_materials.iter_mut())
+        {
+            *written_material = None;
+        }
+        self.shader_materials.replace_sampler(previous, sampler);
+        if let Some(pbr_pipeline) = &mut self.pbr_pipeline {
+            unsafe {
+                self.context.device.device_wait_idle()?;
+            }
+            pbr_pipeline.set_sampler(&self.context.device, sampler);
+        }
+        if let Some(terrain) = &mut self.terrain {
+            unsafe {
+                self.context.device.device_wait_idle()?;
+            }
+            terrain.set_sampler(&self.context.device, sampler);
+        }
 
-        let descriptor_set_layout = Self::create_descriptor_set_layout(device)?;
-        let descriptor_set_layouts = [descriptor_set_layout];
-        let push_constant_ranges = [];
-        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
-            set_layout_count: descriptor_set_layouts.len() as u32,
-            p_set_layouts: &descriptor_set_layouts as *const _,
-            push_constant_range_count: push_constant_ranges.len() as u32,
-            p_push_constant_ranges: &push_constant_ranges as *const _,
-            ..Default::default()
-        };
+        Ok(())
+    }
 
-        let pipeline_layout =
-            unsafe { device.create_pipeline_layout(&pipeline_layout_info, None)? };
-
-        let pipeline_info = vk::GraphicsPipelineCreateInfo {
-            stage_count: shader_stages_infos.len() as u32,
-            p_stages: shader_stages_infos.as_ptr(),
-            p_vertex_input_state: &vertex_input_info as *const _,
-            p_input_assembly_state: &input_assembly_info as *const _,
-            p_viewport_state: &viewport_state as *const _,
-            p_rasterization_state: &rasterizer as *const _,
-            p_multisample_state: &multisampling as *const _,
-            p_color_blend_state: &color_blending as *const _,
-            p_dynamic_state: &dynamic_state_create_info as *const _,
-            layout: pipeline_layout,
-            render_pass: renderpass,
-            subpass: 0,
-            base_pipeline_index: -1,
-            ..Default::default()
-        };
+    /// Filtering of the material textures, as clamped to the device limits
+    pub fn texture_filtering(&self) -> TextureFiltering {
+        self.texture_filtering
+    }
 
-        let pipelines_infos = [pipeline_info];
-        let pipeline = unsafe {
-            device
-                .create_graphics_pipelines(vk::PipelineCache::null(), &pipelines_infos, None)
-                .or_else(|r| AppResult::Err(r.1.into()))?[0]
-        };
+    /// Sets the Vulkan objects the garbage collector destroys at most every frame, the released
+    /// textures and meshes waiting for the following frames beyond it. Defaults to
+    /// `DEFAULT_GC_BUDGET`.
+    pub fn set_gc_budget(&mut self, budget: usize) {
+        self.resources.garbage_collector.set_budget(budget);
+    }
 
-        unsafe {
-            device.destroy_shader_module(vert_module, None);
-            device.destroy_shader_module(frag_module, None);
-        }
+    /// Enables the checks of the writes to the per frame resources mapped by the CPU, panicking
+    /// when a frame in flight or a frame other than the one being prepared is written. Meant for
+    /// debugging, it waits on nothing and only costs a fence status query per frame.
+    pub fn set_frame_validation(&mut self, enabled: bool) {
+        self.frame_guard.set_enabled(enabled);
+    }
 
-        Ok(GraphicsPipelineHolder {
-            renderpass,
-            pipeline,
-            pipeline_layout,
-            descriptor_set_layout,
-        })
+    /// Shows or hides the debug HUD in the top left corner: the frame rate, the CPU and GPU
+    /// frame times, the draw calls and the swapchain properties. Hidden by default, the GPU
+    /// timestamps are only written while it is shown.
+    pub fn set_hud_visible(&mut self, visible: bool) {
+        self.debug_hud.set_visible(visible);
     }
 
-    fn create_render_pass(
-        device: &Device,
-        swapchain: &SwapChainHolder,
-    ) -> AppResult<vk::RenderPass> {
-        let color_attachment = [vk::AttachmentDescription {
-            format: swapchain.image_format,
-            samples: vk::SampleCountFlags::TYPE_1,
-            load_op: vk::AttachmentLoadOp::CLEAR,
-            store_op: vk::AttachmentStoreOp::STORE,
-            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
-            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
-            initial_layout: vk::ImageLayout::UNDEFINED,
-            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-            ..Default::default()
-        }];
+    pub fn hud_visible(&self) -> bool {
+        self.debug_hud.is_visible()
+    }
 
-        let color_attachment_refs = [vk::AttachmentReference {
-            attachment: 0,
-            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        }];
+    /// Timing of the last frame drawn, e.g. to drive animations or cameras. The times are zero
+    /// before the first frame.
+    pub fn frame_timing(&self) -> FrameTiming {
+        self.frame_clock.timing()
+    }
 
-        let subpasses = [vk::SubpassDescription {
-            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
-            color_attachment_count: color_attachment_refs.len() as u32,
-            p_color_attachments: color_attachment_refs.as_ptr(),
-            ..Default::default()
-        }];
+    /// Frame statistics of the debug HUD, refreshed every `HUD_REFRESH_INTERVAL`. The GPU frame
+    /// time is only measured while the HUD is shown.
+    pub fn frame_stats(&self) -> FrameStats {
+        self.debug_hud.stats()
+    }
 
-        let dependencies = [vk::SubpassDependency {
-            src_subpass: vk::SUBPASS_EXTERNAL,
-            dst_subpass: 0,
-            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            src_access_mask: vk::AccessFlags::empty(),
-            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-            ..Default::default()
-        }];
+    /// Loads the TrueType or OpenType font at `path` for the text drawn with
+    /// `Application::draw_text` and `Application::draw_label`, replacing the previous one. Its
+    /// glyphs are baked into a signed distance field atlas at `SDF_BAKE_SIZE`, so the text stays
+    /// crisp at any size.
+    pub fn load_font<P: AsRef<Path>>(&mut self, path: P) -> AppResult<()> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let (font, atlas) =
+            SdfFont::bake(&bytes).map_err(|reason| text::font_error(path, reason))?;
+
+        let atlas = Self::upload_textures(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            &mut [DecodedTexture::from_rgba(vec![atlas])],
+        )?
+        .pop()
+        .unwrap();
+        let sampler = self
+            .resources
+            .sampler_cache
+            .get(&self.context.device, TextureFiltering::default())?;
+
+        unsafe { self.context.device.device_wait_idle()? };
+        self.text_renderer
+            .set_font(&self.context.device, font, atlas, sampler);
 
-        let renderpass_info = vk::RenderPassCreateInfo {
-            attachment_count: color_attachment.len() as u32,
-            p_attachments: color_attachment.as_ptr(),
-            subpass_count: subpasses.len() as u32,
-            p_subpasses: subpasses.as_ptr(),
-            dependency_count: dependencies.len() as u32,
-            p_dependencies: dependencies.as_ptr(),
-            ..Default::default()
-        };
+        Ok(())
+    }
 
-        unsafe { Ok(device.create_render_pass(&renderpass_info, None)?) }
+    /// Draws `text` over the next frame, above the scene and below the debug HUD, with its top
+    /// left corner at `position` in pixels. `size` is the pixel size of the font, and the lines
+    /// are broken at `\n`. Does nothing until a font is loaded with `Application::load_font`.
+    pub fn draw_text(&mut self, text: &str, position: Point2, size: f32, color: Vec4) {
+        self.text_renderer
+            .queue_text(text, position.into(), size, color.into());
     }
 
-    // Code taken from https://github.com/gfx-rs/wgpu
-    fn make_spirv_raw(bytes: &[u8]) -> Vec<u32> {
-        let mut words = vec![0u32; bytes.len() / std::mem::size_of::<u32>()];
-        unsafe {
-            std::ptr::copy_nonoverlapping(
-                bytes.as_ptr(),
-                words.as_mut_ptr() as *mut u8,
-                bytes.len(),
-            );
-        }
+    /// Draws `text` over the next frame, centered above the projection of `position` on the
+    /// screen. Left out when `position` is behind the camera. See `Application::draw_text`.
+    pub fn draw_label(&mut self, text: &str, position: Point3, size: f32, color: Vec4) {
+        self.text_renderer
+            .queue_label(text, position, size, color.into());
+    }
 
-        words
+    /// Width and height in pixels of `text` drawn at the pixel size `size`, e.g. to lay it out.
+    /// `None` until a font is loaded.
+    pub fn measure_text(&self, text: &str, size: f32) -> Option<Vec2> {
+        self.text_renderer.measure(text, size).map(Vec2::from)
     }
 
-    fn create_shader_module(device: &Device, bytes: &[u32]) -> AppResult<vk::ShaderModule> {
-        let create_info = vk::ShaderModuleCreateInfo {
-            code_size: bytes.len() * 4,
-            p_code: bytes.as_ptr(),
-            ..Default::default()
-        };
+    /// Draws a line from `from` to `to` in the scene over the next frame. The debug lines are
+    /// drawn over the scene, without depth test, and below the text.
+    pub fn debug_draw_line(&mut self, from: Point3, to: Point3, color: Vec4) {
+        self.debug_draw.line(from, to, color.into());
+    }
 
-        unsafe { Ok(device.create_shader_module(&create_info, None)?) }
+    /// Draws the edges of `bounds` moved by `transform` over the next frame, e.g. the bounds
+    /// of a pickable object. See `Application::debug_draw_line`.
+    pub fn debug_draw_box(&mut self, bounds: Aabb, transform: Mat4, color: Vec4) {
+        self.debug_draw.aabb(bounds, transform, color.into());
     }
 
-    fn create_descriptor_set_layout(device: &Device) -> AppResult<vk::DescriptorSetLayout> {
-        let ubo_layout_binding = vk::DescriptorSetLayoutBinding {
-            binding: 0,
-            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: 1,
-            stage_flags: vk::ShaderStageFlags::VERTEX,
-            ..Default::default()
-        };
+    /// Draws a circle around each axis of the sphere over the next frame, e.g. the range of a
+    /// light. See `Application::debug_draw_line`.
+    pub fn debug_draw_sphere(&mut self, center: Point3, radius: f32, color: Vec4) {
+        self.debug_draw.sphere(center, radius, color.into());
+    }
 
-        let sampler_layout_binding = vk::DescriptorSetLayoutBinding {
-            binding: 1,
-            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: 1,
-            stage_flags: vk::ShaderStageFlags::FRAGMENT,
-            ..Default::default()
-        };
+    /// Draws the X, Y and Z axes of `transform`, `size` long, in red, green and blue over the
+    /// next frame. See `Application::debug_draw_line`.
+    pub fn debug_draw_axes(&mut self, transform: Mat4, size: f32) {
+        self.debug_draw.axes(transform, size);
+    }
 
-        let bindings = [ubo_layout_binding, sampler_layout_binding];
-        let layout_info = vk::DescriptorSetLayoutCreateInfo {
-            binding_count: bindings.len() as u32,
-            p_bindings: bindings.as_ptr(),
-            ..Default::default()
-        };
+    /// Adds an emitter whose particles are simulated on the CPU and drawn in world space after
+    /// the draw items, adding their color to the scene. The particles don't move while the
+    /// animation is paused.
+    pub fn add_particle_emitter(&mut self, emitter: ParticleEmitter) -> ParticleEmitterId {
+        self.particles.add(emitter)
+    }
 
-        unsafe { Ok(device.create_descriptor_set_layout(&layout_info, None)?) }
+    /// Removes the emitter along with its particles
+    pub fn remove_particle_emitter(&mut self, id: ParticleEmitterId) -> Option<ParticleEmitter> {
+        self.particles.remove(id)
     }
 
-    fn create_frame_buffers(
-        device: &Device,
-        pipeline: &GraphicsPipelineHolder,
-        swapchain: &SwapChainHolder,
-    ) -> AppResult<Vec<vk::Framebuffer>> {
-        let mut frame_buffers = vec![];
-        for &attachment in swapchain.swapchain_image_views.iter() {
-            let attachments = [attachment];
-            let frame_buffer_info = vk::FramebufferCreateInfo {
-                render_pass: pipeline.renderpass,
-                attachment_count: attachments.len() as u32,
-                p_attachments: attachments.as_ptr(),
-                width: swapchain.extent.width,
-                height: swapchain.extent.height,
-                layers: 1,
-                ..Default::default()
-            };
-            frame_buffers.push(unsafe { device.create_framebuffer(&frame_buffer_info, None)? });
-        }
+    /// Returns the emitter to update, e.g. to move it. Its lifetime, acceleration, size and
+    /// colors apply to the particles already emitted.
+    pub fn particle_emitter_mut(&mut self, id: ParticleEmitterId) -> Option<&mut ParticleEmitter> {
+        self.particles.get_mut(id)
+    }
 
-        Ok(frame_buffers)
+    /// Particles alive across every emitter, at most `MAX_PARTICLES`
+    pub fn particle_count(&self) -> usize {
+        self.particles.particle_count()
     }
 
-    fn create_command_pool(
-        device: &Device,
-        queue_families: QueueFamilyIndice,
-    ) -> AppResult<vk::CommandPool> {
-        let pool_info = vk::CommandPoolCreateInfo {
-            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
-            queue_family_index: queue_families.graphics_family.unwrap(),
-            ..Default::default()
-        };
+    /// Plays the animation on its draw items, replacing their transforms every frame until it is
+    /// removed. Like the particles, the animations don't advance while paused.
+    pub fn add_animation(&mut self, animation: Animation) -> AnimationId {
+        self.animations.add(animation)
+    }
 
-        unsafe { Ok(device.create_command_pool(&pool_info, None)?) }
+    /// Stops the animation, its items keeping their last pose
+    pub fn remove_animation(&mut self, id: AnimationId) -> Option<Animation> {
+        self.animations.remove(id)
     }
 
-    fn create_texture_image_view(device: &Device, image: vk::Image) -> AppResult<vk::ImageView> {
-        Self::create_image_view(device, image, vk::Format::R8G8B8A8_SRGB)
+    /// Returns the animation to control, e.g. to switch clips
+    pub fn animation_mut(&mut self, id: AnimationId) -> Option<&mut Animation> {
+        self.animations.get_mut(id)
     }
 
-    fn create_image_view(
-        device: &Device,
-        image: vk::Image,
-        format: vk::Format,
-    ) -> AppResult<vk::ImageView> {
-        let subresource_range = vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: 1,
-            base_array_layer: 0,
-            layer_count: 1,
-        };
+    /// Adds an emitter whose particles are simulated by a compute pass and drawn with an indirect
+    /// draw, with room for `capacity` particles alive at once. The particles stay on the GPU,
+    /// they aren't counted by `particle_count`.
+    pub fn add_gpu_particle_emitter(
+        &mut self,
+        emitter: ParticleEmitter,
+        capacity: u32,
+    ) -> AppResult<GpuParticleEmitterId> {
+        if self.gpu_particles.is_none() {
+            self.gpu_particles = Some(GpuParticles::new(
+                &self.context.device,
+                self.pipeline.renderpass,
+                self.pipeline.samples,
+            )?);
+        }
+        self.gpu_particles.as_mut().unwrap().add(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            emitter,
+            capacity,
+        )
+    }
 
-        let create_info = vk::ImageViewCreateInfo {
-            flags: vk::ImageViewCreateFlags::empty(),
-            image,
-            view_type: vk::ImageViewType::TYPE_2D,
-            format,
-            subresource_range,
-            ..Default::default()
+    /// Removes the GPU emitter along with its particles, waiting for the device to be idle
+    pub fn remove_gpu_particle_emitter(
+        &mut self,
+        id: GpuParticleEmitterId,
+    ) -> AppResult<Option<ParticleEmitter>> {
+        let Some(gpu_particles) = &mut self.gpu_particles else {
+            return Ok(None);
         };
 
-        unsafe { Ok(device.create_image_view(&create_info, None)?) }
+        unsafe { self.context.device.device_wait_idle()? };
+        Ok(gpu_particles.remove(&self.context.device, id))
     }
 
-    fn create_texture_sampler(
-        instance: &Instance,
-        device: &Device,
-        physical_device: vk::PhysicalDevice,
-    ) -> AppResult<vk::Sampler> {
-        let proprieties = unsafe { instance.get_physical_device_properties(physical_device) };
-        let create_info = vk::SamplerCreateInfo {
-            mag_filter: vk::Filter::LINEAR,
-            min_filter: vk::Filter::LINEAR,
-            address_mode_u: vk::SamplerAddressMode::REPEAT,
-            address_mode_v: vk::SamplerAddressMode::REPEAT,
-            address_mode_w: vk::SamplerAddressMode::REPEAT,
-            anisotropy_enable: vk::TRUE,
-            max_anisotropy: proprieties.limits.max_sampler_anisotropy,
-            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
-            unnormalized_coordinates: vk::FALSE,
-            compare_enable: vk::FALSE,
-            compare_op: vk::CompareOp::ALWAYS,
-            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
-            mip_lod_bias: 0.0,
-            min_lod: 0.0,
-            max_lod: 0.0,
-            ..Default::default()
-        };
+    /// Returns the GPU emitter to update, see `particle_emitter_mut`
+    pub fn gpu_particle_emitter_mut(
+        &mut self,
+        id: GpuParticleEmitterId,
+    ) -> Option<&mut ParticleEmitter> {
+        self.gpu_particles.as_mut()?.get_mut(id)
+    }
 
-        Ok(unsafe { device.create_sampler(&create_info, None)? })
+    /// Released textures and meshes the garbage collector hasn't destroyed yet
+    pub fn pending_garbage(&self) -> usize {
+        self.resources.garbage_collector.pending()
     }
 
-    fn create_vertex_buffer(
-        instance: &Instance,
-        device: &Device,
-        graphic_queue: vk::Queue,
-        physical_device: vk::PhysicalDevice,
-        vertex_data: &[Vertex],
-        command_pool: vk::CommandPool,
-    ) -> AppResult<BufferHolder> {
-        let vertex_buffer_usage =
-            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER;
-        let vertex_buffer_mem_proprieties = vk::MemoryPropertyFlags::DEVICE_LOCAL;
-        Self::create_buffer_with_data(
-            instance,
-            device,
-            graphic_queue,
-            physical_device,
-            vertex_data,
-            vertex_buffer_usage,
-            vertex_buffer_mem_proprieties,
-            command_pool,
-        )
+    /// Loads the images at `paths` as textures with a full mip chain, returning a material
+    /// sampling each of them. See `load_texture_handles`.
+    pub fn load_textures<P: AsRef<Path> + Sync>(
+        &mut self,
+        paths: &[P],
+    ) -> AppResult<Vec<MaterialHandle>> {
+        let textures = self.load_texture_handles(paths)?;
+        Ok(textures
+            .into_iter()
+            .map(|texture| self.create_material(texture).unwrap())
+            .collect())
     }
 
-    fn create_index_buffer(
-        instance: &Instance,
-        device: &Device,
-        graphic_queue: vk::Queue,
-        physical_device: vk::PhysicalDevice,
-        index_data: &[u16],
-        command_pool: vk::CommandPool,
-    ) -> AppResult<BufferHolder> {
-        let index_buffer_usage =
-            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER;
-        let index_buffer_mem_proprieties = vk::MemoryPropertyFlags::DEVICE_LOCAL;
-        Self::create_buffer_with_data(
-            instance,
-            device,
-            graphic_queue,
-            physical_device,
-            index_data,
-            index_buffer_usage,
-            index_buffer_mem_proprieties,
-            command_pool,
-        )
+    /// Loads the image at `path` as a texture with a full mip chain, see `load_texture_handles`
+    pub fn load_texture<P: AsRef<Path> + Sync>(&mut self, path: P) -> AppResult<TextureHandle> {
+        Ok(self.load_texture_handles(&[path])?.pop().unwrap())
     }
 
-    fn create_uniform_buffers(
-        instance: &Instance,
-        device: &Device,
-        physical_device: vk::PhysicalDevice,
-        max_frame_in_flight: usize,
-    ) -> AppResult<Vec<MemoryMappedBuffer>> {
-        let buffer_size = std::mem::size_of::<ModelViewProj>() as u64;
-        let buffer_usage = vk::BufferUsageFlags::UNIFORM_BUFFER;
-        let buffer_mem_proprieties =
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+    /// Loads the images at `paths` as textures with a full mip chain, adding a reference to each
+    /// of them to release with `free_texture`.
+    ///
+    /// The textures are cached by path, an image already loaded returning the same texture. The
+    /// other images are decoded in parallel then uploaded in batches, each batch being a single
+    /// submission.
+    pub fn load_texture_handles<P: AsRef<Path> + Sync>(
+        &mut self,
+        paths: &[P],
+    ) -> AppResult<Vec<TextureHandle>> {
+        let path_hashes: Vec<u64> = paths
+            .iter()
+            .map(|path| TextureManager::path_hash(path.as_ref()))
+            .collect();
+
+        // Each missing image is decoded once, even if it appears several times
+        let mut missing = vec![];
+        for (i, &path_hash) in path_hashes.iter().enumerate() {
+            if !self.resources.texture_manager.contains_path(path_hash)
+                && !missing.iter().any(|&j| path_hashes[j] == path_hash)
+            {
+                missing.push(i);
+            }
+        }
 
-        let mut uniform_buffers = Vec::new();
-        for _ in 0..max_frame_in_flight {
-            let buffer = Self::create_buffer(
-                instance,
-                device,
-                physical_device,
-                buffer_size,
-                buffer_usage,
-                buffer_mem_proprieties,
+        let missing_paths: Vec<&P> = missing.iter().map(|&i| &paths[i]).collect();
+        let mut decoded = texture_loader::decode_textures(&missing_paths)?;
+        let mut inserted = vec![];
+        for batch in texture_loader::upload_batches(&decoded, TEXTURE_UPLOAD_BUDGET) {
+            let textures = Self::upload_textures(
+                &self.context.instance,
+                &self.context.device,
+                self.context.graphics_queue,
+                self.context.physical_device,
+                self.resources.command_pool,
+                &self.resources.sync_pool,
+                &mut decoded[batch.clone()],
             )?;
 
-            let buffer_memory_map = unsafe {
-                device.map_memory(buffer.memory, 0, buffer_size, vk::MemoryMapFlags::empty())?
-            };
-
-            uniform_buffers.push(MemoryMappedBuffer::new(
-                buffer.buffer,
-                buffer.memory,
-                buffer_memory_map,
-            ));
+            for (texture, i) in textures.into_iter().zip(batch) {
+                let size = unsafe {
+                    self.context
+                        .device
+                        .get_image_memory_requirements(texture.image.image)
+                        .size
+                };
+                let handle = self
+                    .resources
+                    .texture_manager
+                    .insert(texture, Some(path_hashes[missing[i]]));
+                self.asset_watcher
+                    .watch_texture(paths[missing[i]].as_ref(), handle);
+                inserted.push((missing[i], handle));
+                self.event_log.push(RendererEvent::ResourceCreated {
+                    name: format!("texture {}", handle.0),
+                    size,
+                });
+            }
         }
 
-        Ok(uniform_buffers)
+        // The inserted textures already have their reference
+        Ok(path_hashes
+            .iter()
+            .enumerate()
+            .map(
+                |(i, &path_hash)| match inserted.iter().find(|(inserted_i, _)| *inserted_i == i) {
+                    Some(&(_, handle)) => handle,
+                    None => self.resources.texture_manager.acquire(path_hash).unwrap(),
+                },
+            )
+            .collect())
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn create_buffer_with_data<T>(
-        instance: &Instance,
-        device: &Device,
-        graphic_queue: vk::Queue,
-        physical_device: vk::PhysicalDevice,
-        data: &[T],
-        buffer_usage: vk::BufferUsageFlags,
-        buffer_mem_proprieties: vk::MemoryPropertyFlags,
-        command_pool: vk::CommandPool,
-    ) -> AppResult<BufferHolder> {
-        let buffer_size = std::mem::size_of_val(data) as u64;
-
-        let staging_buffer_mem_proprieties =
-            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE;
-        let staging_buffer = Self::create_buffer(
-            instance,
-            device,
-            physical_device,
-            buffer_size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            staging_buffer_mem_proprieties,
-        )?;
-
-        unsafe {
-            let data_src = data.as_ptr() as *const c_void;
-            let data_dst = device.map_memory(
-                staging_buffer.memory,
-                0,
-                buffer_size,
-                vk::MemoryMapFlags::empty(),
-            )?;
-            std::ptr::copy(data_src, data_dst, buffer_size as usize);
-            device.unmap_memory(staging_buffer.memory);
+    /// Releases a reference to a texture taken by `load_texture_handles`. Along with its last
+    /// reference, the texture is destroyed once the frames in flight are done with it and the
+    /// materials sampling it fall back to the placeholder texture. Returns `false` if the texture
+    /// is already freed.
+    pub fn free_texture(&mut self, texture: TextureHandle) -> bool {
+        let (Some(view), Some(image)) = (
+            self.resources.texture_manager.view(texture),
+            self.resources.texture_manager.image(texture),
+        ) else {
+            return false;
         };
+        let Some(retired) = self.resources.texture_manager.release(texture) else {
+            return true;
+        };
+        self.resources
+            .garbage_collector
+            .retire(Garbage::Texture(retired), self.frames_in_flight);
+        self.asset_watcher.unwatch(WatchedAsset::Texture(texture));
+        self.replace_material_views(
+            view,
+            self.resources.placeholder_texture.view,
+            self.resources.white_lightmap.view,
+        );
 
-        let buffer = Self::create_buffer(
-            instance,
-            device,
-            physical_device,
-            buffer_size,
-            buffer_usage,
-            buffer_mem_proprieties,
-        )?;
+        self.event_log.push(RendererEvent::ResourceDestroyed {
+            name: format!("texture {}", texture.0),
+            size: unsafe {
+                self.context
+                    .device
+                    .get_image_memory_requirements(image)
+                    .size
+            },
+        });
 
-        Self::copy_buffer(
-            device,
-            graphic_queue,
-            staging_buffer.buffer,
-            buffer.buffer,
-            buffer_size,
-            command_pool,
-        )?;
+        true
+    }
 
-        unsafe {
-            device.destroy_buffer(staging_buffer.buffer, None);
-            device.free_memory(staging_buffer.memory, None);
+    // Makes the materials sampling `view` as their texture or lightmap sample the replacements
+    fn replace_material_views(
+        &mut self,
+        view: vk::ImageView,
+        texture_replacement: vk::ImageView,
+        lightmap_replacement: vk::ImageView,
+    ) {
+        self.shader_materials
+            .replace_view(view, texture_replacement);
+        let mut replaced = vec![];
+        for (i, material) in self.materials.iter_mut().enumerate() {
+            if material.texture_view == view {
+                material.texture_view = texture_replacement;
+                replaced.push(MaterialHandle(i));
+            }
+            if material.lightmap_view == view {
+                material.lightmap_view = lightmap_replacement;
+                replaced.push(MaterialHandle(i));
+            }
         }
 
-        Ok(buffer)
+        // The descriptor sets of every frame are rewritten before being used again
+        for frame in self.frames.iter_mut() {
+            for written_material in frame.descriptor_materials.iter_mut() {
+                if written_material.is_some_and(|material| replaced.contains(&material)) {
+                    *written_material = None;
+                }
+            }
+        }
     }
 
-    fn create_buffer(
-        instance: &Instance,
-        device: &Device,
-        physical_device: vk::PhysicalDevice,
-        size: vk::DeviceSize,
-        usage: vk::BufferUsageFlags,
-        mem_proprieties: vk::MemoryPropertyFlags,
-    ) -> AppResult<BufferHolder> {
-        let buffer_info = vk::BufferCreateInfo {
-            size,
-            usage,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
-
-        let buffer = unsafe { device.create_buffer(&buffer_info, None)? };
+    /// Loads the same-sized images at `paths` as the layers of a 2D array texture with a full mip
+    /// chain, sampled by the materials of `create_array_material`. The array is cached by its
+    /// paths, and released with `free_texture` like the other textures. Fails if the images
+    /// don't have the same size.
+    pub fn load_texture_array<P: AsRef<Path> + Sync>(
+        &mut self,
+        paths: &[P],
+    ) -> AppResult<TextureHandle> {
+        let paths_hash = TextureManager::paths_hash(paths.iter().map(AsRef::as_ref));
+        if let Some(texture) = self.resources.texture_manager.acquire(paths_hash) {
+            return Ok(texture);
+        }
 
-        let mem_requirement = unsafe { device.get_buffer_memory_requirements(buffer) };
-        let mem_type_index = Self::find_memory_type(
-            instance,
-            physical_device,
-            mem_requirement.memory_type_bits,
-            mem_proprieties,
+        let mut layers = texture_loader::decode_textures(paths)?;
+        let texture = texture_array::upload_texture_array(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            &mut layers,
         )?;
-
-        let alloc_info = vk::MemoryAllocateInfo {
-            allocation_size: mem_requirement.size,
-            memory_type_index: mem_type_index,
-            ..Default::default()
+        let size = unsafe {
+            self.context
+                .device
+                .get_image_memory_requirements(texture.image.image)
+                .size
         };
-        let buffer_memory = unsafe { device.allocate_memory(&alloc_info, None)? };
-        unsafe {
-            device.bind_buffer_memory(buffer, buffer_memory, 0)?;
-        }
+        let handle = self
+            .resources
+            .texture_manager
+            .insert(texture, Some(paths_hash));
+        self.event_log.push(RendererEvent::ResourceCreated {
+            name: format!("texture {}, {} layers", handle.0, layers.len()),
+            size,
+        });
 
-        Ok(BufferHolder::new(buffer, buffer_memory))
+        Ok(handle)
     }
 
-    fn copy_buffer(
-        device: &Device,
-        queue: vk::Queue,
-        src_buffer: vk::Buffer,
-        dst_buffer: vk::Buffer,
-        size: vk::DeviceSize,
-        command_pool: vk::CommandPool,
+    /// Loads the six same-sized square images at `paths` as the faces of a cubemap drawn behind
+    /// the scene, in the +X, -X, +Y, -Y, +Z, -Z order. Replaces the current skybox, if any, and
+    /// bakes its image based lighting, see `image_based_lighting`. The faces loaded from HDR
+    /// images keep their range, e.g. for a bright sun.
+    pub fn load_skybox<P: AsRef<Path> + Sync>(&mut self, paths: &[P]) -> AppResult<()> {
+        if paths.len() != CUBEMAP_FACES {
+            return AppResult::Err(AppError::new(AppErrorType::InvalidCubemapFaces));
+        }
+
+        let mut faces = texture_loader::decode_textures(paths)?;
+        let cubemap = skybox::upload_cubemap(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            &mut faces,
+        )?;
+
+        self.set_skybox_cubemap(cubemap)
+    }
+
+    /// Loads the equirectangular HDR panorama at `path` as a skybox, projected into a cubemap
+    /// with `face_size` texels wide faces. Replaces the current skybox, if any, and bakes its
+    /// image based lighting.
+    pub fn load_skybox_equirect<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        face_size: u32,
     ) -> AppResult<()> {
-        let command_buffer = Self::begin_singe_time_command(device, command_pool)?;
+        let cubemap = equirect::equirect_to_cubemap(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            path.as_ref(),
+            face_size,
+        )?;
 
-        unsafe {
-            let copy_regions = [vk::BufferCopy {
-                src_offset: 0,
-                dst_offset: 0,
-                size,
-            }];
-            device.cmd_copy_buffer(command_buffer, src_buffer, dst_buffer, &copy_regions);
+        self.set_skybox_cubemap(cubemap)
+    }
+
+    fn set_skybox_cubemap(&mut self, cubemap: TextureHolder) -> AppResult<()> {
+        match &mut self.skybox {
+            Some(skybox) => {
+                self.event_log.push(RendererEvent::ResourceDestroyed {
+                    name: String::from("skybox"),
+                    size: skybox.memory_size(&self.context.device),
+                });
+                // The previous cubemap may still be sampled by the frames in flight
+                unsafe { self.context.device.device_wait_idle()? };
+                skybox.replace_cubemap(&self.context.device, cubemap);
+            }
+            None => {
+                self.skybox = Some(Skybox::new(
+                    &self.context.device,
+                    self.pipeline.renderpass,
+                    self.pipeline.samples,
+                    cubemap,
+                )?);
+                self.event_log.push(RendererEvent::PipelineBuilt {
+                    name: String::from("skybox"),
+                });
+            }
         }
+        self.event_log.push(RendererEvent::ResourceCreated {
+            name: String::from("skybox"),
+            size: self
+                .skybox
+                .as_ref()
+                .unwrap()
+                .memory_size(&self.context.device),
+        });
+
+        self.remove_image_based_lighting()?;
+        let image_based_lighting = ImageBasedLighting::bake(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            self.skybox.as_ref().unwrap().cubemap_view(),
+        )?;
+        self.event_log.push(RendererEvent::ResourceCreated {
+            name: String::from("image based lighting"),
+            size: image_based_lighting.memory_size(&self.context.device),
+        });
+        self.image_based_lighting = Some(image_based_lighting);
 
-        Self::end_single_time_command(device, queue, command_pool, command_buffer)?;
+        Ok(())
+    }
+
+    fn remove_image_based_lighting(&mut self) -> AppResult<()> {
+        if let Some(mut image_based_lighting) = self.image_based_lighting.take() {
+            self.event_log.push(RendererEvent::ResourceDestroyed {
+                name: String::from("image based lighting"),
+                size: image_based_lighting.memory_size(&self.context.device),
+            });
+            // The maps may still be sampled by the render hooks of the frames in flight
+            unsafe { self.context.device.device_wait_idle()? };
+            image_based_lighting.destroy(&self.context.device);
+        }
 
         Ok(())
     }
 
-    fn find_memory_type(
-        instance: &Instance,
-        physical_device: vk::PhysicalDevice,
-        mem_type_filter: u32,
-        proprieties: vk::MemoryPropertyFlags,
-    ) -> AppResult<u32> {
-        let mem_proprieties =
-            unsafe { instance.get_physical_device_memory_properties(physical_device) };
-        for (i, mem_type) in mem_proprieties.memory_types.iter().enumerate() {
-            if mem_type_filter & (1 << i) != 0 && mem_type.property_flags.contains(proprieties) {
-                return Ok(i as u32);
-            }
+    /// Returns the image based lighting maps baked from the skybox when it was loaded, `None`
+    /// without skybox.
+    ///
+    /// There is no lit shader sampling them yet, they are meant to be sampled by render hooks
+    /// computing the ambient lighting with the split sum approximation.
+    pub fn image_based_lighting(&self) -> Option<IblViews> {
+        self.image_based_lighting
+            .as_ref()
+            .map(ImageBasedLighting::views)
+    }
+
+    /// Removes the skybox, the scene being drawn over the clear color again. Returns `false` if
+    /// there is no skybox.
+    pub fn remove_skybox(&mut self) -> AppResult<bool> {
+        let Some(mut skybox) = self.skybox.take() else {
+            return Ok(false);
+        };
+
+        self.event_log.push(RendererEvent::ResourceDestroyed {
+            name: String::from("skybox"),
+            size: skybox.memory_size(&self.context.device),
+        });
+        unsafe { self.context.device.device_wait_idle()? };
+        skybox.destroy(&self.context.device);
+        self.remove_image_based_lighting()?;
+
+        Ok(true)
+    }
+
+    /// Generates a terrain from the heightmap at `path`, a vertex per texel whose height is read
+    /// from the luminance of the texel, replacing the current terrain if any. The terrain is
+    /// split into chunks culled against the view frustum, and blends the `layers` textures by
+    /// the weights of the channels of `splat_map`. The textures must not be freed while the
+    /// terrain samples them.
+    ///
+    /// The terrain is drawn over the scene with a depth buffer of its own, covering the draw
+    /// items behind it and in front of it alike.
+    pub fn load_terrain<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        splat_map: TextureHandle,
+        layers: [TextureHandle; TERRAIN_LAYERS],
+        settings: TerrainSettings,
+    ) -> AppResult<()> {
+        let view_of = |texture: TextureHandle| {
+            self.resources
+                .texture_manager
+                .get(texture)
+                .filter(|holder| holder.array_layers.is_none())
+                .map(|holder| holder.view)
+                .ok_or_else(|| AppError::new(AppErrorType::InvalidTerrainTextures))
+        };
+        let splat_view = view_of(splat_map)?;
+        let layer_views = [
+            view_of(layers[0])?,
+            view_of(layers[1])?,
+            view_of(layers[2])?,
+            view_of(layers[3])?,
+        ];
+
+        let chunks = terrain::generate_chunks(path.as_ref(), &settings)?;
+        self.remove_terrain()?;
+        self.terrain = Some(Terrain::new(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            &chunks,
+            splat_view,
+            layer_views,
+            self.texture_sampler,
+            &settings,
+        )?);
+        self.event_log.push(RendererEvent::PipelineBuilt {
+            name: String::from("terrain"),
+        });
+
+        Ok(())
+    }
+
+    /// Removes the terrain. Returns `false` if there is no terrain.
+    pub fn remove_terrain(&mut self) -> AppResult<bool> {
+        let Some(mut terrain) = self.terrain.take() else {
+            return Ok(false);
+        };
+
+        unsafe { self.context.device.device_wait_idle()? };
+        terrain.destroy(&self.context.device);
+
+        Ok(true)
+    }
+
+    /// Chunks of the terrain in the view frustum of the camera, along with the chunk count.
+    /// `None` without terrain.
+    pub fn terrain_chunks(&self) -> Option<(usize, usize)> {
+        let terrain = self.terrain.as_ref()?;
+        Some((
+            terrain.visible_chunks(self.proj_matrix * self.view_matrix),
+            terrain.chunk_count(),
+        ))
+    }
+
+    /// Returns a material sampling the layer `layer` of the texture array `texture`, with its own
+    /// pipeline. `None` if the texture is freed, isn't an array or doesn't have this layer.
+    pub fn create_array_material(
+        &mut self,
+        texture: TextureHandle,
+        layer: u32,
+    ) -> AppResult<Option<MaterialHandle>> {
+        let Some(holder) = self.resources.texture_manager.get(texture) else {
+            return Ok(None);
+        };
+        if holder.array_layers.is_none_or(|layers| layer >= layers) {
+            return Ok(None);
+        }
+        let texture_view = holder.view;
+
+        if self.texture_arrays.is_none() {
+            self.texture_arrays = Some(TextureArrayPipeline::new(
+                &self.context.device,
+                self.pipeline.renderpass,
+                self.pipeline.samples,
+                self.pipeline.descriptor_set_layout,
+            )?);
+            self.event_log.push(RendererEvent::PipelineBuilt {
+                name: String::from("texture array"),
+            });
         }
 
-        Err(AppError::new(AppErrorType::NoSuitableMemType))
+        let handle = MaterialHandle(self.materials.len());
+        self.materials.push(MaterialHolder {
+            texture_view,
+            sampler: self.texture_sampler,
+            lightmap_view: self.resources.white_lightmap.view,
+            kind: MaterialKind::ArrayLayer(layer),
+        });
+
+        Ok(Some(handle))
     }
 
-    fn create_texture_image<P: AsRef<Path>>(
-        instance: &Instance,
-        device: &Device,
-        graphic_queue: vk::Queue,
-        physical_device: vk::PhysicalDevice,
-        command_pool: vk::CommandPool,
-        texture_path: P,
-    ) -> AppResult<ImageHolder> {
-        let img = Reader::open(texture_path)?.decode()?.into_rgba8();
-        let width = img.width();
-        let height = img.height();
-        let buffer_size = (width * height * 4) as u64;
-
-        let staging_buffer = Self::create_buffer(
-            instance,
-            device,
-            physical_device,
-            buffer_size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    /// Loads the maps of `material` and returns a material shading them with the Cook-Torrance
+    /// BRDF under the lights set with `set_pbr_lighting`, with its own pipeline. The maps other
+    /// than the albedo are sampled as linear values. Fails once `MAX_PBR_MATERIALS` PBR materials
+    /// are created.
+    ///
+    /// The vertices only having a 2D position, the meshes are shaded as lying in their XY plane,
+    /// facing +Z before their transform. The normal maps are oriented by the vertex tangents, see
+    /// `compute_tangents`.
+    pub fn create_pbr_material<P: AsRef<Path> + Sync>(
+        &mut self,
+        material: &PbrMaterial<P>,
+    ) -> AppResult<MaterialHandle> {
+        let decoded = pbr::decode_material(material)?;
+        self.create_pbr_pipeline()?;
+        let (index, albedo_view) = self.pbr_pipeline.as_mut().unwrap().add_material(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            decoded,
         )?;
-        unsafe {
-            let buffer_memory_ptr = device.map_memory(
-                staging_buffer.memory,
-                0,
-                buffer_size,
-                vk::MemoryMapFlags::empty(),
+        let handle = self.push_pbr_material(index, albedo_view);
+
+        let material = material.to_path_bufs();
+        self.asset_watcher.watch_pbr_materials(
+            &material.map_paths(),
+            WatchedAsset::PbrMaterials {
+                first: handle,
+                count: 1,
+            },
+            Rc::new({
+                let material = material.clone();
+                move || Ok(vec![pbr::decode_material(&material)?])
+            }),
+        );
+
+        Ok(handle)
+    }
+
+    /// Creates a PBR material for each metallic-roughness material of the glTF file at `path`,
+    /// in the order of the file, with its maps, factors, alpha mode and culling. The embedded
+    /// images are decoded along with the external ones. Fails past `MAX_PBR_MATERIALS`
+    /// materials, the ones already created being kept.
+    #[cfg(feature = "gltf")]
+    pub fn load_gltf_materials<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> AppResult<Vec<MaterialHandle>> {
+        let path = path.as_ref().to_path_buf();
+        let materials = gltf_import::import_materials(&path)?;
+        self.create_pbr_pipeline()?;
+
+        let mut handles = Vec::with_capacity(materials.len());
+        for material in materials {
+            let (index, albedo_view) = self.pbr_pipeline.as_mut().unwrap().add_material(
+                &self.context.instance,
+                &self.context.device,
+                self.context.graphics_queue,
+                self.context.physical_device,
+                self.resources.command_pool,
+                &self.resources.sync_pool,
+                material,
             )?;
-            std::ptr::copy(
-                img.as_ptr(),
-                buffer_memory_ptr as *mut _,
-                buffer_size as usize,
+            handles.push(self.push_pbr_material(index, albedo_view));
+        }
+
+        if let Some(&first) = handles.first() {
+            self.asset_watcher.watch_pbr_materials(
+                &[&path],
+                WatchedAsset::PbrMaterials {
+                    first,
+                    count: handles.len(),
+                },
+                Rc::new({
+                    let path = path.clone();
+                    move || gltf_import::import_materials(&path)
+                }),
             );
-            device.unmap_memory(staging_buffer.memory)
         }
 
-        let image_format = vk::Format::R8G8B8A8_SRGB;
-        let texture_image = Self::create_image(
-            instance,
-            device,
-            physical_device,
-            width,
-            height,
-            image_format,
-            vk::ImageTiling::OPTIMAL,
-            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        Ok(handles)
+    }
+
+    // Creates the PBR pipeline along with the first PBR material
+    fn create_pbr_pipeline(&mut self) -> AppResult<()> {
+        if self.pbr_pipeline.is_none() {
+            self.pbr_pipeline = Some(PbrPipeline::new(
+                &self.context.instance,
+                &self.context.device,
+                self.context.graphics_queue,
+                self.context.physical_device,
+                self.resources.command_pool,
+                &self.resources.sync_pool,
+                self.pipeline.renderpass,
+                self.pipeline.samples,
+                self.pipeline.descriptor_set_layout,
+                self.texture_sampler,
+                self.frames_in_flight,
+            )?);
+            self.event_log.push(RendererEvent::PipelineBuilt {
+                name: String::from("pbr"),
+            });
+            self.update_pbr_ray_queries()?;
+        }
+
+        Ok(())
+    }
+
+    // Adds the material drawing the PBR material of `index`
+    fn push_pbr_material(&mut self, index: usize, albedo_view: vk::ImageView) -> MaterialHandle {
+        let pbr_pipeline = self.pbr_pipeline.as_ref().unwrap();
+        self.event_log.push(RendererEvent::ResourceCreated {
+            name: format!("pbr material {}", index),
+            size: pbr_pipeline.memory_size(&self.context.device, index),
+        });
+
+        let handle = MaterialHandle(self.materials.len());
+        self.materials.push(MaterialHolder {
+            texture_view: albedo_view,
+            sampler: self.texture_sampler,
+            lightmap_view: self.resources.white_lightmap.view,
+            kind: MaterialKind::Pbr {
+                index,
+                blended: pbr_pipeline.is_blended(index),
+            },
+        });
+
+        handle
+    }
+
+    /// Replaces the lights shading the PBR materials from the next frame, fails with more than
+    /// `MAX_PBR_LIGHTS` lights, more than `MAX_DIRECTIONAL_PBR_LIGHTS` directional ones or more
+    /// than `MAX_SHADOWED_POINT_LIGHTS` casting shadows
+    pub fn set_pbr_lighting(&mut self, lighting: PbrLighting) -> AppResult<()> {
+        if lighting.lights.len() > MAX_PBR_LIGHTS
+            || lighting.directional_count() > MAX_DIRECTIONAL_PBR_LIGHTS
+        {
+            return AppResult::Err(AppError::new(AppErrorType::TooManyPbrLights));
+        }
+        if lighting.shadow_casters().len() > MAX_SHADOWED_POINT_LIGHTS {
+            return AppResult::Err(AppError::new(AppErrorType::TooManyShadowedLights));
+        }
+        self.pbr_lighting = lighting;
+
+        Ok(())
+    }
+
+    /// Lights shading the PBR materials in the next frame
+    pub fn pbr_lighting(&self) -> &PbrLighting {
+        &self.pbr_lighting
+    }
+
+    /// Returns a material drawn with the shaders, textures and parameters of `desc`, with its own
+    /// pipeline. Returns `None` if one of the textures is already freed.
+    pub fn create_shader_material(
+        &mut self,
+        desc: &ShaderMaterialDesc,
+    ) -> AppResult<Option<MaterialHandle>> {
+        let Some(texture_views) = desc
+            .textures
+            .iter()
+            .map(|&texture| self.resources.texture_manager.view(texture))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Ok(None);
+        };
+        let texture_view = texture_views
+            .first()
+            .copied()
+            .unwrap_or(self.resources.placeholder_texture.view);
+
+        let index = self.shader_materials.add(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            &mut self.pipeline.manager,
+            self.pipeline.descriptor_set_layout,
+            self.texture_sampler,
+            desc,
+            texture_views,
+            self.frames_in_flight,
         )?;
+        self.event_log.push(RendererEvent::PipelineBuilt {
+            name: format!("shader material {}", index),
+        });
+
+        let handle = MaterialHandle(self.materials.len());
+        self.materials.push(MaterialHolder {
+            texture_view,
+            sampler: self.texture_sampler,
+            lightmap_view: self.resources.white_lightmap.view,
+            kind: MaterialKind::Shader(index),
+        });
+
+        Ok(Some(handle))
+    }
 
-        Self::transition_image_layout(
-            device,
-            graphic_queue,
-            command_pool,
-            texture_image.image,
-            image_format,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    /// Replaces the parameters of the shader material `material` from the next frames. Fails when
+    /// `material` isn't a shader material or `parameters` isn't the size it was created with.
+    pub fn set_material_parameters(
+        &mut self,
+        material: MaterialHandle,
+        parameters: &[u8],
+    ) -> AppResult<()> {
+        let MaterialKind::Shader(index) = self.materials[material.0].kind else {
+            return AppResult::Err(AppError::new(AppErrorType::InvalidMaterialParameters));
+        };
+
+        self.shader_materials.set_parameters(index, parameters)
+    }
+
+    /// Creates a buffer of `size` bytes bound to its own memory with `memory_properties`, for use
+    /// by render hooks. It must be destroyed with `destroy_gpu_buffer`.
+    pub fn create_gpu_buffer(
+        &mut self,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_properties: vk::MemoryPropertyFlags,
+    ) -> AppResult<GpuBuffer> {
+        let buffer = GpuBuffer::new(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            size,
+            usage,
+            memory_properties,
         )?;
-        Self::copy_buffer_to_image(
-            device,
-            graphic_queue,
-            command_pool,
-            staging_buffer.buffer,
-            texture_image.image,
-            width,
-            height,
+        self.event_log.push(RendererEvent::ResourceCreated {
+            name: String::from("gpu buffer"),
+            size: buffer.memory_size(),
+        });
+
+        Ok(buffer)
+    }
+
+    /// Creates a device local buffer holding `data`, uploaded through a staging buffer. The
+    /// transfer destination usage is added to `usage`.
+    pub fn create_gpu_buffer_with_data(
+        &mut self,
+        data: &[u8],
+        usage: vk::BufferUsageFlags,
+    ) -> AppResult<GpuBuffer> {
+        let buffer = self.create_gpu_buffer(
+            data.len() as u64,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
         )?;
-        Self::transition_image_layout(
-            device,
-            graphic_queue,
-            command_pool,
-            texture_image.image,
-            image_format,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        let staging_buffer = GpuBuffer::new(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            data.len() as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
         )?;
+        staging_buffer.write(&self.context.device, 0, data)?;
+        Self::copy_buffer(
+            &self.context.device,
+            self.context.graphics_queue,
+            staging_buffer.buffer,
+            buffer.buffer,
+            data.len() as u64,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+        )?;
+        staging_buffer.destroy(&self.context.device);
 
-        unsafe {
-            device.destroy_buffer(staging_buffer.buffer, None);
-            device.free_memory(staging_buffer.memory, None);
+        Ok(buffer)
+    }
+
+    /// Copies `data` at `offset` in `buffer`, which must be host visible and coherent. The range
+    /// must not be in use by the GPU, e.g. by the frames in flight.
+    pub fn write_gpu_buffer(
+        &self,
+        buffer: &GpuBuffer,
+        offset: vk::DeviceSize,
+        data: &[u8],
+    ) -> AppResult<()> {
+        let host_coherent =
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        if !buffer.memory_properties().contains(host_coherent)
+            || offset + data.len() as u64 > buffer.size()
+        {
+            return AppResult::Err(AppError::new(AppErrorType::InvalidBufferWrite));
         }
 
-        Ok(texture_image)
+        buffer.write(&self.context.device, offset, data)
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn create_image(
-        instance: &Instance,
-        device: &Device,
-        physical_device: vk::PhysicalDevice,
-        width: u32,
-        height: u32,
+    /// Destroys `buffer` once the device is idle
+    pub fn destroy_gpu_buffer(&mut self, buffer: GpuBuffer) -> AppResult<()> {
+        self.event_log.push(RendererEvent::ResourceDestroyed {
+            name: String::from("gpu buffer"),
+            size: buffer.memory_size(),
+        });
+        unsafe { self.context.device.device_wait_idle()? };
+        buffer.destroy(&self.context.device);
+
+        Ok(())
+    }
+
+    /// Creates a device local 2D image of `extent` with `mip_levels` levels and a view of all
+    /// of them, left in the `UNDEFINED` layout. It must be destroyed with `destroy_gpu_image`.
+    pub fn create_gpu_image(
+        &mut self,
+        extent: vk::Extent2D,
+        mip_levels: u32,
         format: vk::Format,
-        tiling: vk::ImageTiling,
         usage: vk::ImageUsageFlags,
-        proprieties: vk::MemoryPropertyFlags,
-    ) -> AppResult<ImageHolder> {
-        let image_info = vk::ImageCreateInfo {
-            image_type: vk::ImageType::TYPE_2D,
+    ) -> AppResult<GpuImage> {
+        let image = GpuImage::new(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            extent,
+            mip_levels,
             format,
-            extent: vk::Extent3D {
-                width,
-                height,
-                depth: 1,
-            },
-            mip_levels: 1,
-            array_layers: 1,
-            samples: vk::SampleCountFlags::TYPE_1,
-            tiling,
             usage,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            initial_layout: vk::ImageLayout::UNDEFINED,
-            ..Default::default()
-        };
-        unsafe {
-            let image = device.create_image(&image_info, None)?;
-            let mem_requirement = device.get_image_memory_requirements(image);
-            let memory_type = Self::find_memory_type(
-                instance,
-                physical_device,
-                mem_requirement.memory_type_bits,
-                proprieties,
-            )?;
+        )?;
+        self.event_log.push(RendererEvent::ResourceCreated {
+            name: String::from("gpu image"),
+            size: image.memory_size(),
+        });
 
-            let alloc_info = vk::MemoryAllocateInfo {
-                allocation_size: mem_requirement.size,
-                memory_type_index: memory_type,
-                ..Default::default()
-            };
+        Ok(image)
+    }
 
-            let image_memory = device.allocate_memory(&alloc_info, None)?;
-            device.bind_image_memory(image, image_memory, 0)?;
+    /// Destroys `image` once the device is idle
+    pub fn destroy_gpu_image(&mut self, image: GpuImage) -> AppResult<()> {
+        self.event_log.push(RendererEvent::ResourceDestroyed {
+            name: String::from("gpu image"),
+            size: image.memory_size(),
+        });
+        unsafe { self.context.device.device_wait_idle()? };
+        image.destroy(&self.context.device);
 
-            Ok(ImageHolder::new(image, image_memory))
-        }
+        Ok(())
     }
 
-    fn transition_image_layout(
-        device: &Device,
-        queue: vk::Queue,
-        command_pool: vk::CommandPool,
-        image: vk::Image,
-        _format: vk::Format,
-        old_layout: vk::ImageLayout,
-        new_layout: vk::ImageLayout,
-    ) -> AppResult<()> {
-        let mut src_access_mask = vk::AccessFlags::empty();
-        let mut dst_access_mask = vk::AccessFlags::empty();
-        let mut src_stage = vk::PipelineStageFlags::empty();
-        let mut dst_stage = vk::PipelineStageFlags::empty();
-        match (old_layout, new_layout) {
-            (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => {
-                dst_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-                src_stage = vk::PipelineStageFlags::TOP_OF_PIPE;
-                dst_stage = vk::PipelineStageFlags::TRANSFER;
-            }
-            (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => {
-                src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-                dst_access_mask = vk::AccessFlags::SHADER_READ;
+    /// Creates a sampler filtering every level with `filter` and addressing the coordinates
+    /// with `address_mode`. It must be destroyed with `destroy_gpu_sampler`.
+    pub fn create_gpu_sampler(
+        &self,
+        filter: vk::Filter,
+        address_mode: vk::SamplerAddressMode,
+    ) -> AppResult<GpuSampler> {
+        GpuSampler::new(&self.context.device, filter, address_mode)
+    }
 
-                src_stage = vk::PipelineStageFlags::TRANSFER;
-                dst_stage = vk::PipelineStageFlags::FRAGMENT_SHADER;
-            }
-            _ => (),
-        }
-
-        let barriers = [vk::ImageMemoryBarrier {
-            src_access_mask,
-            dst_access_mask,
-            old_layout,
-            new_layout,
-            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-            ..Default::default()
-        }];
+    /// Destroys `sampler` once the device is idle
+    pub fn destroy_gpu_sampler(&self, sampler: GpuSampler) -> AppResult<()> {
+        unsafe { self.context.device.device_wait_idle()? };
+        sampler.destroy(&self.context.device);
 
-        unsafe {
-            let command_buffer = Self::begin_singe_time_command(device, command_pool)?;
-            device.cmd_pipeline_barrier(
-                command_buffer,
-                src_stage,
-                dst_stage,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &barriers,
-            );
-            Self::end_single_time_command(device, queue, command_pool, command_buffer)?;
+        Ok(())
+    }
+
+    /// Returns a material sampling `texture`, `None` if the texture is freed or is an array, see
+    /// `create_array_material`. The material keeps sampling the texture until it is freed.
+    pub fn create_material(&mut self, texture: TextureHandle) -> Option<MaterialHandle> {
+        self.create_texture_material(texture, MaterialKind::Scene)
+    }
+
+    /// Returns a material blending `texture` over the scene with its alpha, see
+    /// `create_material`. The transparent items are drawn after the opaque ones, from the
+    /// farthest to the closest to the camera, and are left out of the shadows, the probe
+    /// captures and the prepasses.
+    pub fn create_transparent_material(
+        &mut self,
+        texture: TextureHandle,
+    ) -> Option<MaterialHandle> {
+        self.create_texture_material(texture, MaterialKind::Transparent)
+    }
+
+    fn create_texture_material(
+        &mut self,
+        texture: TextureHandle,
+        kind: MaterialKind,
+    ) -> Option<MaterialHandle> {
+        let holder = self.resources.texture_manager.get(texture)?;
+        if holder.array_layers.is_some() {
+            return None;
         }
+        let texture_view = holder.view;
 
-        Ok(())
+        let handle = MaterialHandle(self.materials.len());
+        self.materials.push(MaterialHolder {
+            texture_view,
+            sampler: self.texture_sampler,
+            lightmap_view: self.resources.white_lightmap.view,
+            kind,
+        });
+
+        Some(handle)
     }
 
-    fn copy_buffer_to_image(
-        device: &Device,
-        queue: vk::Queue,
-        command_pool: vk::CommandPool,
-        buffer: vk::Buffer,
-        image: vk::Image,
-        width: u32,
-        height: u32,
-    ) -> AppResult<()> {
-        let regions = [vk::BufferImageCopy {
-            buffer_offset: 0,
-            buffer_row_length: 0,
-            buffer_image_height: 0,
-            image_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                mip_level: 0,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
-            image_extent: vk::Extent3D {
-                width,
-                height,
-                depth: 1,
-            },
-        }];
-        unsafe {
-            let command_buffer = Self::begin_singe_time_command(device, command_pool)?;
-            device.cmd_copy_buffer_to_image(
-                command_buffer,
-                buffer,
-                image,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &regions,
-            );
-            Self::end_single_time_command(device, queue, command_pool, command_buffer)?;
+    /// Returns the texture sampled by `material`, `None` if it samples a texture which isn't
+    /// handled by the texture manager, e.g. the placeholder or a streamed texture
+    pub fn material_texture(&self, material: MaterialHandle) -> Option<TextureHandle> {
+        let material = self.materials.get(material.0)?;
+        self.resources
+            .texture_manager
+            .handle_of(material.texture_view)
+    }
+
+    /// Returns whether `texture` was uploaded in its own format or decompressed on the host
+    /// because the device can't sample its block-compressed format. `None` if it is freed.
+    pub fn texture_upload_path(&self, texture: TextureHandle) -> Option<TextureUploadPath> {
+        self.resources
+            .texture_manager
+            .get(texture)
+            .map(|holder| holder.upload_path)
+    }
+
+    /// Returns a material sampling the image at `path`, which is decoded in the background then
+    /// uploaded over the next frames from its smallest mip level to the full resolution one. The
+    /// placeholder texture is sampled until the first level is uploaded.
+    pub fn load_streamed_texture<P: AsRef<Path>>(&mut self, path: P) -> MaterialHandle {
+        let handle = MaterialHandle(self.materials.len());
+        self.materials.push(MaterialHolder {
+            texture_view: self.resources.placeholder_texture.view,
+            sampler: self.texture_sampler,
+            lightmap_view: self.resources.white_lightmap.view,
+            kind: MaterialKind::Scene,
+        });
+        self.resources
+            .texture_streamer
+            .add(path.as_ref().to_path_buf(), handle);
+
+        handle
+    }
+
+    /// Sets how many bytes of streamed textures are uploaded per frame, `DEFAULT_STREAM_BUDGET` by
+    /// default. At least one mip level is uploaded per frame whatever the budget.
+    pub fn set_texture_stream_budget(&mut self, budget: u64) {
+        self.resources.texture_streamer.set_budget(budget);
+    }
+
+    pub fn texture_stream_budget(&self) -> u64 {
+        self.resources.texture_streamer.budget()
+    }
+
+    /// Returns a material sampling a virtual texture whose pages are read from `source`, only the
+    /// pages sampled by the recent frames being resident. Fails if the device doesn't support
+    /// sparse residency.
+    pub fn create_virtual_texture<S: PageSource + 'static>(
+        &mut self,
+        source: S,
+    ) -> AppResult<MaterialHandle> {
+        if !self.context.device_features.sparse_residency {
+            return AppResult::Err(AppError::new(AppErrorType::SparseResidencyUnsupported));
         }
 
-        Ok(())
+        if self.virtual_texturing.is_none() {
+            self.virtual_texturing = Some(VirtualTexturing::new(
+                &self.context.device,
+                self.pipeline.renderpass,
+                self.pipeline.samples,
+                self.pipeline.descriptor_set_layout,
+                self.resources.command_pool,
+                self.frames_in_flight as u32,
+            )?);
+            self.event_log.push(RendererEvent::PipelineBuilt {
+                name: String::from("virtual texture"),
+            });
+        }
+        let virtual_texturing = self.virtual_texturing.as_mut().unwrap();
+
+        let index = virtual_texturing.add(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            self.context.graphics_queue,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            Arc::new(source),
+        )?;
+        self.event_log.push(RendererEvent::ResourceCreated {
+            name: format!("virtual texture {}", index),
+            size: virtual_texturing.memory_size(index),
+        });
+
+        // The scene set still needs a valid image, which the pipeline doesn't sample
+        let handle = MaterialHandle(self.materials.len());
+        self.materials.push(MaterialHolder {
+            texture_view: self.resources.placeholder_texture.view,
+            sampler: self.texture_sampler,
+            lightmap_view: self.resources.white_lightmap.view,
+            kind: MaterialKind::VirtualTexture(index),
+        });
+
+        Ok(handle)
     }
 
-    fn create_descriptor_pool(
-        device: &Device,
-        max_frame_in_flight: u32,
-    ) -> AppResult<vk::DescriptorPool> {
-        let pool_sizes = [
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: max_frame_in_flight,
-            },
-            vk::DescriptorPoolSize {
-                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                descriptor_count: max_frame_in_flight,
-            },
-        ];
+    /// Loads the image at `path` as a virtual texture, see `create_virtual_texture`. The whole
+    /// mip chain is kept in host memory, a terrain or megatexture would rather implement
+    /// `PageSource` to read its pages from the disk.
+    pub fn load_virtual_texture<P: AsRef<Path> + Sync>(
+        &mut self,
+        path: P,
+    ) -> AppResult<MaterialHandle> {
+        let mut decoded = texture_loader::decode_textures(&[path])?.pop().unwrap();
+        // The pages are read from the texels, block-compressed files are decompressed to reach them
+        if !decoded.is_rgba() {
+            decoded = decoded
+                .decompress()
+                .ok_or(AppError::new(AppErrorType::UnsupportedTextureFormat))?;
+        }
+        self.create_virtual_texture(decoded)
+    }
+
+    /// Bakes the lighting of the static mesh made of `vertices` and `indices`, as drawn with
+    /// `transform`, in a lightmap laid out by the lightmap coordinates of its vertices. Returns a
+    /// material sampling the texture of `material` lit by the lightmap, meant for the draw items
+    /// of this mesh and transform only.
+    ///
+    /// The lightmap is cached in the cache directory of the settings, and loaded instead of baked
+    /// again as long as the mesh, the transform and the settings stay the same.
+    pub fn bake_lightmap(
+        &mut self,
+        vertices: &[Vertex],
+        indices: &[u32],
+        transform: Mat4,
+        material: MaterialHandle,
+        settings: &LightmapSettings,
+    ) -> AppResult<MaterialHandle> {
+        let (lightmap, baked) = lightmap::load_or_bake(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            self.context.graphics_queue,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            vertices,
+            indices,
+            transform,
+            settings,
+        )?;
 
-        let pool_info = vk::DescriptorPoolCreateInfo {
-            max_sets: max_frame_in_flight,
-            pool_size_count: 2,
-            p_pool_sizes: pool_sizes.as_ptr(),
-            ..Default::default()
+        let texture = Self::upload_textures(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            &mut [DecodedTexture::from_rgba(vec![lightmap])],
+        )?
+        .pop()
+        .unwrap();
+        let size = unsafe {
+            self.context
+                .device
+                .get_image_memory_requirements(texture.image.image)
+                .size
         };
+        let lightmap_view = texture.view;
+        let texture = self.resources.texture_manager.insert(texture, None);
+        self.event_log.push(RendererEvent::ResourceCreated {
+            name: format!(
+                "{} lightmap, texture {}",
+                if baked { "baked" } else { "cached" },
+                texture.0
+            ),
+            size,
+        });
+
+        let base = &self.materials[material.0];
+        let handle = MaterialHandle(self.materials.len());
+        self.materials.push(MaterialHolder {
+            texture_view: base.texture_view,
+            sampler: base.sampler,
+            lightmap_view,
+            kind: base.kind,
+        });
+
+        Ok(handle)
+    }
 
-        unsafe { Ok(device.create_descriptor_pool(&pool_info, None)?) }
+    /// Places a reflection probe capturing the scene around `position`, captured again with the
+    /// next frame, and influencing the points within `radius`. Fails once `MAX_REFLECTION_PROBES`
+    /// probes are placed.
+    ///
+    /// There is no lit shader sampling the probes yet, their cubemaps are meant to be sampled by
+    /// render hooks using `reflection_probe_view` and `reflection_probe_weights`.
+    pub fn add_reflection_probe(
+        &mut self,
+        position: Point3,
+        radius: f32,
+    ) -> AppResult<ReflectionProbeId> {
+        if self.reflection_probes.is_none() {
+            self.reflection_probes = Some(ReflectionProbes::new(
+                &self.context.device,
+                self.pipeline.descriptor_set_layout,
+            )?);
+            self.event_log.push(RendererEvent::PipelineBuilt {
+                name: String::from("reflection probe"),
+            });
+        }
+        let reflection_probes = self.reflection_probes.as_mut().unwrap();
+
+        let id = reflection_probes.add(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            position,
+            radius,
+        )?;
+        self.event_log.push(RendererEvent::ResourceCreated {
+            name: format!("reflection probe {:?}", id),
+            size: reflection_probes.memory_size(&self.context.device, id),
+        });
+
+        Ok(id)
     }
 
-    fn create_descriptor_sets(
-        device: &Device,
-        uniform_buffers: &[MemoryMappedBuffer],
-        texture_view: vk::ImageView,
-        texture_sampler: vk::Sampler,
-        descriptor_set_layout: vk::DescriptorSetLayout,
-        descriptor_pool: vk::DescriptorPool,
-        max_frame_in_flight: u32,
-    ) -> AppResult<Vec<vk::DescriptorSet>> {
-        let layouts = vec![descriptor_set_layout; max_frame_in_flight as usize];
-
-        let alloc_info = vk::DescriptorSetAllocateInfo {
-            descriptor_pool,
-            descriptor_set_count: max_frame_in_flight,
-            p_set_layouts: layouts.as_ptr(),
-            ..Default::default()
+    /// Moves a reflection probe, captured again with the next frame. Returns `false` if there is
+    /// no such probe.
+    pub fn move_reflection_probe(
+        &mut self,
+        id: ReflectionProbeId,
+        position: Point3,
+        radius: f32,
+    ) -> bool {
+        self.reflection_probes
+            .as_mut()
+            .is_some_and(|reflection_probes| reflection_probes.place(id, position, radius))
+    }
+
+    /// Captures a reflection probe again with the next frame, e.g. once the objects around it
+    /// moved. Returns `false` if there is no such probe.
+    pub fn refresh_reflection_probe(&mut self, id: ReflectionProbeId) -> bool {
+        self.reflection_probes
+            .as_mut()
+            .is_some_and(|reflection_probes| reflection_probes.refresh(id))
+    }
+
+    /// Returns `false` if there is no such probe
+    pub fn remove_reflection_probe(&mut self, id: ReflectionProbeId) -> AppResult<bool> {
+        let Some(reflection_probes) = &mut self.reflection_probes else {
+            return Ok(false);
         };
 
-        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info)? };
-        let mut buffer_infos = vec![];
-        let mut image_infos = vec![];
-        let mut descriptor_writes = vec![];
-        for (i, &desc_set) in descriptor_sets.iter().enumerate() {
-            buffer_infos.push(vk::DescriptorBufferInfo {
-                buffer: uniform_buffers[i].buffer,
-                offset: 0,
-                range: std::mem::size_of::<ModelViewProj>() as u64,
-            });
+        // The probe may still be sampled by the frames in flight
+        unsafe { self.context.device.device_wait_idle()? };
+        reflection_probes.remove(&self.context.device, id)
+    }
 
-            image_infos.push(vk::DescriptorImageInfo {
-                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                image_view: texture_view,
-                sampler: texture_sampler,
-            });
+    /// Returns the prefiltered cubemap of a reflection probe, the roughness of its level `i`
+    /// being `i / (PROBE_MIP_LEVELS - 1)`. The view is in the shader read only layout once the
+    /// probe has been captured.
+    pub fn reflection_probe_view(&self, id: ReflectionProbeId) -> Option<vk::ImageView> {
+        self.reflection_probes
+            .as_ref()
+            .and_then(|reflection_probes| reflection_probes.view(id))
+    }
 
-            descriptor_writes.push(vk::WriteDescriptorSet {
-                dst_set: desc_set,
-                dst_binding: 0,
-                dst_array_element: 0,
-                descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
-                p_buffer_info: &buffer_infos[i] as *const _,
-                ..Default::default()
+    /// Returns the reflection probes reaching `position` with the weight of their contribution
+    /// to its reflections, summing to 1, from the biggest contribution
+    pub fn reflection_probe_weights(&self, position: Point3) -> Vec<(ReflectionProbeId, f32)> {
+        self.reflection_probes
+            .as_ref()
+            .map_or_else(Vec::new, |reflection_probes| {
+                reflection_probes.weights(position)
+            })
+    }
+
+    /// Adds `item` to the objects drawn every frame. The uniform buffers are grown, waiting for the
+    /// device to be idle, once the draw list outgrows them.
+    pub fn add_draw_item(&mut self, item: DrawItem) -> AppResult<DrawItemId> {
+        if self.draw_list.len() >= self.draw_capacity {
+            self.grow_draw_capacity(2 * self.draw_capacity)?;
+        }
+
+        Ok(self.draw_list.add(item))
+    }
+
+    // Recreates the uniform buffers with `capacity` slots, pointing the descriptor sets to the new
+    // buffers
+    fn grow_draw_capacity(&mut self, capacity: usize) -> AppResult<()> {
+        unsafe { self.context.device.device_wait_idle()? };
+
+        let uniform_buffers = Self::create_uniform_buffers(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            self.uniform_stride * capacity as u64,
+            self.frames.len(),
+        )?;
+
+        // The descriptor sets are rewritten with the new buffers before being used again
+        let mut old_buffers = Vec::with_capacity(uniform_buffers.len());
+        for (frame, uniform_buffer) in self.frames.iter_mut().zip(uniform_buffers) {
+            frame.descriptor_materials.fill(None);
+            old_buffers.push(std::mem::replace(&mut frame.uniform_buffer, uniform_buffer));
+        }
+
+        for (i, buffer) in old_buffers.iter().enumerate() {
+            let size = unsafe {
+                self.context
+                    .device
+                    .get_buffer_memory_requirements(buffer.buffer)
+                    .size
+            };
+            self.event_log.push(RendererEvent::ResourceDestroyed {
+                name: format!("uniform buffer {}", i),
+                size,
             });
-            descriptor_writes.push(vk::WriteDescriptorSet {
-                dst_set: desc_set,
-                dst_binding: 1,
-                dst_array_element: 0,
-                descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                p_image_info: &image_infos[i] as *const _,
-                ..Default::default()
+            unsafe { buffer.destroy(&self.context.device) };
+        }
+        for (i, frame) in self.frames.iter().enumerate() {
+            let buffer = &frame.uniform_buffer;
+            let size = unsafe {
+                self.context
+                    .device
+                    .get_buffer_memory_requirements(buffer.buffer)
+                    .size
+            };
+            self.event_log.push(RendererEvent::ResourceCreated {
+                name: format!("uniform buffer {}", i),
+                size,
             });
         }
+        self.draw_capacity = capacity;
 
-        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
-        Ok(descriptor_sets)
+        Ok(())
     }
 
-    fn create_command_buffers(
-        device: &Device,
-        command_pool: vk::CommandPool,
-        max_frame_in_flight: u32,
-    ) -> AppResult<Vec<vk::CommandBuffer>> {
-        let alloc_info = vk::CommandBufferAllocateInfo {
-            command_pool,
-            level: vk::CommandBufferLevel::PRIMARY,
-            command_buffer_count: max_frame_in_flight,
-            ..Default::default()
-        };
-        unsafe { Ok(device.allocate_command_buffers(&alloc_info)?) }
+    /// Returns the usage of the descriptor pools of the draw items, e.g. to size the initial
+    /// scene
+    pub fn descriptor_pool_stats(&self) -> DescriptorPoolStats {
+        self.descriptor_allocator.stats()
     }
 
-    fn begin_singe_time_command(
-        device: &Device,
-        command_pool: vk::CommandPool,
-    ) -> AppResult<vk::CommandBuffer> {
-        let alloc_info = vk::CommandBufferAllocateInfo {
-            command_pool,
-            level: vk::CommandBufferLevel::PRIMARY,
-            command_buffer_count: 1,
-            ..Default::default()
-        };
+    pub fn remove_draw_item(&mut self, id: DrawItemId) -> Option<DrawItem> {
+        if let Some(outlines) = &mut self.outlines {
+            outlines.set(id, None);
+        }
+        self.draw_list.remove(id)
+    }
 
-        unsafe {
-            let command_buffer = device.allocate_command_buffers(&alloc_info)?[0];
-            let begin_info = vk::CommandBufferBeginInfo {
-                flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
-                ..Default::default()
-            };
-            device.begin_command_buffer(command_buffer, &begin_info)?;
-            Ok(command_buffer)
+    pub fn draw_item(&self, id: DrawItemId) -> Option<&DrawItem> {
+        self.draw_list.get(id)
+    }
+
+    /// Returns the item to update, e.g. to move the object or change its material
+    pub fn draw_item_mut(&mut self, id: DrawItemId) -> Option<&mut DrawItem> {
+        self.draw_list.get_mut(id)
+    }
+
+    /// Returns the id of the textured quad in the draw list
+    pub fn mesh_item_id(&self) -> DrawItemId {
+        self.mesh_item_id
+    }
+
+    /// Outlines the draw item `id` in `color`, or stops outlining it when `color` is `None`.
+    /// Returns `false` if the item doesn't exist.
+    ///
+    /// The outlines are drawn over the scene by a pass of their own, with a stencil buffer: the
+    /// item is scaled by `OUTLINE_SCALE` around the origin of its mesh, and only the pixels
+    /// outside of the outlined items are filled.
+    pub fn set_outline(&mut self, id: DrawItemId, color: Option<Vec4>) -> AppResult<bool> {
+        if self.draw_list.get(id).is_none() {
+            return Ok(false);
         }
+
+        if self.outlines.is_none() {
+            self.outlines = Some(Outlines::new(
+                &self.context.instance,
+                &self.context.device,
+                self.context.physical_device,
+                self.pipeline.descriptor_set_layout,
+            )?);
+        }
+        self.outlines
+            .as_mut()
+            .unwrap()
+            .set(id, color.map(Into::into));
+
+        Ok(true)
+    }
+
+    /// Color of the outline of the draw item `id`, `None` if it isn't outlined
+    pub fn outline(&self, id: DrawItemId) -> Option<Vec4> {
+        self.outlines
+            .as_ref()?
+            .items()
+            .iter()
+            .find(|(item_id, _)| *item_id == id)
+            .map(|&(_, color)| color.into())
+    }
+
+    /// Returns the pickable objects, e.g. to register the objects drawn by render hooks
+    pub fn pickables_mut(&mut self) -> &mut Pickables {
+        &mut self.pickables
     }
 
-    fn end_single_time_command(
+    /// Returns the last renderer events, e.g. to attach them to a bug report
+    pub fn event_log(&self) -> &EventLog {
+        &self.event_log
+    }
+
+    /// Returns the name and the allocation size of the long lived resources
+    fn resource_sizes<'a>(
         device: &Device,
-        queue: vk::Queue,
-        command_pool: vk::CommandPool,
-        command_buffer: vk::CommandBuffer,
-    ) -> AppResult<()> {
+        placeholder_texture: &TextureHolder,
+        meshes: &[Option<MeshStorage>],
+        uniform_buffers: impl IntoIterator<Item = &'a MemoryMappedBuffer>,
+    ) -> Vec<(String, u64)> {
         unsafe {
-            device.end_command_buffer(command_buffer)?;
+            let mut sizes = vec![(
+                String::from("placeholder texture"),
+                device
+                    .get_image_memory_requirements(placeholder_texture.image.image)
+                    .size,
+            )];
+
+            for (i, mesh) in meshes.iter().enumerate() {
+                if let Some(mesh) = mesh {
+                    sizes.extend(Self::mesh_sizes(device, i, mesh));
+                }
+            }
+
+            for (i, buffer) in uniform_buffers.into_iter().enumerate() {
+                sizes.push((
+                    format!("uniform buffer {}", i),
+                    device.get_buffer_memory_requirements(buffer.buffer).size,
+                ));
+            }
+
+            sizes
+        }
+    }
+
+    fn mesh_sizes(device: &Device, index: usize, mesh: &MeshStorage) -> Vec<(String, u64)> {
+        let buffer_size = |buffer| unsafe { device.get_buffer_memory_requirements(buffer).size };
+
+        match mesh {
+            MeshStorage::Static(mesh) => vec![
+                (
+                    format!("mesh {} vertex buffer", index),
+                    buffer_size(mesh.vertex_buffer.buffer),
+                ),
+                (
+                    format!("mesh {} index buffer", index),
+                    buffer_size(mesh.index_buffer.buffer),
+                ),
+            ],
+            MeshStorage::Dynamic(mesh) => {
+                let mut sizes = vec![];
+                for (frame, buffer) in mesh.vertex_buffers.iter().enumerate() {
+                    sizes.push((
+                        format!("mesh {} vertex buffer {}", index, frame),
+                        buffer_size(buffer.buffer),
+                    ));
+                }
+                for (frame, buffer) in mesh.index_buffers.iter().enumerate() {
+                    sizes.push((
+                        format!("mesh {} index buffer {}", index, frame),
+                        buffer_size(buffer.buffer),
+                    ));
+                }
+                sizes
+            }
+        }
+    }
+
+    /// Returns the pool of fences and semaphores for transient operations, e.g. uploads made by
+    /// render hooks
+    pub fn sync_pool(&self) -> &SyncPool {
+        &self.resources.sync_pool
+    }
+
+    pub fn input(&self) -> &InputState {
+        &self.input
+    }
+
+    /// Returns the input state, e.g. to forward it the window and device events or change the
+    /// bindings
+    pub fn input_mut(&mut self) -> &mut InputState {
+        &mut self.input
+    }
+
+    pub fn camera(&self) -> &dyn CameraController {
+        self.camera.as_ref()
+    }
+
+    pub fn camera_mut(&mut self) -> &mut dyn CameraController {
+        self.camera.as_mut()
+    }
 
-            let submit_infos = [vk::SubmitInfo {
-                command_buffer_count: 1,
-                p_command_buffers: &command_buffer as *const _,
-                ..Default::default()
-            }];
+    /// Replaces the camera driving the view matrix, returns the previous one
+    pub fn set_camera(&mut self, camera: Box<dyn CameraController>) -> Box<dyn CameraController> {
+        std::mem::replace(&mut self.camera, camera)
+    }
 
-            device.queue_submit(queue, &submit_infos, vk::Fence::null())?;
-            device.queue_wait_idle(queue)?;
-            let command_buffers = [command_buffer];
-            device.free_command_buffers(command_pool, &command_buffers);
+    /// Uploads the font atlas of `context` and creates the renderer drawing the Dear ImGui draw
+    /// data over the frames, after the `RenderHook::AfterUi` hooks. The draw data is given with
+    /// `Application::render_imgui` once the frame of the context is rendered.
+    #[cfg(feature = "imgui")]
+    pub fn enable_imgui(&mut self, context: &mut imgui::Context) -> AppResult<()> {
+        if self.imgui_renderer.is_some() {
+            return Ok(());
         }
+
+        let fonts = context.fonts();
+        let atlas = fonts.build_rgba32_texture();
+        let atlas = RgbaImage::from_raw(atlas.width, atlas.height, atlas.data.to_vec()).unwrap();
+        fonts.tex_id = imgui::TextureId::new(0);
+        context.set_renderer_name(Some(String::from("vulkan-tutorial")));
+
+        let font_atlas = Self::upload_textures(
+            &self.context.instance,
+            &self.context.device,
+            self.context.graphics_queue,
+            self.context.physical_device,
+            self.resources.command_pool,
+            &self.resources.sync_pool,
+            &mut [DecodedTexture::from_rgba(vec![atlas])],
+        )?
+        .pop()
+        .unwrap();
+        let sampler = self
+            .resources
+            .sampler_cache
+            .get(&self.context.device, TextureFiltering::default())?;
+        self.imgui_renderer = Some(ImguiRenderer::new(
+            &self.context.device,
+            self.post_chain.composite_render_pass(),
+            font_atlas,
+            sampler,
+            self.frames.len(),
+        )?);
+
         Ok(())
     }
 
-    fn create_sync_objects(
-        device: &Device,
-        max_frame_in_flight: u32,
-    ) -> AppResult<(Vec<vk::Semaphore>, Vec<vk::Semaphore>, Vec<vk::Fence>)> {
-        let semaphore_info = vk::SemaphoreCreateInfo::default();
-        let fence_info = vk::FenceCreateInfo {
-            flags: vk::FenceCreateFlags::SIGNALED,
-            ..Default::default()
-        };
-        let mut image_avaible_semaphores = vec![];
-        let mut render_done_semaphores = vec![];
-        let mut in_flight_fences = vec![];
+    /// Draws `draw_data` over the next frames, until it is replaced. Does nothing until
+    /// `Application::enable_imgui` is called.
+    #[cfg(feature = "imgui")]
+    pub fn render_imgui(&mut self, draw_data: &imgui::DrawData) {
+        if let Some(imgui_renderer) = &mut self.imgui_renderer {
+            imgui_renderer.set_draw_data(draw_data);
+        }
+    }
 
-        for _ in 0..max_frame_in_flight {
-            unsafe {
-                image_avaible_semaphores.push(device.create_semaphore(&semaphore_info, None)?);
+    /// Runs the rhai script at `path` to tweak the scene, see `ScriptHost`. The script is reloaded
+    /// whenever the file is modified and only reaches the items and materials exposed to it.
+    #[cfg(feature = "scripting")]
+    pub fn load_script<P: AsRef<Path>>(&mut self, path: P) -> AppResult<()> {
+        self.script_host.load(path.as_ref())
+    }
+
+    /// Lets the script refer to `item` as `name`
+    #[cfg(feature = "scripting")]
+    pub fn expose_script_item(&mut self, name: &str, item: DrawItemId) {
+        self.script_host.expose_item(name, item);
+    }
 
-                render_done_semaphores.push(device.create_semaphore(&semaphore_info, None)?);
+    /// Lets the script refer to `material` as `name`
+    #[cfg(feature = "scripting")]
+    pub fn expose_script_material(&mut self, name: &str, material: MaterialHandle) {
+        self.script_host.expose_material(name, material);
+    }
 
-                in_flight_fences.push(device.create_fence(&fence_info, None)?)
+    /// Runs the script for the frame and applies the settings it changed
+    #[cfg(feature = "scripting")]
+    fn run_script(&mut self) -> AppResult<()> {
+        for command in self.script_host.update() {
+            match command {
+                ScriptCommand::FlyCamera {
+                    position,
+                    yaw,
+                    pitch,
+                } => {
+                    self.camera = Box::new(FlyCamera::new(position, yaw, pitch));
+                }
+                ScriptCommand::OrbitCamera {
+                    target,
+                    yaw,
+                    pitch,
+                    distance,
+                } => {
+                    self.camera = Box::new(OrbitCamera::new(target, yaw, pitch, distance));
+                }
+                ScriptCommand::Paused(paused) => self.paused = paused,
+                ScriptCommand::StreamBudget(budget) => {
+                    self.resources.texture_streamer.set_budget(budget)
+                }
+                ScriptCommand::MaterialLayer { material, layer } => {
+                    let Some(handle) = self.script_host.material(&material) else {
+                        Self::report_script_name("material", &material);
+                        continue;
+                    };
+                    let material = &mut self.materials[handle.0];
+                    let layers = self
+                        .resources
+                        .texture_manager
+                        .handle_of(material.texture_view)
+                        .and_then(|texture| self.resources.texture_manager.get(texture))
+                        .and_then(|texture| texture.array_layers);
+                    // The layer of a material created from a plain texture can't be set
+                    if let MaterialKind::ArrayLayer(material_layer) = &mut material.kind {
+                        if layers.is_some_and(|count| layer < count) {
+                            *material_layer = layer;
+                        }
+                    }
+                }
+                ScriptCommand::ItemMaterial { item, material } => {
+                    let Some(id) = self.script_host.item(&item) else {
+                        Self::report_script_name("item", &item);
+                        continue;
+                    };
+                    let Some(handle) = self.script_host.material(&material) else {
+                        Self::report_script_name("material", &material);
+                        continue;
+                    };
+                    if let Some(item) = self.draw_list.get_mut(id) {
+                        item.material = handle;
+                    }
+                }
+                ScriptCommand::ItemTransform { item, transform } => {
+                    let Some(id) = self.script_host.item(&item) else {
+                        Self::report_script_name("item", &item);
+                        continue;
+                    };
+                    if let Some(item) = self.draw_list.get_mut(id) {
+                        item.transform = transform;
+                    }
+                }
+                ScriptCommand::PostEffect { name, enabled } => {
+                    if enabled {
+                        if let Some((order, effect)) = self.script_host.take_disabled_effect(&name)
+                        {
+                            self.insert_post_effect(order, effect)?;
+                        }
+                    } else if let Some(order) = self.post_chain.effect_order(&name) {
+                        if let Some(effect) = self.remove_post_effect(&name)? {
+                            self.script_host.disable_effect(order, effect);
+                        }
+                    }
+                }
             }
         }
 
-        Ok((
-            image_avaible_semaphores,
-            render_done_semaphores,
-            in_flight_fences,
-        ))
+        Ok(())
     }
 
-    /// Sets up the debug messenger for the validation layers
-    #[cfg(feature = "vlayers")]
-    fn setup_debug_messenger(
-        entry: &Entry,
-        instance: &Instance,
-    ) -> AppResult<DebugMessengerHolder> {
-        let debug_util_ext = debug_utils::Instance::new(entry, instance);
+    #[cfg(feature = "scripting")]
+    fn report_script_name(kind: &str, name: &str) {
+        error!(?name, "script error: no {} exposed", kind);
+    }
 
-        let create_info = Self::debug_messenger_create_info();
+    /// Submits `command_buffer` on the graphics queue along with the next frame, in a single
+    /// `vkQueueSubmit`. The frame command buffer being `WorkType::Main`, the command buffers are
+    /// executed in `WorkType` order.
+    ///
+    /// The command buffer must come from a pool of the graphics family and stay valid until the
+    /// frame is done rendering.
+    pub fn submit_with_frame(&mut self, work_type: WorkType, command_buffer: vk::CommandBuffer) {
+        self.scheduler
+            .add(self.context.graphics_queue, work_type, command_buffer);
+    }
 
-        let debug_messenger =
-            unsafe { debug_util_ext.create_debug_utils_messenger(&create_info, None)? };
+    /// Returns the physical and logical size of the rendering surface
+    pub fn surface_size(&self) -> SurfaceSize {
+        self.surface_size
+    }
 
-        Ok(DebugMessengerHolder {
-            debug_util_ext,
-            debug_messenger,
-        })
+    /// Inserts a post effect in the chain, after every effect with an `order` lower or equal to
+    /// `order`. Fails if an effect with the same name is already part of the chain.
+    pub fn insert_post_effect(&mut self, order: i32, effect: Box<dyn PostEffect>) -> AppResult<()> {
+        unsafe {
+            self.context.device.device_wait_idle()?;
+        }
+
+        let name = String::from(effect.name());
+        self.destroy_scene_framebuffers();
+        self.post_chain.insert(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            self.pipeline.renderpass,
+            order,
+            effect,
+        )?;
+        self.event_log
+            .push(RendererEvent::PostEffectInserted { name });
+
+        Ok(())
     }
 
-    /// Creates the VkDebugUtilsMessengerCreateInfoEXT for the debug messenger
-    #[cfg(feature = "vlayers")]
-    fn debug_messenger_create_info() -> vk::DebugUtilsMessengerCreateInfoEXT<'static> {
-        vk::DebugUtilsMessengerCreateInfoEXT {
-            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
-            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
-                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
-            pfn_user_callback: Some(Self::debug_callback),
-            ..Default::default()
+    /// Removes the post effect named `name` from the chain, after destroying its resources
+    pub fn remove_post_effect(&mut self, name: &str) -> AppResult<Option<Box<dyn PostEffect>>> {
+        unsafe {
+            self.context.device.device_wait_idle()?;
+        }
+
+        self.destroy_scene_framebuffers();
+        let effect = self.post_chain.remove(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            self.pipeline.renderpass,
+            name,
+        )?;
+        if effect.is_some() {
+            self.event_log.push(RendererEvent::PostEffectRemoved {
+                name: String::from(name),
+            });
         }
+
+        Ok(effect)
     }
 
-    /// Is called for every validation layers event
-    #[cfg(feature = "vlayers")]
-    extern "system" fn debug_callback(
-        message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-        _message_types: vk::DebugUtilsMessageTypeFlagsEXT,
-        p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-        _p_user_data: *mut std::ffi::c_void,
-    ) -> vk::Bool32 {
-        if message_severity >= LAYER_SEVERITY {
-            let message = unsafe { CStr::from_ptr((*p_callback_data).p_message) };
-            eprintln!(
-                "{} {:?}",
-                "Validation layer:".truecolor(255, 172, 28),
-                message
-            );
+    /// Registers `hook` to record commands at `point` of every frame, letting the caller extend
+    /// the frame without modifying the renderer.
+    ///
+    /// Hooks inside a render pass must use pipelines compatible with
+    /// `RenderHookContext::render_pass`.
+    pub fn add_render_hook(&mut self, point: RenderHook, hook: RenderHookFn) -> RenderHookId {
+        self.render_hooks.add(point, hook)
+    }
+
+    /// Unregisters a hook, returning it if it was registered
+    pub fn remove_render_hook(&mut self, id: RenderHookId) -> Option<RenderHookFn> {
+        self.render_hooks.remove(id)
+    }
+
+    /// Names of the post effects, in execution order
+    pub fn post_effects(&self) -> impl Iterator<Item = &str> {
+        self.post_chain.effect_names()
+    }
+
+    /// Enables or disables the screen space ambient occlusion, darkening the creases and the
+    /// contacts between the meshes.
+    ///
+    /// It runs as the `ssao` and `ssao_blur` post effects, ahead of the other effects, along
+    /// with a prepass drawing the normal and depth of the draw list.
+    pub fn set_ssao(&mut self, enabled: bool) -> AppResult<()> {
+        if enabled == self.ssao_enabled() {
+            return Ok(());
         }
 
-        vk::FALSE
+        if enabled {
+            let ssao = Ssao::new(
+                &self.context.instance,
+                &self.context.device,
+                self.context.graphics_queue,
+                self.context.physical_device,
+                self.resources.command_pool,
+                &self.resources.sync_pool,
+            )?;
+            self.insert_post_effect(SSAO_ORDER, Box::new(ssao))?;
+            self.insert_post_effect(SSAO_ORDER, Box::<SsaoBlur>::default())?;
+        } else {
+            self.remove_post_effect(SSAO_BLUR_NAME)?;
+            self.remove_post_effect(SSAO_NAME)?;
+        }
+
+        Ok(())
     }
 
-    unsafe fn destroy_buffer(&self, buffer: &BufferHolder) {
-        self.device.destroy_buffer(buffer.buffer, None);
-        self.device.free_memory(buffer.memory, None);
+    pub fn ssao_enabled(&self) -> bool {
+        self.post_effects().any(|name| name == SSAO_NAME)
     }
 
-    unsafe fn destroy_memory_mapped_buffer(&self, buffer: &MemoryMappedBuffer) {
-        self.device.destroy_buffer(buffer.buffer, None);
-        self.device.free_memory(buffer.memory, None);
+    /// Enables or disables the fast approximate anti-aliasing, run as the `fxaa` post effect
+    /// after the other effects
+    pub fn set_fxaa(&mut self, enabled: bool) -> AppResult<()> {
+        if enabled == self.fxaa_enabled() {
+            return Ok(());
+        }
+
+        if enabled {
+            self.insert_post_effect(FXAA_ORDER, Box::<Fxaa>::default())
+        } else {
+            self.remove_post_effect(FXAA_NAME).map(|_| ())
+        }
     }
 
-    fn cleanup_swapchain(&self) {
-        unsafe {
-            for (i, _) in self.swapchain_frame_buffers.iter().enumerate() {
-                self.device
-                    .destroy_framebuffer(self.swapchain_frame_buffers[i], None);
-            }
+    pub fn fxaa_enabled(&self) -> bool {
+        self.post_effects().any(|name| name == FXAA_NAME)
+    }
 
-            for &image_view in self.swapchain.swapchain_image_views.iter() {
-                self.device.destroy_image_view(image_view, None)
-            }
+    /// Enables or disables the temporal anti-aliasing, run as the `taa` post effect after the
+    /// ambient occlusion.
+    ///
+    /// While enabled, the projection is jittered by a sub-pixel offset every frame and a prepass
+    /// draws the motion of the draw list, the effect accumulating the frames along it.
+    pub fn set_taa(&mut self, enabled: bool) -> AppResult<()> {
+        if enabled == self.taa_enabled() {
+            return Ok(());
+        }
 
-            self.swapchain
-                .swapchain_ext
-                .destroy_swapchain(self.swapchain.swapchain, None);
+        if enabled {
+            self.insert_post_effect(TAA_ORDER, Box::<Taa>::default())
+        } else {
+            self.remove_post_effect(TAA_NAME).map(|_| ())
         }
     }
 
-    /// Destroys the Vulkan objects
-    pub fn cleanup(&self) {
+    pub fn taa_enabled(&self) -> bool {
+        self.post_effects().any(|name| name == TAA_NAME)
+    }
+
+    /// Changes how the HDR output of the post chain is mapped to the swapchain, from the next
+    /// frame. Defaults to the linear operator with an exposure of 1.
+    pub fn set_tonemapping(&mut self, tonemapping: Tonemapping) {
+        self.post_chain.set_tonemapping(tonemapping);
+    }
+
+    pub fn tonemapping(&self) -> Tonemapping {
+        self.post_chain.tonemapping()
+    }
+
+    /// Saves the last presented frame to `path`, converting it from the swapchain format and
+    /// color space to either a sRGB PNG or a linear EXR (when `path` ends with `.exr`)
+    pub fn save_screenshot<P: AsRef<Path>>(&self, path: P) -> AppResult<()> {
+        self.read_back_screenshot()?.save(path)
+    }
+
+    /// Copies the last presented frame to the clipboard as a sRGB image. The frame is read back
+    /// right away, then converted and written to the clipboard in the background.
+    #[cfg(feature = "clipboard")]
+    pub fn copy_screenshot_to_clipboard(&self) -> AppResult<ClipboardCopy> {
+        Ok(self.read_back_screenshot()?.copy_to_clipboard())
+    }
+
+    fn read_back_screenshot(&self) -> AppResult<RawScreenshot> {
+        let image_index = self
+            .presented_image
+            .ok_or_else(|| AppError::new(AppErrorType::NothingPresented))?;
+
+        let bytes_per_pixel = screenshot::bytes_per_pixel(self.swapchain.image_format)
+            .filter(|_| self.swapchain.readable)
+            .ok_or_else(|| AppError::new(AppErrorType::UnsupportedScreenshotFormat))?;
+
         unsafe {
-            self.device.device_wait_idle().unwrap();
+            self.context.device.device_wait_idle()?;
+        }
+
+        // With exclusive sharing the presented image belongs to the present family, which is
+        // expected to support transfers
+        let (queue, command_pool) = match &self.swapchain.present_transfer {
+            Some(transfer) => (self.context.present_queue, transfer.command_pool),
+            None => (self.context.graphics_queue, self.resources.command_pool),
+        };
 
-            self.cleanup_swapchain();
+        let extent = self.swapchain.extent;
+        let bytes = Self::read_back_image(
+            &self.context.instance,
+            &self.context.device,
+            queue,
+            self.context.physical_device,
+            command_pool,
+            &self.resources.sync_pool,
+            self.swapchain.swapchain_images[image_index as usize],
+            extent,
+            bytes_per_pixel,
+        )?;
 
-            self.destroy_buffer(&self.vertex_buffer);
-            self.destroy_buffer(&self.index_buffer);
+        Ok(RawScreenshot {
+            width: extent.width,
+            height: extent.height,
+            format: self.swapchain.image_format,
+            color_space: self.swapchain.color_space,
+            bytes,
+        })
+    }
 
-            self.device.destroy_sampler(self.texture_sampler, None);
-            self.device
-                .destroy_image_view(self.texture_image_view, None);
-            self.device.destroy_image(self.texture_image.image, None);
-            self.device.free_memory(self.texture_image.memory, None);
+    /// Is called for every validation layers event
+    #[cfg(feature = "vlayers")]
+    extern "system" fn debug_callback(
+        message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        _message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+        p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+        _p_user_data: *mut std::ffi::c_void,
+    ) -> vk::Bool32 {
+        use tracing::{debug, info, warn};
 
-            for buffer in &self.uniform_buffers {
-                self.destroy_memory_mapped_buffer(buffer);
+        let message = unsafe { CStr::from_ptr((*p_callback_data).p_message) }.to_string_lossy();
+        match message_severity {
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
+                error!(target: "validation", "{}", message)
+            }
+            vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => {
+                warn!(target: "validation", "{}", message)
+            }
+            vk::DebugUtilsMessageSeverityFlagsEXT::INFO => {
+                info!(target: "validation", "{}", message)
             }
+            _ => debug!(target: "validation", "{}", message),
+        }
 
-            self.device
-                .destroy_descriptor_pool(self.descriptor_pool, None);
-            self.device
-                .destroy_descriptor_set_layout(self.pipeline.descriptor_set_layout, None);
+        vk::FALSE
+    }
 
-            self.device.destroy_pipeline(self.pipeline.pipeline, None);
-            self.device
-                .destroy_pipeline_layout(self.pipeline.pipeline_layout, None);
+    /// Destroys the Vulkan objects.
+    ///
+    /// Only called when the application is dropped, including while unwinding from a panic, so
+    /// the GPU is idle before anything is destroyed and nothing can use the objects afterwards.
+    fn cleanup(&mut self) {
+        unsafe {
+            // Panicking again while unwinding would abort, the objects are destroyed regardless
+            if let Err(err) = self.context.device.device_wait_idle() {
+                error!(%err, "failed to wait for the device");
+            }
 
-            self.device
-                .destroy_render_pass(self.pipeline.renderpass, None);
+            self.swapchain.destroy(&self.context.device);
+            self.post_chain.destroy(&self.context.device);
+
+            for (name, size) in Self::resource_sizes(
+                &self.context.device,
+                &self.resources.placeholder_texture,
+                &self.resources.meshes,
+                self.frames.iter().map(|frame| &frame.uniform_buffer),
+            ) {
+                self.event_log
+                    .push(RendererEvent::ResourceDestroyed { name, size });
+            }
 
-            for i in 0..MAX_FRAMES_IN_FLIGHT {
-                self.device
-                    .destroy_semaphore(self.image_avaible_semaphores[i], None);
-                self.device
-                    .destroy_semaphore(self.render_done_semaphores[i], None);
-                self.device.destroy_fence(self.in_flight_fences[i], None);
+            for texture in self.resources.texture_manager.iter() {
+                let image = self.resources.texture_manager.image(texture).unwrap();
+                self.event_log.push(RendererEvent::ResourceDestroyed {
+                    name: format!("texture {}", texture.0),
+                    size: self
+                        .context
+                        .device
+                        .get_image_memory_requirements(image)
+                        .size,
+                });
             }
 
-            self.device.destroy_command_pool(self.command_pool, None);
+            if let Some(virtual_texturing) = &mut self.virtual_texturing {
+                virtual_texturing.destroy(&self.context.device);
+            }
+            if let Some(reflection_probes) = &mut self.reflection_probes {
+                reflection_probes.destroy(&self.context.device);
+            }
+            if let Some(outlines) = &mut self.outlines {
+                outlines.destroy(&self.context.device);
+            }
+            if let Some(texture_arrays) = &mut self.texture_arrays {
+                texture_arrays.destroy(&self.context.device);
+            }
+            if let Some(pbr_pipeline) = &mut self.pbr_pipeline {
+                pbr_pipeline.destroy(&self.context.device);
+            }
+            self.shader_materials.destroy(&self.context.device);
+            self.debug_hud.destroy(&self.context.device);
+            self.debug_draw.destroy(&self.context.device);
+            self.particles.destroy(&self.context.device);
+            if let Some(gpu_particles) = &mut self.gpu_particles {
+                gpu_particles.destroy(&self.context.device);
+            }
+            if let Some(mesh_shading) = &mut self.mesh_shading {
+                mesh_shading.destroy(&self.context.device);
+            }
+            if let Some(morph_targets) = &mut self.morph_targets {
+                morph_targets.destroy(&self.context.device);
+            }
+            if let Some(ray_tracing) = &mut self.ray_tracing {
+                ray_tracing.destroy(&self.context.device);
+            }
+            if let Some(scene_accel) = &mut self.scene_accel {
+                scene_accel.destroy(&self.context.device);
+            }
+            self.text_renderer.destroy(&self.context.device);
+            #[cfg(feature = "imgui")]
+            if let Some(imgui_renderer) = &mut self.imgui_renderer {
+                imgui_renderer.destroy(&self.context.device);
+            }
+            if let Some(terrain) = &mut self.terrain {
+                terrain.destroy(&self.context.device);
+            }
+            if let Some(skybox) = &mut self.skybox {
+                skybox.destroy(&self.context.device);
+            }
+            if let Some(image_based_lighting) = &mut self.image_based_lighting {
+                image_based_lighting.destroy(&self.context.device);
+            }
 
-            self.device.destroy_device(None);
+            self.descriptor_allocator.destroy(&self.context.device);
+            self.scene_set_writer.destroy(&self.context.device);
+            self.pipeline.destroy(&self.context.device);
+
+            for frame in self.frames.iter() {
+                frame.uniform_buffer.destroy(&self.context.device);
+                handle_registry::unregister(frame.image_available);
+                self.context
+                    .device
+                    .destroy_semaphore(frame.image_available, None);
+                handle_registry::unregister(frame.render_done);
+                self.context
+                    .device
+                    .destroy_semaphore(frame.render_done, None);
+                handle_registry::unregister(frame.in_flight);
+                self.context.device.destroy_fence(frame.in_flight, None);
+            }
 
-            #[cfg(feature = "vlayers")]
-            self.debug_messenger
-                .debug_util_ext
-                .destroy_debug_utils_messenger(self.debug_messenger.debug_messenger, None);
+            self.resources.destroy(&self.context.device);
+        };
+        self.context.destroy();
 
-            self.surface
-                .surface_ext
-                .destroy_surface(self.surface.surface, None);
+        handle_registry::report_leaks();
+    }
+}
 
-            self.instance.destroy_instance(None);
-        };
+impl Drop for Application {
+    fn drop(&mut self) {
+        self.cleanup();
     }
 }
@@ -0,0 +1,603 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use ash::{vk, Device, Instance};
+use image::RgbaImage;
+use tracing::warn;
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    geometry::{Mat4, Point3, Vec3},
+    handle_registry, load_shader,
+    mesh_cache::{vertex_bits, CacheReader},
+    AppResult, Application, SyncPool, Vertex,
+};
+
+/// Lights a lightmap can be baked with, the size of the arrays of the bake shaders
+pub const MAX_BAKED_LIGHTS: usize = 16;
+/// Extension of the files the baked lightmaps are cached in
+pub const LIGHTMAP_CACHE_EXTENSION: &str = "lightmap";
+
+const CACHE_MAGIC: [u8; 4] = *b"VTLM";
+// Bumped whenever the layout of the cache or the bake shaders change
+const CACHE_VERSION: u32 = 1;
+
+// Stored as sRGB so the bytes read back can be uploaded like any other texture
+const LIGHTMAP_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+// Texels around the triangles filled with their neighbours, so the bilinear filtering at the
+// edges of the triangles doesn't read the background
+const DILATION_PASSES: usize = 4;
+
+/// Light whose intensity decreases with the square of the distance, down to 0 at `radius`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointLight {
+    pub position: Point3,
+    pub color: Vec3,
+    pub radius: f32,
+}
+
+/// Lighting baked in a lightmap
+#[derive(Clone, Debug, PartialEq)]
+pub struct LightmapSettings {
+    /// Width and height of the lightmap
+    pub resolution: u32,
+    /// Light reaching every texel
+    pub ambient: Vec3,
+    /// At most `MAX_BAKED_LIGHTS` lights
+    pub lights: Vec<PointLight>,
+    /// Directory the lightmaps are cached in, baked every time if `None`
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for LightmapSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 256,
+            ambient: Vec3::new(0.2, 0.2, 0.2),
+            lights: Vec::new(),
+            cache_dir: None,
+        }
+    }
+}
+
+// std140 layout of the uniform buffer of the bake shaders
+#[repr(C)]
+struct BakeUniforms {
+    model: [[f32; 4]; 4],
+    ambient: [f32; 4],
+    light_positions: [[f32; 4]; MAX_BAKED_LIGHTS],
+    light_colors: [[f32; 4]; MAX_BAKED_LIGHTS],
+    light_count: u32,
+    _padding: [u32; 3],
+}
+
+impl BakeUniforms {
+    fn new(transform: Mat4, settings: &LightmapSettings) -> Self {
+        let mut light_positions = [[0.0; 4]; MAX_BAKED_LIGHTS];
+        let mut light_colors = [[0.0; 4]; MAX_BAKED_LIGHTS];
+        for (i, light) in settings.lights.iter().enumerate() {
+            let position = light.position;
+            light_positions[i] = [position.x, position.y, position.z, light.radius];
+            light_colors[i] = [light.color.x, light.color.y, light.color.z, 0.0];
+        }
+
+        Self {
+            model: transform.into(),
+            ambient: [
+                settings.ambient.x,
+                settings.ambient.y,
+                settings.ambient.z,
+                0.0,
+            ],
+            light_positions,
+            light_colors,
+            light_count: settings.lights.len() as u32,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Returns the lightmap of the mesh drawn with `transform`, loaded from the cache directory of
+/// the settings if it was already baked with the same mesh, transform and settings, baked then
+/// cached otherwise. The second value tells whether the lightmap was baked.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn load_or_bake(
+    instance: &Instance,
+    device: &Device,
+    physical_device: vk::PhysicalDevice,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    sync_pool: &SyncPool,
+    vertices: &[Vertex],
+    indices: &[u32],
+    transform: Mat4,
+    settings: &LightmapSettings,
+) -> AppResult<(RgbaImage, bool)> {
+    if vertices.is_empty() || indices.is_empty() {
+        return AppResult::Err(AppError::new(AppErrorType::EmptyMesh));
+    }
+    if settings.lights.len() > MAX_BAKED_LIGHTS {
+        return AppResult::Err(AppError::new(AppErrorType::TooManyBakedLights));
+    }
+
+    let cache_path = settings.cache_dir.as_ref().map(|dir| {
+        let key = cache_key(vertices, indices, transform, settings);
+        dir.join(format!("{:016x}.{}", key, LIGHTMAP_CACHE_EXTENSION))
+    });
+    if let Some(cache_path) = &cache_path {
+        match read_cache(cache_path, settings.resolution) {
+            Ok(Some(lightmap)) => return Ok((lightmap, false)),
+            Ok(None) => (),
+            Err(err) => warn!(path = ?cache_path, %err, "invalid lightmap cache"),
+        }
+    }
+
+    let mut lightmap = bake(
+        instance,
+        device,
+        physical_device,
+        queue,
+        command_pool,
+        sync_pool,
+        vertices,
+        indices,
+        transform,
+        settings,
+    )?;
+    dilate(&mut lightmap);
+
+    if let Some(cache_path) = &cache_path {
+        if let Err(err) = write_cache(cache_path, &lightmap) {
+            warn!(path = ?cache_path, %err, "failed to write the lightmap cache");
+        }
+    }
+
+    Ok((lightmap, true))
+}
+
+/// Renders the lighting of every triangle where it lies in the lightmap, then reads it back
+#[allow(clippy::too_many_arguments)]
+fn bake(
+    instance: &Instance,
+    device: &Device,
+    physical_device: vk::PhysicalDevice,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    sync_pool: &SyncPool,
+    vertices: &[Vertex],
+    indices: &[u32],
+    transform: Mat4,
+    settings: &LightmapSettings,
+) -> AppResult<RgbaImage> {
+    let resolution = settings.resolution;
+
+    let vertex_buffer = Application::create_vertex_buffer(
+        instance,
+        device,
+        queue,
+        physical_device,
+        vertices,
+        command_pool,
+        sync_pool,
+    )?;
+    let index_buffer = Application::create_index_buffer(
+        instance,
+        device,
+        queue,
+        physical_device,
+        indices,
+        command_pool,
+        sync_pool,
+    )?;
+    let uniform_buffer = Application::create_buffer_with_data(
+        instance,
+        device,
+        queue,
+        physical_device,
+        &[BakeUniforms::new(transform, settings)],
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::UNIFORM_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        command_pool,
+        sync_pool,
+    )?;
+
+    let render_pass = Application::create_render_pass(
+        device,
+        LIGHTMAP_FORMAT,
+        vk::AttachmentLoadOp::CLEAR,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+    )?;
+
+    let bindings = [vk::DescriptorSetLayoutBinding {
+        binding: 0,
+        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+        descriptor_count: 1,
+        stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        ..Default::default()
+    }];
+    let layout_info = vk::DescriptorSetLayoutCreateInfo {
+        binding_count: bindings.len() as u32,
+        p_bindings: bindings.as_ptr(),
+        ..Default::default()
+    };
+    let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+    handle_registry::register(set_layout);
+
+    let pipeline_layout = Application::create_pipeline_layout(device, &[set_layout], &[])?;
+    // The winding of the triangles in the lightmap has nothing to do with their facing
+    let pipeline = Application::create_scene_pipeline(
+        device,
+        render_pass,
+        vk::SampleCountFlags::TYPE_1,
+        pipeline_layout,
+        &load_shader("lightmap_uv.vert", include_bytes!("spirv/lightmap_uv.spv"))?,
+        &load_shader(
+            "lightmap_bake.frag",
+            include_bytes!("spirv/lightmap_bake.spv"),
+        )?,
+        vk::CullModeFlags::NONE,
+    )?;
+
+    let pool_sizes = [vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::UNIFORM_BUFFER,
+        descriptor_count: 1,
+    }];
+    let pool_info = vk::DescriptorPoolCreateInfo {
+        max_sets: 1,
+        pool_size_count: pool_sizes.len() as u32,
+        p_pool_sizes: pool_sizes.as_ptr(),
+        ..Default::default()
+    };
+    let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+    handle_registry::register(descriptor_pool);
+
+    let alloc_info = vk::DescriptorSetAllocateInfo {
+        descriptor_pool,
+        descriptor_set_count: 1,
+        p_set_layouts: &set_layout as *const _,
+        ..Default::default()
+    };
+    let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+    let buffer_info = vk::DescriptorBufferInfo {
+        buffer: uniform_buffer.buffer,
+        offset: 0,
+        range: vk::WHOLE_SIZE,
+    };
+    let descriptor_write = vk::WriteDescriptorSet {
+        dst_set: descriptor_set,
+        dst_binding: 0,
+        descriptor_count: 1,
+        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+        p_buffer_info: &buffer_info as *const _,
+        ..Default::default()
+    };
+    unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+    let target = Application::create_image(
+        instance,
+        device,
+        physical_device,
+        resolution,
+        resolution,
+        1,
+        LIGHTMAP_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    let target_view = Application::create_image_view(device, target.image, LIGHTMAP_FORMAT, 1)?;
+
+    let attachments = [target_view];
+    let framebuffer_info = vk::FramebufferCreateInfo {
+        render_pass,
+        attachment_count: attachments.len() as u32,
+        p_attachments: attachments.as_ptr(),
+        width: resolution,
+        height: resolution,
+        layers: 1,
+        ..Default::default()
+    };
+    let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None)? };
+    handle_registry::register(framebuffer);
+
+    let buffer_size = resolution as u64 * resolution as u64 * 4;
+    let readback_buffer = Application::create_buffer(
+        instance,
+        device,
+        physical_device,
+        buffer_size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+
+    let render_area = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent: vk::Extent2D {
+            width: resolution,
+            height: resolution,
+        },
+    };
+    // The uncovered texels keep a null alpha so they can be dilated
+    let clear_values = [vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 0.0],
+        },
+    }];
+    let render_pass_info = vk::RenderPassBeginInfo {
+        render_pass,
+        framebuffer,
+        render_area,
+        clear_value_count: clear_values.len() as u32,
+        p_clear_values: clear_values.as_ptr(),
+        ..Default::default()
+    };
+    let viewports = [vk::Viewport {
+        x: 0.0,
+        y: 0.0,
+        width: resolution as f32,
+        height: resolution as f32,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    }];
+
+    let to_transfer = [vk::MemoryBarrier {
+        src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+        ..Default::default()
+    }];
+    let to_host = [vk::MemoryBarrier {
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        dst_access_mask: vk::AccessFlags::HOST_READ,
+        ..Default::default()
+    }];
+    let regions = [vk::BufferImageCopy {
+        buffer_offset: 0,
+        buffer_row_length: 0,
+        buffer_image_height: 0,
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+        image_extent: vk::Extent3D {
+            width: resolution,
+            height: resolution,
+            depth: 1,
+        },
+    }];
+
+    let mut texels = vec![0u8; buffer_size as usize];
+    unsafe {
+        let command_buffer = Application::begin_singe_time_command(device, command_pool)?;
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &render_pass_info,
+            vk::SubpassContents::INLINE,
+        );
+        device.cmd_set_viewport(command_buffer, 0, &viewports);
+        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            0,
+            &[descriptor_set],
+            &[],
+        );
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+        device.cmd_bind_index_buffer(
+            command_buffer,
+            index_buffer.buffer,
+            0,
+            vk::IndexType::UINT32,
+        );
+        device.cmd_draw_indexed(command_buffer, indices.len() as u32, 1, 0, 0, 0);
+        device.cmd_end_render_pass(command_buffer);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &to_transfer,
+            &[],
+            &[],
+        );
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            target.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            readback_buffer.buffer,
+            &regions,
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &to_host,
+            &[],
+            &[],
+        );
+        Application::end_single_time_command(
+            device,
+            queue,
+            command_pool,
+            sync_pool,
+            command_buffer,
+        )?;
+
+        let buffer_memory_ptr = device.map_memory(
+            readback_buffer.memory,
+            0,
+            buffer_size,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        std::ptr::copy(
+            buffer_memory_ptr as *const u8,
+            texels.as_mut_ptr(),
+            buffer_size as usize,
+        );
+        device.unmap_memory(readback_buffer.memory);
+
+        for buffer in [readback_buffer, uniform_buffer, index_buffer, vertex_buffer] {
+            handle_registry::unregister(buffer.buffer);
+            device.destroy_buffer(buffer.buffer, None);
+            handle_registry::unregister(buffer.memory);
+            device.free_memory(buffer.memory, None);
+        }
+        handle_registry::unregister(framebuffer);
+        device.destroy_framebuffer(framebuffer, None);
+        handle_registry::unregister(target_view);
+        device.destroy_image_view(target_view, None);
+        handle_registry::unregister(target.image);
+        device.destroy_image(target.image, None);
+        handle_registry::unregister(target.memory);
+        device.free_memory(target.memory, None);
+        handle_registry::unregister(descriptor_pool);
+        device.destroy_descriptor_pool(descriptor_pool, None);
+        handle_registry::unregister(pipeline);
+        device.destroy_pipeline(pipeline, None);
+        handle_registry::unregister(pipeline_layout);
+        device.destroy_pipeline_layout(pipeline_layout, None);
+        handle_registry::unregister(set_layout);
+        device.destroy_descriptor_set_layout(set_layout, None);
+        handle_registry::unregister(render_pass);
+        device.destroy_render_pass(render_pass, None);
+    }
+
+    Ok(RgbaImage::from_raw(resolution, resolution, texels).unwrap())
+}
+
+/// Fills the texels no triangle covers with the average of their covered neighbours, growing the
+/// covered area by a texel per pass, then makes the lightmap opaque
+fn dilate(lightmap: &mut RgbaImage) {
+    let (width, height) = lightmap.dimensions();
+    for _ in 0..DILATION_PASSES {
+        let source = lightmap.clone();
+        for (x, y, texel) in lightmap.enumerate_pixels_mut() {
+            if texel[3] != 0 {
+                continue;
+            }
+
+            let mut sum = [0u32; 3];
+            let mut count = 0;
+            for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                    continue;
+                }
+                let neighbour = source.get_pixel(nx as u32, ny as u32);
+                if neighbour[3] != 0 {
+                    for (channel, sum) in sum.iter_mut().enumerate() {
+                        *sum += neighbour[channel] as u32;
+                    }
+                    count += 1;
+                }
+            }
+
+            if count == 0 {
+                continue;
+            }
+            *texel = image::Rgba([
+                (sum[0] / count) as u8,
+                (sum[1] / count) as u8,
+                (sum[2] / count) as u8,
+                255,
+            ]);
+        }
+    }
+
+    for texel in lightmap.pixels_mut() {
+        texel[3] = 255;
+    }
+}
+
+/// Hash of everything the lightmap depends on, naming its cache. It is only stable for a given
+/// build, the cache is only meant for the machine that wrote it.
+fn cache_key(
+    vertices: &[Vertex],
+    indices: &[u32],
+    transform: Mat4,
+    settings: &LightmapSettings,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    CACHE_VERSION.hash(&mut hasher);
+    for vertex in vertices {
+        vertex_bits(vertex).hash(&mut hasher);
+    }
+    indices.hash(&mut hasher);
+
+    let transform: [[f32; 4]; 4] = transform.into();
+    let light_values = settings.lights.iter().flat_map(|light| {
+        let (position, color) = (light.position, light.color);
+        [
+            position.x,
+            position.y,
+            position.z,
+            color.x,
+            color.y,
+            color.z,
+            light.radius,
+        ]
+    });
+    let ambient = settings.ambient;
+    for value in transform
+        .iter()
+        .flatten()
+        .copied()
+        .chain([ambient.x, ambient.y, ambient.z])
+        .chain(light_values)
+    {
+        value.to_bits().hash(&mut hasher);
+    }
+    settings.resolution.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+/// Layout: magic, version, width, height, RGBA8 texels in native byte order
+fn write_cache(path: &Path, lightmap: &RgbaImage) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut bytes = Vec::with_capacity(16 + lightmap.as_raw().len());
+    bytes.extend_from_slice(&CACHE_MAGIC);
+    bytes.extend_from_slice(&CACHE_VERSION.to_ne_bytes());
+    bytes.extend_from_slice(&lightmap.width().to_ne_bytes());
+    bytes.extend_from_slice(&lightmap.height().to_ne_bytes());
+    bytes.extend_from_slice(lightmap.as_raw());
+
+    let mut file = fs::File::create(path)?;
+    file.write_all(&bytes)
+}
+
+/// Returns `None` if there is no cache or if it was written by another version
+fn read_cache(path: &Path, resolution: u32) -> io::Result<Option<RgbaImage>> {
+    let mut bytes = Vec::new();
+    match fs::File::open(path) {
+        Ok(mut file) => file.read_to_end(&mut bytes)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let mut reader = CacheReader { bytes: &bytes };
+    if reader.take(4)? != CACHE_MAGIC || reader.u32()? != CACHE_VERSION {
+        return Ok(None);
+    }
+    if (reader.u32()?, reader.u32()?) != (resolution, resolution) {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+
+    let texels = reader.take(resolution as usize * resolution as usize * 4)?;
+    Ok(RgbaImage::from_raw(resolution, resolution, texels.to_vec()))
+}
@@ -1,8 +1,19 @@
-use vulkan_tutorial::Application;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Instant,
+};
+
+use ash::vk;
+use clap::{Parser, ValueEnum};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+use vulkan_tutorial::{prelude::*, ApplicationBuilder, SoftwareRendering, SwapchainSharing};
 
 use winit::{
     application::ApplicationHandler,
-    event::WindowEvent,
+    event::{DeviceEvent, DeviceId, WindowEvent},
     event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
     window::{Window, WindowId},
 };
@@ -10,11 +21,410 @@ use winit::{
 const NAME: &str = "Vulkan tutorial";
 const WIDTH: u32 = 800;
 const HEIGHT: u32 = 600;
+const SCREENSHOT_PATH: &str = "screenshot.png";
+const TEXTURE_PATH: &str = "src/texture.jpg";
+// Loaded when it exists, to tweak the scene without recompiling
+#[cfg(feature = "scripting")]
+const SCRIPT_PATH: &str = "scene.rhai";
+// Number of frames the frame time is averaged over
+const BENCHMARK_FRAMES: u32 = 1000;
+const OUTLINE_COLOR: Vec4 = Vec4::new(1.0, 0.6, 0.0, 1.0);
+
+/// Renders a textured quad, or a model, with the renderer of the tutorial
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Prints the diagnostics of the Vulkan environment instead of opening the window
+    #[arg(long)]
+    diagnose: bool,
+    /// Only renders with a CPU implementation of Vulkan, overriding the environment variable
+    #[arg(long, conflicts_with = "hardware")]
+    software: bool,
+    /// Never renders with a CPU implementation of Vulkan, overriding the environment variable
+    #[arg(long)]
+    hardware: bool,
+    /// Width of the window or of the headless surface
+    #[arg(long, default_value_t = WIDTH)]
+    width: u32,
+    /// Height of the window or of the headless surface
+    #[arg(long, default_value_t = HEIGHT)]
+    height: u32,
+    /// OBJ file drawn instead of the quad, flattened on its XY plane
+    #[arg(long)]
+    model: Option<PathBuf>,
+    /// Texture of the quad or of the model
+    #[arg(long, default_value = TEXTURE_PATH)]
+    texture: PathBuf,
+    /// Presentation mode, when the surface supports it
+    #[arg(long, value_enum)]
+    present_mode: Option<PresentMode>,
+    /// Prefers the device whose name contains GPU, ignoring the case
+    #[arg(long)]
+    gpu: Option<String>,
+    /// Renders to a headless surface instead of a window, saving the last frame as
+    /// screenshot.png
+    #[arg(long)]
+    headless: bool,
+    /// Exits after N frames, printing the average frame time. The headless rendering draws a
+    /// single frame by default.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    frames: Option<u32>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PresentMode {
+    Fifo,
+    FifoRelaxed,
+    Mailbox,
+    Immediate,
+}
+
+impl From<PresentMode> for vk::PresentModeKHR {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::Fifo => vk::PresentModeKHR::FIFO,
+            PresentMode::FifoRelaxed => vk::PresentModeKHR::FIFO_RELAXED,
+            PresentMode::Mailbox => vk::PresentModeKHR::MAILBOX,
+            PresentMode::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        }
+    }
+}
+
+impl Cli {
+    /// Options of the application, the surface aside
+    fn builder(&self) -> ApplicationBuilder {
+        let software_rendering = if self.software {
+            SoftwareRendering::Require
+        } else if self.hardware {
+            SoftwareRendering::Forbid
+        } else {
+            SoftwareRendering::from_env()
+        };
+
+        let mut builder = Application::builder()
+            .app_name(NAME)
+            .software_rendering(software_rendering)
+            .texture(&self.texture);
+        if let Some(model) = &self.model {
+            builder = builder.model(model, import_obj);
+        }
+        if let Some(present_mode) = self.present_mode {
+            builder = builder.present_mode(present_mode.into());
+        }
+        if let Some(gpu) = &self.gpu {
+            builder = builder.preferred_gpu(gpu);
+        }
+        builder
+    }
+}
+
+/// Imports the positions and texture coordinates of the faces of an OBJ file, dropping the z
+/// coordinates as the vertices are 2D. The faces are split in fans of triangles.
+fn import_obj(path: &Path) -> AppResult<(Vec<Vertex>, Vec<u32>)> {
+    let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, line.to_string());
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    for line in fs::read_to_string(path)?.lines() {
+        let mut words = line.split_whitespace();
+        let keyword = words.next();
+        let numbers = || -> Result<Vec<f32>, io::Error> {
+            line.split_whitespace()
+                .skip(1)
+                .map(|word| word.parse().map_err(|_| invalid(line)))
+                .collect()
+        };
+
+        match keyword {
+            Some("v") => match numbers()?[..] {
+                [x, y, ..] => positions.push(Vec2::new(x, y)),
+                _ => return Err(invalid(line).into()),
+            },
+            Some("vt") => match numbers()?[..] {
+                // The OBJ texture coordinates start at the bottom of the image
+                [u, v, ..] => uvs.push(Vec2::new(u, 1.0 - v)),
+                _ => return Err(invalid(line).into()),
+            },
+            Some("f") => {
+                let first = vertices.len() as u32;
+                for corner in words {
+                    let mut references = corner.split('/');
+                    let position = obj_reference(references.next(), &positions)
+                        .ok_or_else(|| invalid(line))?;
+                    let uv = obj_reference(references.next(), &uvs).unwrap_or(Vec2::new(0.0, 0.0));
+                    vertices.push(Vertex::new(position, Vec3::new(1.0, 1.0, 1.0), uv));
+                }
+                let count = vertices.len() as u32 - first;
+                if count < 3 {
+                    return Err(invalid(line).into());
+                }
+                for corner in 1..count - 1 {
+                    indices.extend([first, first + corner, first + corner + 1]);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok((vertices, indices))
+}
+
+/// Element of `elements` an OBJ reference points to, starting at 1 or counting from the end when
+/// negative
+fn obj_reference(reference: Option<&str>, elements: &[Vec2]) -> Option<Vec2> {
+    let index: i64 = reference?.parse().ok()?;
+    let index = if index < 0 {
+        elements.len() as i64 + index
+    } else {
+        index - 1
+    };
+    elements.get(usize::try_from(index).ok()?).copied()
+}
 
-#[derive(Default)]
 struct App {
-    window: Option<Window>,
+    cli: Cli,
+    // Dropped before the window, which must outlive the surface
     application: Option<Application>,
+    window: Option<Window>,
+    fly_camera: bool,
+
+    // Frames drawn since the start, for `--frames`
+    frames_drawn: u32,
+    run_start: Option<Instant>,
+
+    benchmark_frames: u32,
+    benchmark_start: Option<Instant>,
+
+    // Failure which stopped the event loop, reported by the exit status
+    error: Option<AppError>,
+
+    // Copy of the last frame to the clipboard still running
+    #[cfg(feature = "clipboard")]
+    clipboard_copy: Option<vulkan_tutorial::ClipboardCopy>,
+}
+
+impl App {
+    fn new(cli: Cli) -> Self {
+        Self {
+            cli,
+            application: None,
+            window: None,
+            fly_camera: false,
+            frames_drawn: 0,
+            run_start: None,
+            benchmark_frames: 0,
+            benchmark_start: None,
+            error: None,
+            #[cfg(feature = "clipboard")]
+            clipboard_copy: None,
+        }
+    }
+
+    /// Runs the application actions triggered during the frame
+    fn handle_actions(&mut self) {
+        let application = self.application.as_mut().unwrap();
+        let input = application.input();
+        let screenshot = input.action_pressed(actions::SCREENSHOT);
+        #[cfg(feature = "clipboard")]
+        let copy_screenshot = input.action_pressed(actions::COPY_SCREENSHOT);
+        let switch_camera = input.action_pressed(actions::SWITCH_CAMERA);
+        let switch_sharing = input.action_pressed(actions::SWITCH_SWAPCHAIN_SHARING);
+        let pause = input.action_pressed(actions::PAUSE);
+        let toggle_ssao = input.action_pressed(actions::TOGGLE_SSAO);
+        let switch_tonemap = input.action_pressed(actions::SWITCH_TONEMAP_OPERATOR);
+        let toggle_anti_aliasing = input.action_pressed(actions::TOGGLE_ANTI_ALIASING);
+        let toggle_taa = input.action_pressed(actions::TOGGLE_TAA);
+        let toggle_hud = input.action_pressed(actions::TOGGLE_HUD);
+        let toggle_wireframe = input.action_pressed(actions::TOGGLE_WIREFRAME);
+        let toggle_outline = input.action_pressed(actions::TOGGLE_OUTLINE);
+        let pick = input
+            .action_pressed(actions::PICK)
+            .then(|| input.cursor())
+            .flatten();
+        let inspect = input
+            .action_down(actions::INSPECT_PIXEL)
+            .then(|| input.cursor())
+            .flatten();
+
+        if let Some(cursor) = pick {
+            match application.pick(cursor) {
+                Some(hit) => info!(id = ?hit.id, distance = hit.distance, "picked"),
+                None => info!("nothing picked"),
+            }
+        }
+
+        if let Some(cursor) = inspect {
+            match application.inspect_pixel(cursor) {
+                Ok(Some(sample)) => {
+                    let [r, g, b, a] = sample.color;
+                    let color = format_args!("({:.3}, {:.3}, {:.3}, {:.3})", r, g, b, a);
+                    match sample.hit {
+                        Some(hit) => info!(
+                            x = sample.x,
+                            y = sample.y,
+                            %color,
+                            id = ?hit.id,
+                            distance = hit.distance,
+                            "inspected pixel"
+                        ),
+                        None => info!(
+                            x = sample.x,
+                            y = sample.y,
+                            %color,
+                            "inspected pixel, nothing under the cursor"
+                        ),
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => error!("{}", err),
+            }
+        }
+
+        if pause {
+            application.set_paused(!application.is_paused());
+        }
+
+        if toggle_ssao {
+            let enabled = !application.ssao_enabled();
+            match application.set_ssao(enabled) {
+                Ok(()) => info!("ambient occlusion {}", if enabled { "on" } else { "off" }),
+                Err(err) => error!("{}", err),
+            }
+        }
+
+        if screenshot {
+            match application.save_screenshot(SCREENSHOT_PATH) {
+                Ok(()) => info!(path = SCREENSHOT_PATH, "screenshot saved"),
+                Err(err) => error!("{}", err),
+            }
+        }
+
+        #[cfg(feature = "clipboard")]
+        {
+            if copy_screenshot {
+                match application.copy_screenshot_to_clipboard() {
+                    Ok(copy) => self.clipboard_copy = Some(copy),
+                    Err(err) => error!("{}", err),
+                }
+            }
+            if let Some(result) = self.clipboard_copy.as_ref().and_then(|copy| copy.poll()) {
+                self.clipboard_copy = None;
+                match result {
+                    Ok(()) => info!("screenshot copied to the clipboard"),
+                    Err(err) => error!("{}", err),
+                }
+            }
+        }
+
+        if switch_camera {
+            self.fly_camera = !self.fly_camera;
+            if self.fly_camera {
+                application.set_camera(Box::new(FlyCamera::default()));
+            } else {
+                application.set_camera(Box::new(OrbitCamera::default()));
+            }
+        }
+
+        if toggle_anti_aliasing {
+            let enabled = !application.fxaa_enabled();
+            match application.set_fxaa(enabled) {
+                Ok(()) => info!("FXAA {}", if enabled { "on" } else { "off" }),
+                Err(err) => error!("{}", err),
+            }
+        }
+
+        if toggle_taa {
+            let enabled = !application.taa_enabled();
+            match application.set_taa(enabled) {
+                Ok(()) => info!("TAA {}", if enabled { "on" } else { "off" }),
+                Err(err) => error!("{}", err),
+            }
+        }
+
+        if toggle_hud {
+            application.set_hud_visible(!application.hud_visible());
+        }
+
+        if toggle_wireframe {
+            let enabled = !application.wireframe_enabled();
+            match application.set_wireframe(enabled) {
+                Ok(()) => info!("wireframe {}", if enabled { "on" } else { "off" }),
+                Err(err) => error!("{}", err),
+            }
+        }
+
+        if toggle_outline {
+            let id = application.mesh_item_id();
+            let color = match application.outline(id) {
+                Some(_) => None,
+                None => Some(OUTLINE_COLOR),
+            };
+            if let Err(err) = application.set_outline(id, color) {
+                error!("{}", err);
+            }
+        }
+
+        if switch_tonemap {
+            let mut tonemapping = application.tonemapping();
+            tonemapping.operator = match tonemapping.operator {
+                TonemapOperator::Linear => TonemapOperator::Reinhard,
+                TonemapOperator::Reinhard => TonemapOperator::Aces,
+                TonemapOperator::Aces => TonemapOperator::Linear,
+            };
+            application.set_tonemapping(tonemapping);
+            info!(operator = ?tonemapping.operator, "tonemap operator switched");
+        }
+
+        if switch_sharing {
+            let sharing = match application.swapchain_sharing() {
+                SwapchainSharing::Concurrent => SwapchainSharing::Exclusive,
+                SwapchainSharing::Exclusive => SwapchainSharing::Concurrent,
+            };
+            if let Err(err) = application.set_swapchain_sharing(sharing) {
+                error!("{}", err);
+            }
+            self.benchmark_frames = 0;
+            self.benchmark_start = None;
+        }
+    }
+
+    /// Prints the average frame time every `BENCHMARK_FRAMES` frames, to compare the swapchain
+    /// sharing modes
+    fn benchmark_frame(&mut self) {
+        let start = *self.benchmark_start.get_or_insert_with(Instant::now);
+        self.benchmark_frames += 1;
+
+        if self.benchmark_frames == BENCHMARK_FRAMES {
+            let sharing = self.application.as_ref().unwrap().swapchain_sharing();
+            let frame_time = start.elapsed().as_secs_f64() * 1000.0 / BENCHMARK_FRAMES as f64;
+            info!(?sharing, "{:.3} ms/frame", frame_time);
+
+            self.benchmark_frames = 0;
+            self.benchmark_start = None;
+        }
+    }
+
+    /// Counts the frame for `--frames`, returns whether the run is over
+    fn count_frame(&mut self) -> bool {
+        self.run_start.get_or_insert_with(Instant::now);
+        self.frames_drawn += 1;
+        let Some(frames) = self.cli.frames else {
+            return false;
+        };
+
+        if self.frames_drawn >= frames {
+            print_frame_time(self.run_start.unwrap(), self.frames_drawn);
+            return true;
+        }
+        false
+    }
+}
+
+fn print_frame_time(start: Instant, frames: u32) {
+    let frame_time = start.elapsed().as_secs_f64() * 1000.0 / frames as f64;
+    info!(frames, "{:.3} ms/frame", frame_time);
 }
 
 impl ApplicationHandler for App {
@@ -25,38 +435,149 @@ impl ApplicationHandler for App {
 
         let window_attributes = Window::default_attributes()
             .with_title(NAME)
-            .with_inner_size(winit::dpi::LogicalSize::new(WIDTH, HEIGHT));
+            .with_inner_size(winit::dpi::LogicalSize::new(
+                self.cli.width,
+                self.cli.height,
+            ));
 
         let window = event_loop.create_window(window_attributes).unwrap();
-        let application = Application::create(event_loop, &window).unwrap();
+        let mut application = match self.cli.builder().build(event_loop, &window) {
+            Ok(application) => application,
+            Err(err) => {
+                self.error = Some(err);
+                event_loop.exit();
+                return;
+            }
+        };
+
+        application.set_hot_reload(true);
+        application.set_frame_validation(cfg!(debug_assertions));
+
+        #[cfg(feature = "scripting")]
+        {
+            let mesh_item_id = application.mesh_item_id();
+            application.expose_script_item("quad", mesh_item_id);
+            let material = application.draw_item(mesh_item_id).unwrap().material;
+            application.expose_script_material("texture", material);
+            if std::path::Path::new(SCRIPT_PATH).is_file() {
+                if let Err(err) = application.load_script(SCRIPT_PATH) {
+                    error!("{}", err);
+                }
+            }
+        }
 
         self.window = Some(window);
         self.application = Some(application);
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
-        let application = self.application.as_mut().unwrap();
+        // Missing once the creation failed
+        let Some(application) = self.application.as_mut() else {
+            return;
+        };
+        application.input_mut().handle_window_event(&event);
+
         match event {
             WindowEvent::CloseRequested => {
-                println!("The close button was pressed; stopping");
+                info!("the close button was pressed, stopping");
                 event_loop.exit();
             }
 
-            WindowEvent::Resized(_) => application.request_resize(),
+            WindowEvent::Resized(size) => application.request_resize(size),
+
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                application.set_scale_factor(scale_factor)
+            }
 
             WindowEvent::RedrawRequested => {
-                application.draw_frame().unwrap();
+                self.handle_actions();
+                let application = self.application.as_mut().unwrap();
+                if let Err(err) = application.draw_frame() {
+                    error!("renderer events:\n{}", application.event_log().dump());
+                    self.error = Some(err);
+                    event_loop.exit();
+                    return;
+                }
+                self.benchmark_frame();
+                if self.count_frame() {
+                    event_loop.exit();
+                    return;
+                }
                 self.window.as_ref().unwrap().request_redraw();
             }
             _ => (),
         }
     }
+
+    fn device_event(&mut self, _event_loop: &ActiveEventLoop, _id: DeviceId, event: DeviceEvent) {
+        if let Some(application) = self.application.as_mut() {
+            application.input_mut().handle_device_event(&event);
+        }
+    }
 }
 
-fn main() {
+/// Draws `--frames` frames, one by default, to a headless surface and saves the last one
+fn run_headless(cli: &Cli) -> AppResult<()> {
+    let size = winit::dpi::PhysicalSize::new(cli.width, cli.height);
+    let mut application = cli.builder().build_headless(size)?;
+
+    let frames = cli.frames.unwrap_or(1);
+    let start = Instant::now();
+    for _ in 0..frames {
+        application.draw_frame()?;
+    }
+    if cli.frames.is_some() {
+        print_frame_time(start, frames);
+    }
+
+    application.save_screenshot(SCREENSHOT_PATH)?;
+    info!(path = SCREENSHOT_PATH, "saved the last frame");
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    // `RUST_LOG` filters the logs, the information and above being printed otherwise
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+    // The failures are reported by the exit status, for the scripts running the diagnostics or a
+    // number of frames
+    if cli.diagnose {
+        return match Application::diagnose() {
+            Ok(diagnostics) => {
+                print!("{}", diagnostics);
+                ExitCode::SUCCESS
+            }
+            Err(err) => {
+                error!("{}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if cli.headless {
+        return match run_headless(&cli) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                error!("{}", err);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut app = App::default();
+    let mut app = App::new(cli);
     event_loop.run_app(&mut app).unwrap();
+    match app.error {
+        Some(err) => {
+            error!("{}", err);
+            ExitCode::FAILURE
+        }
+        None => ExitCode::SUCCESS,
+    }
 }
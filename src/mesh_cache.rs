@@ -0,0 +1,377 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Write},
+    mem,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use tracing::warn;
+
+use crate::{Aabb, AppResult, Point3, Vertex};
+
+const CACHE_MAGIC: [u8; 4] = *b"VTMC";
+// Bumped whenever the layout of the cache or of `Vertex` changes
+const CACHE_VERSION: u32 = 3;
+/// Extension appended to the source file name to name its cache
+pub const MESH_CACHE_EXTENSION: &str = "meshcache";
+
+const VERTEX_WORDS: usize = mem::size_of::<Vertex>() / 4;
+const _: () = assert!(mem::size_of::<Vertex>() == VERTEX_WORDS * 4);
+
+/// Mesh data ready to be uploaded
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    /// Bounds of the vertex positions, flat on the z axis
+    pub bounds: Aabb,
+}
+
+impl MeshData {
+    /// Merges the identical vertices of an indexed mesh, e.g. the ones an OBJ importer duplicates
+    /// for every face, and computes its bounds. `None` if there is no vertex.
+    pub fn optimized(vertices: &[Vertex], indices: &[u32]) -> Option<Self> {
+        let mut unique_vertices = Vec::new();
+        let mut remap: HashMap<[u32; VERTEX_WORDS], u32> = HashMap::new();
+        let new_indices: Vec<u32> = vertices
+            .iter()
+            .map(|vertex| {
+                *remap.entry(vertex_bits(vertex)).or_insert_with(|| {
+                    unique_vertices.push(vertex.clone());
+                    unique_vertices.len() as u32 - 1
+                })
+            })
+            .collect();
+
+        let indices = indices
+            .iter()
+            .map(|&index| new_indices[index as usize])
+            .collect();
+        let bounds = Aabb::from_points(unique_vertices.iter().map(|vertex| {
+            let position = vertex.position();
+            Point3::new(position.x, position.y, 0.0)
+        }))?;
+
+        Some(Self {
+            vertices: unique_vertices,
+            indices,
+            bounds,
+        })
+    }
+}
+
+/// Loads the mesh imported from `source`, e.g. an OBJ or a glTF file.
+///
+/// The first import goes through `import`, then the optimized mesh is written to a flat binary
+/// file next to the source (`<source>.meshcache`) which is loaded instead on the following runs,
+/// as long as the source isn't modified. The mesh is imported again if the cache is missing, stale
+/// or invalid, the latter being reported.
+pub fn load_mesh_cached<P, F>(source: P, import: F) -> AppResult<MeshData>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&Path) -> AppResult<(Vec<Vertex>, Vec<u32>)>,
+{
+    let source = source.as_ref();
+    let cache_path = cache_path(source);
+    let stamp = source_stamp(source)?;
+
+    match read_cache(&cache_path, stamp) {
+        Ok(Some(mesh)) => return Ok(mesh),
+        Ok(None) => (),
+        Err(err) => warn!(path = ?cache_path, %err, "invalid mesh cache"),
+    }
+
+    let (vertices, indices) = import(source)?;
+    let Some(mesh) = MeshData::optimized(&vertices, &indices) else {
+        return Ok(MeshData {
+            vertices,
+            indices,
+            bounds: Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0)),
+        });
+    };
+
+    if let Err(err) = write_cache(&cache_path, stamp, &mesh) {
+        warn!(path = ?cache_path, %err, "failed to write the mesh cache");
+    }
+
+    Ok(mesh)
+}
+
+fn cache_path(source: &Path) -> PathBuf {
+    let mut file_name = source.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(MESH_CACHE_EXTENSION);
+    source.with_file_name(file_name)
+}
+
+/// Size and modification time of the source, a cache written for another stamp is stale
+fn source_stamp(source: &Path) -> io::Result<(u64, u64)> {
+    let metadata = fs::metadata(source)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    Ok((metadata.len(), modified))
+}
+
+/// Layout: magic, version, source stamp, vertex and index counts, bounds, vertices, indices.
+/// The indices are stored on 16 bits when every vertex can be addressed with them. Everything is
+/// stored in native byte order, the cache is only meant for the machine that wrote it.
+fn write_cache(path: &Path, stamp: (u64, u64), mesh: &MeshData) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(
+        64 + mesh.vertices.len() * mem::size_of::<Vertex>() + mesh.indices.len() * 4,
+    );
+    bytes.extend_from_slice(&CACHE_MAGIC);
+    bytes.extend_from_slice(&CACHE_VERSION.to_ne_bytes());
+    bytes.extend_from_slice(&stamp.0.to_ne_bytes());
+    bytes.extend_from_slice(&stamp.1.to_ne_bytes());
+    bytes.extend_from_slice(&(mesh.vertices.len() as u32).to_ne_bytes());
+    bytes.extend_from_slice(&(mesh.indices.len() as u32).to_ne_bytes());
+    for value in [mesh.bounds.min, mesh.bounds.max]
+        .iter()
+        .flat_map(|point| [point.x, point.y, point.z])
+    {
+        bytes.extend_from_slice(&value.to_ne_bytes());
+    }
+    for vertex in &mesh.vertices {
+        for word in vertex_bits(vertex) {
+            bytes.extend_from_slice(&word.to_ne_bytes());
+        }
+    }
+    if short_indices(mesh.vertices.len()) {
+        for &index in &mesh.indices {
+            bytes.extend_from_slice(&(index as u16).to_ne_bytes());
+        }
+    } else {
+        for index in &mesh.indices {
+            bytes.extend_from_slice(&index.to_ne_bytes());
+        }
+    }
+
+    let mut file = fs::File::create(path)?;
+    file.write_all(&bytes)
+}
+
+/// Returns `None` if there is no cache or if it is stale
+fn read_cache(path: &Path, stamp: (u64, u64)) -> io::Result<Option<MeshData>> {
+    let mut bytes = Vec::new();
+    match fs::File::open(path) {
+        Ok(mut file) => file.read_to_end(&mut bytes)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let mut reader = CacheReader { bytes: &bytes };
+    if reader.take(4)? != CACHE_MAGIC || reader.u32()? != CACHE_VERSION {
+        return Ok(None);
+    }
+    if (reader.u64()?, reader.u64()?) != stamp {
+        return Ok(None);
+    }
+
+    let vertex_count = reader.u32()? as usize;
+    let index_count = reader.u32()? as usize;
+    let mut bounds = [0.0; 6];
+    for value in bounds.iter_mut() {
+        *value = f32::from_bits(reader.u32()?);
+    }
+
+    // The counts of an invalid cache can't make the allocations exceed the cache size
+    let mut vertices =
+        Vec::with_capacity(vertex_count.min(reader.bytes.len() / mem::size_of::<Vertex>()));
+    for _ in 0..vertex_count {
+        let mut words = [0; VERTEX_WORDS];
+        for word in words.iter_mut() {
+            *word = reader.u32()?;
+        }
+        vertices.push(vertex_from_bits(words));
+    }
+
+    let mut indices = Vec::with_capacity(index_count.min(reader.bytes.len() / 2));
+    for _ in 0..index_count {
+        let index = if short_indices(vertex_count) {
+            reader.u16()? as u32
+        } else {
+            reader.u32()?
+        };
+        if index as usize >= vertex_count {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        indices.push(index);
+    }
+
+    Ok(Some(MeshData {
+        vertices,
+        indices,
+        bounds: Aabb::new(
+            Point3::new(bounds[0], bounds[1], bounds[2]),
+            Point3::new(bounds[3], bounds[4], bounds[5]),
+        ),
+    }))
+}
+
+/// Reads the native byte order values of a cache, failing at its end
+pub(crate) struct CacheReader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> CacheReader<'a> {
+    pub(crate) fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < count {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_ne_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_ne_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_ne_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+fn short_indices(vertex_count: usize) -> bool {
+    vertex_count <= u16::MAX as usize + 1
+}
+
+pub(crate) fn vertex_bits(vertex: &Vertex) -> [u32; VERTEX_WORDS] {
+    // Safety: `Vertex` is `repr(C)` and only made of f32
+    unsafe { mem::transmute_copy(vertex) }
+}
+
+fn vertex_from_bits(words: [u32; VERTEX_WORDS]) -> Vertex {
+    // Safety: `Vertex` is `repr(C)` and only made of f32, any bit pattern is valid
+    unsafe { mem::transmute_copy(&words) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::{Vec2, Vec3};
+
+    const STAMP: (u64, u64) = (42, 1_000);
+
+    // Path in the temporary directory, unique to the test and the process
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mesh-cache-{}-{}", std::process::id(), name))
+    }
+
+    fn vertex(x: f32, y: f32) -> Vertex {
+        Vertex::new(Vec2::new(x, y), Vec3::new(1.0, 0.5, 0.0), Vec2::new(x, y))
+    }
+
+    // Quad whose two triangles duplicate the vertices of their shared edge
+    fn quad() -> (Vec<Vertex>, Vec<u32>) {
+        let vertices = vec![
+            vertex(0.0, 0.0),
+            vertex(1.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 1.0),
+            vertex(0.0, 0.0),
+        ];
+        (vertices, vec![0, 1, 2, 3, 4, 5])
+    }
+
+    fn quad_mesh() -> MeshData {
+        let (vertices, indices) = quad();
+        MeshData::optimized(&vertices, &indices).unwrap()
+    }
+
+    #[test]
+    fn merges_identical_vertices() {
+        let mesh = quad_mesh();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, [0, 1, 2, 2, 3, 0]);
+        assert_eq!(mesh.bounds.min, Point3::new(0.0, 0.0, 0.0));
+        assert_eq!(mesh.bounds.max, Point3::new(1.0, 1.0, 0.0));
+        assert!(MeshData::optimized(&[], &[]).is_none());
+    }
+
+    #[test]
+    fn round_trips_meshes() {
+        let path = temp_path("round-trip");
+        let mesh = quad_mesh();
+        write_cache(&path, STAMP, &mesh).unwrap();
+        let cached = read_cache(&path, STAMP);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(cached.unwrap(), Some(mesh));
+    }
+
+    #[test]
+    fn ignores_missing_and_stale_caches() {
+        let path = temp_path("stale");
+        assert!(read_cache(&path, STAMP).unwrap().is_none());
+
+        write_cache(&path, STAMP, &quad_mesh()).unwrap();
+        let stale = read_cache(&path, (STAMP.0, STAMP.1 + 1));
+        // Caches written by another version of the layout are stale too
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[4..8].copy_from_slice(&(CACHE_VERSION + 1).to_ne_bytes());
+        fs::write(&path, bytes).unwrap();
+        let other_version = read_cache(&path, STAMP);
+        fs::remove_file(&path).unwrap();
+
+        assert!(stale.unwrap().is_none());
+        assert!(other_version.unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_corrupt_caches() {
+        let path = temp_path("corrupt");
+        write_cache(&path, STAMP, &quad_mesh()).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let truncated = read_cache(&path, STAMP);
+        // The last index, on 16 bits, addressing a vertex past the end
+        let mut out_of_range = bytes.clone();
+        let last = out_of_range.len() - 2;
+        out_of_range[last..].copy_from_slice(&4u16.to_ne_bytes());
+        fs::write(&path, out_of_range).unwrap();
+        let invalid = read_cache(&path, STAMP);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(truncated.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(invalid.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn imports_only_when_the_source_changes() {
+        let source = temp_path("source.obj");
+        fs::write(&source, "first").unwrap();
+        let imports = Cell::new(0);
+        let import = |_: &Path| {
+            imports.set(imports.get() + 1);
+            Ok(quad())
+        };
+
+        let first = load_mesh_cached(&source, import).unwrap();
+        let second = load_mesh_cached(&source, import).unwrap();
+        let imports_before_change = imports.get();
+        // The size is part of the stamp, the modification time may not have changed
+        fs::write(&source, "modified").unwrap();
+        load_mesh_cached(&source, import).unwrap();
+        fs::remove_file(cache_path(&source)).unwrap();
+        fs::remove_file(&source).unwrap();
+
+        assert_eq!(first, quad_mesh());
+        assert_eq!(second, first);
+        assert_eq!(imports_before_change, 1);
+        assert_eq!(imports.get(), 2);
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+
+use ash::{ext::mesh_shader, vk, Device, Instance};
+
+use crate::{
+    descriptor_allocator::DescriptorAllocator, handle_registry, load_shader, AppResult,
+    Application, BufferHolder, Mat4, SyncPool, Vertex,
+};
+
+/// Most vertices a meshlet references, the limit of the mesh shader outputs
+pub const MAX_MESHLET_VERTICES: usize = 64;
+
+/// Most triangles in a meshlet, a multiple of 4 below 128 as advised by the vendors
+pub const MAX_MESHLET_TRIANGLES: usize = 124;
+
+// Meshlets handled by a task shader workgroup, one per invocation
+const TASK_WORKGROUP_SIZE: u32 = 32;
+
+/// Group of triangles of a mesh drawn by a single mesh shader workgroup
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct Meshlet {
+    // Smallest and largest position of its vertices, in the XY plane of the mesh
+    bounds: [f32; 4],
+    // Range of the meshlet in the vertex list
+    vertex_offset: u32,
+    vertex_count: u32,
+    // Range of the meshlet in the triangle list
+    triangle_offset: u32,
+    triangle_count: u32,
+}
+
+#[derive(Default)]
+struct MeshletData {
+    meshlets: Vec<Meshlet>,
+    // Index of the mesh vertex behind each vertex of the meshlets
+    vertices: Vec<u32>,
+    // The three vertices of a triangle, as indices into the vertices of its meshlet packed in
+    // the low bytes
+    triangles: Vec<u32>,
+}
+
+#[repr(C)]
+struct DrawConstants {
+    model_view_proj: Mat4,
+    meshlet_count: u32,
+}
+
+struct MeshletMesh {
+    // The vertices of the mesh, the meshlets, their vertex list and their triangle list
+    buffers: [BufferHolder; 4],
+    descriptor_set: vk::DescriptorSet,
+    meshlet_count: u32,
+}
+
+/// Splits the triangles of `indices` into meshlets, in the order of the list. A meshlet is
+/// closed as soon as the next triangle would exceed its vertex or triangle limit.
+fn build_meshlets(vertices: &[Vertex], indices: &[u32]) -> MeshletData {
+    let mut data = MeshletData::default();
+    let mut current = Meshlet::default();
+    // Position of the mesh vertices in the current meshlet
+    let mut local_indices: HashMap<u32, u32> = HashMap::new();
+
+    for triangle in indices.chunks_exact(3) {
+        let new_vertices = triangle
+            .iter()
+            .filter(|vertex| !local_indices.contains_key(vertex))
+            .count();
+        if current.vertex_count as usize + new_vertices > MAX_MESHLET_VERTICES
+            || current.triangle_count as usize == MAX_MESHLET_TRIANGLES
+        {
+            data.meshlets.push(current);
+            current = Meshlet {
+                vertex_offset: data.vertices.len() as u32,
+                triangle_offset: data.triangles.len() as u32,
+                ..Default::default()
+            };
+            local_indices.clear();
+        }
+
+        let mut packed = 0;
+        for (corner, &vertex) in triangle.iter().enumerate() {
+            let local = *local_indices.entry(vertex).or_insert_with(|| {
+                data.vertices.push(vertex);
+                current.vertex_count += 1;
+                current.vertex_count - 1
+            });
+            packed |= local << (corner * 8);
+        }
+        data.triangles.push(packed);
+        current.triangle_count += 1;
+    }
+    if current.triangle_count > 0 {
+        data.meshlets.push(current);
+    }
+
+    for meshlet in data.meshlets.iter_mut() {
+        let start = meshlet.vertex_offset as usize;
+        let end = start + meshlet.vertex_count as usize;
+        let mut bounds = [f32::MAX, f32::MAX, f32::MIN, f32::MIN];
+        for &vertex in &data.vertices[start..end] {
+            let position = vertices[vertex as usize].position();
+            bounds[0] = bounds[0].min(position.x);
+            bounds[1] = bounds[1].min(position.y);
+            bounds[2] = bounds[2].max(position.x);
+            bounds[3] = bounds[3].max(position.y);
+        }
+        meshlet.bounds = bounds;
+    }
+
+    data
+}
+
+/// Draws the static meshes split into meshlets with a task and a mesh shader, instead of the
+/// vertex and index buffers, when the device supports `VK_EXT_mesh_shader`.
+///
+/// The task shader culls the meshlets outside the view and launches a mesh shader workgroup
+/// for each of the others, which reads the vertices and the triangles of its meshlet from
+/// storage buffers. Only the meshes created while it is enabled are split, on load.
+pub(crate) struct MeshShading {
+    loader: mesh_shader::Device,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    // The sets of the removed meshes are recycled once no frame in flight reads them
+    descriptor_allocator: DescriptorAllocator,
+    frame_count: usize,
+    meshes: HashMap<usize, MeshletMesh>,
+    // Transform of every slot of the draw list for the current frame
+    model_view_projs: Vec<Mat4>,
+    enabled: bool,
+}
+
+impl MeshShading {
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        scene_render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        scene_set_layout: vk::DescriptorSetLayout,
+        frame_count: usize,
+    ) -> AppResult<Self> {
+        let bindings = [0, 1, 2, 3].map(|binding| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::TASK_EXT | vk::ShaderStageFlags::MESH_EXT,
+            ..Default::default()
+        });
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        // The scene set provides the texture and the lightmap sampled by the fragment shader
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[scene_set_layout, set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::TASK_EXT | vk::ShaderStageFlags::MESH_EXT,
+                offset: 0,
+                size: std::mem::size_of::<DrawConstants>() as u32,
+            }],
+        )?;
+        let pipeline = Self::create_pipeline(device, scene_render_pass, samples, pipeline_layout)?;
+
+        Ok(Self {
+            loader: mesh_shader::Device::new(instance, device),
+            set_layout,
+            pipeline_layout,
+            pipeline,
+            descriptor_allocator: DescriptorAllocator::new(vec![vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: bindings.len() as u32,
+            }]),
+            frame_count,
+            meshes: HashMap::new(),
+            model_view_projs: Vec::new(),
+            enabled: true,
+        })
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> AppResult<vk::Pipeline> {
+        let task_module = Application::create_shader_module(
+            device,
+            &load_shader(
+                "meshlet_dispatch.task",
+                include_bytes!("spirv/meshlet_dispatch.spv"),
+            )?,
+        )?;
+        let mesh_module = Application::create_shader_module(
+            device,
+            &load_shader("meshlet.mesh", include_bytes!("spirv/meshlet.spv"))?,
+        )?;
+        let frag_module = Application::create_shader_module(
+            device,
+            &load_shader("fragment.frag", include_bytes!("spirv/fragment.spv"))?,
+        )?;
+
+        let entry_point = CString::new("main").unwrap();
+        let shader_stages_infos = [
+            (vk::ShaderStageFlags::TASK_EXT, task_module),
+            (vk::ShaderStageFlags::MESH_EXT, mesh_module),
+            (vk::ShaderStageFlags::FRAGMENT, frag_module),
+        ]
+        .map(|(stage, module)| vk::PipelineShaderStageCreateInfo {
+            stage,
+            module,
+            p_name: entry_point.as_ptr(),
+            ..Default::default()
+        });
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo {
+            dynamic_state_count: dynamic_states.len() as u32,
+            p_dynamic_states: &dynamic_states as *const _,
+            ..Default::default()
+        };
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            viewport_count: 1,
+            scissor_count: 1,
+            ..Default::default()
+        };
+
+        // Same rasterization as the scene pipeline
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo {
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::CLOCKWISE,
+            line_width: 1.0,
+            ..Default::default()
+        };
+
+        let multisampling = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: samples,
+            min_sample_shading: 1.0,
+            ..Default::default()
+        };
+
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState {
+            blend_enable: false.into(),
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            ..Default::default()
+        }];
+        let color_blending = vk::PipelineColorBlendStateCreateInfo {
+            logic_op: vk::LogicOp::COPY,
+            attachment_count: color_blend_attachments.len() as u32,
+            p_attachments: color_blend_attachments.as_ptr(),
+            ..Default::default()
+        };
+
+        // A mesh pipeline has no vertex input nor input assembly state
+        let pipeline_info = vk::GraphicsPipelineCreateInfo {
+            stage_count: shader_stages_infos.len() as u32,
+            p_stages: shader_stages_infos.as_ptr(),
+            p_viewport_state: &viewport_state as *const _,
+            p_rasterization_state: &rasterizer as *const _,
+            p_multisample_state: &multisampling as *const _,
+            p_color_blend_state: &color_blending as *const _,
+            p_dynamic_state: &dynamic_state_create_info as *const _,
+            layout: pipeline_layout,
+            render_pass,
+            subpass: 0,
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipeline = unsafe {
+            device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+                .or_else(|r| AppResult::Err(r.1.into()))?[0]
+        };
+        handle_registry::register(pipeline);
+
+        unsafe {
+            for module in [task_module, mesh_module, frag_module] {
+                handle_registry::unregister(module);
+                device.destroy_shader_module(module, None);
+            }
+        }
+
+        Ok(pipeline)
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Splits the mesh of index `mesh` into meshlets and uploads them, replacing its previous
+    /// ones which are returned to be retired
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn add_mesh(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        mesh: usize,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> AppResult<Option<[BufferHolder; 4]>> {
+        let data = build_meshlets(vertices, indices);
+        let upload = |bytes: &[u8]| {
+            Application::create_buffer_with_data(
+                instance,
+                device,
+                queue,
+                physical_device,
+                bytes,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                command_pool,
+                sync_pool,
+            )
+        };
+        let buffers = [
+            upload(as_bytes(vertices))?,
+            upload(as_bytes(&data.meshlets))?,
+            upload(as_bytes(&data.vertices))?,
+            upload(as_bytes(&data.triangles))?,
+        ];
+
+        let descriptor_set = self
+            .descriptor_allocator
+            .allocate(device, self.set_layout, 1)?[0];
+        let buffer_infos = buffers.each_ref().map(|holder| vk::DescriptorBufferInfo {
+            buffer: holder.buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        });
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: buffer_infos.len() as u32,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            p_buffer_info: buffer_infos.as_ptr(),
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        let previous = self.meshes.insert(
+            mesh,
+            MeshletMesh {
+                buffers,
+                descriptor_set,
+                meshlet_count: data.meshlets.len() as u32,
+            },
+        );
+        Ok(previous.map(|previous| self.retire_mesh(previous)))
+    }
+
+    /// Forgets the meshlets of a mesh, returning their buffers to be retired
+    pub(crate) fn remove_mesh(&mut self, mesh: usize) -> Option<[BufferHolder; 4]> {
+        let mesh = self.meshes.remove(&mesh)?;
+        Some(self.retire_mesh(mesh))
+    }
+
+    fn retire_mesh(&mut self, mesh: MeshletMesh) -> [BufferHolder; 4] {
+        self.descriptor_allocator
+            .retire(&[mesh.descriptor_set], self.frame_count);
+        mesh.buffers
+    }
+
+    /// Recycles the sets of the removed meshes no frame in flight reads anymore, once per frame
+    pub(crate) fn collect_descriptor_sets(&mut self) {
+        self.descriptor_allocator.collect();
+    }
+
+    pub(crate) fn has_mesh(&self, mesh: usize) -> bool {
+        self.meshes.contains_key(&mesh)
+    }
+
+    pub(crate) fn pipeline(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+
+    pub(crate) fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout
+    }
+
+    /// Sets the transforms of the draw list slots for the draws of the frame
+    pub(crate) fn set_model_view_projs(&mut self, model_view_projs: Vec<Mat4>) {
+        self.model_view_projs = model_view_projs;
+    }
+
+    /// Records the draw of the meshlets of a mesh for the item in `slot` of the draw list, the
+    /// pipeline and the scene set being bound
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        mesh: usize,
+        slot: usize,
+    ) {
+        let mesh = &self.meshes[&mesh];
+        let constants = DrawConstants {
+            model_view_proj: self.model_view_projs[slot],
+            meshlet_count: mesh.meshlet_count,
+        };
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                1,
+                &[mesh.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::TASK_EXT | vk::ShaderStageFlags::MESH_EXT,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const DrawConstants as *const u8,
+                    std::mem::size_of::<DrawConstants>(),
+                ),
+            );
+            self.loader.cmd_draw_mesh_tasks(
+                command_buffer,
+                mesh.meshlet_count.div_ceil(TASK_WORKGROUP_SIZE),
+                1,
+                1,
+            );
+        }
+    }
+
+    /// Destroys the meshlets and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for mesh in self.meshes.drain().map(|(_, mesh)| mesh) {
+                for holder in mesh.buffers {
+                    handle_registry::unregister(holder.buffer);
+                    device.destroy_buffer(holder.buffer, None);
+                    handle_registry::unregister(holder.memory);
+                    device.free_memory(holder.memory, None);
+                }
+            }
+            self.descriptor_allocator.destroy(device);
+
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.set_layout);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
+
+fn as_bytes<T>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
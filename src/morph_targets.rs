@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+
+use ash::{vk, Device, Instance};
+
+use crate::{
+    descriptor_allocator::DescriptorAllocator, garbage_collector::Garbage, handle_registry,
+    load_shader, AppError, AppErrorType, AppResult, Application, BufferHolder, MemoryMappedBuffer,
+    SyncPool, Vec2, Vec3,
+};
+
+/// Shape of a mesh as offsets of its vertices, added to the mesh scaled by the weight of the
+/// target, see `Application::create_morph_mesh`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MorphTarget {
+    /// Offset of the position of every vertex, in the XY plane of the mesh
+    pub positions: Vec<Vec2>,
+    /// Offset of the color of every vertex, empty to leave the colors unchanged
+    pub colors: Vec<Vec3>,
+}
+
+/// Fails unless there is at least one target, each with an offset for each of the
+/// `vertex_count` vertices
+pub(crate) fn check_targets(vertex_count: usize, targets: &[MorphTarget]) -> AppResult<()> {
+    let valid = !targets.is_empty()
+        && targets.iter().all(|target| {
+            target.positions.len() == vertex_count
+                && (target.colors.is_empty() || target.colors.len() == vertex_count)
+        });
+    if !valid {
+        return AppResult::Err(AppError::new(AppErrorType::InvalidMorphTargets));
+    }
+
+    Ok(())
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct MorphDelta {
+    position: [f32; 4],
+    color: [f32; 4],
+}
+
+#[repr(C)]
+struct MorphConstants {
+    vertex_count: u32,
+    target_count: u32,
+}
+
+struct MorphMesh {
+    deltas: BufferHolder,
+    // The weights as of each frame in flight, along with the set reading them and the deltas
+    weight_buffers: Vec<MemoryMappedBuffer>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    weights: Vec<f32>,
+    vertex_count: u32,
+}
+
+/// Draws the meshes with morph targets with a vertex shader adding the offsets of the targets to
+/// the vertices, scaled by the weights of the mesh. The offsets stay in a storage buffer, only
+/// the weights are written every frame.
+///
+/// Only the items with the scene material are morphed, the other pipelines along with the
+/// shadows and the ray traced scene see the mesh as uploaded.
+pub(crate) struct MorphTargets {
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    // The sets of the removed meshes are recycled once no frame in flight reads them
+    descriptor_allocator: DescriptorAllocator,
+    meshes: HashMap<usize, MorphMesh>,
+}
+
+impl MorphTargets {
+    pub(crate) fn new(
+        device: &Device,
+        scene_render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        scene_set_layout: vk::DescriptorSetLayout,
+    ) -> AppResult<Self> {
+        let bindings = [0, 1].map(|binding| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            ..Default::default()
+        });
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[scene_set_layout, set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<MorphConstants>() as u32,
+            }],
+        )?;
+        let pipeline = Application::create_scene_pipeline(
+            device,
+            scene_render_pass,
+            samples,
+            pipeline_layout,
+            &load_shader("morph.vert", include_bytes!("spirv/morph.spv"))?,
+            &load_shader("fragment.frag", include_bytes!("spirv/fragment.spv"))?,
+            vk::CullModeFlags::BACK,
+        )?;
+
+        Ok(Self {
+            set_layout,
+            pipeline_layout,
+            pipeline,
+            descriptor_allocator: DescriptorAllocator::new(vec![vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: bindings.len() as u32,
+            }]),
+            meshes: HashMap::new(),
+        })
+    }
+
+    /// Uploads the targets of the mesh of index `mesh`, with `frame_count` frames in flight.
+    /// The weights start at zero, leaving the mesh as uploaded. The targets must have been
+    /// checked with `check_targets`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn add_mesh(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        frame_count: usize,
+        mesh: usize,
+        vertex_count: usize,
+        targets: &[MorphTarget],
+    ) -> AppResult<()> {
+        let deltas: Vec<_> = targets
+            .iter()
+            .flat_map(|target| {
+                (0..vertex_count).map(|vertex| {
+                    let position = target.positions[vertex];
+                    let color = target
+                        .colors
+                        .get(vertex)
+                        .copied()
+                        .unwrap_or(Vec3::new(0.0, 0.0, 0.0));
+                    MorphDelta {
+                        position: [position.x, position.y, 0.0, 0.0],
+                        color: [color.x, color.y, color.z, 0.0],
+                    }
+                })
+            })
+            .collect();
+        let deltas = Application::create_buffer_with_data(
+            instance,
+            device,
+            queue,
+            physical_device,
+            &deltas,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            command_pool,
+            sync_pool,
+        )?;
+
+        let weights = vec![0.0; targets.len()];
+        let weight_buffers = Application::create_host_visible_buffers(
+            instance,
+            device,
+            physical_device,
+            std::mem::size_of_val(weights.as_slice()) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            frame_count,
+        )?;
+        let descriptor_sets =
+            self.descriptor_allocator
+                .allocate(device, self.set_layout, frame_count as u32)?;
+        for (&descriptor_set, weight_buffer) in descriptor_sets.iter().zip(&weight_buffers) {
+            unsafe {
+                std::ptr::copy(
+                    weights.as_ptr(),
+                    weight_buffer.memory_map as *mut f32,
+                    weights.len(),
+                );
+            }
+
+            let buffer_infos =
+                [deltas.buffer, weight_buffer.buffer].map(|buffer| vk::DescriptorBufferInfo {
+                    buffer,
+                    offset: 0,
+                    range: vk::WHOLE_SIZE,
+                });
+            let descriptor_write = vk::WriteDescriptorSet {
+                dst_set: descriptor_set,
+                dst_binding: 0,
+                dst_array_element: 0,
+                descriptor_count: buffer_infos.len() as u32,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_buffer_info: buffer_infos.as_ptr(),
+                ..Default::default()
+            };
+            unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+        }
+
+        self.meshes.insert(
+            mesh,
+            MorphMesh {
+                deltas,
+                weight_buffers,
+                descriptor_sets,
+                weights,
+                vertex_count: vertex_count as u32,
+            },
+        );
+        Ok(())
+    }
+
+    /// Forgets the targets of a mesh, returning their buffers to be retired
+    pub(crate) fn remove_mesh(&mut self, mesh: usize) -> Vec<Garbage> {
+        let Some(mesh) = self.meshes.remove(&mesh) else {
+            return Vec::new();
+        };
+
+        // A set per frame in flight
+        self.descriptor_allocator
+            .retire(&mesh.descriptor_sets, mesh.descriptor_sets.len());
+        std::iter::once((mesh.deltas.buffer, mesh.deltas.memory))
+            .chain(
+                mesh.weight_buffers
+                    .iter()
+                    .map(|buffer| (buffer.buffer, buffer.memory)),
+            )
+            .map(|(buffer, memory)| Garbage::Buffer(buffer, memory))
+            .collect()
+    }
+
+    /// Recycles the sets of the removed meshes no frame in flight reads anymore, once per frame
+    pub(crate) fn collect_descriptor_sets(&mut self) {
+        self.descriptor_allocator.collect();
+    }
+
+    pub(crate) fn has_mesh(&self, mesh: usize) -> bool {
+        self.meshes.contains_key(&mesh)
+    }
+
+    /// Sets the weights of the targets of a mesh, in the order of the targets. The missing
+    /// weights are zero and the extra ones are ignored. Returns `false` if the mesh has no
+    /// targets.
+    pub(crate) fn set_weights(&mut self, mesh: usize, weights: &[f32]) -> bool {
+        let Some(mesh) = self.meshes.get_mut(&mesh) else {
+            return false;
+        };
+
+        for (index, weight) in mesh.weights.iter_mut().enumerate() {
+            *weight = weights.get(index).copied().unwrap_or(0.0);
+        }
+        true
+    }
+
+    pub(crate) fn weights(&self, mesh: usize) -> Option<&[f32]> {
+        self.meshes.get(&mesh).map(|mesh| mesh.weights.as_slice())
+    }
+
+    /// Writes the weights of every mesh to the buffers of `frame`
+    pub(crate) fn upload(&self, frame: usize) {
+        for mesh in self.meshes.values() {
+            unsafe {
+                std::ptr::copy(
+                    mesh.weights.as_ptr(),
+                    mesh.weight_buffers[frame].memory_map as *mut f32,
+                    mesh.weights.len(),
+                );
+            }
+        }
+    }
+
+    pub(crate) fn pipeline(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+
+    pub(crate) fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout
+    }
+
+    /// Binds the targets and the weights of a mesh for `frame`, the pipeline, the scene set
+    /// and the buffers of the mesh being bound
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        mesh: usize,
+        frame: usize,
+    ) {
+        let mesh = &self.meshes[&mesh];
+        let constants = MorphConstants {
+            vertex_count: mesh.vertex_count,
+            target_count: mesh.weights.len() as u32,
+        };
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                1,
+                &[mesh.descriptor_sets[frame]],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const MorphConstants as *const u8,
+                    std::mem::size_of::<MorphConstants>(),
+                ),
+            );
+        }
+    }
+
+    /// Destroys the targets and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for (_, mesh) in self.meshes.drain() {
+                Garbage::Buffer(mesh.deltas.buffer, mesh.deltas.memory).destroy(device);
+                for buffer in mesh.weight_buffers {
+                    Garbage::Buffer(buffer.buffer, buffer.memory).destroy(device);
+                }
+            }
+            self.descriptor_allocator.destroy(device);
+
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.set_layout);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use ash::{vk, Device, Instance};
+
+use crate::{
+    handle_registry, load_shader, post::SCENE_COLOR_FORMAT, AppResult, Application, DrawItemId,
+    ImageHolder, MeshAttachments, StencilState,
+};
+
+/// Scale of the outlined meshes around their origin, giving the width of the outlines
+pub const OUTLINE_SCALE: f32 = 1.05;
+
+// Stencil value written where the outlined items are drawn
+const OUTLINED_STENCIL: u32 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct OutlineConstants {
+    color: [f32; 4],
+    scale: f32,
+}
+
+/// Geometry and scene set of an outlined draw item
+pub(crate) struct OutlineDraw {
+    pub(crate) vertex_buffer: vk::Buffer,
+    pub(crate) index_buffer: vk::Buffer,
+    pub(crate) index_count: u32,
+    pub(crate) index_type: vk::IndexType,
+    pub(crate) descriptor_set: vk::DescriptorSet,
+    pub(crate) uniform_offset: u32,
+    pub(crate) color: [f32; 4],
+}
+
+// Stencil attachment drawn along with the scene target
+struct OutlineTarget {
+    image: ImageHolder,
+    view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+}
+
+/// Outlines draw items over the rendered scene with the stencil buffer, see
+/// `Application::set_outline`.
+///
+/// The pass loads the scene target along with a stencil attachment of its own. The items are
+/// first drawn to the stencil alone, then drawn again scaled by `OUTLINE_SCALE` in their outline
+/// color, the stencil test keeping the fragments outside of the items.
+pub(crate) struct Outlines {
+    format: vk::Format,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    mask_pipeline: vk::Pipeline,
+    outline_pipeline: vk::Pipeline,
+    // Created with the scene target it is drawn with, see `Outlines::destroy_target`
+    target: Option<OutlineTarget>,
+    items: Vec<(DrawItemId, [f32; 4])>,
+}
+
+impl Outlines {
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        scene_set_layout: vk::DescriptorSetLayout,
+    ) -> AppResult<Self> {
+        let format = Self::find_depth_stencil_format(instance, physical_device);
+        let render_pass = Self::create_render_pass(device, format)?;
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[scene_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<OutlineConstants>() as u32,
+            }],
+        )?;
+
+        let vert_shader_code = load_shader("outline.vert", include_bytes!("spirv/outline.spv"))?;
+        let frag_shader_code = load_shader(
+            "outline_color.frag",
+            include_bytes!("spirv/outline_color.spv"),
+        )?;
+        let mask_pipeline = Application::create_mesh_pipeline(
+            device,
+            render_pass,
+            vk::SampleCountFlags::TYPE_1,
+            pipeline_layout,
+            &vert_shader_code,
+            &frag_shader_code,
+            vk::CullModeFlags::NONE,
+            MeshAttachments::Stencil(StencilState {
+                compare_op: vk::CompareOp::ALWAYS,
+                pass_op: vk::StencilOp::REPLACE,
+                reference: OUTLINED_STENCIL,
+            }),
+            vk::PolygonMode::FILL,
+        )?;
+        let outline_pipeline = Application::create_mesh_pipeline(
+            device,
+            render_pass,
+            vk::SampleCountFlags::TYPE_1,
+            pipeline_layout,
+            &vert_shader_code,
+            &frag_shader_code,
+            vk::CullModeFlags::NONE,
+            MeshAttachments::ColorStencil(StencilState {
+                compare_op: vk::CompareOp::NOT_EQUAL,
+                pass_op: vk::StencilOp::KEEP,
+                reference: OUTLINED_STENCIL,
+            }),
+            vk::PolygonMode::FILL,
+        )?;
+
+        Ok(Self {
+            format,
+            render_pass,
+            pipeline_layout,
+            mask_pipeline,
+            outline_pipeline,
+            target: None,
+            items: Vec::new(),
+        })
+    }
+
+    // Every device can render to one of the two formats
+    fn find_depth_stencil_format(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> vk::Format {
+        [
+            vk::Format::D24_UNORM_S8_UINT,
+            vk::Format::D32_SFLOAT_S8_UINT,
+        ]
+        .into_iter()
+        .find(|&format| {
+            let properties =
+                unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+            properties
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .unwrap_or(vk::Format::D32_SFLOAT_S8_UINT)
+    }
+
+    // Draws over the scene target, left to be sampled by the post chain
+    fn create_render_pass(device: &Device, format: vk::Format) -> AppResult<vk::RenderPass> {
+        let attachments = [
+            vk::AttachmentDescription {
+                format: SCENE_COLOR_FORMAT,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::LOAD,
+                store_op: vk::AttachmentStoreOp::STORE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ..Default::default()
+            },
+            vk::AttachmentDescription {
+                format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::DONT_CARE,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                stencil_load_op: vk::AttachmentLoadOp::CLEAR,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            },
+        ];
+        let color_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+        let stencil_attachment_ref = vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        let subpass = vk::SubpassDescription {
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref as *const _,
+            p_depth_stencil_attachment: &stencil_attachment_ref as *const _,
+            ..Default::default()
+        };
+
+        let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let dependencies = [
+            // The scene pass writes the color, the previous frame may still be testing the
+            // stencil
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: attachment_stages,
+                dst_stage_mask: attachment_stages,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ..Default::default()
+            },
+            vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                ..Default::default()
+            },
+        ];
+
+        let render_pass_info = vk::RenderPassCreateInfo {
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: 1,
+            p_subpasses: &subpass as *const _,
+            dependency_count: dependencies.len() as u32,
+            p_dependencies: dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_render_pass(&render_pass_info, None)?,
+            ))
+        }
+    }
+
+    /// Outlines the draw item `id` in `color`, or stops outlining it when `color` is `None`
+    pub(crate) fn set(&mut self, id: DrawItemId, color: Option<[f32; 4]>) {
+        self.items.retain(|(item_id, _)| *item_id != id);
+        if let Some(color) = color {
+            self.items.push((id, color));
+        }
+    }
+
+    /// Outlined draw items along with their color
+    pub(crate) fn items(&self) -> &[(DrawItemId, [f32; 4])] {
+        &self.items
+    }
+
+    /// Creates the stencil attachment and the framebuffer drawing over `scene_view`, unless they
+    /// already exist
+    pub(crate) fn create_target(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        scene_view: vk::ImageView,
+        extent: vk::Extent2D,
+    ) -> AppResult<()> {
+        if self.target.is_some() {
+            return Ok(());
+        }
+
+        let image = Application::create_image(
+            instance,
+            device,
+            physical_device,
+            extent.width,
+            extent.height,
+            1,
+            self.format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let create_info = vk::ImageViewCreateInfo {
+            image: image.image,
+            view_type: vk::ImageViewType::TYPE_2D,
+            format: self.format,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        };
+        let view = unsafe { device.create_image_view(&create_info, None)? };
+        handle_registry::register(view);
+
+        let attachments = [scene_view, view];
+        let frame_buffer_info = vk::FramebufferCreateInfo {
+            render_pass: self.render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+        let framebuffer = unsafe { device.create_framebuffer(&frame_buffer_info, None)? };
+        handle_registry::register(framebuffer);
+
+        self.target = Some(OutlineTarget {
+            image,
+            view,
+            framebuffer,
+        });
+
+        Ok(())
+    }
+
+    /// Destroys the stencil attachment and the framebuffer, to be recreated along with the scene
+    /// target. The device must be idle.
+    pub(crate) fn destroy_target(&mut self, device: &Device) {
+        let Some(target) = self.target.take() else {
+            return;
+        };
+
+        unsafe {
+            handle_registry::unregister(target.framebuffer);
+            device.destroy_framebuffer(target.framebuffer, None);
+            handle_registry::unregister(target.view);
+            device.destroy_image_view(target.view, None);
+            handle_registry::unregister(target.image.image);
+            device.destroy_image(target.image.image, None);
+            handle_registry::unregister(target.image.memory);
+            device.free_memory(target.image.memory, None);
+        }
+    }
+
+    /// Records the outline pass over the scene target, once its target is created. Returns the
+    /// number of draw calls.
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        draws: &[OutlineDraw],
+    ) -> u32 {
+        let Some(target) = &self.target else {
+            return 0;
+        };
+
+        let clear_values = [
+            vk::ClearValue::default(),
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_info = vk::RenderPassBeginInfo {
+            render_pass: self.render_pass,
+            framebuffer: target.framebuffer,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+            ..Default::default()
+        };
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        }];
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
+
+            // Every item is masked before any outline is drawn, so the outline of an item doesn't
+            // cover the other outlined items
+            for (pipeline, scale) in [
+                (self.mask_pipeline, 1.0),
+                (self.outline_pipeline, OUTLINE_SCALE),
+            ] {
+                device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                for draw in draws {
+                    let constants = OutlineConstants {
+                        color: draw.color,
+                        scale,
+                    };
+                    device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        std::slice::from_raw_parts(
+                            &constants as *const OutlineConstants as *const u8,
+                            std::mem::size_of::<OutlineConstants>(),
+                        ),
+                    );
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.pipeline_layout,
+                        0,
+                        &[draw.descriptor_set],
+                        &[draw.uniform_offset],
+                    );
+                    device.cmd_bind_vertex_buffers(command_buffer, 0, &[draw.vertex_buffer], &[0]);
+                    device.cmd_bind_index_buffer(
+                        command_buffer,
+                        draw.index_buffer,
+                        0,
+                        draw.index_type,
+                    );
+                    device.cmd_draw_indexed(command_buffer, draw.index_count, 1, 0, 0, 0);
+                }
+            }
+
+            device.cmd_end_render_pass(command_buffer);
+        }
+        2 * draws.len() as u32
+    }
+
+    /// Destroys the target and the pipelines, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        self.destroy_target(device);
+
+        unsafe {
+            for pipeline in [self.mask_pipeline, self.outline_pipeline] {
+                handle_registry::unregister(pipeline);
+                device.destroy_pipeline(pipeline, None);
+            }
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.render_pass);
+            device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use std::ffi::CString;
+
+use ash::{vk, Device};
+
+use crate::{handle_registry, AppResult, Application};
+
+/// Vertex of the textured overlays laid out in pixels from the top left corner of the screen,
+/// transformed by overlay.vert
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct OverlayVertex {
+    pub(crate) position: [f32; 2],
+    pub(crate) uv: [f32; 2],
+    pub(crate) color: [f32; 4],
+}
+
+impl OverlayVertex {
+    pub(crate) fn bindings() -> [vk::VertexInputBindingDescription; 1] {
+        [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }]
+    }
+
+    pub(crate) fn attributes() -> [vk::VertexInputAttributeDescription; 3] {
+        [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 8,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: 16,
+            },
+        ]
+    }
+}
+
+/// How the fragments of an overlay pipeline are blended into the target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OverlayBlend {
+    /// Over the target by their alpha
+    Alpha,
+    /// Added to the target, scaled by their alpha, e.g. for glowing particles
+    Additive,
+}
+
+/// Two triangles covering `size` pixels from `position`, sampling the rectangle from `uv_min`
+/// to `uv_max`
+pub(crate) fn push_quad(
+    vertices: &mut Vec<OverlayVertex>,
+    position: [f32; 2],
+    size: [f32; 2],
+    [u0, v0]: [f32; 2],
+    [u1, v1]: [f32; 2],
+    color: [f32; 4],
+) {
+    let [x0, y0] = position;
+    let [x1, y1] = [x0 + size[0], y0 + size[1]];
+    let corners = [
+        ([x0, y0], [u0, v0]),
+        ([x1, y0], [u1, v0]),
+        ([x1, y1], [u1, v1]),
+        ([x1, y1], [u1, v1]),
+        ([x0, y1], [u0, v1]),
+        ([x0, y0], [u0, v0]),
+    ];
+    vertices.extend(corners.map(|(position, uv)| OverlayVertex {
+        position,
+        uv,
+        color,
+    }));
+}
+
+/// Binds a pipeline drawing `OverlayVertex`es along with its single descriptor set, and maps
+/// the pixels of `extent` to the clip space
+pub(crate) fn bind_overlay_pipeline(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_set: vk::DescriptorSet,
+    extent: vk::Extent2D,
+) {
+    let viewports = [vk::Viewport {
+        x: 0.0,
+        y: 0.0,
+        width: extent.width as f32,
+        height: extent.height as f32,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    }];
+    let scissors = [vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    }];
+    let scale = [2.0 / extent.width as f32, 2.0 / extent.height as f32];
+
+    unsafe {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_set_viewport(command_buffer, 0, &viewports);
+        device.cmd_set_scissor(command_buffer, 0, &scissors);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            0,
+            &[descriptor_set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            std::slice::from_raw_parts(scale.as_ptr() as *const u8, std::mem::size_of_val(&scale)),
+        );
+    }
+}
+
+/// Creates a pipeline drawing blended geometry over the swapchain image, in the composite render
+/// pass left open for the overlays, or over the scene in the scene render pass. The viewport and
+/// scissor are dynamic, and there is no depth test nor culling.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn create_overlay_pipeline(
+    device: &Device,
+    render_pass: vk::RenderPass,
+    samples: vk::SampleCountFlags,
+    pipeline_layout: vk::PipelineLayout,
+    vertex_code: &[u32],
+    fragment_code: &[u32],
+    bindings: &[vk::VertexInputBindingDescription],
+    attributes: &[vk::VertexInputAttributeDescription],
+    topology: vk::PrimitiveTopology,
+    blend: OverlayBlend,
+) -> AppResult<vk::Pipeline> {
+    let vert_module = Application::create_shader_module(device, vertex_code)?;
+    let frag_module = Application::create_shader_module(device, fragment_code)?;
+
+    let entry_point = CString::new("main").unwrap();
+    let shader_stages_infos = [
+        vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::VERTEX,
+            module: vert_module,
+            p_name: entry_point.as_ptr(),
+            ..Default::default()
+        },
+        vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::FRAGMENT,
+            module: frag_module,
+            p_name: entry_point.as_ptr(),
+            ..Default::default()
+        },
+    ];
+
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo {
+        dynamic_state_count: dynamic_states.len() as u32,
+        p_dynamic_states: &dynamic_states as *const _,
+        ..Default::default()
+    };
+
+    let vertex_input_info = vk::PipelineVertexInputStateCreateInfo {
+        vertex_binding_description_count: bindings.len() as u32,
+        p_vertex_binding_descriptions: bindings.as_ptr(),
+        vertex_attribute_description_count: attributes.len() as u32,
+        p_vertex_attribute_descriptions: attributes.as_ptr(),
+        ..Default::default()
+    };
+
+    let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo {
+        topology,
+        primitive_restart_enable: false.into(),
+        ..Default::default()
+    };
+
+    let viewport_state = vk::PipelineViewportStateCreateInfo {
+        viewport_count: 1,
+        scissor_count: 1,
+        ..Default::default()
+    };
+
+    let rasterizer = vk::PipelineRasterizationStateCreateInfo {
+        polygon_mode: vk::PolygonMode::FILL,
+        cull_mode: vk::CullModeFlags::NONE,
+        front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        line_width: 1.0,
+        ..Default::default()
+    };
+
+    let multisampling = vk::PipelineMultisampleStateCreateInfo {
+        rasterization_samples: samples,
+        min_sample_shading: 1.0,
+        ..Default::default()
+    };
+
+    let color_blend_attachments = [match blend {
+        OverlayBlend::Alpha => vk::PipelineColorBlendAttachmentState {
+            blend_enable: true.into(),
+            src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        },
+        // The alpha of the target is kept
+        OverlayBlend::Additive => vk::PipelineColorBlendAttachmentState {
+            blend_enable: true.into(),
+            src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+            dst_color_blend_factor: vk::BlendFactor::ONE,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ZERO,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        },
+    }];
+    let color_blending = vk::PipelineColorBlendStateCreateInfo {
+        logic_op: vk::LogicOp::COPY,
+        attachment_count: color_blend_attachments.len() as u32,
+        p_attachments: color_blend_attachments.as_ptr(),
+        ..Default::default()
+    };
+
+    let pipeline_info = vk::GraphicsPipelineCreateInfo {
+        stage_count: shader_stages_infos.len() as u32,
+        p_stages: shader_stages_infos.as_ptr(),
+        p_vertex_input_state: &vertex_input_info as *const _,
+        p_input_assembly_state: &input_assembly_info as *const _,
+        p_viewport_state: &viewport_state as *const _,
+        p_rasterization_state: &rasterizer as *const _,
+        p_multisample_state: &multisampling as *const _,
+        p_color_blend_state: &color_blending as *const _,
+        p_dynamic_state: &dynamic_state_create_info as *const _,
+        layout: pipeline_layout,
+        render_pass,
+        subpass: 0,
+        base_pipeline_index: -1,
+        ..Default::default()
+    };
+
+    let pipeline = unsafe {
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+            .or_else(|r| AppResult::Err(r.1.into()))?[0]
+    };
+    handle_registry::register(pipeline);
+
+    unsafe {
+        handle_registry::unregister(vert_module);
+        device.destroy_shader_module(vert_module, None);
+        handle_registry::unregister(frag_module);
+        device.destroy_shader_module(frag_module, None);
+    }
+
+    Ok(pipeline)
+}
@@ -0,0 +1,379 @@
+use ash::{vk, Device, Instance};
+
+use crate::{
+    garbage_collector::{Garbage, GarbageCollector},
+    handle_registry, load_shader,
+    overlay::{create_overlay_pipeline, OverlayBlend},
+    AppResult, Application, Mat4, MemoryMappedBuffer, PerFrame, Point3, Vec3, Vec4,
+};
+
+/// Particles alive at once across every emitter, the emitters stop emitting past it
+pub const MAX_PARTICLES: usize = 16384;
+
+// Two triangles per particle
+const VERTICES_PER_PARTICLE: usize = 6;
+
+/// Emits particles from a point, simulated on the CPU and drawn as quads facing the camera, see
+/// `Application::add_particle_emitter`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParticleEmitter {
+    /// Where the particles are emitted, in world space
+    pub position: Point3,
+    /// Particles emitted every second
+    pub rate: f32,
+    /// Seconds a particle lives
+    pub lifetime: f32,
+    /// Velocity of the particles when emitted, in units per second
+    pub velocity: Vec3,
+    /// Each component of the velocity of a new particle differs from `velocity` by up to this
+    /// amount
+    pub velocity_spread: f32,
+    /// Added to the velocity of the particles every second, e.g. the gravity
+    pub acceleration: Vec3,
+    /// Width of the quads, in world units
+    pub size: f32,
+    /// Color of a new particle, the alpha scaling the light it adds to the scene
+    pub start_color: Vec4,
+    /// Color of a particle at the end of its life, reached linearly from `start_color`
+    pub end_color: Vec4,
+}
+
+impl Default for ParticleEmitter {
+    /// Orange sparks thrown upwards and falling back
+    fn default() -> Self {
+        Self {
+            position: Point3::new(0.0, 0.0, 0.0),
+            rate: 100.0,
+            lifetime: 1.5,
+            velocity: Vec3::new(0.0, 0.0, 1.0),
+            velocity_spread: 0.3,
+            acceleration: Vec3::new(0.0, 0.0, -1.0),
+            size: 0.05,
+            start_color: Vec4::new(1.0, 0.6, 0.2, 1.0),
+            end_color: Vec4::new(1.0, 0.1, 0.0, 0.0),
+        }
+    }
+}
+
+/// Identifies a particle emitter so it can be updated or removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ParticleEmitterId(u64);
+
+struct Particle {
+    position: Point3,
+    velocity: Vec3,
+    age: f32,
+}
+
+struct Emitter {
+    id: ParticleEmitterId,
+    desc: ParticleEmitter,
+    particles: Vec<Particle>,
+    // Fraction of a particle left to emit from the previous updates
+    pending: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ParticleVertex {
+    position: [f32; 3],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+// Vertex buffer of a frame in flight, grown when the particles outgrow it
+#[derive(Default)]
+struct ParticleBuffer {
+    vertex_buffer: Option<MemoryMappedBuffer>,
+    vertex_capacity: usize,
+    vertex_count: u32,
+}
+
+/// Simulates the particles of the emitters and draws them in the scene render pass, after the
+/// draw items. The particles add their color to the scene without depth test, so they don't need
+/// to be sorted.
+pub(crate) struct ParticleSystem {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    buffers: PerFrame<ParticleBuffer>,
+    emitters: Vec<Emitter>,
+    next_id: u64,
+    // State of the xorshift generator spreading the velocities
+    random_state: u32,
+    vertices: Vec<ParticleVertex>,
+}
+
+impl ParticleSystem {
+    pub(crate) fn new(
+        device: &Device,
+        scene_render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        frame_count: usize,
+    ) -> AppResult<Self> {
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<Mat4>() as u32,
+            }],
+        )?;
+        let pipeline = create_overlay_pipeline(
+            device,
+            scene_render_pass,
+            samples,
+            pipeline_layout,
+            &load_shader("particle.vert", include_bytes!("spirv/particle.spv"))?,
+            &load_shader(
+                "particle_sprite.frag",
+                include_bytes!("spirv/particle_sprite.spv"),
+            )?,
+            &[vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: std::mem::size_of::<ParticleVertex>() as u32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }],
+            &[
+                vk::VertexInputAttributeDescription {
+                    location: 0,
+                    binding: 0,
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: 0,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 1,
+                    binding: 0,
+                    format: vk::Format::R32G32_SFLOAT,
+                    offset: 12,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 2,
+                    binding: 0,
+                    format: vk::Format::R32G32B32A32_SFLOAT,
+                    offset: 20,
+                },
+            ],
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            OverlayBlend::Additive,
+        )?;
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            buffers: PerFrame::new(frame_count, |_| Ok(ParticleBuffer::default()))?,
+            emitters: Vec::new(),
+            next_id: 0,
+            random_state: 0x9e37_79b9,
+            vertices: Vec::new(),
+        })
+    }
+
+    pub(crate) fn add(&mut self, desc: ParticleEmitter) -> ParticleEmitterId {
+        let id = ParticleEmitterId(self.next_id);
+        self.next_id += 1;
+        self.emitters.push(Emitter {
+            id,
+            desc,
+            particles: Vec::new(),
+            pending: 0.0,
+        });
+        id
+    }
+
+    /// Removes the emitter along with its particles
+    pub(crate) fn remove(&mut self, id: ParticleEmitterId) -> Option<ParticleEmitter> {
+        let index = self.emitters.iter().position(|emitter| emitter.id == id)?;
+        Some(self.emitters.remove(index).desc)
+    }
+
+    pub(crate) fn get_mut(&mut self, id: ParticleEmitterId) -> Option<&mut ParticleEmitter> {
+        self.emitters
+            .iter_mut()
+            .find(|emitter| emitter.id == id)
+            .map(|emitter| &mut emitter.desc)
+    }
+
+    pub(crate) fn particle_count(&self) -> usize {
+        self.emitters
+            .iter()
+            .map(|emitter| emitter.particles.len())
+            .sum()
+    }
+
+    // Uniformly distributed in [-1, 1]
+    fn next_random(&mut self) -> f32 {
+        self.random_state ^= self.random_state << 13;
+        self.random_state ^= self.random_state >> 17;
+        self.random_state ^= self.random_state << 5;
+        self.random_state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    /// Ages and moves the particles by `delta_time` seconds, removes the dead ones and emits the
+    /// new ones
+    pub(crate) fn update(&mut self, delta_time: f32) {
+        for emitter in self.emitters.iter_mut() {
+            let desc = &emitter.desc;
+            emitter.particles.retain_mut(|particle| {
+                particle.age += delta_time;
+                particle.velocity += desc.acceleration * delta_time;
+                particle.position += particle.velocity * delta_time;
+                particle.age < desc.lifetime
+            });
+        }
+
+        let mut alive = self.particle_count();
+        for index in 0..self.emitters.len() {
+            let emitter = &mut self.emitters[index];
+            emitter.pending += emitter.desc.rate * delta_time;
+            let count = emitter.pending as usize;
+            emitter.pending -= count as f32;
+
+            let desc = emitter.desc;
+            for _ in 0..count.min(MAX_PARTICLES - alive) {
+                let spread = Vec3::new(self.next_random(), self.next_random(), self.next_random());
+                self.emitters[index].particles.push(Particle {
+                    position: desc.position,
+                    velocity: desc.velocity + spread * desc.velocity_spread,
+                    age: 0.0,
+                });
+                alive += 1;
+            }
+        }
+    }
+
+    /// Builds the quads of the particles facing the camera of `view`, and copies them to the
+    /// vertex buffer of `frame`, which must not be in use by the GPU, growing it if needed
+    pub(crate) fn upload(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        garbage_collector: &mut GarbageCollector,
+        frame: usize,
+        view: Mat4,
+    ) -> AppResult<()> {
+        // The rows of the rotation of the view are the camera axes in world space
+        let right = Vec3::new(view.x.x, view.y.x, view.z.x);
+        let up = Vec3::new(view.x.y, view.y.y, view.z.y);
+
+        self.vertices.clear();
+        for emitter in &self.emitters {
+            let desc = &emitter.desc;
+            let half_right = right * desc.size * 0.5;
+            let half_up = up * desc.size * 0.5;
+            for particle in &emitter.particles {
+                let life = particle.age / desc.lifetime;
+                let color = desc.start_color + (desc.end_color - desc.start_color) * life;
+                let corner = |x: f32, y: f32| ParticleVertex {
+                    position: (particle.position + half_right * x + half_up * y).into(),
+                    uv: [(x + 1.0) * 0.5, (1.0 - y) * 0.5],
+                    color: color.into(),
+                };
+                self.vertices.extend([
+                    corner(-1.0, 1.0),
+                    corner(1.0, 1.0),
+                    corner(1.0, -1.0),
+                    corner(1.0, -1.0),
+                    corner(-1.0, -1.0),
+                    corner(-1.0, 1.0),
+                ]);
+            }
+        }
+
+        let frame_count = self.buffers.len();
+        let buffers = &mut self.buffers[frame];
+        buffers.vertex_count = self.vertices.len() as u32;
+        if self.vertices.is_empty() {
+            return Ok(());
+        }
+
+        if buffers.vertex_capacity < self.vertices.len() {
+            let capacity = self
+                .vertices
+                .len()
+                .next_power_of_two()
+                .min(MAX_PARTICLES * VERTICES_PER_PARTICLE);
+            let buffer = Application::create_host_visible_buffers(
+                instance,
+                device,
+                physical_device,
+                (capacity * std::mem::size_of::<ParticleVertex>()) as u64,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                1,
+            )?
+            .pop()
+            .unwrap();
+            if let Some(old) = buffers.vertex_buffer.replace(buffer) {
+                garbage_collector.retire(Garbage::Buffer(old.buffer, old.memory), frame_count);
+            }
+            buffers.vertex_capacity = capacity;
+        }
+
+        unsafe {
+            std::ptr::copy(
+                self.vertices.as_ptr(),
+                buffers.vertex_buffer.as_ref().unwrap().memory_map as *mut ParticleVertex,
+                self.vertices.len(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Records the particles of `frame` seen through `view_proj`, in the scene render pass.
+    /// Returns the number of draw calls.
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        view_proj: Mat4,
+    ) -> u32 {
+        let buffers = &self.buffers[frame];
+        let Some(vertex_buffer) = &buffers.vertex_buffer else {
+            return 0;
+        };
+        if buffers.vertex_count == 0 {
+            return 0;
+        }
+
+        // The viewport and scissor of the scene pass are already set
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                std::slice::from_raw_parts(
+                    &view_proj as *const Mat4 as *const u8,
+                    std::mem::size_of::<Mat4>(),
+                ),
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_draw(command_buffer, buffers.vertex_count, 1, 0, 0);
+        }
+        1
+    }
+
+    /// Destroys the buffers and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for buffers in self.buffers.iter_mut() {
+                if let Some(buffer) = buffers.vertex_buffer.take() {
+                    Garbage::Buffer(buffer.buffer, buffer.memory).destroy(device);
+                }
+            }
+
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,1183 @@
+use std::path::{Path, PathBuf};
+
+use ash::{vk, Device, Instance};
+use cgmath::{EuclideanSpace, InnerSpace, SquareMatrix};
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    clustered_lighting::ClusteredLighting,
+    geometry::{Mat4, Point3, Vec3, Vec4},
+    handle_registry, load_shader,
+    point_shadows::{PointShadows, ShadowCaster, MAX_SHADOWED_POINT_LIGHTS},
+    texture_loader::{self, DecodedTexture, TextureLevel},
+    AppResult, Application, MemoryMappedBuffer, MeshAttachments, SyncPool, TextureHolder,
+};
+
+/// Lights shading the PBR materials at most, the point ones being culled per cluster of the view
+pub const MAX_PBR_LIGHTS: usize = 256;
+/// Directional lights among the `MAX_PBR_LIGHTS`, as they reach every fragment
+pub const MAX_DIRECTIONAL_PBR_LIGHTS: usize = 16;
+pub const MAX_PBR_MATERIALS: usize = 64;
+
+// Texel of the normal map of the materials without one, pointing along the surface normal
+const FLAT_NORMAL: [u8; 4] = [128, 128, 255, 255];
+// Radiance under which a point light is considered out of reach, bounding the lights to the
+// clusters they are culled against
+const LIGHT_CUTOFF: f32 = 0.005;
+
+/// Light as read by the shaders: position and influence radius for a point light or direction and
+/// 0, radiance and shadow cubemap or -1, then attenuation and shadow range
+pub(crate) type GpuLight = [[f32; 4]; 3];
+
+/// Maps and factors of a metallic-roughness material, following the glTF conventions. The maps
+/// other than the albedo and the emissive one are sampled as linear values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PbrMaterial<P> {
+    pub albedo: P,
+    /// Tangent space normal map, the surface keeps its normal without one
+    pub normal: Option<P>,
+    /// Roughness in the green channel and metallic in the blue one, the factors alone are used
+    /// without one
+    pub metallic_roughness: Option<P>,
+    /// Ambient occlusion in the red channel
+    pub occlusion: Option<P>,
+    /// Light emitted by the surface, the factor alone is used without one
+    pub emissive: Option<P>,
+    /// Multiplies the albedo map, its alpha included
+    pub base_color: Vec4,
+    /// Multiplies the metallic channel of the map
+    pub metallic: f32,
+    /// Multiplies the roughness channel of the map
+    pub roughness: f32,
+    /// Multiplies the emissive map, no light being emitted by default
+    pub emissive_factor: Vec3,
+    pub alpha_mode: AlphaMode,
+    /// Whether the back faces are drawn too, with their normal flipped, instead of being culled
+    pub double_sided: bool,
+}
+
+impl<P> PbrMaterial<P> {
+    /// Material of the `albedo` map alone, with the glTF defaults for the factors
+    pub fn new(albedo: P) -> Self {
+        Self {
+            albedo,
+            normal: None,
+            metallic_roughness: None,
+            occlusion: None,
+            emissive: None,
+            base_color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+            metallic: 1.0,
+            roughness: 1.0,
+            emissive_factor: Vec3::new(0.0, 0.0, 0.0),
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+        }
+    }
+}
+
+impl<P: AsRef<Path>> PbrMaterial<P> {
+    /// Paths of the maps, the albedo first
+    pub(crate) fn map_paths(&self) -> Vec<&Path> {
+        let optional_maps = [
+            &self.normal,
+            &self.metallic_roughness,
+            &self.occlusion,
+            &self.emissive,
+        ];
+        let mut paths = vec![self.albedo.as_ref()];
+        paths.extend(optional_maps.into_iter().flatten().map(P::as_ref));
+        paths
+    }
+
+    pub(crate) fn to_path_bufs(&self) -> PbrMaterial<PathBuf> {
+        let path_buf = |map: &Option<P>| map.as_ref().map(|path| path.as_ref().to_path_buf());
+
+        PbrMaterial {
+            albedo: self.albedo.as_ref().to_path_buf(),
+            normal: path_buf(&self.normal),
+            metallic_roughness: path_buf(&self.metallic_roughness),
+            occlusion: path_buf(&self.occlusion),
+            emissive: path_buf(&self.emissive),
+            base_color: self.base_color,
+            metallic: self.metallic,
+            roughness: self.roughness,
+            emissive_factor: self.emissive_factor,
+            alpha_mode: self.alpha_mode,
+            double_sided: self.double_sided,
+        }
+    }
+}
+
+/// Decodes the maps of `material`, each on its own thread
+pub(crate) fn decode_material<P: AsRef<Path> + Sync>(
+    material: &PbrMaterial<P>,
+) -> AppResult<PbrMaterial<DecodedTexture>> {
+    let mut decoded = texture_loader::decode_textures(&material.map_paths())?.into_iter();
+    let albedo = decoded.next().unwrap();
+    let mut given = |map: &Option<P>| map.as_ref().map(|_| decoded.next().unwrap());
+
+    Ok(PbrMaterial {
+        albedo,
+        normal: given(&material.normal),
+        metallic_roughness: given(&material.metallic_roughness),
+        occlusion: given(&material.occlusion),
+        emissive: given(&material.emissive),
+        base_color: material.base_color,
+        metallic: material.metallic,
+        roughness: material.roughness,
+        emissive_factor: material.emissive_factor,
+        alpha_mode: material.alpha_mode,
+        double_sided: material.double_sided,
+    })
+}
+
+/// How the alpha of the albedo of a PBR material is used
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AlphaMode {
+    /// The alpha is ignored, the surface being fully opaque
+    #[default]
+    Opaque,
+    /// The fragments whose alpha is below the cutoff are discarded, the others being opaque
+    Mask(f32),
+    /// Blended over the scene with its alpha like the transparent materials, the items being
+    /// drawn from back to front after the opaque ones
+    Blend,
+}
+
+/// Distance attenuation of a point light, whose radiance is divided by
+/// `constant + linear * distance + quadratic * distance²`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightAttenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Default for LightAttenuation {
+    /// Inverse square law, clamped to the full radiance within a unit of the light
+    fn default() -> Self {
+        Self {
+            constant: 1.0,
+            linear: 0.0,
+            quadratic: 1.0,
+        }
+    }
+}
+
+/// Punctual light shading the PBR materials, its radiance being `color * intensity`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PbrLight {
+    /// Light travelling along `direction` from infinitely far away, e.g. the sun
+    Directional {
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+    },
+    Point {
+        position: Point3,
+        color: Vec3,
+        intensity: f32,
+        attenuation: LightAttenuation,
+        /// Distance up to which the light casts shadows, or `None` for a light going through
+        /// the meshes. At most `MAX_SHADOWED_POINT_LIGHTS` lights cast shadows.
+        shadow_range: Option<f32>,
+    },
+}
+
+/// Lights shading the PBR materials, see `Application::set_pbr_lighting`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PbrLighting {
+    /// Light reaching every point, scaled by the ambient occlusion
+    pub ambient: Vec3,
+    /// At most `MAX_PBR_LIGHTS` lights, including at most `MAX_DIRECTIONAL_PBR_LIGHTS`
+    /// directional ones
+    pub lights: Vec<PbrLight>,
+}
+
+impl Default for PbrLighting {
+    fn default() -> Self {
+        Self {
+            ambient: Vec3::new(0.03, 0.03, 0.03),
+            lights: Vec::new(),
+        }
+    }
+}
+
+impl PbrLighting {
+    /// Point lights casting shadows, in the order of their shadow cubemaps
+    pub fn shadow_casters(&self) -> Vec<ShadowCaster> {
+        self.lights
+            .iter()
+            .filter_map(|light| match *light {
+                PbrLight::Point {
+                    position,
+                    shadow_range: Some(range),
+                    ..
+                } => Some(ShadowCaster { position, range }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn directional_count(&self) -> usize {
+        self.lights
+            .iter()
+            .filter(|light| matches!(light, PbrLight::Directional { .. }))
+            .count()
+    }
+}
+
+// Distance beyond which the radiance of a point light falls under `LIGHT_CUTOFF`, solving
+// `constant + linear * d + quadratic * d² = peak / LIGHT_CUTOFF`
+fn influence_radius(radiance: Vec3, attenuation: LightAttenuation) -> f32 {
+    let LightAttenuation {
+        constant,
+        linear,
+        quadratic,
+    } = attenuation;
+    let target = radiance.x.max(radiance.y).max(radiance.z) / LIGHT_CUTOFF;
+    let radius = if quadratic > 0.0 {
+        let discriminant = linear * linear - 4.0 * quadratic * (constant - target);
+        (discriminant.max(0.0).sqrt() - linear) / (2.0 * quadratic)
+    } else if linear > 0.0 {
+        (target - constant) / linear
+    } else {
+        f32::MAX
+    };
+
+    radius.max(0.0)
+}
+
+// std140 layout of the lighting uniform buffer of the PBR fragment shader and of the light
+// culling pass
+#[repr(C)]
+struct LightingUniforms {
+    camera_position: [f32; 4],
+    ambient: [f32; 4],
+    // Directional then point lights
+    light_count: [u32; 4],
+    view: [[f32; 4]; 4],
+    inverse_proj: [[f32; 4]; 4],
+    // Size of the target in pixels, then near and far planes
+    cluster: [f32; 4],
+    directional_lights: [GpuLight; MAX_DIRECTIONAL_PBR_LIGHTS],
+}
+
+// Factors of a material, pushed along with its set
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MaterialFactors {
+    base_color: [f32; 4],
+    // The alpha cutoff in w, 0 for the materials not masked
+    emissive: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    // 1 for the opaque materials, whose alpha is ignored
+    opaque: f32,
+}
+
+// Variants of the pipelines, indexed by `variant_index`
+const PIPELINE_VARIANTS: usize = 4;
+
+fn variant_index(double_sided: bool, blended: bool) -> usize {
+    double_sided as usize | (blended as usize) << 1
+}
+
+struct MaterialSet {
+    // Albedo, normal, metallic-roughness, occlusion and emissive maps the material was given
+    maps: Vec<TextureHolder>,
+    // Normal, metallic-roughness, occlusion and emissive views of the set, given or default
+    map_views: [vk::ImageView; 4],
+    descriptor_set: vk::DescriptorSet,
+    factors: MaterialFactors,
+    variant: usize,
+}
+
+/// Variant of the pipeline tracing the shadows with ray queries against the top-level
+/// acceleration structure of the frame, bound with set 3
+struct RayQueryShadows {
+    pipelines: [vk::Pipeline; PIPELINE_VARIANTS],
+    pipeline_layout: vk::PipelineLayout,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    sets: Vec<vk::DescriptorSet>,
+}
+
+/// Scene pipeline shading the materials with the Cook-Torrance BRDF under punctual lights, as
+/// an alternative to the unlit textured pipeline selected per material.
+///
+/// The albedo is bound in place of the texture of the scene set, the other maps with set 1 and
+/// the lighting of the frame with set 2, along with the shadow cubemaps and the clustered lists of
+/// the point lights. The shadows are traced with ray queries instead of sampling the cubemaps
+/// while `set_ray_query_shadows` is enabled.
+///
+/// Each material is drawn with the variant of the pipeline matching its culling and blending.
+pub(crate) struct PbrPipeline {
+    pipelines: [vk::Pipeline; PIPELINE_VARIANTS],
+    pipeline_layout: vk::PipelineLayout,
+    // Samples of the scene render pass, for the ray query variant
+    samples: vk::SampleCountFlags,
+    material_set_layout: vk::DescriptorSetLayout,
+    lighting_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    lighting_buffers: Vec<MemoryMappedBuffer>,
+    lighting_sets: Vec<vk::DescriptorSet>,
+    // Stand in for the missing maps
+    flat_normal: TextureHolder,
+    white: TextureHolder,
+    sampler: vk::Sampler,
+    materials: Vec<MaterialSet>,
+    point_shadows: PointShadows,
+    clustered_lighting: ClusteredLighting,
+    // Created when the ray traced shadows are first enabled
+    ray_query_shadows: Option<RayQueryShadows>,
+    ray_queries_enabled: bool,
+}
+
+impl PbrPipeline {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        scene_render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        scene_set_layout: vk::DescriptorSetLayout,
+        sampler: vk::Sampler,
+        frame_count: usize,
+    ) -> AppResult<Self> {
+        let map_binding = |binding| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        };
+        let material_set_layout = Self::create_set_layout(
+            device,
+            &[
+                map_binding(0),
+                map_binding(1),
+                map_binding(2),
+                map_binding(3),
+            ],
+        )?;
+        let lighting_set_layout = Self::create_set_layout(
+            device,
+            &[
+                vk::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE,
+                    ..Default::default()
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: MAX_SHADOWED_POINT_LIGHTS as u32,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                    ..Default::default()
+                },
+                // Point lights, then the lists of the lights of each cluster
+                vk::DescriptorSetLayoutBinding {
+                    binding: 2,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE,
+                    ..Default::default()
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 3,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE,
+                    ..Default::default()
+                },
+            ],
+        )?;
+
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[scene_set_layout, material_set_layout, lighting_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<MaterialFactors>() as u32,
+            }],
+        )?;
+        let pipelines = Self::create_pipelines(
+            device,
+            scene_render_pass,
+            samples,
+            pipeline_layout,
+            &load_shader("pbr.frag", include_bytes!("spirv/pbr.spv"))?,
+        )?;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: (4 * MAX_PBR_MATERIALS + MAX_SHADOWED_POINT_LIGHTS * frame_count)
+                    as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: frame_count as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 2 * frame_count as u32,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: (MAX_PBR_MATERIALS + frame_count) as u32,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let lighting_buffers = Application::create_uniform_buffers(
+            instance,
+            device,
+            physical_device,
+            std::mem::size_of::<LightingUniforms>() as u64,
+            frame_count,
+        )?;
+        let layouts = vec![lighting_set_layout; frame_count];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+        let lighting_sets = unsafe { device.allocate_descriptor_sets(&alloc_info)? };
+        let buffer_infos: Vec<vk::DescriptorBufferInfo> = lighting_buffers
+            .iter()
+            .map(|buffer| vk::DescriptorBufferInfo {
+                buffer: buffer.buffer,
+                offset: 0,
+                range: std::mem::size_of::<LightingUniforms>() as u64,
+            })
+            .collect();
+        let point_shadows = PointShadows::new(
+            instance,
+            device,
+            queue,
+            physical_device,
+            command_pool,
+            sync_pool,
+            scene_set_layout,
+        )?;
+        let clustered_lighting = ClusteredLighting::new(
+            instance,
+            device,
+            physical_device,
+            lighting_set_layout,
+            frame_count,
+        )?;
+        let shadow_infos = point_shadows.image_infos();
+        let storage_infos: Vec<[vk::DescriptorBufferInfo; 2]> = (0..frame_count)
+            .map(|frame| clustered_lighting.buffer_infos(frame))
+            .collect();
+        let descriptor_writes: Vec<vk::WriteDescriptorSet> = lighting_sets
+            .iter()
+            .zip(buffer_infos.iter().zip(storage_infos.iter()))
+            .flat_map(|(&dst_set, (buffer_info, storage_infos))| {
+                [
+                    vk::WriteDescriptorSet {
+                        dst_set,
+                        dst_binding: 0,
+                        descriptor_count: 1,
+                        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                        p_buffer_info: buffer_info as *const _,
+                        ..Default::default()
+                    },
+                    vk::WriteDescriptorSet {
+                        dst_set,
+                        dst_binding: 1,
+                        descriptor_count: shadow_infos.len() as u32,
+                        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                        p_image_info: shadow_infos.as_ptr(),
+                        ..Default::default()
+                    },
+                    // The point lights and cluster lists are consecutive bindings
+                    vk::WriteDescriptorSet {
+                        dst_set,
+                        dst_binding: 2,
+                        descriptor_count: storage_infos.len() as u32,
+                        descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                        p_buffer_info: storage_infos.as_ptr(),
+                        ..Default::default()
+                    },
+                ]
+            })
+            .collect();
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        let texel = |data: [u8; 4]| DecodedTexture {
+            format: vk::Format::R8G8B8A8_UNORM,
+            levels: vec![TextureLevel {
+                width: 1,
+                height: 1,
+                data: data.to_vec(),
+            }],
+        };
+        let mut defaults = Application::upload_textures(
+            instance,
+            device,
+            queue,
+            physical_device,
+            command_pool,
+            sync_pool,
+            &mut [texel(FLAT_NORMAL), texel([u8::MAX; 4])],
+        )?;
+        let white = defaults.pop().unwrap();
+        let flat_normal = defaults.pop().unwrap();
+
+        Ok(Self {
+            pipelines,
+            pipeline_layout,
+            samples,
+            material_set_layout,
+            lighting_set_layout,
+            descriptor_pool,
+            lighting_buffers,
+            lighting_sets,
+            flat_normal,
+            white,
+            sampler,
+            materials: Vec::new(),
+            point_shadows,
+            clustered_lighting,
+            ray_query_shadows: None,
+            ray_queries_enabled: false,
+        })
+    }
+
+    fn create_set_layout(
+        device: &Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> AppResult<vk::DescriptorSetLayout> {
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_descriptor_set_layout(&layout_info, None)?,
+            ))
+        }
+    }
+
+    /// Creates the variants of the pipeline shading with `frag_shader_code`, drawing the
+    /// double-sided materials without culling and blending the others
+    fn create_pipelines(
+        device: &Device,
+        scene_render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        pipeline_layout: vk::PipelineLayout,
+        frag_shader_code: &[u32],
+    ) -> AppResult<[vk::Pipeline; PIPELINE_VARIANTS]> {
+        let vert_shader_code = load_shader("lit.vert", include_bytes!("spirv/lit.spv"))?;
+        let mut pipelines = [vk::Pipeline::null(); PIPELINE_VARIANTS];
+        for double_sided in [false, true] {
+            for blended in [false, true] {
+                pipelines[variant_index(double_sided, blended)] =
+                    Application::create_mesh_pipeline(
+                        device,
+                        scene_render_pass,
+                        samples,
+                        pipeline_layout,
+                        &vert_shader_code,
+                        frag_shader_code,
+                        if double_sided {
+                            vk::CullModeFlags::NONE
+                        } else {
+                            vk::CullModeFlags::BACK
+                        },
+                        if blended {
+                            MeshAttachments::BlendedColor
+                        } else {
+                            MeshAttachments::Color
+                        },
+                        vk::PolygonMode::FILL,
+                    )?;
+            }
+        }
+
+        Ok(pipelines)
+    }
+
+    /// Variant of the pipeline drawing the material `index`
+    pub(crate) fn pipeline(&self, index: usize) -> vk::Pipeline {
+        let variant = self.materials[index].variant;
+        self.active_ray_queries()
+            .map_or(self.pipelines[variant], |shadows| {
+                shadows.pipelines[variant]
+            })
+    }
+
+    /// Whether the material `index` is blended over the scene, see `AlphaMode::Blend`
+    pub(crate) fn is_blended(&self, index: usize) -> bool {
+        self.materials[index].variant >= variant_index(false, true)
+    }
+
+    /// Layout the scene set is bound with for this pipeline, as it has its own sets
+    pub(crate) fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.active_ray_queries()
+            .map_or(self.pipeline_layout, |shadows| shadows.pipeline_layout)
+    }
+
+    fn active_ray_queries(&self) -> Option<&RayQueryShadows> {
+        self.ray_query_shadows
+            .as_ref()
+            .filter(|_| self.ray_queries_enabled)
+    }
+
+    /// Traces the shadows of every light with ray queries against `tlases`, the top-level
+    /// acceleration structure of each frame, instead of sampling the shadow cubemaps. The variant
+    /// of the pipeline is created the first time, `None` going back to the cubemaps.
+    pub(crate) fn set_ray_query_shadows(
+        &mut self,
+        device: &Device,
+        scene_render_pass: vk::RenderPass,
+        scene_set_layout: vk::DescriptorSetLayout,
+        tlases: Option<&[vk::AccelerationStructureKHR]>,
+    ) -> AppResult<()> {
+        if let (None, Some(tlases)) = (&self.ray_query_shadows, tlases) {
+            self.ray_query_shadows = Some(self.create_ray_query_shadows(
+                device,
+                scene_render_pass,
+                scene_set_layout,
+                tlases,
+            )?);
+        }
+        self.ray_queries_enabled = tlases.is_some();
+
+        Ok(())
+    }
+
+    fn create_ray_query_shadows(
+        &self,
+        device: &Device,
+        scene_render_pass: vk::RenderPass,
+        scene_set_layout: vk::DescriptorSetLayout,
+        tlases: &[vk::AccelerationStructureKHR],
+    ) -> AppResult<RayQueryShadows> {
+        let set_layout = Self::create_set_layout(
+            device,
+            &[vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            }],
+        )?;
+        // Compatible with the layout of the cubemap pipeline for the first three sets
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[
+                scene_set_layout,
+                self.material_set_layout,
+                self.lighting_set_layout,
+                set_layout,
+            ],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<MaterialFactors>() as u32,
+            }],
+        )?;
+        let pipelines = Self::create_pipelines(
+            device,
+            scene_render_pass,
+            self.samples,
+            pipeline_layout,
+            &load_shader(
+                "pbr_ray_query.frag",
+                include_bytes!("spirv/pbr_ray_query.spv"),
+            )?,
+        )?;
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            descriptor_count: tlases.len() as u32,
+        };
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: tlases.len() as u32,
+            pool_size_count: 1,
+            p_pool_sizes: &pool_size,
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let layouts = vec![set_layout; tlases.len()];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+        let sets = unsafe { device.allocate_descriptor_sets(&alloc_info)? };
+        for (&dst_set, tlas) in sets.iter().zip(tlases) {
+            let mut accel_write = vk::WriteDescriptorSetAccelerationStructureKHR {
+                acceleration_structure_count: 1,
+                p_acceleration_structures: tlas,
+                ..Default::default()
+            };
+            let write = vk::WriteDescriptorSet {
+                dst_set,
+                dst_binding: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                ..Default::default()
+            }
+            .push_next(&mut accel_write);
+            unsafe { device.update_descriptor_sets(&[write], &[]) };
+        }
+
+        Ok(RayQueryShadows {
+            pipelines,
+            pipeline_layout,
+            set_layout,
+            descriptor_pool,
+            sets,
+        })
+    }
+
+    /// Uploads the maps of `material` and returns its index along with the view of its albedo,
+    /// which is bound with the scene set. The albedo and emissive maps are sampled as sRGB, the
+    /// others as linear values. Fails once `MAX_PBR_MATERIALS` materials are added.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn add_material(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        material: PbrMaterial<DecodedTexture>,
+    ) -> AppResult<(usize, vk::ImageView)> {
+        if self.materials.len() >= MAX_PBR_MATERIALS {
+            return AppResult::Err(AppError::new(AppErrorType::TooManyPbrMaterials));
+        }
+
+        let mut material_set = self.upload_material(
+            instance,
+            device,
+            queue,
+            physical_device,
+            command_pool,
+            sync_pool,
+            material,
+        )?;
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool: self.descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &self.material_set_layout as *const _,
+            ..Default::default()
+        };
+        material_set.descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+        Self::write_material_set(
+            device,
+            material_set.descriptor_set,
+            material_set.map_views,
+            self.sampler,
+        );
+
+        let albedo_view = material_set.maps[0].view;
+        let index = self.materials.len();
+        self.materials.push(material_set);
+
+        Ok((index, albedo_view))
+    }
+
+    /// Replaces the maps and factors of the material `index`, rewriting its set which must not be
+    /// in use by the GPU. Returns the view of the new albedo along with the previous maps, to be
+    /// destroyed once the GPU is done with them.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn replace_material(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        index: usize,
+        material: PbrMaterial<DecodedTexture>,
+    ) -> AppResult<(vk::ImageView, Vec<TextureHolder>)> {
+        let mut material_set = self.upload_material(
+            instance,
+            device,
+            queue,
+            physical_device,
+            command_pool,
+            sync_pool,
+            material,
+        )?;
+        material_set.descriptor_set = self.materials[index].descriptor_set;
+        Self::write_material_set(
+            device,
+            material_set.descriptor_set,
+            material_set.map_views,
+            self.sampler,
+        );
+
+        let albedo_view = material_set.maps[0].view;
+        let previous = std::mem::replace(&mut self.materials[index], material_set);
+
+        Ok((albedo_view, previous.maps))
+    }
+
+    // Uploads the maps of a material, its set being left for the caller to allocate or reuse
+    #[allow(clippy::too_many_arguments)]
+    fn upload_material(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        material: PbrMaterial<DecodedTexture>,
+    ) -> AppResult<MaterialSet> {
+        let given_maps = [
+            material.normal.is_some(),
+            material.metallic_roughness.is_some(),
+            material.occlusion.is_some(),
+            material.emissive.is_some(),
+        ];
+        let linear = |map: Option<DecodedTexture>| map.map(DecodedTexture::into_linear);
+        let mut decoded: Vec<DecodedTexture> = [
+            Some(material.albedo),
+            linear(material.normal),
+            linear(material.metallic_roughness),
+            linear(material.occlusion),
+            material.emissive,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let maps = Application::upload_textures(
+            instance,
+            device,
+            queue,
+            physical_device,
+            command_pool,
+            sync_pool,
+            &mut decoded,
+        )?;
+
+        // The given maps follow the albedo in order, the defaults stand in for the others
+        let mut given = maps.iter().skip(1).map(|map| map.view);
+        let defaults = [&self.flat_normal, &self.white, &self.white, &self.white];
+        let map_views: Vec<vk::ImageView> = given_maps
+            .iter()
+            .zip(defaults)
+            .map(|(&is_given, default)| {
+                if is_given {
+                    given.next().unwrap()
+                } else {
+                    default.view
+                }
+            })
+            .collect();
+        let map_views = [map_views[0], map_views[1], map_views[2], map_views[3]];
+
+        let blended = material.alpha_mode == AlphaMode::Blend;
+        let alpha_cutoff = match material.alpha_mode {
+            AlphaMode::Mask(cutoff) => cutoff,
+            AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+        };
+        let emissive = material.emissive_factor;
+        let factors = MaterialFactors {
+            base_color: material.base_color.into(),
+            emissive: [emissive.x, emissive.y, emissive.z, alpha_cutoff],
+            metallic: material.metallic,
+            roughness: material.roughness,
+            opaque: if blended { 0.0 } else { 1.0 },
+        };
+
+        Ok(MaterialSet {
+            maps,
+            map_views,
+            descriptor_set: vk::DescriptorSet::null(),
+            factors,
+            variant: variant_index(material.double_sided, blended),
+        })
+    }
+
+    fn write_material_set(
+        device: &Device,
+        descriptor_set: vk::DescriptorSet,
+        map_views: [vk::ImageView; 4],
+        sampler: vk::Sampler,
+    ) {
+        let image_infos = map_views.map(|image_view| vk::DescriptorImageInfo {
+            sampler,
+            image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        });
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            descriptor_count: image_infos.len() as u32,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: image_infos.as_ptr(),
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+    }
+
+    /// Samples the maps of every material with `sampler`, rewriting their sets which must not be
+    /// in use by the GPU
+    pub(crate) fn set_sampler(&mut self, device: &Device, sampler: vk::Sampler) {
+        self.sampler = sampler;
+        for material in self.materials.iter() {
+            Self::write_material_set(device, material.descriptor_set, material.map_views, sampler);
+        }
+    }
+
+    /// Size of the memory of the maps of a material
+    pub(crate) fn memory_size(&self, device: &Device, index: usize) -> u64 {
+        self.materials[index]
+            .maps
+            .iter()
+            .map(|map| unsafe { device.get_image_memory_requirements(map.image.image).size })
+            .sum()
+    }
+
+    /// Writes the lighting seen from `view` through `proj` on a target of `extent` to the buffers
+    /// of `frame`, which must not be in use by the GPU
+    pub(crate) fn write_lighting(
+        &self,
+        frame: usize,
+        lighting: &PbrLighting,
+        view: Mat4,
+        proj: Mat4,
+        extent: vk::Extent2D,
+    ) {
+        let camera_position = view.invert().map_or(Point3::origin(), |inverse| {
+            Point3::from_vec(inverse.w.truncate())
+        });
+        let mut directional_lights = [[[0.0; 4]; 3]; MAX_DIRECTIONAL_PBR_LIGHTS];
+        let mut directional_count = 0;
+        let mut point_lights = Vec::with_capacity(lighting.lights.len());
+        let mut shadow_count = 0;
+        for light in lighting.lights.iter().take(MAX_PBR_LIGHTS) {
+            match *light {
+                PbrLight::Directional {
+                    direction,
+                    color,
+                    intensity,
+                } => {
+                    if directional_count == MAX_DIRECTIONAL_PBR_LIGHTS {
+                        continue;
+                    }
+                    let direction = direction.normalize();
+                    let radiance = color * intensity;
+                    directional_lights[directional_count] = [
+                        [direction.x, direction.y, direction.z, 0.0],
+                        [radiance.x, radiance.y, radiance.z, 0.0],
+                        [1.0, 0.0, 0.0, 0.0],
+                    ];
+                    directional_count += 1;
+                }
+                PbrLight::Point {
+                    position,
+                    color,
+                    intensity,
+                    attenuation,
+                    shadow_range,
+                } => {
+                    let radiance = color * intensity;
+                    // Same order as `PbrLighting::shadow_casters`
+                    let (shadow_map, range) = match shadow_range {
+                        Some(range) => {
+                            shadow_count += 1;
+                            ((shadow_count - 1) as f32, range)
+                        }
+                        None => (-1.0, 0.0),
+                    };
+                    point_lights.push([
+                        [
+                            position.x,
+                            position.y,
+                            position.z,
+                            influence_radius(radiance, attenuation),
+                        ],
+                        [radiance.x, radiance.y, radiance.z, shadow_map],
+                        [
+                            attenuation.constant,
+                            attenuation.linear,
+                            attenuation.quadratic,
+                            range,
+                        ],
+                    ]);
+                }
+            }
+        }
+        self.clustered_lighting.write_lights(frame, &point_lights);
+
+        // Planes of a right handed perspective projection, mapping the depth to [-1, 1]
+        let near = proj.w.z / (proj.z.z - 1.0);
+        let far = proj.w.z / (proj.z.z + 1.0);
+        let uniforms = LightingUniforms {
+            camera_position: [camera_position.x, camera_position.y, camera_position.z, 1.0],
+            ambient: [
+                lighting.ambient.x,
+                lighting.ambient.y,
+                lighting.ambient.z,
+                0.0,
+            ],
+            light_count: [directional_count as u32, point_lights.len() as u32, 0, 0],
+            view: view.into(),
+            inverse_proj: proj.invert().unwrap_or(Mat4::identity()).into(),
+            cluster: [extent.width as f32, extent.height as f32, near, far],
+            directional_lights,
+        };
+
+        unsafe {
+            std::ptr::copy(
+                &uniforms as *const LightingUniforms,
+                self.lighting_buffers[frame].memory_map as *mut LightingUniforms,
+                1,
+            );
+        }
+    }
+
+    /// Records the culling of the point lights of `frame` against the clusters of the view,
+    /// before the scene render pass
+    pub(crate) fn record_light_culling(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        self.clustered_lighting
+            .record(device, command_buffer, self.lighting_sets[frame]);
+    }
+
+    /// Buffer of the cluster lists of `frame`, written by the light culling and read by the
+    /// scene render pass
+    pub(crate) fn cluster_buffer(&self, frame: usize) -> vk::Buffer {
+        self.clustered_lighting.cluster_buffer(frame)
+    }
+
+    /// Image holding the shadow cubemaps of every shadowed light
+    pub(crate) fn shadow_image(&self) -> vk::Image {
+        self.point_shadows.image()
+    }
+
+    /// Records the rendering of the shadow cubemaps of the point lights of `lighting`, before the
+    /// scene render pass. `draw` records the draws of the scene with the scene set bound with the
+    /// given layout. Nothing is rendered while the shadows are traced with ray queries.
+    pub(crate) fn record_shadows(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        lighting: &PbrLighting,
+        draw: impl FnMut(vk::PipelineLayout),
+    ) {
+        if self.active_ray_queries().is_some() {
+            return;
+        }
+
+        self.point_shadows
+            .record(device, command_buffer, &lighting.shadow_casters(), draw);
+    }
+
+    /// Binds the maps of the material `index` and the lighting of `frame` for the following
+    /// draws, the scene set being bound with `pipeline_layout`
+    pub(crate) fn record_material(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        index: usize,
+        frame: usize,
+    ) {
+        let material = &self.materials[index];
+        let mut sets = vec![material.descriptor_set, self.lighting_sets[frame]];
+        sets.extend(self.active_ray_queries().map(|shadows| shadows.sets[frame]));
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout(),
+                1,
+                &sets,
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout(),
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::slice::from_raw_parts(
+                    &material.factors as *const MaterialFactors as *const u8,
+                    std::mem::size_of::<MaterialFactors>(),
+                ),
+            );
+        }
+    }
+
+    /// Destroys the maps of every material and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            let maps = self.materials.iter().flat_map(|material| &material.maps);
+            for texture in maps.chain([&self.flat_normal, &self.white]) {
+                handle_registry::unregister(texture.view);
+                device.destroy_image_view(texture.view, None);
+                handle_registry::unregister(texture.image.image);
+                device.destroy_image(texture.image.image, None);
+                handle_registry::unregister(texture.image.memory);
+                device.free_memory(texture.image.memory, None);
+            }
+            self.materials.clear();
+            self.point_shadows.destroy(device);
+            self.clustered_lighting.destroy(device);
+            if let Some(shadows) = self.ray_query_shadows.take() {
+                handle_registry::unregister(shadows.descriptor_pool);
+                device.destroy_descriptor_pool(shadows.descriptor_pool, None);
+                for pipeline in shadows.pipelines {
+                    handle_registry::unregister(pipeline);
+                    device.destroy_pipeline(pipeline, None);
+                }
+                handle_registry::unregister(shadows.pipeline_layout);
+                device.destroy_pipeline_layout(shadows.pipeline_layout, None);
+                handle_registry::unregister(shadows.set_layout);
+                device.destroy_descriptor_set_layout(shadows.set_layout, None);
+            }
+
+            for buffer in self.lighting_buffers.iter() {
+                handle_registry::unregister(buffer.buffer);
+                device.destroy_buffer(buffer.buffer, None);
+                handle_registry::unregister(buffer.memory);
+                device.free_memory(buffer.memory, None);
+            }
+            handle_registry::unregister(self.descriptor_pool);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            for pipeline in self.pipelines {
+                handle_registry::unregister(pipeline);
+                device.destroy_pipeline(pipeline, None);
+            }
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            for set_layout in [self.material_set_layout, self.lighting_set_layout] {
+                handle_registry::unregister(set_layout);
+                device.destroy_descriptor_set_layout(set_layout, None);
+            }
+        }
+    }
+}
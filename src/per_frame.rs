@@ -0,0 +1,101 @@
+use std::ops::{Index, IndexMut};
+
+use crate::AppResult;
+
+/// One `T` per frame in flight, indexed by the frame, so the resources of a frame are created,
+/// resized and destroyed together instead of being kept in sync by hand across several lists
+#[derive(Clone, Debug, Default)]
+pub struct PerFrame<T> {
+    frames: Vec<T>,
+}
+
+impl<T> PerFrame<T> {
+    /// Creates the resources of each of the `count` frames with `create`, which is given the
+    /// index of the frame
+    pub fn new(count: usize, create: impl FnMut(usize) -> AppResult<T>) -> AppResult<Self> {
+        Ok(Self {
+            frames: (0..count).map(create).collect::<AppResult<_>>()?,
+        })
+    }
+
+    /// Number of frames in flight
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Index of the frame following `frame`, wrapping around
+    pub fn next_index(&self, frame: usize) -> usize {
+        (frame + 1) % self.frames.len()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.frames.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.frames.iter_mut()
+    }
+
+    /// Sets the number of frames in flight to `count`, creating the resources of the new frames
+    /// with `create`. Returns the resources of the removed frames, to be destroyed once the GPU
+    /// is done with them.
+    pub fn resize_with(
+        &mut self,
+        count: usize,
+        mut create: impl FnMut(usize) -> AppResult<T>,
+    ) -> AppResult<Vec<T>> {
+        if count <= self.frames.len() {
+            return Ok(self.frames.split_off(count));
+        }
+
+        for frame in self.frames.len()..count {
+            self.frames.push(create(frame)?);
+        }
+
+        Ok(Vec::new())
+    }
+}
+
+impl<T> FromIterator<T> for PerFrame<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self {
+            frames: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<T> Index<usize> for PerFrame<T> {
+    type Output = T;
+
+    fn index(&self, frame: usize) -> &T {
+        &self.frames[frame]
+    }
+}
+
+impl<T> IndexMut<usize> for PerFrame<T> {
+    fn index_mut(&mut self, frame: usize) -> &mut T {
+        &mut self.frames[frame]
+    }
+}
+
+impl<'a, T> IntoIterator for &'a PerFrame<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.frames.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut PerFrame<T> {
+    type Item = &'a mut T;
+    type IntoIter = std::slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.frames.iter_mut()
+    }
+}
@@ -0,0 +1,183 @@
+use cgmath::{EuclideanSpace, InnerSpace, SquareMatrix, Transform, Vector4};
+use dpi::PhysicalPosition;
+
+use crate::{Mat4, Point3, Vec3};
+
+/// Axis aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Self {
+        Self { min, max }
+    }
+
+    /// Smallest box containing every point, `None` if there is none
+    pub fn from_points(points: impl IntoIterator<Item = Point3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self::new(first, first), |aabb, point| Self {
+            min: Point3::new(
+                aabb.min.x.min(point.x),
+                aabb.min.y.min(point.y),
+                aabb.min.z.min(point.z),
+            ),
+            max: Point3::new(
+                aabb.max.x.max(point.x),
+                aabb.max.y.max(point.y),
+                aabb.max.z.max(point.z),
+            ),
+        }))
+    }
+
+    /// Box containing this one once transformed by `transform`
+    pub fn transformed(&self, transform: Mat4) -> Self {
+        let corners = (0..8).map(|i| {
+            let corner = Point3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            transform.transform_point(corner)
+        });
+
+        Self::from_points(corners).unwrap()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Point3,
+    /// Normalized direction
+    pub direction: Vec3,
+}
+
+impl Ray {
+    /// Ray going from the camera through `cursor`, in physical pixels from the top left corner of
+    /// a surface of `width` by `height` pixels. `None` if the matrices can't be inverted.
+    pub fn from_cursor(
+        cursor: PhysicalPosition<f64>,
+        width: u32,
+        height: u32,
+        view: Mat4,
+        proj: Mat4,
+    ) -> Option<Self> {
+        let inverse = (proj * view).invert()?;
+
+        // The projection isn't flipped, clip space Y points down like the cursor Y
+        let x = (2.0 * cursor.x / width as f64 - 1.0) as f32;
+        let y = (2.0 * cursor.y / height as f64 - 1.0) as f32;
+
+        let unproject = |depth: f32| {
+            let point = inverse * Vector4::new(x, y, depth, 1.0);
+            Point3::from_vec(point.truncate() / point.w)
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+
+        Some(Self {
+            origin: near,
+            direction: (far - near).normalize(),
+        })
+    }
+
+    /// Distance along the ray to the entry point in `aabb`, 0 if the origin is inside
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = f32::INFINITY;
+
+        for axis in 0..3 {
+            let origin = self.origin[axis];
+            let direction = self.direction[axis];
+            let (min, max) = (aabb.min[axis], aabb.max[axis]);
+
+            if direction.abs() < f32::EPSILON {
+                // Parallel to the slab, misses it unless already between the planes
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+
+            let t1 = (min - origin) / direction;
+            let t2 = (max - origin) / direction;
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+            if t_min > t_max {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+}
+
+/// Identifies a pickable object
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PickId(u64);
+
+/// Closest object under the cursor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PickHit {
+    pub id: PickId,
+    /// Distance from the camera to the bounding volume of the object
+    pub distance: f32,
+    /// Point where the ray enters the bounding volume, in world space
+    pub position: Point3,
+}
+
+/// Objects that can be picked, each made of a local bounding box and a model transform
+#[derive(Default)]
+pub struct Pickables {
+    objects: Vec<(PickId, Aabb, Mat4)>,
+    next_id: u64,
+}
+
+impl Pickables {
+    pub fn add(&mut self, bounds: Aabb, transform: Mat4) -> PickId {
+        let id = PickId(self.next_id);
+        self.next_id += 1;
+        self.objects.push((id, bounds, transform));
+        id
+    }
+
+    /// Returns whether the object exists
+    pub fn set_transform(&mut self, id: PickId, transform: Mat4) -> bool {
+        match self
+            .objects
+            .iter_mut()
+            .find(|(object_id, _, _)| *object_id == id)
+        {
+            Some(object) => {
+                object.2 = transform;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether the object existed
+    pub fn remove(&mut self, id: PickId) -> bool {
+        let count = self.objects.len();
+        self.objects.retain(|(object_id, _, _)| *object_id != id);
+        count != self.objects.len()
+    }
+
+    /// Returns the closest object hit by `ray`
+    pub fn pick(&self, ray: &Ray) -> Option<PickHit> {
+        self.objects
+            .iter()
+            .filter_map(|(id, bounds, transform)| {
+                let distance = ray.intersect_aabb(&bounds.transformed(*transform))?;
+                Some(PickHit {
+                    id: *id,
+                    distance,
+                    position: ray.origin + ray.direction * distance,
+                })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
@@ -0,0 +1,631 @@
+use std::ffi::CString;
+
+use ash::{vk, Device};
+
+use crate::{
+    handle_registry, load_shader,
+    pipeline_manager::{MeshPipelineState, PipelineDerivation, PipelineManager},
+    post::SCENE_COLOR_FORMAT,
+    AppResult, Application, GraphicsPipelineHolder, MeshAttachments, Vertex,
+};
+
+impl Application {
+    /// Creates the scene pipeline drawing `samples` samples per pixel, along with its wireframe
+    /// variant when `wireframe` is set
+    pub(crate) fn create_graphics_pipeline(
+        device: &Device,
+        wireframe: bool,
+        samples: vk::SampleCountFlags,
+    ) -> AppResult<GraphicsPipelineHolder> {
+        // The scene is rendered offscreen, resolved when multisampled, then read by the post
+        // chain
+        let renderpass = Self::create_multisampled_render_pass(
+            device,
+            SCENE_COLOR_FORMAT,
+            samples,
+            vk::AttachmentLoadOp::CLEAR,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+
+        let descriptor_set_layout = Self::create_descriptor_set_layout(device)?;
+        let pipeline_layout = Self::create_pipeline_layout(device, &[descriptor_set_layout], &[])?;
+
+        let vert_shader_code = load_shader("vertex.vert", include_bytes!("spirv/vertex.spv"))?;
+        let frag_shader_code = load_shader("fragment.frag", include_bytes!("spirv/fragment.spv"))?;
+        let mut manager = PipelineManager::new(
+            device,
+            renderpass,
+            samples,
+            pipeline_layout,
+            &vert_shader_code,
+            &frag_shader_code,
+        )?;
+        let pipeline = manager.base_pipeline();
+        let transparent_pipeline = manager.get_or_create(
+            device,
+            pipeline_layout,
+            &vert_shader_code,
+            &frag_shader_code,
+            MeshPipelineState {
+                attachments: MeshAttachments::BlendedColor,
+                ..Default::default()
+            },
+        )?;
+        // Draws the edges of the meshes, needs the `fill_mode_non_solid` feature
+        let wireframe_pipeline = if wireframe {
+            Some(manager.get_or_create(
+                device,
+                pipeline_layout,
+                &vert_shader_code,
+                &load_shader("wireframe.frag", include_bytes!("spirv/wireframe.spv"))?,
+                MeshPipelineState {
+                    cull_mode: vk::CullModeFlags::NONE,
+                    attachments: MeshAttachments::Color,
+                    polygon_mode: vk::PolygonMode::LINE,
+                },
+            )?)
+        } else {
+            None
+        };
+
+        Ok(GraphicsPipelineHolder {
+            renderpass,
+            samples,
+            manager,
+            pipeline,
+            transparent_pipeline,
+            wireframe_pipeline,
+            pipeline_layout,
+            descriptor_set_layout,
+        })
+    }
+
+    /// Creates a pipeline drawing meshes with the vertex layout and states of the scene pass,
+    /// `samples` being the samples per pixel of `renderpass`
+    pub(crate) fn create_scene_pipeline(
+        device: &Device,
+        renderpass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        pipeline_layout: vk::PipelineLayout,
+        vert_shader_code: &[u32],
+        frag_shader_code: &[u32],
+        cull_mode: vk::CullModeFlags,
+    ) -> AppResult<vk::Pipeline> {
+        Self::create_mesh_pipeline(
+            device,
+            renderpass,
+            samples,
+            pipeline_layout,
+            vert_shader_code,
+            frag_shader_code,
+            cull_mode,
+            MeshAttachments::Color,
+            vk::PolygonMode::FILL,
+        )
+    }
+
+    /// Creates a pipeline drawing the meshes to the depth attachment alone of `renderpass`, with
+    /// depth testing, e.g. for shadow maps
+    pub(crate) fn create_depth_pipeline(
+        device: &Device,
+        renderpass: vk::RenderPass,
+        pipeline_layout: vk::PipelineLayout,
+        vert_shader_code: &[u32],
+        frag_shader_code: &[u32],
+        cull_mode: vk::CullModeFlags,
+    ) -> AppResult<vk::Pipeline> {
+        Self::create_mesh_pipeline(
+            device,
+            renderpass,
+            vk::SampleCountFlags::TYPE_1,
+            pipeline_layout,
+            vert_shader_code,
+            frag_shader_code,
+            cull_mode,
+            MeshAttachments::Depth,
+            vk::PolygonMode::FILL,
+        )
+    }
+
+    /// Creates a pipeline drawing the meshes to the color and depth attachments of `renderpass`,
+    /// with depth testing, e.g. for the geometry prepass
+    pub(crate) fn create_depth_tested_pipeline(
+        device: &Device,
+        renderpass: vk::RenderPass,
+        pipeline_layout: vk::PipelineLayout,
+        vert_shader_code: &[u32],
+        frag_shader_code: &[u32],
+        cull_mode: vk::CullModeFlags,
+    ) -> AppResult<vk::Pipeline> {
+        Self::create_mesh_pipeline(
+            device,
+            renderpass,
+            vk::SampleCountFlags::TYPE_1,
+            pipeline_layout,
+            vert_shader_code,
+            frag_shader_code,
+            cull_mode,
+            MeshAttachments::ColorDepth,
+            vk::PolygonMode::FILL,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn create_mesh_pipeline(
+        device: &Device,
+        renderpass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        pipeline_layout: vk::PipelineLayout,
+        vert_shader_code: &[u32],
+        frag_shader_code: &[u32],
+        cull_mode: vk::CullModeFlags,
+        attachments: MeshAttachments,
+        polygon_mode: vk::PolygonMode,
+    ) -> AppResult<vk::Pipeline> {
+        Self::create_derived_mesh_pipeline(
+            device,
+            renderpass,
+            samples,
+            pipeline_layout,
+            vert_shader_code,
+            frag_shader_code,
+            cull_mode,
+            attachments,
+            polygon_mode,
+            PipelineDerivation::Standalone,
+        )
+    }
+
+    /// Creates a mesh pipeline as the base or a derivative of other pipelines, see
+    /// `PipelineManager`
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn create_derived_mesh_pipeline(
+        device: &Device,
+        renderpass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        pipeline_layout: vk::PipelineLayout,
+        vert_shader_code: &[u32],
+        frag_shader_code: &[u32],
+        cull_mode: vk::CullModeFlags,
+        attachments: MeshAttachments,
+        polygon_mode: vk::PolygonMode,
+        derivation: PipelineDerivation,
+    ) -> AppResult<vk::Pipeline> {
+        let vert_module = Self::create_shader_module(device, vert_shader_code)?;
+        let frag_module = Self::create_shader_module(device, frag_shader_code)?;
+
+        let entry_point = CString::new("main").unwrap();
+        let vert_shader_stage_info = vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::VERTEX,
+            module: vert_module,
+            p_name: entry_point.as_ptr(),
+            ..Default::default()
+        };
+        let frag_shader_stage_info = vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::FRAGMENT,
+            module: frag_module,
+            p_name: entry_point.as_ptr(),
+            ..Default::default()
+        };
+
+        let shader_stages_infos = [vert_shader_stage_info, frag_shader_stage_info];
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo {
+            dynamic_state_count: dynamic_states.len() as u32,
+            p_dynamic_states: &dynamic_states as *const _,
+            ..Default::default()
+        };
+
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo {
+            vertex_binding_description_count: Vertex::BINDING_DESCRIPTIONS.len() as u32,
+            p_vertex_binding_descriptions: Vertex::BINDING_DESCRIPTIONS.as_ptr(),
+            vertex_attribute_description_count: Vertex::ATTRIBUTE_DESCRIPTIONS.len() as u32,
+            p_vertex_attribute_descriptions: Vertex::ATTRIBUTE_DESCRIPTIONS.as_ptr(),
+            ..Default::default()
+        };
+
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart_enable: false.into(),
+            ..Default::default()
+        };
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            viewport_count: 1,
+            scissor_count: 1,
+            ..Default::default()
+        };
+
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo {
+            depth_clamp_enable: false.into(),
+            rasterizer_discard_enable: false.into(),
+            polygon_mode,
+            cull_mode,
+            front_face: vk::FrontFace::CLOCKWISE,
+            depth_bias_enable: false.into(),
+            depth_bias_constant_factor: 0.0,
+            depth_bias_clamp: 0.0,
+            depth_bias_slope_factor: 0.0,
+            line_width: 1.0,
+            ..Default::default()
+        };
+
+        let multisampling = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: samples,
+            sample_shading_enable: false.into(),
+            min_sample_shading: 1.0,
+            alpha_to_coverage_enable: false.into(),
+            alpha_to_one_enable: false.into(),
+            ..Default::default()
+        };
+
+        let color_blend_attachment = match attachments {
+            MeshAttachments::BlendedColor => vk::PipelineColorBlendAttachmentState {
+                blend_enable: true.into(),
+                src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+                dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                color_blend_op: vk::BlendOp::ADD,
+                src_alpha_blend_factor: vk::BlendFactor::ONE,
+                dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                alpha_blend_op: vk::BlendOp::ADD,
+                color_write_mask: vk::ColorComponentFlags::RGBA,
+            },
+            MeshAttachments::Stencil(_) => vk::PipelineColorBlendAttachmentState {
+                blend_enable: false.into(),
+                color_write_mask: vk::ColorComponentFlags::empty(),
+                ..Default::default()
+            },
+            _ => vk::PipelineColorBlendAttachmentState {
+                blend_enable: false.into(),
+                color_write_mask: vk::ColorComponentFlags::RGBA,
+                ..Default::default()
+            },
+        };
+
+        let color_blend_attachments = [color_blend_attachment];
+        let color_blending = vk::PipelineColorBlendStateCreateInfo {
+            logic_op_enable: false.into(),
+            logic_op: vk::LogicOp::COPY,
+            attachment_count: if attachments == MeshAttachments::Depth {
+                0
+            } else {
+                color_blend_attachments.len() as u32
+            },
+            p_attachments: &color_blend_attachment as *const _,
+            blend_constants: [0.0; 4],
+            ..Default::default()
+        };
+
+        let depth_stencil = match attachments {
+            MeshAttachments::Stencil(stencil) | MeshAttachments::ColorStencil(stencil) => {
+                let stencil_op_state = vk::StencilOpState {
+                    fail_op: vk::StencilOp::KEEP,
+                    pass_op: stencil.pass_op,
+                    depth_fail_op: vk::StencilOp::KEEP,
+                    compare_op: stencil.compare_op,
+                    compare_mask: !0,
+                    write_mask: !0,
+                    reference: stencil.reference,
+                };
+                vk::PipelineDepthStencilStateCreateInfo {
+                    stencil_test_enable: true.into(),
+                    front: stencil_op_state,
+                    back: stencil_op_state,
+                    ..Default::default()
+                }
+            }
+            _ => vk::PipelineDepthStencilStateCreateInfo {
+                depth_test_enable: true.into(),
+                depth_write_enable: true.into(),
+                depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+                ..Default::default()
+            },
+        };
+        // The scene render passes have no depth attachment
+        let p_depth_stencil_state = match attachments {
+            MeshAttachments::Color | MeshAttachments::BlendedColor => std::ptr::null(),
+            _ => &depth_stencil as *const _,
+        };
+
+        let pipeline_info = vk::GraphicsPipelineCreateInfo {
+            flags: derivation.flags(),
+            stage_count: shader_stages_infos.len() as u32,
+            p_stages: shader_stages_infos.as_ptr(),
+            p_vertex_input_state: &vertex_input_info as *const _,
+            p_input_assembly_state: &input_assembly_info as *const _,
+            p_viewport_state: &viewport_state as *const _,
+            p_rasterization_state: &rasterizer as *const _,
+            p_multisample_state: &multisampling as *const _,
+            p_color_blend_state: &color_blending as *const _,
+            p_depth_stencil_state,
+            p_dynamic_state: &dynamic_state_create_info as *const _,
+            layout: pipeline_layout,
+            render_pass: renderpass,
+            subpass: 0,
+            base_pipeline_handle: derivation.base_pipeline(),
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipelines_infos = [pipeline_info];
+        let pipeline = unsafe {
+            device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &pipelines_infos, None)
+                .or_else(|r| AppResult::Err(r.1.into()))?[0]
+        };
+        handle_registry::register(pipeline);
+
+        unsafe {
+            handle_registry::unregister(vert_module);
+            device.destroy_shader_module(vert_module, None);
+            handle_registry::unregister(frag_module);
+            device.destroy_shader_module(frag_module, None);
+        }
+
+        Ok(pipeline)
+    }
+
+    pub(crate) fn create_compute_pipeline(
+        device: &Device,
+        pipeline_layout: vk::PipelineLayout,
+        shader_code: &[u32],
+    ) -> AppResult<vk::Pipeline> {
+        let module = Self::create_shader_module(device, shader_code)?;
+        let entry_point = CString::new("main").unwrap();
+
+        let pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                stage: vk::ShaderStageFlags::COMPUTE,
+                module,
+                p_name: entry_point.as_ptr(),
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            ..Default::default()
+        };
+
+        let pipeline = unsafe {
+            device
+                .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+                .or_else(|r| AppResult::Err(r.1.into()))?[0]
+        };
+        handle_registry::register(pipeline);
+
+        unsafe {
+            handle_registry::unregister(module);
+            device.destroy_shader_module(module, None);
+        }
+
+        Ok(pipeline)
+    }
+
+    pub(crate) fn create_pipeline_layout(
+        device: &Device,
+        descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_ranges: &[vk::PushConstantRange],
+    ) -> AppResult<vk::PipelineLayout> {
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: descriptor_set_layouts.len() as u32,
+            p_set_layouts: descriptor_set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_pipeline_layout(&pipeline_layout_info, None)?,
+            ))
+        }
+    }
+
+    /// Creates a render pass with a single color attachment, left in `final_layout`
+    pub(crate) fn create_render_pass(
+        device: &Device,
+        format: vk::Format,
+        load_op: vk::AttachmentLoadOp,
+        final_layout: vk::ImageLayout,
+    ) -> AppResult<vk::RenderPass> {
+        Self::create_multisampled_render_pass(
+            device,
+            format,
+            vk::SampleCountFlags::TYPE_1,
+            load_op,
+            final_layout,
+        )
+    }
+
+    /// Creates a render pass with a single color attachment of `samples` samples per pixel. With
+    /// more than one sample, the attachment is resolved into a second, single sample attachment
+    /// left in `final_layout`, the framebuffers taking the multisampled view first.
+    pub(crate) fn create_multisampled_render_pass(
+        device: &Device,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        load_op: vk::AttachmentLoadOp,
+        final_layout: vk::ImageLayout,
+    ) -> AppResult<vk::RenderPass> {
+        let resolved = samples != vk::SampleCountFlags::TYPE_1;
+        let color_attachment = vk::AttachmentDescription {
+            format,
+            samples,
+            load_op,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout,
+            ..Default::default()
+        };
+        let attachments = if resolved {
+            vec![
+                // Only the resolved samples outlive the pass
+                vk::AttachmentDescription {
+                    store_op: vk::AttachmentStoreOp::DONT_CARE,
+                    final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ..color_attachment
+                },
+                vk::AttachmentDescription {
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    load_op: vk::AttachmentLoadOp::DONT_CARE,
+                    ..color_attachment
+                },
+            ]
+        } else {
+            vec![color_attachment]
+        };
+
+        let color_attachment_refs = [vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        let resolve_attachment_refs = [vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+
+        let subpasses = [vk::SubpassDescription {
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            color_attachment_count: color_attachment_refs.len() as u32,
+            p_color_attachments: color_attachment_refs.as_ptr(),
+            p_resolve_attachments: if resolved {
+                resolve_attachment_refs.as_ptr()
+            } else {
+                std::ptr::null()
+            },
+            ..Default::default()
+        }];
+
+        // The attachment may still be sampled by the previous frame, and is sampled by the
+        // following passes once written
+        let dependencies = [
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                src_access_mask: vk::AccessFlags::empty(),
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ..Default::default()
+            },
+            vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                ..Default::default()
+            },
+        ];
+
+        let renderpass_info = vk::RenderPassCreateInfo {
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: subpasses.len() as u32,
+            p_subpasses: subpasses.as_ptr(),
+            dependency_count: dependencies.len() as u32,
+            p_dependencies: dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_render_pass(&renderpass_info, None)?,
+            ))
+        }
+    }
+
+    // Code taken from https://github.com/gfx-rs/wgpu
+    pub(crate) fn make_spirv_raw(bytes: &[u8]) -> Vec<u32> {
+        let mut words = vec![0u32; bytes.len() / std::mem::size_of::<u32>()];
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                words.as_mut_ptr() as *mut u8,
+                bytes.len(),
+            );
+        }
+
+        words
+    }
+
+    pub(crate) fn create_shader_module(
+        device: &Device,
+        bytes: &[u32],
+    ) -> AppResult<vk::ShaderModule> {
+        let create_info = vk::ShaderModuleCreateInfo {
+            code_size: bytes.len() * 4,
+            p_code: bytes.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_shader_module(&create_info, None)?,
+            ))
+        }
+    }
+
+    pub(crate) fn create_descriptor_set_layout(
+        device: &Device,
+    ) -> AppResult<vk::DescriptorSetLayout> {
+        let ubo_layout_binding = vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            ..Default::default()
+        };
+
+        let sampler_layout_binding = vk::DescriptorSetLayoutBinding {
+            binding: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        };
+
+        let lightmap_layout_binding = vk::DescriptorSetLayoutBinding {
+            binding: 2,
+            ..sampler_layout_binding
+        };
+
+        let bindings = [
+            ubo_layout_binding,
+            sampler_layout_binding,
+            lightmap_layout_binding,
+        ];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_descriptor_set_layout(&layout_info, None)?,
+            ))
+        }
+    }
+}
+
+impl GraphicsPipelineHolder {
+    /// Destroys the scene pipelines along with their layouts and render pass, the device must be
+    /// idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            handle_registry::unregister(self.descriptor_set_layout);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+
+            self.manager.destroy(device);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+
+            handle_registry::unregister(self.renderpass);
+            device.destroy_render_pass(self.renderpass, None);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use ash::{vk, Device};
+
+use crate::{handle_registry, AppResult, Application, MeshAttachments};
+
+/// Relation of a pipeline to the others it is created with, letting the driver reuse the
+/// compiled state of a parent pipeline for its variants
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PipelineDerivation {
+    Standalone,
+    /// The pipeline may be the parent of derivatives
+    Base,
+    /// The pipeline is a variant of the given base pipeline
+    Derivative(vk::Pipeline),
+}
+
+impl PipelineDerivation {
+    pub(crate) fn flags(self) -> vk::PipelineCreateFlags {
+        match self {
+            Self::Standalone => vk::PipelineCreateFlags::empty(),
+            Self::Base => vk::PipelineCreateFlags::ALLOW_DERIVATIVES,
+            Self::Derivative(_) => vk::PipelineCreateFlags::DERIVATIVE,
+        }
+    }
+
+    pub(crate) fn base_pipeline(self) -> vk::Pipeline {
+        match self {
+            Self::Derivative(base_pipeline) => base_pipeline,
+            _ => vk::Pipeline::null(),
+        }
+    }
+}
+
+/// Render state of a mesh pipeline
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct MeshPipelineState {
+    pub(crate) cull_mode: vk::CullModeFlags,
+    pub(crate) attachments: MeshAttachments,
+    pub(crate) polygon_mode: vk::PolygonMode,
+}
+
+impl Default for MeshPipelineState {
+    // The opaque meshes of the scene pass
+    fn default() -> Self {
+        Self {
+            cull_mode: vk::CullModeFlags::BACK,
+            attachments: MeshAttachments::Color,
+            polygon_mode: vk::PolygonMode::FILL,
+        }
+    }
+}
+
+// Everything a pipeline of the manager is created from but the render pass and its samples,
+// which are the same for all of them. The vertex layout is the one of `Vertex` for every mesh
+// pipeline.
+#[derive(PartialEq, Eq, Hash)]
+struct PipelineKey {
+    vert_shader_code: Vec<u32>,
+    frag_shader_code: Vec<u32>,
+    pipeline_layout: vk::PipelineLayout,
+    state: MeshPipelineState,
+}
+
+/// Owns the pipelines of the scene pass, caching them by their shaders, layout and render state
+/// so that the materials asking for the same pipeline share it.
+///
+/// The opaque scene pipeline is the base of the others, the variants being created as its
+/// derivatives so that they are cheaper to create.
+pub(crate) struct PipelineManager {
+    renderpass: vk::RenderPass,
+    samples: vk::SampleCountFlags,
+    base_pipeline: vk::Pipeline,
+    // Every pipeline of the manager, the base one included
+    pipelines: HashMap<PipelineKey, vk::Pipeline>,
+}
+
+impl PipelineManager {
+    /// Creates the base pipeline, drawing the opaque meshes of `renderpass` with `samples`
+    /// samples per pixel
+    pub(crate) fn new(
+        device: &Device,
+        renderpass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        pipeline_layout: vk::PipelineLayout,
+        vert_shader_code: &[u32],
+        frag_shader_code: &[u32],
+    ) -> AppResult<Self> {
+        let key = PipelineKey {
+            vert_shader_code: vert_shader_code.to_vec(),
+            frag_shader_code: frag_shader_code.to_vec(),
+            pipeline_layout,
+            state: MeshPipelineState::default(),
+        };
+        let base_pipeline =
+            Self::create_pipeline(device, renderpass, samples, &key, PipelineDerivation::Base)?;
+
+        Ok(Self {
+            renderpass,
+            samples,
+            base_pipeline,
+            pipelines: HashMap::from([(key, base_pipeline)]),
+        })
+    }
+
+    pub(crate) fn base_pipeline(&self) -> vk::Pipeline {
+        self.base_pipeline
+    }
+
+    /// Returns the pipeline drawing with the given shaders, layout and render state, creating it
+    /// as a variant of the base pipeline the first time it is asked for. The pipeline is owned
+    /// by the manager.
+    pub(crate) fn get_or_create(
+        &mut self,
+        device: &Device,
+        pipeline_layout: vk::PipelineLayout,
+        vert_shader_code: &[u32],
+        frag_shader_code: &[u32],
+        state: MeshPipelineState,
+    ) -> AppResult<vk::Pipeline> {
+        let key = PipelineKey {
+            vert_shader_code: vert_shader_code.to_vec(),
+            frag_shader_code: frag_shader_code.to_vec(),
+            pipeline_layout,
+            state,
+        };
+        if let Some(&pipeline) = self.pipelines.get(&key) {
+            return Ok(pipeline);
+        }
+
+        let pipeline = Self::create_pipeline(
+            device,
+            self.renderpass,
+            self.samples,
+            &key,
+            PipelineDerivation::Derivative(self.base_pipeline),
+        )?;
+        self.pipelines.insert(key, pipeline);
+
+        Ok(pipeline)
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        renderpass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        key: &PipelineKey,
+        derivation: PipelineDerivation,
+    ) -> AppResult<vk::Pipeline> {
+        Application::create_derived_mesh_pipeline(
+            device,
+            renderpass,
+            samples,
+            key.pipeline_layout,
+            &key.vert_shader_code,
+            &key.frag_shader_code,
+            key.state.cull_mode,
+            key.state.attachments,
+            key.state.polygon_mode,
+            derivation,
+        )
+    }
+
+    /// Destroys every pipeline of the manager, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        for (_, pipeline) in self.pipelines.drain() {
+            unsafe {
+                handle_registry::unregister(pipeline);
+                device.destroy_pipeline(pipeline, None);
+            }
+        }
+        self.base_pipeline = vk::Pipeline::null();
+    }
+}
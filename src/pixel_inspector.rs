@@ -0,0 +1,170 @@
+use std::time::{Duration, Instant};
+
+use ash::{vk, Device, Instance};
+use half::f16;
+
+use crate::{handle_registry, AppResult, Application, PickHit, SyncPool};
+
+/// Readbacks made at most by `Application::inspect_pixel` every second, as each one waits for
+/// the device to be idle
+pub const PIXEL_INSPECTIONS_PER_SECOND: u32 = 4;
+
+// Size of a texel of the scene target
+const TEXEL_SIZE: u64 = 4 * std::mem::size_of::<f16>() as u64;
+
+/// Values found under a pixel of the last rendered frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelSample {
+    /// Pixel inspected, from the top left corner of the surface
+    pub x: u32,
+    pub y: u32,
+    /// Linear color of the scene target, before the post effects
+    pub color: [f32; 4],
+    /// Closest pickable object, its distance standing for the depth of the pixel
+    pub hit: Option<PickHit>,
+}
+
+/// Throttles the pixel inspections and reads back the texels of the scene target
+#[derive(Default)]
+pub(crate) struct PixelInspector {
+    last_query: Option<Instant>,
+}
+
+impl PixelInspector {
+    /// Whether a new inspection can be made at `now`, which is then recorded
+    pub(crate) fn try_query(&mut self, now: Instant) -> bool {
+        let interval = Duration::from_secs(1) / PIXEL_INSPECTIONS_PER_SECOND;
+        if self
+            .last_query
+            .is_some_and(|last| now.duration_since(last) < interval)
+        {
+            return false;
+        }
+
+        self.last_query = Some(now);
+        true
+    }
+
+    /// Copies the texel at (`x`, `y`) of the scene target, which must be in the
+    /// `SHADER_READ_ONLY_OPTIMAL` layout and not in use by the GPU. The work is waited for.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn read_texel(
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        image: vk::Image,
+        x: u32,
+        y: u32,
+    ) -> AppResult<[f32; 4]> {
+        let buffer = Application::create_buffer(
+            instance,
+            device,
+            physical_device,
+            TEXEL_SIZE,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let to_transfer = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::SHADER_READ,
+            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+            old_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        };
+        let to_shader = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_READ,
+            dst_access_mask: vk::AccessFlags::SHADER_READ,
+            old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ..to_transfer
+        };
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            },
+            image_extent: vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+        };
+
+        unsafe {
+            let command_buffer = Application::begin_singe_time_command(device, command_pool)?;
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_shader],
+            );
+            Application::end_single_time_command(
+                device,
+                queue,
+                command_pool,
+                sync_pool,
+                command_buffer,
+            )?;
+        }
+
+        let mut texel = [0u8; TEXEL_SIZE as usize];
+        unsafe {
+            let memory_ptr =
+                device.map_memory(buffer.memory, 0, TEXEL_SIZE, vk::MemoryMapFlags::empty())?;
+            std::ptr::copy(memory_ptr as *const u8, texel.as_mut_ptr(), texel.len());
+            device.unmap_memory(buffer.memory);
+
+            handle_registry::unregister(buffer.buffer);
+            device.destroy_buffer(buffer.buffer, None);
+            handle_registry::unregister(buffer.memory);
+            device.free_memory(buffer.memory, None);
+        }
+
+        Ok(std::array::from_fn(|i| {
+            f16::from_le_bytes([texel[2 * i], texel[2 * i + 1]]).to_f32()
+        }))
+    }
+}
@@ -0,0 +1,450 @@
+use ash::{vk, Device, Instance};
+
+use crate::{
+    geometry::{Mat4, Point3, Vec3},
+    handle_registry, load_shader,
+    reflection_probes::FACE_CAMERAS,
+    AppResult, Application, ImageHolder, SyncPool,
+};
+
+/// Size of the faces of the shadow cubemaps of the point lights
+pub const POINT_SHADOW_RESOLUTION: u32 = 256;
+/// Point lights casting shadows at most, see `PbrLight::Point`
+pub const MAX_SHADOWED_POINT_LIGHTS: usize = 4;
+
+// Distance to the light over its range, every device can sample it and render to it
+const SHADOW_FORMAT: vk::Format = vk::Format::D16_UNORM;
+// Near plane of the face projections
+const SHADOW_NEAR: f32 = 0.05;
+
+/// Light casting a shadow in the frame
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowCaster {
+    pub position: Point3,
+    /// Distance up to which the light casts shadows, mapped to the far plane of its cubemap
+    pub range: f32,
+}
+
+// Push constants of the shadow pipeline, shared by both stages
+#[repr(C)]
+struct FaceConstants {
+    view_proj: [[f32; 4]; 4],
+    // xyz: position of the light, w: range
+    light: [f32; 4],
+}
+
+/// Cubemaps of the distance from the shadowed point lights to the closest surface, rendered every
+/// frame before the scene pass by drawing the draw list once per face.
+///
+/// The cubemaps are the layers of a single depth image, each face storing the distance to the
+/// light over its range. The shading compares the distance of a point to the stored one.
+pub(crate) struct PointShadows {
+    render_pass: vk::RenderPass,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    image: ImageHolder,
+    // One cube view per light, then one view and framebuffer per light and face, light major
+    cube_views: Vec<vk::ImageView>,
+    face_views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+    sampler: vk::Sampler,
+}
+
+impl PointShadows {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        scene_set_layout: vk::DescriptorSetLayout,
+    ) -> AppResult<Self> {
+        let render_pass = Self::create_render_pass(device)?;
+
+        // The draw items keep their scene set for their model matrix
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[scene_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<FaceConstants>() as u32,
+            }],
+        )?;
+        let pipeline = Application::create_depth_pipeline(
+            device,
+            render_pass,
+            pipeline_layout,
+            &load_shader(
+                "point_shadow.vert",
+                include_bytes!("spirv/point_shadow.spv"),
+            )?,
+            &load_shader(
+                "point_shadow_depth.frag",
+                include_bytes!("spirv/point_shadow_depth.spv"),
+            )?,
+            // The faces are mirrored compared to the camera, which flips the winding
+            vk::CullModeFlags::NONE,
+        )?;
+
+        let layer_count = 6 * MAX_SHADOWED_POINT_LIGHTS as u32;
+        let image_info = vk::ImageCreateInfo {
+            flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            image_type: vk::ImageType::TYPE_2D,
+            format: SHADOW_FORMAT,
+            extent: vk::Extent3D {
+                width: POINT_SHADOW_RESOLUTION,
+                height: POINT_SHADOW_RESOLUTION,
+                depth: 1,
+            },
+            mip_levels: 1,
+            array_layers: layer_count,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            ..Default::default()
+        };
+        let image = unsafe { device.create_image(&image_info, None)? };
+        handle_registry::register(image);
+
+        let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
+        let alloc_info = vk::MemoryAllocateInfo {
+            allocation_size: mem_requirements.size,
+            memory_type_index: Application::find_memory_type(
+                instance,
+                physical_device,
+                mem_requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?,
+            ..Default::default()
+        };
+        let memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+        handle_registry::register(memory);
+        unsafe { device.bind_image_memory(image, memory, 0)? };
+
+        // Every cubemap is bound, including the ones of the lights not cast in a frame
+        let barrier = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::SHADER_READ,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count,
+            },
+            ..Default::default()
+        };
+        unsafe {
+            let command_buffer = Application::begin_singe_time_command(device, command_pool)?;
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+            Application::end_single_time_command(
+                device,
+                queue,
+                command_pool,
+                sync_pool,
+                command_buffer,
+            )?;
+        }
+
+        let mut cube_views = Vec::with_capacity(MAX_SHADOWED_POINT_LIGHTS);
+        let mut face_views = Vec::with_capacity(layer_count as usize);
+        let mut framebuffers = Vec::with_capacity(layer_count as usize);
+        for light in 0..MAX_SHADOWED_POINT_LIGHTS as u32 {
+            cube_views.push(Self::create_view(
+                device,
+                image,
+                vk::ImageViewType::CUBE,
+                6 * light,
+                6,
+            )?);
+
+            for face in 0..6 {
+                let view = Self::create_view(
+                    device,
+                    image,
+                    vk::ImageViewType::TYPE_2D,
+                    6 * light + face,
+                    1,
+                )?;
+                let attachments = [view];
+                let framebuffer_info = vk::FramebufferCreateInfo {
+                    render_pass,
+                    attachment_count: attachments.len() as u32,
+                    p_attachments: attachments.as_ptr(),
+                    width: POINT_SHADOW_RESOLUTION,
+                    height: POINT_SHADOW_RESOLUTION,
+                    layers: 1,
+                    ..Default::default()
+                };
+                let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None)? };
+                handle_registry::register(framebuffer);
+
+                face_views.push(view);
+                framebuffers.push(framebuffer);
+            }
+        }
+
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+        handle_registry::register(sampler);
+
+        Ok(Self {
+            render_pass,
+            pipeline,
+            pipeline_layout,
+            image: ImageHolder::new(image, memory),
+            cube_views,
+            face_views,
+            framebuffers,
+            sampler,
+        })
+    }
+
+    // Clears and writes the depth of a face, left to be sampled
+    fn create_render_pass(device: &Device) -> AppResult<vk::RenderPass> {
+        let depth_attachment = vk::AttachmentDescription {
+            format: SHADOW_FORMAT,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ..Default::default()
+        };
+        let depth_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        let subpass = vk::SubpassDescription {
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            p_depth_stencil_attachment: &depth_attachment_ref as *const _,
+            ..Default::default()
+        };
+
+        let depth_stages = vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let dependencies = [
+            // The previous frame may still be sampling the cubemap
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                dst_stage_mask: depth_stages,
+                src_access_mask: vk::AccessFlags::SHADER_READ,
+                dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ..Default::default()
+            },
+            vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: depth_stages,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                ..Default::default()
+            },
+        ];
+
+        let render_pass_info = vk::RenderPassCreateInfo {
+            attachment_count: 1,
+            p_attachments: &depth_attachment as *const _,
+            subpass_count: 1,
+            p_subpasses: &subpass as *const _,
+            dependency_count: dependencies.len() as u32,
+            p_dependencies: dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_render_pass(&render_pass_info, None)?,
+            ))
+        }
+    }
+
+    fn create_view(
+        device: &Device,
+        image: vk::Image,
+        view_type: vk::ImageViewType,
+        base_array_layer: u32,
+        layer_count: u32,
+    ) -> AppResult<vk::ImageView> {
+        let create_info = vk::ImageViewCreateInfo {
+            image,
+            view_type,
+            format: SHADOW_FORMAT,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer,
+                layer_count,
+            },
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_image_view(&create_info, None)?,
+            ))
+        }
+    }
+
+    /// Cubemap of every light slot, in order, to bind as an array of samplers
+    pub(crate) fn image_infos(&self) -> Vec<vk::DescriptorImageInfo> {
+        self.cube_views
+            .iter()
+            .map(|&image_view| vk::DescriptorImageInfo {
+                sampler: self.sampler,
+                image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            })
+            .collect()
+    }
+
+    /// Image of the cubemaps, one per light slot, sampled in `SHADER_READ_ONLY_OPTIMAL`
+    pub(crate) fn image(&self) -> vk::Image {
+        self.image.image
+    }
+
+    /// Records the rendering of the cubemaps of `casters`, the first `MAX_SHADOWED_POINT_LIGHTS`
+    /// ones taking the slots in order. `draw` records the draws of the scene with the pipeline
+    /// bound, binding their scene set with the given layout.
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        casters: &[ShadowCaster],
+        mut draw: impl FnMut(vk::PipelineLayout),
+    ) {
+        let extent = vk::Extent2D {
+            width: POINT_SHADOW_RESOLUTION,
+            height: POINT_SHADOW_RESOLUTION,
+        };
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+        let clear_values = [vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        }];
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+
+        let casters = casters.iter().take(MAX_SHADOWED_POINT_LIGHTS);
+        for (slot, caster) in casters.enumerate() {
+            let proj = cgmath::perspective(cgmath::Deg(90.0), 1.0, SHADOW_NEAR, caster.range);
+            let position = caster.position;
+
+            for (face, (direction, up)) in FACE_CAMERAS.iter().enumerate() {
+                let view = Mat4::look_to_rh(position, Vec3::from(*direction), Vec3::from(*up));
+                let constants = FaceConstants {
+                    view_proj: (proj * view).into(),
+                    light: [position.x, position.y, position.z, caster.range],
+                };
+                let render_pass_info = vk::RenderPassBeginInfo {
+                    render_pass: self.render_pass,
+                    framebuffer: self.framebuffers[slot * 6 + face],
+                    render_area,
+                    clear_value_count: clear_values.len() as u32,
+                    p_clear_values: clear_values.as_ptr(),
+                    ..Default::default()
+                };
+
+                unsafe {
+                    device.cmd_begin_render_pass(
+                        command_buffer,
+                        &render_pass_info,
+                        vk::SubpassContents::INLINE,
+                    );
+                    device.cmd_set_viewport(command_buffer, 0, &viewports);
+                    device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.pipeline,
+                    );
+                    device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        std::slice::from_raw_parts(
+                            &constants as *const FaceConstants as *const u8,
+                            std::mem::size_of::<FaceConstants>(),
+                        ),
+                    );
+                }
+                draw(self.pipeline_layout);
+                unsafe { device.cmd_end_render_pass(command_buffer) };
+            }
+        }
+    }
+
+    /// Destroys the cubemaps and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for (&framebuffer, &view) in self.framebuffers.iter().zip(&self.face_views) {
+                handle_registry::unregister(framebuffer);
+                device.destroy_framebuffer(framebuffer, None);
+                handle_registry::unregister(view);
+                device.destroy_image_view(view, None);
+            }
+            for &view in self.cube_views.iter() {
+                handle_registry::unregister(view);
+                device.destroy_image_view(view, None);
+            }
+            handle_registry::unregister(self.image.image);
+            device.destroy_image(self.image.image, None);
+            handle_registry::unregister(self.image.memory);
+            device.free_memory(self.image.memory, None);
+
+            handle_registry::unregister(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.render_pass);
+            device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use ash::vk;
+
+/// Features that a `VK_KHR_portability_subset` implementation (e.g. MoltenVK) may not support.
+///
+/// Every field is `true` on a fully conformant implementation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortabilitySubset {
+    pub constant_alpha_color_blend_factors: bool,
+    pub events: bool,
+    pub image_view_format_reinterpretation: bool,
+    pub image_view_format_swizzle: bool,
+    pub image_view_2d_on_3d_image: bool,
+    pub multisample_array_image: bool,
+    pub mutable_comparison_samplers: bool,
+    pub point_polygons: bool,
+    pub sampler_mip_lod_bias: bool,
+    pub separate_stencil_mask_ref: bool,
+    pub shader_sample_rate_interpolation_functions: bool,
+    pub tessellation_isolines: bool,
+    pub tessellation_point_mode: bool,
+    pub triangle_fans: bool,
+    pub vertex_attribute_access_beyond_stride: bool,
+}
+
+impl PortabilitySubset {
+    /// Builds the feature struct to chain into the `VkDeviceCreateInfo`, enabling every
+    /// feature the implementation supports
+    pub fn to_vk(self) -> vk::PhysicalDevicePortabilitySubsetFeaturesKHR<'static> {
+        vk::PhysicalDevicePortabilitySubsetFeaturesKHR {
+            constant_alpha_color_blend_factors: self.constant_alpha_color_blend_factors.into(),
+            events: self.events.into(),
+            image_view_format_reinterpretation: self.image_view_format_reinterpretation.into(),
+            image_view_format_swizzle: self.image_view_format_swizzle.into(),
+            image_view2_d_on3_d_image: self.image_view_2d_on_3d_image.into(),
+            multisample_array_image: self.multisample_array_image.into(),
+            mutable_comparison_samplers: self.mutable_comparison_samplers.into(),
+            point_polygons: self.point_polygons.into(),
+            sampler_mip_lod_bias: self.sampler_mip_lod_bias.into(),
+            separate_stencil_mask_ref: self.separate_stencil_mask_ref.into(),
+            shader_sample_rate_interpolation_functions: self
+                .shader_sample_rate_interpolation_functions
+                .into(),
+            tessellation_isolines: self.tessellation_isolines.into(),
+            tessellation_point_mode: self.tessellation_point_mode.into(),
+            triangle_fans: self.triangle_fans.into(),
+            vertex_attribute_access_beyond_stride: self
+                .vertex_attribute_access_beyond_stride
+                .into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<vk::PhysicalDevicePortabilitySubsetFeaturesKHR<'_>> for PortabilitySubset {
+    fn from(value: vk::PhysicalDevicePortabilitySubsetFeaturesKHR<'_>) -> Self {
+        Self {
+            constant_alpha_color_blend_factors: value.constant_alpha_color_blend_factors
+                == vk::TRUE,
+            events: value.events == vk::TRUE,
+            image_view_format_reinterpretation: value.image_view_format_reinterpretation
+                == vk::TRUE,
+            image_view_format_swizzle: value.image_view_format_swizzle == vk::TRUE,
+            image_view_2d_on_3d_image: value.image_view2_d_on3_d_image == vk::TRUE,
+            multisample_array_image: value.multisample_array_image == vk::TRUE,
+            mutable_comparison_samplers: value.mutable_comparison_samplers == vk::TRUE,
+            point_polygons: value.point_polygons == vk::TRUE,
+            sampler_mip_lod_bias: value.sampler_mip_lod_bias == vk::TRUE,
+            separate_stencil_mask_ref: value.separate_stencil_mask_ref == vk::TRUE,
+            shader_sample_rate_interpolation_functions: value
+                .shader_sample_rate_interpolation_functions
+                == vk::TRUE,
+            tessellation_isolines: value.tessellation_isolines == vk::TRUE,
+            tessellation_point_mode: value.tessellation_point_mode == vk::TRUE,
+            triangle_fans: value.triangle_fans == vk::TRUE,
+            vertex_attribute_access_beyond_stride: value.vertex_attribute_access_beyond_stride
+                == vk::TRUE,
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::ffi::CString;
+
+use ash::{vk, Device};
+
+use crate::{handle_registry, load_shader, AppResult, Application};
+
+/// Default format of the targets written by the post effects
+pub const POST_COLOR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// An image a post effect can read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostInput {
+    /// Output of the previous enabled effect of the chain, or the scene if there is none
+    Previous,
+    /// The scene as rendered before any post effect
+    Scene,
+    /// View space normal (xyz) and linear depth (w) of the closest surfaces, in the
+    /// `GEOMETRY_FORMAT`. The depth is 0 where nothing is drawn. The chain only renders the
+    /// geometry prepass while an effect reads it.
+    Geometry,
+    /// Motion of the closest surfaces since the previous frame, in texture coordinates and the
+    /// `VELOCITY_FORMAT`, the previous position of a pixel being its coordinates minus its
+    /// velocity. Rendered by a prepass while an effect reads it.
+    Velocity,
+    /// Output of the effect itself in the previous frame, the chain alternating between two
+    /// targets for the effects reading it. Its content is undefined while
+    /// `PostPass::history_valid` is false.
+    History,
+}
+
+/// Objects an effect builds its pipelines against
+pub struct PostSetup<'a> {
+    pub device: &'a Device,
+    /// Render pass writing the effect output, already begun by the chain when recording
+    pub render_pass: vk::RenderPass,
+    /// Layout of the descriptor set 0, with one combined image sampler per declared input,
+    /// in declaration order
+    pub input_set_layout: vk::DescriptorSetLayout,
+    pub extent: vk::Extent2D,
+}
+
+/// State available to an effect while it records its pass
+pub struct PostPass<'a> {
+    pub device: &'a Device,
+    pub command_buffer: vk::CommandBuffer,
+    /// Descriptor set matching `PostSetup::input_set_layout`, to bind at set 0
+    pub input_set: vk::DescriptorSet,
+    pub extent: vk::Extent2D,
+    pub frame_index: usize,
+    /// Whether `PostInput::History` holds the previous output, which isn't the case in the first
+    /// frame following the creation or the resize of the chain targets
+    pub history_valid: bool,
+}
+
+/// A full-screen pass of the post-processing chain.
+///
+/// The chain owns the output target of every effect and begins its render pass before calling
+/// `record`, with the viewport and scissor already covering the whole target. The targets are
+/// shared between the effects of a same output format, so the output of an effect only outlives
+/// the pass of the following one when it reads `PostInput::History`.
+pub trait PostEffect {
+    /// Unique name of the effect in the chain
+    fn name(&self) -> &str;
+
+    /// Images sampled by the effect, bound in this order in the input descriptor set
+    fn inputs(&self) -> &[PostInput] {
+        &[PostInput::Previous]
+    }
+
+    /// Format of the image written by the effect
+    fn output_format(&self) -> vk::Format {
+        POST_COLOR_FORMAT
+    }
+
+    /// Creates the effect resources, called when the effect is inserted in the chain
+    fn create(&mut self, setup: &PostSetup) -> AppResult<()>;
+
+    /// Called when the chain targets are resized
+    fn resize(&mut self, _setup: &PostSetup) -> AppResult<()> {
+        Ok(())
+    }
+
+    /// Records the commands of the effect inside the render pass begun by the chain
+    fn record(&mut self, pass: &PostPass);
+
+    /// Destroys the effect resources, called when the effect is removed from the chain
+    fn destroy(&mut self, device: &Device);
+}
+
+/// Creates a pipeline drawing a full-screen triangle (3 vertices, no vertex buffer) shaded by
+/// `fragment_code`, suitable for most post effects. Effects can get their fragment code from
+/// `load_shader` so it can be overridden at runtime.
+pub fn create_fullscreen_pipeline(
+    device: &Device,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    fragment_code: &[u32],
+) -> AppResult<vk::Pipeline> {
+    create_multisampled_fullscreen_pipeline(
+        device,
+        render_pass,
+        vk::SampleCountFlags::TYPE_1,
+        pipeline_layout,
+        fragment_code,
+    )
+}
+
+/// Creates a full-screen pipeline like `create_fullscreen_pipeline` in a render pass of `samples`
+/// samples per pixel, such as the scene render pass
+pub(crate) fn create_multisampled_fullscreen_pipeline(
+    device: &Device,
+    render_pass: vk::RenderPass,
+    samples: vk::SampleCountFlags,
+    pipeline_layout: vk::PipelineLayout,
+    fragment_code: &[u32],
+) -> AppResult<vk::Pipeline> {
+    let vert_shader_code =
+        load_shader("fullscreen.vert", include_bytes!("../spirv/fullscreen.spv"))?;
+
+    let vert_module = Application::create_shader_module(device, &vert_shader_code)?;
+    let frag_module = Application::create_shader_module(device, fragment_code)?;
+
+    let entry_point = CString::new("main").unwrap();
+    let shader_stages_infos = [
+        vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::VERTEX,
+            module: vert_module,
+            p_name: entry_point.as_ptr(),
+            ..Default::default()
+        },
+        vk::PipelineShaderStageCreateInfo {
+            stage: vk::ShaderStageFlags::FRAGMENT,
+            module: frag_module,
+            p_name: entry_point.as_ptr(),
+            ..Default::default()
+        },
+    ];
+
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo {
+        dynamic_state_count: dynamic_states.len() as u32,
+        p_dynamic_states: &dynamic_states as *const _,
+        ..Default::default()
+    };
+
+    let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::default();
+
+    let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo {
+        topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        primitive_restart_enable: false.into(),
+        ..Default::default()
+    };
+
+    let viewport_state = vk::PipelineViewportStateCreateInfo {
+        viewport_count: 1,
+        scissor_count: 1,
+        ..Default::default()
+    };
+
+    let rasterizer = vk::PipelineRasterizationStateCreateInfo {
+        polygon_mode: vk::PolygonMode::FILL,
+        cull_mode: vk::CullModeFlags::NONE,
+        front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        line_width: 1.0,
+        ..Default::default()
+    };
+
+    let multisampling = vk::PipelineMultisampleStateCreateInfo {
+        rasterization_samples: samples,
+        min_sample_shading: 1.0,
+        ..Default::default()
+    };
+
+    let color_blend_attachments = [vk::PipelineColorBlendAttachmentState {
+        blend_enable: false.into(),
+        color_write_mask: vk::ColorComponentFlags::RGBA,
+        ..Default::default()
+    }];
+    let color_blending = vk::PipelineColorBlendStateCreateInfo {
+        logic_op: vk::LogicOp::COPY,
+        attachment_count: color_blend_attachments.len() as u32,
+        p_attachments: color_blend_attachments.as_ptr(),
+        ..Default::default()
+    };
+
+    let pipeline_info = vk::GraphicsPipelineCreateInfo {
+        stage_count: shader_stages_infos.len() as u32,
+        p_stages: shader_stages_infos.as_ptr(),
+        p_vertex_input_state: &vertex_input_info as *const _,
+        p_input_assembly_state: &input_assembly_info as *const _,
+        p_viewport_state: &viewport_state as *const _,
+        p_rasterization_state: &rasterizer as *const _,
+        p_multisample_state: &multisampling as *const _,
+        p_color_blend_state: &color_blending as *const _,
+        p_dynamic_state: &dynamic_state_create_info as *const _,
+        layout: pipeline_layout,
+        render_pass,
+        subpass: 0,
+        base_pipeline_index: -1,
+        ..Default::default()
+    };
+
+    let pipeline = unsafe {
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+            .or_else(|r| AppResult::Err(r.1.into()))?[0]
+    };
+
+    unsafe {
+        handle_registry::unregister(vert_module);
+        device.destroy_shader_module(vert_module, None);
+        handle_registry::unregister(frag_module);
+        device.destroy_shader_module(frag_module, None);
+    }
+
+    Ok(pipeline)
+}
@@ -0,0 +1,62 @@
+use ash::{vk, Device};
+
+use super::{create_fullscreen_pipeline, PostEffect, PostPass, PostSetup};
+use crate::{handle_registry, load_shader, AppResult, Application};
+
+pub(crate) const FXAA_NAME: &str = "fxaa";
+
+/// Fast approximate anti-aliasing, blending the pixels across the edges found from the luma of
+/// the previous effect. Much cheaper than multisampling, at the cost of softening the details.
+#[derive(Default)]
+pub(crate) struct Fxaa {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl PostEffect for Fxaa {
+    fn name(&self) -> &str {
+        FXAA_NAME
+    }
+
+    fn create(&mut self, setup: &PostSetup) -> AppResult<()> {
+        self.pipeline_layout =
+            Application::create_pipeline_layout(setup.device, &[setup.input_set_layout], &[])?;
+        self.pipeline = create_fullscreen_pipeline(
+            setup.device,
+            setup.render_pass,
+            self.pipeline_layout,
+            &load_shader("fxaa.frag", include_bytes!("../spirv/fxaa.spv"))?,
+        )?;
+        handle_registry::register(self.pipeline);
+
+        Ok(())
+    }
+
+    fn record(&mut self, pass: &PostPass) {
+        unsafe {
+            pass.device.cmd_bind_pipeline(
+                pass.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            pass.device.cmd_bind_descriptor_sets(
+                pass.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[pass.input_set],
+                &[],
+            );
+            pass.device.cmd_draw(pass.command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        unsafe {
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
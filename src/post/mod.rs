@@ -0,0 +1,868 @@
+mod effect;
+mod fxaa;
+mod prepass;
+mod ssao;
+mod taa;
+mod tonemap;
+
+pub(crate) use effect::create_multisampled_fullscreen_pipeline;
+pub use effect::{
+    create_fullscreen_pipeline, PostEffect, PostInput, PostPass, PostSetup, POST_COLOR_FORMAT,
+};
+pub(crate) use fxaa::{Fxaa, FXAA_NAME};
+pub use prepass::{GEOMETRY_FORMAT, VELOCITY_FORMAT};
+pub(crate) use ssao::{Ssao, SsaoBlur, SSAO_BLUR_NAME, SSAO_NAME};
+pub(crate) use taa::{halton, Taa, TAA_NAME};
+pub use tonemap::{TonemapOperator, Tonemapping};
+
+use prepass::Prepass;
+use tonemap::TonemapConstants;
+
+use ash::{vk, Device, Instance};
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    handle_registry, load_shader, AppResult, Application, ImageHolder,
+};
+
+/// Format of the offscreen target the scene is rendered into
+pub(crate) const SCENE_COLOR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// An offscreen color image that can be rendered to and sampled
+struct ColorTarget {
+    image: ImageHolder,
+    view: vk::ImageView,
+    // Rendered to and resolved into `image` at the end of the render pass, with more than one
+    // sample per pixel
+    multisampled: Option<(ImageHolder, vk::ImageView)>,
+    framebuffer: vk::Framebuffer,
+}
+
+impl ColorTarget {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        extent: vk::Extent2D,
+    ) -> AppResult<Self> {
+        let image = Application::create_image(
+            instance,
+            device,
+            physical_device,
+            extent.width,
+            extent.height,
+            1,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            // Read back by the pixel inspection
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let view = Application::create_image_view(device, image.image, format, 1)?;
+        let multisampled = if samples == vk::SampleCountFlags::TYPE_1 {
+            None
+        } else {
+            let image = Application::create_multisampled_image(
+                instance,
+                device,
+                physical_device,
+                extent,
+                format,
+                samples,
+            )?;
+            let view = Application::create_image_view(device, image.image, format, 1)?;
+            Some((image, view))
+        };
+
+        // The multisampled attachment comes first, see `create_multisampled_render_pass`
+        let attachments: Vec<vk::ImageView> = multisampled
+            .iter()
+            .map(|&(_, view)| view)
+            .chain([view])
+            .collect();
+        let frame_buffer_info = vk::FramebufferCreateInfo {
+            render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+        let framebuffer = unsafe { device.create_framebuffer(&frame_buffer_info, None)? };
+        handle_registry::register(framebuffer);
+
+        Ok(Self {
+            image,
+            view,
+            multisampled,
+            framebuffer,
+        })
+    }
+
+    unsafe fn destroy(&self, device: &Device) {
+        handle_registry::unregister(self.framebuffer);
+        device.destroy_framebuffer(self.framebuffer, None);
+        if let Some((image, view)) = &self.multisampled {
+            handle_registry::unregister(*view);
+            device.destroy_image_view(*view, None);
+            handle_registry::unregister(image.image);
+            device.destroy_image(image.image, None);
+            handle_registry::unregister(image.memory);
+            device.free_memory(image.memory, None);
+        }
+        handle_registry::unregister(self.view);
+        device.destroy_image_view(self.view, None);
+        handle_registry::unregister(self.image.image);
+        device.destroy_image(self.image.image, None);
+        handle_registry::unregister(self.image.memory);
+        device.free_memory(self.image.memory, None);
+    }
+}
+
+struct PostEntry {
+    order: i32,
+    effect: Box<dyn PostEffect>,
+    render_pass: vk::RenderPass,
+    input_set_layout: vk::DescriptorSetLayout,
+    // One set per parity of the frame, see `PostChain::parity`
+    input_sets: [vk::DescriptorSet; 2],
+    // Indices in `PostChain::targets` of the target written in the frames of each parity, which
+    // differ only when the effect reads its history
+    targets: [usize; 2],
+}
+
+impl PostEntry {
+    fn reads(&self, input: PostInput) -> bool {
+        self.effect.inputs().contains(&input)
+    }
+
+    /// Target written in the frames of `parity`
+    fn target<'a>(&self, targets: &'a [ColorTarget], parity: usize) -> &'a ColorTarget {
+        &targets[self.targets[parity]]
+    }
+
+    /// Target written in the previous frame, when the effect reads its history
+    fn history<'a>(&self, targets: &'a [ColorTarget], parity: usize) -> &'a ColorTarget {
+        &targets[self.targets[parity ^ 1]]
+    }
+}
+
+/// Runs the post effects over the rendered scene, then composites the result on the swapchain
+pub(crate) struct PostChain {
+    entries: Vec<PostEntry>,
+    extent: vk::Extent2D,
+    scene_target: Option<ColorTarget>,
+    // Samples per pixel of the scene render pass
+    scene_samples: vk::SampleCountFlags,
+    // Written by the effects. The ones reading their history own a pair of targets, the others
+    // ping-pong between two targets per output format, each effect reading the other one.
+    targets: Vec<ColorTarget>,
+    geometry: Prepass,
+    velocity: Prepass,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+
+    composite_render_pass: vk::RenderPass,
+    composite_set_layout: vk::DescriptorSetLayout,
+    composite_pipeline_layout: vk::PipelineLayout,
+    composite_pipeline: vk::Pipeline,
+    composite_sets: [vk::DescriptorSet; 2],
+    tonemapping: Tonemapping,
+    // Alternates every frame, selecting the targets written by the effects reading their history
+    parity: usize,
+    // Whether the history targets were written since they were created
+    history_valid: bool,
+}
+
+impl PostChain {
+    /// Creates an empty chain. `scene_render_pass` is the render pass the scene is drawn with,
+    /// with `scene_samples` samples per pixel, `scene_set_layout` the layout of the scene set of
+    /// the draw items, `swapchain_format` the format the chain composites to.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        scene_render_pass: vk::RenderPass,
+        scene_samples: vk::SampleCountFlags,
+        scene_set_layout: vk::DescriptorSetLayout,
+        swapchain_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> AppResult<Self> {
+        let composite_render_pass = Application::create_render_pass(
+            device,
+            swapchain_format,
+            vk::AttachmentLoadOp::DONT_CARE,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )?;
+        let composite_set_layout = Self::create_input_set_layout(device, 1)?;
+        let composite_pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[composite_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<TonemapConstants>() as u32,
+            }],
+        )?;
+        let composite_pipeline = create_fullscreen_pipeline(
+            device,
+            composite_render_pass,
+            composite_pipeline_layout,
+            &load_shader("composite.frag", include_bytes!("../spirv/composite.spv"))?,
+        )?;
+        handle_registry::register(composite_pipeline);
+
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+        handle_registry::register(sampler);
+
+        let mut chain = Self {
+            entries: Vec::new(),
+            extent,
+            scene_target: None,
+            scene_samples,
+            targets: Vec::new(),
+            geometry: Prepass::new(
+                device,
+                scene_set_layout,
+                GEOMETRY_FORMAT,
+                &load_shader("geometry.vert", include_bytes!("../spirv/geometry.spv"))?,
+                // Flips the normal of the back faces
+                &load_shader(
+                    "geometry_normal_depth.frag",
+                    include_bytes!("../spirv/geometry_normal_depth.spv"),
+                )?,
+            )?,
+            velocity: Prepass::new(
+                device,
+                scene_set_layout,
+                VELOCITY_FORMAT,
+                &load_shader("velocity.vert", include_bytes!("../spirv/velocity.spv"))?,
+                &load_shader(
+                    "velocity_uv.frag",
+                    include_bytes!("../spirv/velocity_uv.spv"),
+                )?,
+            )?,
+            sampler,
+            descriptor_pool: vk::DescriptorPool::null(),
+
+            composite_render_pass,
+            composite_set_layout,
+            composite_pipeline_layout,
+            composite_pipeline,
+            composite_sets: [vk::DescriptorSet::null(); 2],
+            tonemapping: Tonemapping::default(),
+            parity: 0,
+            history_valid: false,
+        };
+        chain.create_targets(instance, device, physical_device, scene_render_pass)?;
+
+        Ok(chain)
+    }
+
+    /// Render pass compatible with the swapchain framebuffers
+    pub(crate) fn composite_render_pass(&self) -> vk::RenderPass {
+        self.composite_render_pass
+    }
+
+    /// Framebuffer the scene must be rendered into
+    pub(crate) fn scene_framebuffer(&self) -> vk::Framebuffer {
+        self.scene_target.as_ref().unwrap().framebuffer
+    }
+
+    /// Images of the prepasses read by the effects of the chain
+    pub(crate) fn prepass_images(&self) -> Vec<vk::Image> {
+        [&self.geometry, &self.velocity]
+            .into_iter()
+            .filter_map(|prepass| prepass.image())
+            .collect()
+    }
+
+    /// Image the next recorded frame of the effects ends in, `None` when the chain has no effect
+    /// and composites the scene target
+    pub(crate) fn next_output_image(&self) -> Option<vk::Image> {
+        self.entries
+            .last()
+            .map(|entry| entry.target(&self.targets, self.parity ^ 1).image.image)
+    }
+
+    /// Image and view the scene is rendered into
+    pub(crate) fn scene_target(&self) -> (vk::Image, vk::ImageView) {
+        let target = self.scene_target.as_ref().unwrap();
+        (target.image.image, target.view)
+    }
+
+    pub(crate) fn tonemapping(&self) -> Tonemapping {
+        self.tonemapping
+    }
+
+    /// Changes the tonemapping of the composite pass, from the next recorded frame
+    pub(crate) fn set_tonemapping(&mut self, tonemapping: Tonemapping) {
+        self.tonemapping = tonemapping;
+    }
+
+    /// Names of the effects, in execution order
+    pub(crate) fn effect_names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.effect.name())
+    }
+
+    /// Order the effect named `name` was inserted with, if it is part of the chain
+    #[cfg(feature = "scripting")]
+    pub(crate) fn effect_order(&self, name: &str) -> Option<i32> {
+        self.entries
+            .iter()
+            .find(|entry| entry.effect.name() == name)
+            .map(|entry| entry.order)
+    }
+
+    /// Inserts `effect` after every effect with an `order` lower or equal to `order`.
+    ///
+    /// The device must be idle.
+    pub(crate) fn insert(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        scene_render_pass: vk::RenderPass,
+        order: i32,
+        mut effect: Box<dyn PostEffect>,
+    ) -> AppResult<()> {
+        if self.effect_names().any(|name| name == effect.name()) {
+            return Err(AppError::new(AppErrorType::DuplicatePostEffect));
+        }
+
+        let render_pass = Application::create_render_pass(
+            device,
+            effect.output_format(),
+            vk::AttachmentLoadOp::DONT_CARE,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+        let input_set_layout = Self::create_input_set_layout(device, effect.inputs().len())?;
+
+        effect.create(&PostSetup {
+            device,
+            render_pass,
+            input_set_layout,
+            extent: self.extent,
+        })?;
+
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.order > order)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(
+            index,
+            PostEntry {
+                order,
+                effect,
+                render_pass,
+                input_set_layout,
+                input_sets: [vk::DescriptorSet::null(); 2],
+                targets: [0; 2],
+            },
+        );
+
+        self.create_targets(instance, device, physical_device, scene_render_pass)
+    }
+
+    /// Removes and destroys the effect named `name`, returning it if it was part of the chain.
+    ///
+    /// The device must be idle.
+    pub(crate) fn remove(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        scene_render_pass: vk::RenderPass,
+        name: &str,
+    ) -> AppResult<Option<Box<dyn PostEffect>>> {
+        let Some(index) = self.effect_names().position(|n| n == name) else {
+            return Ok(None);
+        };
+
+        let mut entry = self.entries.remove(index);
+        unsafe {
+            handle_registry::unregister(entry.render_pass);
+            device.destroy_render_pass(entry.render_pass, None);
+            handle_registry::unregister(entry.input_set_layout);
+            device.destroy_descriptor_set_layout(entry.input_set_layout, None);
+        }
+        entry.effect.destroy(device);
+
+        self.create_targets(instance, device, physical_device, scene_render_pass)?;
+
+        Ok(Some(entry.effect))
+    }
+
+    /// Recreates the targets with the new extent. The device must be idle.
+    pub(crate) fn resize(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        scene_render_pass: vk::RenderPass,
+        extent: vk::Extent2D,
+    ) -> AppResult<()> {
+        self.extent = extent;
+        self.create_targets(instance, device, physical_device, scene_render_pass)?;
+
+        for entry in self.entries.iter_mut() {
+            entry.effect.resize(&PostSetup {
+                device,
+                render_pass: entry.render_pass,
+                input_set_layout: entry.input_set_layout,
+                extent,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Records the geometry and velocity prepasses read by the effects of the chain. `draw`
+    /// records the draws of the scene with the prepass pipeline bound, binding their scene set
+    /// with the given layout.
+    pub(crate) fn record_prepasses(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        mut draw: impl FnMut(vk::PipelineLayout),
+    ) {
+        let (viewports, scissors) = self.full_viewport();
+        for prepass in [&self.geometry, &self.velocity] {
+            prepass.record(device, command_buffer, &viewports, &scissors, &mut draw);
+        }
+    }
+
+    /// Whether an effect of the chain reads `PostInput::Velocity`, which needs the previous
+    /// transforms of the draw items
+    pub(crate) fn uses_velocity(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.reads(PostInput::Velocity))
+    }
+
+    /// Records every effect, each in its own render pass
+    pub(crate) fn record_effects(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+    ) {
+        let extent = self.extent;
+        let (viewports, scissors) = self.full_viewport();
+        self.parity ^= 1;
+        let parity = self.parity;
+
+        // The history read in the first frame was never written, it only needs a valid layout
+        if !self.history_valid {
+            let barriers: Vec<vk::ImageMemoryBarrier> = self
+                .entries
+                .iter()
+                .filter(|entry| entry.reads(PostInput::History))
+                .map(|entry| vk::ImageMemoryBarrier {
+                    src_access_mask: vk::AccessFlags::empty(),
+                    dst_access_mask: vk::AccessFlags::SHADER_READ,
+                    old_layout: vk::ImageLayout::UNDEFINED,
+                    new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    image: entry.history(&self.targets, parity).image.image,
+                    subresource_range: vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    },
+                    ..Default::default()
+                })
+                .collect();
+            if !barriers.is_empty() {
+                unsafe {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        vk::PipelineStageFlags::TOP_OF_PIPE,
+                        vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &barriers,
+                    );
+                }
+            }
+        }
+
+        for entry in self.entries.iter_mut() {
+            let render_pass_info = vk::RenderPassBeginInfo {
+                render_pass: entry.render_pass,
+                framebuffer: entry.target(&self.targets, parity).framebuffer,
+                render_area: scissors[0],
+                ..Default::default()
+            };
+
+            unsafe {
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_info,
+                    vk::SubpassContents::INLINE,
+                );
+                device.cmd_set_viewport(command_buffer, 0, &viewports);
+                device.cmd_set_scissor(command_buffer, 0, &scissors);
+            }
+
+            entry.effect.record(&PostPass {
+                device,
+                command_buffer,
+                input_set: entry.input_sets[parity],
+                extent,
+                frame_index,
+                history_valid: self.history_valid,
+            });
+
+            unsafe { device.cmd_end_render_pass(command_buffer) };
+        }
+        self.history_valid = true;
+    }
+
+    /// Begins the swapchain render pass and composites the chain output into
+    /// `swapchain_framebuffer`, tonemapping it. The render pass is left open for the overlays.
+    pub(crate) fn begin_composite(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        swapchain_framebuffer: vk::Framebuffer,
+    ) {
+        let (viewports, scissors) = self.full_viewport();
+        let render_pass_info = vk::RenderPassBeginInfo {
+            render_pass: self.composite_render_pass,
+            framebuffer: swapchain_framebuffer,
+            render_area: scissors[0],
+            ..Default::default()
+        };
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.composite_pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.composite_pipeline_layout,
+                0,
+                &[self.composite_sets[self.parity]],
+                &[],
+            );
+            let constants = TonemapConstants::from(self.tonemapping);
+            device.cmd_push_constants(
+                command_buffer,
+                self.composite_pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const TonemapConstants as *const u8,
+                    std::mem::size_of::<TonemapConstants>(),
+                ),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    fn full_viewport(&self) -> ([vk::Viewport; 1], [vk::Rect2D; 1]) {
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        }];
+
+        (viewports, scissors)
+    }
+
+    /// Destroys the chain and every effect it contains
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            self.destroy_targets(device);
+            self.geometry.destroy(device);
+            self.velocity.destroy(device);
+
+            for mut entry in self.entries.drain(..) {
+                entry.effect.destroy(device);
+                handle_registry::unregister(entry.render_pass);
+                device.destroy_render_pass(entry.render_pass, None);
+                handle_registry::unregister(entry.input_set_layout);
+                device.destroy_descriptor_set_layout(entry.input_set_layout, None);
+            }
+
+            handle_registry::unregister(self.composite_pipeline);
+            device.destroy_pipeline(self.composite_pipeline, None);
+            handle_registry::unregister(self.composite_pipeline_layout);
+            device.destroy_pipeline_layout(self.composite_pipeline_layout, None);
+            handle_registry::unregister(self.composite_set_layout);
+            device.destroy_descriptor_set_layout(self.composite_set_layout, None);
+            handle_registry::unregister(self.composite_render_pass);
+            device.destroy_render_pass(self.composite_render_pass, None);
+            handle_registry::unregister(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+        }
+    }
+
+    fn create_input_set_layout(
+        device: &Device,
+        input_count: usize,
+    ) -> AppResult<vk::DescriptorSetLayout> {
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = (0..input_count as u32)
+            .map(|binding| vk::DescriptorSetLayoutBinding {
+                binding,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            })
+            .collect();
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_descriptor_set_layout(&layout_info, None)?,
+            ))
+        }
+    }
+
+    unsafe fn destroy_targets(&mut self, device: &Device) {
+        if let Some(target) = self.scene_target.take() {
+            target.destroy(device);
+        }
+        self.geometry.destroy_target(device);
+        self.velocity.destroy_target(device);
+
+        for target in self.targets.drain(..) {
+            target.destroy(device);
+        }
+
+        handle_registry::unregister(self.descriptor_pool);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.descriptor_pool = vk::DescriptorPool::null();
+    }
+
+    /// (Re)creates the target of the scene and of every effect, and the descriptor sets
+    /// binding them to their readers
+    fn create_targets(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        scene_render_pass: vk::RenderPass,
+    ) -> AppResult<()> {
+        unsafe { self.destroy_targets(device) };
+
+        let scene_target = ColorTarget::new(
+            instance,
+            device,
+            physical_device,
+            scene_render_pass,
+            SCENE_COLOR_FORMAT,
+            self.scene_samples,
+            self.extent,
+        )?;
+        let uses_geometry = self
+            .entries
+            .iter()
+            .any(|entry| entry.reads(PostInput::Geometry));
+        self.geometry.create_target(
+            instance,
+            device,
+            physical_device,
+            self.extent,
+            uses_geometry,
+        )?;
+        let uses_velocity = self.uses_velocity();
+        self.velocity.create_target(
+            instance,
+            device,
+            physical_device,
+            self.extent,
+            uses_velocity,
+        )?;
+        // Targets shared by the effects of each output format, and which of the two the next
+        // effect writes. The render passes of a format being compatible, the framebuffers of a
+        // pair are created with the render pass of its first effect.
+        let mut ping_pongs: Vec<(vk::Format, [Option<usize>; 2], usize)> = Vec::new();
+        for entry in self.entries.iter_mut() {
+            let format = entry.effect.output_format();
+            if entry.reads(PostInput::History) {
+                for parity in 0..2 {
+                    entry.targets[parity] = self.targets.len();
+                    self.targets.push(ColorTarget::new(
+                        instance,
+                        device,
+                        physical_device,
+                        entry.render_pass,
+                        format,
+                        vk::SampleCountFlags::TYPE_1,
+                        self.extent,
+                    )?);
+                }
+                continue;
+            }
+
+            let index = match ping_pongs.iter().position(|&(f, _, _)| f == format) {
+                Some(index) => index,
+                None => {
+                    ping_pongs.push((format, [None; 2], 0));
+                    ping_pongs.len() - 1
+                }
+            };
+            let (_, pair, next) = &mut ping_pongs[index];
+            let target = match pair[*next] {
+                Some(target) => target,
+                None => {
+                    self.targets.push(ColorTarget::new(
+                        instance,
+                        device,
+                        physical_device,
+                        entry.render_pass,
+                        format,
+                        vk::SampleCountFlags::TYPE_1,
+                        self.extent,
+                    )?);
+                    pair[*next] = Some(self.targets.len() - 1);
+                    self.targets.len() - 1
+                }
+            };
+            entry.targets = [target; 2];
+            *next ^= 1;
+        }
+        self.parity = 0;
+        self.history_valid = false;
+
+        // One set per effect plus the composite one, for each parity
+        let set_count = 2 * (self.entries.len() as u32 + 1);
+        let sampler_count = 2
+            * (self
+                .entries
+                .iter()
+                .map(|entry| entry.effect.inputs().len() as u32)
+                .sum::<u32>()
+                + 1);
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: sampler_count,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: set_count,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        self.descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(self.descriptor_pool);
+
+        let layouts: Vec<vk::DescriptorSetLayout> = self
+            .entries
+            .iter()
+            .map(|entry| entry.input_set_layout)
+            .chain([self.composite_set_layout])
+            .cycle()
+            .take(set_count as usize)
+            .collect();
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool: self.descriptor_pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+        let sets = unsafe { device.allocate_descriptor_sets(&alloc_info)? };
+
+        // Resolves the views read by every set before building the writes, so the image infos
+        // aren't moved while referenced
+        let mut inputs: Vec<(vk::DescriptorSet, u32, vk::ImageView)> = Vec::new();
+        let mut sets = sets.into_iter();
+        for parity in 0..2 {
+            let mut previous = scene_target.view;
+            for entry in self.entries.iter_mut() {
+                let set = sets.next().unwrap();
+                entry.input_sets[parity] = set;
+                for (binding, input) in entry.effect.inputs().iter().enumerate() {
+                    let view = match input {
+                        PostInput::Previous => previous,
+                        PostInput::Scene => scene_target.view,
+                        PostInput::Geometry => self.geometry.view().unwrap(),
+                        PostInput::Velocity => self.velocity.view().unwrap(),
+                        PostInput::History => entry.history(&self.targets, parity).view,
+                    };
+                    inputs.push((set, binding as u32, view));
+                }
+                previous = entry.target(&self.targets, parity).view;
+            }
+            self.composite_sets[parity] = sets.next().unwrap();
+            inputs.push((self.composite_sets[parity], 0, previous));
+        }
+
+        let image_infos: Vec<vk::DescriptorImageInfo> = inputs
+            .iter()
+            .map(|&(_, _, image_view)| vk::DescriptorImageInfo {
+                sampler: self.sampler,
+                image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            })
+            .collect();
+        let descriptor_writes: Vec<vk::WriteDescriptorSet> = inputs
+            .iter()
+            .zip(image_infos.iter())
+            .map(
+                |(&(dst_set, dst_binding, _), image_info)| vk::WriteDescriptorSet {
+                    dst_set,
+                    dst_binding,
+                    dst_array_element: 0,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    p_image_info: image_info as *const _,
+                    ..Default::default()
+                },
+            )
+            .collect();
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        self.scene_target = Some(scene_target);
+
+        Ok(())
+    }
+}
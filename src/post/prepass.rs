@@ -0,0 +1,351 @@
+use ash::{vk, Device, Instance};
+
+use crate::{handle_registry, AppResult, Application, ImageHolder};
+
+/// Format of the geometry target, view space normal in xyz and linear depth in w
+pub const GEOMETRY_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Format of the velocity target, motion of the closest surfaces since the previous frame in
+/// texture coordinates
+pub const VELOCITY_FORMAT: vk::Format = vk::Format::R16G16_SFLOAT;
+
+// Only tests the draws against each other, every device can render to it
+const PREPASS_DEPTH_FORMAT: vk::Format = vk::Format::D16_UNORM;
+
+/// Attributes of the closest surfaces, with the depth buffer they are tested against
+struct PrepassTarget {
+    image: ImageHolder,
+    view: vk::ImageView,
+    depth_image: ImageHolder,
+    depth_view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+}
+
+impl PrepassTarget {
+    fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> AppResult<Self> {
+        let image = Application::create_image(
+            instance,
+            device,
+            physical_device,
+            extent.width,
+            extent.height,
+            1,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let view = Application::create_image_view(device, image.image, format, 1)?;
+
+        let depth_image = Application::create_image(
+            instance,
+            device,
+            physical_device,
+            extent.width,
+            extent.height,
+            1,
+            PREPASS_DEPTH_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let create_info = vk::ImageViewCreateInfo {
+            image: depth_image.image,
+            view_type: vk::ImageViewType::TYPE_2D,
+            format: PREPASS_DEPTH_FORMAT,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        };
+        let depth_view = unsafe { device.create_image_view(&create_info, None)? };
+        handle_registry::register(depth_view);
+
+        let attachments = [view, depth_view];
+        let frame_buffer_info = vk::FramebufferCreateInfo {
+            render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+        let framebuffer = unsafe { device.create_framebuffer(&frame_buffer_info, None)? };
+        handle_registry::register(framebuffer);
+
+        Ok(Self {
+            image,
+            view,
+            depth_image,
+            depth_view,
+            framebuffer,
+        })
+    }
+
+    unsafe fn destroy(&self, device: &Device) {
+        handle_registry::unregister(self.framebuffer);
+        device.destroy_framebuffer(self.framebuffer, None);
+        for (image, view) in [
+            (&self.image, self.view),
+            (&self.depth_image, self.depth_view),
+        ] {
+            handle_registry::unregister(view);
+            device.destroy_image_view(view, None);
+            handle_registry::unregister(image.image);
+            device.destroy_image(image.image, None);
+            handle_registry::unregister(image.memory);
+            device.free_memory(image.memory, None);
+        }
+    }
+}
+
+/// Prepass drawing an attribute of the draw list for the effects reading it, e.g. the view space
+/// normal and linear depth of `PostInput::Geometry`. The target only exists while such an effect
+/// is in the chain.
+///
+/// The pipeline binds the scene set of the draw items as set 0, for their transforms.
+pub(super) struct Prepass {
+    format: vk::Format,
+    render_pass: vk::RenderPass,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    target: Option<PrepassTarget>,
+}
+
+impl Prepass {
+    /// Creates a prepass writing the output of `frag_shader_code` to a target of `format`. The
+    /// meshes are drawn from both sides.
+    pub(super) fn new(
+        device: &Device,
+        scene_set_layout: vk::DescriptorSetLayout,
+        format: vk::Format,
+        vert_shader_code: &[u32],
+        frag_shader_code: &[u32],
+    ) -> AppResult<Self> {
+        let render_pass = Self::create_render_pass(device, format)?;
+        let pipeline_layout =
+            Application::create_pipeline_layout(device, &[scene_set_layout], &[])?;
+        let pipeline = Application::create_depth_tested_pipeline(
+            device,
+            render_pass,
+            pipeline_layout,
+            vert_shader_code,
+            frag_shader_code,
+            vk::CullModeFlags::NONE,
+        )?;
+
+        Ok(Self {
+            format,
+            render_pass,
+            pipeline,
+            pipeline_layout,
+            target: None,
+        })
+    }
+
+    // Clears and writes the target, left to be sampled by the effects
+    fn create_render_pass(device: &Device, format: vk::Format) -> AppResult<vk::RenderPass> {
+        let attachments = [
+            vk::AttachmentDescription {
+                format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::STORE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ..Default::default()
+            },
+            vk::AttachmentDescription {
+                format: PREPASS_DEPTH_FORMAT,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            },
+        ];
+        let color_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+        let depth_attachment_ref = vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        let subpass = vk::SubpassDescription {
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref as *const _,
+            p_depth_stencil_attachment: &depth_attachment_ref as *const _,
+            ..Default::default()
+        };
+
+        let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let dependencies = [
+            // The effects of the previous frame may still be sampling the target
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                dst_stage_mask: attachment_stages,
+                src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ..Default::default()
+            },
+            vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                ..Default::default()
+            },
+        ];
+
+        let render_pass_info = vk::RenderPassCreateInfo {
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: 1,
+            p_subpasses: &subpass as *const _,
+            dependency_count: dependencies.len() as u32,
+            p_dependencies: dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_render_pass(&render_pass_info, None)?,
+            ))
+        }
+    }
+
+    /// View of the target, if it exists
+    pub(super) fn image(&self) -> Option<vk::Image> {
+        self.target.as_ref().map(|target| target.image.image)
+    }
+
+    pub(super) fn view(&self) -> Option<vk::ImageView> {
+        self.target.as_ref().map(|target| target.view)
+    }
+
+    /// Creates the target with `extent`, or destroys it when `enabled` is false
+    pub(super) fn create_target(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        enabled: bool,
+    ) -> AppResult<()> {
+        unsafe { self.destroy_target(device) };
+        if enabled {
+            self.target = Some(PrepassTarget::new(
+                instance,
+                device,
+                physical_device,
+                self.render_pass,
+                self.format,
+                extent,
+            )?);
+        }
+
+        Ok(())
+    }
+
+    pub(super) unsafe fn destroy_target(&mut self, device: &Device) {
+        if let Some(target) = self.target.take() {
+            target.destroy(device);
+        }
+    }
+
+    /// Records the prepass if the target exists. `draw` records the draws of the scene with the
+    /// pipeline bound, binding their scene set with the given layout.
+    pub(super) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        viewports: &[vk::Viewport],
+        scissors: &[vk::Rect2D],
+        draw: impl FnOnce(vk::PipelineLayout),
+    ) {
+        let Some(target) = &self.target else {
+            return;
+        };
+
+        // Null attributes mark the pixels without geometry
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 0.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_info = vk::RenderPassBeginInfo {
+            render_pass: self.render_pass,
+            framebuffer: target.framebuffer,
+            render_area: scissors[0],
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_set_viewport(command_buffer, 0, viewports);
+            device.cmd_set_scissor(command_buffer, 0, scissors);
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+        }
+
+        draw(self.pipeline_layout);
+
+        unsafe { device.cmd_end_render_pass(command_buffer) };
+    }
+
+    pub(super) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            self.destroy_target(device);
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.render_pass);
+            device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
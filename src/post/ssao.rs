@@ -0,0 +1,511 @@
+use ash::{vk, Device, Instance};
+
+use super::{create_fullscreen_pipeline, PostEffect, PostInput, PostPass, PostSetup};
+use crate::{
+    geometry::Mat4, handle_registry, load_shader, AppResult, Application, BufferHolder,
+    ImageHolder, SyncPool,
+};
+
+pub(crate) const SSAO_NAME: &str = "ssao";
+pub(crate) const SSAO_BLUR_NAME: &str = "ssao_blur";
+
+// Samples of the hemisphere kernel, must match `KERNEL_SIZE` of ssao.frag
+const KERNEL_SIZE: usize = 16;
+// Size of the tiled noise texture, the blur averages blocks of the same size
+const NOISE_SIZE: u32 = 4;
+// Radius of the hemisphere in view space units
+const RADIUS: f32 = 0.5;
+// Depth offset keeping the surfaces from occluding themselves
+const DEPTH_BIAS: f32 = 0.025;
+// Exponent of the ambient term, darkening the occluded areas further
+const STRENGTH: f32 = 1.5;
+// Order of the 4x4 texels of the noise, spreading the rotations over each block
+const BAYER_MATRIX: [[u32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// Push constants of ssao.frag
+#[repr(C)]
+struct SsaoConstants {
+    proj: [[f32; 4]; 4],
+    // x: radius, y: depth bias, z: strength
+    params: [f32; 4],
+}
+
+/// Ambient occlusion of the scene, estimated by testing the depth of the geometry prepass at
+/// points of a hemisphere around the normal of each pixel. The hemisphere is rotated by a tiled
+/// noise texture so few samples are needed, the `SsaoBlur` effect removing the noise pattern.
+///
+/// Writes the ambient term in its red channel, 1 being unoccluded.
+pub(crate) struct Ssao {
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    kernel_buffer: BufferHolder,
+    noise_image: ImageHolder,
+    noise_view: vk::ImageView,
+    noise_sampler: vk::Sampler,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    proj: Mat4,
+}
+
+impl Ssao {
+    /// Creates the kernel and the noise texture, the pipeline is created once the effect is
+    /// inserted in the chain
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+    ) -> AppResult<Self> {
+        let kernel = Self::kernel();
+        let kernel_size = std::mem::size_of_val(&kernel) as u64;
+        let kernel_buffer = Application::create_buffer(
+            instance,
+            device,
+            physical_device,
+            kernel_size,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        unsafe {
+            let memory_map = device.map_memory(
+                kernel_buffer.memory,
+                0,
+                kernel_size,
+                vk::MemoryMapFlags::empty(),
+            )?;
+            std::ptr::copy(kernel.as_ptr(), memory_map as *mut [f32; 4], kernel.len());
+            device.unmap_memory(kernel_buffer.memory);
+        }
+
+        let (noise_image, noise_view) = Self::create_noise(
+            instance,
+            device,
+            queue,
+            physical_device,
+            command_pool,
+            sync_pool,
+        )?;
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            ..Default::default()
+        };
+        let noise_sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+        handle_registry::register(noise_sampler);
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+        ];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: 1,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &set_layout as *const _,
+            ..Default::default()
+        };
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+
+        let buffer_info = vk::DescriptorBufferInfo {
+            buffer: kernel_buffer.buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+        let image_info = vk::DescriptorImageInfo {
+            sampler: noise_sampler,
+            image_view: noise_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let descriptor_writes = [
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_set,
+                dst_binding: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                p_buffer_info: &buffer_info as *const _,
+                ..Default::default()
+            },
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_set,
+                dst_binding: 1,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                p_image_info: &image_info as *const _,
+                ..Default::default()
+            },
+        ];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Ok(Self {
+            set_layout,
+            descriptor_pool,
+            descriptor_set,
+            kernel_buffer,
+            noise_image,
+            noise_view,
+            noise_sampler,
+            pipeline: vk::Pipeline::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            proj: Application::camera_projection(vk::Extent2D {
+                width: 1,
+                height: 1,
+            }),
+        })
+    }
+
+    /// Points of the hemisphere around +Z, spread along a golden angle spiral and gathered
+    /// toward its center, where the occluders matter the most
+    fn kernel() -> [[f32; 4]; KERNEL_SIZE] {
+        let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+        let mut kernel = [[0.0; 4]; KERNEL_SIZE];
+        for (i, sample) in kernel.iter_mut().enumerate() {
+            let t = (i as f32 + 0.5) / KERNEL_SIZE as f32;
+            let z = 1.0 - t;
+            let r = (1.0 - z * z).sqrt();
+            let phi = golden_angle * i as f32;
+            let scale = 0.1 + 0.9 * t * t;
+            *sample = [r * phi.cos() * scale, r * phi.sin() * scale, z * scale, 0.0];
+        }
+
+        kernel
+    }
+
+    // Rotations of the kernel around the normal, as unit vectors of the XY plane
+    fn create_noise(
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+    ) -> AppResult<(ImageHolder, vk::ImageView)> {
+        let format = vk::Format::R8G8B8A8_SNORM;
+        let texels: Vec<[i8; 4]> = BAYER_MATRIX
+            .iter()
+            .flatten()
+            .map(|&rank| {
+                let angle = rank as f32 / 16.0 * std::f32::consts::TAU;
+                let x = (angle.cos() * 127.0).round() as i8;
+                let y = (angle.sin() * 127.0).round() as i8;
+                [x, y, 0, 0]
+            })
+            .collect();
+        let size = std::mem::size_of_val(texels.as_slice()) as u64;
+
+        let staging_buffer = Application::create_buffer(
+            instance,
+            device,
+            physical_device,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let image = Application::create_image(
+            instance,
+            device,
+            physical_device,
+            NOISE_SIZE,
+            NOISE_SIZE,
+            1,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let to_transfer = [vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::empty(),
+            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: image.image,
+            subresource_range,
+            ..Default::default()
+        }];
+        let to_shader = [vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::SHADER_READ,
+            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ..to_transfer[0]
+        }];
+        let region = vk::BufferImageCopy {
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_extent: vk::Extent3D {
+                width: NOISE_SIZE,
+                height: NOISE_SIZE,
+                depth: 1,
+            },
+            ..Default::default()
+        };
+
+        unsafe {
+            let memory_map =
+                device.map_memory(staging_buffer.memory, 0, size, vk::MemoryMapFlags::empty())?;
+            std::ptr::copy(texels.as_ptr(), memory_map as *mut [i8; 4], texels.len());
+            device.unmap_memory(staging_buffer.memory);
+
+            let command_buffer = Application::begin_singe_time_command(device, command_pool)?;
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_transfer,
+            );
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.buffer,
+                image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_shader,
+            );
+            Application::end_single_time_command(
+                device,
+                queue,
+                command_pool,
+                sync_pool,
+                command_buffer,
+            )?;
+
+            handle_registry::unregister(staging_buffer.buffer);
+            device.destroy_buffer(staging_buffer.buffer, None);
+            handle_registry::unregister(staging_buffer.memory);
+            device.free_memory(staging_buffer.memory, None);
+        }
+
+        let view = Application::create_image_view(device, image.image, format, 1)?;
+
+        Ok((image, view))
+    }
+}
+
+impl PostEffect for Ssao {
+    fn name(&self) -> &str {
+        SSAO_NAME
+    }
+
+    fn inputs(&self) -> &[PostInput] {
+        &[PostInput::Geometry]
+    }
+
+    fn output_format(&self) -> vk::Format {
+        vk::Format::R8_UNORM
+    }
+
+    fn create(&mut self, setup: &PostSetup) -> AppResult<()> {
+        self.pipeline_layout = Application::create_pipeline_layout(
+            setup.device,
+            &[setup.input_set_layout, self.set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<SsaoConstants>() as u32,
+            }],
+        )?;
+        self.pipeline = create_fullscreen_pipeline(
+            setup.device,
+            setup.render_pass,
+            self.pipeline_layout,
+            &load_shader("ssao.frag", include_bytes!("../spirv/ssao.spv"))?,
+        )?;
+        handle_registry::register(self.pipeline);
+
+        self.resize(setup)
+    }
+
+    fn resize(&mut self, setup: &PostSetup) -> AppResult<()> {
+        self.proj = Application::camera_projection(setup.extent);
+
+        Ok(())
+    }
+
+    fn record(&mut self, pass: &PostPass) {
+        let constants = SsaoConstants {
+            proj: self.proj.into(),
+            params: [RADIUS, DEPTH_BIAS, STRENGTH, 0.0],
+        };
+
+        unsafe {
+            pass.device.cmd_bind_pipeline(
+                pass.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            pass.device.cmd_bind_descriptor_sets(
+                pass.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[pass.input_set, self.descriptor_set],
+                &[],
+            );
+            pass.device.cmd_push_constants(
+                pass.command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const SsaoConstants as *const u8,
+                    std::mem::size_of::<SsaoConstants>(),
+                ),
+            );
+            pass.device.cmd_draw(pass.command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        unsafe {
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.descriptor_pool);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            handle_registry::unregister(self.set_layout);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+            handle_registry::unregister(self.noise_sampler);
+            device.destroy_sampler(self.noise_sampler, None);
+            handle_registry::unregister(self.noise_view);
+            device.destroy_image_view(self.noise_view, None);
+            handle_registry::unregister(self.noise_image.image);
+            device.destroy_image(self.noise_image.image, None);
+            handle_registry::unregister(self.noise_image.memory);
+            device.free_memory(self.noise_image.memory, None);
+            handle_registry::unregister(self.kernel_buffer.buffer);
+            device.destroy_buffer(self.kernel_buffer.buffer, None);
+            handle_registry::unregister(self.kernel_buffer.memory);
+            device.free_memory(self.kernel_buffer.memory, None);
+        }
+    }
+}
+
+/// Blurs the ambient term of the previous `Ssao` effect over the blocks of its noise, leaving
+/// out the pixels of other surfaces, and darkens the scene with it
+#[derive(Default)]
+pub(crate) struct SsaoBlur {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl PostEffect for SsaoBlur {
+    fn name(&self) -> &str {
+        SSAO_BLUR_NAME
+    }
+
+    fn inputs(&self) -> &[PostInput] {
+        &[PostInput::Previous, PostInput::Scene, PostInput::Geometry]
+    }
+
+    fn create(&mut self, setup: &PostSetup) -> AppResult<()> {
+        self.pipeline_layout =
+            Application::create_pipeline_layout(setup.device, &[setup.input_set_layout], &[])?;
+        self.pipeline = create_fullscreen_pipeline(
+            setup.device,
+            setup.render_pass,
+            self.pipeline_layout,
+            &load_shader("ssao_blur.frag", include_bytes!("../spirv/ssao_blur.spv"))?,
+        )?;
+        handle_registry::register(self.pipeline);
+
+        Ok(())
+    }
+
+    fn record(&mut self, pass: &PostPass) {
+        unsafe {
+            pass.device.cmd_bind_pipeline(
+                pass.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            pass.device.cmd_bind_descriptor_sets(
+                pass.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[pass.input_set],
+                &[],
+            );
+            pass.device.cmd_draw(pass.command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        unsafe {
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use ash::{vk, Device};
+
+use super::{create_fullscreen_pipeline, PostEffect, PostInput, PostPass, PostSetup};
+use crate::{handle_registry, load_shader, AppResult, Application};
+
+pub(crate) const TAA_NAME: &str = "taa";
+
+// Weight of the reprojected history in the output, the higher the smoother but the longer the
+// ghosting of the moving meshes
+const HISTORY_WEIGHT: f32 = 0.9;
+
+/// Temporal anti-aliasing, blending each frame with its reprojected previous output. The
+/// projection being jittered by a sub-pixel offset every frame, the blend converges towards a
+/// supersampled image.
+///
+/// The history is moved along the velocity prepass and clamped to the colors around each pixel,
+/// so what was disoccluded or changed doesn't smear.
+#[derive(Default)]
+pub(crate) struct Taa {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl PostEffect for Taa {
+    fn name(&self) -> &str {
+        TAA_NAME
+    }
+
+    fn inputs(&self) -> &[PostInput] {
+        &[PostInput::Previous, PostInput::History, PostInput::Velocity]
+    }
+
+    fn create(&mut self, setup: &PostSetup) -> AppResult<()> {
+        self.pipeline_layout = Application::create_pipeline_layout(
+            setup.device,
+            &[setup.input_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<f32>() as u32,
+            }],
+        )?;
+        self.pipeline = create_fullscreen_pipeline(
+            setup.device,
+            setup.render_pass,
+            self.pipeline_layout,
+            &load_shader("taa.frag", include_bytes!("../spirv/taa.spv"))?,
+        )?;
+        handle_registry::register(self.pipeline);
+
+        Ok(())
+    }
+
+    fn record(&mut self, pass: &PostPass) {
+        let history_weight: f32 = if pass.history_valid {
+            HISTORY_WEIGHT
+        } else {
+            0.0
+        };
+
+        unsafe {
+            pass.device.cmd_bind_pipeline(
+                pass.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            pass.device.cmd_bind_descriptor_sets(
+                pass.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[pass.input_set],
+                &[],
+            );
+            pass.device.cmd_push_constants(
+                pass.command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &history_weight.to_ne_bytes(),
+            );
+            pass.device.cmd_draw(pass.command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        unsafe {
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
+
+/// Element `index` of the Halton sequence in `base`, in [0, 1), spreading the successive jitter
+/// offsets evenly over the pixel
+pub(crate) fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
+}
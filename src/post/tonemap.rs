@@ -0,0 +1,49 @@
+/// Curve mapping the HDR scene to the range of the swapchain
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TonemapOperator {
+    /// Leaves the colors as is, the ones out of the range of the swapchain being clamped
+    #[default]
+    Linear,
+    /// `c / (1 + c)`, compressing the highlights without ever reaching white
+    Reinhard,
+    /// Fit of the ACES filmic curve, with a toe darkening the shadows and saturated highlights
+    Aces,
+}
+
+/// Tonemapping of the final pass compositing the chain output on the swapchain, see
+/// `Application::set_tonemapping`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tonemapping {
+    pub operator: TonemapOperator,
+    /// Scale of the scene colors before the operator
+    pub exposure: f32,
+}
+
+impl Default for Tonemapping {
+    fn default() -> Self {
+        Self {
+            operator: TonemapOperator::default(),
+            exposure: 1.0,
+        }
+    }
+}
+
+// Push constants of composite.frag
+#[repr(C)]
+pub(super) struct TonemapConstants {
+    exposure: f32,
+    operator: u32,
+}
+
+impl From<Tonemapping> for TonemapConstants {
+    fn from(tonemapping: Tonemapping) -> Self {
+        Self {
+            exposure: tonemapping.exposure,
+            operator: match tonemapping.operator {
+                TonemapOperator::Linear => 0,
+                TonemapOperator::Reinhard => 1,
+                TonemapOperator::Aces => 2,
+            },
+        }
+    }
+}
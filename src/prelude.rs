@@ -0,0 +1,7 @@
+pub use crate::{
+    actions, compute_tangents, AppError, AppErrorType, AppResult, Application, ApplicationBuilder,
+    Binding, CameraController, DrawItem, DrawItemId, FlyCamera, InputState, Mat4, MaterialHandle,
+    MeshHandle, OrbitCamera, PbrLight, PbrLighting, PbrMaterial, PickHit, PickId, Point2, Point3,
+    RendererEvent, ShaderMaterialDesc, TextureFiltering, TextureHandle, TonemapOperator,
+    Tonemapping, Vec2, Vec3, Vec4, Vertex,
+};
@@ -0,0 +1,188 @@
+use ash::{vk, Device};
+
+use crate::{
+    handle_registry,
+    queue_families::QueueFamilyIndice,
+    submit::{SubmitScheduler, WorkType},
+    AppResult,
+};
+
+/// How the swapchain images are shared between the graphics and the present queue families.
+///
+/// Both modes behave the same when a single queue family does graphics and presentation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SwapchainSharing {
+    /// The images are accessible from both families, which may prevent some driver optimizations
+    #[default]
+    Concurrent,
+    /// The images are owned by one family at a time and transferred to the present family after
+    /// rendering
+    Exclusive,
+}
+
+/// Transfers the ownership of the swapchain images from the graphics to the present family
+/// before they are presented, used with `SwapchainSharing::Exclusive` when the families differ.
+///
+/// The release half of the transfer is recorded at the end of the frame command buffer, the
+/// acquire half is pre-recorded for every swapchain image and submitted on the present queue.
+pub(crate) struct PresentTransfer {
+    graphics_family: u32,
+    present_family: u32,
+    pub command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+    acquired_semaphores: Vec<vk::Semaphore>,
+}
+
+impl PresentTransfer {
+    /// Returns `None` when no transfer is needed
+    pub(crate) fn new(
+        device: &Device,
+        indices: QueueFamilyIndice,
+        sharing: SwapchainSharing,
+        swapchain_images: &[vk::Image],
+        max_frame_in_flight: u32,
+    ) -> AppResult<Option<Self>> {
+        let graphics_family = indices.graphics_family.unwrap();
+        let present_family = indices.present_family.unwrap();
+        if sharing == SwapchainSharing::Concurrent || graphics_family == present_family {
+            return Ok(None);
+        }
+
+        let pool_info = vk::CommandPoolCreateInfo {
+            queue_family_index: present_family,
+            ..Default::default()
+        };
+        let command_pool = unsafe { device.create_command_pool(&pool_info, None)? };
+        handle_registry::register(command_pool);
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: swapchain_images.len() as u32,
+            ..Default::default()
+        };
+        let command_buffers = unsafe { device.allocate_command_buffers(&alloc_info)? };
+
+        let semaphore_info = vk::SemaphoreCreateInfo::default();
+        let mut acquired_semaphores = vec![];
+        for _ in 0..max_frame_in_flight {
+            let semaphore = unsafe { device.create_semaphore(&semaphore_info, None)? };
+            acquired_semaphores.push(handle_registry::register(semaphore));
+        }
+
+        let transfer = Self {
+            graphics_family,
+            present_family,
+            command_pool,
+            command_buffers,
+            acquired_semaphores,
+        };
+
+        // The image is only presented once the acquire is done, so a command buffer is never
+        // pending when its image is acquired again
+        let begin_info = vk::CommandBufferBeginInfo {
+            flags: vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
+            ..Default::default()
+        };
+        for (&command_buffer, &image) in transfer.command_buffers.iter().zip(swapchain_images) {
+            let barrier = transfer.barrier(image);
+            unsafe {
+                device.begin_command_buffer(command_buffer, &begin_info)?;
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                );
+                device.end_command_buffer(command_buffer)?;
+            }
+        }
+
+        Ok(Some(transfer))
+    }
+
+    /// Records the release of `image` by the graphics family, after the render pass writing it
+    pub(crate) fn record_release(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+    ) {
+        let barrier = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ..self.barrier(image)
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+        }
+    }
+
+    /// Schedules the acquire of the image `image_index` on the present queue once `render_done`
+    /// is signaled, returns the semaphore the presentation has to wait on
+    pub(crate) fn schedule_acquire(
+        &self,
+        scheduler: &mut SubmitScheduler,
+        present_queue: vk::Queue,
+        image_index: u32,
+        render_done: vk::Semaphore,
+        frame_index: usize,
+    ) -> vk::Semaphore {
+        let acquired = self.acquired_semaphores[frame_index];
+
+        scheduler.wait(
+            present_queue,
+            render_done,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+        );
+        scheduler.add(
+            present_queue,
+            WorkType::Present,
+            self.command_buffers[image_index as usize],
+        );
+        scheduler.signal(present_queue, acquired);
+
+        acquired
+    }
+
+    pub(crate) fn destroy(&self, device: &Device) {
+        unsafe {
+            for &semaphore in &self.acquired_semaphores {
+                handle_registry::unregister(semaphore);
+                device.destroy_semaphore(semaphore, None);
+            }
+            handle_registry::unregister(self.command_pool);
+            device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+
+    /// Ownership transfer of a presentable image, shared by both halves
+    fn barrier(&self, image: vk::Image) -> vk::ImageMemoryBarrier<'static> {
+        vk::ImageMemoryBarrier {
+            old_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            src_queue_family_index: self.graphics_family,
+            dst_queue_family_index: self.present_family,
+            image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        }
+    }
+}
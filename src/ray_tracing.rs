@@ -0,0 +1,1125 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+
+use ash::{khr::ray_tracing_pipeline, vk, Device, Instance};
+use cgmath::SquareMatrix;
+
+use crate::{
+    accel::{
+        align_up, buffer_device_address, instances_geometry, triangles_geometry, Accel,
+        AccelBuilder, Scratch,
+    },
+    garbage_collector::Garbage,
+    handle_registry, load_shader,
+    overlay::{create_overlay_pipeline, OverlayBlend},
+    AppResult, Application, BufferHolder, ImageHolder, Mat4, MemoryMappedBuffer, SyncPool, Vertex,
+};
+
+/// Draw items placed in the top-level acceleration structure of a frame at most, the following
+/// ones being left out of the ray traced image and shadows
+pub const MAX_RAY_TRACED_INSTANCES: usize = 1024;
+
+// Format of the ray traced image, which every device supports as a storage image
+const OUTPUT_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+// Raygen, miss and hit group of the shader binding table
+const SHADER_GROUP_COUNT: u32 = 3;
+
+/// How the ray traced image of the scene is combined with the rasterized one, see
+/// `Application::set_ray_tracing`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RayTracingMode {
+    /// Only the rasterized scene is drawn
+    #[default]
+    Disabled,
+    /// The ray traced meshes are drawn over the rasterized scene
+    Replace,
+    /// The ray traced meshes are blended over the rasterized scene with this opacity, between 0
+    /// and 1
+    Blend(f32),
+}
+
+impl RayTracingMode {
+    fn opacity(self) -> f32 {
+        match self {
+            RayTracingMode::Disabled => 0.0,
+            RayTracingMode::Replace => 1.0,
+            RayTracingMode::Blend(opacity) => opacity.clamp(0.0, 1.0),
+        }
+    }
+}
+
+// Addresses of the vertices and indices of an instance, read by the closest hit shader
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct InstanceGeometry {
+    vertices: vk::DeviceAddress,
+    indices: vk::DeviceAddress,
+}
+
+/// Bottom-level acceleration structure of a static mesh, compacted once built
+struct Blas {
+    accel: Accel,
+    // The vertices and indices it is built from, kept for the closest hit shader
+    vertices: BufferHolder,
+    indices: BufferHolder,
+    geometry: InstanceGeometry,
+}
+
+/// Bottom-level acceleration structure of a dynamic mesh for a frame in flight, built from the
+/// buffers of the mesh for that frame. It is refitted when the mesh is updated with as many
+/// triangles as its last build, and rebuilt otherwise.
+struct DynamicBlas {
+    accel: Accel,
+    scratch: Scratch,
+    vertex_capacity: usize,
+    geometry: InstanceGeometry,
+    // Triangles of the last build, `None` before the first one
+    built_triangles: Option<u32>,
+    // Triangles of the update to build with the frame
+    pending_triangles: Option<u32>,
+}
+
+enum MeshAccel {
+    Static(Blas),
+    // One per frame in flight
+    Dynamic(Vec<DynamicBlas>),
+}
+
+/// Top-level acceleration structure of a frame in flight, rebuilt every frame from the draw list
+struct FrameAccel {
+    tlas: Accel,
+    scratch: Scratch,
+    instances: MemoryMappedBuffer,
+    instance_address: vk::DeviceAddress,
+    geometries: MemoryMappedBuffer,
+    instance_count: u32,
+}
+
+struct FrameTracing {
+    trace_set: vk::DescriptorSet,
+    blend_set: vk::DescriptorSet,
+}
+
+struct OutputImage {
+    image: ImageHolder,
+    view: vk::ImageView,
+}
+
+/// Shader binding table, a region per shader group
+struct ShaderBindingTable {
+    buffer: BufferHolder,
+    raygen: vk::StridedDeviceAddressRegionKHR,
+    miss: vk::StridedDeviceAddressRegionKHR,
+    hit: vk::StridedDeviceAddressRegionKHR,
+}
+
+/// Acceleration structures of the scene, traced by the ray tracing pipeline and by the ray
+/// queries of the shadows.
+///
+/// Every mesh created while it is in use gets a bottom-level acceleration structure, refitted
+/// every frame the mesh is updated for the dynamic ones, and every frame builds a top-level one
+/// holding the draw items.
+pub(crate) struct SceneAccel {
+    builder: AccelBuilder,
+    frames: Vec<FrameAccel>,
+    meshes: HashMap<usize, MeshAccel>,
+}
+
+impl SceneAccel {
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        frame_count: usize,
+    ) -> AppResult<Self> {
+        let mut scene_accel = Self {
+            builder: AccelBuilder::new(instance, device, physical_device),
+            frames: Vec::with_capacity(frame_count),
+            meshes: HashMap::new(),
+        };
+        for _ in 0..frame_count {
+            let frame = scene_accel.create_frame(instance, device, physical_device)?;
+            scene_accel.frames.push(frame);
+        }
+
+        Ok(scene_accel)
+    }
+
+    fn create_frame(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+    ) -> AppResult<FrameAccel> {
+        let instance_size = std::mem::size_of::<vk::AccelerationStructureInstanceKHR>();
+        let mut buffers = Application::create_host_visible_buffers(
+            instance,
+            device,
+            physical_device,
+            (MAX_RAY_TRACED_INSTANCES * instance_size) as u64,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            1,
+        )?;
+        let instances = buffers.pop().unwrap();
+        let instance_address = buffer_device_address(device, instances.buffer);
+        let mut buffers = Application::create_host_visible_buffers(
+            instance,
+            device,
+            physical_device,
+            (MAX_RAY_TRACED_INSTANCES * std::mem::size_of::<InstanceGeometry>()) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            1,
+        )?;
+        let geometries = buffers.pop().unwrap();
+
+        // Sized for the most instances, a build with fewer fitting in the same storage
+        let (tlas, scratch) = self.builder.create(
+            instance,
+            device,
+            physical_device,
+            vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE,
+            &instances_geometry(instance_address),
+            MAX_RAY_TRACED_INSTANCES as u32,
+        )?;
+
+        Ok(FrameAccel {
+            tlas,
+            scratch,
+            instances,
+            instance_address,
+            geometries,
+            instance_count: 0,
+        })
+    }
+
+    /// Builds the bottom-level acceleration structure of the static mesh of index `mesh`,
+    /// waiting for the build. Returns the resources of its previous one to be retired.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn add_mesh(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        mesh: usize,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> AppResult<Vec<Garbage>> {
+        let usage = vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR
+            | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::STORAGE_BUFFER;
+        let vertex_buffer = Application::create_buffer_with_data(
+            instance,
+            device,
+            queue,
+            physical_device,
+            vertices,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            command_pool,
+            sync_pool,
+        )?;
+        let index_buffer = Application::create_buffer_with_data(
+            instance,
+            device,
+            queue,
+            physical_device,
+            indices,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            command_pool,
+            sync_pool,
+        )?;
+        let geometry = InstanceGeometry {
+            vertices: buffer_device_address(device, vertex_buffer.buffer),
+            indices: buffer_device_address(device, index_buffer.buffer),
+        };
+
+        let accel = self.builder.build_compacted(
+            instance,
+            device,
+            queue,
+            physical_device,
+            command_pool,
+            sync_pool,
+            &triangles_geometry(geometry.vertices, vertices.len(), geometry.indices),
+            (indices.len() / 3) as u32,
+        )?;
+        let previous = self.meshes.insert(
+            mesh,
+            MeshAccel::Static(Blas {
+                accel,
+                vertices: vertex_buffer,
+                indices: index_buffer,
+                geometry,
+            }),
+        );
+        Ok(previous.map_or_else(Vec::new, |previous| self.mesh_garbage(previous)))
+    }
+
+    /// Creates the bottom-level acceleration structures of the dynamic mesh of index `mesh`,
+    /// built from its buffers of each frame in flight once it is updated. Returns the resources
+    /// of its previous ones to be retired.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn add_dynamic_mesh(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        mesh: usize,
+        buffers: impl Iterator<Item = (vk::Buffer, vk::Buffer)>,
+        vertex_capacity: usize,
+        index_capacity: usize,
+    ) -> AppResult<Vec<Garbage>> {
+        let mut frames = Vec::with_capacity(self.frames.len());
+        for (vertex_buffer, index_buffer) in buffers {
+            let geometry = InstanceGeometry {
+                vertices: buffer_device_address(device, vertex_buffer),
+                indices: buffer_device_address(device, index_buffer),
+            };
+            let (accel, scratch) = self.builder.create(
+                instance,
+                device,
+                physical_device,
+                vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
+                vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD
+                    | vk::BuildAccelerationStructureFlagsKHR::ALLOW_UPDATE,
+                &triangles_geometry(geometry.vertices, vertex_capacity, geometry.indices),
+                (index_capacity / 3) as u32,
+            )?;
+            frames.push(DynamicBlas {
+                accel,
+                scratch,
+                vertex_capacity,
+                geometry,
+                built_triangles: None,
+                pending_triangles: None,
+            });
+        }
+
+        let previous = self.meshes.insert(mesh, MeshAccel::Dynamic(frames));
+        Ok(previous.map_or_else(Vec::new, |previous| self.mesh_garbage(previous)))
+    }
+
+    /// Builds the acceleration structure of `frame` of a dynamic mesh along with the frame, its
+    /// buffers of the frame now holding `triangle_count` triangles
+    pub(crate) fn update_dynamic_mesh(&mut self, frame: usize, mesh: usize, triangle_count: u32) {
+        if let Some(MeshAccel::Dynamic(frames)) = self.meshes.get_mut(&mesh) {
+            frames[frame].pending_triangles = Some(triangle_count);
+        }
+    }
+
+    /// Forgets the acceleration structures of a mesh, returning their resources to be retired
+    pub(crate) fn remove_mesh(&mut self, mesh: usize) -> Vec<Garbage> {
+        self.meshes
+            .remove(&mesh)
+            .map_or_else(Vec::new, |mesh| self.mesh_garbage(mesh))
+    }
+
+    fn mesh_garbage(&self, mesh: MeshAccel) -> Vec<Garbage> {
+        match mesh {
+            MeshAccel::Static(blas) => {
+                let mut garbage = Vec::from(self.builder.garbage(blas.accel));
+                garbage.extend([
+                    Garbage::Buffer(blas.vertices.buffer, blas.vertices.memory),
+                    Garbage::Buffer(blas.indices.buffer, blas.indices.memory),
+                ]);
+                garbage
+            }
+            MeshAccel::Dynamic(frames) => frames
+                .into_iter()
+                .flat_map(|blas| {
+                    let [accel, buffer] = self.builder.garbage(blas.accel);
+                    [accel, buffer, blas.scratch.into_garbage()]
+                })
+                .collect(),
+        }
+    }
+
+    /// Writes the instances of the top-level acceleration structure of `frame`, one for each
+    /// mesh and transform having an acceleration structure
+    pub(crate) fn write_instances(
+        &mut self,
+        frame: usize,
+        instances: impl Iterator<Item = (usize, Mat4)>,
+    ) {
+        let frame_accel = &mut self.frames[frame];
+        let instance_map =
+            frame_accel.instances.memory_map as *mut vk::AccelerationStructureInstanceKHR;
+        let geometry_map = frame_accel.geometries.memory_map as *mut InstanceGeometry;
+
+        let mut count = 0;
+        for (mesh, model) in instances {
+            let (address, geometry) = match self.meshes.get(&mesh) {
+                Some(MeshAccel::Static(blas)) => (blas.accel.address, blas.geometry),
+                // Left out until the first update of the mesh is built
+                Some(MeshAccel::Dynamic(frames))
+                    if frames[frame]
+                        .built_triangles
+                        .or(frames[frame].pending_triangles)
+                        .is_some() =>
+                {
+                    (frames[frame].accel.address, frames[frame].geometry)
+                }
+                _ => continue,
+            };
+            if count == MAX_RAY_TRACED_INSTANCES {
+                break;
+            }
+
+            // Rows of the affine part of the model matrix, which is stored by columns
+            let mut matrix = [0.0; 12];
+            for row in 0..3 {
+                for column in 0..4 {
+                    matrix[row * 4 + column] = model[column][row];
+                }
+            }
+            let instance = vk::AccelerationStructureInstanceKHR {
+                transform: vk::TransformMatrixKHR { matrix },
+                instance_custom_index_and_mask: vk::Packed24_8::new(count as u32, 0xFF),
+                instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
+                    0,
+                    vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
+                ),
+                acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+                    device_handle: address,
+                },
+            };
+            unsafe {
+                instance_map.add(count).write(instance);
+                geometry_map.add(count).write(geometry);
+            }
+            count += 1;
+        }
+        frame_accel.instance_count = count as u32;
+    }
+
+    /// Top-level acceleration structure of a frame, built by `record_build`
+    pub(crate) fn tlas(&self, frame: usize) -> vk::AccelerationStructureKHR {
+        self.frames[frame].tlas.handle
+    }
+
+    /// Addresses of the geometries of the instances of a frame, in the order of their custom
+    /// index
+    pub(crate) fn geometries_buffer(&self, frame: usize) -> vk::Buffer {
+        self.frames[frame].geometries.buffer
+    }
+
+    /// Refits or rebuilds the acceleration structures of the dynamic meshes updated for `frame`,
+    /// then builds the top-level acceleration structure of `frame` from its instances, before the
+    /// shaders of `dst_stage` trace it
+    pub(crate) fn record_build(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        dst_stage: vk::PipelineStageFlags,
+    ) {
+        let build_barrier = vk::MemoryBarrier {
+            src_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR,
+            dst_access_mask: vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR,
+            ..Default::default()
+        };
+
+        let mut dynamic_builds = false;
+        for mesh in self.meshes.values_mut() {
+            let MeshAccel::Dynamic(frames) = mesh else {
+                continue;
+            };
+            let blas = &mut frames[frame];
+            let Some(triangle_count) = blas.pending_triangles.take() else {
+                continue;
+            };
+
+            let geometry = triangles_geometry(
+                blas.geometry.vertices,
+                blas.vertex_capacity,
+                blas.geometry.indices,
+            );
+            self.builder.record_build(
+                command_buffer,
+                &blas.accel,
+                &blas.scratch,
+                &geometry,
+                triangle_count,
+                blas.built_triangles == Some(triangle_count),
+            );
+            blas.built_triangles = Some(triangle_count);
+            dynamic_builds = true;
+        }
+
+        let frame_accel = &self.frames[frame];
+        unsafe {
+            // The instances reference the bottom-level structures just built
+            if dynamic_builds {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                    vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                    vk::DependencyFlags::empty(),
+                    &[build_barrier],
+                    &[],
+                    &[],
+                );
+            }
+            self.builder.record_build(
+                command_buffer,
+                &frame_accel.tlas,
+                &frame_accel.scratch,
+                &instances_geometry(frame_accel.instance_address),
+                frame_accel.instance_count,
+                false,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[build_barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    /// Destroys the acceleration structures, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        let mut garbage = Vec::new();
+        for (_, mesh) in std::mem::take(&mut self.meshes) {
+            garbage.extend(self.mesh_garbage(mesh));
+        }
+        for frame in std::mem::take(&mut self.frames) {
+            garbage.extend(self.builder.garbage(frame.tlas));
+            garbage.extend([
+                frame.scratch.into_garbage(),
+                Garbage::Buffer(frame.instances.buffer, frame.instances.memory),
+                Garbage::Buffer(frame.geometries.buffer, frame.geometries.memory),
+            ]);
+        }
+        for garbage in garbage {
+            unsafe { garbage.destroy(device) };
+        }
+    }
+}
+
+/// Ray traced image of the scene, made with `VK_KHR_ray_tracing_pipeline` and blended over the
+/// rasterized meshes at the end of the opaque and transparent draws.
+///
+/// A ray is traced per pixel through the acceleration structures of the `SceneAccel`, the
+/// closest hit shading the mesh with its interpolated vertex colors, and the misses leaving the
+/// pixel transparent so the rasterized scene shows through.
+pub(crate) struct RayTracing {
+    pipeline_loader: ray_tracing_pipeline::Device,
+    trace_set_layout: vk::DescriptorSetLayout,
+    trace_pipeline_layout: vk::PipelineLayout,
+    trace_pipeline: vk::Pipeline,
+    binding_table: ShaderBindingTable,
+    blend_set_layout: vk::DescriptorSetLayout,
+    blend_pipeline_layout: vk::PipelineLayout,
+    blend_pipeline: vk::Pipeline,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    frames: Vec<FrameTracing>,
+    // An image per frame in flight, created along with the scene target
+    outputs: Vec<OutputImage>,
+    mode: RayTracingMode,
+}
+
+impl RayTracing {
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        scene_render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        scene_accel: &SceneAccel,
+        mode: RayTracingMode,
+    ) -> AppResult<Self> {
+        let pipeline_loader = ray_tracing_pipeline::Device::new(instance, device);
+
+        let mut pipeline_properties = vk::PhysicalDeviceRayTracingPipelinePropertiesKHR::default();
+        let mut properties2 =
+            vk::PhysicalDeviceProperties2::default().push_next(&mut pipeline_properties);
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties2) };
+
+        // The scene, the ray traced image and the geometries of the instances
+        let trace_bindings = [
+            (
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                vk::ShaderStageFlags::RAYGEN_KHR,
+            ),
+            (
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::RAYGEN_KHR,
+            ),
+            (
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+            ),
+        ];
+        let trace_set_layout = create_set_layout(device, &trace_bindings)?;
+        let blend_set_layout = create_set_layout(
+            device,
+            &[(
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::ShaderStageFlags::FRAGMENT,
+            )],
+        )?;
+
+        let trace_pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[trace_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::RAYGEN_KHR,
+                offset: 0,
+                size: std::mem::size_of::<Mat4>() as u32,
+            }],
+        )?;
+        let trace_pipeline =
+            Self::create_trace_pipeline(device, &pipeline_loader, trace_pipeline_layout)?;
+        let binding_table = Self::create_binding_table(
+            instance,
+            device,
+            physical_device,
+            &pipeline_loader,
+            trace_pipeline,
+            &pipeline_properties,
+        )?;
+
+        let blend_pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[blend_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<f32>() as u32,
+            }],
+        )?;
+        let blend_pipeline = create_overlay_pipeline(
+            device,
+            scene_render_pass,
+            samples,
+            blend_pipeline_layout,
+            &load_shader("fullscreen.vert", include_bytes!("spirv/fullscreen.spv"))?,
+            &load_shader(
+                "ray_traced_blend.frag",
+                include_bytes!("spirv/ray_traced_blend.spv"),
+            )?,
+            &[],
+            &[],
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            OverlayBlend::Alpha,
+        )?;
+
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+        handle_registry::register(sampler);
+
+        let pool_sizes = [
+            vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            vk::DescriptorType::STORAGE_IMAGE,
+            vk::DescriptorType::STORAGE_BUFFER,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        ]
+        .map(|ty| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: scene_accel.frames.len() as u32,
+        });
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: 2 * scene_accel.frames.len() as u32,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let mut ray_tracing = Self {
+            pipeline_loader,
+            trace_set_layout,
+            trace_pipeline_layout,
+            trace_pipeline,
+            binding_table,
+            blend_set_layout,
+            blend_pipeline_layout,
+            blend_pipeline,
+            sampler,
+            descriptor_pool,
+            frames: Vec::with_capacity(scene_accel.frames.len()),
+            outputs: Vec::new(),
+            mode,
+        };
+        for frame in 0..scene_accel.frames.len() {
+            let frame = ray_tracing.create_frame(device, scene_accel, frame)?;
+            ray_tracing.frames.push(frame);
+        }
+
+        Ok(ray_tracing)
+    }
+
+    fn create_trace_pipeline(
+        device: &Device,
+        pipeline_loader: &ray_tracing_pipeline::Device,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> AppResult<vk::Pipeline> {
+        let raygen_module = Application::create_shader_module(
+            device,
+            &load_shader("primary_ray.rgen", include_bytes!("spirv/primary_ray.spv"))?,
+        )?;
+        let miss_module = Application::create_shader_module(
+            device,
+            &load_shader("sky_miss.rmiss", include_bytes!("spirv/sky_miss.spv"))?,
+        )?;
+        let hit_module = Application::create_shader_module(
+            device,
+            &load_shader(
+                "vertex_color_hit.rchit",
+                include_bytes!("spirv/vertex_color_hit.spv"),
+            )?,
+        )?;
+
+        let entry_point = CString::new("main").unwrap();
+        let stages = [
+            (vk::ShaderStageFlags::RAYGEN_KHR, raygen_module),
+            (vk::ShaderStageFlags::MISS_KHR, miss_module),
+            (vk::ShaderStageFlags::CLOSEST_HIT_KHR, hit_module),
+        ]
+        .map(|(stage, module)| vk::PipelineShaderStageCreateInfo {
+            stage,
+            module,
+            p_name: entry_point.as_ptr(),
+            ..Default::default()
+        });
+
+        // The groups follow the stages, in the order of the shader binding table
+        let general_group = |shader| vk::RayTracingShaderGroupCreateInfoKHR {
+            ty: vk::RayTracingShaderGroupTypeKHR::GENERAL,
+            general_shader: shader,
+            closest_hit_shader: vk::SHADER_UNUSED_KHR,
+            any_hit_shader: vk::SHADER_UNUSED_KHR,
+            intersection_shader: vk::SHADER_UNUSED_KHR,
+            ..Default::default()
+        };
+        let groups = [
+            general_group(0),
+            general_group(1),
+            vk::RayTracingShaderGroupCreateInfoKHR {
+                ty: vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP,
+                general_shader: vk::SHADER_UNUSED_KHR,
+                closest_hit_shader: 2,
+                any_hit_shader: vk::SHADER_UNUSED_KHR,
+                intersection_shader: vk::SHADER_UNUSED_KHR,
+                ..Default::default()
+            },
+        ];
+
+        let pipeline_info = vk::RayTracingPipelineCreateInfoKHR {
+            stage_count: stages.len() as u32,
+            p_stages: stages.as_ptr(),
+            group_count: groups.len() as u32,
+            p_groups: groups.as_ptr(),
+            // The closest hit shader doesn't trace any ray
+            max_pipeline_ray_recursion_depth: 1,
+            layout: pipeline_layout,
+            ..Default::default()
+        };
+
+        let pipeline = unsafe {
+            pipeline_loader
+                .create_ray_tracing_pipelines(
+                    vk::DeferredOperationKHR::null(),
+                    vk::PipelineCache::null(),
+                    &[pipeline_info],
+                    None,
+                )
+                .or_else(|r| AppResult::Err(r.1.into()))?[0]
+        };
+        handle_registry::register(pipeline);
+
+        unsafe {
+            for module in [raygen_module, miss_module, hit_module] {
+                handle_registry::unregister(module);
+                device.destroy_shader_module(module, None);
+            }
+        }
+
+        Ok(pipeline)
+    }
+
+    /// Copies the handles of the shader groups to a buffer, each at the start of its region
+    fn create_binding_table(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        pipeline_loader: &ray_tracing_pipeline::Device,
+        pipeline: vk::Pipeline,
+        properties: &vk::PhysicalDeviceRayTracingPipelinePropertiesKHR,
+    ) -> AppResult<ShaderBindingTable> {
+        let handle_size = properties.shader_group_handle_size as u64;
+        let handle_stride = align_up(handle_size, properties.shader_group_handle_alignment as u64);
+        let region_size = align_up(handle_stride, properties.shader_group_base_alignment as u64);
+        let base_alignment = properties.shader_group_base_alignment as u64;
+
+        let handles = unsafe {
+            pipeline_loader.get_ray_tracing_shader_group_handles(
+                pipeline,
+                0,
+                SHADER_GROUP_COUNT,
+                (SHADER_GROUP_COUNT as u64 * handle_size) as usize,
+            )?
+        };
+
+        // Room for aligning the first region, the buffer address only being aligned to its
+        // memory requirements
+        let buffer_size = SHADER_GROUP_COUNT as u64 * region_size + base_alignment;
+        let buffer = Application::create_buffer(
+            instance,
+            device,
+            physical_device,
+            buffer_size,
+            vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR
+                | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let buffer_address = buffer_device_address(device, buffer.buffer);
+        let table_address = align_up(buffer_address, base_alignment);
+
+        unsafe {
+            let memory_map =
+                device.map_memory(buffer.memory, 0, buffer_size, vk::MemoryMapFlags::empty())?
+                    as *mut u8;
+            for (group, handle) in handles.chunks_exact(handle_size as usize).enumerate() {
+                let offset = table_address - buffer_address + group as u64 * region_size;
+                std::ptr::copy_nonoverlapping(
+                    handle.as_ptr(),
+                    memory_map.add(offset as usize),
+                    handle.len(),
+                );
+            }
+            device.unmap_memory(buffer.memory);
+        }
+
+        let region = |group: u64| vk::StridedDeviceAddressRegionKHR {
+            device_address: table_address + group * region_size,
+            stride: handle_stride,
+            size: handle_stride,
+        };
+        Ok(ShaderBindingTable {
+            buffer,
+            // The stride of the raygen region must be its size
+            raygen: vk::StridedDeviceAddressRegionKHR {
+                stride: region_size,
+                size: region_size,
+                ..region(0)
+            },
+            miss: region(1),
+            hit: region(2),
+        })
+    }
+
+    fn create_frame(
+        &self,
+        device: &Device,
+        scene_accel: &SceneAccel,
+        frame: usize,
+    ) -> AppResult<FrameTracing> {
+        let layouts = [self.trace_set_layout, self.blend_set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool: self.descriptor_pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+        let sets = unsafe { device.allocate_descriptor_sets(&alloc_info)? };
+
+        let tlases = [scene_accel.tlas(frame)];
+        let mut accel_write = vk::WriteDescriptorSetAccelerationStructureKHR {
+            acceleration_structure_count: tlases.len() as u32,
+            p_acceleration_structures: tlases.as_ptr(),
+            ..Default::default()
+        };
+        let geometries_info = vk::DescriptorBufferInfo {
+            buffer: scene_accel.geometries_buffer(frame),
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+        let writes = [
+            vk::WriteDescriptorSet {
+                dst_set: sets[0],
+                dst_binding: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+                ..Default::default()
+            }
+            .push_next(&mut accel_write),
+            vk::WriteDescriptorSet {
+                dst_set: sets[0],
+                dst_binding: 2,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_buffer_info: &geometries_info,
+                ..Default::default()
+            },
+        ];
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+
+        Ok(FrameTracing {
+            trace_set: sets[0],
+            blend_set: sets[1],
+        })
+    }
+
+    pub(crate) fn mode(&self) -> RayTracingMode {
+        self.mode
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: RayTracingMode) {
+        self.mode = mode;
+    }
+
+    /// Ray traced image of a frame, once the target is created
+    pub(crate) fn output_image(&self, frame: usize) -> Option<vk::Image> {
+        self.outputs.get(frame).map(|output| output.image.image)
+    }
+
+    /// Creates the ray traced images with the size of the scene target, unless they already
+    /// exist
+    pub(crate) fn create_target(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+    ) -> AppResult<()> {
+        if !self.outputs.is_empty() {
+            return Ok(());
+        }
+
+        for frame in self.frames.iter() {
+            let image = Application::create_image(
+                instance,
+                device,
+                physical_device,
+                extent.width,
+                extent.height,
+                1,
+                OUTPUT_FORMAT,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?;
+            let view = Application::create_image_view(device, image.image, OUTPUT_FORMAT, 1)?;
+
+            let storage_info = vk::DescriptorImageInfo {
+                image_view: view,
+                image_layout: vk::ImageLayout::GENERAL,
+                ..Default::default()
+            };
+            let sampled_info = vk::DescriptorImageInfo {
+                sampler: self.sampler,
+                image_view: view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            };
+            let writes = [
+                vk::WriteDescriptorSet {
+                    dst_set: frame.trace_set,
+                    dst_binding: 1,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                    p_image_info: &storage_info,
+                    ..Default::default()
+                },
+                vk::WriteDescriptorSet {
+                    dst_set: frame.blend_set,
+                    dst_binding: 0,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    p_image_info: &sampled_info,
+                    ..Default::default()
+                },
+            ];
+            unsafe { device.update_descriptor_sets(&writes, &[]) };
+
+            self.outputs.push(OutputImage { image, view });
+        }
+
+        Ok(())
+    }
+
+    /// Destroys the ray traced images, to be recreated along with the scene target. The device
+    /// must be idle.
+    pub(crate) fn destroy_target(&mut self, device: &Device) {
+        for output in self.outputs.drain(..) {
+            unsafe {
+                handle_registry::unregister(output.view);
+                device.destroy_image_view(output.view, None);
+                handle_registry::unregister(output.image.image);
+                device.destroy_image(output.image.image, None);
+                handle_registry::unregister(output.image.memory);
+                device.free_memory(output.image.memory, None);
+            }
+        }
+    }
+
+    /// Traces the scene seen through `view_proj` to the image of `frame`, once the target is
+    /// created and the top-level acceleration structure of the frame is built
+    pub(crate) fn record_trace(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        extent: vk::Extent2D,
+        view_proj: Mat4,
+    ) {
+        if self.outputs.is_empty() {
+            return;
+        }
+
+        // The rays are unprojected from the near to the far plane
+        let inverse_view_proj = view_proj.invert().unwrap_or(Mat4::identity());
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                self.trace_pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::RAY_TRACING_KHR,
+                self.trace_pipeline_layout,
+                0,
+                &[self.frames[frame].trace_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.trace_pipeline_layout,
+                vk::ShaderStageFlags::RAYGEN_KHR,
+                0,
+                std::slice::from_raw_parts(
+                    &inverse_view_proj as *const Mat4 as *const u8,
+                    std::mem::size_of::<Mat4>(),
+                ),
+            );
+            self.pipeline_loader.cmd_trace_rays(
+                command_buffer,
+                &self.binding_table.raygen,
+                &self.binding_table.miss,
+                &self.binding_table.hit,
+                &vk::StridedDeviceAddressRegionKHR::default(),
+                extent.width,
+                extent.height,
+                1,
+            );
+        }
+    }
+
+    /// Blends the ray traced image of `frame` over the scene, in the scene render pass. Returns
+    /// the number of draw calls.
+    pub(crate) fn record_blend(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) -> u32 {
+        if self.outputs.is_empty() {
+            return 0;
+        }
+
+        let opacity = self.mode.opacity();
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.blend_pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.blend_pipeline_layout,
+                0,
+                &[self.frames[frame].blend_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.blend_pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &opacity.to_ne_bytes(),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+        1
+    }
+
+    /// Destroys the images and the pipelines, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        self.destroy_target(device);
+
+        unsafe {
+            Garbage::Buffer(
+                self.binding_table.buffer.buffer,
+                self.binding_table.buffer.memory,
+            )
+            .destroy(device);
+
+            handle_registry::unregister(self.descriptor_pool);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            handle_registry::unregister(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+            handle_registry::unregister(self.blend_pipeline);
+            device.destroy_pipeline(self.blend_pipeline, None);
+            handle_registry::unregister(self.blend_pipeline_layout);
+            device.destroy_pipeline_layout(self.blend_pipeline_layout, None);
+            handle_registry::unregister(self.blend_set_layout);
+            device.destroy_descriptor_set_layout(self.blend_set_layout, None);
+            handle_registry::unregister(self.trace_pipeline);
+            device.destroy_pipeline(self.trace_pipeline, None);
+            handle_registry::unregister(self.trace_pipeline_layout);
+            device.destroy_pipeline_layout(self.trace_pipeline_layout, None);
+            handle_registry::unregister(self.trace_set_layout);
+            device.destroy_descriptor_set_layout(self.trace_set_layout, None);
+        }
+    }
+}
+
+fn create_set_layout(
+    device: &Device,
+    bindings: &[(vk::DescriptorType, vk::ShaderStageFlags)],
+) -> AppResult<vk::DescriptorSetLayout> {
+    let bindings: Vec<vk::DescriptorSetLayoutBinding> = bindings
+        .iter()
+        .enumerate()
+        .map(
+            |(binding, &(descriptor_type, stage_flags))| vk::DescriptorSetLayoutBinding {
+                binding: binding as u32,
+                descriptor_type,
+                descriptor_count: 1,
+                stage_flags,
+                ..Default::default()
+            },
+        )
+        .collect();
+    let layout_info = vk::DescriptorSetLayoutCreateInfo {
+        binding_count: bindings.len() as u32,
+        p_bindings: bindings.as_ptr(),
+        ..Default::default()
+    };
+    let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+
+    Ok(handle_registry::register(set_layout))
+}
@@ -0,0 +1,599 @@
+use ash::{vk, Device, Instance};
+use cgmath::MetricSpace;
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    geometry::{Mat4, Point3, Vec3},
+    handle_registry, load_shader,
+    post::{create_fullscreen_pipeline, SCENE_COLOR_FORMAT},
+    AppResult, Application, ImageHolder,
+};
+
+/// Size of the faces of the probe cubemaps
+pub const PROBE_RESOLUTION: u32 = 128;
+/// Levels of the probe cubemaps, prefiltered from a mirror-like surface at level 0 to a fully
+/// rough one at the last level
+pub const PROBE_MIP_LEVELS: u32 = 5;
+pub const MAX_REFLECTION_PROBES: usize = 16;
+
+// Captured with the scene pipelines, in a single sample render pass of the scene format
+const PROBE_FORMAT: vk::Format = SCENE_COLOR_FORMAT;
+
+// Forward direction and up vector of the camera of each face, in the cubemap face order
+pub(crate) const FACE_CAMERAS: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+];
+
+/// Identifies a reflection probe so it can be moved, refreshed or removed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ReflectionProbeId(u64);
+
+struct Probe {
+    id: ReflectionProbeId,
+    position: Point3,
+    radius: f32,
+    cubemap: ImageHolder,
+    // Every level, sampled when shading
+    cube_view: vk::ImageView,
+    // Level 0 only, read by the prefiltering of the other levels
+    capture_view: vk::ImageView,
+    // One view and framebuffer per level and face, level major
+    face_views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+    descriptor_set: vk::DescriptorSet,
+    // Captured again with the next frame
+    stale: bool,
+}
+
+/// Cubemaps of the scene captured from placed points, prefiltered for specular image based
+/// lighting.
+///
+/// A probe is captured with the frame following its placement or refresh, by drawing the draw list
+/// once per face, then each level is prefiltered with the GGX distribution for a roughness
+/// growing with the level. The shading blends the probes around a point with `weights`.
+pub(crate) struct ReflectionProbes {
+    // Leaves each face in the shader read only layout
+    render_pass: vk::RenderPass,
+    capture_pipeline: vk::Pipeline,
+    capture_layout: vk::PipelineLayout,
+    prefilter_pipeline: vk::Pipeline,
+    prefilter_layout: vk::PipelineLayout,
+    prefilter_set_layout: vk::DescriptorSetLayout,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    probes: Vec<Probe>,
+    next_id: u64,
+}
+
+impl ReflectionProbes {
+    pub(crate) fn new(
+        device: &Device,
+        scene_set_layout: vk::DescriptorSetLayout,
+    ) -> AppResult<Self> {
+        // The scene render pass may be multisampled, which the faces are not
+        let render_pass = Application::create_render_pass(
+            device,
+            PROBE_FORMAT,
+            vk::AttachmentLoadOp::CLEAR,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+        // The draw items keep their scene set, only the view and projection are replaced
+        let capture_layout = Application::create_pipeline_layout(
+            device,
+            &[scene_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<Mat4>() as u32,
+            }],
+        )?;
+        let capture_pipeline = Application::create_scene_pipeline(
+            device,
+            render_pass,
+            vk::SampleCountFlags::TYPE_1,
+            capture_layout,
+            &load_shader(
+                "probe_capture.vert",
+                include_bytes!("spirv/probe_capture.spv"),
+            )?,
+            &load_shader("fragment.frag", include_bytes!("spirv/fragment.spv"))?,
+            // The faces are mirrored compared to the camera, which flips the winding
+            vk::CullModeFlags::NONE,
+        )?;
+
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        }];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let prefilter_set_layout =
+            unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(prefilter_set_layout);
+
+        let prefilter_layout = Application::create_pipeline_layout(
+            device,
+            &[prefilter_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: 8,
+            }],
+        )?;
+        let prefilter_pipeline = create_fullscreen_pipeline(
+            device,
+            render_pass,
+            prefilter_layout,
+            &load_shader(
+                "probe_prefilter.frag",
+                include_bytes!("spirv/probe_prefilter.spv"),
+            )?,
+        )?;
+        handle_registry::register(prefilter_pipeline);
+
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            max_lod: vk::LOD_CLAMP_NONE,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+        handle_registry::register(sampler);
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: MAX_REFLECTION_PROBES as u32,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            max_sets: MAX_REFLECTION_PROBES as u32,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        Ok(Self {
+            render_pass,
+            capture_pipeline,
+            capture_layout,
+            prefilter_pipeline,
+            prefilter_layout,
+            prefilter_set_layout,
+            sampler,
+            descriptor_pool,
+            probes: Vec::new(),
+            next_id: 0,
+        })
+    }
+
+    /// Places a probe influencing the points within `radius` of `position`, captured with the
+    /// next frame. Fails once `MAX_REFLECTION_PROBES` probes are placed.
+    pub(crate) fn add(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        position: Point3,
+        radius: f32,
+    ) -> AppResult<ReflectionProbeId> {
+        if self.probes.len() >= MAX_REFLECTION_PROBES {
+            return AppResult::Err(AppError::new(AppErrorType::TooManyReflectionProbes));
+        }
+
+        let image_info = vk::ImageCreateInfo {
+            flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            image_type: vk::ImageType::TYPE_2D,
+            format: PROBE_FORMAT,
+            extent: vk::Extent3D {
+                width: PROBE_RESOLUTION,
+                height: PROBE_RESOLUTION,
+                depth: 1,
+            },
+            mip_levels: PROBE_MIP_LEVELS,
+            array_layers: 6,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            ..Default::default()
+        };
+        let image = unsafe { device.create_image(&image_info, None)? };
+        handle_registry::register(image);
+
+        let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
+        let alloc_info = vk::MemoryAllocateInfo {
+            allocation_size: mem_requirements.size,
+            memory_type_index: Application::find_memory_type(
+                instance,
+                physical_device,
+                mem_requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?,
+            ..Default::default()
+        };
+        let memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+        handle_registry::register(memory);
+        unsafe { device.bind_image_memory(image, memory, 0)? };
+
+        let cube_view = Self::create_view(
+            device,
+            image,
+            vk::ImageViewType::CUBE,
+            0,
+            PROBE_MIP_LEVELS,
+            0,
+            6,
+        )?;
+        let capture_view = Self::create_view(device, image, vk::ImageViewType::CUBE, 0, 1, 0, 6)?;
+
+        let mut face_views = Vec::with_capacity((PROBE_MIP_LEVELS * 6) as usize);
+        let mut framebuffers = Vec::with_capacity(face_views.capacity());
+        for level in 0..PROBE_MIP_LEVELS {
+            for face in 0..6 {
+                let view = Self::create_view(
+                    device,
+                    image,
+                    vk::ImageViewType::TYPE_2D,
+                    level,
+                    1,
+                    face,
+                    1,
+                )?;
+                let attachments = [view];
+                let framebuffer_info = vk::FramebufferCreateInfo {
+                    render_pass: self.render_pass,
+                    attachment_count: attachments.len() as u32,
+                    p_attachments: attachments.as_ptr(),
+                    width: PROBE_RESOLUTION >> level,
+                    height: PROBE_RESOLUTION >> level,
+                    layers: 1,
+                    ..Default::default()
+                };
+                let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None)? };
+                handle_registry::register(framebuffer);
+
+                face_views.push(view);
+                framebuffers.push(framebuffer);
+            }
+        }
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool: self.descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &self.prefilter_set_layout as *const _,
+            ..Default::default()
+        };
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+        let image_info = vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: capture_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: &image_info as *const _,
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        let id = ReflectionProbeId(self.next_id);
+        self.next_id += 1;
+        self.probes.push(Probe {
+            id,
+            position,
+            radius,
+            cubemap: ImageHolder::new(image, memory),
+            cube_view,
+            capture_view,
+            face_views,
+            framebuffers,
+            descriptor_set,
+            stale: true,
+        });
+
+        Ok(id)
+    }
+
+    /// Moves a probe, which is captured again with the next frame. Returns `false` if there is
+    /// no such probe.
+    pub(crate) fn place(&mut self, id: ReflectionProbeId, position: Point3, radius: f32) -> bool {
+        let Some(probe) = self.probes.iter_mut().find(|probe| probe.id == id) else {
+            return false;
+        };
+        probe.position = position;
+        probe.radius = radius;
+        probe.stale = true;
+        true
+    }
+
+    /// Captures a probe again with the next frame, e.g. once the scene around it changed. Returns
+    /// `false` if there is no such probe.
+    pub(crate) fn refresh(&mut self, id: ReflectionProbeId) -> bool {
+        let Some(probe) = self.probes.iter_mut().find(|probe| probe.id == id) else {
+            return false;
+        };
+        probe.stale = true;
+        true
+    }
+
+    /// Destroys a probe, the device must be idle. Returns `false` if there is no such probe.
+    pub(crate) fn remove(&mut self, device: &Device, id: ReflectionProbeId) -> AppResult<bool> {
+        let Some(index) = self.probes.iter().position(|probe| probe.id == id) else {
+            return Ok(false);
+        };
+        let probe = self.probes.remove(index);
+        unsafe {
+            device.free_descriptor_sets(self.descriptor_pool, &[probe.descriptor_set])?;
+            Self::destroy_probe(device, probe);
+        }
+        Ok(true)
+    }
+
+    /// Returns the cubemap of a probe, in the shader read only layout once captured
+    pub(crate) fn view(&self, id: ReflectionProbeId) -> Option<vk::ImageView> {
+        self.probes
+            .iter()
+            .find(|probe| probe.id == id)
+            .map(|probe| probe.cube_view)
+    }
+
+    /// Returns the probes whose radius contains `position` with their contribution to its
+    /// reflections, the closer the bigger, summing to 1. The probes are sorted from the biggest
+    /// contribution, empty if no probe reaches `position`.
+    pub(crate) fn weights(&self, position: Point3) -> Vec<(ReflectionProbeId, f32)> {
+        let mut weights: Vec<(ReflectionProbeId, f32)> = self
+            .probes
+            .iter()
+            .filter_map(|probe| {
+                let distance = probe.position.distance(position);
+                (distance < probe.radius).then(|| (probe.id, 1.0 - distance / probe.radius))
+            })
+            .collect();
+
+        let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+        // Every weight is positive as the points at the edge of a radius aren't reached
+        for (_, weight) in weights.iter_mut() {
+            *weight /= total;
+        }
+        weights.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        weights
+    }
+
+    /// Records the capture and the prefiltering of the stale probes. `draw` records the draws of
+    /// the scene with the pipeline bound, binding their scene set with the given layout.
+    pub(crate) fn record_captures(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        mut draw: impl FnMut(vk::PipelineLayout),
+    ) {
+        let proj = cgmath::perspective(cgmath::Deg(90.0), 1.0, 0.05, 20.0);
+
+        for probe in self.probes.iter_mut().filter(|probe| probe.stale) {
+            probe.stale = false;
+
+            for (face, (direction, up)) in FACE_CAMERAS.iter().enumerate() {
+                let view =
+                    Mat4::look_to_rh(probe.position, Vec3::from(*direction), Vec3::from(*up));
+                let view_proj: [[f32; 4]; 4] = (proj * view).into();
+
+                unsafe {
+                    Self::begin_pass(device, command_buffer, self.render_pass, probe, 0, face);
+                    device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.capture_pipeline,
+                    );
+                    device.cmd_push_constants(
+                        command_buffer,
+                        self.capture_layout,
+                        vk::ShaderStageFlags::VERTEX,
+                        0,
+                        std::slice::from_raw_parts(
+                            view_proj.as_ptr() as *const u8,
+                            std::mem::size_of_val(&view_proj),
+                        ),
+                    );
+                }
+                draw(self.capture_layout);
+                unsafe { device.cmd_end_render_pass(command_buffer) };
+            }
+
+            // The render pass leaves level 0 in the shader read only layout
+            for level in 1..PROBE_MIP_LEVELS {
+                let roughness = level as f32 / (PROBE_MIP_LEVELS - 1) as f32;
+                for face in 0..6 {
+                    let constants = [face as u32, roughness.to_bits()];
+                    unsafe {
+                        Self::begin_pass(
+                            device,
+                            command_buffer,
+                            self.render_pass,
+                            probe,
+                            level,
+                            face,
+                        );
+                        device.cmd_bind_pipeline(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            self.prefilter_pipeline,
+                        );
+                        device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            self.prefilter_layout,
+                            0,
+                            &[probe.descriptor_set],
+                            &[],
+                        );
+                        device.cmd_push_constants(
+                            command_buffer,
+                            self.prefilter_layout,
+                            vk::ShaderStageFlags::FRAGMENT,
+                            0,
+                            std::slice::from_raw_parts(constants.as_ptr() as *const u8, 8),
+                        );
+                        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+                        device.cmd_end_render_pass(command_buffer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Begins the render pass writing `face` of `level`, with the viewport covering it
+    unsafe fn begin_pass(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        render_pass: vk::RenderPass,
+        probe: &Probe,
+        level: u32,
+        face: usize,
+    ) {
+        let extent = vk::Extent2D {
+            width: PROBE_RESOLUTION >> level,
+            height: PROBE_RESOLUTION >> level,
+        };
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+        let clear_values = [vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 1.0],
+            },
+        }];
+        let render_pass_info = vk::RenderPassBeginInfo {
+            render_pass,
+            framebuffer: probe.framebuffers[level as usize * 6 + face],
+            render_area,
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+            ..Default::default()
+        };
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &render_pass_info,
+            vk::SubpassContents::INLINE,
+        );
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        device.cmd_set_viewport(command_buffer, 0, &viewports);
+        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+    }
+
+    /// Size of the memory of the probe cubemaps
+    pub(crate) fn memory_size(&self, device: &Device, id: ReflectionProbeId) -> u64 {
+        self.probes
+            .iter()
+            .find(|probe| probe.id == id)
+            .map_or(0, |probe| unsafe {
+                device
+                    .get_image_memory_requirements(probe.cubemap.image)
+                    .size
+            })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_view(
+        device: &Device,
+        image: vk::Image,
+        view_type: vk::ImageViewType,
+        base_mip_level: u32,
+        level_count: u32,
+        base_array_layer: u32,
+        layer_count: u32,
+    ) -> AppResult<vk::ImageView> {
+        let create_info = vk::ImageViewCreateInfo {
+            image,
+            view_type,
+            format: PROBE_FORMAT,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level,
+                level_count,
+                base_array_layer,
+                layer_count,
+            },
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_image_view(&create_info, None)?,
+            ))
+        }
+    }
+
+    unsafe fn destroy_probe(device: &Device, probe: Probe) {
+        for (&framebuffer, &view) in probe.framebuffers.iter().zip(&probe.face_views) {
+            handle_registry::unregister(framebuffer);
+            device.destroy_framebuffer(framebuffer, None);
+            handle_registry::unregister(view);
+            device.destroy_image_view(view, None);
+        }
+        for view in [probe.cube_view, probe.capture_view] {
+            handle_registry::unregister(view);
+            device.destroy_image_view(view, None);
+        }
+        handle_registry::unregister(probe.cubemap.image);
+        device.destroy_image(probe.cubemap.image, None);
+        handle_registry::unregister(probe.cubemap.memory);
+        device.free_memory(probe.cubemap.memory, None);
+    }
+
+    /// Destroys the probes and the pipelines, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for probe in self.probes.drain(..) {
+                Self::destroy_probe(device, probe);
+            }
+
+            handle_registry::unregister(self.descriptor_pool);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            handle_registry::unregister(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+            handle_registry::unregister(self.prefilter_pipeline);
+            device.destroy_pipeline(self.prefilter_pipeline, None);
+            handle_registry::unregister(self.prefilter_layout);
+            device.destroy_pipeline_layout(self.prefilter_layout, None);
+            handle_registry::unregister(self.prefilter_set_layout);
+            device.destroy_descriptor_set_layout(self.prefilter_set_layout, None);
+            handle_registry::unregister(self.capture_pipeline);
+            device.destroy_pipeline(self.capture_pipeline, None);
+            handle_registry::unregister(self.capture_layout);
+            device.destroy_pipeline_layout(self.capture_layout, None);
+            handle_registry::unregister(self.render_pass);
+            device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
@@ -0,0 +1,522 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use ash::{vk, Device};
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    AppResult,
+};
+
+/// A resource of a `RenderGraph`, returned when it is added
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ResourceId(usize);
+
+enum Resource {
+    Image {
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+        // Layout the image is in before the first pass using it
+        layout: vk::ImageLayout,
+    },
+    Buffer(vk::Buffer),
+}
+
+/// How a pass reads or writes a resource
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ResourceUse {
+    resource: ResourceId,
+    stage: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+    // Layout the image must be in when the pass begins, `UNDEFINED` when the pass doesn't need
+    // it in a given layout, e.g. when its render pass transitions the image itself
+    layout: vk::ImageLayout,
+    // Layout the pass leaves the image in
+    final_layout: vk::ImageLayout,
+}
+
+impl ResourceUse {
+    const WRITE_ACCESS: vk::AccessFlags = vk::AccessFlags::from_raw(
+        vk::AccessFlags::SHADER_WRITE.as_raw()
+            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE.as_raw()
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+            | vk::AccessFlags::TRANSFER_WRITE.as_raw(),
+    );
+
+    /// Image sampled by the fragment shaders of the pass
+    pub(crate) fn sampled(resource: ResourceId) -> Self {
+        Self {
+            resource,
+            stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            access: vk::AccessFlags::SHADER_READ,
+            layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    /// Color attachment of the render pass of the pass, which leaves it in `final_layout`
+    pub(crate) fn color_attachment(resource: ResourceId, final_layout: vk::ImageLayout) -> Self {
+        Self {
+            resource,
+            stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            layout: vk::ImageLayout::UNDEFINED,
+            final_layout,
+        }
+    }
+
+    /// Depth attachment of the render pass of the pass, which leaves it in `final_layout`
+    pub(crate) fn depth_attachment(resource: ResourceId, final_layout: vk::ImageLayout) -> Self {
+        Self {
+            resource,
+            stage: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            layout: vk::ImageLayout::UNDEFINED,
+            final_layout,
+        }
+    }
+
+    /// Storage buffer read by the shaders of `stage`
+    pub(crate) fn storage_read(resource: ResourceId, stage: vk::PipelineStageFlags) -> Self {
+        Self {
+            resource,
+            stage,
+            access: vk::AccessFlags::SHADER_READ,
+            layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::UNDEFINED,
+        }
+    }
+
+    /// Storage buffer written by the shaders of `stage`
+    pub(crate) fn storage_write(resource: ResourceId, stage: vk::PipelineStageFlags) -> Self {
+        Self {
+            resource,
+            stage,
+            access: vk::AccessFlags::SHADER_WRITE,
+            layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::UNDEFINED,
+        }
+    }
+
+    /// Storage image written by the shaders of `stage`, in the general layout
+    pub(crate) fn storage_image_write(resource: ResourceId, stage: vk::PipelineStageFlags) -> Self {
+        Self {
+            resource,
+            stage,
+            access: vk::AccessFlags::SHADER_WRITE,
+            layout: vk::ImageLayout::GENERAL,
+            final_layout: vk::ImageLayout::GENERAL,
+        }
+    }
+
+    /// Buffer the arguments of the indirect draws of the pass are read from
+    pub(crate) fn indirect_read(resource: ResourceId) -> Self {
+        Self {
+            resource,
+            stage: vk::PipelineStageFlags::DRAW_INDIRECT,
+            access: vk::AccessFlags::INDIRECT_COMMAND_READ,
+            layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::UNDEFINED,
+        }
+    }
+
+    fn writes(&self) -> bool {
+        self.access.intersects(Self::WRITE_ACCESS)
+    }
+}
+
+/// Last accesses of a resource while the steps are compiled
+struct ResourceState {
+    stage: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+    layout: vk::ImageLayout,
+}
+
+/// A pass of a compiled `RenderGraph`, along with the barriers to record before it
+pub(crate) struct GraphStep<P> {
+    pub(crate) pass: P,
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+    image_barriers: Vec<vk::ImageMemoryBarrier<'static>>,
+    buffer_barriers: Vec<vk::BufferMemoryBarrier<'static>>,
+}
+
+impl<P> GraphStep<P> {
+    /// Records the barriers the pass needs, if any
+    pub(crate) fn record_barriers(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        if self.image_barriers.is_empty() && self.buffer_barriers.is_empty() {
+            return;
+        }
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                self.src_stage,
+                self.dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &self.buffer_barriers,
+                &self.image_barriers,
+            );
+        }
+    }
+}
+
+/// The passes of a frame along with the images and buffers they read and write.
+///
+/// The passes are ordered so that every pass writing a resource runs before the ones only
+/// reading it, the passes writing a same resource keeping the order they were added in, and so
+/// do the passes without dependencies between them. The barriers and the layout transitions
+/// between the uses of each resource are derived from that order, a resource being expected to
+/// be fully written before it is read.
+///
+/// `P` identifies the passes, the caller recording each one when iterating over the compiled
+/// steps.
+pub(crate) struct RenderGraph<P> {
+    resources: Vec<Resource>,
+    passes: Vec<(P, Vec<ResourceUse>)>,
+}
+
+impl<P: Copy> RenderGraph<P> {
+    pub(crate) fn new() -> Self {
+        Self {
+            resources: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    /// Adds an image, in `layout` before the first pass using it
+    pub(crate) fn add_image(
+        &mut self,
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+        layout: vk::ImageLayout,
+    ) -> ResourceId {
+        self.resources.push(Resource::Image {
+            image,
+            aspect_mask,
+            layout,
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub(crate) fn add_buffer(&mut self, buffer: vk::Buffer) -> ResourceId {
+        self.resources.push(Resource::Buffer(buffer));
+        ResourceId(self.resources.len() - 1)
+    }
+
+    /// Adds `pass`, using the resources as described by `uses`
+    pub(crate) fn add_pass(&mut self, pass: P, uses: impl IntoIterator<Item = ResourceUse>) {
+        self.passes.push((pass, uses.into_iter().collect()));
+    }
+
+    /// Orders the passes and derives the barriers recorded before each of them. Fails when the
+    /// passes depend on each other in a cycle.
+    pub(crate) fn compile(&self) -> AppResult<Vec<GraphStep<P>>> {
+        let order = self.execution_order()?;
+
+        let mut states: Vec<Option<ResourceState>> = self.resources.iter().map(|_| None).collect();
+        let mut steps = Vec::with_capacity(order.len());
+        for index in order {
+            let (pass, uses) = &self.passes[index];
+            let mut step = GraphStep {
+                pass: *pass,
+                src_stage: vk::PipelineStageFlags::empty(),
+                dst_stage: vk::PipelineStageFlags::empty(),
+                image_barriers: Vec::new(),
+                buffer_barriers: Vec::new(),
+            };
+
+            for resource_use in uses {
+                let resource = &self.resources[resource_use.resource.0];
+                let state = &mut states[resource_use.resource.0];
+                let (src_stage, src_access, old_layout) = match state {
+                    Some(state) => (state.stage, state.access, state.layout),
+                    None => match resource {
+                        Resource::Image { layout, .. } => (
+                            vk::PipelineStageFlags::TOP_OF_PIPE,
+                            vk::AccessFlags::empty(),
+                            *layout,
+                        ),
+                        Resource::Buffer(_) => (
+                            vk::PipelineStageFlags::TOP_OF_PIPE,
+                            vk::AccessFlags::empty(),
+                            vk::ImageLayout::UNDEFINED,
+                        ),
+                    },
+                };
+                let new_layout = match resource_use.layout {
+                    vk::ImageLayout::UNDEFINED => old_layout,
+                    layout => layout,
+                };
+
+                let transitions = old_layout != new_layout;
+                let hazard = state.is_some()
+                    && (resource_use.writes() || src_access.intersects(ResourceUse::WRITE_ACCESS));
+                if transitions || hazard {
+                    step.src_stage |= src_stage;
+                    step.dst_stage |= resource_use.stage;
+                    match *resource {
+                        Resource::Image {
+                            image, aspect_mask, ..
+                        } => step.image_barriers.push(vk::ImageMemoryBarrier {
+                            src_access_mask: src_access,
+                            dst_access_mask: resource_use.access,
+                            old_layout,
+                            new_layout,
+                            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                            image,
+                            subresource_range: vk::ImageSubresourceRange {
+                                aspect_mask,
+                                base_mip_level: 0,
+                                level_count: vk::REMAINING_MIP_LEVELS,
+                                base_array_layer: 0,
+                                layer_count: vk::REMAINING_ARRAY_LAYERS,
+                            },
+                            ..Default::default()
+                        }),
+                        Resource::Buffer(buffer) => {
+                            step.buffer_barriers.push(vk::BufferMemoryBarrier {
+                                src_access_mask: src_access,
+                                dst_access_mask: resource_use.access,
+                                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                                buffer,
+                                offset: 0,
+                                size: vk::WHOLE_SIZE,
+                                ..Default::default()
+                            })
+                        }
+                    }
+                }
+
+                let final_layout = match resource_use.final_layout {
+                    vk::ImageLayout::UNDEFINED => new_layout,
+                    layout => layout,
+                };
+                *state = Some(match state {
+                    // The following reads only need to wait for the last write, the other reads
+                    // are kept for the next write to wait for them
+                    Some(state) if !transitions && !hazard => ResourceState {
+                        stage: state.stage | resource_use.stage,
+                        access: state.access | resource_use.access,
+                        layout: final_layout,
+                    },
+                    _ => ResourceState {
+                        stage: resource_use.stage,
+                        access: resource_use.access,
+                        layout: final_layout,
+                    },
+                });
+            }
+
+            steps.push(step);
+        }
+
+        Ok(steps)
+    }
+
+    /// Indices of the passes in execution order, the passes free to run being taken in the order
+    /// they were added
+    fn execution_order(&self) -> AppResult<Vec<usize>> {
+        let pass_count = self.passes.len();
+        let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); pass_count];
+        let mut dependency_counts = vec![0; pass_count];
+
+        for resource in 0..self.resources.len() {
+            let uses_resource = |uses: &[ResourceUse], writes: bool| {
+                uses.iter()
+                    .any(|u| u.resource.0 == resource && u.writes() == writes)
+            };
+            let writers: Vec<usize> = (0..pass_count)
+                .filter(|&pass| uses_resource(&self.passes[pass].1, true))
+                .collect();
+            let readers = (0..pass_count).filter(|&pass| {
+                uses_resource(&self.passes[pass].1, false) && !writers.contains(&pass)
+            });
+
+            // The writers run in the order they were added, then the readers
+            let edges = writers.windows(2).map(|pair| (pair[0], pair[1])).chain(
+                writers
+                    .last()
+                    .into_iter()
+                    .flat_map(|&last| readers.clone().map(move |reader| (last, reader))),
+            );
+            for (from, to) in edges {
+                dependents[from].push(to);
+                dependency_counts[to] += 1;
+            }
+        }
+
+        let mut ready: BinaryHeap<Reverse<usize>> = (0..pass_count)
+            .filter(|&pass| dependency_counts[pass] == 0)
+            .map(Reverse)
+            .collect();
+        let mut order = Vec::with_capacity(pass_count);
+        while let Some(Reverse(pass)) = ready.pop() {
+            order.push(pass);
+            for &dependent in &dependents[pass] {
+                dependency_counts[dependent] -= 1;
+                if dependency_counts[dependent] == 0 {
+                    ready.push(Reverse(dependent));
+                }
+            }
+        }
+
+        if order.len() != pass_count {
+            return Err(AppError::new(AppErrorType::RenderGraphCycle));
+        }
+
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    fn image(graph: &mut RenderGraph<&'static str>, raw: u64) -> ResourceId {
+        graph.add_image(
+            vk::Image::from_raw(raw),
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+        )
+    }
+
+    fn pass_order(graph: &RenderGraph<&'static str>) -> Vec<&'static str> {
+        graph
+            .execution_order()
+            .unwrap()
+            .into_iter()
+            .map(|index| graph.passes[index].0)
+            .collect()
+    }
+
+    #[test]
+    fn runs_writers_before_readers_in_the_order_they_were_added() {
+        let mut graph = RenderGraph::new();
+        let color = image(&mut graph, 1);
+        let attached = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+        graph.add_pass("read", [ResourceUse::sampled(color)]);
+        graph.add_pass(
+            "first write",
+            [ResourceUse::color_attachment(color, attached)],
+        );
+        graph.add_pass(
+            "second write",
+            [ResourceUse::color_attachment(color, attached)],
+        );
+        graph.add_pass("second read", [ResourceUse::sampled(color)]);
+
+        assert_eq!(
+            pass_order(&graph),
+            ["first write", "second write", "read", "second read"]
+        );
+    }
+
+    #[test]
+    fn keeps_the_independent_passes_in_the_order_they_were_added() {
+        let mut graph = RenderGraph::new();
+        let first = image(&mut graph, 1);
+        let second = image(&mut graph, 2);
+        let layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        graph.add_pass("second", [ResourceUse::color_attachment(second, layout)]);
+        graph.add_pass("first", [ResourceUse::color_attachment(first, layout)]);
+
+        assert_eq!(pass_order(&graph), ["second", "first"]);
+    }
+
+    #[test]
+    fn reports_a_cycle() {
+        let mut graph = RenderGraph::new();
+        let first = image(&mut graph, 1);
+        let second = image(&mut graph, 2);
+        let layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        graph.add_pass(
+            "first",
+            [
+                ResourceUse::color_attachment(first, layout),
+                ResourceUse::sampled(second),
+            ],
+        );
+        graph.add_pass(
+            "second",
+            [
+                ResourceUse::color_attachment(second, layout),
+                ResourceUse::sampled(first),
+            ],
+        );
+
+        let error = graph.execution_order().unwrap_err();
+        assert_eq!(error.error_type, AppErrorType::RenderGraphCycle);
+        let error = graph.compile().err().unwrap();
+        assert_eq!(error.error_type, AppErrorType::RenderGraphCycle);
+    }
+
+    #[test]
+    fn transitions_an_image_read_after_it_is_written() {
+        let mut graph = RenderGraph::new();
+        let color = image(&mut graph, 1);
+        graph.add_pass(
+            "write",
+            [ResourceUse::color_attachment(
+                color,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )],
+        );
+        graph.add_pass("read", [ResourceUse::sampled(color)]);
+
+        let steps = graph.compile().unwrap();
+        // The render pass of the writer transitions the image itself
+        assert!(steps[0].image_barriers.is_empty());
+
+        let read = &steps[1];
+        assert_eq!(read.pass, "read");
+        assert_eq!(
+            read.src_stage,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(read.dst_stage, vk::PipelineStageFlags::FRAGMENT_SHADER);
+        assert!(read.buffer_barriers.is_empty());
+        let [barrier] = read.image_barriers.as_slice() else {
+            panic!("expected a single image barrier");
+        };
+        assert_eq!(barrier.image, vk::Image::from_raw(1));
+        assert_eq!(
+            barrier.old_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(
+            barrier.new_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+        assert_eq!(
+            barrier.src_access_mask,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        );
+        assert_eq!(barrier.dst_access_mask, vk::AccessFlags::SHADER_READ);
+    }
+
+    #[test]
+    fn adds_no_barrier_between_reads() {
+        let mut graph = RenderGraph::new();
+        let color = image(&mut graph, 1);
+        graph.add_pass(
+            "write",
+            [ResourceUse::color_attachment(
+                color,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )],
+        );
+        graph.add_pass("first read", [ResourceUse::sampled(color)]);
+        graph.add_pass("second read", [ResourceUse::sampled(color)]);
+
+        let steps = graph.compile().unwrap();
+        assert_eq!(steps[1].image_barriers.len(), 1);
+        assert!(steps[2].image_barriers.is_empty());
+        assert!(steps[2].buffer_barriers.is_empty());
+    }
+}
@@ -0,0 +1,1466 @@
+use std::time::Instant;
+
+use ash::{vk, Device};
+
+use crate::{
+    debug_hud::SwapchainInfo,
+    descriptor_allocator::DescriptorAllocator,
+    descriptor_writes::{DescriptorWriteBatch, SceneSetData, SceneSetWriter},
+    draw_list::DrawList,
+    handle_registry,
+    outline::OutlineDraw,
+    post::halton,
+    queue_families::QueueFamilyIndice,
+    render_graph::{RenderGraph, ResourceId, ResourceUse},
+    AppResult, Application, DrawItem, FixedUpdateContext, FramePass, Mat4, MaterialHandle,
+    MaterialHolder, MaterialKind, MemoryMappedBuffer, MeshStorage, ModelViewProj, RenderHook,
+    RenderHookContext, RendererEvent, Vec3, Vertex, WorkType, TAA_JITTER_PERIOD,
+};
+
+impl Application {
+    /// Renders and presents a frame, then resets the per-frame input state. Waits first for the
+    /// frame to start when the frame rate is capped, see `set_fps_limit`.
+    pub fn draw_frame(&mut self) -> AppResult<()> {
+        self.frame_limiter.wait();
+        self.reload_changed_assets();
+        #[cfg(feature = "scripting")]
+        let result = self.run_script().and_then(|_| self.render_frame());
+        #[cfg(not(feature = "scripting"))]
+        let result = self.render_frame();
+        self.input.end_frame();
+
+        if let Err(err) = &result {
+            self.event_log.push(RendererEvent::Error {
+                message: err.to_string(),
+            });
+        }
+        result
+    }
+
+    pub(crate) fn render_frame(&mut self) -> AppResult<()> {
+        // Nothing can be presented on a minimized window
+        if self.surface_size.is_empty() {
+            return Ok(());
+        }
+
+        unsafe {
+            self.context.device.wait_for_fences(
+                &[self.frames[self.current_frame].in_flight],
+                true,
+                std::u64::MAX,
+            )?;
+
+            let result = self.swapchain.swapchain_ext.acquire_next_image(
+                self.swapchain.swapchain,
+                std::u64::MAX,
+                self.frames[self.current_frame].image_available,
+                vk::Fence::null(),
+            );
+
+            let image_index = match result {
+                Ok((v, _)) => v,
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    self.recreate_swapchain()?;
+                    return Ok(());
+                }
+                Err(res) => return AppResult::Err(res.into()),
+            };
+
+            let cpu_start = Instant::now();
+            self.frame_guard.begin_writes(
+                &self.context.device,
+                self.current_frame,
+                self.frames[self.current_frame].in_flight,
+            )?;
+            self.debug_hud
+                .read_gpu_time(&self.context.device, self.current_frame);
+            self.context
+                .device
+                .reset_fences(&[self.frames[self.current_frame].in_flight])?;
+            self.resources
+                .garbage_collector
+                .collect(&self.context.device);
+            if let Some(mesh_shading) = &mut self.mesh_shading {
+                mesh_shading.collect_descriptor_sets();
+            }
+            if let Some(morph_targets) = &mut self.morph_targets {
+                morph_targets.collect_descriptor_sets();
+            }
+            self.frame_arena.reset();
+
+            self.context.device.reset_command_buffer(
+                self.frames[self.current_frame].command_buffer,
+                vk::CommandBufferResetFlags::empty(),
+            )?;
+
+            self.update_uniform_buffer();
+            self.stream_textures()?;
+            self.update_virtual_textures()?;
+            self.update_descriptor_materials()?;
+            self.frame_guard
+                .check(self.current_frame, "shader material parameters");
+            self.shader_materials
+                .update(self.current_frame, &mut self.descriptor_writes);
+            self.descriptor_writes.flush(&self.context.device);
+            self.update_dynamic_meshes();
+            self.frame_guard
+                .check(self.current_frame, "debug HUD vertices");
+            self.debug_hud.upload(
+                self.current_frame,
+                &SwapchainInfo {
+                    extent: self.swapchain.extent,
+                    format: self.swapchain.image_format,
+                    present_mode: self.swapchain.present_mode,
+                    image_count: self.swapchain.swapchain_images.len(),
+                },
+            );
+            if let Some(morph_targets) = &self.morph_targets {
+                self.frame_guard.check(self.current_frame, "morph weights");
+                morph_targets.upload(self.current_frame);
+            }
+            self.frame_guard
+                .check(self.current_frame, "particle vertices");
+            self.particles.upload(
+                &self.context.instance,
+                &self.context.device,
+                self.context.physical_device,
+                &mut self.resources.garbage_collector,
+                self.current_frame,
+                self.view_matrix,
+            )?;
+            self.frame_guard.check(self.current_frame, "debug lines");
+            self.debug_draw.upload(
+                &self.context.instance,
+                &self.context.device,
+                self.context.physical_device,
+                &mut self.resources.garbage_collector,
+                self.current_frame,
+            )?;
+            self.frame_guard.check(self.current_frame, "text vertices");
+            self.text_renderer.upload(
+                &self.context.instance,
+                &self.context.device,
+                self.context.physical_device,
+                &mut self.resources.garbage_collector,
+                self.current_frame,
+                self.proj_matrix * self.view_matrix,
+                self.swapchain.extent,
+            )?;
+            #[cfg(feature = "imgui")]
+            if let Some(imgui_renderer) = &mut self.imgui_renderer {
+                self.frame_guard.check(self.current_frame, "ImGui buffers");
+                imgui_renderer.upload(
+                    &self.context.instance,
+                    &self.context.device,
+                    self.context.physical_device,
+                    &mut self.resources.garbage_collector,
+                    self.current_frame,
+                )?;
+            }
+
+            self.create_scene_framebuffers()?;
+
+            let draw_calls = self.record_command_buffer(image_index)?;
+
+            let render_done = self.frames[self.current_frame].render_done;
+            self.scheduler.add(
+                self.context.graphics_queue,
+                WorkType::Main,
+                self.frames[self.current_frame].command_buffer,
+            );
+            self.scheduler.wait(
+                self.context.graphics_queue,
+                self.frames[self.current_frame].image_available,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            );
+            self.scheduler
+                .signal(self.context.graphics_queue, render_done);
+
+            // With exclusive sharing the image must be acquired by the present family first
+            let present_wait = match &self.swapchain.present_transfer {
+                Some(transfer) => transfer.schedule_acquire(
+                    &mut self.scheduler,
+                    self.context.present_queue,
+                    image_index,
+                    render_done,
+                    self.current_frame,
+                ),
+                None => render_done,
+            };
+
+            self.scheduler.flush(
+                &self.context.device,
+                &[(
+                    self.context.graphics_queue,
+                    self.frames[self.current_frame].in_flight,
+                )],
+            )?;
+            self.frame_guard.end_writes();
+            let cpu_time = cpu_start.elapsed();
+            self.debug_hud.end_frame(cpu_time, draw_calls);
+            self.frame_clock.end_frame(cpu_time);
+
+            let present_info = vk::PresentInfoKHR::default()
+                .wait_semaphores(std::slice::from_ref(&present_wait))
+                .swapchains(std::slice::from_ref(&self.swapchain.swapchain))
+                .image_indices(std::slice::from_ref(&image_index));
+
+            let result = self
+                .swapchain
+                .swapchain_ext
+                .queue_present(self.context.present_queue, &present_info);
+
+            if result.is_ok() {
+                self.presented_image = Some(image_index);
+            }
+
+            match result {
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) | Ok(true) | Ok(_) if self.resize_flag => {
+                    self.resize_flag = false;
+                    self.recreate_swapchain()?;
+                    return Ok(());
+                }
+                Err(res) => return AppResult::Err(res.into()),
+                _ => (),
+            };
+        }
+
+        self.current_frame = self.frames.next_index(self.current_frame);
+
+        Ok(())
+    }
+
+    // Pipeline and layout drawing the materials of `kind`, the scene ones until the pipeline of
+    // the kind is created
+    pub(crate) fn material_pipeline(
+        &self,
+        kind: MaterialKind,
+    ) -> (vk::Pipeline, vk::PipelineLayout) {
+        match (
+            kind,
+            &self.virtual_texturing,
+            &self.texture_arrays,
+            &self.pbr_pipeline,
+        ) {
+            (MaterialKind::VirtualTexture(_), Some(virtual_texturing), _, _) => {
+                (virtual_texturing.pipeline(), self.pipeline.pipeline_layout)
+            }
+            (MaterialKind::ArrayLayer(_), _, Some(texture_arrays), _) => {
+                (texture_arrays.pipeline(), texture_arrays.pipeline_layout())
+            }
+            (MaterialKind::Pbr { index, .. }, _, _, Some(pbr_pipeline)) => {
+                (pbr_pipeline.pipeline(index), pbr_pipeline.pipeline_layout())
+            }
+            (MaterialKind::Shader(index), _, _, _) => (
+                self.shader_materials.pipeline(index),
+                self.shader_materials.pipeline_layout(index),
+            ),
+            (MaterialKind::Transparent, _, _, _) => (
+                self.pipeline.transparent_pipeline,
+                self.pipeline.pipeline_layout,
+            ),
+            _ => (self.pipeline.pipeline, self.pipeline.pipeline_layout),
+        }
+    }
+
+    /// Records the command buffer of the current frame, returns the number of draw calls of the
+    /// scene render pass
+    pub(crate) fn record_command_buffer(&mut self, image_index: u32) -> AppResult<u32> {
+        let begin_info = vk::CommandBufferBeginInfo::default();
+
+        unsafe {
+            self.context.device.begin_command_buffer(
+                self.frames[self.current_frame].command_buffer,
+                &begin_info,
+            )?;
+        }
+
+        let command_buffer = self.frames[self.current_frame].command_buffer;
+        self.debug_hud
+            .begin_timing(&self.context.device, command_buffer, self.current_frame);
+        let mut draw_calls = 0;
+        let steps = self.frame_graph(image_index).compile()?;
+
+        let clear_color = vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 1.0],
+            },
+        };
+
+        let offset = vk::Offset2D { x: 0, y: 0 };
+        let render_area = vk::Rect2D {
+            offset,
+            extent: self.swapchain.extent,
+        };
+
+        let clear_values = [clear_color];
+        let render_pass_info = vk::RenderPassBeginInfo {
+            render_pass: self.pipeline.renderpass,
+            framebuffer: self.post_chain.scene_framebuffer(),
+            render_area,
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: &clear_values as *const _,
+            ..Default::default()
+        };
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.swapchain.extent.width as f32,
+            height: self.swapchain.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+
+        let offset = vk::Offset2D { x: 0, y: 0 };
+        let scissors = [vk::Rect2D {
+            offset,
+            extent: self.swapchain.extent,
+        }];
+
+        let (scene_image, scene_view) = self.post_chain.scene_target();
+        let mut hook_context = RenderHookContext {
+            device: &self.context.device,
+            command_buffer,
+            frame_index: self.current_frame,
+            extent: self.swapchain.extent,
+            render_pass: self.pipeline.renderpass,
+            samples: self.pipeline.samples,
+            color_image: scene_image,
+            color_view: scene_view,
+            frame_descriptor_set: self.frames[self.current_frame].descriptor_sets[0],
+            frame_uniform_offset: 0,
+            frame_pipeline_layout: self.pipeline.pipeline_layout,
+        };
+
+        for step in steps {
+            step.record_barriers(&self.context.device, command_buffer);
+
+            match step.pass {
+                FramePass::LightCulling => {
+                    if let Some(pbr_pipeline) = &self.pbr_pipeline {
+                        pbr_pipeline.record_light_culling(
+                            &self.context.device,
+                            command_buffer,
+                            self.current_frame,
+                        );
+                    }
+                }
+                FramePass::Shadows => {
+                    if let Some(pbr_pipeline) = &self.pbr_pipeline {
+                        pbr_pipeline.record_shadows(
+                            &self.context.device,
+                            command_buffer,
+                            &self.pbr_lighting,
+                            |pipeline_layout| {
+                                Self::record_probe_draws(
+                                    &self.context.device,
+                                    command_buffer,
+                                    pipeline_layout,
+                                    &self.draw_list,
+                                    &self.resources.meshes,
+                                    &self.materials,
+                                    &self.frames[self.current_frame].descriptor_sets,
+                                    self.uniform_stride,
+                                    self.current_frame,
+                                )
+                            },
+                        );
+                    }
+                }
+                FramePass::ProbeCaptures => {
+                    if let Some(reflection_probes) = &mut self.reflection_probes {
+                        reflection_probes.record_captures(
+                            &self.context.device,
+                            command_buffer,
+                            |pipeline_layout| {
+                                Self::record_probe_draws(
+                                    &self.context.device,
+                                    command_buffer,
+                                    pipeline_layout,
+                                    &self.draw_list,
+                                    &self.resources.meshes,
+                                    &self.materials,
+                                    &self.frames[self.current_frame].descriptor_sets,
+                                    self.uniform_stride,
+                                    self.current_frame,
+                                )
+                            },
+                        );
+                    }
+                }
+                FramePass::ParticleSimulation => {
+                    if let Some(gpu_particles) = &mut self.gpu_particles {
+                        gpu_particles.record_simulation(&self.context.device, command_buffer);
+                    }
+                }
+                FramePass::AccelerationStructures => {
+                    // Traced by the fragment shaders of the shadows or the ray tracing
+                    let mut dst_stage = vk::PipelineStageFlags::FRAGMENT_SHADER;
+                    if self.active_ray_tracing().is_some() {
+                        dst_stage |= vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR;
+                    }
+                    if let Some(scene_accel) = &mut self.scene_accel {
+                        scene_accel.record_build(
+                            &self.context.device,
+                            command_buffer,
+                            self.current_frame,
+                            dst_stage,
+                        );
+                    }
+                }
+                FramePass::RayTracing => {
+                    if let Some(ray_tracing) = &self.ray_tracing {
+                        ray_tracing.record_trace(
+                            &self.context.device,
+                            command_buffer,
+                            self.current_frame,
+                            self.swapchain.extent,
+                            self.proj_matrix * self.view_matrix,
+                        );
+                    }
+                }
+                FramePass::Prepasses => {
+                    self.post_chain.record_prepasses(
+                        &self.context.device,
+                        command_buffer,
+                        |pipeline_layout| {
+                            Self::record_probe_draws(
+                                &self.context.device,
+                                command_buffer,
+                                pipeline_layout,
+                                &self.draw_list,
+                                &self.resources.meshes,
+                                &self.materials,
+                                &self.frames[self.current_frame].descriptor_sets,
+                                self.uniform_stride,
+                                self.current_frame,
+                            )
+                        },
+                    );
+                }
+                FramePass::Scene => {
+                    unsafe {
+                        self.context.device.cmd_begin_render_pass(
+                            command_buffer,
+                            &render_pass_info,
+                            vk::SubpassContents::INLINE,
+                        );
+
+                        self.context
+                            .device
+                            .cmd_set_viewport(command_buffer, 0, &viewports);
+                        self.context
+                            .device
+                            .cmd_set_scissor(command_buffer, 0, &scissors);
+                    }
+
+                    // Without depth buffer, the sky is kept behind the scene by being drawn first
+                    if let Some(skybox) = &self.skybox {
+                        skybox.record(
+                            &self.context.device,
+                            command_buffer,
+                            self.view_matrix,
+                            self.proj_matrix,
+                        );
+                    }
+
+                    self.render_hooks
+                        .run(RenderHook::BeforeOpaque, &hook_context);
+
+                    draw_calls = self.record_draw_list(command_buffer, false);
+
+                    self.render_hooks
+                        .run(RenderHook::AfterOpaque, &hook_context);
+
+                    draw_calls += self.record_draw_list(command_buffer, true);
+                    if let Some(ray_tracing) = self.active_ray_tracing() {
+                        draw_calls += ray_tracing.record_blend(
+                            &self.context.device,
+                            command_buffer,
+                            self.current_frame,
+                        );
+                    }
+                    draw_calls += self.particles.record(
+                        &self.context.device,
+                        command_buffer,
+                        self.current_frame,
+                        self.proj_matrix * self.view_matrix,
+                    );
+                    if let Some(gpu_particles) = &self.gpu_particles {
+                        draw_calls += gpu_particles.record(
+                            &self.context.device,
+                            command_buffer,
+                            self.view_matrix,
+                            self.proj_matrix * self.view_matrix,
+                        );
+                    }
+
+                    unsafe {
+                        self.context.device.cmd_end_render_pass(command_buffer);
+                    }
+
+                    if let Some(virtual_texturing) = &self.virtual_texturing {
+                        virtual_texturing
+                            .record_feedback_barrier(&self.context.device, command_buffer);
+                    }
+                }
+                FramePass::Terrain => {
+                    if let Some(terrain) = &self.terrain {
+                        draw_calls += terrain.record(
+                            &self.context.device,
+                            command_buffer,
+                            self.swapchain.extent,
+                            self.proj_matrix * self.view_matrix,
+                        );
+                    }
+                }
+                FramePass::Outlines => {
+                    if let Some(outlines) = &self.outlines {
+                        draw_calls += outlines.record(
+                            &self.context.device,
+                            command_buffer,
+                            self.swapchain.extent,
+                            &self.outline_draws(),
+                        );
+                    }
+                }
+                FramePass::PostEffects => {
+                    hook_context.render_pass = vk::RenderPass::null();
+                    hook_context.samples = vk::SampleCountFlags::TYPE_1;
+                    self.render_hooks.run(RenderHook::BeforePost, &hook_context);
+
+                    self.post_chain.record_effects(
+                        &self.context.device,
+                        command_buffer,
+                        self.current_frame,
+                    );
+                }
+                FramePass::Composite => {
+                    self.post_chain.begin_composite(
+                        &self.context.device,
+                        command_buffer,
+                        self.swapchain.frame_buffers[image_index as usize],
+                    );
+
+                    hook_context.render_pass = self.post_chain.composite_render_pass();
+                    hook_context.color_image =
+                        self.swapchain.swapchain_images[image_index as usize];
+                    hook_context.color_view =
+                        self.swapchain.swapchain_image_views[image_index as usize];
+                    self.render_hooks.run(RenderHook::AfterUi, &hook_context);
+                    self.debug_draw.record(
+                        &self.context.device,
+                        command_buffer,
+                        self.swapchain.extent,
+                        self.current_frame,
+                        self.proj_matrix * self.view_matrix,
+                    );
+                    self.text_renderer.record(
+                        &self.context.device,
+                        command_buffer,
+                        self.swapchain.extent,
+                        self.current_frame,
+                    );
+                    self.debug_hud.record(
+                        &self.context.device,
+                        command_buffer,
+                        self.swapchain.extent,
+                        self.current_frame,
+                    );
+                    #[cfg(feature = "imgui")]
+                    if let Some(imgui_renderer) = &self.imgui_renderer {
+                        imgui_renderer.record(
+                            &self.context.device,
+                            command_buffer,
+                            self.swapchain.extent,
+                            self.current_frame,
+                        );
+                    }
+
+                    unsafe {
+                        self.context.device.cmd_end_render_pass(command_buffer);
+                    }
+                }
+            }
+        }
+
+        if let Some(transfer) = &self.swapchain.present_transfer {
+            transfer.record_release(
+                &self.context.device,
+                command_buffer,
+                self.swapchain.swapchain_images[image_index as usize],
+            );
+        }
+
+        self.debug_hud
+            .end_timing(&self.context.device, command_buffer, self.current_frame);
+        unsafe {
+            self.context.device.end_command_buffer(command_buffer)?;
+        }
+
+        Ok(draw_calls)
+    }
+
+    /// Render graph of the frame presented to the swapchain image `image_index`
+    pub(crate) fn frame_graph(&self, image_index: u32) -> RenderGraph<FramePass> {
+        let mut graph = RenderGraph::new();
+
+        let (scene_image, _) = self.post_chain.scene_target();
+        let scene_color = graph.add_image(
+            scene_image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+        );
+        let prepass_targets: Vec<ResourceId> = self
+            .post_chain
+            .prepass_images()
+            .into_iter()
+            .map(|image| {
+                graph.add_image(
+                    image,
+                    vk::ImageAspectFlags::COLOR,
+                    vk::ImageLayout::UNDEFINED,
+                )
+            })
+            .collect();
+        // Without any effect, the composite pass reads the scene target
+        let post_output = match self.post_chain.next_output_image() {
+            Some(image) => graph.add_image(
+                image,
+                vk::ImageAspectFlags::COLOR,
+                vk::ImageLayout::UNDEFINED,
+            ),
+            None => scene_color,
+        };
+        let swapchain_image = graph.add_image(
+            self.swapchain.swapchain_images[image_index as usize],
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+        );
+
+        let mut scene_uses = vec![ResourceUse::color_attachment(
+            scene_color,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )];
+        if let Some(pbr_pipeline) = &self.pbr_pipeline {
+            let clusters = graph.add_buffer(pbr_pipeline.cluster_buffer(self.current_frame));
+            // The shadow cubemaps stay in the layout they are sampled in between the frames
+            let shadow_maps = graph.add_image(
+                pbr_pipeline.shadow_image(),
+                vk::ImageAspectFlags::DEPTH,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+            graph.add_pass(
+                FramePass::LightCulling,
+                [ResourceUse::storage_write(
+                    clusters,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                )],
+            );
+            graph.add_pass(
+                FramePass::Shadows,
+                [ResourceUse::depth_attachment(
+                    shadow_maps,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )],
+            );
+            scene_uses.push(ResourceUse::storage_read(
+                clusters,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ));
+            scene_uses.push(ResourceUse::sampled(shadow_maps));
+        }
+        // The captures synchronize the probe cubemaps themselves, being added before the scene
+        // pass is enough to run ahead of it
+        if self.reflection_probes.is_some() {
+            graph.add_pass(FramePass::ProbeCaptures, []);
+        }
+        // The simulation waits for the draws of the previous frame itself, the particle buffers
+        // being shared by the frames in flight
+        if let Some(gpu_particles) = &self.gpu_particles {
+            let mut simulation_uses = Vec::new();
+            for buffers in gpu_particles.buffers() {
+                let [particles, alive, draw] = buffers.map(|buffer| graph.add_buffer(buffer));
+                simulation_uses.extend([particles, alive, draw].map(|buffer| {
+                    ResourceUse::storage_write(buffer, vk::PipelineStageFlags::COMPUTE_SHADER)
+                }));
+                scene_uses.extend([
+                    ResourceUse::storage_read(particles, vk::PipelineStageFlags::VERTEX_SHADER),
+                    ResourceUse::storage_read(alive, vk::PipelineStageFlags::VERTEX_SHADER),
+                    ResourceUse::indirect_read(draw),
+                ]);
+            }
+            graph.add_pass(FramePass::ParticleSimulation, simulation_uses);
+        }
+        // The build synchronizes the top-level acceleration structure itself, being added before
+        // the passes tracing it is enough to run ahead of them
+        if self.accel_in_use() {
+            graph.add_pass(FramePass::AccelerationStructures, []);
+        }
+        // The ray traced image is sampled by the scene pass, which blends it over the meshes
+        if let Some(output) = self
+            .active_ray_tracing()
+            .and_then(|ray_tracing| ray_tracing.output_image(self.current_frame))
+        {
+            let ray_traced = graph.add_image(
+                output,
+                vk::ImageAspectFlags::COLOR,
+                vk::ImageLayout::UNDEFINED,
+            );
+            graph.add_pass(
+                FramePass::RayTracing,
+                [ResourceUse::storage_image_write(
+                    ray_traced,
+                    vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR,
+                )],
+            );
+            scene_uses.push(ResourceUse::sampled(ray_traced));
+        }
+        graph.add_pass(
+            FramePass::Prepasses,
+            prepass_targets.iter().map(|&target| {
+                ResourceUse::color_attachment(target, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            }),
+        );
+        graph.add_pass(FramePass::Scene, scene_uses);
+        if self.terrain.is_some() {
+            graph.add_pass(
+                FramePass::Terrain,
+                [ResourceUse::color_attachment(
+                    scene_color,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )],
+            );
+        }
+        if self
+            .outlines
+            .as_ref()
+            .is_some_and(|outlines| !outlines.items().is_empty())
+        {
+            graph.add_pass(
+                FramePass::Outlines,
+                [ResourceUse::color_attachment(
+                    scene_color,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )],
+            );
+        }
+
+        let mut post_uses: Vec<ResourceUse> = prepass_targets
+            .iter()
+            .chain([&scene_color])
+            .map(|&target| ResourceUse::sampled(target))
+            .collect();
+        if post_output != scene_color {
+            post_uses.push(ResourceUse::color_attachment(
+                post_output,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ));
+        }
+        graph.add_pass(FramePass::PostEffects, post_uses);
+        graph.add_pass(
+            FramePass::Composite,
+            [
+                ResourceUse::sampled(post_output),
+                ResourceUse::color_attachment(swapchain_image, vk::ImageLayout::PRESENT_SRC_KHR),
+            ],
+        );
+
+        graph
+    }
+
+    /// Records the draws of the draw list with the pipeline of their material, in the scene
+    /// render pass: the opaque items in the order of the list, or the transparent ones from back
+    /// to front. Returns the number of draw calls.
+    pub(crate) fn record_draw_list(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        transparent: bool,
+    ) -> u32 {
+        let items: Vec<_> = self.draw_list.iter().enumerate().collect();
+        let items: Vec<_> = if transparent {
+            self.transparent_order
+                .iter()
+                .map(|&slot| items[slot])
+                .collect()
+        } else {
+            items
+                .into_iter()
+                .filter(|(_, item)| !self.is_transparent(item))
+                .collect()
+        };
+
+        let mut draw_calls = 0;
+        unsafe {
+            let mut bound_pipeline = vk::Pipeline::null();
+            for (slot, item) in items {
+                // The items of a destroyed mesh keep their uniform buffer slot but aren't drawn
+                let Some(mesh) = &self.resources.meshes[item.mesh.0] else {
+                    continue;
+                };
+
+                // Every pipeline shares the scene set, bound as set 0
+                let kind = self.materials[item.material.0].kind;
+                if let Some(mesh_shading) = self.mesh_shading.as_ref().filter(|mesh_shading| {
+                    mesh_shading.enabled()
+                        && !self.wireframe
+                        && matches!(kind, MaterialKind::Scene)
+                        && mesh_shading.has_mesh(item.mesh.0)
+                }) {
+                    if mesh_shading.pipeline() != bound_pipeline {
+                        self.context.device.cmd_bind_pipeline(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            mesh_shading.pipeline(),
+                        );
+                        bound_pipeline = mesh_shading.pipeline();
+                    }
+                    self.context.device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        mesh_shading.pipeline_layout(),
+                        0,
+                        &[self.frames[self.current_frame].descriptor_sets[item.material.0]],
+                        &[Self::uniform_offset(self.uniform_stride, slot)],
+                    );
+                    mesh_shading.record(&self.context.device, command_buffer, item.mesh.0, slot);
+                    draw_calls += 1;
+                    continue;
+                }
+
+                let (vertex_buffer, index_buffer, index_count, index_type) =
+                    mesh.frame_buffers(self.current_frame);
+                if index_count == 0 {
+                    continue;
+                }
+
+                let vertex_buffers = [vertex_buffer];
+                let offsets = [0];
+                self.context.device.cmd_bind_vertex_buffers(
+                    command_buffer,
+                    0,
+                    &vertex_buffers,
+                    &offsets,
+                );
+
+                self.context.device.cmd_bind_index_buffer(
+                    command_buffer,
+                    index_buffer,
+                    0,
+                    index_type,
+                );
+
+                let wireframe_pipeline =
+                    self.pipeline.wireframe_pipeline.filter(|_| self.wireframe);
+                // The morph pipeline draws with the scene material only
+                let morph_targets = self.morph_targets.as_ref().filter(|morph_targets| {
+                    wireframe_pipeline.is_none()
+                        && matches!(kind, MaterialKind::Scene)
+                        && morph_targets.has_mesh(item.mesh.0)
+                });
+                let (pipeline, pipeline_layout) = match (wireframe_pipeline, morph_targets) {
+                    (Some(pipeline), _) => (pipeline, self.pipeline.pipeline_layout),
+                    (None, Some(morph_targets)) => {
+                        (morph_targets.pipeline(), morph_targets.pipeline_layout())
+                    }
+                    (None, None) => self.material_pipeline(kind),
+                };
+                if pipeline != bound_pipeline {
+                    self.context.device.cmd_bind_pipeline(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline,
+                    );
+                    bound_pipeline = pipeline;
+                }
+
+                self.context.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    0,
+                    &[self.frames[self.current_frame].descriptor_sets[item.material.0]],
+                    &[Self::uniform_offset(self.uniform_stride, slot)],
+                );
+                match (
+                    kind,
+                    &self.virtual_texturing,
+                    &self.texture_arrays,
+                    &self.pbr_pipeline,
+                ) {
+                    // The wireframe pipeline only reads the scene set
+                    _ if wireframe_pipeline.is_some() => (),
+                    (MaterialKind::VirtualTexture(index), Some(virtual_texturing), _, _) => {
+                        self.context.device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            virtual_texturing.pipeline_layout(),
+                            1,
+                            &[virtual_texturing.descriptor_set(index, self.current_frame)],
+                            &[],
+                        );
+                    }
+                    (MaterialKind::ArrayLayer(layer), _, Some(texture_arrays), _) => {
+                        texture_arrays.record_layer(&self.context.device, command_buffer, layer);
+                    }
+                    (MaterialKind::Pbr { index, .. }, _, _, Some(pbr_pipeline)) => {
+                        pbr_pipeline.record_material(
+                            &self.context.device,
+                            command_buffer,
+                            index,
+                            self.current_frame,
+                        );
+                    }
+                    (MaterialKind::Shader(index), _, _, _) => {
+                        self.shader_materials.record(
+                            &self.context.device,
+                            command_buffer,
+                            index,
+                            self.current_frame,
+                        );
+                    }
+                    _ => (),
+                }
+                if let Some(morph_targets) = morph_targets {
+                    morph_targets.record(
+                        &self.context.device,
+                        command_buffer,
+                        item.mesh.0,
+                        self.current_frame,
+                    );
+                }
+
+                self.context
+                    .device
+                    .cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+                draw_calls += 1;
+            }
+        }
+        draw_calls
+    }
+
+    /// Records the draw list as seen by a reflection probe, which doesn't need the virtual
+    /// textures. The items sampling a texture array are left out, as the capture pipeline
+    /// samples a 2D texture, along with the transparent items which write no depth.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn record_probe_draws(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        draw_list: &DrawList,
+        meshes: &[Option<MeshStorage>],
+        materials: &[MaterialHolder],
+        descriptor_sets: &[vk::DescriptorSet],
+        uniform_stride: u64,
+        frame: usize,
+    ) {
+        for (slot, item) in draw_list.iter().enumerate() {
+            let Some(mesh) = &meshes[item.mesh.0] else {
+                continue;
+            };
+            let kind = materials[item.material.0].kind;
+            if matches!(kind, MaterialKind::ArrayLayer(_)) || kind.is_transparent() {
+                continue;
+            }
+
+            let (vertex_buffer, index_buffer, index_count, index_type) = mesh.frame_buffers(frame);
+            if index_count == 0 {
+                continue;
+            }
+
+            unsafe {
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+                device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, index_type);
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    0,
+                    &[descriptor_sets[item.material.0]],
+                    &[Self::uniform_offset(uniform_stride, slot)],
+                );
+                device.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+            }
+        }
+    }
+
+    /// Creates the framebuffers of the passes drawing over the scene target with an attachment of
+    /// their own, unless they already exist
+    pub(crate) fn create_scene_framebuffers(&mut self) -> AppResult<()> {
+        let (_, scene_view) = self.post_chain.scene_target();
+        if let Some(outlines) = &mut self.outlines {
+            outlines.create_target(
+                &self.context.instance,
+                &self.context.device,
+                self.context.physical_device,
+                scene_view,
+                self.swapchain.extent,
+            )?;
+        }
+        if let Some(terrain) = &mut self.terrain {
+            terrain.create_target(
+                &self.context.instance,
+                &self.context.device,
+                self.context.physical_device,
+                scene_view,
+                self.swapchain.extent,
+            )?;
+        }
+        if let Some(ray_tracing) = &mut self.ray_tracing {
+            ray_tracing.create_target(
+                &self.context.instance,
+                &self.context.device,
+                self.context.physical_device,
+                self.swapchain.extent,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Destroys the framebuffers binding the scene target before the post chain recreates it,
+    /// the device must be idle
+    pub(crate) fn destroy_scene_framebuffers(&mut self) {
+        if let Some(outlines) = &mut self.outlines {
+            outlines.destroy_target(&self.context.device);
+        }
+        if let Some(terrain) = &mut self.terrain {
+            terrain.destroy_target(&self.context.device);
+        }
+        if let Some(ray_tracing) = &mut self.ray_tracing {
+            ray_tracing.destroy_target(&self.context.device);
+        }
+    }
+
+    /// Geometry and scene set of the outlined items, leaving out the removed items and the items
+    /// of a destroyed mesh
+    pub(crate) fn outline_draws(&self) -> Vec<OutlineDraw> {
+        let Some(outlines) = &self.outlines else {
+            return Vec::new();
+        };
+
+        outlines
+            .items()
+            .iter()
+            .filter_map(|&(id, color)| {
+                let slot = self.draw_list.slot(id)?;
+                let item = self.draw_list.get(id)?;
+                let mesh = self.resources.meshes[item.mesh.0].as_ref()?;
+                let (vertex_buffer, index_buffer, index_count, index_type) =
+                    mesh.frame_buffers(self.current_frame);
+                Some(OutlineDraw {
+                    vertex_buffer,
+                    index_buffer,
+                    index_count,
+                    index_type,
+                    descriptor_set: self.frames[self.current_frame].descriptor_sets
+                        [item.material.0],
+                    uniform_offset: Self::uniform_offset(self.uniform_stride, slot),
+                    color,
+                })
+            })
+            .filter(|draw| draw.index_count > 0)
+            .collect()
+    }
+
+    pub(crate) fn is_transparent(&self, item: &DrawItem) -> bool {
+        self.materials[item.material.0].kind.is_transparent()
+    }
+
+    /// Projection of the camera rendering to a target of `extent`
+    pub(crate) fn camera_projection(extent: vk::Extent2D) -> Mat4 {
+        let aspect_ratio = extent.width as f32 / extent.height as f32;
+        cgmath::perspective(cgmath::Deg(45.0), aspect_ratio, 0.1, 10.0)
+    }
+
+    pub(crate) fn update_uniform_buffer(&mut self) {
+        let delta_time = self.frame_clock.start_frame();
+        // The camera follows the real time, the scene the scaled one
+        let scene_delta_time = delta_time * self.time_scale;
+
+        if !self.paused {
+            let tick = self.fixed_timestep.tick();
+            for _ in 0..self.fixed_timestep.advance(scene_delta_time) {
+                self.previous_animation_time = self.animation_time;
+                self.animation_time += tick;
+                self.particles.update(tick);
+                if let Some(fixed_update) = &mut self.fixed_update {
+                    fixed_update(&FixedUpdateContext {
+                        tick,
+                        time: self.animation_time,
+                    });
+                }
+            }
+            // Sampled rather than simulated, they follow the frames
+            self.animations.update(
+                scene_delta_time,
+                &mut self.draw_list,
+                self.morph_targets.as_mut(),
+            );
+        }
+        // Still simulated while paused to list the live particles, without moving them
+        if let Some(gpu_particles) = &mut self.gpu_particles {
+            gpu_particles.update(if self.paused { 0.0 } else { scene_delta_time });
+        }
+
+        let render_time = self.previous_animation_time
+            + (self.animation_time - self.previous_animation_time) * self.fixed_timestep.alpha();
+        // Rotates the scene 90 degres every 4 seconds, unless the caller transforms it
+        let scene_transform = self.scene_transform.unwrap_or_else(|| {
+            Mat4::from_angle_z(cgmath::Rad(std::f32::consts::PI / 8.0) * render_time)
+        });
+
+        self.camera.update(&self.input, delta_time);
+        let view = self
+            .view_override
+            .unwrap_or_else(|| self.camera.view_matrix());
+
+        let proj = Self::camera_projection(self.swapchain.extent);
+        let view_proj = proj * view;
+        // The temporal anti-aliasing needs a different sub-pixel offset every frame to gather
+        // the coverage of several samples per pixel
+        let jittered_proj = if self.taa_enabled() {
+            self.jitter_index = (self.jitter_index + 1) % TAA_JITTER_PERIOD;
+            let extent = self.swapchain.extent;
+            let jitter_x = halton(self.jitter_index + 1, 2) - 0.5;
+            let jitter_y = halton(self.jitter_index + 1, 3) - 0.5;
+            Mat4::from_translation(Vec3::new(
+                2.0 * jitter_x / extent.width as f32,
+                2.0 * jitter_y / extent.height as f32,
+                0.0,
+            )) * proj
+        } else {
+            proj
+        };
+
+        self.view_matrix = view;
+        self.proj_matrix = proj;
+        if let Some(item) = self.draw_list.get(self.mesh_item_id) {
+            self.pickables
+                .set_transform(self.mesh_pick_id, scene_transform * item.transform);
+        }
+
+        self.frame_guard.check(self.current_frame, "uniform buffer");
+        let memory_map = self.frames[self.current_frame].uniform_buffer.memory_map;
+        // The slots new to the draw list have no motion in their first frame
+        self.previous_model_view_projs
+            .truncate(self.draw_list.len());
+        let mut transparent_depths = Vec::new();
+        for (slot, item) in self.draw_list.iter().enumerate() {
+            let model = scene_transform * item.transform;
+            let model_view_proj = view_proj * model;
+            if self.is_transparent(item) {
+                // View space depth of the item origin, the camera looking down -Z
+                transparent_depths.push(((view * model).w.z, slot));
+            }
+            if slot == self.previous_model_view_projs.len() {
+                self.previous_model_view_projs.push(model_view_proj);
+            }
+            let ubo = ModelViewProj::new(
+                model,
+                view,
+                jittered_proj,
+                model_view_proj,
+                self.previous_model_view_projs[slot],
+            );
+            self.previous_model_view_projs[slot] = model_view_proj;
+
+            let src_ptr = &ubo as *const ModelViewProj;
+            unsafe {
+                let dst_ptr = memory_map.byte_add(slot * self.uniform_stride as usize);
+                std::ptr::copy(src_ptr, dst_ptr as *mut ModelViewProj, 1);
+            }
+        }
+        if let Some(mesh_shading) = &mut self.mesh_shading {
+            let jittered_view_proj = jittered_proj * view;
+            mesh_shading.set_model_view_projs(
+                self.draw_list
+                    .iter()
+                    .map(|item| jittered_view_proj * scene_transform * item.transform)
+                    .collect(),
+            );
+        }
+        if let Some(scene_accel) = &mut self.scene_accel {
+            self.frame_guard
+                .check(self.current_frame, "ray tracing instances");
+            scene_accel.write_instances(
+                self.current_frame,
+                self.draw_list
+                    .iter()
+                    .map(|item| (item.mesh.0, scene_transform * item.transform)),
+            );
+        }
+        transparent_depths.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        self.transparent_order = transparent_depths
+            .into_iter()
+            .map(|(_, slot)| slot)
+            .collect();
+        if let Some(pbr_pipeline) = &self.pbr_pipeline {
+            self.frame_guard.check(self.current_frame, "PBR lighting");
+            pbr_pipeline.write_lighting(
+                self.current_frame,
+                &self.pbr_lighting,
+                view,
+                proj,
+                self.swapchain.extent,
+            );
+        }
+    }
+
+    /// Copies the latest data of the dynamic meshes to the buffers of the current frame, the frame
+    /// must not be in use by the GPU
+    pub(crate) fn update_dynamic_meshes(&mut self) {
+        for (index, mesh) in self.resources.meshes.iter_mut().enumerate() {
+            let Some(MeshStorage::Dynamic(mesh)) = mesh else {
+                continue;
+            };
+            if !mesh.stale_frames[self.current_frame] {
+                continue;
+            }
+
+            self.frame_guard.check(self.current_frame, "dynamic mesh");
+            unsafe {
+                std::ptr::copy(
+                    mesh.vertices.as_ptr(),
+                    mesh.vertex_buffers[self.current_frame].memory_map as *mut Vertex,
+                    mesh.vertices.len(),
+                );
+                std::ptr::copy(
+                    mesh.indices.as_ptr(),
+                    mesh.index_buffers[self.current_frame].memory_map as *mut u32,
+                    mesh.indices.len(),
+                );
+            }
+            mesh.index_counts[self.current_frame] = mesh.indices.len() as u32;
+            mesh.stale_frames[self.current_frame] = false;
+            if let Some(scene_accel) = &mut self.scene_accel {
+                scene_accel.update_dynamic_mesh(
+                    self.current_frame,
+                    index,
+                    (mesh.indices.len() / 3) as u32,
+                );
+            }
+        }
+    }
+
+    /// Uploads the next mip levels of the streamed textures along with the current frame, the
+    /// frame must not be in use by the GPU
+    pub(crate) fn stream_textures(&mut self) -> AppResult<()> {
+        let update = self.resources.texture_streamer.update(
+            &self.context.instance,
+            &self.context.device,
+            self.context.physical_device,
+            self.current_frame,
+        )?;
+
+        if let Some(command_buffer) = update.command_buffer {
+            self.scheduler.add(
+                self.context.graphics_queue,
+                WorkType::Upload,
+                command_buffer,
+            );
+        }
+
+        for (material, view) in update.refined {
+            self.materials[material.0].texture_view = view;
+            for written_material in self
+                .frames
+                .iter_mut()
+                .flat_map(|frame| frame.descriptor_materials.iter_mut())
+            {
+                if *written_material == Some(material) {
+                    *written_material = None;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Requests the pages of the virtual textures sampled by the previous use of the current frame,
+    /// and uploads the loaded ones along with it
+    pub(crate) fn update_virtual_textures(&mut self) -> AppResult<()> {
+        let Some(virtual_texturing) = &mut self.virtual_texturing else {
+            return Ok(());
+        };
+
+        let update = virtual_texturing.update(
+            &self.context.device,
+            self.context.graphics_queue,
+            self.current_frame,
+            &self.frame_arena,
+        )?;
+        if let Some(command_buffer) = update.command_buffer {
+            self.scheduler.add(
+                self.context.graphics_queue,
+                WorkType::Upload,
+                command_buffer,
+            );
+        }
+        if let Some(bind_semaphore) = update.bind_semaphore {
+            self.scheduler.wait(
+                self.context.graphics_queue,
+                bind_semaphore,
+                vk::PipelineStageFlags::TRANSFER,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Creates the descriptor sets of the materials new to the current frame, and points the sets
+    /// of the drawn materials to their texture. The frame must not be in use by the GPU.
+    pub(crate) fn update_descriptor_materials(&mut self) -> AppResult<()> {
+        let frame = &mut self.frames[self.current_frame];
+        let created = frame.descriptor_sets.len();
+        if created < self.materials.len() {
+            // Written below once their material is drawn
+            let descriptor_sets = self.descriptor_allocator.allocate(
+                &self.context.device,
+                self.pipeline.descriptor_set_layout,
+                (self.materials.len() - created) as u32,
+            )?;
+            frame.descriptor_sets.extend(descriptor_sets);
+            frame
+                .descriptor_materials
+                .resize(self.materials.len(), None);
+        }
+
+        // The set of the first material is the one given to the render hooks
+        let mut drawn = vec![MaterialHandle(0)];
+        drawn.extend(self.draw_list.iter().map(|item| item.material));
+        drawn.sort_by_key(|material| material.0);
+        drawn.dedup();
+        self.descriptor_allocator
+            .record_usage((drawn.len() * self.frames_in_flight) as u32);
+
+        for material in drawn {
+            let written_material = &mut frame.descriptor_materials[material.0];
+            if *written_material == Some(material) {
+                continue;
+            }
+            *written_material = Some(material);
+            self.frame_guard.check(self.current_frame, "descriptor set");
+
+            self.scene_set_writer.write(
+                &self.context.device,
+                &mut self.descriptor_writes,
+                frame.descriptor_sets[material.0],
+                &SceneSetData {
+                    uniform_buffer: Self::uniform_buffer_info(&frame.uniform_buffer),
+                    images: self.materials[material.0].image_infos(),
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn create_command_pool(
+        device: &Device,
+        queue_families: QueueFamilyIndice,
+    ) -> AppResult<vk::CommandPool> {
+        let pool_info = vk::CommandPoolCreateInfo {
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            queue_family_index: queue_families.graphics_family.unwrap(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_command_pool(&pool_info, None)?,
+            ))
+        }
+    }
+
+    pub(crate) fn create_command_buffers(
+        device: &Device,
+        command_pool: vk::CommandPool,
+        max_frame_in_flight: u32,
+    ) -> AppResult<Vec<vk::CommandBuffer>> {
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: max_frame_in_flight,
+            ..Default::default()
+        };
+        unsafe { Ok(device.allocate_command_buffers(&alloc_info)?) }
+    }
+
+    pub(crate) fn create_sync_objects(
+        device: &Device,
+        max_frame_in_flight: u32,
+    ) -> AppResult<(Vec<vk::Semaphore>, Vec<vk::Semaphore>, Vec<vk::Fence>)> {
+        let semaphore_info = vk::SemaphoreCreateInfo::default();
+        let fence_info = vk::FenceCreateInfo {
+            flags: vk::FenceCreateFlags::SIGNALED,
+            ..Default::default()
+        };
+        let mut image_avaible_semaphores = vec![];
+        let mut render_done_semaphores = vec![];
+        let mut in_flight_fences = vec![];
+
+        for _ in 0..max_frame_in_flight {
+            unsafe {
+                let semaphore = device.create_semaphore(&semaphore_info, None)?;
+                image_avaible_semaphores.push(handle_registry::register(semaphore));
+
+                let semaphore = device.create_semaphore(&semaphore_info, None)?;
+                render_done_semaphores.push(handle_registry::register(semaphore));
+
+                let fence = device.create_fence(&fence_info, None)?;
+                in_flight_fences.push(handle_registry::register(fence));
+            }
+        }
+
+        Ok((
+            image_avaible_semaphores,
+            render_done_semaphores,
+            in_flight_fences,
+        ))
+    }
+
+    /// Creates the descriptor sets of `materials`, reading `uniform_buffer` at the dynamic offset
+    /// of the drawn slot. The sets may only be written once `descriptor_writes` is flushed.
+    pub(crate) fn create_descriptor_sets(
+        device: &Device,
+        scene_set_writer: &SceneSetWriter,
+        descriptor_writes: &mut DescriptorWriteBatch,
+        uniform_buffer: &MemoryMappedBuffer,
+        materials: &[MaterialHolder],
+        descriptor_set_layout: vk::DescriptorSetLayout,
+        descriptor_allocator: &mut DescriptorAllocator,
+    ) -> AppResult<Vec<vk::DescriptorSet>> {
+        let descriptor_sets =
+            descriptor_allocator.allocate(device, descriptor_set_layout, materials.len() as u32)?;
+        for (material, &desc_set) in materials.iter().zip(descriptor_sets.iter()) {
+            scene_set_writer.write(
+                device,
+                descriptor_writes,
+                desc_set,
+                &SceneSetData {
+                    uniform_buffer: Self::uniform_buffer_info(uniform_buffer),
+                    images: material.image_infos(),
+                },
+            );
+        }
+
+        Ok(descriptor_sets)
+    }
+
+    // Points a set to the first slot of `uniform_buffer`, the slot drawn being selected by the
+    // dynamic offset the set is bound with
+    fn uniform_buffer_info(uniform_buffer: &MemoryMappedBuffer) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: uniform_buffer.buffer,
+            offset: 0,
+            range: std::mem::size_of::<ModelViewProj>() as u64,
+        }
+    }
+
+    /// Dynamic offset of the uniform buffer slot of a draw item
+    pub(crate) fn uniform_offset(uniform_stride: u64, slot: usize) -> u32 {
+        (slot as u64 * uniform_stride) as u32
+    }
+}
@@ -0,0 +1,937 @@
+use std::ffi::c_void;
+
+use ash::{vk, Device, Instance};
+use image::RgbaImage;
+
+use crate::{
+    context::Context,
+    creation_guard::DeviceObjectGuard,
+    garbage_collector::GarbageCollector,
+    handle_registry,
+    sampler_cache::SamplerCache,
+    texture_loader::{self, DecodedTexture},
+    texture_manager::TextureManager,
+    texture_stream::TextureStreamer,
+    AppError, AppErrorType, AppResult, Application, BufferHolder, ErrorContext, ImageHolder,
+    MemoryMappedBuffer, MeshStorage, ModelViewProj, SyncPool, TextureHolder, Vertex,
+    PLACEHOLDER_CHECKER, PLACEHOLDER_SIZE,
+};
+
+/// Command pool the uploads and the frames are recorded from, along with the meshes, the textures
+/// and the samplers of the scene
+pub(crate) struct Resources {
+    pub command_pool: vk::CommandPool,
+    pub sync_pool: SyncPool,
+    // Destroyed meshes leave a hole so the handles of the others stay valid
+    pub meshes: Vec<Option<MeshStorage>>,
+    pub texture_manager: TextureManager,
+    pub garbage_collector: GarbageCollector,
+    pub texture_streamer: TextureStreamer,
+    // Sampled by the default material and by the materials whose texture isn't available
+    pub placeholder_texture: TextureHolder,
+    // Multiplies the texture of the materials which aren't baked
+    pub white_lightmap: TextureHolder,
+    pub sampler_cache: SamplerCache,
+}
+
+impl Resources {
+    /// Creates the command pool and uploads the placeholder textures, without any mesh
+    pub(crate) fn new(context: &Context, frames_in_flight: usize) -> AppResult<Self> {
+        let device = &context.device;
+
+        // The command buffers are freed along with their pool
+        let command_pool = Application::create_command_pool(device, context.queue_family_indices)
+            .context("creating the command pool")?;
+        let command_pool = DeviceObjectGuard::new(device, command_pool, |command_pool, device| {
+            handle_registry::unregister(*command_pool);
+            unsafe { device.destroy_command_pool(*command_pool, None) };
+        });
+        let sync_pool = DeviceObjectGuard::new(device, SyncPool::default(), |sync_pool, device| {
+            sync_pool.destroy(device)
+        });
+        let texture_streamer = TextureStreamer::new(device, *command_pool, frames_in_flight as u32)
+            .context("creating the texture streamer")?;
+        let texture_streamer =
+            DeviceObjectGuard::new(device, texture_streamer, TextureStreamer::destroy);
+
+        let checker = RgbaImage::from_fn(PLACEHOLDER_SIZE, PLACEHOLDER_SIZE, |x, y| {
+            if (x / PLACEHOLDER_CHECKER + y / PLACEHOLDER_CHECKER).is_multiple_of(2) {
+                image::Rgba([160, 160, 160, 255])
+            } else {
+                image::Rgba([96, 96, 96, 255])
+            }
+        });
+        let mut textures = Application::upload_textures(
+            &context.instance,
+            device,
+            context.graphics_queue,
+            context.physical_device,
+            *command_pool,
+            &sync_pool,
+            &mut [
+                DecodedTexture::from_rgba(vec![checker]),
+                DecodedTexture::from_rgba(vec![RgbaImage::from_pixel(1, 1, image::Rgba([255; 4]))]),
+            ],
+        )
+        .context("uploading the placeholder textures")?;
+        let white_lightmap = textures.pop().unwrap();
+        let placeholder_texture = textures.pop().unwrap();
+
+        let limits = unsafe {
+            context
+                .instance
+                .get_physical_device_properties(context.physical_device)
+                .limits
+        };
+        Ok(Self {
+            command_pool: command_pool.release(),
+            sync_pool: sync_pool.release(),
+            meshes: Vec::new(),
+            texture_manager: TextureManager::default(),
+            garbage_collector: GarbageCollector::default(),
+            texture_streamer: texture_streamer.release(),
+            placeholder_texture,
+            white_lightmap,
+            sampler_cache: SamplerCache::new(&limits),
+        })
+    }
+
+    /// Destroys the meshes, the textures, the samplers and the command pool, the device must be
+    /// idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for mesh in self.meshes.iter().flatten() {
+                mesh.destroy(device);
+            }
+            self.texture_manager.destroy(device);
+            self.garbage_collector.destroy(device);
+            self.texture_streamer.destroy(device);
+
+            self.white_lightmap.destroy(device);
+            self.placeholder_texture.destroy(device);
+            self.sampler_cache.destroy(device);
+
+            handle_registry::unregister(self.command_pool);
+            device.destroy_command_pool(self.command_pool, None);
+            self.sync_pool.destroy(device);
+        }
+    }
+}
+
+impl Application {
+    pub(crate) fn create_image_view(
+        device: &Device,
+        image: vk::Image,
+        format: vk::Format,
+        mip_levels: u32,
+    ) -> AppResult<vk::ImageView> {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        let create_info = vk::ImageViewCreateInfo {
+            flags: vk::ImageViewCreateFlags::empty(),
+            image,
+            view_type: vk::ImageViewType::TYPE_2D,
+            format,
+            subresource_range,
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_image_view(&create_info, None)?,
+            ))
+        }
+    }
+
+    pub(crate) fn create_vertex_buffer(
+        instance: &Instance,
+        device: &Device,
+        graphic_queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        vertex_data: &[Vertex],
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+    ) -> AppResult<BufferHolder> {
+        let vertex_buffer_usage =
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER;
+        let vertex_buffer_mem_proprieties = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+        Self::create_buffer_with_data(
+            instance,
+            device,
+            graphic_queue,
+            physical_device,
+            vertex_data,
+            vertex_buffer_usage,
+            vertex_buffer_mem_proprieties,
+            command_pool,
+            sync_pool,
+        )
+    }
+
+    pub(crate) fn create_index_buffer<T>(
+        instance: &Instance,
+        device: &Device,
+        graphic_queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        index_data: &[T],
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+    ) -> AppResult<BufferHolder> {
+        let index_buffer_usage =
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER;
+        let index_buffer_mem_proprieties = vk::MemoryPropertyFlags::DEVICE_LOCAL;
+        Self::create_buffer_with_data(
+            instance,
+            device,
+            graphic_queue,
+            physical_device,
+            index_data,
+            index_buffer_usage,
+            index_buffer_mem_proprieties,
+            command_pool,
+            sync_pool,
+        )
+    }
+
+    pub(crate) fn create_uniform_buffers(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        buffer_size: u64,
+        max_frame_in_flight: usize,
+    ) -> AppResult<Vec<MemoryMappedBuffer>> {
+        Self::create_host_visible_buffers(
+            instance,
+            device,
+            physical_device,
+            buffer_size,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            max_frame_in_flight,
+        )
+    }
+
+    /// Creates `count` persistently mapped buffers, e.g. one per frame in flight
+    pub(crate) fn create_host_visible_buffers(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        buffer_size: u64,
+        buffer_usage: vk::BufferUsageFlags,
+        count: usize,
+    ) -> AppResult<Vec<MemoryMappedBuffer>> {
+        let buffer_mem_proprieties =
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+
+        // The buffers already created are destroyed if a later one fails to be created
+        let mut buffers = DeviceObjectGuard::new(device, Vec::new(), |buffers, device| {
+            for buffer in buffers.iter() {
+                unsafe { MemoryMappedBuffer::destroy(buffer, device) };
+            }
+        });
+        for _ in 0..count {
+            let buffer = Self::create_buffer(
+                instance,
+                device,
+                physical_device,
+                buffer_size,
+                buffer_usage,
+                buffer_mem_proprieties,
+            )?;
+            let buffer = DeviceObjectGuard::new(device, buffer, |buffer, device| unsafe {
+                buffer.destroy(device)
+            });
+
+            let buffer_memory_map = unsafe {
+                device.map_memory(buffer.memory, 0, buffer_size, vk::MemoryMapFlags::empty())?
+            };
+
+            let buffer = buffer.release();
+            buffers.push(MemoryMappedBuffer::new(
+                buffer.buffer,
+                buffer.memory,
+                buffer_memory_map,
+            ));
+        }
+
+        Ok(buffers.release())
+    }
+
+    /// Returns the distance between two slots of a uniform buffer, so each slot can be bound at
+    /// its own offset
+    pub(crate) fn uniform_stride(instance: &Instance, physical_device: vk::PhysicalDevice) -> u64 {
+        let alignment = unsafe {
+            instance
+                .get_physical_device_properties(physical_device)
+                .limits
+                .min_uniform_buffer_offset_alignment
+        };
+
+        (std::mem::size_of::<ModelViewProj>() as u64).next_multiple_of(alignment)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[tracing::instrument(skip_all, fields(size = std::mem::size_of_val(data)))]
+    pub(crate) fn create_buffer_with_data<T>(
+        instance: &Instance,
+        device: &Device,
+        graphic_queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        data: &[T],
+        buffer_usage: vk::BufferUsageFlags,
+        buffer_mem_proprieties: vk::MemoryPropertyFlags,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+    ) -> AppResult<BufferHolder> {
+        let buffer_size = std::mem::size_of_val(data) as u64;
+
+        let staging_buffer_mem_proprieties =
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE;
+        let staging_buffer = Self::create_buffer(
+            instance,
+            device,
+            physical_device,
+            buffer_size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            staging_buffer_mem_proprieties,
+        )?;
+
+        unsafe {
+            let data_src = data.as_ptr() as *const c_void;
+            let data_dst = device.map_memory(
+                staging_buffer.memory,
+                0,
+                buffer_size,
+                vk::MemoryMapFlags::empty(),
+            )?;
+            std::ptr::copy(data_src, data_dst, buffer_size as usize);
+            device.unmap_memory(staging_buffer.memory);
+        };
+
+        let buffer = Self::create_buffer(
+            instance,
+            device,
+            physical_device,
+            buffer_size,
+            buffer_usage,
+            buffer_mem_proprieties,
+        )?;
+
+        Self::copy_buffer(
+            device,
+            graphic_queue,
+            staging_buffer.buffer,
+            buffer.buffer,
+            buffer_size,
+            command_pool,
+            sync_pool,
+        )?;
+
+        unsafe {
+            handle_registry::unregister(staging_buffer.buffer);
+            device.destroy_buffer(staging_buffer.buffer, None);
+            handle_registry::unregister(staging_buffer.memory);
+            device.free_memory(staging_buffer.memory, None);
+        }
+
+        Ok(buffer)
+    }
+
+    pub(crate) fn create_buffer(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        mem_proprieties: vk::MemoryPropertyFlags,
+    ) -> AppResult<BufferHolder> {
+        let buffer_info = vk::BufferCreateInfo {
+            size,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+
+        let buffer = unsafe { device.create_buffer(&buffer_info, None)? };
+        handle_registry::register(buffer);
+
+        let mem_requirement = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let mem_type_index = Self::find_memory_type(
+            instance,
+            physical_device,
+            mem_requirement.memory_type_bits,
+            mem_proprieties,
+        )?;
+
+        let mut alloc_info = vk::MemoryAllocateInfo {
+            allocation_size: mem_requirement.size,
+            memory_type_index: mem_type_index,
+            ..Default::default()
+        };
+        // A buffer read through its device address needs memory allocated for it
+        let mut alloc_flags = vk::MemoryAllocateFlagsInfo {
+            flags: vk::MemoryAllocateFlags::DEVICE_ADDRESS,
+            ..Default::default()
+        };
+        if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+            alloc_info = alloc_info.push_next(&mut alloc_flags);
+        }
+        let buffer_memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+        handle_registry::register(buffer_memory);
+        unsafe {
+            device.bind_buffer_memory(buffer, buffer_memory, 0)?;
+        }
+
+        Ok(BufferHolder::new(buffer, buffer_memory))
+    }
+
+    pub(crate) fn copy_buffer(
+        device: &Device,
+        queue: vk::Queue,
+        src_buffer: vk::Buffer,
+        dst_buffer: vk::Buffer,
+        size: vk::DeviceSize,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+    ) -> AppResult<()> {
+        let command_buffer = Self::begin_singe_time_command(device, command_pool)?;
+
+        unsafe {
+            let copy_regions = [vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: 0,
+                size,
+            }];
+            device.cmd_copy_buffer(command_buffer, src_buffer, dst_buffer, &copy_regions);
+        }
+
+        Self::end_single_time_command(device, queue, command_pool, sync_pool, command_buffer)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn find_memory_type(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        mem_type_filter: u32,
+        proprieties: vk::MemoryPropertyFlags,
+    ) -> AppResult<u32> {
+        let mem_proprieties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        for (i, mem_type) in mem_proprieties.memory_types.iter().enumerate() {
+            if mem_type_filter & (1 << i) != 0 && mem_type.property_flags.contains(proprieties) {
+                return Ok(i as u32);
+            }
+        }
+
+        Err(AppError::new(AppErrorType::NoSuitableMemType))
+    }
+
+    /// Uploads decoded textures with a single submission, through one staging buffer holding every
+    /// mip level. The block-compressed textures the device can't sample are decompressed first,
+    /// see `texture_loader::fit_to_device`.
+    #[tracing::instrument(skip_all, fields(count = textures.len()))]
+    pub(crate) fn upload_textures(
+        instance: &Instance,
+        device: &Device,
+        graphic_queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        textures: &mut [DecodedTexture],
+    ) -> AppResult<Vec<TextureHolder>> {
+        let mut upload_paths = vec![];
+        for texture in textures.iter_mut() {
+            upload_paths.push(texture_loader::fit_to_device(
+                instance,
+                physical_device,
+                texture,
+            )?);
+        }
+        let textures = &*textures;
+
+        let buffer_size = textures.iter().map(DecodedTexture::byte_size).sum();
+        let staging_buffer = Self::create_buffer(
+            instance,
+            device,
+            physical_device,
+            buffer_size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let mut images = vec![];
+        let mut regions = vec![];
+        let mut offset = 0;
+        unsafe {
+            let buffer_memory_ptr = device.map_memory(
+                staging_buffer.memory,
+                0,
+                buffer_size,
+                vk::MemoryMapFlags::empty(),
+            )? as *mut u8;
+
+            for texture in textures {
+                let image = Self::create_image(
+                    instance,
+                    device,
+                    physical_device,
+                    texture.width(),
+                    texture.height(),
+                    texture.mip_levels(),
+                    texture.format,
+                    vk::ImageTiling::OPTIMAL,
+                    vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )?;
+
+                let mut image_regions = vec![];
+                for (mip_level, level) in texture.levels.iter().enumerate() {
+                    let data = &level.data;
+                    std::ptr::copy(data.as_ptr(), buffer_memory_ptr.add(offset), data.len());
+
+                    image_regions.push(vk::BufferImageCopy {
+                        buffer_offset: offset as u64,
+                        image_subresource: vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: mip_level as u32,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        },
+                        image_extent: vk::Extent3D {
+                            width: level.width,
+                            height: level.height,
+                            depth: 1,
+                        },
+                        ..Default::default()
+                    });
+                    offset += data.len();
+                }
+
+                regions.push(image_regions);
+                images.push(image);
+            }
+
+            device.unmap_memory(staging_buffer.memory);
+        }
+
+        let layout_barriers = |old_layout, new_layout, src_access_mask, dst_access_mask| {
+            images
+                .iter()
+                .zip(textures)
+                .map(|(image, texture)| vk::ImageMemoryBarrier {
+                    src_access_mask,
+                    dst_access_mask,
+                    old_layout,
+                    new_layout,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    image: image.image,
+                    subresource_range: vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: texture.mip_levels(),
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    },
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        unsafe {
+            let command_buffer = Self::begin_singe_time_command(device, command_pool)?;
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &layout_barriers(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            );
+            for (image, image_regions) in images.iter().zip(regions.iter()) {
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    staging_buffer.buffer,
+                    image.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    image_regions,
+                );
+            }
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &layout_barriers(
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+            );
+            Self::end_single_time_command(
+                device,
+                graphic_queue,
+                command_pool,
+                sync_pool,
+                command_buffer,
+            )?;
+
+            handle_registry::unregister(staging_buffer.buffer);
+            device.destroy_buffer(staging_buffer.buffer, None);
+            handle_registry::unregister(staging_buffer.memory);
+            device.free_memory(staging_buffer.memory, None);
+        }
+
+        let mut uploaded = vec![];
+        for ((image, texture), upload_path) in images.into_iter().zip(textures).zip(upload_paths) {
+            let view =
+                Self::create_image_view(device, image.image, texture.format, texture.mip_levels())?;
+            uploaded.push(TextureHolder {
+                image,
+                view,
+                array_layers: None,
+                upload_path,
+            });
+        }
+
+        Ok(uploaded)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn create_image(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        format: vk::Format,
+        tiling: vk::ImageTiling,
+        usage: vk::ImageUsageFlags,
+        proprieties: vk::MemoryPropertyFlags,
+    ) -> AppResult<ImageHolder> {
+        let image_info = vk::ImageCreateInfo {
+            image_type: vk::ImageType::TYPE_2D,
+            format,
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_levels,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            ..Default::default()
+        };
+        Self::allocate_image(instance, device, physical_device, &image_info, proprieties)
+    }
+
+    /// Creates a device local color attachment of `samples` samples per pixel, only written and
+    /// resolved within a render pass
+    pub(crate) fn create_multisampled_image(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> AppResult<ImageHolder> {
+        let image_info = vk::ImageCreateInfo {
+            image_type: vk::ImageType::TYPE_2D,
+            format,
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            mip_levels: 1,
+            array_layers: 1,
+            samples,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            ..Default::default()
+        };
+        Self::allocate_image(
+            instance,
+            device,
+            physical_device,
+            &image_info,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+    }
+
+    fn allocate_image(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        image_info: &vk::ImageCreateInfo,
+        proprieties: vk::MemoryPropertyFlags,
+    ) -> AppResult<ImageHolder> {
+        unsafe {
+            let image = handle_registry::register(device.create_image(image_info, None)?);
+            let mem_requirement = device.get_image_memory_requirements(image);
+            let memory_type = Self::find_memory_type(
+                instance,
+                physical_device,
+                mem_requirement.memory_type_bits,
+                proprieties,
+            )?;
+
+            let alloc_info = vk::MemoryAllocateInfo {
+                allocation_size: mem_requirement.size,
+                memory_type_index: memory_type,
+                ..Default::default()
+            };
+
+            let image_memory =
+                handle_registry::register(device.allocate_memory(&alloc_info, None)?);
+            device.bind_image_memory(image, image_memory, 0)?;
+
+            Ok(ImageHolder::new(image, image_memory))
+        }
+    }
+
+    /// Copies the content of a presentable image to the host.
+    /// The image must be in the `PRESENT_SRC_KHR` layout and is left in it.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn read_back_image(
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        image: vk::Image,
+        extent: vk::Extent2D,
+        bytes_per_pixel: usize,
+    ) -> AppResult<Vec<u8>> {
+        let buffer_size = extent.width as u64 * extent.height as u64 * bytes_per_pixel as u64;
+        let buffer = Self::create_buffer(
+            instance,
+            device,
+            physical_device,
+            buffer_size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        // Destroyed once the copy is waited for, or on failure once the device is idle
+        let buffer = DeviceObjectGuard::new(device, buffer, |buffer, device| unsafe {
+            buffer.destroy(device)
+        });
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        let to_transfer = [vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::MEMORY_READ,
+            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+            old_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range,
+            ..Default::default()
+        }];
+
+        let to_present = [vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_READ,
+            dst_access_mask: vk::AccessFlags::MEMORY_READ,
+            old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            ..to_transfer[0]
+        }];
+
+        let regions = [vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        }];
+
+        unsafe {
+            let command_buffer = Self::begin_singe_time_command(device, command_pool)?;
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_transfer,
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &regions,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_present,
+            );
+            Self::end_single_time_command(device, queue, command_pool, sync_pool, command_buffer)?;
+        }
+
+        let mut bytes = vec![0u8; buffer_size as usize];
+        unsafe {
+            let buffer_memory_ptr =
+                device.map_memory(buffer.memory, 0, buffer_size, vk::MemoryMapFlags::empty())?;
+            std::ptr::copy(
+                buffer_memory_ptr as *const u8,
+                bytes.as_mut_ptr(),
+                buffer_size as usize,
+            );
+            device.unmap_memory(buffer.memory);
+            buffer.release().destroy(device);
+        }
+
+        Ok(bytes)
+    }
+
+    pub(crate) fn begin_singe_time_command(
+        device: &Device,
+        command_pool: vk::CommandPool,
+    ) -> AppResult<vk::CommandBuffer> {
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: 1,
+            ..Default::default()
+        };
+
+        unsafe {
+            let command_buffer = device.allocate_command_buffers(&alloc_info)?[0];
+            let begin_info = vk::CommandBufferBeginInfo {
+                flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                ..Default::default()
+            };
+            if let Err(err) = device.begin_command_buffer(command_buffer, &begin_info) {
+                device.free_command_buffers(command_pool, &[command_buffer]);
+                return Err(err.into());
+            }
+            Ok(command_buffer)
+        }
+    }
+
+    pub(crate) fn end_single_time_command(
+        device: &Device,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        command_buffer: vk::CommandBuffer,
+    ) -> AppResult<()> {
+        let submit_infos = [vk::SubmitInfo {
+            command_buffer_count: 1,
+            p_command_buffers: &command_buffer as *const _,
+            ..Default::default()
+        }];
+
+        unsafe {
+            let submitted = device
+                .end_command_buffer(command_buffer)
+                .map_err(AppError::from)
+                .and_then(|()| {
+                    // Waits on a fence rather than the whole queue, which may be running frames
+                    sync_pool.with_fence(device, |fence| {
+                        device.queue_submit(queue, &submit_infos, fence)?;
+                        device.wait_for_fences(&[fence], true, u64::MAX)?;
+                        Ok(())
+                    })
+                });
+
+            // A failed submission is waited for by `with_fence`
+            device.free_command_buffers(command_pool, &[command_buffer]);
+            submitted
+        }
+    }
+}
+
+impl BufferHolder {
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        handle_registry::unregister(self.buffer);
+        device.destroy_buffer(self.buffer, None);
+        handle_registry::unregister(self.memory);
+        device.free_memory(self.memory, None);
+    }
+}
+
+impl MemoryMappedBuffer {
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        handle_registry::unregister(self.buffer);
+        device.destroy_buffer(self.buffer, None);
+        handle_registry::unregister(self.memory);
+        device.free_memory(self.memory, None);
+    }
+}
+
+impl MeshStorage {
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        match self {
+            MeshStorage::Static(mesh) => {
+                mesh.vertex_buffer.destroy(device);
+                mesh.index_buffer.destroy(device);
+            }
+            MeshStorage::Dynamic(mesh) => {
+                for buffer in mesh.vertex_buffers.iter().chain(&mesh.index_buffers) {
+                    buffer.destroy(device);
+                }
+            }
+        }
+    }
+}
+
+impl TextureHolder {
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        handle_registry::unregister(self.view);
+        device.destroy_image_view(self.view, None);
+        handle_registry::unregister(self.image.image);
+        device.destroy_image(self.image.image, None);
+        handle_registry::unregister(self.image.memory);
+        device.free_memory(self.image.memory, None);
+    }
+}
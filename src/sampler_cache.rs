@@ -0,0 +1,107 @@
+use ash::{vk, Device};
+
+use crate::{handle_registry, AppResult};
+
+/// Highest anisotropy level of `TextureFiltering`, further clamped to the device limit
+pub const MAX_ANISOTROPY: f32 = 16.0;
+
+/// Filtering quality of the material textures, see `Application::set_texture_filtering`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureFiltering {
+    /// Samples taken along the axis of anisotropy, from 1 (disabled) to `MAX_ANISOTROPY`
+    pub anisotropy: f32,
+    /// Blends the two closest mip levels, or only samples the closest one (bilinear)
+    pub trilinear: bool,
+    /// Added to the mip level computed by the GPU, positive values blurring the textures
+    pub mip_lod_bias: f32,
+}
+
+impl Default for TextureFiltering {
+    fn default() -> Self {
+        Self {
+            anisotropy: MAX_ANISOTROPY,
+            trilinear: true,
+            mip_lod_bias: 0.0,
+        }
+    }
+}
+
+/// Samplers of the material textures, created once per filtering setting and kept until the
+/// application is destroyed so switching back and forth doesn't create new ones
+pub(crate) struct SamplerCache {
+    samplers: Vec<(TextureFiltering, vk::Sampler)>,
+    max_anisotropy: f32,
+    max_lod_bias: f32,
+}
+
+impl SamplerCache {
+    pub(crate) fn new(limits: &vk::PhysicalDeviceLimits) -> Self {
+        Self {
+            samplers: Vec::new(),
+            max_anisotropy: limits.max_sampler_anisotropy.min(MAX_ANISOTROPY),
+            max_lod_bias: limits.max_sampler_lod_bias,
+        }
+    }
+
+    /// Clamps `filtering` to what the device supports
+    pub(crate) fn clamp(&self, filtering: TextureFiltering) -> TextureFiltering {
+        TextureFiltering {
+            anisotropy: filtering
+                .anisotropy
+                .clamp(1.0, self.max_anisotropy.max(1.0)),
+            mip_lod_bias: filtering
+                .mip_lod_bias
+                .clamp(-self.max_lod_bias, self.max_lod_bias),
+            ..filtering
+        }
+    }
+
+    /// Returns the sampler of `filtering`, which must be clamped, creating it the first time
+    pub(crate) fn get(
+        &mut self,
+        device: &Device,
+        filtering: TextureFiltering,
+    ) -> AppResult<vk::Sampler> {
+        if let Some(&(_, sampler)) = self.samplers.iter().find(|(f, _)| *f == filtering) {
+            return Ok(sampler);
+        }
+
+        let mipmap_mode = match filtering.trilinear {
+            true => vk::SamplerMipmapMode::LINEAR,
+            false => vk::SamplerMipmapMode::NEAREST,
+        };
+        let create_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            anisotropy_enable: (filtering.anisotropy > 1.0).into(),
+            max_anisotropy: filtering.anisotropy,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            unnormalized_coordinates: vk::FALSE,
+            compare_enable: vk::FALSE,
+            compare_op: vk::CompareOp::ALWAYS,
+            mipmap_mode,
+            mip_lod_bias: filtering.mip_lod_bias,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.create_sampler(&create_info, None)? };
+        self.samplers
+            .push((filtering, handle_registry::register(sampler)));
+
+        Ok(sampler)
+    }
+
+    /// Destroys every sampler, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        for (_, sampler) in self.samplers.drain(..) {
+            unsafe {
+                handle_registry::unregister(sampler);
+                device.destroy_sampler(sampler, None);
+            }
+        }
+    }
+}
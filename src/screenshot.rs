@@ -0,0 +1,268 @@
+#[cfg(feature = "clipboard")]
+use std::{borrow::Cow, sync::mpsc, thread};
+use std::{fs::File, io::BufWriter, path::Path};
+
+use ash::vk;
+use half::f16;
+use image::{ImageBuffer, Rgba};
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    AppResult,
+};
+
+// Reference white used when mapping absolute luminance (PQ) back to SDR, in nits (ITU-R BT.2408)
+const SDR_REFERENCE_WHITE: f32 = 203.0;
+const PQ_MAX_LUMINANCE: f32 = 10000.0;
+
+// Linear BT.2020 to linear BT.709 primaries conversion (row major)
+const BT2020_TO_BT709: [[f32; 3]; 3] = [
+    [1.6605, -0.5876, -0.0728],
+    [-0.1246, 1.1329, -0.0083],
+    [-0.0182, -0.1006, 1.1187],
+];
+
+/// Raw content of a swapchain image read back from the GPU
+pub(crate) struct RawScreenshot {
+    pub width: u32,
+    pub height: u32,
+    pub format: vk::Format,
+    pub color_space: vk::ColorSpaceKHR,
+    pub bytes: Vec<u8>,
+}
+
+/// Copy of a screenshot to the clipboard running in the background, see
+/// `Application::copy_screenshot_to_clipboard`
+#[cfg(feature = "clipboard")]
+pub struct ClipboardCopy {
+    receiver: mpsc::Receiver<AppResult<()>>,
+}
+
+#[cfg(feature = "clipboard")]
+impl ClipboardCopy {
+    /// Returns the result of the copy once it is done, only once
+    pub fn poll(&self) -> Option<AppResult<()>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Blocks until the copy is done
+    pub fn wait(self) -> AppResult<()> {
+        self.receiver
+            .recv()
+            .unwrap_or_else(|_| Err(AppError::new(AppErrorType::ClipboardUnavailable)))
+    }
+}
+
+/// Returns the size in bytes of a single texel of the given swapchain format, or `None` if the
+/// format can't be converted to a screenshot
+pub(crate) fn bytes_per_pixel(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::B8G8R8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::R8G8B8A8_UNORM
+        | vk::Format::A8B8G8R8_SRGB_PACK32
+        | vk::Format::A8B8G8R8_UNORM_PACK32
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::A2R10G10B10_UNORM_PACK32 => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        _ => None,
+    }
+}
+
+impl RawScreenshot {
+    /// Writes the screenshot to `path`.
+    ///
+    /// Files with an `.exr` extension are written as linear BT.709 floating point data, keeping
+    /// the values above SDR white. Every other path is written as an 8 bits sRGB PNG tagged with
+    /// the sRGB chunk, clipping the values that don't fit into the SDR range.
+    pub(crate) fn save<P: AsRef<Path>>(&self, path: P) -> AppResult<()> {
+        let path = path.as_ref();
+        let linear = self.to_linear()?;
+
+        let is_exr = path
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("exr"))
+            .unwrap_or(false);
+
+        if is_exr {
+            let data = linear.into_iter().flatten().collect();
+            let img: ImageBuffer<Rgba<f32>, Vec<f32>> =
+                ImageBuffer::from_raw(self.width, self.height, data)
+                    .ok_or_else(|| AppError::new(AppErrorType::UnsupportedScreenshotFormat))?;
+            img.save(path)?;
+        } else {
+            let data = Self::encode_srgb8(linear);
+            let writer = BufWriter::new(File::create(path)?);
+            let mut encoder = png::Encoder::new(writer, self.width, self.height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+            encoder.write_header()?.write_image_data(&data)?;
+        }
+
+        Ok(())
+    }
+
+    // Encodes linear values as 8 bits sRGB RGBA texels, clipping the values above SDR white
+    fn encode_srgb8(linear: Vec<[f32; 4]>) -> Vec<u8> {
+        linear
+            .into_iter()
+            .flat_map(|[r, g, b, a]| {
+                [
+                    encode_u8(srgb_oetf(r)),
+                    encode_u8(srgb_oetf(g)),
+                    encode_u8(srgb_oetf(b)),
+                    encode_u8(a),
+                ]
+            })
+            .collect()
+    }
+
+    /// Converts the screenshot to 8 bits sRGB and writes it to the clipboard from a background
+    /// thread, as the conversion of a large frame takes a while
+    #[cfg(feature = "clipboard")]
+    pub fn copy_to_clipboard(self) -> ClipboardCopy {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let result = self.to_linear().and_then(|linear| {
+                let image = arboard::ImageData {
+                    width: self.width as usize,
+                    height: self.height as usize,
+                    bytes: Cow::Owned(Self::encode_srgb8(linear)),
+                };
+                arboard::Clipboard::new()
+                    .and_then(|mut clipboard| clipboard.set_image(image))
+                    .map_err(|err| AppError::with_source(AppErrorType::ClipboardUnavailable, err))
+            });
+            // The copy may not be waited for
+            let _ = sender.send(result);
+        });
+
+        ClipboardCopy { receiver }
+    }
+
+    /// Decodes the raw swapchain bytes into linear BT.709 RGBA values where 1.0 is SDR white
+    fn to_linear(&self) -> AppResult<Vec<[f32; 4]>> {
+        let bpp = bytes_per_pixel(self.format)
+            .ok_or_else(|| AppError::new(AppErrorType::UnsupportedScreenshotFormat))?;
+
+        let texels = self.bytes.chunks_exact(bpp).map(|texel| match self.format {
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => [
+                decode_u8(texel[2]),
+                decode_u8(texel[1]),
+                decode_u8(texel[0]),
+                decode_u8(texel[3]),
+            ],
+            vk::Format::R8G8B8A8_SRGB
+            | vk::Format::R8G8B8A8_UNORM
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::A8B8G8R8_UNORM_PACK32 => [
+                decode_u8(texel[0]),
+                decode_u8(texel[1]),
+                decode_u8(texel[2]),
+                decode_u8(texel[3]),
+            ],
+            vk::Format::A2B10G10R10_UNORM_PACK32 => {
+                let packed = u32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]]);
+                [
+                    decode_u10(packed),
+                    decode_u10(packed >> 10),
+                    decode_u10(packed >> 20),
+                    (packed >> 30) as f32 / 3.0,
+                ]
+            }
+            vk::Format::A2R10G10B10_UNORM_PACK32 => {
+                let packed = u32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]]);
+                [
+                    decode_u10(packed >> 20),
+                    decode_u10(packed >> 10),
+                    decode_u10(packed),
+                    (packed >> 30) as f32 / 3.0,
+                ]
+            }
+            // R16G16B16A16_SFLOAT
+            _ => {
+                let channel =
+                    |i: usize| f16::from_le_bytes([texel[2 * i], texel[2 * i + 1]]).to_f32();
+                [channel(0), channel(1), channel(2), channel(3)]
+            }
+        });
+
+        let color_space = self.color_space;
+        let linear = texels
+            .map(|[r, g, b, a]| {
+                let [r, g, b] = match color_space {
+                    // The hardware already encoded the values when writing to a _SRGB format,
+                    // and an UNORM image is displayed as if it were sRGB encoded
+                    vk::ColorSpaceKHR::SRGB_NONLINEAR
+                    | vk::ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT => {
+                        [srgb_eotf(r), srgb_eotf(g), srgb_eotf(b)]
+                    }
+                    // scRGB already uses 1.0 as SDR white
+                    vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => [r, g, b],
+                    vk::ColorSpaceKHR::HDR10_ST2084_EXT => {
+                        let rgb = [r, g, b].map(|c| pq_eotf(c) / SDR_REFERENCE_WHITE);
+                        bt2020_to_bt709(rgb)
+                    }
+                    vk::ColorSpaceKHR::BT2020_LINEAR_EXT => bt2020_to_bt709([r, g, b]),
+                    _ => [srgb_eotf(r), srgb_eotf(g), srgb_eotf(b)],
+                };
+                [r, g, b, a]
+            })
+            .collect();
+
+        Ok(linear)
+    }
+}
+
+fn decode_u8(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+fn decode_u10(value: u32) -> f32 {
+    (value & 0x3ff) as f32 / 1023.0
+}
+
+fn encode_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// sRGB encoded value to linear light, mirrored for the negative values of extended sRGB
+fn srgb_eotf(value: f32) -> f32 {
+    let v = value.abs();
+    let linear = if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    };
+
+    linear.copysign(value)
+}
+
+/// Linear light to sRGB encoded value
+fn srgb_oetf(value: f32) -> f32 {
+    let v = value.clamp(0.0, 1.0);
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// SMPTE ST 2084 (PQ) encoded value to absolute luminance in nits
+fn pq_eotf(value: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let e = value.clamp(0.0, 1.0).powf(1.0 / M2);
+    let l = ((e - C1).max(0.0) / (C2 - C3 * e)).powf(1.0 / M1);
+    l * PQ_MAX_LUMINANCE
+}
+
+fn bt2020_to_bt709([r, g, b]: [f32; 3]) -> [f32; 3] {
+    BT2020_TO_BT709.map(|row| row[0] * r + row[1] * g + row[2] * b)
+}
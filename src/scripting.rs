@@ -0,0 +1,294 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Instant, SystemTime},
+};
+
+use cgmath::{Deg, Rad};
+use rhai::{Engine, Scope, AST, FLOAT, INT};
+use tracing::error;
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    AppResult, DrawItemId, Mat4, MaterialHandle, Point3, PostEffect, Vec3,
+};
+
+// Bounds the work of a single run so a looping script can't freeze the renderer
+const MAX_OPERATIONS: u64 = 1_000_000;
+// Function called every frame with the seconds elapsed since the script was (re)loaded
+const FRAME_FN: &str = "frame";
+
+/// Renderer setting changed by a script, applied by the application before the next frame.
+/// Items and materials are referred to by the name they were exposed with.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ScriptCommand {
+    FlyCamera {
+        position: Point3,
+        yaw: Rad<f32>,
+        pitch: Rad<f32>,
+    },
+    OrbitCamera {
+        target: Point3,
+        yaw: Rad<f32>,
+        pitch: Rad<f32>,
+        distance: f32,
+    },
+    Paused(bool),
+    StreamBudget(u64),
+    MaterialLayer {
+        material: String,
+        layer: u32,
+    },
+    ItemMaterial {
+        item: String,
+        material: String,
+    },
+    ItemTransform {
+        item: String,
+        transform: Mat4,
+    },
+    PostEffect {
+        name: String,
+        enabled: bool,
+    },
+}
+
+struct LoadedScript {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    ast: AST,
+    scope: Scope<'static>,
+    loaded_at: Instant,
+}
+
+/// Runs a rhai script driving a safe subset of the renderer settings, reloaded whenever the file
+/// is modified. The script only queues commands, it never touches the Vulkan objects.
+///
+/// The top level of the script runs once per (re)load, then its `frame(time)` function, if any,
+/// runs every frame. The functions avaible to the script are:
+/// - `fly_camera(x, y, z, yaw, pitch)` and `orbit_camera(x, y, z, yaw, pitch, distance)`, angles
+///   in degrees
+/// - `set_paused(paused)` and `set_stream_budget(bytes)`
+/// - `set_material_layer(material, layer)`, for the materials sampling a texture array
+/// - `set_item_material(item, material)` and `set_item_transform(item, x, y, z, angle, scale)`,
+///   the angle being around the up axis in degrees
+/// - `set_post_effect(name, enabled)`
+///
+/// Items and materials are only reachable once exposed under a name by the host. Lights are baked
+/// into lightmaps, so they can't be tweaked at runtime.
+pub(crate) struct ScriptHost {
+    engine: Engine,
+    commands: Rc<RefCell<Vec<ScriptCommand>>>,
+    script: Option<LoadedScript>,
+    items: HashMap<String, DrawItemId>,
+    materials: HashMap<String, MaterialHandle>,
+    // Effects disabled by the script along with their order, inserted back once enabled
+    disabled_effects: Vec<(i32, Box<dyn PostEffect>)>,
+}
+
+impl ScriptHost {
+    pub(crate) fn new() -> Self {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let commands = Rc::new(RefCell::new(vec![]));
+        Self::register_commands(&mut engine, &commands);
+
+        Self {
+            engine,
+            commands,
+            script: None,
+            items: HashMap::new(),
+            materials: HashMap::new(),
+            disabled_effects: vec![],
+        }
+    }
+
+    fn register_commands(engine: &mut Engine, commands: &Rc<RefCell<Vec<ScriptCommand>>>) {
+        let queue = commands.clone();
+        engine.register_fn(
+            "fly_camera",
+            move |x: FLOAT, y: FLOAT, z: FLOAT, yaw: FLOAT, pitch: FLOAT| {
+                queue.borrow_mut().push(ScriptCommand::FlyCamera {
+                    position: Point3::new(x as f32, y as f32, z as f32),
+                    yaw: Deg(yaw as f32).into(),
+                    pitch: Deg(pitch as f32).into(),
+                });
+            },
+        );
+        let queue = commands.clone();
+        engine.register_fn(
+            "orbit_camera",
+            move |x: FLOAT, y: FLOAT, z: FLOAT, yaw: FLOAT, pitch: FLOAT, distance: FLOAT| {
+                queue.borrow_mut().push(ScriptCommand::OrbitCamera {
+                    target: Point3::new(x as f32, y as f32, z as f32),
+                    yaw: Deg(yaw as f32).into(),
+                    pitch: Deg(pitch as f32).into(),
+                    distance: distance as f32,
+                });
+            },
+        );
+        let queue = commands.clone();
+        engine.register_fn("set_paused", move |paused: bool| {
+            queue.borrow_mut().push(ScriptCommand::Paused(paused));
+        });
+        let queue = commands.clone();
+        engine.register_fn("set_stream_budget", move |budget: INT| {
+            queue
+                .borrow_mut()
+                .push(ScriptCommand::StreamBudget(budget.max(0) as u64));
+        });
+        let queue = commands.clone();
+        engine.register_fn("set_material_layer", move |material: &str, layer: INT| {
+            queue.borrow_mut().push(ScriptCommand::MaterialLayer {
+                material: String::from(material),
+                layer: layer.clamp(0, u32::MAX as INT) as u32,
+            });
+        });
+        let queue = commands.clone();
+        engine.register_fn("set_item_material", move |item: &str, material: &str| {
+            queue.borrow_mut().push(ScriptCommand::ItemMaterial {
+                item: String::from(item),
+                material: String::from(material),
+            });
+        });
+        let queue = commands.clone();
+        engine.register_fn(
+            "set_item_transform",
+            move |item: &str, x: FLOAT, y: FLOAT, z: FLOAT, angle: FLOAT, scale: FLOAT| {
+                let transform = Mat4::from_translation(Vec3::new(x as f32, y as f32, z as f32))
+                    * Mat4::from_angle_z(Deg(angle as f32))
+                    * Mat4::from_scale(scale as f32);
+                queue.borrow_mut().push(ScriptCommand::ItemTransform {
+                    item: String::from(item),
+                    transform,
+                });
+            },
+        );
+        let queue = commands.clone();
+        engine.register_fn("set_post_effect", move |name: &str, enabled: bool| {
+            queue.borrow_mut().push(ScriptCommand::PostEffect {
+                name: String::from(name),
+                enabled,
+            });
+        });
+    }
+
+    /// Compiles and runs the script at `path`, replacing the previous one. Fails if the file
+    /// can't be read or compiled, a failing top level only being reported.
+    pub(crate) fn load(&mut self, path: &Path) -> AppResult<()> {
+        let modified = fs::metadata(path)?.modified().ok();
+        let ast = self.compile(path)?;
+
+        let mut script = LoadedScript {
+            path: path.to_path_buf(),
+            modified,
+            ast,
+            scope: Scope::new(),
+            loaded_at: Instant::now(),
+        };
+        self.run_top_level(&mut script);
+        self.script = Some(script);
+
+        Ok(())
+    }
+
+    fn compile(&self, path: &Path) -> AppResult<AST> {
+        let source = fs::read_to_string(path)?;
+        self.engine.compile(source).map_err(|err| {
+            AppError::with_message(
+                AppErrorType::ScriptCompilationFailed,
+                format!("{:?}: {}", path, err),
+            )
+        })
+    }
+
+    fn run_top_level(&self, script: &mut LoadedScript) {
+        if let Err(err) = self
+            .engine
+            .run_ast_with_scope(&mut script.scope, &script.ast)
+        {
+            Self::report(&script.path, &err.to_string());
+        }
+    }
+
+    fn report(path: &Path, message: &str) {
+        error!(?path, "script error: {}", message);
+    }
+
+    /// Reloads the script if its file changed, runs its `frame` function and returns the queued
+    /// commands. The previous version keeps running when the new one doesn't compile.
+    pub(crate) fn update(&mut self) -> Vec<ScriptCommand> {
+        if let Some(mut script) = self.script.take() {
+            let modified = fs::metadata(&script.path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            if modified.is_some() && modified != script.modified {
+                script.modified = modified;
+                match self.compile(&script.path) {
+                    Ok(ast) => {
+                        script.ast = ast;
+                        script.scope = Scope::new();
+                        script.loaded_at = Instant::now();
+                        self.run_top_level(&mut script);
+                    }
+                    Err(err) => Self::report(&script.path, &err.message),
+                }
+            }
+
+            let has_frame_fn = script
+                .ast
+                .iter_functions()
+                .any(|function| function.name == FRAME_FN && function.params.len() == 1);
+            if has_frame_fn {
+                let time = script.loaded_at.elapsed().as_secs_f64() as FLOAT;
+                if let Err(err) =
+                    self.engine
+                        .call_fn::<()>(&mut script.scope, &script.ast, FRAME_FN, (time,))
+                {
+                    Self::report(&script.path, &err.to_string());
+                }
+            }
+
+            self.script = Some(script);
+        }
+
+        std::mem::take(&mut *self.commands.borrow_mut())
+    }
+
+    /// Lets the script refer to `item` as `name`
+    pub(crate) fn expose_item(&mut self, name: &str, item: DrawItemId) {
+        self.items.insert(String::from(name), item);
+    }
+
+    /// Lets the script refer to `material` as `name`
+    pub(crate) fn expose_material(&mut self, name: &str, material: MaterialHandle) {
+        self.materials.insert(String::from(name), material);
+    }
+
+    pub(crate) fn item(&self, name: &str) -> Option<DrawItemId> {
+        self.items.get(name).copied()
+    }
+
+    pub(crate) fn material(&self, name: &str) -> Option<MaterialHandle> {
+        self.materials.get(name).copied()
+    }
+
+    pub(crate) fn disable_effect(&mut self, order: i32, effect: Box<dyn PostEffect>) {
+        self.disabled_effects.push((order, effect));
+    }
+
+    /// Takes back the effect named `name` disabled by the script, along with its order
+    pub(crate) fn take_disabled_effect(
+        &mut self,
+        name: &str,
+    ) -> Option<(i32, Box<dyn PostEffect>)> {
+        let index = self
+            .disabled_effects
+            .iter()
+            .position(|(_, effect)| effect.name() == name)?;
+        Some(self.disabled_effects.swap_remove(index))
+    }
+}
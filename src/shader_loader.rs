@@ -0,0 +1,92 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use tracing::info;
+
+use crate::{AppError, AppErrorType, AppResult, Application};
+
+/// Environment variable pointing to a directory of shaders overriding the embedded ones
+pub const SHADER_DIR_ENV: &str = "VULKAN_TUTORIAL_SHADER_DIR";
+/// Environment variable overriding the glslc executable used to compile GLSL overrides
+pub const GLSLC_ENV: &str = "GLSLC";
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// Returns the SPIR-V code of the shader `file_name` (e.g. `vertex.vert`).
+///
+/// When `VULKAN_TUTORIAL_SHADER_DIR` is set, `<dir>/<stem>.spv` is loaded if it exists, otherwise
+/// `<dir>/<file_name>` is compiled with glslc if it exists. The `embedded` code is used in every
+/// other case, so a released binary can be iterated on without rebuilding the crate.
+pub fn load_shader(file_name: &str, embedded: &[u8]) -> AppResult<Vec<u32>> {
+    if let Some(dir) = env::var_os(SHADER_DIR_ENV) {
+        let dir = PathBuf::from(dir);
+
+        let stem = Path::new(file_name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(file_name);
+        let spirv_path = dir.join(format!("{}.spv", stem));
+        if spirv_path.is_file() {
+            info!(path = ?spirv_path, "shader override");
+            return to_words(&fs::read(&spirv_path)?, &spirv_path);
+        }
+
+        let glsl_path = dir.join(file_name);
+        if glsl_path.is_file() {
+            info!(path = ?glsl_path, "shader override");
+            return compile_glsl(&glsl_path);
+        }
+    }
+
+    Ok(Application::make_spirv_raw(embedded))
+}
+
+/// Compiles a GLSL shader with glslc, the stage being deduced from the file extension
+fn compile_glsl(path: &Path) -> AppResult<Vec<u32>> {
+    let glslc = env::var_os(GLSLC_ENV).unwrap_or_else(|| "glslc".into());
+    let mut command = Command::new(glslc);
+    // Task, mesh, ray tracing and ray query shaders need SPIR-V 1.4, which comes with Vulkan 1.3
+    let ray_query = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem.ends_with("_ray_query"));
+    if ray_query
+        || matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("task" | "mesh" | "rgen" | "rmiss" | "rchit")
+        )
+    {
+        command.arg("--target-env=vulkan1.3");
+    }
+    let output = command
+        .arg(path)
+        .arg("-o")
+        .arg("-")
+        .output()
+        .map_err(|err| AppError::with_source(AppErrorType::ShaderCompilationFailed, err))?;
+
+    if !output.status.success() {
+        return Err(AppError::with_message(
+            AppErrorType::ShaderCompilationFailed,
+            format!("{:?}: {}", path, String::from_utf8_lossy(&output.stderr)),
+        ));
+    }
+
+    to_words(&output.stdout, path)
+}
+
+fn to_words(bytes: &[u8], path: &Path) -> AppResult<Vec<u32>> {
+    // Checked before the copy, `make_spirv_raw` expects a whole number of words
+    let whole_words = bytes.len() & 3 == 0;
+    if !whole_words || bytes.get(..4) != Some(&SPIRV_MAGIC.to_ne_bytes()[..]) {
+        return Err(AppError::with_message(
+            AppErrorType::InvalidShader,
+            format!("{:?} is not a valid SPIR-V module.", path),
+        ));
+    }
+
+    Ok(Application::make_spirv_raw(bytes))
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+
+use ash::{vk, Device, Instance};
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    descriptor_writes::DescriptorWriteBatch,
+    handle_registry,
+    pipeline_manager::{MeshPipelineState, PipelineManager},
+    AppResult, Application, MemoryMappedBuffer, TextureHandle,
+};
+
+// Vulkan doesn't allow empty buffers, the parameters of a material without any fill this size
+const MIN_PARAMETERS_SIZE: usize = 16;
+
+/// Shaders, textures and constants of a material drawn with a pipeline of its own, see
+/// `Application::create_shader_material`.
+///
+/// Set 0 is the scene set of the draw (model, view and projection at binding 0, the first
+/// texture at binding 1 and the lightmap at binding 2). Set 1 holds the parameters as a uniform
+/// buffer at binding 0, then the textures from binding 1.
+#[derive(Clone, Copy, Debug)]
+pub struct ShaderMaterialDesc<'a> {
+    /// SPIR-V of the vertex shader, reading the `Vertex` attributes
+    pub vertex_shader: &'a [u32],
+    /// SPIR-V of the fragment shader, writing to the scene color target
+    pub fragment_shader: &'a [u32],
+    pub textures: &'a [TextureHandle],
+    /// Initial content of the parameter uniform buffer, which keeps its size
+    pub parameters: &'a [u8],
+    pub cull_mode: vk::CullModeFlags,
+}
+
+struct ShaderMaterial {
+    // Shared with the materials with the same shaders and number of textures, the pipeline
+    // being owned by the pipeline manager and the layout by `ShaderMaterials`
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_pool: vk::DescriptorPool,
+    // One set and parameter buffer per frame in flight
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    parameter_buffers: Vec<MemoryMappedBuffer>,
+    parameters: Vec<u8>,
+    sampler: vk::Sampler,
+    texture_views: Vec<vk::ImageView>,
+    // Frames whose set or parameter buffer must be rewritten before use
+    stale_frames: Vec<bool>,
+}
+
+/// Materials with their own shaders, pipelines and parameters, drawn in the same frame as the
+/// others
+#[derive(Default)]
+pub(crate) struct ShaderMaterials {
+    materials: Vec<ShaderMaterial>,
+    // Set and pipeline layouts by number of textures
+    layouts: HashMap<u32, (vk::DescriptorSetLayout, vk::PipelineLayout)>,
+}
+
+impl ShaderMaterials {
+    /// Creates the sets of a material whose textures are sampled through `texture_views`, its
+    /// pipeline coming from `pipelines`, and returns its index
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn add(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        pipelines: &mut PipelineManager,
+        scene_set_layout: vk::DescriptorSetLayout,
+        sampler: vk::Sampler,
+        desc: &ShaderMaterialDesc,
+        texture_views: Vec<vk::ImageView>,
+        frame_count: usize,
+    ) -> AppResult<usize> {
+        let texture_count = texture_views.len() as u32;
+        let (set_layout, pipeline_layout) = match self.layouts.get(&texture_count) {
+            Some(&layouts) => layouts,
+            None => {
+                let layouts = Self::create_layouts(device, scene_set_layout, texture_count)?;
+                self.layouts.insert(texture_count, layouts);
+                layouts
+            }
+        };
+        let pipeline = pipelines.get_or_create(
+            device,
+            pipeline_layout,
+            desc.vertex_shader,
+            desc.fragment_shader,
+            MeshPipelineState {
+                cull_mode: desc.cull_mode,
+                ..Default::default()
+            },
+        )?;
+
+        let mut pool_sizes = vec![vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: frame_count as u32,
+        }];
+        if texture_count > 0 {
+            pool_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: texture_count * frame_count as u32,
+            });
+        }
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: frame_count as u32,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let layouts = vec![set_layout; frame_count];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info)? };
+
+        let parameter_buffers = Application::create_uniform_buffers(
+            instance,
+            device,
+            physical_device,
+            desc.parameters.len().max(MIN_PARAMETERS_SIZE) as u64,
+            frame_count,
+        )?;
+
+        // The textures and parameters are written with the other descriptors of the frame
+        let index = self.materials.len();
+        self.materials.push(ShaderMaterial {
+            pipeline,
+            pipeline_layout,
+            descriptor_pool,
+            descriptor_sets,
+            parameter_buffers,
+            parameters: desc.parameters.to_vec(),
+            sampler,
+            texture_views,
+            stale_frames: vec![true; frame_count],
+        });
+
+        Ok(index)
+    }
+
+    // Set 1 holds the parameters then `texture_count` textures
+    fn create_layouts(
+        device: &Device,
+        scene_set_layout: vk::DescriptorSetLayout,
+        texture_count: u32,
+    ) -> AppResult<(vk::DescriptorSetLayout, vk::PipelineLayout)> {
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = [vk::DescriptorType::UNIFORM_BUFFER]
+            .into_iter()
+            .chain(std::iter::repeat_n(
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                texture_count as usize,
+            ))
+            .enumerate()
+            .map(
+                |(binding, descriptor_type)| vk::DescriptorSetLayoutBinding {
+                    binding: binding as u32,
+                    descriptor_type,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    ..Default::default()
+                },
+            )
+            .collect();
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        let pipeline_layout =
+            Application::create_pipeline_layout(device, &[scene_set_layout, set_layout], &[])?;
+
+        Ok((set_layout, pipeline_layout))
+    }
+
+    pub(crate) fn pipeline(&self, index: usize) -> vk::Pipeline {
+        self.materials[index].pipeline
+    }
+
+    /// Layout the scene set is bound with for the material, as it has its own set
+    pub(crate) fn pipeline_layout(&self, index: usize) -> vk::PipelineLayout {
+        self.materials[index].pipeline_layout
+    }
+
+    /// Replaces the parameters of the material `index` from the next frames, fails when
+    /// `parameters` isn't the size of the previous ones
+    pub(crate) fn set_parameters(&mut self, index: usize, parameters: &[u8]) -> AppResult<()> {
+        let material = &mut self.materials[index];
+        if parameters.len() != material.parameters.len() {
+            return AppResult::Err(AppError::new(AppErrorType::InvalidMaterialParameters));
+        }
+
+        material.parameters.copy_from_slice(parameters);
+        material.stale_frames.fill(true);
+
+        Ok(())
+    }
+
+    /// Makes the materials sampling `view` sample `replacement` from the next frames
+    pub(crate) fn replace_view(&mut self, view: vk::ImageView, replacement: vk::ImageView) {
+        for material in self.materials.iter_mut() {
+            let mut replaced = false;
+            for texture_view in material.texture_views.iter_mut() {
+                if *texture_view == view {
+                    *texture_view = replacement;
+                    replaced = true;
+                }
+            }
+            if replaced {
+                material.stale_frames.fill(true);
+            }
+        }
+    }
+
+    /// Makes the materials sampling with `sampler` sample with `replacement` from the next frames
+    pub(crate) fn replace_sampler(&mut self, sampler: vk::Sampler, replacement: vk::Sampler) {
+        for material in self.materials.iter_mut() {
+            if material.sampler == sampler {
+                material.sampler = replacement;
+                material.stale_frames.fill(true);
+            }
+        }
+    }
+
+    /// Copies the outdated parameters of `frame` to its buffers and queues the writes of its
+    /// outdated sets, the frame must not be in use by the GPU
+    pub(crate) fn update(&mut self, frame: usize, descriptor_writes: &mut DescriptorWriteBatch) {
+        for material in self.materials.iter_mut() {
+            if !std::mem::take(&mut material.stale_frames[frame]) {
+                continue;
+            }
+
+            let buffer = &material.parameter_buffers[frame];
+            unsafe {
+                std::ptr::copy(
+                    material.parameters.as_ptr(),
+                    buffer.memory_map as *mut u8,
+                    material.parameters.len(),
+                );
+            }
+
+            let set = material.descriptor_sets[frame];
+            descriptor_writes.write_buffers(
+                set,
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                [vk::DescriptorBufferInfo {
+                    buffer: buffer.buffer,
+                    offset: 0,
+                    range: vk::WHOLE_SIZE,
+                }],
+            );
+            if !material.texture_views.is_empty() {
+                let sampler = material.sampler;
+                descriptor_writes.write_images(
+                    set,
+                    1,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    material
+                        .texture_views
+                        .iter()
+                        .map(|&image_view| vk::DescriptorImageInfo {
+                            sampler,
+                            image_view,
+                            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        }),
+                );
+            }
+        }
+    }
+
+    /// Binds the set of the material `index` for `frame`, after the scene set
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        index: usize,
+        frame: usize,
+    ) {
+        let material = &self.materials[index];
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                material.pipeline_layout,
+                1,
+                &[material.descriptor_sets[frame]],
+                &[],
+            );
+        }
+    }
+
+    /// Destroys the layouts and buffers of every material, the device must be idle. Their
+    /// pipelines are destroyed with the pipeline manager.
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for material in self.materials.drain(..) {
+                for buffer in material.parameter_buffers {
+                    handle_registry::unregister(buffer.buffer);
+                    device.destroy_buffer(buffer.buffer, None);
+                    handle_registry::unregister(buffer.memory);
+                    device.free_memory(buffer.memory, None);
+                }
+                handle_registry::unregister(material.descriptor_pool);
+                device.destroy_descriptor_pool(material.descriptor_pool, None);
+            }
+            for (_, (set_layout, pipeline_layout)) in self.layouts.drain() {
+                handle_registry::unregister(pipeline_layout);
+                device.destroy_pipeline_layout(pipeline_layout, None);
+                handle_registry::unregister(set_layout);
+                device.destroy_descriptor_set_layout(set_layout, None);
+            }
+        }
+    }
+}
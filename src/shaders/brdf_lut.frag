@@ -0,0 +1,55 @@
+#version 450
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+const float PI = 3.14159265359;
+const uint SAMPLE_COUNT = 256u;
+
+vec2 hammersley(uint i, uint count) {
+    uint bits = bitfieldReverse(i);
+    return vec2(float(i) / float(count), float(bits) * 2.3283064365386963e-10);
+}
+
+// Halfway vector around the +Z normal
+vec3 importanceSampleGgx(vec2 xi, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    return vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+}
+
+float geometrySchlickGgx(float nDotX, float roughness) {
+    float k = roughness * roughness / 2.0;
+    return nDotX / (nDotX * (1.0 - k) + k);
+}
+
+// Scale and bias of the Fresnel reflectance at normal incidence of the split sum approximation,
+// for the cosine of the view angle along u and the roughness along v
+void main() {
+    float nDotV = max(fragUv.x, 0.0001);
+    float roughness = fragUv.y;
+    vec3 view = vec3(sqrt(1.0 - nDotV * nDotV), 0.0, nDotV);
+
+    float scale = 0.0;
+    float bias = 0.0;
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec3 halfway = importanceSampleGgx(hammersley(i, SAMPLE_COUNT), roughness);
+        vec3 light = normalize(2.0 * dot(view, halfway) * halfway - view);
+
+        float nDotL = max(light.z, 0.0);
+        float nDotH = max(halfway.z, 0.0);
+        float vDotH = max(dot(view, halfway), 0.0);
+        if (nDotL > 0.0) {
+            float geometry = geometrySchlickGgx(nDotV, roughness) * geometrySchlickGgx(nDotL, roughness);
+            float visibility = geometry * vDotH / (nDotH * nDotV);
+            float fresnel = pow(1.0 - vDotH, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+
+    outColor = vec4(scale / float(SAMPLE_COUNT), bias / float(SAMPLE_COUNT), 0.0, 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,44 @@
+#version 450
+
+const uint LINEAR = 0;
+const uint REINHARD = 1;
+const uint ACES = 2;
+
+layout(binding = 0)uniform sampler2D inputColor;
+
+layout(push_constant)uniform Tonemap {
+    float exposure;
+    // One of the operators above
+    uint mode;
+} tonemap;
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 color) {
+    vec3 numerator = color * (2.51 * color + 0.03);
+    vec3 denominator = color * (2.43 * color + 0.59) + 0.14;
+    return clamp(numerator / denominator, 0.0, 1.0);
+}
+
+void main() {
+    vec4 color = texture(inputColor, fragUv);
+    vec3 exposed = color.rgb * tonemap.exposure;
+
+    vec3 mapped;
+    switch (tonemap.mode) {
+        case REINHARD:
+            mapped = exposed / (1.0 + exposed);
+            break;
+        case ACES:
+            mapped = aces(exposed);
+            break;
+        default:
+            mapped = exposed;
+            break;
+    }
+
+    outColor = vec4(mapped, color.a);
+}
\ No newline at end of file
@@ -0,0 +1,34 @@
+#version 450
+
+// Panorama covering every direction, the longitude along u and the latitude along v
+layout(binding = 0)uniform sampler2D equirect;
+
+layout(push_constant)uniform Face {
+    uint face;
+} target;
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+const float PI = 3.14159265359;
+
+// Direction of the texel of the face, following the cubemap face layout
+vec3 faceDirection(uint face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    switch (face) {
+        case 0u: return normalize(vec3(1.0, -st.y, -st.x));
+        case 1u: return normalize(vec3(-1.0, -st.y, st.x));
+        case 2u: return normalize(vec3(st.x, 1.0, st.y));
+        case 3u: return normalize(vec3(st.x, -1.0, -st.y));
+        case 4u: return normalize(vec3(st.x, -st.y, 1.0));
+        default: return normalize(vec3(-st.x, -st.y, -1.0));
+    }
+}
+
+// The world up being +Z, the top row of the panorama is straight up
+void main() {
+    vec3 direction = faceDirection(target.face, fragUv);
+    vec2 uv = vec2(atan(direction.y, direction.x) / (2.0 * PI) + 0.5, acos(direction.z) / PI);
+    outColor = vec4(texture(equirect, uv).rgb, 1.0);
+}
\ No newline at end of file
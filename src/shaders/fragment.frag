@@ -1,12 +1,15 @@
 #version 450
 
 layout(binding = 1)uniform sampler2D texSampler;
+// Baked lighting of the object, white when it isn't baked
+layout(binding = 2)uniform sampler2D lightmap;
 
 layout(location = 0)in vec3 fragColor;
 layout(location = 1)in vec2 fragUv;
+layout(location = 2)in vec2 fragLightmapUv;
 
 layout(location = 0)out vec4 outColor;
 
 void main() {
-    outColor = texture(texSampler, fragUv);
+    outColor = texture(texSampler, fragUv) * vec4(texture(lightmap, fragLightmapUv).rgb, 1.0);
 }
\ No newline at end of file
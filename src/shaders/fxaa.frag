@@ -0,0 +1,137 @@
+#version 450
+
+// Contrast below which a pixel isn't on an edge, absolute and relative to the brightest neighbour
+const float EDGE_THRESHOLD_MIN = 0.0312;
+const float EDGE_THRESHOLD_MAX = 0.125;
+// How much of the sub-pixel aliasing is removed, blurring the details
+const float SUBPIXEL_QUALITY = 0.75;
+// Steps taken along the edge in each direction to find its ends
+const int EDGE_STEPS = 12;
+const float STEP_SIZES[EDGE_STEPS] = float[](
+    1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0
+);
+
+layout(binding = 0)uniform sampler2D inputColor;
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+// Perceived brightness, the HDR colors being compressed so the thresholds keep their meaning
+float luma(vec2 uv) {
+    vec3 color = texture(inputColor, uv).rgb;
+    color /= 1.0 + color;
+    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(inputColor, 0));
+    vec4 color = texture(inputColor, fragUv);
+
+    float lumaCenter = luma(fragUv);
+    float lumaDown = luma(fragUv + vec2(0.0, -texel.y));
+    float lumaUp = luma(fragUv + vec2(0.0, texel.y));
+    float lumaLeft = luma(fragUv + vec2(-texel.x, 0.0));
+    float lumaRight = luma(fragUv + vec2(texel.x, 0.0));
+
+    float lumaMin = min(lumaCenter, min(min(lumaDown, lumaUp), min(lumaLeft, lumaRight)));
+    float lumaMax = max(lumaCenter, max(max(lumaDown, lumaUp), max(lumaLeft, lumaRight)));
+    float range = lumaMax - lumaMin;
+    if (range < max(EDGE_THRESHOLD_MIN, lumaMax * EDGE_THRESHOLD_MAX)) {
+        outColor = color;
+        return;
+    }
+
+    float lumaDownLeft = luma(fragUv + vec2(-texel.x, -texel.y));
+    float lumaUpRight = luma(fragUv + vec2(texel.x, texel.y));
+    float lumaUpLeft = luma(fragUv + vec2(-texel.x, texel.y));
+    float lumaDownRight = luma(fragUv + vec2(texel.x, -texel.y));
+
+    float lumaDownUp = lumaDown + lumaUp;
+    float lumaLeftRight = lumaLeft + lumaRight;
+    float lumaLeftCorners = lumaDownLeft + lumaUpLeft;
+    float lumaDownCorners = lumaDownLeft + lumaDownRight;
+    float lumaRightCorners = lumaDownRight + lumaUpRight;
+    float lumaUpCorners = lumaUpRight + lumaUpLeft;
+
+    // The edge is horizontal when the luma changes more along the vertical axis
+    float gradientHorizontal = abs(-2.0 * lumaLeft + lumaLeftCorners)
+        + abs(-2.0 * lumaCenter + lumaDownUp) * 2.0
+        + abs(-2.0 * lumaRight + lumaRightCorners);
+    float gradientVertical = abs(-2.0 * lumaUp + lumaUpCorners)
+        + abs(-2.0 * lumaCenter + lumaLeftRight) * 2.0
+        + abs(-2.0 * lumaDown + lumaDownCorners);
+    bool horizontal = gradientHorizontal >= gradientVertical;
+
+    // Side of the pixel the edge lies on
+    float luma1 = horizontal ? lumaDown : lumaLeft;
+    float luma2 = horizontal ? lumaUp : lumaRight;
+    float gradient1 = luma1 - lumaCenter;
+    float gradient2 = luma2 - lumaCenter;
+    bool steepest1 = abs(gradient1) >= abs(gradient2);
+    float gradientScaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+    float stepLength = horizontal ? texel.y : texel.x;
+    float lumaLocalAverage;
+    if (steepest1) {
+        stepLength = -stepLength;
+        lumaLocalAverage = 0.5 * (luma1 + lumaCenter);
+    } else {
+        lumaLocalAverage = 0.5 * (luma2 + lumaCenter);
+    }
+
+    // Walks along the edge, halfway between the pixel and its steepest neighbour
+    vec2 edgeUv = fragUv;
+    if (horizontal) {
+        edgeUv.y += stepLength * 0.5;
+    } else {
+        edgeUv.x += stepLength * 0.5;
+    }
+    vec2 offset = horizontal ? vec2(texel.x, 0.0) : vec2(0.0, texel.y);
+    vec2 uv1 = edgeUv - offset;
+    vec2 uv2 = edgeUv + offset;
+    float lumaEnd1 = luma(uv1) - lumaLocalAverage;
+    float lumaEnd2 = luma(uv2) - lumaLocalAverage;
+    bool reached1 = abs(lumaEnd1) >= gradientScaled;
+    bool reached2 = abs(lumaEnd2) >= gradientScaled;
+
+    for (int i = 0; i < EDGE_STEPS && !(reached1 && reached2); i++) {
+        if (!reached1) {
+            uv1 -= offset * STEP_SIZES[i];
+            lumaEnd1 = luma(uv1) - lumaLocalAverage;
+            reached1 = abs(lumaEnd1) >= gradientScaled;
+        }
+        if (!reached2) {
+            uv2 += offset * STEP_SIZES[i];
+            lumaEnd2 = luma(uv2) - lumaLocalAverage;
+            reached2 = abs(lumaEnd2) >= gradientScaled;
+        }
+    }
+
+    float distance1 = horizontal ? fragUv.x - uv1.x : fragUv.y - uv1.y;
+    float distance2 = horizontal ? uv2.x - fragUv.x : uv2.y - fragUv.y;
+    bool closest1 = distance1 < distance2;
+    float distanceFinal = min(distance1, distance2);
+    float edgeLength = distance1 + distance2;
+
+    // Only blends toward the end of the edge whose luma variation matches the pixel's
+    bool centerSmaller = lumaCenter < lumaLocalAverage;
+    bool correctVariation = ((closest1 ? lumaEnd1 : lumaEnd2) < 0.0) != centerSmaller;
+    float pixelOffset = correctVariation ? -distanceFinal / edgeLength + 0.5 : 0.0;
+
+    // Sub-pixel aliasing, from the contrast of the pixel with its 3x3 neighbourhood
+    float lumaCorners = lumaLeftCorners + lumaRightCorners;
+    float lumaAverage = (2.0 * (lumaDownUp + lumaLeftRight) + lumaCorners) / 12.0;
+    float subPixelOffset1 = clamp(abs(lumaAverage - lumaCenter) / range, 0.0, 1.0);
+    float subPixelOffset2 = (-2.0 * subPixelOffset1 + 3.0) * subPixelOffset1 * subPixelOffset1;
+    float subPixelOffset = subPixelOffset2 * subPixelOffset2 * SUBPIXEL_QUALITY;
+    pixelOffset = max(pixelOffset, subPixelOffset);
+
+    vec2 finalUv = fragUv;
+    if (horizontal) {
+        finalUv.y += pixelOffset * stepLength;
+    } else {
+        finalUv.x += pixelOffset * stepLength;
+    }
+    outColor = vec4(texture(inputColor, finalUv).rgb, color.a);
+}
\ No newline at end of file
@@ -0,0 +1,21 @@
+#version 450
+
+layout(binding = 0)uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+layout(location = 0)in vec2 inPosition;
+
+layout(location = 0)out vec3 fragViewPosition;
+layout(location = 1)out vec3 fragViewNormal;
+
+// The meshes lie in their XY plane, facing +Z
+void main() {
+    mat4 modelView = ubo.view * ubo.model;
+    vec4 position = modelView * vec4(inPosition, 0.0, 1.0);
+    gl_Position = ubo.proj * position;
+    fragViewPosition = position.xyz;
+    fragViewNormal = transpose(inverse(mat3(modelView))) * vec3(0.0, 0.0, 1.0);
+}
\ No newline at end of file
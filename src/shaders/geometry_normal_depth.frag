@@ -0,0 +1,16 @@
+#version 450
+
+layout(location = 0)in vec3 fragViewPosition;
+layout(location = 1)in vec3 fragViewNormal;
+
+// xyz: view space normal, w: linear depth
+layout(location = 0)out vec4 outGeometry;
+
+void main() {
+    vec3 normal = normalize(fragViewNormal);
+    // The meshes are seen from both sides, the normal faces the camera
+    if (dot(normal, fragViewPosition) > 0.0) {
+        normal = -normal;
+    }
+    outGeometry = vec4(normal, -fragViewPosition.z);
+}
\ No newline at end of file
@@ -0,0 +1,47 @@
+#version 450
+
+struct Particle {
+    // xyz: position, w: age in seconds
+    vec4 positionAge;
+    vec4 velocity;
+};
+
+layout(std430, set = 0, binding = 0)readonly buffer Particles {
+    Particle particles[];
+};
+
+// Indices of the live particles, one per instance
+layout(std430, set = 0, binding = 2)readonly buffer Alive {
+    uint alive[];
+};
+
+layout(push_constant)uniform Constants {
+    mat4 viewProj;
+    // Camera axes in world space, w: width of the quads for the right axis, lifetime of the
+    // particles for the up one
+    vec4 right;
+    vec4 up;
+    vec4 startColor;
+    vec4 endColor;
+} constants;
+
+layout(location = 0)out vec2 fragUv;
+layout(location = 1)out vec4 fragColor;
+
+// Corners of the two triangles of a quad
+const vec2 CORNERS[6] = vec2[](
+    vec2(-1.0, 1.0), vec2(1.0, 1.0), vec2(1.0, -1.0),
+    vec2(1.0, -1.0), vec2(-1.0, -1.0), vec2(-1.0, 1.0)
+);
+
+void main() {
+    Particle particle = particles[alive[gl_InstanceIndex]];
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec3 offset = (constants.right.xyz * corner.x + constants.up.xyz * corner.y)
+        * constants.right.w * 0.5;
+
+    gl_Position = constants.viewProj * vec4(particle.positionAge.xyz + offset, 1.0);
+    fragUv = vec2(corner.x + 1.0, 1.0 - corner.y) * 0.5;
+    float life = particle.positionAge.w / constants.up.w;
+    fragColor = mix(constants.startColor, constants.endColor, life);
+}
\ No newline at end of file
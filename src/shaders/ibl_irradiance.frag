@@ -0,0 +1,50 @@
+#version 450
+
+// Environment lighting the scene
+layout(binding = 0)uniform samplerCube environment;
+
+layout(push_constant)uniform Face {
+    uint face;
+} target;
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+const float PI = 3.14159265359;
+const float SAMPLE_STEP = 0.025;
+
+// Direction of the texel of the face, following the cubemap face layout
+vec3 faceDirection(uint face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    switch (face) {
+        case 0u: return normalize(vec3(1.0, -st.y, -st.x));
+        case 1u: return normalize(vec3(-1.0, -st.y, st.x));
+        case 2u: return normalize(vec3(st.x, 1.0, st.y));
+        case 3u: return normalize(vec3(st.x, -1.0, -st.y));
+        case 4u: return normalize(vec3(st.x, -st.y, 1.0));
+        default: return normalize(vec3(-st.x, -st.y, -1.0));
+    }
+}
+
+// Diffuse irradiance, the cosine weighted average of the environment over the hemisphere
+void main() {
+    vec3 normal = faceDirection(target.face, fragUv);
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+
+    vec3 irradiance = vec3(0.0);
+    float sampleCount = 0.0;
+    for (float phi = 0.0; phi < 2.0 * PI; phi += SAMPLE_STEP) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += SAMPLE_STEP) {
+            vec3 local = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 direction = tangent * local.x + bitangent * local.y + normal * local.z;
+            // The solid angle of the samples shrinks toward the pole
+            irradiance += texture(environment, direction).rgb * cos(theta) * sin(theta);
+            sampleCount += 1.0;
+        }
+    }
+
+    outColor = vec4(PI * irradiance / sampleCount, 1.0);
+}
\ No newline at end of file
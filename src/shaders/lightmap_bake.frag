@@ -0,0 +1,27 @@
+#version 450
+
+layout(binding = 0)uniform Bake {
+    mat4 model;
+    vec4 ambient;
+    // xyz: position, w: radius
+    vec4 lightPositions[16];
+    vec4 lightColors[16];
+    uint lightCount;
+} bake;
+
+layout(location = 0)in vec3 fragPosition;
+
+layout(location = 0)out vec4 outColor;
+
+void main() {
+    vec3 light = bake.ambient.rgb;
+    for (uint i = 0u; i < bake.lightCount; i++) {
+        float radius = bake.lightPositions[i].w;
+        float distance = length(bake.lightPositions[i].xyz - fragPosition);
+        float falloff = clamp(1.0 - distance / radius, 0.0, 1.0);
+        light += bake.lightColors[i].rgb * falloff * falloff;
+    }
+
+    // The alpha marks the texels covered by a triangle
+    outColor = vec4(min(light, vec3(1.0)), 1.0);
+}
\ No newline at end of file
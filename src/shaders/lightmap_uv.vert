@@ -0,0 +1,21 @@
+#version 450
+
+layout(binding = 0)uniform Bake {
+    mat4 model;
+    vec4 ambient;
+    // xyz: position, w: radius
+    vec4 lightPositions[16];
+    vec4 lightColors[16];
+    uint lightCount;
+} bake;
+
+layout(location = 0)in vec2 inPosition;
+layout(location = 3)in vec2 lightmapUv;
+
+layout(location = 0)out vec3 fragPosition;
+
+// Every triangle is rasterized where it lies in the lightmap, at its position in the scene
+void main() {
+    gl_Position = vec4(lightmapUv * 2.0 - 1.0, 0.0, 1.0);
+    fragPosition = (bake.model * vec4(inPosition, 0.0, 1.0)).xyz;
+}
\ No newline at end of file
@@ -0,0 +1,28 @@
+#version 450
+
+layout(binding = 0)uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+layout(location = 0)in vec2 inPosition;
+layout(location = 1)in vec3 inColor;
+layout(location = 2)in vec2 uv;
+layout(location = 3)in vec2 lightmapUv;
+layout(location = 4)in vec4 inTangent;
+
+layout(location = 0)out vec3 fragPosition;
+layout(location = 1)out vec2 fragUv;
+layout(location = 2)out vec3 fragNormal;
+layout(location = 3)out vec4 fragTangent;
+
+// The meshes lie in their XY plane, facing +Z
+void main() {
+    vec4 position = ubo.model * vec4(inPosition, 0.0, 1.0);
+    gl_Position = ubo.proj * ubo.view * position;
+    fragPosition = position.xyz;
+    fragUv = uv;
+    fragNormal = transpose(inverse(mat3(ubo.model))) * vec3(0.0, 0.0, 1.0);
+    fragTangent = vec4(mat3(ubo.model) * inTangent.xyz, inTangent.w);
+}
\ No newline at end of file
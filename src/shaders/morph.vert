@@ -0,0 +1,53 @@
+#version 450
+
+layout(binding = 0)uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+struct MorphDelta {
+    vec4 position;
+    vec4 color;
+};
+
+// Offsets of every vertex of the mesh for the first target, then for the second and so on
+layout(std430, set = 1, binding = 0)readonly buffer Deltas {
+    MorphDelta deltas[];
+};
+
+layout(std430, set = 1, binding = 1)readonly buffer Weights {
+    float weights[];
+};
+
+layout(push_constant)uniform MorphConstants {
+    uint vertexCount;
+    uint targetCount;
+} morph;
+
+layout(location = 0)in vec2 inPosition;
+layout(location = 1)in vec3 inColor;
+layout(location = 2)in vec2 uv;
+layout(location = 3)in vec2 lightmapUv;
+
+layout(location = 0)out vec3 fragColor;
+layout(location = 1)out vec2 fragUv;
+layout(location = 2)out vec2 fragLightmapUv;
+
+void main() {
+    vec2 position = inPosition;
+    vec3 color = inColor;
+    for (uint morphTarget = 0; morphTarget < morph.targetCount; morphTarget++) {
+        float weight = weights[morphTarget];
+        if (weight != 0.0) {
+            MorphDelta delta = deltas[morphTarget * morph.vertexCount + gl_VertexIndex];
+            position += weight * delta.position.xy;
+            color += weight * delta.color.rgb;
+        }
+    }
+
+    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(position, 0.0, 1.0);
+    fragColor = color;
+    fragUv = uv;
+    fragLightmapUv = lightmapUv;
+}
\ No newline at end of file
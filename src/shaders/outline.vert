@@ -0,0 +1,19 @@
+#version 450
+
+layout(binding = 0)uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+// Scale of the mesh around its origin, and color of the outline
+layout(push_constant)uniform OutlineConstants {
+    vec4 color;
+    float scale;
+} constants;
+
+layout(location = 0)in vec2 inPosition;
+
+void main() {
+    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(inPosition * constants.scale, 0.0, 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,12 @@
+#version 450
+
+layout(push_constant)uniform OutlineConstants {
+    vec4 color;
+    float scale;
+} constants;
+
+layout(location = 0)out vec4 outColor;
+
+void main() {
+    outColor = constants.color;
+}
\ No newline at end of file
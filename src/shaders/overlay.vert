@@ -0,0 +1,19 @@
+#version 450
+
+layout(push_constant)uniform Constants {
+    // Maps the pixels to the clip space
+    vec2 scale;
+} constants;
+
+layout(location = 0)in vec2 inPosition;
+layout(location = 1)in vec2 inUv;
+layout(location = 2)in vec4 inColor;
+
+layout(location = 0)out vec2 fragUv;
+layout(location = 1)out vec4 fragColor;
+
+void main() {
+    gl_Position = vec4(inPosition * constants.scale - 1.0, 0.0, 1.0);
+    fragUv = inUv;
+    fragColor = inColor;
+}
\ No newline at end of file
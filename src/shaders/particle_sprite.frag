@@ -0,0 +1,13 @@
+#version 450
+
+layout(location = 0)in vec2 fragUv;
+layout(location = 1)in vec4 fragColor;
+
+layout(location = 0)out vec4 outColor;
+
+void main() {
+    // Round sprite fading out towards its edge
+    float distance = length(fragUv * 2.0 - 1.0);
+    float falloff = 1.0 - smoothstep(0.5, 1.0, distance);
+    outColor = vec4(fragColor.rgb, fragColor.a * falloff);
+}
\ No newline at end of file
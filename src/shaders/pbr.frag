@@ -0,0 +1,26 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "pbr_lighting.glsl"
+
+// Distance to the closest surface over the range of the light, along each direction
+layout(set = 2, binding = 1)uniform samplerCube shadowMaps[4];
+
+// Keeps the surfaces from shadowing themselves, relative to the range of the light
+const float SHADOW_BIAS = 0.01;
+
+// The directional lights cast no shadow
+bool directionalShadow(vec3 direction) {
+    return false;
+}
+
+bool pointShadow(Light light, vec3 toLight, float distance) {
+    int shadowMap = int(light.radiance.w);
+    float range = light.attenuation.w;
+    if (shadowMap < 0 || distance >= range) {
+        return false;
+    }
+
+    float closest = texture(shadowMaps[shadowMap], -toLight).r;
+    return distance / range - SHADOW_BIAS > closest;
+}
\ No newline at end of file
@@ -0,0 +1,181 @@
+// Cook-Torrance shading of the PBR materials, the variants defining how the shadows are found
+
+// Albedo, bound in place of the texture of the scene set
+layout(binding = 1)uniform sampler2D albedoMap;
+
+layout(set = 1, binding = 0)uniform sampler2D normalMap;
+// Roughness in green, metallic in blue
+layout(set = 1, binding = 1)uniform sampler2D metallicRoughnessMap;
+layout(set = 1, binding = 2)uniform sampler2D occlusionMap;
+layout(set = 1, binding = 3)uniform sampler2D emissiveMap;
+
+struct Light {
+    // xyz: direction the light travels along for a directional light, w: 0
+    // xyz: position of a point light, w: radius of influence
+    vec4 position;
+    // Color scaled by the intensity, w: index of the shadow cubemap of a point light or -1
+    vec4 radiance;
+    // Constant, linear and quadratic terms of the distance attenuation, w: range of the shadow
+    vec4 attenuation;
+};
+
+layout(set = 2, binding = 0)uniform Lighting {
+    vec4 cameraPosition;
+    vec4 ambient;
+    // x: directional lights, y: point lights
+    uvec4 lightCount;
+    mat4 view;
+    mat4 inverseProj;
+    // xy: size of the target in pixels, z: near plane, w: far plane
+    vec4 cluster;
+    Light directionalLights[16];
+} lighting;
+layout(std430, set = 2, binding = 2)readonly buffer PointLights {
+    Light pointLights[];
+};
+
+// Light count of each cluster followed by the indices of its lights
+layout(std430, set = 2, binding = 3)readonly buffer Clusters {
+    uint clusters[];
+};
+
+layout(push_constant)uniform Factors {
+    vec4 baseColor;
+    // w: alpha under which the fragments are discarded
+    vec4 emissive;
+    float metallic;
+    float roughness;
+    // 1 for the opaque materials, whose alpha is ignored
+    float opaque;
+} factors;
+
+layout(location = 0)in vec3 fragPosition;
+layout(location = 1)in vec2 fragUv;
+layout(location = 2)in vec3 fragNormal;
+// w: sign of the bitangent
+layout(location = 3)in vec4 fragTangent;
+
+layout(location = 0)out vec4 outColor;
+
+const float PI = 3.14159265359;
+const uvec3 CLUSTER_GRID = uvec3(16u, 9u, 24u);
+const uint MAX_LIGHTS_PER_CLUSTER = 64u;
+
+float distributionGgx(float nDotH, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = nDotH * nDotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+float geometrySchlickGgx(float nDotX, float roughness) {
+    float r = roughness + 1.0;
+    float k = r * r / 8.0;
+    return nDotX / (nDotX * (1.0 - k) + k);
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+struct Surface {
+    vec3 albedo;
+    vec3 normal;
+    vec3 view;
+    float nDotV;
+    vec3 f0;
+    float roughness;
+    float metallic;
+};
+
+// Cook-Torrance specular and Lambert diffuse lighting of a light coming from `light`
+vec3 shade(Surface surface, vec3 light, vec3 radiance) {
+    float nDotL = dot(surface.normal, light);
+    if (nDotL <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 halfway = normalize(surface.view + light);
+    float nDotH = max(dot(surface.normal, halfway), 0.0);
+    float distribution = distributionGgx(nDotH, surface.roughness);
+    float geometry = geometrySchlickGgx(surface.nDotV, surface.roughness)
+        * geometrySchlickGgx(nDotL, surface.roughness);
+    vec3 fresnel = fresnelSchlick(max(dot(halfway, surface.view), 0.0), surface.f0);
+
+    vec3 specular = distribution * geometry * fresnel / (4.0 * surface.nDotV * nDotL);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - surface.metallic) * surface.albedo / PI;
+    return (diffuse + specular) * radiance * nDotL;
+}
+
+// Index of the cluster of the fragment, the depth slices growing exponentially
+uint clusterIndex() {
+    uvec2 tile = uvec2(gl_FragCoord.xy / lighting.cluster.xy * vec2(CLUSTER_GRID.xy));
+    tile = min(tile, CLUSTER_GRID.xy - 1u);
+    float depth = -(lighting.view * vec4(fragPosition, 1.0)).z;
+    float slices = log(depth / lighting.cluster.z) / log(lighting.cluster.w / lighting.cluster.z);
+    uint slice = uint(clamp(slices * float(CLUSTER_GRID.z), 0.0, float(CLUSTER_GRID.z - 1u)));
+    return tile.x + tile.y * CLUSTER_GRID.x + slice * CLUSTER_GRID.x * CLUSTER_GRID.y;
+}
+
+// Whether the fragment is hidden from a directional light travelling along `direction`
+bool directionalShadow(vec3 direction);
+// Whether the fragment is hidden from a point light, `toLight` being `distance` long
+bool pointShadow(Light light, vec3 toLight, float distance);
+
+// Lighting of the directional lights and of the point lights of the cluster of the fragment
+void main() {
+    vec4 albedo = texture(albedoMap, fragUv) * factors.baseColor;
+    if (albedo.a < factors.emissive.w) {
+        discard;
+    }
+    vec3 metallicRoughness = texture(metallicRoughnessMap, fragUv).rgb;
+    float roughness = clamp(metallicRoughness.g * factors.roughness, 0.04, 1.0);
+    float metallic = clamp(metallicRoughness.b * factors.metallic, 0.0, 1.0);
+    float occlusion = texture(occlusionMap, fragUv).r;
+
+    // Tangent space normal mapping, the tangent being orthogonalized after interpolation. The
+    // back faces of the double-sided materials face the other way.
+    vec3 normal = normalize(gl_FrontFacing ? fragNormal : -fragNormal);
+    vec3 tangent = normalize(fragTangent.xyz - dot(fragTangent.xyz, normal) * normal);
+    vec3 bitangent = cross(normal, tangent) * fragTangent.w;
+    vec3 mapped = texture(normalMap, fragUv).xyz * 2.0 - 1.0;
+    normal = normalize(mat3(tangent, bitangent, normal) * mapped);
+
+    vec3 view = normalize(lighting.cameraPosition.xyz - fragPosition);
+    Surface surface = Surface(
+        albedo.rgb,
+        normal,
+        view,
+        max(dot(normal, view), 0.0001),
+        mix(vec3(0.04), albedo.rgb, metallic),
+        roughness,
+        metallic
+    );
+
+    vec3 color = lighting.ambient.rgb * albedo.rgb * occlusion;
+    for (uint i = 0u; i < lighting.lightCount.x; i++) {
+        Light light = lighting.directionalLights[i];
+        if (directionalShadow(light.position.xyz)) {
+            continue;
+        }
+        color += shade(surface, -light.position.xyz, light.radiance.rgb);
+    }
+
+    uint cluster = clusterIndex() * (MAX_LIGHTS_PER_CLUSTER + 1u);
+    for (uint i = 0u; i < clusters[cluster]; i++) {
+        Light light = pointLights[clusters[cluster + 1u + i]];
+        vec3 toLight = light.position.xyz - fragPosition;
+        float distance = length(toLight);
+        vec3 terms = light.attenuation.xyz;
+        vec3 radiance = light.radiance.rgb;
+        radiance /= max(terms.x + terms.y * distance + terms.z * distance * distance, 1.0);
+
+        if (pointShadow(light, toLight, distance)) {
+            continue;
+        }
+        color += shade(surface, toLight / distance, radiance);
+    }
+
+    color += texture(emissiveMap, fragUv).rgb * factors.emissive.rgb;
+    outColor = vec4(color, factors.opaque > 0.5 ? 1.0 : albedo.a);
+}
\ No newline at end of file
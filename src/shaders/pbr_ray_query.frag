@@ -0,0 +1,43 @@
+#version 460
+#extension GL_GOOGLE_include_directive : require
+#extension GL_EXT_ray_query : require
+
+#include "pbr_lighting.glsl"
+
+// Draw items of the frame
+layout(set = 3, binding = 0)uniform accelerationStructureEXT scene;
+
+// Start of the shadow rays, keeping the surfaces from shadowing themselves
+const float RAY_MIN = 0.001;
+
+// Whether any mesh lies along `direction` within `distance` of the fragment
+bool occluded(vec3 direction, float distance) {
+    rayQueryEXT query;
+    rayQueryInitializeEXT(
+        query,
+        scene,
+        gl_RayFlagsOpaqueEXT | gl_RayFlagsTerminateOnFirstHitEXT,
+        0xFF,
+        fragPosition,
+        RAY_MIN,
+        direction,
+        distance
+    );
+    while (rayQueryProceedEXT(query)) {
+    }
+    return rayQueryGetIntersectionTypeEXT(query, true) != gl_RayQueryCommittedIntersectionNoneEXT;
+}
+
+// Traced up to the far plane
+bool directionalShadow(vec3 direction) {
+    return occluded(-direction, lighting.cluster.w);
+}
+
+// Traced for the lights casting shadows, within their shadow range
+bool pointShadow(Light light, vec3 toLight, float distance) {
+    if (int(light.radiance.w) < 0 || distance >= light.attenuation.w) {
+        return false;
+    }
+
+    return occluded(toLight / distance, distance);
+}
\ No newline at end of file
@@ -0,0 +1,24 @@
+#version 450
+
+layout(binding = 0)uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+// View and projection of the cubemap face being rendered, the ones of the camera are ignored
+layout(push_constant)uniform Face {
+    mat4 viewProj;
+    // xyz: position of the light, w: range of the shadow
+    vec4 light;
+} face;
+
+layout(location = 0)in vec2 inPosition;
+
+layout(location = 0)out vec3 fragPosition;
+
+void main() {
+    vec4 position = ubo.model * vec4(inPosition, 0.0, 1.0);
+    fragPosition = position.xyz;
+    gl_Position = face.viewProj * position;
+}
\ No newline at end of file
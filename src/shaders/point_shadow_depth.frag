@@ -0,0 +1,14 @@
+#version 450
+
+layout(push_constant)uniform Face {
+    mat4 viewProj;
+    // xyz: position of the light, w: range of the shadow
+    vec4 light;
+} face;
+
+layout(location = 0)in vec3 fragPosition;
+
+// Linear distance to the light over the range, compared the same way from every face
+void main() {
+    gl_FragDepth = length(fragPosition - face.light.xyz) / face.light.w;
+}
\ No newline at end of file
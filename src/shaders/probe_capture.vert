@@ -0,0 +1,28 @@
+#version 450
+
+layout(binding = 0)uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+// View and projection of the cubemap face being captured, the ones of the camera are ignored
+layout(push_constant)uniform Face {
+    mat4 viewProj;
+} face;
+
+layout(location = 0)in vec2 inPosition;
+layout(location = 1)in vec3 inColor;
+layout(location = 2)in vec2 uv;
+layout(location = 3)in vec2 lightmapUv;
+
+layout(location = 0)out vec3 fragColor;
+layout(location = 1)out vec2 fragUv;
+layout(location = 2)out vec2 fragLightmapUv;
+
+void main() {
+    gl_Position = face.viewProj * ubo.model * vec4(inPosition, 0.0, 1.0);
+    fragColor = inColor;
+    fragUv = uv;
+    fragLightmapUv = lightmapUv;
+}
\ No newline at end of file
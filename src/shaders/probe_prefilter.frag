@@ -0,0 +1,67 @@
+#version 450
+
+// Level 0 of the cubemap, as captured
+layout(binding = 0)uniform samplerCube capture;
+
+layout(push_constant)uniform Level {
+    uint face;
+    float roughness;
+} level;
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+const float PI = 3.14159265359;
+const uint SAMPLE_COUNT = 64u;
+
+// Direction of the texel of the face, following the cubemap face layout
+vec3 faceDirection(uint face, vec2 uv) {
+    vec2 st = uv * 2.0 - 1.0;
+    switch (face) {
+        case 0u: return normalize(vec3(1.0, -st.y, -st.x));
+        case 1u: return normalize(vec3(-1.0, -st.y, st.x));
+        case 2u: return normalize(vec3(st.x, 1.0, st.y));
+        case 3u: return normalize(vec3(st.x, -1.0, -st.y));
+        case 4u: return normalize(vec3(st.x, -st.y, 1.0));
+        default: return normalize(vec3(-st.x, -st.y, -1.0));
+    }
+}
+
+vec2 hammersley(uint i, uint count) {
+    uint bits = bitfieldReverse(i);
+    return vec2(float(i) / float(count), float(bits) * 2.3283064365386963e-10);
+}
+
+vec3 importanceSampleGgx(vec2 xi, vec3 normal, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    vec3 halfway = vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(tangent * halfway.x + bitangent * halfway.y + normal * halfway.z);
+}
+
+// Specular prefiltering with the GGX distribution, assuming the view direction is the normal
+void main() {
+    vec3 normal = faceDirection(level.face, fragUv);
+
+    vec3 color = vec3(0.0);
+    float totalWeight = 0.0;
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec3 halfway = importanceSampleGgx(hammersley(i, SAMPLE_COUNT), normal, level.roughness);
+        vec3 light = normalize(2.0 * dot(normal, halfway) * halfway - normal);
+
+        float nDotL = dot(normal, light);
+        if (nDotL > 0.0) {
+            color += texture(capture, light).rgb * nDotL;
+            totalWeight += nDotL;
+        }
+    }
+
+    outColor = vec4(color / max(totalWeight, 0.0001), 1.0);
+}
\ No newline at end of file
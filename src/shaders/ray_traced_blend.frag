@@ -0,0 +1,16 @@
+#version 450
+
+layout(binding = 0)uniform sampler2D rayTraced;
+
+layout(push_constant)uniform Constants {
+    float opacity;
+} constants;
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+void main() {
+    vec4 color = texture(rayTraced, fragUv);
+    outColor = vec4(color.rgb, color.a * constants.opacity);
+}
\ No newline at end of file
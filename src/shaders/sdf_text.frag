@@ -0,0 +1,17 @@
+#version 450
+
+// Signed distance to the glyph edges in the alpha channel, 0.5 on the edges
+layout(binding = 0)uniform sampler2D distanceField;
+
+layout(location = 0)in vec2 fragUv;
+layout(location = 1)in vec4 fragColor;
+
+layout(location = 0)out vec4 outColor;
+
+void main() {
+    float distance = texture(distanceField, fragUv).a;
+    // Antialiases over about a pixel of the screen, whatever the scale of the text
+    float width = fwidth(distance);
+    float coverage = smoothstep(0.5 - width, 0.5 + width, distance);
+    outColor = vec4(fragColor.rgb, fragColor.a * coverage);
+}
\ No newline at end of file
@@ -0,0 +1,17 @@
+#version 450
+
+layout(binding = 0)uniform samplerCube sky;
+
+// Maps the screen back to world directions, the view having no translation
+layout(push_constant)uniform Camera {
+    mat4 inverseViewProj;
+} camera;
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+void main() {
+    vec4 direction = camera.inverseViewProj * vec4(fragUv * 2.0 - 1.0, 1.0, 1.0);
+    outColor = texture(sky, direction.xyz / direction.w);
+}
\ No newline at end of file
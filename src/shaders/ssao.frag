@@ -0,0 +1,71 @@
+#version 450
+
+// Must match KERNEL_SIZE of the ssao effect
+const int KERNEL_SIZE = 16;
+
+// xyz: view space normal, w: linear depth, 0 where nothing is drawn
+layout(set = 0, binding = 0)uniform sampler2D geometry;
+
+// Points of the hemisphere around +Z
+layout(set = 1, binding = 0)uniform Kernel {
+    vec4 samples[KERNEL_SIZE];
+} kernel;
+// Rotations of the hemisphere around the normal, tiled over the screen
+layout(set = 1, binding = 1)uniform sampler2D noise;
+
+layout(push_constant)uniform Params {
+    mat4 proj;
+    // x: radius, y: depth bias, z: strength
+    vec4 params;
+} ssao;
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outOcclusion;
+
+void main() {
+    ivec2 size = textureSize(geometry, 0);
+    vec4 center = texelFetch(geometry, ivec2(gl_FragCoord.xy), 0);
+    if (center.w == 0.0) {
+        outOcclusion = vec4(1.0);
+        return;
+    }
+
+    float radius = ssao.params.x;
+    float bias = ssao.params.y;
+
+    // Back from the linear depth to the view space position
+    vec2 ndc = fragUv * 2.0 - 1.0;
+    vec3 position = vec3(ndc.x / ssao.proj[0][0], ndc.y / ssao.proj[1][1], -1.0) * center.w;
+    vec3 normal = normalize(center.xyz);
+
+    ivec2 noisePixel = ivec2(gl_FragCoord.xy) % textureSize(noise, 0);
+    vec3 rotation = vec3(texelFetch(noise, noisePixel, 0).xy, 0.0);
+    vec3 tangent = rotation - normal * dot(rotation, normal);
+    if (dot(tangent, tangent) < 1e-4) {
+        tangent = abs(normal.x) < 0.9 ? vec3(1.0, 0.0, 0.0) : vec3(0.0, 1.0, 0.0);
+        tangent -= normal * dot(tangent, normal);
+    }
+    tangent = normalize(tangent);
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+    float occlusion = 0.0;
+    for (int i = 0; i < KERNEL_SIZE; i++) {
+        vec3 samplePosition = position + tbn * kernel.samples[i].xyz * radius;
+        vec4 clip = ssao.proj * vec4(samplePosition, 1.0);
+        vec2 uv = clip.xy / clip.w * 0.5 + 0.5;
+        if (any(lessThan(uv, vec2(0.0))) || any(greaterThanEqual(uv, vec2(1.0)))) {
+            continue;
+        }
+
+        float depth = texelFetch(geometry, ivec2(uv * vec2(size)), 0).w;
+        if (depth == 0.0) {
+            continue;
+        }
+        // A surface in front of the sample occludes it, the ones far from the pixel fading out
+        float range = smoothstep(0.0, 1.0, radius / abs(center.w - depth));
+        occlusion += (depth <= -samplePosition.z - bias ? 1.0 : 0.0) * range;
+    }
+
+    outOcclusion = vec4(pow(1.0 - occlusion / float(KERNEL_SIZE), ssao.params.z));
+}
\ No newline at end of file
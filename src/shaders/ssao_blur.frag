@@ -0,0 +1,37 @@
+#version 450
+
+// Relative depth difference from which a pixel belongs to another surface
+const float DEPTH_TOLERANCE = 0.05;
+
+layout(binding = 0)uniform sampler2D occlusion;
+layout(binding = 1)uniform sampler2D scene;
+// xyz: view space normal, w: linear depth
+layout(binding = 2)uniform sampler2D geometry;
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    ivec2 maxPixel = textureSize(occlusion, 0) - 1;
+    float depth = texelFetch(geometry, pixel, 0).w;
+
+    // Averages the 4x4 block the noise repeats over
+    float sum = 0.0;
+    float weight = 0.0;
+    for (int x = -2; x < 2; x++) {
+        for (int y = -2; y < 2; y++) {
+            ivec2 neighbour = clamp(pixel + ivec2(x, y), ivec2(0), maxPixel);
+            float neighbourDepth = texelFetch(geometry, neighbour, 0).w;
+            if (abs(neighbourDepth - depth) <= DEPTH_TOLERANCE * depth) {
+                sum += texelFetch(occlusion, neighbour, 0).r;
+                weight += 1.0;
+            }
+        }
+    }
+    float ambient = weight > 0.0 ? sum / weight : 1.0;
+
+    vec4 color = texture(scene, fragUv);
+    outColor = vec4(color.rgb * ambient, color.a);
+}
\ No newline at end of file
@@ -0,0 +1,43 @@
+#version 450
+
+layout(binding = 0)uniform sampler2D inputColor;
+layout(binding = 1)uniform sampler2D historyColor;
+layout(binding = 2)uniform sampler2D velocity;
+
+layout(push_constant)uniform Constants {
+    // Weight of the history in the output, 0 when there is no valid history
+    float historyWeight;
+} constants;
+
+layout(location = 0)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    ivec2 maxPixel = textureSize(inputColor, 0) - 1;
+
+    vec3 current = texelFetch(inputColor, pixel, 0).rgb;
+
+    // The history is clamped to the colors around the pixel, rejecting what was disoccluded or
+    // changed since the previous frame
+    vec3 minColor = current;
+    vec3 maxColor = current;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            ivec2 neighbour = clamp(pixel + ivec2(x, y), ivec2(0), maxPixel);
+            vec3 color = texelFetch(inputColor, neighbour, 0).rgb;
+            minColor = min(minColor, color);
+            maxColor = max(maxColor, color);
+        }
+    }
+
+    vec2 previousUv = fragUv - texelFetch(velocity, pixel, 0).xy;
+    float weight = constants.historyWeight;
+    if (any(lessThan(previousUv, vec2(0.0))) || any(greaterThan(previousUv, vec2(1.0)))) {
+        weight = 0.0;
+    }
+
+    vec3 history = clamp(texture(historyColor, previousUv).rgb, minColor, maxColor);
+    outColor = vec4(mix(current, history, weight), 1.0);
+}
\ No newline at end of file
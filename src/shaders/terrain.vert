@@ -0,0 +1,20 @@
+#version 450
+
+layout(push_constant)uniform Constants {
+    mat4 viewProj;
+    float layerTiling;
+} constants;
+
+layout(location = 0)in vec3 inPosition;
+layout(location = 1)in vec3 inNormal;
+layout(location = 2)in vec2 inUv;
+
+layout(location = 0)out vec3 fragNormal;
+layout(location = 1)out vec2 fragUv;
+
+// The terrain is generated in world space
+void main() {
+    gl_Position = constants.viewProj * vec4(inPosition, 1.0);
+    fragNormal = inNormal;
+    fragUv = inUv;
+}
\ No newline at end of file
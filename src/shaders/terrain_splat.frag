@@ -0,0 +1,34 @@
+#version 450
+
+// Weight of each layer in its channel, covering the whole terrain
+layout(set = 0, binding = 0)uniform sampler2D splatMap;
+layout(set = 0, binding = 1)uniform sampler2D layers[4];
+
+layout(push_constant)uniform Constants {
+    mat4 viewProj;
+    // Repetitions of the layers across the terrain
+    float layerTiling;
+} constants;
+
+layout(location = 0)in vec3 fragNormal;
+layout(location = 1)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+// Fixed sun lighting the terrain, which isn't lit by the scene lights
+const vec3 SUN_DIRECTION = normalize(vec3(0.4, 0.3, 1.0));
+const float AMBIENT = 0.3;
+
+void main() {
+    vec4 weights = texture(splatMap, fragUv);
+    weights /= max(dot(weights, vec4(1.0)), 1e-4);
+
+    vec2 layerUv = fragUv * constants.layerTiling;
+    vec3 albedo = texture(layers[0], layerUv).rgb * weights.r
+        + texture(layers[1], layerUv).rgb * weights.g
+        + texture(layers[2], layerUv).rgb * weights.b
+        + texture(layers[3], layerUv).rgb * weights.a;
+
+    float diffuse = max(dot(normalize(fragNormal), SUN_DIRECTION), 0.0);
+    outColor = vec4(albedo * (AMBIENT + (1.0 - AMBIENT) * diffuse), 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,20 @@
+#version 450
+
+// Bound in place of the texture of the scene set
+layout(binding = 1)uniform sampler2DArray texSampler;
+layout(binding = 2)uniform sampler2D lightmap;
+
+layout(push_constant)uniform Layer {
+    uint index;
+} layer;
+
+layout(location = 0)in vec3 fragColor;
+layout(location = 1)in vec2 fragUv;
+layout(location = 2)in vec2 fragLightmapUv;
+
+layout(location = 0)out vec4 outColor;
+
+void main() {
+    vec4 color = texture(texSampler, vec3(fragUv, float(layer.index)));
+    outColor = color * vec4(texture(lightmap, fragLightmapUv).rgb, 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,22 @@
+#version 450
+
+layout(binding = 0)uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+    // Without the jitter of the projection, this frame and the previous one
+    mat4 modelViewProj;
+    mat4 previousModelViewProj;
+} ubo;
+
+layout(location = 0)in vec2 inPosition;
+
+layout(location = 0)out vec4 fragClipPosition;
+layout(location = 1)out vec4 fragPreviousClipPosition;
+
+void main() {
+    vec4 position = vec4(inPosition, 0.0, 1.0);
+    gl_Position = ubo.proj * ubo.view * ubo.model * position;
+    fragClipPosition = ubo.modelViewProj * position;
+    fragPreviousClipPosition = ubo.previousModelViewProj * position;
+}
\ No newline at end of file
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0)in vec4 fragClipPosition;
+layout(location = 1)in vec4 fragPreviousClipPosition;
+
+layout(location = 0)out vec2 outVelocity;
+
+// Motion in texture coordinates since the previous frame, the jitter left out so a still scene
+// has no velocity
+void main() {
+    vec2 ndc = fragClipPosition.xy / fragClipPosition.w;
+    vec2 previousNdc = fragPreviousClipPosition.xy / fragPreviousClipPosition.w;
+    outVelocity = (ndc - previousNdc) * 0.5;
+}
\ No newline at end of file
@@ -9,12 +9,15 @@ layout(binding = 0)uniform UniformBufferObject {
 layout(location = 0)in vec2 inPosition;
 layout(location = 1)in vec3 inColor;
 layout(location = 2)in vec2 uv;
+layout(location = 3)in vec2 lightmapUv;
 
 layout(location = 0)out vec3 fragColor;
 layout(location = 1)out vec2 fragUv;
+layout(location = 2)out vec2 fragLightmapUv;
 
 void main() {
     gl_Position = ubo.proj * ubo.view * ubo.model * vec4(inPosition, 0.0, 1.0);
     fragColor = inColor;
     fragUv = uv;
+    fragLightmapUv = lightmapUv;
 }
\ No newline at end of file
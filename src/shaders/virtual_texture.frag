@@ -0,0 +1,26 @@
+#version 450
+
+// Set 0 is the scene one, only its uniform buffer is read by the vertex shader
+layout(set = 1, binding = 0)uniform sampler2D virtualTexture;
+// Most detailed level that can be sampled in each region covered by a page of level 0
+layout(set = 1, binding = 1)uniform usampler2D pageTable;
+// Most detailed level wanted in each region, read back to request the missing pages
+layout(set = 1, binding = 2)buffer Feedback {
+    uint wantedLevels[];
+} feedback;
+
+layout(location = 0)in vec3 fragColor;
+layout(location = 1)in vec2 fragUv;
+
+layout(location = 0)out vec4 outColor;
+
+void main() {
+    ivec2 regionCount = textureSize(pageTable, 0);
+    ivec2 region = min(ivec2(fract(fragUv) * vec2(regionCount)), regionCount - 1);
+
+    float wantedLevel = max(textureQueryLod(virtualTexture, fragUv).x, 0.0);
+    atomicMin(feedback.wantedLevels[region.y * regionCount.x + region.x], uint(wantedLevel));
+
+    uint residentLevel = texelFetch(pageTable, region, 0).r;
+    outColor = textureLod(virtualTexture, fragUv, max(wantedLevel, float(residentLevel)));
+}
\ No newline at end of file
@@ -0,0 +1,256 @@
+use ash::{vk, Device, Instance};
+use cgmath::{SquareMatrix, Vector4};
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    geometry::Mat4,
+    handle_registry, load_shader,
+    post::create_multisampled_fullscreen_pipeline,
+    texture_array,
+    texture_loader::DecodedTexture,
+    AppResult, Application, SyncPool, TextureHolder,
+};
+
+/// Number of faces of a cubemap, loaded in the +X, -X, +Y, -Y, +Z, -Z order
+pub const CUBEMAP_FACES: usize = 6;
+
+/// Uploads six square faces of the same size and format as a cubemap, in the layer order of
+/// `CUBEMAP_FACES`. The faces are decompressed if the device can't sample their block-compressed
+/// format, see `texture_loader::fit_to_device`.
+pub(crate) fn upload_cubemap(
+    instance: &Instance,
+    device: &Device,
+    queue: vk::Queue,
+    physical_device: vk::PhysicalDevice,
+    command_pool: vk::CommandPool,
+    sync_pool: &SyncPool,
+    faces: &mut [DecodedTexture],
+) -> AppResult<TextureHolder> {
+    if faces.len() != CUBEMAP_FACES
+        || faces[0].width() != faces[0].height()
+        || !texture_array::same_layout(faces)
+    {
+        return AppResult::Err(AppError::new(AppErrorType::InvalidCubemapFaces));
+    }
+
+    texture_array::upload_layers(
+        instance,
+        device,
+        queue,
+        physical_device,
+        command_pool,
+        sync_pool,
+        faces,
+        vk::ImageViewType::CUBE,
+    )
+}
+
+/// Background of the scene sampled from a cubemap in the direction of each pixel. It is drawn
+/// first in the scene render pass, as a full-screen triangle the draw items then cover.
+pub(crate) struct Skybox {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    set_layout: vk::DescriptorSetLayout,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    cubemap: TextureHolder,
+}
+
+impl Skybox {
+    pub(crate) fn new(
+        device: &Device,
+        scene_render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        cubemap: TextureHolder,
+    ) -> AppResult<Self> {
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        }];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<Mat4>() as u32,
+            }],
+        )?;
+        let pipeline = create_multisampled_fullscreen_pipeline(
+            device,
+            scene_render_pass,
+            samples,
+            pipeline_layout,
+            &load_shader("skybox.frag", include_bytes!("spirv/skybox.spv"))?,
+        )?;
+        handle_registry::register(pipeline);
+
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            max_lod: vk::LOD_CLAMP_NONE,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+        handle_registry::register(sampler);
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: 1,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &set_layout as *const _,
+            ..Default::default()
+        };
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+
+        let skybox = Self {
+            pipeline,
+            pipeline_layout,
+            set_layout,
+            sampler,
+            descriptor_pool,
+            descriptor_set,
+            cubemap,
+        };
+        skybox.write_descriptor_set(device);
+
+        Ok(skybox)
+    }
+
+    fn write_descriptor_set(&self, device: &Device) {
+        let image_info = vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: self.cubemap.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: &image_info as *const _,
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+    }
+
+    /// Samples `cubemap` instead of the current one, which is destroyed. The device must be idle.
+    pub(crate) fn replace_cubemap(&mut self, device: &Device, cubemap: TextureHolder) {
+        let previous = std::mem::replace(&mut self.cubemap, cubemap);
+        unsafe { Self::destroy_cubemap(device, &previous) };
+        self.write_descriptor_set(device);
+    }
+
+    /// View of the whole cubemap, in the shader read only layout
+    pub(crate) fn cubemap_view(&self) -> vk::ImageView {
+        self.cubemap.view
+    }
+
+    pub(crate) fn memory_size(&self, device: &Device) -> u64 {
+        unsafe {
+            device
+                .get_image_memory_requirements(self.cubemap.image.image)
+                .size
+        }
+    }
+
+    /// Draws the sky seen through `view` and `proj`, to record in the scene render pass before
+    /// the draw items
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        view: Mat4,
+        proj: Mat4,
+    ) {
+        // The sky is infinitely far away, so only the rotation of the view matters
+        let mut rotation = view;
+        rotation.w = Vector4::new(0.0, 0.0, 0.0, 1.0);
+        let inverse_view_proj: [[f32; 4]; 4] = (proj * rotation)
+            .invert()
+            .unwrap_or(Mat4::identity())
+            .into();
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::slice::from_raw_parts(
+                    inverse_view_proj.as_ptr() as *const u8,
+                    std::mem::size_of_val(&inverse_view_proj),
+                ),
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    unsafe fn destroy_cubemap(device: &Device, cubemap: &TextureHolder) {
+        handle_registry::unregister(cubemap.view);
+        device.destroy_image_view(cubemap.view, None);
+        handle_registry::unregister(cubemap.image.image);
+        device.destroy_image(cubemap.image.image, None);
+        handle_registry::unregister(cubemap.image.memory);
+        device.free_memory(cubemap.image.memory, None);
+    }
+
+    /// Destroys the cubemap and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            Self::destroy_cubemap(device, &self.cubemap);
+
+            handle_registry::unregister(self.descriptor_pool);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            handle_registry::unregister(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.set_layout);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
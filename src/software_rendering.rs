@@ -0,0 +1,60 @@
+use std::env;
+
+use ash::{vk, Instance};
+use tracing::warn;
+
+/// Environment variable setting the `SoftwareRendering` policy of `Application::create`, one of
+/// `fallback`, `require` or `forbid`
+pub const SOFTWARE_RENDERING_ENV: &str = "VULKAN_TUTORIAL_SOFTWARE_RENDERING";
+
+/// Whether a CPU implementation of Vulkan, e.g. lavapipe or SwiftShader, may be picked instead of
+/// a GPU, so the application can run on machines and CI runners without one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SoftwareRendering {
+    /// A CPU implementation is only picked when no GPU is suitable, with a warning
+    #[default]
+    Fallback,
+    /// Only CPU implementations are picked, e.g. for reproducible CI runs
+    Require,
+    /// CPU implementations are never picked
+    Forbid,
+}
+
+impl SoftwareRendering {
+    /// Reads the policy from `VULKAN_TUTORIAL_SOFTWARE_RENDERING`, `Fallback` when it isn't set
+    /// or holds an unknown value
+    pub fn from_env() -> Self {
+        let Some(value) = env::var_os(SOFTWARE_RENDERING_ENV) else {
+            return Self::default();
+        };
+
+        match value.to_string_lossy().to_ascii_lowercase().as_str() {
+            "fallback" => Self::Fallback,
+            "require" => Self::Require,
+            "forbid" => Self::Forbid,
+            _ => {
+                warn!(
+                    variable = SOFTWARE_RENDERING_ENV,
+                    ?value,
+                    "ignored variable, it isn't fallback, require or forbid"
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Whether a device of this kind may be picked
+    pub fn accepts(self, software: bool) -> bool {
+        match self {
+            Self::Fallback => true,
+            Self::Require => software,
+            Self::Forbid => !software,
+        }
+    }
+}
+
+/// Returns whether `device` is a CPU implementation
+pub(crate) fn is_software_device(instance: &Instance, device: vk::PhysicalDevice) -> bool {
+    let properties = unsafe { instance.get_physical_device_properties(device) };
+    properties.device_type == vk::PhysicalDeviceType::CPU
+}
@@ -0,0 +1,135 @@
+use ash::{vk, Device};
+
+use crate::AppResult;
+
+/// Kind of work submitted during a frame, command buffers of a queue are executed in this order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WorkType {
+    Upload,
+    Shadow,
+    Main,
+    Ui,
+    /// Preparation of the swapchain image for the presentation
+    Present,
+}
+
+/// Work gathered for one queue
+struct Batch {
+    queue: vk::Queue,
+    command_buffers: Vec<(WorkType, vk::CommandBuffer)>,
+    wait_semaphores: Vec<vk::Semaphore>,
+    wait_stages: Vec<vk::PipelineStageFlags>,
+    signal_semaphores: Vec<vk::Semaphore>,
+}
+
+/// Gathers the command buffers of a frame and submits them with a single `vkQueueSubmit` per
+/// queue.
+///
+/// The command buffers of a queue are executed in `WorkType` order and their synchronization is
+/// left to pipeline barriers, only the semaphores chaining queues (or the presentation engine)
+/// are waited and signaled, once per queue.
+///
+/// The batches and their lists are recycled from frame to frame, so a frame submitting as much
+/// work as the previous ones doesn't allocate.
+#[derive(Default)]
+pub(crate) struct SubmitScheduler {
+    batches: Vec<Batch>,
+    // Batches of the previous frames, emptied
+    spare_batches: Vec<Batch>,
+    // Command buffers of the batch being submitted, in execution order
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+impl SubmitScheduler {
+    pub(crate) fn add(
+        &mut self,
+        queue: vk::Queue,
+        work_type: WorkType,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        self.batch(queue)
+            .command_buffers
+            .push((work_type, command_buffer));
+    }
+
+    /// Waits `semaphore` at `stage` before running the work of `queue`
+    pub(crate) fn wait(
+        &mut self,
+        queue: vk::Queue,
+        semaphore: vk::Semaphore,
+        stage: vk::PipelineStageFlags,
+    ) {
+        let batch = self.batch(queue);
+        batch.wait_semaphores.push(semaphore);
+        batch.wait_stages.push(stage);
+    }
+
+    /// Signals `semaphore` once the work of `queue` is done
+    pub(crate) fn signal(&mut self, queue: vk::Queue, semaphore: vk::Semaphore) {
+        self.batch(queue).signal_semaphores.push(semaphore);
+    }
+
+    /// Submits the gathered work, queues being submitted in the order they were first used so
+    /// semaphores are signaled before being waited on. `fences` are signaled once the work of
+    /// their queue is done.
+    pub(crate) fn flush(
+        &mut self,
+        device: &Device,
+        fences: &[(vk::Queue, vk::Fence)],
+    ) -> AppResult<()> {
+        for mut batch in self.batches.drain(..) {
+            // Stable, keeps the submission order of a work type
+            batch
+                .command_buffers
+                .sort_by_key(|(work_type, _)| *work_type);
+            self.command_buffers.clear();
+            self.command_buffers
+                .extend(batch.command_buffers.iter().map(|(_, cb)| *cb));
+
+            // The builders borrow the lists, which outlive the submission
+            let submit_info = vk::SubmitInfo::default()
+                .wait_semaphores(&batch.wait_semaphores)
+                .wait_dst_stage_mask(&batch.wait_stages)
+                .command_buffers(&self.command_buffers)
+                .signal_semaphores(&batch.signal_semaphores);
+
+            let fence = fences
+                .iter()
+                .find(|(queue, _)| *queue == batch.queue)
+                .map_or(vk::Fence::null(), |(_, fence)| *fence);
+
+            let submitted = unsafe { device.queue_submit(batch.queue, &[submit_info], fence) };
+
+            batch.command_buffers.clear();
+            batch.wait_semaphores.clear();
+            batch.wait_stages.clear();
+            batch.signal_semaphores.clear();
+            self.spare_batches.push(batch);
+            submitted?;
+        }
+
+        Ok(())
+    }
+
+    fn batch(&mut self, queue: vk::Queue) -> &mut Batch {
+        let index = match self.batches.iter().position(|batch| batch.queue == queue) {
+            Some(index) => index,
+            None => {
+                let batch = match self.spare_batches.pop() {
+                    Some(batch) => Batch { queue, ..batch },
+                    None => Batch {
+                        queue,
+                        command_buffers: vec![],
+                        wait_semaphores: vec![],
+                        wait_stages: vec![],
+                        signal_semaphores: vec![],
+                    },
+                };
+                self.batches.push(batch);
+                self.batches.len() - 1
+            }
+        };
+
+        &mut self.batches[index]
+    }
+}
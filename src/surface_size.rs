@@ -0,0 +1,32 @@
+use dpi::{LogicalSize, PhysicalSize};
+
+/// Size of the rendering surface, in physical pixels, along with the scale factor of the monitor
+/// it is displayed on.
+///
+/// The swapchain and the viewport always use the physical size, layers drawing UI or text should
+/// use the logical size (or multiply their sizes by `scale_factor`) to keep a constant apparent
+/// size across monitors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceSize {
+    pub physical: PhysicalSize<u32>,
+    pub scale_factor: f64,
+}
+
+impl SurfaceSize {
+    pub fn new(physical: PhysicalSize<u32>, scale_factor: f64) -> Self {
+        Self {
+            physical,
+            scale_factor,
+        }
+    }
+
+    /// Size of the surface in logical (scale independent) pixels
+    pub fn logical(&self) -> LogicalSize<f64> {
+        self.physical.to_logical(self.scale_factor)
+    }
+
+    /// Whether the surface has no area, e.g. when the window is minimized
+    pub fn is_empty(&self) -> bool {
+        self.physical.width == 0 || self.physical.height == 0
+    }
+}
@@ -0,0 +1,320 @@
+use ash::{khr::swapchain, vk, Device};
+
+use crate::{
+    context::Context, handle_registry, present_transfer::PresentTransfer,
+    queue_families::QueueFamilyIndice, AppResult, Application, ErrorContext, EventLog,
+    RendererEvent, SurfaceSize, SwapchainSharing,
+};
+
+/// Swapchain along with the views of its images, the framebuffers the composite pass draws into
+/// them and the transfer of the images to the present queue family
+pub(crate) struct Swapchain {
+    pub swapchain_ext: swapchain::Device,
+    pub swapchain: vk::SwapchainKHR,
+    pub swapchain_images: Vec<vk::Image>,
+    pub swapchain_image_views: Vec<vk::ImageView>,
+    pub image_format: vk::Format,
+    pub color_space: vk::ColorSpaceKHR,
+    pub present_mode: vk::PresentModeKHR,
+    pub extent: vk::Extent2D,
+    pub readable: bool,
+    // Created once the composite render pass exists, see `create_frame_buffers`
+    pub frame_buffers: Vec<vk::Framebuffer>,
+    // `None` when no transfer is needed, see `PresentTransfer::new`
+    pub present_transfer: Option<PresentTransfer>,
+}
+
+impl Swapchain {
+    /// Creates the swapchain of the surface of `context` along with the views of its images, the
+    /// framebuffers and the present transfer being created afterwards
+    pub(crate) fn new(
+        context: &Context,
+        indices: QueueFamilyIndice,
+        surface_size: SurfaceSize,
+        sharing: SwapchainSharing,
+        preferred_present_mode: Option<vk::PresentModeKHR>,
+    ) -> AppResult<Self> {
+        let swapchain_support =
+            Context::query_swapchain_support(context.physical_device, &context.surface)?;
+
+        let surface_format = Self::choose_swap_surface_format(&swapchain_support.formats);
+        let present_mode = Self::choose_swap_present_mode(
+            &swapchain_support.present_modes,
+            preferred_present_mode,
+        );
+        let extent = Self::choose_swap_extent(swapchain_support.capabilities, surface_size);
+
+        let mut image_count = swapchain_support.capabilities.min_image_count + 1;
+        if swapchain_support.capabilities.max_image_count != 0 {
+            image_count = image_count.clamp(
+                swapchain_support.capabilities.min_image_count,
+                swapchain_support.capabilities.min_image_count,
+            );
+        }
+
+        // Allows reading back the presented images for screenshots when the surface supports it
+        let readable = swapchain_support
+            .capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC);
+        let mut image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        if readable {
+            image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+
+        let mut create_info = vk::SwapchainCreateInfoKHR {
+            surface: context.surface.surface,
+            min_image_count: image_count,
+            image_format: surface_format.format,
+            image_color_space: surface_format.color_space,
+            image_extent: extent,
+            image_array_layers: 1,
+            image_usage,
+            image_sharing_mode: vk::SharingMode::EXCLUSIVE,
+            pre_transform: swapchain_support.capabilities.current_transform,
+            composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+            present_mode,
+            clipped: true.into(),
+            ..Default::default()
+        };
+
+        let graphics = indices.graphics_family.unwrap();
+        let present = indices.present_family.unwrap();
+        let indices = [graphics, present];
+        if graphics != present && sharing == SwapchainSharing::Concurrent {
+            create_info.image_sharing_mode = vk::SharingMode::CONCURRENT;
+            create_info.queue_family_index_count = indices.len() as u32;
+            create_info.p_queue_family_indices = &indices as *const _;
+        }
+
+        let swapchain_ext = swapchain::Device::new(&context.instance, &context.device);
+        let swapchain = unsafe { swapchain_ext.create_swapchain(&create_info, None)? };
+        handle_registry::register(swapchain);
+
+        let swapchain_images = unsafe { swapchain_ext.get_swapchain_images(swapchain)? };
+        let swapchain_image_views =
+            Self::create_image_views(&context.device, &swapchain_images, surface_format.format)?;
+
+        Ok(Self {
+            swapchain_ext,
+            swapchain,
+            swapchain_images,
+            swapchain_image_views,
+            image_format: surface_format.format,
+            color_space: surface_format.color_space,
+            present_mode,
+            extent,
+            readable,
+            frame_buffers: Vec::new(),
+            present_transfer: None,
+        })
+    }
+
+    /// Chooses the best surface format avaible for the swapchains.
+    ///
+    /// # Panic
+    /// Panics if `avaible_format` is empty.
+    pub(crate) fn choose_swap_surface_format(
+        avaible_formats: &Vec<vk::SurfaceFormatKHR>,
+    ) -> vk::SurfaceFormatKHR {
+        assert!(!avaible_formats.is_empty());
+
+        for &format in avaible_formats {
+            if format.format == vk::Format::B8G8R8A8_SRGB
+                && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            {
+                return format;
+            }
+        }
+
+        avaible_formats[0]
+    }
+
+    /// Chooses `preferred`, mailbox when none is preferred, falling back to FIFO which every
+    /// surface supports
+    pub(crate) fn choose_swap_present_mode(
+        avaible_present_modes: &Vec<vk::PresentModeKHR>,
+        preferred: Option<vk::PresentModeKHR>,
+    ) -> vk::PresentModeKHR {
+        let preferred = preferred.unwrap_or(vk::PresentModeKHR::MAILBOX);
+        for &present_mode in avaible_present_modes {
+            if present_mode == preferred {
+                return present_mode;
+            }
+        }
+
+        vk::PresentModeKHR::FIFO
+    }
+
+    /// Chooses the swapchain extent, in physical pixels.
+    ///
+    /// Some platforms (e.g. Wayland) let the swapchain define the surface size, in which case the
+    /// physical size of the window is used.
+    pub(crate) fn choose_swap_extent(
+        capabilities: vk::SurfaceCapabilitiesKHR,
+        surface_size: SurfaceSize,
+    ) -> vk::Extent2D {
+        if capabilities.current_extent.width != std::u32::MAX {
+            return capabilities.current_extent;
+        }
+
+        vk::Extent2D {
+            width: surface_size.physical.width.clamp(
+                capabilities.min_image_extent.width,
+                capabilities.max_image_extent.width,
+            ),
+            height: surface_size.physical.height.clamp(
+                capabilities.min_image_extent.height,
+                capabilities.max_image_extent.height,
+            ),
+        }
+    }
+
+    fn create_image_views(
+        device: &Device,
+        images: &Vec<vk::Image>,
+        image_format: vk::Format,
+    ) -> AppResult<Vec<vk::ImageView>> {
+        let mut image_views = Vec::with_capacity(images.len());
+
+        for &image in images {
+            image_views.push(Application::create_image_view(
+                device,
+                image,
+                image_format,
+                1,
+            )?);
+        }
+
+        Ok(image_views)
+    }
+
+    /// Creates the framebuffers of the swapchain images for the composite pass `render_pass`
+    pub(crate) fn create_frame_buffers(
+        &mut self,
+        device: &Device,
+        render_pass: vk::RenderPass,
+    ) -> AppResult<()> {
+        for &attachment in self.swapchain_image_views.iter() {
+            let attachments = [attachment];
+            let frame_buffer_info = vk::FramebufferCreateInfo {
+                render_pass,
+                attachment_count: attachments.len() as u32,
+                p_attachments: attachments.as_ptr(),
+                width: self.extent.width,
+                height: self.extent.height,
+                layers: 1,
+                ..Default::default()
+            };
+            let frame_buffer = unsafe { device.create_framebuffer(&frame_buffer_info, None)? };
+            self.frame_buffers
+                .push(handle_registry::register(frame_buffer));
+        }
+
+        Ok(())
+    }
+
+    /// Destroys the framebuffers, the present transfer, the image views and the swapchain, the
+    /// device must be idle. Does nothing if they are already destroyed, e.g. when the recreation
+    /// of the swapchain failed.
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for frame_buffer in self.frame_buffers.drain(..) {
+                handle_registry::unregister(frame_buffer);
+                device.destroy_framebuffer(frame_buffer, None);
+            }
+
+            if let Some(transfer) = self.present_transfer.take() {
+                transfer.destroy(device);
+            }
+
+            for image_view in self.swapchain_image_views.drain(..) {
+                handle_registry::unregister(image_view);
+                device.destroy_image_view(image_view, None)
+            }
+
+            if self.swapchain != vk::SwapchainKHR::null() {
+                handle_registry::unregister(self.swapchain);
+                self.swapchain_ext.destroy_swapchain(self.swapchain, None);
+                self.swapchain = vk::SwapchainKHR::null();
+            }
+        }
+    }
+}
+
+impl Application {
+    #[tracing::instrument(skip_all)]
+    pub fn recreate_swapchain(&mut self) -> AppResult<()> {
+        unsafe {
+            self.context.device.device_wait_idle()?;
+        }
+
+        self.swapchain.destroy(&self.context.device);
+        self.presented_image = None;
+
+        let queue_families = Context::find_queue_families(
+            &self.context.instance,
+            self.context.physical_device,
+            &self.context.surface,
+        )?;
+        self.swapchain = Swapchain::new(
+            &self.context,
+            queue_families,
+            self.surface_size,
+            self.swapchain_sharing,
+            self.present_mode,
+        )
+        .context("recreating the swapchain")?;
+        Self::log_swapchain_created(&self.event_log, &self.swapchain);
+        self.swapchain.present_transfer = PresentTransfer::new(
+            &self.context.device,
+            queue_families,
+            self.swapchain_sharing,
+            &self.swapchain.swapchain_images,
+            self.frames_in_flight as u32,
+        )
+        .context("recreating the present transfer")?;
+
+        self.swapchain
+            .create_frame_buffers(
+                &self.context.device,
+                self.post_chain.composite_render_pass(),
+            )
+            .context("recreating the swapchain framebuffers")?;
+
+        self.destroy_scene_framebuffers();
+        self.post_chain
+            .resize(
+                &self.context.instance,
+                &self.context.device,
+                self.context.physical_device,
+                self.pipeline.renderpass,
+                self.swapchain.extent,
+            )
+            .context("resizing the post-processing chain")?;
+
+        Ok(())
+    }
+
+    pub(crate) fn log_swapchain_created(event_log: &EventLog, swapchain: &Swapchain) {
+        event_log.push(RendererEvent::SwapchainCreated {
+            extent: swapchain.extent,
+            format: swapchain.image_format,
+            color_space: swapchain.color_space,
+            image_count: swapchain.swapchain_images.len(),
+        });
+    }
+
+    /// Changes how the swapchain images are shared between the graphics and the present queue
+    /// families, recreating the swapchain
+    pub fn set_swapchain_sharing(&mut self, sharing: SwapchainSharing) -> AppResult<()> {
+        self.swapchain_sharing = sharing;
+        self.recreate_swapchain()
+    }
+
+    /// Returns how the swapchain images are shared between the graphics and the present queue
+    /// families, see `set_swapchain_sharing`
+    pub fn swapchain_sharing(&self) -> SwapchainSharing {
+        self.swapchain_sharing
+    }
+}
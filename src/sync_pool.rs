@@ -0,0 +1,152 @@
+#[cfg(debug_assertions)]
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+#[cfg(debug_assertions)]
+use ash::vk::Handle;
+use ash::{vk, Device};
+#[cfg(debug_assertions)]
+use tracing::warn;
+
+use crate::{handle_registry, AppResult};
+
+#[derive(Default)]
+struct Pools {
+    fences: Vec<vk::Fence>,
+    semaphores: Vec<vk::Semaphore>,
+    // Handles handed out and not released yet
+    #[cfg(debug_assertions)]
+    acquired: HashSet<u64>,
+}
+
+/// Recycles the fences and semaphores of transient operations (uploads, readbacks, loads)
+/// instead of creating and destroying them for every operation.
+///
+/// In debug builds, the objects still acquired when the pool is destroyed are reported.
+#[derive(Default)]
+pub struct SyncPool {
+    pools: Mutex<Pools>,
+}
+
+impl SyncPool {
+    /// Returns an unsignaled fence
+    pub fn acquire_fence(&self, device: &Device) -> AppResult<vk::Fence> {
+        let mut pools = self.pools.lock().unwrap();
+        let fence = match pools.fences.pop() {
+            Some(fence) => fence,
+            None => unsafe {
+                handle_registry::register(
+                    device.create_fence(&vk::FenceCreateInfo::default(), None)?,
+                )
+            },
+        };
+
+        #[cfg(debug_assertions)]
+        pools.acquired.insert(fence.as_raw());
+
+        Ok(fence)
+    }
+
+    /// Gives `fence` back to the pool, it must not be used by a pending submission anymore
+    pub fn release_fence(&self, device: &Device, fence: vk::Fence) -> AppResult<()> {
+        unsafe { device.reset_fences(&[fence])? };
+
+        let mut pools = self.pools.lock().unwrap();
+        #[cfg(debug_assertions)]
+        assert!(
+            pools.acquired.remove(&fence.as_raw()),
+            "Fence released twice or not from this pool"
+        );
+        pools.fences.push(fence);
+
+        Ok(())
+    }
+
+    /// Runs `operation` with an unsignaled fence, which it must wait for before succeeding. The
+    /// fence is given back to the pool when the operation succeeds. When it fails, possibly after
+    /// submitting work signaling the fence, the fence is destroyed once the device is idle, or
+    /// leaked if the device can't be waited for.
+    pub fn with_fence<T>(
+        &self,
+        device: &Device,
+        operation: impl FnOnce(vk::Fence) -> AppResult<T>,
+    ) -> AppResult<T> {
+        let fence = self.acquire_fence(device)?;
+        match operation(fence) {
+            Ok(value) => {
+                self.release_fence(device, fence)?;
+                Ok(value)
+            }
+            Err(err) => {
+                self.discard_fence(device, fence);
+                Err(err)
+            }
+        }
+    }
+
+    // Destroys an acquired fence which may still be used by a pending submission
+    fn discard_fence(&self, device: &Device, fence: vk::Fence) {
+        unsafe {
+            if device.device_wait_idle().is_err() {
+                return;
+            }
+            handle_registry::unregister(fence);
+            device.destroy_fence(fence, None);
+        }
+
+        #[cfg(debug_assertions)]
+        self.pools.lock().unwrap().acquired.remove(&fence.as_raw());
+    }
+
+    /// Returns an unsignaled binary semaphore
+    pub fn acquire_semaphore(&self, device: &Device) -> AppResult<vk::Semaphore> {
+        let mut pools = self.pools.lock().unwrap();
+        let semaphore = match pools.semaphores.pop() {
+            Some(semaphore) => semaphore,
+            None => unsafe {
+                let create_info = vk::SemaphoreCreateInfo::default();
+                handle_registry::register(device.create_semaphore(&create_info, None)?)
+            },
+        };
+
+        #[cfg(debug_assertions)]
+        pools.acquired.insert(semaphore.as_raw());
+
+        Ok(semaphore)
+    }
+
+    /// Gives `semaphore` back to the pool, it must be unsignaled with no pending wait
+    pub fn release_semaphore(&self, semaphore: vk::Semaphore) {
+        let mut pools = self.pools.lock().unwrap();
+        #[cfg(debug_assertions)]
+        assert!(
+            pools.acquired.remove(&semaphore.as_raw()),
+            "Semaphore released twice or not from this pool"
+        );
+        pools.semaphores.push(semaphore);
+    }
+
+    /// Destroys the pooled objects, the acquired ones are leaked
+    pub fn destroy(&self, device: &Device) {
+        let mut pools = self.pools.lock().unwrap();
+
+        #[cfg(debug_assertions)]
+        if !pools.acquired.is_empty() {
+            warn!(
+                count = pools.acquired.len(),
+                "fences or semaphores of the sync pool were never released"
+            );
+        }
+
+        unsafe {
+            for fence in pools.fences.drain(..) {
+                handle_registry::unregister(fence);
+                device.destroy_fence(fence, None);
+            }
+            for semaphore in pools.semaphores.drain(..) {
+                handle_registry::unregister(semaphore);
+                device.destroy_semaphore(semaphore, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,861 @@
+use std::{ffi::CString, path::Path};
+
+use ash::{vk, Device, Instance};
+use cgmath::InnerSpace;
+use image::{ImageBuffer, ImageReader, Luma};
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    handle_registry, load_shader,
+    post::SCENE_COLOR_FORMAT,
+    Aabb, AppResult, Application, BufferHolder, ImageHolder, Mat4, Point3, SyncPool, Vec2, Vec3,
+    Vec4,
+};
+
+/// Textures blended by the splat map of a terrain, one per channel of the map
+pub const TERRAIN_LAYERS: usize = 4;
+
+/// Shape of a terrain generated from a heightmap, see `Application::load_terrain`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerrainSettings {
+    /// Extent of the terrain along X and Y in world units, centered on the origin. The first row
+    /// of the heightmap lies at the largest Y.
+    pub size: Vec2,
+    /// Height of the white texels of the heightmap along Z, the black ones lying at 0
+    pub height_scale: f32,
+    /// Quads along each side of a chunk, the chunks being culled as a whole
+    pub chunk_quads: u32,
+    /// Repetitions of the layer textures across the terrain, the splat map covering it once
+    pub layer_tiling: f32,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            size: Vec2::new(20.0, 20.0),
+            height_scale: 2.0,
+            chunk_quads: 32,
+            layer_tiling: 16.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TerrainVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    uv: [f32; 2],
+}
+
+#[repr(C)]
+struct TerrainConstants {
+    view_proj: Mat4,
+    layer_tiling: f32,
+}
+
+/// Geometry of a chunk, before it is uploaded
+pub(crate) struct ChunkMesh {
+    vertices: Vec<TerrainVertex>,
+    indices: Vec<u32>,
+    bounds: Aabb,
+}
+
+struct TerrainChunk {
+    vertex_buffer: BufferHolder,
+    index_buffer: BufferHolder,
+    index_count: u32,
+    bounds: Aabb,
+}
+
+// Depth attachment drawn along with the scene target
+struct TerrainTarget {
+    image: ImageHolder,
+    view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+}
+
+/// Decodes the heightmap at `path` and generates the grid of its terrain, a vertex per texel,
+/// split into chunks of `settings.chunk_quads` quads aside. The chunks share the vertices of
+/// their borders, and the normals come from the slopes between the neighbouring texels.
+pub(crate) fn generate_chunks(
+    path: &Path,
+    settings: &TerrainSettings,
+) -> AppResult<Vec<ChunkMesh>> {
+    let heightmap = ImageReader::open(path)?.decode()?.into_luma16();
+    let (width, height) = heightmap.dimensions();
+    if width < 2 || height < 2 {
+        return AppResult::Err(AppError::new(AppErrorType::EmptyMesh));
+    }
+
+    let chunk_quads = settings.chunk_quads.max(1);
+    let mut chunks = Vec::new();
+    for y0 in (0..height - 1).step_by(chunk_quads as usize) {
+        for x0 in (0..width - 1).step_by(chunk_quads as usize) {
+            let x1 = (x0 + chunk_quads).min(width - 1);
+            let y1 = (y0 + chunk_quads).min(height - 1);
+            chunks.push(generate_chunk(&heightmap, settings, [x0, y0], [x1, y1]));
+        }
+    }
+
+    Ok(chunks)
+}
+
+// The quads between the texels `from` and `to` of the heightmap, both included
+fn generate_chunk(
+    heightmap: &ImageBuffer<Luma<u16>, Vec<u16>>,
+    settings: &TerrainSettings,
+    from: [u32; 2],
+    to: [u32; 2],
+) -> ChunkMesh {
+    let (width, height) = heightmap.dimensions();
+    let spacing = Vec2::new(
+        settings.size.x / (width - 1) as f32,
+        settings.size.y / (height - 1) as f32,
+    );
+    let height_at = |x: u32, y: u32| {
+        heightmap.get_pixel(x, y).0[0] as f32 / u16::MAX as f32 * settings.height_scale
+    };
+
+    let mut vertices = Vec::new();
+    for y in from[1]..=to[1] {
+        for x in from[0]..=to[0] {
+            // Central differences, one-sided on the borders of the heightmap. The rows go
+            // towards -Y.
+            let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+            let (up, down) = (y.saturating_sub(1), (y + 1).min(height - 1));
+            let slope_x =
+                (height_at(right, y) - height_at(left, y)) / ((right - left) as f32 * spacing.x);
+            let slope_y =
+                (height_at(x, up) - height_at(x, down)) / ((down - up) as f32 * spacing.y);
+            let normal = Vec3::new(-slope_x, -slope_y, 1.0).normalize();
+
+            vertices.push(TerrainVertex {
+                position: [
+                    x as f32 * spacing.x - settings.size.x * 0.5,
+                    settings.size.y * 0.5 - y as f32 * spacing.y,
+                    height_at(x, y),
+                ],
+                normal: normal.into(),
+                uv: [
+                    x as f32 / (width - 1) as f32,
+                    y as f32 / (height - 1) as f32,
+                ],
+            });
+        }
+    }
+
+    // Two counter-clockwise triangles per quad, seen from +Z
+    let columns = to[0] - from[0] + 1;
+    let mut indices = Vec::new();
+    for row in 0..to[1] - from[1] {
+        for column in 0..columns - 1 {
+            let top_left = row * columns + column;
+            let bottom_left = top_left + columns;
+            indices.extend([
+                top_left,
+                bottom_left,
+                bottom_left + 1,
+                top_left,
+                bottom_left + 1,
+                top_left + 1,
+            ]);
+        }
+    }
+
+    let bounds =
+        Aabb::from_points(vertices.iter().map(|vertex| Point3::from(vertex.position))).unwrap();
+    ChunkMesh {
+        vertices,
+        indices,
+        bounds,
+    }
+}
+
+// Whether the corners of `bounds` all lie beyond the same plane of the frustum of `view_proj`
+fn outside_frustum(bounds: &Aabb, view_proj: Mat4) -> bool {
+    let corners: Vec<Vec4> = (0..8)
+        .map(|i| {
+            let corner = |axis: usize| match i & (1 << axis) {
+                0 => bounds.min[axis],
+                _ => bounds.max[axis],
+            };
+            view_proj * Vec4::new(corner(0), corner(1), corner(2), 1.0)
+        })
+        .collect();
+
+    // The clip space of the camera projection spans [-w, w] along each axis
+    (0..3).any(|axis| {
+        corners.iter().all(|corner| corner[axis] < -corner.w)
+            || corners.iter().all(|corner| corner[axis] > corner.w)
+    })
+}
+
+/// Terrain generated from a heightmap, drawn in chunks whose bounding boxes are culled against
+/// the view frustum, see `Application::load_terrain`.
+///
+/// The scene pass having no depth attachment, the terrain is drawn after it in a pass of its own
+/// loading the scene target along with a depth attachment, so it covers the draw items. Its
+/// fragments blend the layer textures by the weights of the splat map, lit by a fixed sun.
+pub(crate) struct Terrain {
+    depth_format: vk::Format,
+    render_pass: vk::RenderPass,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    splat_view: vk::ImageView,
+    layer_views: [vk::ImageView; TERRAIN_LAYERS],
+    chunks: Vec<TerrainChunk>,
+    layer_tiling: f32,
+    // Created with the scene target it is drawn with, see `Terrain::destroy_target`
+    target: Option<TerrainTarget>,
+}
+
+impl Terrain {
+    /// Uploads the chunks and creates the pass drawing them, sampling the splat map and the
+    /// layers with `sampler`, which must outlive the terrain along with the textures
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        instance: &Instance,
+        device: &Device,
+        queue: vk::Queue,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        chunks: &[ChunkMesh],
+        splat_view: vk::ImageView,
+        layer_views: [vk::ImageView; TERRAIN_LAYERS],
+        sampler: vk::Sampler,
+        settings: &TerrainSettings,
+    ) -> AppResult<Self> {
+        let depth_format = Self::find_depth_format(instance, physical_device);
+        let render_pass = Self::create_render_pass(device, depth_format)?;
+
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: TERRAIN_LAYERS as u32,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+        ];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1 + TERRAIN_LAYERS as u32,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: 1,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &set_layout,
+            ..Default::default()
+        };
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<TerrainConstants>() as u32,
+            }],
+        )?;
+        let pipeline = Self::create_pipeline(device, render_pass, pipeline_layout)?;
+
+        let chunks = chunks
+            .iter()
+            .map(|chunk| {
+                Ok(TerrainChunk {
+                    vertex_buffer: Application::create_buffer_with_data(
+                        instance,
+                        device,
+                        queue,
+                        physical_device,
+                        &chunk.vertices,
+                        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
+                        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                        command_pool,
+                        sync_pool,
+                    )?,
+                    index_buffer: Application::create_index_buffer(
+                        instance,
+                        device,
+                        queue,
+                        physical_device,
+                        &chunk.indices,
+                        command_pool,
+                        sync_pool,
+                    )?,
+                    index_count: chunk.indices.len() as u32,
+                    bounds: chunk.bounds,
+                })
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+
+        let mut terrain = Self {
+            depth_format,
+            render_pass,
+            set_layout,
+            pipeline_layout,
+            pipeline,
+            descriptor_pool,
+            descriptor_set,
+            splat_view,
+            layer_views,
+            chunks,
+            layer_tiling: settings.layer_tiling,
+            target: None,
+        };
+        terrain.set_sampler(device, sampler);
+
+        Ok(terrain)
+    }
+
+    // Every device can render to one of the two formats
+    fn find_depth_format(instance: &Instance, physical_device: vk::PhysicalDevice) -> vk::Format {
+        [vk::Format::D32_SFLOAT, vk::Format::X8_D24_UNORM_PACK32]
+            .into_iter()
+            .find(|&format| {
+                let properties = unsafe {
+                    instance.get_physical_device_format_properties(physical_device, format)
+                };
+                properties
+                    .optimal_tiling_features
+                    .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+            })
+            .unwrap_or(vk::Format::X8_D24_UNORM_PACK32)
+    }
+
+    // Draws over the scene target, left to be sampled by the post chain
+    fn create_render_pass(device: &Device, depth_format: vk::Format) -> AppResult<vk::RenderPass> {
+        let attachments = [
+            vk::AttachmentDescription {
+                format: SCENE_COLOR_FORMAT,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::LOAD,
+                store_op: vk::AttachmentStoreOp::STORE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ..Default::default()
+            },
+            vk::AttachmentDescription {
+                format: depth_format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            },
+        ];
+        let color_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+        let depth_attachment_ref = vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        let subpass = vk::SubpassDescription {
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref as *const _,
+            p_depth_stencil_attachment: &depth_attachment_ref as *const _,
+            ..Default::default()
+        };
+
+        let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+        let dependencies = [
+            // The scene pass writes the color, the previous frame may still be testing the depth
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: attachment_stages,
+                dst_stage_mask: attachment_stages,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ..Default::default()
+            },
+            vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ,
+                ..Default::default()
+            },
+        ];
+
+        let render_pass_info = vk::RenderPassCreateInfo {
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: 1,
+            p_subpasses: &subpass as *const _,
+            dependency_count: dependencies.len() as u32,
+            p_dependencies: dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_render_pass(&render_pass_info, None)?,
+            ))
+        }
+    }
+
+    // Depth tested and written, both faces being drawn
+    fn create_pipeline(
+        device: &Device,
+        render_pass: vk::RenderPass,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> AppResult<vk::Pipeline> {
+        let vert_module = Application::create_shader_module(
+            device,
+            &load_shader("terrain.vert", include_bytes!("spirv/terrain.spv"))?,
+        )?;
+        let frag_module = Application::create_shader_module(
+            device,
+            &load_shader(
+                "terrain_splat.frag",
+                include_bytes!("spirv/terrain_splat.spv"),
+            )?,
+        )?;
+
+        let entry_point = CString::new("main").unwrap();
+        let shader_stages_infos = [
+            vk::PipelineShaderStageCreateInfo {
+                stage: vk::ShaderStageFlags::VERTEX,
+                module: vert_module,
+                p_name: entry_point.as_ptr(),
+                ..Default::default()
+            },
+            vk::PipelineShaderStageCreateInfo {
+                stage: vk::ShaderStageFlags::FRAGMENT,
+                module: frag_module,
+                p_name: entry_point.as_ptr(),
+                ..Default::default()
+            },
+        ];
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo {
+            dynamic_state_count: dynamic_states.len() as u32,
+            p_dynamic_states: &dynamic_states as *const _,
+            ..Default::default()
+        };
+
+        let bindings = [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<TerrainVertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+        let attributes = [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: 12,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: 24,
+            },
+        ];
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo {
+            vertex_binding_description_count: bindings.len() as u32,
+            p_vertex_binding_descriptions: bindings.as_ptr(),
+            vertex_attribute_description_count: attributes.len() as u32,
+            p_vertex_attribute_descriptions: attributes.as_ptr(),
+            ..Default::default()
+        };
+
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart_enable: false.into(),
+            ..Default::default()
+        };
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            viewport_count: 1,
+            scissor_count: 1,
+            ..Default::default()
+        };
+
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo {
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            ..Default::default()
+        };
+
+        let multisampling = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            min_sample_shading: 1.0,
+            ..Default::default()
+        };
+
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState {
+            blend_enable: false.into(),
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            ..Default::default()
+        }];
+        let color_blending = vk::PipelineColorBlendStateCreateInfo {
+            logic_op: vk::LogicOp::COPY,
+            attachment_count: color_blend_attachments.len() as u32,
+            p_attachments: color_blend_attachments.as_ptr(),
+            ..Default::default()
+        };
+
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo {
+            depth_test_enable: true.into(),
+            depth_write_enable: true.into(),
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+            ..Default::default()
+        };
+
+        let pipeline_info = vk::GraphicsPipelineCreateInfo {
+            stage_count: shader_stages_infos.len() as u32,
+            p_stages: shader_stages_infos.as_ptr(),
+            p_vertex_input_state: &vertex_input_info as *const _,
+            p_input_assembly_state: &input_assembly_info as *const _,
+            p_viewport_state: &viewport_state as *const _,
+            p_rasterization_state: &rasterizer as *const _,
+            p_multisample_state: &multisampling as *const _,
+            p_depth_stencil_state: &depth_stencil as *const _,
+            p_color_blend_state: &color_blending as *const _,
+            p_dynamic_state: &dynamic_state_create_info as *const _,
+            layout: pipeline_layout,
+            render_pass,
+            subpass: 0,
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipeline = unsafe {
+            device
+                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+                .or_else(|r| AppResult::Err(r.1.into()))?[0]
+        };
+        handle_registry::register(pipeline);
+
+        unsafe {
+            handle_registry::unregister(vert_module);
+            device.destroy_shader_module(vert_module, None);
+            handle_registry::unregister(frag_module);
+            device.destroy_shader_module(frag_module, None);
+        }
+
+        Ok(pipeline)
+    }
+
+    /// Samples the splat map and the layers with `sampler`. The device must be idle.
+    pub(crate) fn set_sampler(&mut self, device: &Device, sampler: vk::Sampler) {
+        let image_info = |image_view: vk::ImageView| vk::DescriptorImageInfo {
+            sampler,
+            image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let splat_info = image_info(self.splat_view);
+        let layer_infos = self.layer_views.map(image_info);
+        let descriptor_writes = [
+            vk::WriteDescriptorSet {
+                dst_set: self.descriptor_set,
+                dst_binding: 0,
+                dst_array_element: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                p_image_info: &splat_info,
+                ..Default::default()
+            },
+            vk::WriteDescriptorSet {
+                dst_set: self.descriptor_set,
+                dst_binding: 1,
+                dst_array_element: 0,
+                descriptor_count: layer_infos.len() as u32,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                p_image_info: layer_infos.as_ptr(),
+                ..Default::default()
+            },
+        ];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+    }
+
+    pub(crate) fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Chunks of the terrain seen through `view_proj`
+    pub(crate) fn visible_chunks(&self, view_proj: Mat4) -> usize {
+        self.chunks
+            .iter()
+            .filter(|chunk| !outside_frustum(&chunk.bounds, view_proj))
+            .count()
+    }
+
+    /// Creates the depth attachment and the framebuffer drawing over `scene_view`, unless they
+    /// already exist
+    pub(crate) fn create_target(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        scene_view: vk::ImageView,
+        extent: vk::Extent2D,
+    ) -> AppResult<()> {
+        if self.target.is_some() {
+            return Ok(());
+        }
+
+        let image = Application::create_image(
+            instance,
+            device,
+            physical_device,
+            extent.width,
+            extent.height,
+            1,
+            self.depth_format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let create_info = vk::ImageViewCreateInfo {
+            image: image.image,
+            view_type: vk::ImageViewType::TYPE_2D,
+            format: self.depth_format,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        };
+        let view = unsafe { device.create_image_view(&create_info, None)? };
+        handle_registry::register(view);
+
+        let attachments = [scene_view, view];
+        let frame_buffer_info = vk::FramebufferCreateInfo {
+            render_pass: self.render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+        let framebuffer = unsafe { device.create_framebuffer(&frame_buffer_info, None)? };
+        handle_registry::register(framebuffer);
+
+        self.target = Some(TerrainTarget {
+            image,
+            view,
+            framebuffer,
+        });
+
+        Ok(())
+    }
+
+    /// Destroys the depth attachment and the framebuffer, to be recreated along with the scene
+    /// target. The device must be idle.
+    pub(crate) fn destroy_target(&mut self, device: &Device) {
+        let Some(target) = self.target.take() else {
+            return;
+        };
+
+        unsafe {
+            handle_registry::unregister(target.framebuffer);
+            device.destroy_framebuffer(target.framebuffer, None);
+            handle_registry::unregister(target.view);
+            device.destroy_image_view(target.view, None);
+            handle_registry::unregister(target.image.image);
+            device.destroy_image(target.image.image, None);
+            handle_registry::unregister(target.image.memory);
+            device.free_memory(target.image.memory, None);
+        }
+    }
+
+    /// Records the terrain pass over the scene target, once its target is created, drawing the
+    /// chunks seen through `view_proj`. Returns the number of draw calls.
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        view_proj: Mat4,
+    ) -> u32 {
+        let Some(target) = &self.target else {
+            return 0;
+        };
+
+        let clear_values = [
+            vk::ClearValue::default(),
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_pass_info = vk::RenderPassBeginInfo {
+            render_pass: self.render_pass,
+            framebuffer: target.framebuffer,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+            ..Default::default()
+        };
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        }];
+        let constants = TerrainConstants {
+            view_proj,
+            layer_tiling: self.layer_tiling,
+        };
+
+        let mut draw_calls = 0;
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::slice::from_raw_parts(
+                    &constants as *const TerrainConstants as *const u8,
+                    std::mem::size_of::<TerrainConstants>(),
+                ),
+            );
+
+            for chunk in &self.chunks {
+                if outside_frustum(&chunk.bounds, view_proj) {
+                    continue;
+                }
+                device.cmd_bind_vertex_buffers(
+                    command_buffer,
+                    0,
+                    &[chunk.vertex_buffer.buffer],
+                    &[0],
+                );
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    chunk.index_buffer.buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                device.cmd_draw_indexed(command_buffer, chunk.index_count, 1, 0, 0, 0);
+                draw_calls += 1;
+            }
+
+            device.cmd_end_render_pass(command_buffer);
+        }
+
+        draw_calls
+    }
+
+    /// Destroys the chunks, the target and the pipeline, the device must be idle. The textures
+    /// aren't owned by the terrain.
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        self.destroy_target(device);
+
+        unsafe {
+            for chunk in self.chunks.drain(..) {
+                for holder in [chunk.vertex_buffer, chunk.index_buffer] {
+                    handle_registry::unregister(holder.buffer);
+                    device.destroy_buffer(holder.buffer, None);
+                    handle_registry::unregister(holder.memory);
+                    device.free_memory(holder.memory, None);
+                }
+            }
+
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.descriptor_pool);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            handle_registry::unregister(self.set_layout);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+            handle_registry::unregister(self.render_pass);
+            device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
@@ -0,0 +1,580 @@
+use std::collections::HashMap;
+
+use ash::{vk, Device, Instance};
+use fontdue::{Font, FontSettings};
+use image::RgbaImage;
+
+use crate::{
+    garbage_collector::{Garbage, GarbageCollector},
+    geometry::{Mat4, Point3},
+    handle_registry, load_shader,
+    overlay::{self, create_overlay_pipeline, OverlayBlend, OverlayVertex},
+    AppError, AppErrorType, AppResult, Application, MemoryMappedBuffer, PerFrame, TextureHolder,
+};
+
+/// Pixel size the glyphs of a font are baked at in its signed distance field atlas. The text
+/// stays crisp well above it, the finer details of the glyphs fading out far below it.
+pub const SDF_BAKE_SIZE: f32 = 48.0;
+
+// Distance, in baked pixels, over which the field goes from fully inside a glyph to fully
+// outside. Also the padding around the glyphs in the atlas.
+const SDF_SPREAD: usize = 6;
+const ATLAS_WIDTH: usize = 1024;
+// Stands for "no inside (or outside) pixel" in the distance transforms, finite so the parabola
+// intersections don't turn into NaNs
+const FAR_DISTANCE: f32 = 1e20;
+
+// A glyph of the atlas, in baked pixels
+struct Glyph {
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    // From the pen position on the baseline to the top left corner of the padded glyph
+    offset: [f32; 2],
+    size: [f32; 2],
+    advance: f32,
+}
+
+/// A font whose printable ASCII and Latin-1 characters are baked into a signed distance field
+/// atlas: the alpha of each texel holds the distance to the closest glyph edge, 0.5 on the edge
+/// and increasing towards the inside. The other characters are drawn as `?`.
+pub(crate) struct SdfFont {
+    font: Font,
+    glyphs: HashMap<char, Glyph>,
+    ascent: f32,
+    line_height: f32,
+}
+
+impl SdfFont {
+    /// Parses the TrueType or OpenType font in `bytes` and bakes its atlas
+    pub(crate) fn bake(bytes: &[u8]) -> Result<(Self, RgbaImage), &'static str> {
+        let font = Font::from_bytes(bytes, FontSettings::default())?;
+        let (ascent, line_height) = match font.horizontal_line_metrics(SDF_BAKE_SIZE) {
+            Some(metrics) => (metrics.ascent, metrics.new_line_size),
+            None => (0.8 * SDF_BAKE_SIZE, 1.2 * SDF_BAKE_SIZE),
+        };
+
+        let chars = (' '..='~').chain('\u{a1}'..='\u{ff}');
+        let fields: Vec<_> = chars
+            .filter(|&c| c == ' ' || font.has_glyph(c))
+            .map(|c| {
+                let (metrics, coverage) = font.rasterize(c, SDF_BAKE_SIZE);
+                let field = distance_field(&coverage, metrics.width, metrics.height);
+                (c, metrics, field)
+            })
+            .collect();
+
+        // Packs the glyphs in rows, from the top left corner
+        let mut placements = Vec::with_capacity(fields.len());
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for (_, metrics, _) in fields.iter() {
+            let (width, height) = padded_size(metrics.width, metrics.height);
+            if x + width > ATLAS_WIDTH {
+                (x, y, row_height) = (0, y + row_height, 0);
+            }
+            placements.push((x, y));
+            x += width;
+            row_height = row_height.max(height);
+        }
+        let atlas_height = (y + row_height).max(1);
+
+        let mut atlas = RgbaImage::from_pixel(
+            ATLAS_WIDTH as u32,
+            atlas_height as u32,
+            image::Rgba([255, 255, 255, 0]),
+        );
+        let mut glyphs = HashMap::with_capacity(fields.len());
+        for ((c, metrics, field), (x, y)) in fields.into_iter().zip(placements) {
+            let (width, height) = padded_size(metrics.width, metrics.height);
+            for (i, &distance) in field.iter().enumerate() {
+                let pixel = atlas.get_pixel_mut((x + i % width) as u32, (y + i / width) as u32);
+                pixel.0[3] = distance;
+            }
+
+            let padding = SDF_SPREAD as f32;
+            glyphs.insert(
+                c,
+                Glyph {
+                    uv_min: [
+                        x as f32 / ATLAS_WIDTH as f32,
+                        y as f32 / atlas_height as f32,
+                    ],
+                    uv_max: [
+                        (x + width) as f32 / ATLAS_WIDTH as f32,
+                        (y + height) as f32 / atlas_height as f32,
+                    ],
+                    offset: [
+                        metrics.xmin as f32 - padding,
+                        -(metrics.ymin as f32 + metrics.height as f32 + padding),
+                    ],
+                    size: [width as f32, height as f32],
+                    advance: metrics.advance_width,
+                },
+            );
+        }
+
+        let font = Self {
+            font,
+            glyphs,
+            ascent,
+            line_height,
+        };
+        Ok((font, atlas))
+    }
+
+    /// Lays `text` out at the pixel size `size`, from the top left corner of its first line.
+    /// `emit` receives the position and size of each glyph quad along with its atlas rectangle.
+    /// Returns the width and height of the text.
+    fn layout(
+        &self,
+        text: &str,
+        size: f32,
+        mut emit: impl FnMut([f32; 2], [f32; 2], [f32; 2], [f32; 2]),
+    ) -> [f32; 2] {
+        let scale = size / SDF_BAKE_SIZE;
+        let (mut pen_x, mut baseline) = (0.0, self.ascent);
+        let mut width: f32 = 0.0;
+        let mut previous = None;
+
+        for c in text.chars() {
+            if c == '\n' {
+                (pen_x, baseline, previous) = (0.0, baseline + self.line_height, None);
+                continue;
+            }
+            let c = if self.glyphs.contains_key(&c) { c } else { '?' };
+            let Some(glyph) = self.glyphs.get(&c) else {
+                continue;
+            };
+
+            if let Some(previous) = previous {
+                pen_x += self
+                    .font
+                    .horizontal_kern(previous, c, SDF_BAKE_SIZE)
+                    .unwrap_or(0.0);
+            }
+            if c != ' ' {
+                emit(
+                    [
+                        (pen_x + glyph.offset[0]) * scale,
+                        (baseline + glyph.offset[1]) * scale,
+                    ],
+                    [glyph.size[0] * scale, glyph.size[1] * scale],
+                    glyph.uv_min,
+                    glyph.uv_max,
+                );
+            }
+            pen_x += glyph.advance;
+            width = width.max(pen_x);
+            previous = Some(c);
+        }
+
+        let height = baseline - self.ascent + self.line_height;
+        [width * scale, height * scale]
+    }
+}
+
+// Glyph size with the padding of the field
+fn padded_size(width: usize, height: usize) -> (usize, usize) {
+    (width + 2 * SDF_SPREAD, height + 2 * SDF_SPREAD)
+}
+
+/// Signed distance field of a glyph coverage bitmap, padded by `SDF_SPREAD` on each side
+fn distance_field(coverage: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let (padded_width, padded_height) = padded_size(width, height);
+    let inside = |x: usize, y: usize| {
+        (SDF_SPREAD..SDF_SPREAD + width).contains(&x)
+            && (SDF_SPREAD..SDF_SPREAD + height).contains(&y)
+            && coverage[(y - SDF_SPREAD) * width + x - SDF_SPREAD] >= 128
+    };
+
+    // Squared distances to the closest inside pixel and to the closest outside pixel
+    let mut to_inside = vec![FAR_DISTANCE; padded_width * padded_height];
+    let mut to_outside = vec![FAR_DISTANCE; padded_width * padded_height];
+    for y in 0..padded_height {
+        for x in 0..padded_width {
+            if inside(x, y) {
+                to_inside[y * padded_width + x] = 0.0;
+            } else {
+                to_outside[y * padded_width + x] = 0.0;
+            }
+        }
+    }
+    distance_transform(&mut to_inside, padded_width, padded_height);
+    distance_transform(&mut to_outside, padded_width, padded_height);
+
+    to_inside
+        .iter()
+        .zip(to_outside.iter())
+        .map(|(to_inside, to_outside)| {
+            let distance = to_outside.sqrt() - to_inside.sqrt();
+            let value = 0.5 + distance / (2.0 * SDF_SPREAD as f32);
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Exact squared euclidean distance transform of a grid holding 0 on the feature pixels and
+/// `FAR_DISTANCE` elsewhere, one dimension after the other (Felzenszwalb and Huttenlocher)
+fn distance_transform(grid: &mut [f32], width: usize, height: usize) {
+    let length = width.max(height);
+    let mut line = vec![0.0; length];
+    let mut distances = vec![0.0; length];
+    let mut parabolas = vec![0; length];
+    let mut bounds = vec![0.0; length + 1];
+
+    for x in 0..width {
+        for y in 0..height {
+            line[y] = grid[y * width + x];
+        }
+        distance_transform_1d(&line[..height], &mut distances, &mut parabolas, &mut bounds);
+        for y in 0..height {
+            grid[y * width + x] = distances[y];
+        }
+    }
+    for y in 0..height {
+        line[..width].copy_from_slice(&grid[y * width..(y + 1) * width]);
+        distance_transform_1d(&line[..width], &mut distances, &mut parabolas, &mut bounds);
+        grid[y * width..(y + 1) * width].copy_from_slice(&distances[..width]);
+    }
+}
+
+// Lower envelope of the parabolas rooted at each sample of `f`
+fn distance_transform_1d(
+    f: &[f32],
+    distances: &mut [f32],
+    parabolas: &mut [usize],
+    bounds: &mut [f32],
+) {
+    let intersection = |q: usize, p: usize| {
+        let (qf, pf) = (q as f32, p as f32);
+        ((f[q] + qf * qf) - (f[p] + pf * pf)) / (2.0 * (qf - pf))
+    };
+
+    let mut k = 0;
+    parabolas[0] = 0;
+    bounds[0] = f32::NEG_INFINITY;
+    bounds[1] = f32::INFINITY;
+    for q in 1..f.len() {
+        let mut s = intersection(q, parabolas[k]);
+        while s <= bounds[k] {
+            k -= 1;
+            s = intersection(q, parabolas[k]);
+        }
+        k += 1;
+        parabolas[k] = q;
+        bounds[k] = s;
+        bounds[k + 1] = f32::INFINITY;
+    }
+
+    k = 0;
+    for (q, distance) in distances.iter_mut().enumerate().take(f.len()) {
+        while bounds[k + 1] < q as f32 {
+            k += 1;
+        }
+        let offset = q as f32 - parabolas[k] as f32;
+        *distance = offset * offset + f[parabolas[k]];
+    }
+}
+
+// Text anchored to a point of the scene, laid out once the frame's camera is known
+struct Label {
+    text: String,
+    position: Point3,
+    size: f32,
+    color: [f32; 4],
+}
+
+// Vertex buffer of a frame in flight, grown when the text outgrows it
+#[derive(Default)]
+struct TextBuffer {
+    vertex_buffer: Option<MemoryMappedBuffer>,
+    vertex_capacity: usize,
+    vertex_count: u32,
+}
+
+/// Draws the text queued with `Application::draw_text` and `Application::draw_label` over the
+/// next frame, in the composite render pass. The glyphs are batched into one vertex buffer per
+/// frame and sampled from the signed distance field atlas of the loaded font.
+pub(crate) struct TextRenderer {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    // Set by `Application::load_font`, nothing is drawn without it
+    font: Option<(SdfFont, TextureHolder)>,
+    buffers: PerFrame<TextBuffer>,
+
+    // Text of the next frame
+    vertices: Vec<OverlayVertex>,
+    labels: Vec<Label>,
+}
+
+impl TextRenderer {
+    pub(crate) fn new(
+        device: &Device,
+        render_pass: vk::RenderPass,
+        frame_count: usize,
+    ) -> AppResult<Self> {
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        }];
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: 1,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(descriptor_pool);
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &set_layout,
+            ..Default::default()
+        };
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<[f32; 2]>() as u32,
+            }],
+        )?;
+        let pipeline = create_overlay_pipeline(
+            device,
+            render_pass,
+            vk::SampleCountFlags::TYPE_1,
+            pipeline_layout,
+            &load_shader("overlay.vert", include_bytes!("spirv/overlay.spv"))?,
+            &load_shader("sdf_text.frag", include_bytes!("spirv/sdf_text.spv"))?,
+            &OverlayVertex::bindings(),
+            &OverlayVertex::attributes(),
+            vk::PrimitiveTopology::TRIANGLE_LIST,
+            OverlayBlend::Alpha,
+        )?;
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            set_layout,
+            descriptor_pool,
+            descriptor_set,
+            font: None,
+            buffers: PerFrame::new(frame_count, |_| Ok(TextBuffer::default()))?,
+            vertices: Vec::new(),
+            labels: Vec::new(),
+        })
+    }
+
+    /// Replaces the font, sampling its uploaded atlas with `sampler`. The device must be idle,
+    /// the previous atlas is destroyed right away.
+    pub(crate) fn set_font(
+        &mut self,
+        device: &Device,
+        font: SdfFont,
+        atlas: TextureHolder,
+        sampler: vk::Sampler,
+    ) {
+        let image_info = vk::DescriptorImageInfo {
+            sampler,
+            image_view: atlas.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: &image_info,
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        if let Some((_, previous)) = self.font.replace((font, atlas)) {
+            unsafe { Garbage::Texture(previous).destroy(device) };
+        }
+    }
+
+    /// Width and height of `text` drawn at the pixel size `size`, `None` without a font
+    pub(crate) fn measure(&self, text: &str, size: f32) -> Option<[f32; 2]> {
+        let (font, _) = self.font.as_ref()?;
+        Some(font.layout(text, size, |_, _, _, _| ()))
+    }
+
+    /// Queues `text` for the next frame, with its top left corner at `position` in pixels
+    pub(crate) fn queue_text(
+        &mut self,
+        text: &str,
+        position: [f32; 2],
+        size: f32,
+        color: [f32; 4],
+    ) {
+        let Some((font, _)) = &self.font else {
+            return;
+        };
+
+        let vertices = &mut self.vertices;
+        font.layout(text, size, |offset, quad_size, uv_min, uv_max| {
+            let quad_position = [position[0] + offset[0], position[1] + offset[1]];
+            overlay::push_quad(vertices, quad_position, quad_size, uv_min, uv_max, color);
+        });
+    }
+
+    /// Queues `text` for the next frame, centered above `position` in the scene
+    pub(crate) fn queue_label(&mut self, text: &str, position: Point3, size: f32, color: [f32; 4]) {
+        if self.font.is_some() {
+            self.labels.push(Label {
+                text: text.to_owned(),
+                position,
+                size,
+                color,
+            });
+        }
+    }
+
+    /// Lays the labels out with `view_proj`, then copies the queued text to the vertex buffer
+    /// of `frame`, which must not be in use by the GPU, growing it if needed. The queue is
+    /// emptied for the following frame.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn upload(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        garbage_collector: &mut GarbageCollector,
+        frame: usize,
+        view_proj: Mat4,
+        extent: vk::Extent2D,
+    ) -> AppResult<()> {
+        for label in std::mem::take(&mut self.labels) {
+            // Behind the camera or out of the depth range
+            let clip = view_proj * label.position.to_homogeneous();
+            if clip.w <= 0.0 || !(-clip.w..=clip.w).contains(&clip.z) {
+                continue;
+            }
+            let Some(size) = self.measure(&label.text, label.size) else {
+                continue;
+            };
+
+            let x = (clip.x / clip.w + 1.0) * 0.5 * extent.width as f32;
+            let y = (clip.y / clip.w + 1.0) * 0.5 * extent.height as f32;
+            let position = [x - 0.5 * size[0], y - size[1]];
+            self.queue_text(&label.text, position, label.size, label.color);
+        }
+
+        let frame_count = self.buffers.len();
+        let buffers = &mut self.buffers[frame];
+        buffers.vertex_count = self.vertices.len() as u32;
+        if self.vertices.is_empty() {
+            return Ok(());
+        }
+
+        if buffers.vertex_capacity < self.vertices.len() {
+            let capacity = self.vertices.len().next_power_of_two();
+            let buffer = Application::create_host_visible_buffers(
+                instance,
+                device,
+                physical_device,
+                (capacity * std::mem::size_of::<OverlayVertex>()) as u64,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                1,
+            )?
+            .pop()
+            .unwrap();
+            if let Some(old) = buffers.vertex_buffer.replace(buffer) {
+                garbage_collector.retire(Garbage::Buffer(old.buffer, old.memory), frame_count);
+            }
+            buffers.vertex_capacity = capacity;
+        }
+
+        unsafe {
+            std::ptr::copy(
+                self.vertices.as_ptr(),
+                buffers.vertex_buffer.as_ref().unwrap().memory_map as *mut OverlayVertex,
+                self.vertices.len(),
+            );
+        }
+        self.vertices.clear();
+
+        Ok(())
+    }
+
+    /// Records the text of `frame`, in the composite render pass
+    pub(crate) fn record(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        frame: usize,
+    ) {
+        let buffers = &self.buffers[frame];
+        let Some(vertex_buffer) = &buffers.vertex_buffer else {
+            return;
+        };
+        if self.font.is_none() || buffers.vertex_count == 0 {
+            return;
+        }
+
+        overlay::bind_overlay_pipeline(
+            device,
+            command_buffer,
+            self.pipeline,
+            self.pipeline_layout,
+            self.descriptor_set,
+            extent,
+        );
+        unsafe {
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            device.cmd_draw(command_buffer, buffers.vertex_count, 1, 0, 0);
+        }
+    }
+
+    /// Destroys the buffers, the atlas and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for buffers in self.buffers.iter_mut() {
+                if let Some(buffer) = buffers.vertex_buffer.take() {
+                    Garbage::Buffer(buffer.buffer, buffer.memory).destroy(device);
+                }
+            }
+            if let Some((_, atlas)) = self.font.take() {
+                Garbage::Texture(atlas).destroy(device);
+            }
+
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.descriptor_pool);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            handle_registry::unregister(self.set_layout);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
+
+/// Error of a font file which couldn't be parsed
+pub(crate) fn font_error(path: &std::path::Path, reason: &str) -> AppError {
+    AppError::with_message(AppErrorType::InvalidFont, format!("{:?}: {}", path, reason))
+}
@@ -0,0 +1,332 @@
+use ash::{vk, Device, Instance};
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    handle_registry, load_shader,
+    texture_loader::{self, DecodedTexture},
+    AppResult, Application, ImageHolder, SyncPool, TextureHolder, TextureUploadPath,
+};
+
+/// Uploads same-sized images as the layers of a 2D array texture, through a single staging
+/// buffer holding every level of every layer. Fails if the images don't have the same size and
+/// format. The layers are decompressed if the device can't sample their block-compressed format,
+/// see `texture_loader::fit_to_device`.
+pub(crate) fn upload_texture_array(
+    instance: &Instance,
+    device: &Device,
+    queue: vk::Queue,
+    physical_device: vk::PhysicalDevice,
+    command_pool: vk::CommandPool,
+    sync_pool: &SyncPool,
+    layers: &mut [DecodedTexture],
+) -> AppResult<TextureHolder> {
+    if layers.is_empty() || !same_layout(layers) {
+        return AppResult::Err(AppError::new(AppErrorType::MismatchedArrayLayers));
+    }
+
+    upload_layers(
+        instance,
+        device,
+        queue,
+        physical_device,
+        command_pool,
+        sync_pool,
+        layers,
+        vk::ImageViewType::TYPE_2D_ARRAY,
+    )
+}
+
+/// Whether the layers have the same size, format and mip level count
+pub(crate) fn same_layout(layers: &[DecodedTexture]) -> bool {
+    let layout = |layer: &DecodedTexture| {
+        (
+            layer.width(),
+            layer.height(),
+            layer.format,
+            layer.mip_levels(),
+        )
+    };
+    layers
+        .windows(2)
+        .all(|pair| layout(&pair[0]) == layout(&pair[1]))
+}
+
+/// Uploads `layers` as the layers of a single image seen through a `view_type` view, the image
+/// being cube compatible for a `CUBE` view. The layers must have the same layout.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn upload_layers(
+    instance: &Instance,
+    device: &Device,
+    queue: vk::Queue,
+    physical_device: vk::PhysicalDevice,
+    command_pool: vk::CommandPool,
+    sync_pool: &SyncPool,
+    layers: &mut [DecodedTexture],
+    view_type: vk::ImageViewType,
+) -> AppResult<TextureHolder> {
+    let (width, height, mip_levels) = (
+        layers[0].width(),
+        layers[0].height(),
+        layers[0].mip_levels(),
+    );
+    // The layers share their format, so they all take the same path
+    let mut upload_path = TextureUploadPath::Native;
+    for layer in layers.iter_mut() {
+        upload_path = texture_loader::fit_to_device(instance, physical_device, layer)?;
+    }
+    let format = layers[0].format;
+    let layer_count = layers.len() as u32;
+
+    let buffer_size = layers.iter().map(DecodedTexture::byte_size).sum();
+    let staging_buffer = Application::create_buffer(
+        instance,
+        device,
+        physical_device,
+        buffer_size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+
+    let mut regions = vec![];
+    unsafe {
+        let buffer_memory_ptr = device.map_memory(
+            staging_buffer.memory,
+            0,
+            buffer_size,
+            vk::MemoryMapFlags::empty(),
+        )? as *mut u8;
+
+        let mut offset = 0;
+        for (layer_index, layer) in layers.iter().enumerate() {
+            for (mip_level, level) in layer.levels.iter().enumerate() {
+                let data = &level.data;
+                std::ptr::copy(data.as_ptr(), buffer_memory_ptr.add(offset), data.len());
+
+                regions.push(vk::BufferImageCopy {
+                    buffer_offset: offset as u64,
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: mip_level as u32,
+                        base_array_layer: layer_index as u32,
+                        layer_count: 1,
+                    },
+                    image_extent: vk::Extent3D {
+                        width: level.width,
+                        height: level.height,
+                        depth: 1,
+                    },
+                    ..Default::default()
+                });
+                offset += data.len();
+            }
+        }
+
+        device.unmap_memory(staging_buffer.memory);
+    }
+
+    let flags = match view_type {
+        vk::ImageViewType::CUBE | vk::ImageViewType::CUBE_ARRAY => {
+            vk::ImageCreateFlags::CUBE_COMPATIBLE
+        }
+        _ => vk::ImageCreateFlags::empty(),
+    };
+    let image_info = vk::ImageCreateInfo {
+        flags,
+        image_type: vk::ImageType::TYPE_2D,
+        format,
+        extent: vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        },
+        mip_levels,
+        array_layers: layer_count,
+        samples: vk::SampleCountFlags::TYPE_1,
+        tiling: vk::ImageTiling::OPTIMAL,
+        usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        initial_layout: vk::ImageLayout::UNDEFINED,
+        ..Default::default()
+    };
+    let image = unsafe { device.create_image(&image_info, None)? };
+    handle_registry::register(image);
+
+    let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
+    let alloc_info = vk::MemoryAllocateInfo {
+        allocation_size: mem_requirements.size,
+        memory_type_index: Application::find_memory_type(
+            instance,
+            physical_device,
+            mem_requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?,
+        ..Default::default()
+    };
+    let memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+    handle_registry::register(memory);
+    unsafe { device.bind_image_memory(image, memory, 0)? };
+
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: mip_levels,
+        base_array_layer: 0,
+        layer_count,
+    };
+    let to_transfer = [vk::ImageMemoryBarrier {
+        src_access_mask: vk::AccessFlags::empty(),
+        dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        old_layout: vk::ImageLayout::UNDEFINED,
+        new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        subresource_range,
+        ..Default::default()
+    }];
+    let to_shader = [vk::ImageMemoryBarrier {
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        dst_access_mask: vk::AccessFlags::SHADER_READ,
+        old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        ..to_transfer[0]
+    }];
+
+    unsafe {
+        let command_buffer = Application::begin_singe_time_command(device, command_pool)?;
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_transfer,
+        );
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging_buffer.buffer,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &regions,
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_shader,
+        );
+        Application::end_single_time_command(
+            device,
+            queue,
+            command_pool,
+            sync_pool,
+            command_buffer,
+        )?;
+
+        handle_registry::unregister(staging_buffer.buffer);
+        device.destroy_buffer(staging_buffer.buffer, None);
+        handle_registry::unregister(staging_buffer.memory);
+        device.free_memory(staging_buffer.memory, None);
+    }
+
+    let create_info = vk::ImageViewCreateInfo {
+        image,
+        view_type,
+        format,
+        subresource_range,
+        ..Default::default()
+    };
+    let view = unsafe { device.create_image_view(&create_info, None)? };
+    handle_registry::register(view);
+
+    Ok(TextureHolder {
+        image: ImageHolder::new(image, memory),
+        view,
+        array_layers: Some(layer_count),
+        upload_path,
+    })
+}
+
+/// Scene pipeline sampling the layer of a texture array selected by the material, e.g. the tile
+/// of a tile set
+pub(crate) struct TextureArrayPipeline {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+}
+
+impl TextureArrayPipeline {
+    pub(crate) fn new(
+        device: &Device,
+        scene_render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        scene_set_layout: vk::DescriptorSetLayout,
+    ) -> AppResult<Self> {
+        // The array is bound in place of the texture of the scene set
+        let pipeline_layout = Application::create_pipeline_layout(
+            device,
+            &[scene_set_layout],
+            &[vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                offset: 0,
+                size: std::mem::size_of::<u32>() as u32,
+            }],
+        )?;
+        let pipeline = Application::create_scene_pipeline(
+            device,
+            scene_render_pass,
+            samples,
+            pipeline_layout,
+            &load_shader("vertex.vert", include_bytes!("spirv/vertex.spv"))?,
+            &load_shader(
+                "texture_array.frag",
+                include_bytes!("spirv/texture_array.spv"),
+            )?,
+            vk::CullModeFlags::BACK,
+        )?;
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+        })
+    }
+
+    pub(crate) fn pipeline(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+
+    /// Layout the scene set is bound with for this pipeline, as it has its own push constants
+    pub(crate) fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout
+    }
+
+    /// Selects the layer sampled by the following draws
+    pub(crate) fn record_layer(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        layer: u32,
+    ) {
+        unsafe {
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &layer.to_ne_bytes(),
+            );
+        }
+    }
+
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+}
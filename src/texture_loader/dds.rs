@@ -0,0 +1,293 @@
+use std::path::Path;
+
+use ash::vk;
+
+use super::{decompress::block_layout, file_error, DecodedTexture, TextureLevel};
+use crate::{app_error::AppErrorType, AppResult};
+
+/// Extension of the DDS containers, loaded with their block-compressed format and mip chain
+pub const DDS_EXTENSION: &str = "dds";
+
+const MAGIC: &[u8; 4] = b"DDS ";
+// The magic followed by the DDS_HEADER, and the DDS_HEADER_DXT10 of the DXGI formats
+const HEADER_SIZE: usize = 128;
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_DEPTH: u32 = 0x800000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DXGI_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+const DXGI_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Format of the legacy FourCC codes, the color ones being sampled as sRGB like the images
+fn fourcc_format(fourcc: &[u8]) -> Option<vk::Format> {
+    let format = match fourcc {
+        b"DXT1" => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        b"DXT2" | b"DXT3" => vk::Format::BC2_SRGB_BLOCK,
+        b"DXT4" | b"DXT5" => vk::Format::BC3_SRGB_BLOCK,
+        b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+        b"BC4S" => vk::Format::BC4_SNORM_BLOCK,
+        b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+        b"BC5S" => vk::Format::BC5_SNORM_BLOCK,
+        _ => return None,
+    };
+    Some(format)
+}
+
+/// Format of the BC `DXGI_FORMAT` values, the typeless ones being read as UNORM
+fn dxgi_format(dxgi_format: u32) -> Option<vk::Format> {
+    let format = match dxgi_format {
+        70 | 71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        73 | 74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        76 | 77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        79 | 80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        82 | 83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        94 | 95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        97 | 98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    };
+    Some(format)
+}
+
+/// Reads the 2D texture held by the DDS container `bytes` read from `path`, level 0 being the full
+/// image. Only the BC1 to BC7 formats are supported, their blocks being uploaded as they are.
+pub(super) fn decode(bytes: &[u8], path: &Path) -> AppResult<DecodedTexture> {
+    if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+        return Err(file_error(
+            AppErrorType::InvalidTextureFile,
+            path,
+            "not a DDS container",
+        ));
+    }
+
+    let flags = read_u32(bytes, 8);
+    let height = read_u32(bytes, 12);
+    let width = read_u32(bytes, 16);
+    let depth = read_u32(bytes, 24);
+    // Some exporters count levels past the 1×1 one
+    let mip_levels = match flags & DDSD_MIPMAPCOUNT {
+        0 => 1,
+        _ => read_u32(bytes, 28).clamp(1, 32 - width.max(height).leading_zeros()),
+    };
+    let pixel_flags = read_u32(bytes, 80);
+    let fourcc = &bytes[84..88];
+    let caps2 = read_u32(bytes, 112);
+
+    let is_volume = flags & DDSD_DEPTH != 0 && depth > 1;
+    let is_cubemap = caps2 & DDSCAPS2_CUBEMAP != 0;
+    if is_volume || is_cubemap || width == 0 || height == 0 {
+        return Err(file_error(
+            AppErrorType::UnsupportedTextureFormat,
+            path,
+            "only 2D textures can be loaded",
+        ));
+    }
+
+    let (format, data_offset) = if pixel_flags & DDPF_FOURCC != 0 && fourcc == b"DX10" {
+        if bytes.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+            return Err(file_error(
+                AppErrorType::InvalidTextureFile,
+                path,
+                "the DX10 header is truncated",
+            ));
+        }
+
+        let dimension = read_u32(bytes, HEADER_SIZE + 4);
+        let misc_flags = read_u32(bytes, HEADER_SIZE + 8);
+        let array_size = read_u32(bytes, HEADER_SIZE + 12);
+        if dimension != DXGI_RESOURCE_DIMENSION_TEXTURE2D
+            || misc_flags & DXGI_RESOURCE_MISC_TEXTURECUBE != 0
+            || array_size > 1
+        {
+            return Err(file_error(
+                AppErrorType::UnsupportedTextureFormat,
+                path,
+                "only 2D textures can be loaded",
+            ));
+        }
+
+        let code = read_u32(bytes, HEADER_SIZE);
+        let Some(format) = dxgi_format(code) else {
+            return Err(file_error(
+                AppErrorType::UnsupportedTextureFormat,
+                path,
+                &format!("DXGI format {} isn't block-compressed", code),
+            ));
+        };
+        (format, HEADER_SIZE + DX10_HEADER_SIZE)
+    } else {
+        let format = (pixel_flags & DDPF_FOURCC != 0)
+            .then(|| fourcc_format(fourcc))
+            .flatten();
+        let Some(format) = format else {
+            return Err(file_error(
+                AppErrorType::UnsupportedTextureFormat,
+                path,
+                "only the BC1 to BC7 formats can be loaded",
+            ));
+        };
+        (format, HEADER_SIZE)
+    };
+
+    let blocks = block_layout(format).unwrap();
+    let mut levels = vec![];
+    let mut offset = data_offset;
+    for level in 0..mip_levels {
+        let level_width = (width >> level).max(1);
+        let level_height = (height >> level).max(1);
+        let size = blocks.level_size(level_width, level_height);
+        let Some(data) = bytes.get(offset..offset + size) else {
+            return Err(file_error(
+                AppErrorType::InvalidTextureFile,
+                path,
+                &format!("level {} is truncated", level),
+            ));
+        };
+
+        levels.push(TextureLevel {
+            width: level_width,
+            height: level_height,
+            data: data.to_vec(),
+        });
+        offset += size;
+    }
+
+    Ok(DecodedTexture { format, levels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DXGI_FORMAT_BC7_UNORM: u32 = 98;
+
+    fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    // Header of a `width`×`height` texture with `mip_count` levels when set
+    fn header(width: u32, height: u32, mip_count: Option<u32>, fourcc: &[u8; 4]) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(MAGIC);
+        write_u32(&mut bytes, 4, 124);
+        write_u32(&mut bytes, 8, mip_count.map_or(0, |_| DDSD_MIPMAPCOUNT));
+        write_u32(&mut bytes, 12, height);
+        write_u32(&mut bytes, 16, width);
+        write_u32(&mut bytes, 28, mip_count.unwrap_or(0));
+        write_u32(&mut bytes, 76, 32);
+        write_u32(&mut bytes, 80, DDPF_FOURCC);
+        bytes[84..88].copy_from_slice(fourcc);
+        bytes
+    }
+
+    fn dx10_header(dxgi_format: u32, misc_flags: u32, array_size: u32) -> Vec<u8> {
+        let mut bytes = vec![0; DX10_HEADER_SIZE];
+        write_u32(&mut bytes, 0, dxgi_format);
+        write_u32(&mut bytes, 4, DXGI_RESOURCE_DIMENSION_TEXTURE2D);
+        write_u32(&mut bytes, 8, misc_flags);
+        write_u32(&mut bytes, 12, array_size);
+        bytes
+    }
+
+    fn decode_error(bytes: &[u8]) -> AppErrorType {
+        decode(bytes, Path::new("test.dds"))
+            .err()
+            .unwrap()
+            .error_type
+    }
+
+    #[test]
+    fn decodes_bc1_mip_chain() {
+        let mut bytes = header(8, 8, Some(2), b"DXT1");
+        // Four blocks for the 8×8 level, one for the 4×4 one
+        bytes.extend((0..40).map(|i| i as u8));
+
+        let texture = decode(&bytes, Path::new("test.dds")).unwrap();
+        assert_eq!(texture.format, vk::Format::BC1_RGBA_SRGB_BLOCK);
+        assert_eq!(texture.levels.len(), 2);
+        assert_eq!((texture.levels[0].width, texture.levels[0].height), (8, 8));
+        assert_eq!(texture.levels[0].data, (0..32).collect::<Vec<u8>>());
+        assert_eq!((texture.levels[1].width, texture.levels[1].height), (4, 4));
+        assert_eq!(texture.levels[1].data, (32..40).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn decodes_bc7_from_dx10_header() {
+        let mut bytes = header(4, 4, None, b"DX10");
+        bytes.extend(dx10_header(DXGI_FORMAT_BC7_UNORM, 0, 1));
+        bytes.extend([7; 16]);
+
+        let texture = decode(&bytes, Path::new("test.dds")).unwrap();
+        assert_eq!(texture.format, vk::Format::BC7_UNORM_BLOCK);
+        assert_eq!(texture.levels.len(), 1);
+        assert_eq!(texture.levels[0].data, [7; 16]);
+    }
+
+    #[test]
+    fn clamps_mip_count_to_full_chain() {
+        let mut bytes = header(4, 4, Some(40), b"DXT1");
+        // The 4×4, 2×2 and 1×1 levels each take a block
+        bytes.extend([0; 24]);
+
+        let texture = decode(&bytes, Path::new("test.dds")).unwrap();
+        let sizes: Vec<_> = texture
+            .levels
+            .iter()
+            .map(|level| (level.width, level.height))
+            .collect();
+        assert_eq!(sizes, [(4, 4), (2, 2), (1, 1)]);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let mut bytes = header(8, 8, None, b"DXT1");
+        bytes.extend([0; 16]);
+        assert_eq!(decode_error(&bytes), AppErrorType::InvalidTextureFile);
+
+        let mut bytes = header(4, 4, None, b"DX10");
+        bytes.extend(&dx10_header(DXGI_FORMAT_BC7_UNORM, 0, 1)[..8]);
+        assert_eq!(decode_error(&bytes), AppErrorType::InvalidTextureFile);
+
+        assert_eq!(
+            decode_error(&header(4, 4, None, b"DXT1")[..64]),
+            AppErrorType::InvalidTextureFile
+        );
+    }
+
+    #[test]
+    fn rejects_cubemaps() {
+        let mut bytes = header(4, 4, None, b"DXT1");
+        write_u32(&mut bytes, 112, DDSCAPS2_CUBEMAP);
+        bytes.extend([0; 48]);
+        assert_eq!(decode_error(&bytes), AppErrorType::UnsupportedTextureFormat);
+
+        let mut bytes = header(4, 4, None, b"DX10");
+        bytes.extend(dx10_header(
+            DXGI_FORMAT_BC7_UNORM,
+            DXGI_RESOURCE_MISC_TEXTURECUBE,
+            1,
+        ));
+        bytes.extend([0; 96]);
+        assert_eq!(decode_error(&bytes), AppErrorType::UnsupportedTextureFormat);
+    }
+
+    #[test]
+    fn rejects_uncompressed_formats() {
+        let mut bytes = header(4, 4, None, b"\0\0\0\0");
+        write_u32(&mut bytes, 80, 0);
+        bytes.extend([0; 64]);
+        assert_eq!(decode_error(&bytes), AppErrorType::UnsupportedTextureFormat);
+    }
+}
@@ -0,0 +1,542 @@
+use ash::vk;
+
+use super::{DecodedTexture, TextureLevel};
+
+/// Texel extent and byte size of the blocks of a block-compressed format
+#[derive(Clone, Copy, Debug)]
+pub(super) struct BlockLayout {
+    pub width: u32,
+    pub height: u32,
+    pub bytes: usize,
+}
+
+impl BlockLayout {
+    /// Size of a `width`×`height` level, the partial blocks on its edges taking a whole one
+    pub(super) fn level_size(&self, width: u32, height: u32) -> usize {
+        width.div_ceil(self.width) as usize * height.div_ceil(self.height) as usize * self.bytes
+    }
+}
+
+// Block extents of the ASTC formats, whose UNORM and SRGB variants follow each other from
+// ASTC_4X4_UNORM_BLOCK on
+const ASTC_EXTENTS: [(u32, u32); 14] = [
+    (4, 4),
+    (5, 4),
+    (5, 5),
+    (6, 5),
+    (6, 6),
+    (8, 5),
+    (8, 6),
+    (8, 8),
+    (10, 5),
+    (10, 6),
+    (10, 8),
+    (10, 10),
+    (12, 10),
+    (12, 12),
+];
+
+// Intensity modifiers of the ETC individual and differential modes, indexed by the table
+// codeword, the negative ones being their opposite
+const ETC_MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8],
+    [5, 17],
+    [9, 29],
+    [13, 42],
+    [18, 60],
+    [24, 80],
+    [33, 106],
+    [47, 183],
+];
+// Distances between the paint colors of the ETC2 T and H modes
+const ETC_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+// Modifiers of the EAC alpha blocks, indexed by the table index then the texel index
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+// Texels of a 4×4 block, row by row
+type Block = [[u8; 4]; 16];
+
+/// Layout of the blocks of `format`, `None` if it isn't a BC, ETC2, EAC or ASTC format
+pub(super) fn block_layout(format: vk::Format) -> Option<BlockLayout> {
+    let bytes = match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK
+        | vk::Format::EAC_R11_UNORM_BLOCK
+        | vk::Format::EAC_R11_SNORM_BLOCK => 8,
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK
+        | vk::Format::EAC_R11G11_UNORM_BLOCK
+        | vk::Format::EAC_R11G11_SNORM_BLOCK => 16,
+        _ => {
+            let astc = format.as_raw() - vk::Format::ASTC_4X4_UNORM_BLOCK.as_raw();
+            let &(width, height) = ASTC_EXTENTS.get(usize::try_from(astc).ok()? / 2)?;
+            return Some(BlockLayout {
+                width,
+                height,
+                bytes: 16,
+            });
+        }
+    };
+
+    Some(BlockLayout {
+        width: 4,
+        height: 4,
+        bytes,
+    })
+}
+
+/// RGBA8 format the texels of `format` are decompressed to, `None` if there is no software
+/// decoder for it
+fn decompressed_format(format: vk::Format) -> Option<vk::Format> {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK => Some(vk::Format::R8G8B8A8_UNORM),
+        vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK => Some(vk::Format::R8G8B8A8_SRGB),
+        _ => None,
+    }
+}
+
+/// See `DecodedTexture::decompress`, the sRGB encoding of the format being kept. The signed,
+/// BC6H, BC7, EAC and ASTC formats have no decoder.
+pub(super) fn decompress(texture: &DecodedTexture) -> Option<DecodedTexture> {
+    let format = decompressed_format(texture.format)?;
+    let layout = block_layout(texture.format)?;
+
+    let levels = texture
+        .levels
+        .iter()
+        .map(|level| {
+            let (width, height) = (level.width, level.height);
+            let blocks_per_row = width.div_ceil(layout.width);
+            let mut data = vec![0; width as usize * height as usize * 4];
+            for (i, block) in level.data.chunks_exact(layout.bytes).enumerate() {
+                let texels = decode_block(texture.format, block);
+                let block_x = i as u32 % blocks_per_row * 4;
+                let block_y = i as u32 / blocks_per_row * 4;
+                for (j, texel) in texels.iter().enumerate() {
+                    let (x, y) = (block_x + j as u32 % 4, block_y + j as u32 / 4);
+                    // The blocks on the edges overhang the levels whose size isn't a multiple of 4
+                    if x < width && y < height {
+                        let offset = (y * width + x) as usize * 4;
+                        data[offset..offset + 4].copy_from_slice(texel);
+                    }
+                }
+            }
+
+            TextureLevel {
+                width,
+                height,
+                data,
+            }
+        })
+        .collect();
+
+    Some(DecodedTexture { format, levels })
+}
+
+fn decode_block(format: vk::Format, block: &[u8]) -> Block {
+    let mut texels = [[0; 4]; 16];
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK | vk::Format::BC1_RGB_SRGB_BLOCK => {
+            decode_bc1(block, false, &mut texels);
+            // The transparent color of the 3-color mode is black without alpha channel
+            for texel in texels.iter_mut() {
+                texel[3] = u8::MAX;
+            }
+        }
+        vk::Format::BC1_RGBA_UNORM_BLOCK | vk::Format::BC1_RGBA_SRGB_BLOCK => {
+            decode_bc1(block, false, &mut texels);
+        }
+        vk::Format::BC2_UNORM_BLOCK | vk::Format::BC2_SRGB_BLOCK => {
+            decode_bc1(&block[8..], true, &mut texels);
+            let alphas = u64::from_le_bytes(block[..8].try_into().unwrap());
+            for (i, texel) in texels.iter_mut().enumerate() {
+                texel[3] = (alphas >> (4 * i) & 0xf) as u8 * 17;
+            }
+        }
+        vk::Format::BC3_UNORM_BLOCK | vk::Format::BC3_SRGB_BLOCK => {
+            decode_bc1(&block[8..], true, &mut texels);
+            for (texel, alpha) in texels.iter_mut().zip(decode_bc4(&block[..8])) {
+                texel[3] = alpha;
+            }
+        }
+        vk::Format::BC4_UNORM_BLOCK => {
+            for (texel, red) in texels.iter_mut().zip(decode_bc4(block)) {
+                *texel = [red, 0, 0, u8::MAX];
+            }
+        }
+        vk::Format::BC5_UNORM_BLOCK => {
+            let reds = decode_bc4(&block[..8]);
+            let greens = decode_bc4(&block[8..]);
+            for (i, texel) in texels.iter_mut().enumerate() {
+                *texel = [reds[i], greens[i], 0, u8::MAX];
+            }
+        }
+        vk::Format::ETC2_R8G8B8_UNORM_BLOCK | vk::Format::ETC2_R8G8B8_SRGB_BLOCK => {
+            decode_etc2(block, &mut texels);
+        }
+        vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK => {
+            decode_etc2(&block[8..], &mut texels);
+            decode_eac_alpha(&block[..8], &mut texels);
+        }
+        _ => unreachable!("{:?} has no decoder", format),
+    }
+
+    texels
+}
+
+fn rgb565(color: u16) -> [u8; 4] {
+    let (r, g, b) = (color >> 11, color >> 5 & 0x3f, color & 0x1f);
+    [
+        (r << 3 | r >> 2) as u8,
+        (g << 2 | g >> 4) as u8,
+        (b << 3 | b >> 2) as u8,
+        u8::MAX,
+    ]
+}
+
+/// Decodes the color block of the BC1 to BC3 formats, the BC2 and BC3 ones always being in the
+/// 4-color mode
+fn decode_bc1(block: &[u8], four_colors: bool, texels: &mut Block) {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let (c0, c1) = (rgb565(color0), rgb565(color1));
+    let mix = |w0: u32, w1: u32| {
+        std::array::from_fn(|i| ((c0[i] as u32 * w0 + c1[i] as u32 * w1) / (w0 + w1)) as u8)
+    };
+    let palette = if four_colors || color0 > color1 {
+        [c0, c1, mix(2, 1), mix(1, 2)]
+    } else {
+        [c0, c1, mix(1, 1), [0; 4]]
+    };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[(indices >> (2 * i) & 3) as usize];
+    }
+}
+
+/// Decodes a single channel block of BC4, also used by the alpha of BC3 and the channels of BC5
+fn decode_bc4(block: &[u8]) -> [u8; 16] {
+    let (v0, v1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [v0, v1, 0, 0, 0, 0, 0, u8::MAX as u32];
+    if v0 > v1 {
+        for i in 1..7 {
+            palette[i as usize + 1] = (v0 * (7 - i) + v1 * i) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i as usize + 1] = (v0 * (5 - i) + v1 * i) / 5;
+        }
+    }
+
+    let mut indices = [0; 8];
+    indices[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(indices);
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(indices >> (3 * i) & 7) as usize] as u8;
+    }
+    values
+}
+
+fn extend4(value: i32) -> i32 {
+    value << 4 | value
+}
+
+fn extend5(value: i32) -> i32 {
+    value << 3 | value >> 2
+}
+
+fn add_clamped(color: [i32; 3], offset: i32) -> [u8; 4] {
+    [
+        (color[0] + offset).clamp(0, 255) as u8,
+        (color[1] + offset).clamp(0, 255) as u8,
+        (color[2] + offset).clamp(0, 255) as u8,
+        u8::MAX,
+    ]
+}
+
+/// Decodes an ETC2 RGB8 block, ETC1 blocks included. Its texels are indexed column by column.
+fn decode_etc2(block: &[u8], texels: &mut Block) {
+    let bits = u64::from_be_bytes(block.try_into().unwrap());
+    let field = |shift: u32, len: u32| (bits >> shift & ((1 << len) - 1)) as i32;
+    // The most significant bit of the index of each texel is in the upper half
+    let index = |x: usize, y: usize| {
+        let bit = (x * 4 + y) as u32;
+        (field(bit + 16, 1) << 1 | field(bit, 1)) as usize
+    };
+
+    let write_subblocks = |texels: &mut Block, colors: [[i32; 3]; 2]| {
+        let tables = [field(37, 3) as usize, field(34, 3) as usize];
+        let flipped = field(32, 1) == 1;
+        for (i, texel) in texels.iter_mut().enumerate() {
+            let (x, y) = (i % 4, i / 4);
+            let subblock = if flipped { y / 2 } else { x / 2 };
+            let [small, large] = ETC_MODIFIERS[tables[subblock]];
+            let modifier = [small, large, -small, -large][index(x, y)];
+            *texel = add_clamped(colors[subblock], modifier);
+        }
+    };
+    let write_paint = |texels: &mut Block, paint: [[u8; 4]; 4]| {
+        for (i, texel) in texels.iter_mut().enumerate() {
+            *texel = paint[index(i % 4, i / 4)];
+        }
+    };
+
+    // Individual mode, two 4-bit colors
+    if field(33, 1) == 0 {
+        let colors = [
+            [field(60, 4), field(52, 4), field(44, 4)].map(extend4),
+            [field(56, 4), field(48, 4), field(40, 4)].map(extend4),
+        ];
+        write_subblocks(texels, colors);
+        return;
+    }
+
+    // Differential mode, the second color being a 3-bit signed offset from the first, unless it
+    // overflows to select one of the ETC2 modes
+    let base = [field(59, 5), field(51, 5), field(43, 5)];
+    let offset =
+        [field(56, 3), field(48, 3), field(40, 3)]
+            .map(|delta| if delta >= 4 { delta - 8 } else { delta });
+    let second = [0, 1, 2].map(|i| base[i] + offset[i]);
+
+    if !(0..32).contains(&second[0]) {
+        // T mode
+        let c0 = [field(59, 2) << 2 | field(56, 2), field(52, 4), field(48, 4)].map(extend4);
+        let c1 = [field(44, 4), field(40, 4), field(36, 4)].map(extend4);
+        let distance = ETC_DISTANCES[(field(34, 2) << 1 | field(32, 1)) as usize];
+        let paint = [
+            add_clamped(c0, 0),
+            add_clamped(c1, distance),
+            add_clamped(c1, 0),
+            add_clamped(c1, -distance),
+        ];
+        write_paint(texels, paint);
+    } else if !(0..32).contains(&second[1]) {
+        // H mode, the order of the colors giving the lowest bit of the distance
+        let c0 = [
+            field(59, 4),
+            field(56, 3) << 1 | field(52, 1),
+            field(51, 1) << 3 | field(47, 3),
+        ];
+        let c1 = [field(43, 4), field(39, 4), field(35, 4)];
+        let packed = |c: [i32; 3]| c[0] << 8 | c[1] << 4 | c[2];
+        let order = (packed(c0) >= packed(c1)) as i32;
+        let distance = ETC_DISTANCES[(field(34, 1) << 2 | field(32, 1) << 1 | order) as usize];
+        let (c0, c1) = (c0.map(extend4), c1.map(extend4));
+        let paint = [
+            add_clamped(c0, distance),
+            add_clamped(c0, -distance),
+            add_clamped(c1, distance),
+            add_clamped(c1, -distance),
+        ];
+        write_paint(texels, paint);
+    } else if !(0..32).contains(&second[2]) {
+        // Planar mode, the colors of the origin, right and bottom corners being interpolated
+        let extend6 = |value: i32| value << 2 | value >> 4;
+        let extend7 = |value: i32| value << 1 | value >> 6;
+        let origin = [
+            extend6(field(57, 6)),
+            extend7(field(56, 1) << 6 | field(49, 6)),
+            extend6(field(48, 1) << 5 | field(43, 2) << 3 | field(39, 3)),
+        ];
+        let horizontal = [
+            extend6(field(34, 5) << 1 | field(32, 1)),
+            extend7(field(25, 7)),
+            extend6(field(19, 6)),
+        ];
+        let vertical = [
+            extend6(field(13, 6)),
+            extend7(field(6, 7)),
+            extend6(field(0, 6)),
+        ];
+        for (i, texel) in texels.iter_mut().enumerate() {
+            let (x, y) = ((i % 4) as i32, (i / 4) as i32);
+            for c in 0..3 {
+                let value = x * (horizontal[c] - origin[c])
+                    + y * (vertical[c] - origin[c])
+                    + 4 * origin[c]
+                    + 2;
+                texel[c] = (value >> 2).clamp(0, 255) as u8;
+            }
+            texel[3] = u8::MAX;
+        }
+    } else {
+        write_subblocks(texels, [base.map(extend5), second.map(extend5)]);
+    }
+}
+
+/// Decodes the EAC alpha block of ETC2 RGBA8 in the alpha of `texels`, its texels being indexed
+/// column by column
+fn decode_eac_alpha(block: &[u8], texels: &mut Block) {
+    let bits = u64::from_be_bytes(block.try_into().unwrap());
+    let base = (bits >> 56) as i32;
+    let multiplier = (bits >> 52 & 0xf) as i32;
+    let modifiers = EAC_MODIFIERS[(bits >> 48 & 0xf) as usize];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let (x, y) = (i % 4, i / 4);
+        let index = (bits >> (45 - 3 * (x * 4 + y)) & 7) as usize;
+        texel[3] = (base + modifiers[index] * multiplier).clamp(0, 255) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    // Decompresses a single `width`×`height` level made of `data`
+    fn decompress_level(format: vk::Format, (width, height): (u32, u32), data: &[u8]) -> Vec<u8> {
+        let texture = DecodedTexture {
+            format,
+            levels: vec![TextureLevel {
+                width,
+                height,
+                data: data.to_vec(),
+            }],
+        };
+        decompress(&texture).unwrap().levels.remove(0).data
+    }
+
+    fn decompress_block(format: vk::Format, block: &[u8]) -> Vec<[u8; 4]> {
+        decompress_level(format, (4, 4), block)
+            .chunks_exact(4)
+            .map(|texel| texel.try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn decodes_bc1_four_color_blocks() {
+        // Red and blue endpoints, each row using the indices 0 to 3
+        let block = [0x00, 0xf8, 0x1f, 0x00, 0xe4, 0xe4, 0xe4, 0xe4];
+        let row = [RED, BLUE, [170, 0, 85, 255], [85, 0, 170, 255]];
+        let texels = decompress_block(vk::Format::BC1_RGB_UNORM_BLOCK, &block);
+        assert_eq!(texels, row.repeat(4));
+    }
+
+    #[test]
+    fn decodes_bc1_three_color_blocks() {
+        // The endpoints are swapped, the indices 2 and 3 being their midpoint and transparent black
+        let block = [0x1f, 0x00, 0x00, 0xf8, 0xe4, 0xe4, 0xe4, 0xe4];
+        let midpoint = [127, 0, 127, 255];
+
+        let texels = decompress_block(vk::Format::BC1_RGBA_UNORM_BLOCK, &block);
+        assert_eq!(texels, [BLUE, RED, midpoint, [0; 4]].repeat(4));
+        let texels = decompress_block(vk::Format::BC1_RGB_UNORM_BLOCK, &block);
+        assert_eq!(texels, [BLUE, RED, midpoint, [0, 0, 0, 255]].repeat(4));
+    }
+
+    #[test]
+    fn decodes_etc2_individual_blocks() {
+        // Red left and blue right subblocks with the modifiers of the first table, the texel at
+        // (0, 1) selecting the most negative one
+        let block = [0xf0, 0x00, 0x0f, 0x00, 0x00, 0x02, 0x00, 0x02];
+        let texels = decompress_block(vk::Format::ETC2_R8G8B8_UNORM_BLOCK, &block);
+        for (i, texel) in texels.iter().enumerate() {
+            let expected = match (i % 4, i / 4) {
+                (0, 1) => [247, 0, 0, 255],
+                (0 | 1, _) => [255, 2, 2, 255],
+                _ => [2, 2, 255, 255],
+            };
+            assert_eq!(*texel, expected, "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn decodes_etc2_differential_blocks() {
+        // Red base of 16, the right subblock being one step redder
+        let bits: u64 = 16 << 59 | 1 << 56 | 1 << 33;
+        let texels = decompress_block(vk::Format::ETC2_R8G8B8_SRGB_BLOCK, &bits.to_be_bytes());
+        for (i, texel) in texels.iter().enumerate() {
+            let red = if i % 4 < 2 { 134 } else { 142 };
+            assert_eq!(*texel, [red, 2, 2, 255], "texel {}", i);
+        }
+    }
+
+    #[test]
+    fn decodes_etc2_alpha_blocks() {
+        // Base alpha of 128 with a multiplier of 1, every texel using the -3 modifier
+        let alpha: u64 = 128 << 56 | 1 << 52;
+        let color: u64 = 1 << 33;
+        let mut block = alpha.to_be_bytes().to_vec();
+        block.extend_from_slice(&color.to_be_bytes());
+
+        let texels = decompress_block(vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK, &block);
+        assert_eq!(texels, [[2, 2, 2, 125]; 16]);
+    }
+
+    #[test]
+    fn crops_blocks_overhanging_the_level() {
+        let block = [0x00, 0xf8, 0x1f, 0x00, 0xe4, 0xe4, 0xe4, 0xe4];
+        let data = decompress_level(vk::Format::BC1_RGB_SRGB_BLOCK, (2, 2), &block);
+        assert_eq!(data, [RED, BLUE, RED, BLUE].concat());
+    }
+
+    #[test]
+    fn keeps_the_srgb_encoding() {
+        let texture = DecodedTexture {
+            format: vk::Format::BC1_RGB_SRGB_BLOCK,
+            levels: vec![],
+        };
+        assert_eq!(
+            decompress(&texture).unwrap().format,
+            vk::Format::R8G8B8A8_SRGB
+        );
+
+        let texture = DecodedTexture {
+            format: vk::Format::BC7_UNORM_BLOCK,
+            levels: vec![],
+        };
+        assert!(decompress(&texture).is_none());
+    }
+}
@@ -0,0 +1,321 @@
+use std::{io::Read, path::Path};
+
+use ash::vk;
+use flate2::read::ZlibDecoder;
+use ktx2::{Format, Reader, SupercompressionScheme};
+use ruzstd::decoding::StreamingDecoder;
+
+use super::{
+    decompress::{block_layout, BlockLayout},
+    file_error, DecodedTexture, TextureLevel, RGBA_FORMAT,
+};
+use crate::{app_error::AppErrorType, AppResult};
+
+/// Extension of the KTX2 containers, loaded with their own mip chain and block-compressed format
+pub const KTX2_EXTENSION: &str = "ktx2";
+
+// Layout of the texels of the formats which can be converted to RGBA8
+struct TexelLayout {
+    channels: usize,
+    bgr: bool,
+}
+
+fn texel_layout(format: Format) -> Option<TexelLayout> {
+    let (channels, bgr) = match format {
+        Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => (4, false),
+        Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => (4, true),
+        Format::R8G8B8_UNORM | Format::R8G8B8_SRGB => (3, false),
+        Format::B8G8R8_UNORM | Format::B8G8R8_SRGB => (3, true),
+        _ => return None,
+    };
+    Some(TexelLayout { channels, bgr })
+}
+
+// How the levels of a format are read
+enum LevelLayout {
+    // Blocks uploaded as they are
+    Blocks(BlockLayout),
+    // 8-bit texels converted to RGBA8
+    Texels(TexelLayout),
+}
+
+fn to_rgba(data: &[u8], layout: &TexelLayout, texel_count: usize) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(texel_count * 4);
+    for texel in data.chunks_exact(layout.channels).take(texel_count) {
+        let (r, b) = if layout.bgr {
+            (texel[2], texel[0])
+        } else {
+            (texel[0], texel[2])
+        };
+        let a = texel.get(3).copied().unwrap_or(u8::MAX);
+        rgba.extend_from_slice(&[r, texel[1], b, a]);
+    }
+    rgba
+}
+
+/// Decodes the levels of the 2D texture held by the KTX2 container `bytes` read from `path`, level
+/// 0 being the full image. The levels are undone of their Zstandard or zlib supercompression,
+/// then the BC, ETC2, EAC and ASTC blocks are kept as they are while the 8-bit texels are
+/// converted to RGBA8.
+///
+/// Basis Universal data can't be transcoded, such files must be exported with another format.
+pub(super) fn decode(bytes: &[u8], path: &Path) -> AppResult<DecodedTexture> {
+    let reader = Reader::new(bytes)
+        .map_err(|err| file_error(AppErrorType::InvalidTextureFile, path, &err.to_string()))?;
+    let header = reader.header();
+
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+        return Err(file_error(
+            AppErrorType::UnsupportedTextureFormat,
+            path,
+            "only 2D textures can be loaded",
+        ));
+    }
+    let Some(format) = header.format else {
+        return Err(file_error(
+            AppErrorType::UnsupportedTextureFormat,
+            path,
+            "Basis Universal data can't be transcoded",
+        ));
+    };
+    // ktx2 formats are the Vulkan ones
+    let vk_format = vk::Format::from_raw(format.value() as i32);
+    let layout = match (block_layout(vk_format), texel_layout(format)) {
+        (Some(blocks), _) => LevelLayout::Blocks(blocks),
+        (None, Some(texels)) => LevelLayout::Texels(texels),
+        (None, None) => {
+            return Err(file_error(
+                AppErrorType::UnsupportedTextureFormat,
+                path,
+                &format!("{:?} textures can't be loaded", format),
+            ))
+        }
+    };
+
+    let mut levels = vec![];
+    for (i, level) in reader.levels().enumerate() {
+        let mut data = match header.supercompression_scheme {
+            None => level.data.to_vec(),
+            Some(SupercompressionScheme::Zstandard) => {
+                let mut data = Vec::with_capacity(level.uncompressed_byte_length as usize);
+                StreamingDecoder::new(level.data)
+                    .map_err(|err| {
+                        file_error(AppErrorType::InvalidTextureFile, path, &err.to_string())
+                    })?
+                    .read_to_end(&mut data)?;
+                data
+            }
+            Some(SupercompressionScheme::ZLIB) => {
+                let mut data = Vec::with_capacity(level.uncompressed_byte_length as usize);
+                ZlibDecoder::new(level.data).read_to_end(&mut data)?;
+                data
+            }
+            Some(scheme) => {
+                return Err(file_error(
+                    AppErrorType::UnsupportedTextureFormat,
+                    path,
+                    &format!("{:?} supercompressed data can't be transcoded", scheme),
+                ))
+            }
+        };
+
+        let width = (header.pixel_width >> i).max(1);
+        let height = (header.pixel_height >> i).max(1);
+        let texel_count = width as usize * height as usize;
+        let size = match &layout {
+            LevelLayout::Blocks(blocks) => blocks.level_size(width, height),
+            LevelLayout::Texels(texels) => texel_count * texels.channels,
+        };
+        if data.len() < size {
+            return Err(file_error(
+                AppErrorType::InvalidTextureFile,
+                path,
+                &format!("level {} is truncated", i),
+            ));
+        }
+
+        let data = match &layout {
+            LevelLayout::Blocks(_) => {
+                data.truncate(size);
+                data
+            }
+            LevelLayout::Texels(texels) => to_rgba(&data, texels, texel_count),
+        };
+        levels.push(TextureLevel {
+            width,
+            height,
+            data,
+        });
+    }
+
+    if levels.is_empty() {
+        return Err(file_error(
+            AppErrorType::InvalidTextureFile,
+            path,
+            "the container holds no level",
+        ));
+    }
+
+    let format = match layout {
+        LevelLayout::Blocks(_) => vk_format,
+        LevelLayout::Texels(_) => RGBA_FORMAT,
+    };
+    Ok(DecodedTexture { format, levels })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+    use ktx2::{Header, Index};
+
+    use super::*;
+
+    // Container of a `width`×`height` texture with the given levels, the data format descriptor
+    // being empty
+    fn container(
+        format: Option<Format>,
+        (width, height): (u32, u32),
+        face_count: u32,
+        supercompression_scheme: Option<SupercompressionScheme>,
+        levels: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let dfd_byte_offset = Header::LENGTH + 24 * levels.len();
+        let header = Header {
+            format,
+            type_size: 1,
+            pixel_width: width,
+            pixel_height: height,
+            pixel_depth: 0,
+            layer_count: 0,
+            face_count,
+            level_count: levels.len() as u32,
+            supercompression_scheme,
+            index: Index {
+                dfd_byte_offset: dfd_byte_offset as u32,
+                dfd_byte_length: 4,
+                kvd_byte_offset: 0,
+                kvd_byte_length: 0,
+                sgd_byte_offset: 0,
+                sgd_byte_length: 0,
+            },
+        };
+
+        let mut bytes = header.as_bytes().to_vec();
+        let mut offset = dfd_byte_offset as u64 + 4;
+        for level in levels {
+            let length = level.len() as u64;
+            for value in [offset, length, length] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            offset += length;
+        }
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        for level in levels {
+            bytes.extend_from_slice(level);
+        }
+        bytes
+    }
+
+    fn decode_error(bytes: &[u8]) -> AppErrorType {
+        decode(bytes, Path::new("test.ktx2"))
+            .err()
+            .unwrap()
+            .error_type
+    }
+
+    #[test]
+    fn keeps_compressed_blocks() {
+        let levels = [vec![1; 16], vec![2; 16]];
+        let bytes = container(Some(Format::BC7_UNORM_BLOCK), (4, 4), 1, None, &levels);
+
+        let texture = decode(&bytes, Path::new("test.ktx2")).unwrap();
+        assert_eq!(texture.format, vk::Format::BC7_UNORM_BLOCK);
+        assert_eq!(texture.levels.len(), 2);
+        assert_eq!((texture.levels[1].width, texture.levels[1].height), (2, 2));
+        assert_eq!(texture.levels[0].data, levels[0]);
+        assert_eq!(texture.levels[1].data, levels[1]);
+    }
+
+    #[test]
+    fn converts_texels_to_rgba() {
+        let rgb = container(
+            Some(Format::R8G8B8_UNORM),
+            (2, 1),
+            1,
+            None,
+            &[vec![1, 2, 3, 4, 5, 6]],
+        );
+        let texture = decode(&rgb, Path::new("test.ktx2")).unwrap();
+        assert_eq!(texture.format, RGBA_FORMAT);
+        assert_eq!(texture.levels[0].data, [1, 2, 3, 255, 4, 5, 6, 255]);
+
+        let bgra = container(
+            Some(Format::B8G8R8A8_UNORM),
+            (1, 1),
+            1,
+            None,
+            &[vec![1, 2, 3, 4]],
+        );
+        let texture = decode(&bgra, Path::new("test.ktx2")).unwrap();
+        assert_eq!(texture.levels[0].data, [3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn undoes_zlib_supercompression() {
+        let texels: Vec<u8> = (0..16).collect();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&texels).unwrap();
+        let bytes = container(
+            Some(Format::R8G8B8A8_UNORM),
+            (2, 2),
+            1,
+            Some(SupercompressionScheme::ZLIB),
+            &[encoder.finish().unwrap()],
+        );
+
+        let texture = decode(&bytes, Path::new("test.ktx2")).unwrap();
+        assert_eq!(texture.levels[0].data, texels);
+    }
+
+    #[test]
+    fn rejects_truncated_levels() {
+        let bytes = container(
+            Some(Format::BC7_UNORM_BLOCK),
+            (8, 8),
+            1,
+            None,
+            &[vec![0; 48]],
+        );
+        assert_eq!(decode_error(&bytes), AppErrorType::InvalidTextureFile);
+        assert_eq!(decode_error(&bytes[..40]), AppErrorType::InvalidTextureFile);
+    }
+
+    #[test]
+    fn rejects_unsupported_textures() {
+        let cubemap = container(
+            Some(Format::BC7_UNORM_BLOCK),
+            (4, 4),
+            6,
+            None,
+            &[vec![0; 96]],
+        );
+        assert_eq!(
+            decode_error(&cubemap),
+            AppErrorType::UnsupportedTextureFormat
+        );
+
+        let basis = container(None, (4, 4), 1, None, &[vec![0; 16]]);
+        assert_eq!(decode_error(&basis), AppErrorType::UnsupportedTextureFormat);
+
+        let float = container(
+            Some(Format::R32G32B32A32_SFLOAT),
+            (1, 1),
+            1,
+            None,
+            &[vec![0; 16]],
+        );
+        assert_eq!(decode_error(&float), AppErrorType::UnsupportedTextureFormat);
+    }
+}
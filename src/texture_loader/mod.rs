@@ -0,0 +1,298 @@
+mod dds;
+mod decompress;
+mod ktx;
+
+pub use dds::DDS_EXTENSION;
+pub use ktx::KTX2_EXTENSION;
+
+use std::{fs, ops::Range, path::Path};
+
+use ash::{vk, Instance};
+use half::f16;
+use image::{imageops::FilterType, ImageBuffer, ImageReader, Pixel, Rgba32FImage, RgbaImage};
+use rayon::prelude::*;
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    AppResult,
+};
+
+/// Extension of the Radiance HDR images, decoded to floating-point textures instead of RGBA8
+pub const HDR_EXTENSION: &str = "hdr";
+
+/// Format of the textures decoded to RGBA8, sampled as sRGB like the images they come from
+pub(crate) const RGBA_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Format of the textures decoded from HDR images, keeping their range without clamping. Every
+/// device samples it with linear filtering.
+pub(crate) const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// A mip level of a decoded texture, its texels or blocks tightly packed
+pub(crate) struct TextureLevel {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl From<RgbaImage> for TextureLevel {
+    fn from(image: RgbaImage) -> Self {
+        Self {
+            width: image.width(),
+            height: image.height(),
+            data: image.into_raw(),
+        }
+    }
+}
+
+impl From<Rgba32FImage> for TextureLevel {
+    fn from(image: Rgba32FImage) -> Self {
+        Self {
+            width: image.width(),
+            height: image.height(),
+            data: image
+                .as_raw()
+                .iter()
+                .flat_map(|&value| f16::from_f32(value).to_le_bytes())
+                .collect(),
+        }
+    }
+}
+
+/// A texture decoded on the host along with its mip chain, level 0 being the full image. The
+/// levels are in `format`, either RGBA8, `HDR_FORMAT` or the block-compressed format of the
+/// file.
+pub(crate) struct DecodedTexture {
+    pub format: vk::Format,
+    pub levels: Vec<TextureLevel>,
+}
+
+impl DecodedTexture {
+    pub(crate) fn from_rgba(levels: Vec<RgbaImage>) -> Self {
+        Self {
+            format: RGBA_FORMAT,
+            levels: levels.into_iter().map(TextureLevel::from).collect(),
+        }
+    }
+
+    /// A texture of `HDR_FORMAT`, the texels being converted to half floats
+    pub(crate) fn from_rgba32f(levels: Vec<Rgba32FImage>) -> Self {
+        Self {
+            format: HDR_FORMAT,
+            levels: levels.into_iter().map(TextureLevel::from).collect(),
+        }
+    }
+
+    pub(crate) fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    pub(crate) fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+
+    pub(crate) fn mip_levels(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    /// Whether the texels can be read on the host, i.e. the texture isn't block-compressed
+    pub(crate) fn is_rgba(&self) -> bool {
+        matches!(
+            self.format,
+            vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM
+        )
+    }
+
+    /// Decodes the blocks of a block-compressed texture to RGBA8 texels. `None` if there is no
+    /// software decoder for its format, only BC1 to BC5 and the ETC2 RGB8 and RGBA8 formats
+    /// having one.
+    pub(crate) fn decompress(&self) -> Option<Self> {
+        decompress::decompress(self)
+    }
+
+    /// Samples the texels as linear values instead of sRGB encoded colors, e.g. for normal maps.
+    /// The data is kept as is, only the format changes.
+    pub(crate) fn into_linear(self) -> Self {
+        let format = match self.format {
+            vk::Format::R8G8B8A8_SRGB => vk::Format::R8G8B8A8_UNORM,
+            vk::Format::BC1_RGB_SRGB_BLOCK => vk::Format::BC1_RGB_UNORM_BLOCK,
+            vk::Format::BC1_RGBA_SRGB_BLOCK => vk::Format::BC1_RGBA_UNORM_BLOCK,
+            vk::Format::BC2_SRGB_BLOCK => vk::Format::BC2_UNORM_BLOCK,
+            vk::Format::BC3_SRGB_BLOCK => vk::Format::BC3_UNORM_BLOCK,
+            vk::Format::BC7_SRGB_BLOCK => vk::Format::BC7_UNORM_BLOCK,
+            vk::Format::ETC2_R8G8B8_SRGB_BLOCK => vk::Format::ETC2_R8G8B8_UNORM_BLOCK,
+            vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK => vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK,
+            vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK => vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK,
+            // The sRGB variant of each ASTC format follows its UNORM one
+            format
+                if (vk::Format::ASTC_4X4_SRGB_BLOCK.as_raw()
+                    ..=vk::Format::ASTC_12X12_SRGB_BLOCK.as_raw())
+                    .contains(&format.as_raw())
+                    && (format.as_raw() - vk::Format::ASTC_4X4_UNORM_BLOCK.as_raw()) % 2 == 1 =>
+            {
+                vk::Format::from_raw(format.as_raw() - 1)
+            }
+            format => format,
+        };
+
+        Self { format, ..self }
+    }
+
+    /// Size of every level once copied in a staging buffer
+    pub(crate) fn byte_size(&self) -> u64 {
+        self.levels
+            .iter()
+            .map(|level| level.data.len() as u64)
+            .sum()
+    }
+}
+
+/// Returns whether the textures of `format` can be sampled on `physical_device`
+pub(crate) fn is_format_supported(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+) -> bool {
+    let properties =
+        unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+    properties
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+}
+
+/// How a texture reached the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureUploadPath {
+    /// Uploaded in the format it was loaded with
+    Native,
+    /// Decompressed to RGBA8 on the host, the device being unable to sample the block-compressed
+    /// format `from`
+    Decompressed { from: vk::Format },
+}
+
+/// Makes `texture` samplable by `physical_device`, decompressing it to RGBA8 when the device
+/// can't sample its block-compressed format. Fails if there is no software decoder for it, see
+/// `DecodedTexture::decompress`.
+pub(crate) fn fit_to_device(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    texture: &mut DecodedTexture,
+) -> AppResult<TextureUploadPath> {
+    let from = texture.format;
+    if is_format_supported(instance, physical_device, from) {
+        return Ok(TextureUploadPath::Native);
+    }
+
+    let Some(decompressed) = texture.decompress() else {
+        return Err(AppError::with_message(
+            AppErrorType::UnsupportedTextureFormat,
+            format!(
+                "{:?} textures can't be sampled by the device nor decompressed",
+                from
+            ),
+        ));
+    };
+    *texture = decompressed;
+    Ok(TextureUploadPath::Decompressed { from })
+}
+
+/// Error of the texture file at `path`
+fn file_error(error_type: AppErrorType, path: &Path, reason: &str) -> AppError {
+    AppError::with_message(error_type, format!("{:?}: {}", path, reason))
+}
+
+/// Decodes the images at `paths` and builds their mip chain, each image on its own thread. The
+/// KTX2 and DDS containers are loaded with their own mip chain when they have one, keeping their
+/// block-compressed format. The HDR images keep their range in `HDR_FORMAT`.
+pub(crate) fn decode_textures<P: AsRef<Path> + Sync>(
+    paths: &[P],
+) -> AppResult<Vec<DecodedTexture>> {
+    paths.par_iter().map(decode_texture).collect()
+}
+
+fn decode_texture<P: AsRef<Path>>(path: P) -> AppResult<DecodedTexture> {
+    let path = path.as_ref();
+    let has_extension = |extension| {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+    };
+    if has_extension(DDS_EXTENSION) {
+        return dds::decode(&fs::read(path)?, path);
+    }
+    if has_extension(HDR_EXTENSION) {
+        let image = ImageReader::open(path)?.decode()?.into_rgba32f();
+        return Ok(DecodedTexture::from_rgba32f(mip_chain(image)));
+    }
+
+    let mut texture = if has_extension(KTX2_EXTENSION) {
+        ktx::decode(&fs::read(path)?, path)?
+    } else {
+        DecodedTexture::from_rgba(vec![ImageReader::open(path)?.decode()?.into_rgba8()])
+    };
+
+    // Only the full image is known, the smaller levels are generated unless it is compressed
+    if texture.is_rgba() && texture.levels.len() == 1 {
+        let level = texture.levels.pop().unwrap();
+        let image = RgbaImage::from_raw(level.width, level.height, level.data).unwrap();
+        texture = DecodedTexture {
+            format: texture.format,
+            levels: mip_chain(image)
+                .into_iter()
+                .map(TextureLevel::from)
+                .collect(),
+        };
+    }
+
+    Ok(texture)
+}
+
+/// Texture of an image decoded elsewhere, along with its mip chain
+#[cfg(feature = "gltf")]
+pub(crate) fn texture_from_rgba(image: RgbaImage) -> DecodedTexture {
+    DecodedTexture::from_rgba(mip_chain(image))
+}
+
+/// Builds the mip chain of `image` down to a single texel, level 0 being the image
+fn mip_chain<P: Pixel + 'static>(
+    image: ImageBuffer<P, Vec<P::Subpixel>>,
+) -> Vec<ImageBuffer<P, Vec<P::Subpixel>>> {
+    let mut levels = vec![image];
+    loop {
+        let previous = levels.last().unwrap();
+        let (width, height) = (previous.width(), previous.height());
+        if width == 1 && height == 1 {
+            break;
+        }
+
+        let level = image::imageops::resize(
+            previous,
+            (width / 2).max(1),
+            (height / 2).max(1),
+            FilterType::Triangle,
+        );
+        levels.push(level);
+    }
+
+    levels
+}
+
+/// Splits `textures` in consecutive batches whose staging data fits in `budget` bytes, a texture
+/// bigger than the budget being uploaded alone
+pub(crate) fn upload_batches(textures: &[DecodedTexture], budget: u64) -> Vec<Range<usize>> {
+    let mut batches = vec![];
+    let mut start = 0;
+    let mut size = 0;
+    for (i, texture) in textures.iter().enumerate() {
+        let texture_size = texture.byte_size();
+        if i > start && size + texture_size > budget {
+            batches.push(start..i);
+            start = i;
+            size = 0;
+        }
+        size += texture_size;
+    }
+    if start < textures.len() {
+        batches.push(start..textures.len());
+    }
+
+    batches
+}
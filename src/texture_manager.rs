@@ -0,0 +1,153 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use ash::{vk, Device};
+
+use crate::{garbage_collector::Garbage, TextureHolder};
+
+/// Identifies a texture loaded by the application
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(pub(crate) usize);
+
+struct ManagedTexture {
+    texture: TextureHolder,
+    // Hash of the path the texture was loaded from, `None` for the generated ones
+    path_hash: Option<u64>,
+    references: usize,
+}
+
+/// Textures loaded once per path and shared by the materials, counting their references.
+///
+/// A texture whose last reference is released is handed back to be retired, as the frames in
+/// flight may still sample it.
+#[derive(Default)]
+pub(crate) struct TextureManager {
+    // Freed textures leave a hole so the handles of the others stay valid
+    textures: Vec<Option<ManagedTexture>>,
+    by_path: HashMap<u64, TextureHandle>,
+}
+
+impl TextureManager {
+    /// Hash the textures loaded from `path` are cached by, the same for every spelling of the
+    /// path when it exists
+    pub(crate) fn path_hash(path: &Path) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        match path.canonicalize() {
+            Ok(path) => path.hash(&mut hasher),
+            Err(_) => path.hash(&mut hasher),
+        }
+        hasher.finish()
+    }
+
+    /// Hash the texture arrays loaded from `paths` are cached by, one layer per path
+    pub(crate) fn paths_hash<'a>(paths: impl Iterator<Item = &'a Path>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for path in paths {
+            Self::path_hash(path).hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Returns the texture loaded from the path of `path_hash` with a new reference, if any
+    pub(crate) fn acquire(&mut self, path_hash: u64) -> Option<TextureHandle> {
+        let handle = *self.by_path.get(&path_hash)?;
+        self.textures[handle.0].as_mut().unwrap().references += 1;
+        Some(handle)
+    }
+
+    /// Adds a texture with a single reference, cached by `path_hash` if it was loaded from a file
+    pub(crate) fn insert(
+        &mut self,
+        texture: TextureHolder,
+        path_hash: Option<u64>,
+    ) -> TextureHandle {
+        let handle = TextureHandle(self.textures.len());
+        self.textures.push(Some(ManagedTexture {
+            texture,
+            path_hash,
+            references: 1,
+        }));
+        if let Some(path_hash) = path_hash {
+            self.by_path.insert(path_hash, handle);
+        }
+        handle
+    }
+
+    pub(crate) fn contains_path(&self, path_hash: u64) -> bool {
+        self.by_path.contains_key(&path_hash)
+    }
+
+    pub(crate) fn view(&self, handle: TextureHandle) -> Option<vk::ImageView> {
+        self.get(handle).map(|texture| texture.view)
+    }
+
+    pub(crate) fn image(&self, handle: TextureHandle) -> Option<vk::Image> {
+        self.get(handle).map(|texture| texture.image.image)
+    }
+
+    /// Returns the texture `view` belongs to, if it is managed
+    pub(crate) fn handle_of(&self, view: vk::ImageView) -> Option<TextureHandle> {
+        self.textures
+            .iter()
+            .position(|texture| texture.as_ref().is_some_and(|t| t.texture.view == view))
+            .map(TextureHandle)
+    }
+
+    /// Releases a reference to a texture, returning it to be destroyed along with its last
+    /// reference
+    pub(crate) fn release(&mut self, handle: TextureHandle) -> Option<TextureHolder> {
+        let slot = self.textures.get_mut(handle.0)?;
+        let managed = slot.as_mut()?;
+
+        managed.references -= 1;
+        if managed.references > 0 {
+            return None;
+        }
+
+        let managed = slot.take().unwrap();
+        if let Some(path_hash) = managed.path_hash {
+            self.by_path.remove(&path_hash);
+        }
+        Some(managed.texture)
+    }
+
+    /// Replaces the texture of `handle`, e.g. reloaded from its file, returning the previous one to
+    /// be destroyed. `None` if the texture is freed.
+    pub(crate) fn replace(
+        &mut self,
+        handle: TextureHandle,
+        texture: TextureHolder,
+    ) -> Option<TextureHolder> {
+        let managed = self.textures.get_mut(handle.0)?.as_mut()?;
+        Some(std::mem::replace(&mut managed.texture, texture))
+    }
+
+    /// Iterates over the textures which aren't freed
+    pub(crate) fn iter(&self) -> impl Iterator<Item = TextureHandle> + '_ {
+        self.textures
+            .iter()
+            .enumerate()
+            .filter(|(_, texture)| texture.is_some())
+            .map(|(i, _)| TextureHandle(i))
+    }
+
+    pub(crate) fn get(&self, handle: TextureHandle) -> Option<&TextureHolder> {
+        self.textures
+            .get(handle.0)?
+            .as_ref()
+            .map(|managed| &managed.texture)
+    }
+
+    /// Destroys every texture, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for managed in self.textures.drain(..).flatten() {
+                Garbage::Texture(managed.texture).destroy(device);
+            }
+        }
+        self.by_path.clear();
+    }
+}
@@ -0,0 +1,394 @@
+use std::{
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
+
+use ash::{vk, Device, Instance};
+use tracing::warn;
+
+use crate::{
+    handle_registry,
+    texture_loader::{self, DecodedTexture},
+    AppResult, Application, BufferHolder, ImageHolder, MaterialHandle,
+};
+
+/// Bytes uploaded per frame by default
+pub const DEFAULT_STREAM_BUDGET: u64 = 4 * 1024 * 1024;
+
+struct StreamedTexture {
+    material: MaterialHandle,
+    // Set while the image is decoded in the background
+    receiver: Option<Receiver<AppResult<DecodedTexture>>>,
+    // Set until every level is resident
+    decoded: Option<DecodedTexture>,
+    image: Option<ImageHolder>,
+    view: vk::ImageView,
+    // Most detailed level uploaded, the mip count when none is
+    resident_level: u32,
+}
+
+#[derive(Default)]
+struct StreamFrame {
+    command_buffer: vk::CommandBuffer,
+    staging_buffer: Option<BufferHolder>,
+    // Views replaced while the frame was recorded, other frames may still sample them
+    retired_views: Vec<vk::ImageView>,
+}
+
+/// Work of a frame returned by `TextureStreamer::update`
+#[derive(Default)]
+pub(crate) struct StreamUpdate {
+    /// Uploads to execute before the frame samples the new levels
+    pub command_buffer: Option<vk::CommandBuffer>,
+    /// Materials whose texture gained levels, with the view covering the resident ones
+    pub refined: Vec<(MaterialHandle, vk::ImageView)>,
+}
+
+/// Textures uploaded progressively, from the smallest mip level to the full resolution image, so a
+/// big texture never stalls a frame. The bytes uploaded each frame are limited by a budget.
+pub(crate) struct TextureStreamer {
+    textures: Vec<StreamedTexture>,
+    frames: Vec<StreamFrame>,
+    budget: u64,
+}
+
+impl TextureStreamer {
+    pub(crate) fn new(
+        device: &Device,
+        command_pool: vk::CommandPool,
+        max_frame_in_flight: u32,
+    ) -> AppResult<Self> {
+        let command_buffers =
+            Application::create_command_buffers(device, command_pool, max_frame_in_flight)?;
+
+        Ok(Self {
+            textures: Vec::new(),
+            frames: command_buffers
+                .into_iter()
+                .map(|command_buffer| StreamFrame {
+                    command_buffer,
+                    ..Default::default()
+                })
+                .collect(),
+            budget: DEFAULT_STREAM_BUDGET,
+        })
+    }
+
+    /// Decodes the image at `path` in the background, its levels then being streamed to
+    /// `material`
+    pub(crate) fn add(&mut self, path: PathBuf, material: MaterialHandle) {
+        let (sender, receiver) = mpsc::channel();
+        rayon::spawn(move || {
+            let decoded =
+                texture_loader::decode_textures(&[path]).map(|mut decoded| decoded.pop().unwrap());
+            // The streamer may have been destroyed in the meantime
+            let _ = sender.send(decoded);
+        });
+
+        self.textures.push(StreamedTexture {
+            material,
+            receiver: Some(receiver),
+            decoded: None,
+            image: None,
+            view: vk::ImageView::null(),
+            resident_level: 0,
+        });
+    }
+
+    pub(crate) fn set_budget(&mut self, budget: u64) {
+        self.budget = budget;
+    }
+
+    pub(crate) fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Records the uploads of `frame`, at least one level if any is left even if it exceeds the
+    /// budget. The previous work of `frame` must be done.
+    pub(crate) fn update(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        frame: usize,
+    ) -> AppResult<StreamUpdate> {
+        unsafe { Self::release_frame(device, &mut self.frames[frame]) };
+
+        for texture in self.textures.iter_mut() {
+            let Some(receiver) = &texture.receiver else {
+                continue;
+            };
+
+            let mut decoded = match receiver.try_recv() {
+                Ok(Ok(decoded)) => decoded,
+                Ok(Err(err)) => {
+                    warn!(%err, "failed to decode a streamed texture");
+                    texture.receiver = None;
+                    continue;
+                }
+                Err(TryRecvError::Empty) => continue,
+                Err(TryRecvError::Disconnected) => {
+                    texture.receiver = None;
+                    continue;
+                }
+            };
+            if let Err(err) = texture_loader::fit_to_device(instance, physical_device, &mut decoded)
+            {
+                warn!(%err, "unsupported streamed texture");
+                texture.receiver = None;
+                continue;
+            }
+
+            texture.image = Some(Application::create_image(
+                instance,
+                device,
+                physical_device,
+                decoded.width(),
+                decoded.height(),
+                decoded.mip_levels(),
+                decoded.format,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?);
+            texture.resident_level = decoded.mip_levels();
+            texture.decoded = Some(decoded);
+            texture.receiver = None;
+        }
+
+        // Picks the levels to upload, the smallest first
+        let mut uploads: Vec<(usize, u32)> = vec![];
+        let mut upload_size = 0;
+        'textures: for (i, texture) in self.textures.iter().enumerate() {
+            let Some(decoded) = &texture.decoded else {
+                continue;
+            };
+            for level in (0..texture.resident_level).rev() {
+                let size = decoded.levels[level as usize].data.len() as u64;
+                if upload_size > 0 && upload_size + size > self.budget {
+                    break 'textures;
+                }
+                uploads.push((i, level));
+                upload_size += size;
+            }
+        }
+
+        if uploads.is_empty() {
+            return Ok(StreamUpdate::default());
+        }
+
+        let staging_buffer = Application::create_buffer(
+            instance,
+            device,
+            physical_device,
+            upload_size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let staging = (staging_buffer.buffer, staging_buffer.memory);
+        let command_buffer = self.frames[frame].command_buffer;
+        self.frames[frame].staging_buffer = Some(staging_buffer);
+
+        let level_barrier =
+            |image, level, old_layout, new_layout, src_access, dst_access| vk::ImageMemoryBarrier {
+                src_access_mask: src_access,
+                dst_access_mask: dst_access,
+                old_layout,
+                new_layout,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: level,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                ..Default::default()
+            };
+
+        unsafe {
+            let buffer_memory_ptr =
+                device.map_memory(staging.1, 0, upload_size, vk::MemoryMapFlags::empty())?
+                    as *mut u8;
+
+            device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+            let begin_info = vk::CommandBufferBeginInfo {
+                flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                ..Default::default()
+            };
+            device.begin_command_buffer(command_buffer, &begin_info)?;
+
+            let mut offset = 0;
+            for &(i, level) in uploads.iter() {
+                let texture = &self.textures[i];
+                let image = texture.image.as_ref().unwrap().image;
+                let level_data = &texture.decoded.as_ref().unwrap().levels[level as usize];
+                let data = &level_data.data;
+                let (width, height) = (level_data.width, level_data.height);
+                std::ptr::copy(data.as_ptr(), buffer_memory_ptr.add(offset), data.len());
+
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[level_barrier(
+                        image,
+                        level,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    )],
+                );
+                let region = vk::BufferImageCopy {
+                    buffer_offset: offset as u64,
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    },
+                    image_extent: vk::Extent3D {
+                        width,
+                        height,
+                        depth: 1,
+                    },
+                    ..Default::default()
+                };
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    staging.0,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[region],
+                );
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[level_barrier(
+                        image,
+                        level,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::SHADER_READ,
+                    )],
+                );
+
+                offset += data.len();
+            }
+
+            device.unmap_memory(staging.1);
+            device.end_command_buffer(command_buffer)?;
+        }
+
+        // Exposes the new levels through a new view, the previous one is destroyed once no frame
+        // uses it anymore
+        let mut refined = vec![];
+        for (n, &(i, level)) in uploads.iter().enumerate() {
+            // The levels of a texture are consecutive, the last one being the most detailed
+            if uploads.get(n + 1).is_some_and(|&(next, _)| next == i) {
+                continue;
+            }
+
+            let texture = &mut self.textures[i];
+            let decoded = texture.decoded.as_ref().unwrap();
+            let view = Self::create_view(
+                device,
+                texture.image.as_ref().unwrap().image,
+                decoded.format,
+                level,
+                decoded.mip_levels() - level,
+            )?;
+            if texture.view != vk::ImageView::null() {
+                self.frames[frame].retired_views.push(texture.view);
+            }
+            texture.view = view;
+            texture.resident_level = level;
+            if level == 0 {
+                texture.decoded = None;
+            }
+
+            refined.push((texture.material, view));
+        }
+
+        Ok(StreamUpdate {
+            command_buffer: Some(command_buffer),
+            refined,
+        })
+    }
+
+    /// Destroys the streamed textures, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for frame in self.frames.iter_mut() {
+                Self::release_frame(device, frame);
+            }
+
+            for texture in self.textures.drain(..) {
+                if texture.view != vk::ImageView::null() {
+                    handle_registry::unregister(texture.view);
+                    device.destroy_image_view(texture.view, None);
+                }
+                if let Some(image) = texture.image {
+                    handle_registry::unregister(image.image);
+                    device.destroy_image(image.image, None);
+                    handle_registry::unregister(image.memory);
+                    device.free_memory(image.memory, None);
+                }
+            }
+        }
+    }
+
+    /// Destroys the staging buffer and the views retired by the previous use of `frame`
+    unsafe fn release_frame(device: &Device, frame: &mut StreamFrame) {
+        if let Some(buffer) = frame.staging_buffer.take() {
+            handle_registry::unregister(buffer.buffer);
+            device.destroy_buffer(buffer.buffer, None);
+            handle_registry::unregister(buffer.memory);
+            device.free_memory(buffer.memory, None);
+        }
+
+        for view in frame.retired_views.drain(..) {
+            handle_registry::unregister(view);
+            device.destroy_image_view(view, None);
+        }
+    }
+
+    fn create_view(
+        device: &Device,
+        image: vk::Image,
+        format: vk::Format,
+        base_mip_level: u32,
+        level_count: u32,
+    ) -> AppResult<vk::ImageView> {
+        let create_info = vk::ImageViewCreateInfo {
+            image,
+            view_type: vk::ImageViewType::TYPE_2D,
+            format,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level,
+                level_count,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.create_image_view(&create_info, None)?,
+            ))
+        }
+    }
+}
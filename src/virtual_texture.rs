@@ -0,0 +1,1400 @@
+use std::{
+    collections::{HashMap, HashSet},
+    slice,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+};
+
+use ash::{vk, Device, Instance};
+
+use crate::{
+    app_error::{AppError, AppErrorType},
+    frame_arena::FrameArena,
+    handle_registry, load_shader,
+    texture_loader::DecodedTexture,
+    AppResult, Application, ImageHolder, MemoryMappedBuffer, SyncPool,
+};
+
+/// Format of the virtual textures, a page source writes RGBA8 texels
+pub const VIRTUAL_TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+/// Pages each virtual texture can keep resident, besides the coarsest levels which always are
+pub const VIRTUAL_TEXTURE_POOL_PAGES: u32 = 256;
+
+const PAGE_TABLE_FORMAT: vk::Format = vk::Format::R8_UINT;
+// Feedback value of a region no draw sampled
+const NO_REQUEST: u32 = u32::MAX;
+// Pages read by the page source at the same time
+const MAX_PENDING_PAGES: usize = 32;
+const MAX_PAGE_UPLOADS_PER_FRAME: usize = 16;
+
+/// Provides the texels of a virtual texture, page by page, from the background threads
+pub trait PageSource: Send + Sync {
+    /// Size of the level 0 of the texture
+    fn extent(&self) -> (u32, u32);
+
+    /// Writes the RGBA8 texels of the `width` × `height` area of `level` at (`x`, `y`) to
+    /// `texels`, row by row
+    fn read(&self, level: u32, x: u32, y: u32, width: u32, height: u32, texels: &mut [u8]);
+}
+
+impl PageSource for DecodedTexture {
+    fn extent(&self) -> (u32, u32) {
+        (self.width(), self.height())
+    }
+
+    fn read(&self, level: u32, x: u32, y: u32, width: u32, height: u32, texels: &mut [u8]) {
+        let image = &self.levels[level as usize];
+        let row_size = width as usize * 4;
+        let rows = texels.chunks_exact_mut(row_size).take(height as usize);
+        for (row, texels) in rows.enumerate() {
+            let start = ((y as usize + row) * image.width as usize + x as usize) * 4;
+            texels.copy_from_slice(&image.data[start..start + row_size]);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct PageKey {
+    level: u32,
+    x: u32,
+    y: u32,
+}
+
+enum PageState {
+    Loading,
+    Resident { slot: u32, last_seen: u64 },
+}
+
+struct PagedLevel {
+    width: u32,
+    height: u32,
+    pages_x: u32,
+    pages_y: u32,
+}
+
+struct TextureFrame {
+    descriptor_set: vk::DescriptorSet,
+    feedback: MemoryMappedBuffer,
+    staging: MemoryMappedBuffer,
+}
+
+/// A sparse image whose pages are made resident when the feedback of the frames samples them
+struct VirtualTexture {
+    source: Arc<dyn PageSource>,
+    image: vk::Image,
+    view: vk::ImageView,
+    mip_levels: u32,
+    // Levels bound page by page, the following ones are in the mip tail
+    levels: Vec<PagedLevel>,
+    // First level whose pages are always resident
+    pinned_level: u32,
+    page_extent: vk::Extent2D,
+    page_size: u64,
+    pool: vk::DeviceMemory,
+    free_slots: Vec<u32>,
+    mip_tail: Option<vk::DeviceMemory>,
+    pages: HashMap<PageKey, PageState>,
+    // Loaded pages waiting for a slot
+    loaded: Vec<(PageKey, Vec<u8>)>,
+    sender: Sender<(PageKey, Vec<u8>)>,
+    receiver: Receiver<(PageKey, Vec<u8>)>,
+    pending: usize,
+    // One texel per page of level 0, the most detailed level that can be sampled there
+    page_table: Vec<u8>,
+    page_table_image: ImageHolder,
+    page_table_view: vk::ImageView,
+    page_table_dirty: bool,
+    descriptor_pool: vk::DescriptorPool,
+    frames: Vec<TextureFrame>,
+    frame_counter: u64,
+}
+
+struct UploadFrame {
+    command_buffer: vk::CommandBuffer,
+    bind_semaphore: vk::Semaphore,
+}
+
+/// Work of a frame returned by `VirtualTexturing::update`
+#[derive(Default)]
+pub(crate) struct VirtualTextureUpdate {
+    /// Uploads to execute before the frame samples the new pages
+    pub command_buffer: Option<vk::CommandBuffer>,
+    /// Signaled once the new pages are bound, the uploads must wait for it
+    pub bind_semaphore: Option<vk::Semaphore>,
+}
+
+/// Virtual textures, i.e. sparse images only partially resident, drawn by their own scene pipeline.
+///
+/// The pipeline writes the most detailed level sampled in each page sized region to a feedback
+/// buffer, which is read back once the frame is done. The missing pages are then read from their
+/// source in the background and bound and uploaded along with a later frame, the least recently
+/// used pages being evicted once the pool of a texture is full. Until then the shader samples the
+/// most detailed resident level, found in a page table.
+pub(crate) struct VirtualTexturing {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    set_layout: vk::DescriptorSetLayout,
+    sampler: vk::Sampler,
+    // The page table is fetched, an integer format can't be filtered
+    page_table_sampler: vk::Sampler,
+    frames: Vec<UploadFrame>,
+    textures: Vec<VirtualTexture>,
+}
+
+impl VirtualTexturing {
+    pub(crate) fn new(
+        device: &Device,
+        scene_render_pass: vk::RenderPass,
+        samples: vk::SampleCountFlags,
+        scene_set_layout: vk::DescriptorSetLayout,
+        command_pool: vk::CommandPool,
+        max_frame_in_flight: u32,
+    ) -> AppResult<Self> {
+        let bindings = [
+            (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 0),
+            (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1),
+            (vk::DescriptorType::STORAGE_BUFFER, 2),
+        ]
+        .map(
+            |(descriptor_type, binding)| vk::DescriptorSetLayoutBinding {
+                binding,
+                descriptor_type,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+        );
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+        handle_registry::register(set_layout);
+
+        // The scene set comes first so the sets of the scene pipeline stay compatible
+        let pipeline_layout =
+            Application::create_pipeline_layout(device, &[scene_set_layout, set_layout], &[])?;
+        let pipeline = Application::create_scene_pipeline(
+            device,
+            scene_render_pass,
+            samples,
+            pipeline_layout,
+            &load_shader("vertex.vert", include_bytes!("spirv/vertex.spv"))?,
+            &load_shader(
+                "virtual_texture.frag",
+                include_bytes!("spirv/virtual_texture.spv"),
+            )?,
+            vk::CullModeFlags::BACK,
+        )?;
+
+        // Anisotropic filtering would read further than the neighbour pages kept resident
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            max_lod: vk::LOD_CLAMP_NONE,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.create_sampler(&sampler_info, None)? };
+        handle_registry::register(sampler);
+        let page_table_sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..Default::default()
+        };
+        let page_table_sampler = unsafe { device.create_sampler(&page_table_sampler_info, None)? };
+        handle_registry::register(page_table_sampler);
+
+        let command_buffers =
+            Application::create_command_buffers(device, command_pool, max_frame_in_flight)?;
+        let mut frames = Vec::with_capacity(command_buffers.len());
+        for command_buffer in command_buffers {
+            let semaphore_info = vk::SemaphoreCreateInfo::default();
+            let bind_semaphore = unsafe { device.create_semaphore(&semaphore_info, None)? };
+            handle_registry::register(bind_semaphore);
+            frames.push(UploadFrame {
+                command_buffer,
+                bind_semaphore,
+            });
+        }
+
+        Ok(Self {
+            pipeline,
+            pipeline_layout,
+            set_layout,
+            sampler,
+            page_table_sampler,
+            frames,
+            textures: Vec::new(),
+        })
+    }
+
+    pub(crate) fn pipeline(&self) -> vk::Pipeline {
+        self.pipeline
+    }
+
+    pub(crate) fn pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline_layout
+    }
+
+    /// Returns the set binding the texture `index` to the pipeline in `frame`, as set 1
+    pub(crate) fn descriptor_set(&self, index: usize, frame: usize) -> vk::DescriptorSet {
+        self.textures[index].frames[frame].descriptor_set
+    }
+
+    /// Creates a virtual texture reading its pages from `source`, binds and uploads its coarsest
+    /// levels then returns its index
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn add(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        source: Arc<dyn PageSource>,
+    ) -> AppResult<usize> {
+        let texture = VirtualTexture::new(
+            instance,
+            device,
+            physical_device,
+            queue,
+            command_pool,
+            sync_pool,
+            self.set_layout,
+            [self.sampler, self.page_table_sampler],
+            source,
+            self.frames.len(),
+        )?;
+        self.textures.push(texture);
+
+        Ok(self.textures.len() - 1)
+    }
+
+    /// Size of the memory backing the texture `index`
+    pub(crate) fn memory_size(&self, index: usize) -> u64 {
+        let texture = &self.textures[index];
+        let pool_pages = texture.free_slots.len() + texture.resident_count();
+        pool_pages as u64 * texture.page_size
+    }
+
+    /// Reads the feedback of the previous use of `frame`, requests the missing pages, then binds
+    /// and records the upload of the loaded ones. The previous work of `frame` must be done.
+    pub(crate) fn update(
+        &mut self,
+        device: &Device,
+        queue: vk::Queue,
+        frame: usize,
+        arena: &FrameArena,
+    ) -> AppResult<VirtualTextureUpdate> {
+        let upload_frame = &self.frames[frame];
+        let command_buffer = upload_frame.command_buffer;
+
+        let mut binds = arena.vec();
+        let mut recorded = false;
+        for texture in self.textures.iter_mut() {
+            texture.read_feedback(frame);
+            texture.receive_pages();
+
+            let (texture_binds, uploads) = texture.allocate_pages(self.frames.len());
+            if !texture_binds.is_empty() {
+                binds.push((texture.image, texture_binds));
+            }
+            if uploads.is_empty() && !texture.page_table_dirty {
+                continue;
+            }
+
+            unsafe {
+                if !recorded {
+                    device.reset_command_buffer(
+                        command_buffer,
+                        vk::CommandBufferResetFlags::empty(),
+                    )?;
+                    let begin_info = vk::CommandBufferBeginInfo {
+                        flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                        ..Default::default()
+                    };
+                    device.begin_command_buffer(command_buffer, &begin_info)?;
+                    recorded = true;
+                }
+                texture.record_uploads(device, command_buffer, frame, &uploads, arena);
+            }
+        }
+
+        if !recorded {
+            return Ok(VirtualTextureUpdate::default());
+        }
+        unsafe { device.end_command_buffer(command_buffer)? };
+
+        if binds.is_empty() {
+            return Ok(VirtualTextureUpdate {
+                command_buffer: Some(command_buffer),
+                bind_semaphore: None,
+            });
+        }
+
+        let image_binds = arena.collect(binds.iter().map(|(image, binds)| {
+            vk::SparseImageMemoryBindInfo::default()
+                .image(*image)
+                .binds(binds)
+        }));
+        let bind_info = vk::BindSparseInfo {
+            image_bind_count: image_binds.len() as u32,
+            p_image_binds: image_binds.as_ptr(),
+            signal_semaphore_count: 1,
+            p_signal_semaphores: &upload_frame.bind_semaphore as *const _,
+            ..Default::default()
+        };
+        unsafe { device.queue_bind_sparse(queue, &[bind_info], vk::Fence::null())? };
+
+        Ok(VirtualTextureUpdate {
+            command_buffer: Some(command_buffer),
+            bind_semaphore: Some(upload_frame.bind_semaphore),
+        })
+    }
+
+    /// Makes the feedback written by the draws of the frame visible to the host, recorded once
+    /// the scene pass ends
+    pub(crate) fn record_feedback_barrier(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        let barrier = vk::MemoryBarrier {
+            src_access_mask: vk::AccessFlags::SHADER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            ..Default::default()
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    /// Destroys the virtual textures and the pipeline, the device must be idle
+    pub(crate) fn destroy(&mut self, device: &Device) {
+        unsafe {
+            for texture in self.textures.drain(..) {
+                texture.destroy(device);
+            }
+
+            for frame in self.frames.iter() {
+                handle_registry::unregister(frame.bind_semaphore);
+                device.destroy_semaphore(frame.bind_semaphore, None);
+            }
+
+            handle_registry::unregister(self.sampler);
+            device.destroy_sampler(self.sampler, None);
+            handle_registry::unregister(self.page_table_sampler);
+            device.destroy_sampler(self.page_table_sampler, None);
+            handle_registry::unregister(self.pipeline);
+            device.destroy_pipeline(self.pipeline, None);
+            handle_registry::unregister(self.pipeline_layout);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            handle_registry::unregister(self.set_layout);
+            device.destroy_descriptor_set_layout(self.set_layout, None);
+        }
+    }
+}
+
+impl VirtualTexture {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        set_layout: vk::DescriptorSetLayout,
+        samplers: [vk::Sampler; 2],
+        source: Arc<dyn PageSource>,
+        frame_count: usize,
+    ) -> AppResult<Self> {
+        let usage = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+        let format_properties = unsafe {
+            instance.get_physical_device_sparse_image_format_properties(
+                physical_device,
+                VIRTUAL_TEXTURE_FORMAT,
+                vk::ImageType::TYPE_2D,
+                vk::SampleCountFlags::TYPE_1,
+                usage,
+                vk::ImageTiling::OPTIMAL,
+            )
+        };
+        if format_properties.is_empty() {
+            return AppResult::Err(AppError::new(AppErrorType::SparseResidencyUnsupported));
+        }
+
+        let (width, height) = source.extent();
+        let mip_levels = 32 - width.max(height).leading_zeros();
+        let image_info = vk::ImageCreateInfo {
+            flags: vk::ImageCreateFlags::SPARSE_BINDING | vk::ImageCreateFlags::SPARSE_RESIDENCY,
+            image_type: vk::ImageType::TYPE_2D,
+            format: VIRTUAL_TEXTURE_FORMAT,
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_levels,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            ..Default::default()
+        };
+        let image = unsafe { device.create_image(&image_info, None)? };
+        handle_registry::register(image);
+
+        let memory_requirements = unsafe { device.get_image_memory_requirements(image) };
+        let sparse_requirements = unsafe { device.get_image_sparse_memory_requirements(image) }
+            .into_iter()
+            .find(|requirements| {
+                requirements
+                    .format_properties
+                    .aspect_mask
+                    .contains(vk::ImageAspectFlags::COLOR)
+            })
+            .ok_or_else(|| AppError::new(AppErrorType::SparseResidencyUnsupported))?;
+        let granularity = sparse_requirements.format_properties.image_granularity;
+        let page_extent = vk::Extent2D {
+            width: granularity.width,
+            height: granularity.height,
+        };
+        let page_size = memory_requirements.alignment;
+        let memory_type_index = Application::find_memory_type(
+            instance,
+            physical_device,
+            memory_requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let tail_level = sparse_requirements.image_mip_tail_first_lod.min(mip_levels);
+        let levels: Vec<PagedLevel> = (0..tail_level)
+            .map(|level| {
+                let width = (width >> level).max(1);
+                let height = (height >> level).max(1);
+                PagedLevel {
+                    width,
+                    height,
+                    pages_x: width.div_ceil(page_extent.width),
+                    pages_y: height.div_ceil(page_extent.height),
+                }
+            })
+            .collect();
+        // Without mip tail the last level is a single page
+        let pinned_level = tail_level.min(mip_levels - 1);
+        let pinned_pages: u32 = levels[pinned_level as usize..]
+            .iter()
+            .map(|level| level.pages_x * level.pages_y)
+            .sum();
+
+        let pool_pages = VIRTUAL_TEXTURE_POOL_PAGES + pinned_pages;
+        let pool = Self::allocate(device, pool_pages as u64 * page_size, memory_type_index)?;
+        let mip_tail = if tail_level < mip_levels {
+            Some(Self::allocate(
+                device,
+                sparse_requirements.image_mip_tail_size,
+                memory_type_index,
+            )?)
+        } else {
+            None
+        };
+
+        // A texture small enough to fit in its mip tail has a single region
+        let (regions_x, regions_y) = levels
+            .first()
+            .map_or((1, 1), |level| (level.pages_x, level.pages_y));
+        let page_table_image = Application::create_image(
+            instance,
+            device,
+            physical_device,
+            regions_x,
+            regions_y,
+            1,
+            PAGE_TABLE_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let page_table_view =
+            Application::create_image_view(device, page_table_image.image, PAGE_TABLE_FORMAT, 1)?;
+        let view =
+            Application::create_image_view(device, image, VIRTUAL_TEXTURE_FORMAT, mip_levels)?;
+
+        let (sender, receiver) = mpsc::channel();
+        let mut texture = Self {
+            source,
+            image,
+            view,
+            mip_levels,
+            levels,
+            pinned_level,
+            page_extent,
+            page_size,
+            pool,
+            free_slots: (0..pool_pages).rev().collect(),
+            mip_tail,
+            pages: HashMap::new(),
+            loaded: Vec::new(),
+            sender,
+            receiver,
+            pending: 0,
+            page_table: vec![pinned_level as u8; (regions_x * regions_y) as usize],
+            page_table_image,
+            page_table_view,
+            page_table_dirty: false,
+            descriptor_pool: vk::DescriptorPool::null(),
+            frames: Vec::with_capacity(frame_count),
+            frame_counter: 0,
+        };
+
+        texture.upload_pinned_levels(
+            instance,
+            device,
+            physical_device,
+            queue,
+            command_pool,
+            sync_pool,
+            &sparse_requirements,
+        )?;
+        texture.create_frames(
+            instance,
+            device,
+            physical_device,
+            set_layout,
+            samplers,
+            frame_count,
+        )?;
+
+        Ok(texture)
+    }
+
+    fn allocate(device: &Device, size: u64, memory_type_index: u32) -> AppResult<vk::DeviceMemory> {
+        let alloc_info = vk::MemoryAllocateInfo {
+            allocation_size: size,
+            memory_type_index,
+            ..Default::default()
+        };
+
+        unsafe {
+            Ok(handle_registry::register(
+                device.allocate_memory(&alloc_info, None)?,
+            ))
+        }
+    }
+
+    /// Binds the mip tail and the pinned pages, then uploads them along with the page table. Every
+    /// level is left in the general layout, so pages can be uploaded while others are sampled.
+    #[allow(clippy::too_many_arguments)]
+    fn upload_pinned_levels(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+        sync_pool: &SyncPool,
+        sparse_requirements: &vk::SparseImageMemoryRequirements,
+    ) -> AppResult<()> {
+        let mut page_binds = vec![];
+        let mut page_texels = vec![];
+        for level in self.pinned_level..self.levels.len() as u32 {
+            let paged_level = &self.levels[level as usize];
+            for y in 0..paged_level.pages_y {
+                for x in 0..paged_level.pages_x {
+                    let key = PageKey { level, x, y };
+                    let slot = self.free_slots.pop().unwrap();
+                    page_binds.push(self.page_bind(key, self.pool, slot));
+                    page_texels.push((key, self.read_page(key)));
+                    self.pages.insert(
+                        key,
+                        PageState::Resident {
+                            slot,
+                            last_seen: u64::MAX,
+                        },
+                    );
+                }
+            }
+        }
+
+        let opaque_binds: Vec<vk::SparseMemoryBind> = self
+            .mip_tail
+            .iter()
+            .map(|&memory| vk::SparseMemoryBind {
+                resource_offset: sparse_requirements.image_mip_tail_offset,
+                size: sparse_requirements.image_mip_tail_size,
+                memory,
+                memory_offset: 0,
+                ..Default::default()
+            })
+            .collect();
+        let image_binds = [vk::SparseImageMemoryBindInfo::default()
+            .image(self.image)
+            .binds(&page_binds)];
+        let opaque_bind_infos = [vk::SparseImageOpaqueMemoryBindInfo::default()
+            .image(self.image)
+            .binds(&opaque_binds)];
+        let bind_info = vk::BindSparseInfo {
+            image_bind_count: u32::from(!page_binds.is_empty()),
+            p_image_binds: image_binds.as_ptr(),
+            image_opaque_bind_count: u32::from(!opaque_binds.is_empty()),
+            p_image_opaque_binds: opaque_bind_infos.as_ptr(),
+            ..Default::default()
+        };
+        sync_pool.with_fence(device, |fence| unsafe {
+            device.queue_bind_sparse(queue, &[bind_info], fence)?;
+            device.wait_for_fences(&[fence], true, u64::MAX)?;
+            Ok(())
+        })?;
+
+        // The mip tail levels are uploaded whole
+        let tail_texels: Vec<(u32, Vec<u8>)> = (self.levels.len() as u32..self.mip_levels)
+            .map(|level| {
+                let (width, height) = self.level_extent(level);
+                let mut texels = vec![0; (width * height * 4) as usize];
+                self.source.read(level, 0, 0, width, height, &mut texels);
+                (level, texels)
+            })
+            .collect();
+
+        let staging_size = page_texels
+            .iter()
+            .map(|(_, texels)| texels.len())
+            .chain(tail_texels.iter().map(|(_, texels)| texels.len()))
+            .sum::<usize>()
+            + self.page_table.len();
+        let staging_buffer = Application::create_buffer(
+            instance,
+            device,
+            physical_device,
+            staging_size as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let mut copies = vec![];
+        unsafe {
+            let staging_ptr = device.map_memory(
+                staging_buffer.memory,
+                0,
+                staging_size as u64,
+                vk::MemoryMapFlags::empty(),
+            )? as *mut u8;
+
+            let mut offset = 0;
+            for (key, texels) in page_texels.iter() {
+                std::ptr::copy(texels.as_ptr(), staging_ptr.add(offset), texels.len());
+                copies.push(self.page_copy(*key, offset as u64));
+                offset += texels.len();
+            }
+            for (level, texels) in tail_texels.iter() {
+                std::ptr::copy(texels.as_ptr(), staging_ptr.add(offset), texels.len());
+                let (width, height) = self.level_extent(*level);
+                copies.push(Self::copy_region(
+                    *level,
+                    0,
+                    0,
+                    width,
+                    height,
+                    offset as u64,
+                ));
+                offset += texels.len();
+            }
+            std::ptr::copy(
+                self.page_table.as_ptr(),
+                staging_ptr.add(offset),
+                self.page_table.len(),
+            );
+            let page_table_copy = self.page_table_copy(offset as u64);
+
+            device.unmap_memory(staging_buffer.memory);
+
+            let command_buffer = Application::begin_singe_time_command(device, command_pool)?;
+            let to_transfer = [
+                Self::image_barrier(
+                    self.image,
+                    self.mip_levels,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                Self::image_barrier(
+                    self.page_table_image.image,
+                    1,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            ];
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_transfer,
+            );
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.buffer,
+                self.image,
+                vk::ImageLayout::GENERAL,
+                &copies,
+            );
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_buffer.buffer,
+                self.page_table_image.image,
+                vk::ImageLayout::GENERAL,
+                &[page_table_copy],
+            );
+            let to_shader = [
+                Self::image_barrier(
+                    self.image,
+                    self.mip_levels,
+                    vk::ImageLayout::GENERAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+                Self::image_barrier(
+                    self.page_table_image.image,
+                    1,
+                    vk::ImageLayout::GENERAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+            ];
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_shader,
+            );
+            Application::end_single_time_command(
+                device,
+                queue,
+                command_pool,
+                sync_pool,
+                command_buffer,
+            )?;
+
+            handle_registry::unregister(staging_buffer.buffer);
+            device.destroy_buffer(staging_buffer.buffer, None);
+            handle_registry::unregister(staging_buffer.memory);
+            device.free_memory(staging_buffer.memory, None);
+        }
+
+        Ok(())
+    }
+
+    /// Creates the feedback and staging buffers and the descriptor set of each frame in flight
+    fn create_frames(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        physical_device: vk::PhysicalDevice,
+        set_layout: vk::DescriptorSetLayout,
+        samplers: [vk::Sampler; 2],
+        frame_count: usize,
+    ) -> AppResult<()> {
+        let feedback_size = (self.page_table.len() * 4) as u64;
+        let feedbacks = Application::create_host_visible_buffers(
+            instance,
+            device,
+            physical_device,
+            feedback_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            frame_count,
+        )?;
+        let page_bytes = (self.page_extent.width * self.page_extent.height * 4) as usize;
+        let stagings = Application::create_host_visible_buffers(
+            instance,
+            device,
+            physical_device,
+            (MAX_PAGE_UPLOADS_PER_FRAME * page_bytes + self.page_table.len()) as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            frame_count,
+        )?;
+
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 2 * frame_count as u32,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: frame_count as u32,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: frame_count as u32,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        self.descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None)? };
+        handle_registry::register(self.descriptor_pool);
+
+        let layouts = vec![set_layout; frame_count];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool: self.descriptor_pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_sets = unsafe { device.allocate_descriptor_sets(&alloc_info)? };
+
+        let image_infos = [
+            vk::DescriptorImageInfo {
+                sampler: samplers[0],
+                image_view: self.view,
+                image_layout: vk::ImageLayout::GENERAL,
+            },
+            vk::DescriptorImageInfo {
+                sampler: samplers[1],
+                image_view: self.page_table_view,
+                image_layout: vk::ImageLayout::GENERAL,
+            },
+        ];
+        for ((descriptor_set, feedback), staging) in
+            descriptor_sets.into_iter().zip(feedbacks).zip(stagings)
+        {
+            // Safety: The buffer is mapped and holds one value per region
+            unsafe {
+                slice::from_raw_parts_mut(feedback.memory_map as *mut u32, self.page_table.len())
+                    .fill(NO_REQUEST);
+            }
+
+            let buffer_info = vk::DescriptorBufferInfo {
+                buffer: feedback.buffer,
+                offset: 0,
+                range: feedback_size,
+            };
+            let descriptor_writes = [
+                vk::WriteDescriptorSet {
+                    dst_set: descriptor_set,
+                    dst_binding: 0,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    p_image_info: &image_infos[0] as *const _,
+                    ..Default::default()
+                },
+                vk::WriteDescriptorSet {
+                    dst_set: descriptor_set,
+                    dst_binding: 1,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    p_image_info: &image_infos[1] as *const _,
+                    ..Default::default()
+                },
+                vk::WriteDescriptorSet {
+                    dst_set: descriptor_set,
+                    dst_binding: 2,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    p_buffer_info: &buffer_info as *const _,
+                    ..Default::default()
+                },
+            ];
+            unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+            self.frames.push(TextureFrame {
+                descriptor_set,
+                feedback,
+                staging,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Requests the pages sampled by the previous use of `frame` which aren't resident, along
+    /// with their coarser levels and their neighbours read by the filtering at their borders
+    fn read_feedback(&mut self, frame: usize) {
+        self.frame_counter += 1;
+
+        let (regions_x, _) = self.regions();
+        // Safety: The frame is done and its feedback made visible to the host
+        let feedback = unsafe {
+            slice::from_raw_parts_mut(
+                self.frames[frame].feedback.memory_map as *mut u32,
+                self.page_table.len(),
+            )
+        };
+
+        let mut sampled = HashSet::new();
+        for (region, wanted_level) in feedback.iter_mut().enumerate() {
+            if *wanted_level == NO_REQUEST {
+                continue;
+            }
+            let level = (*wanted_level).min(self.pinned_level);
+            *wanted_level = NO_REQUEST;
+
+            let (x, y) = (region as u32 % regions_x, region as u32 / regions_x);
+            sampled.insert(PageKey {
+                level,
+                x: x >> level,
+                y: y >> level,
+            });
+        }
+
+        let mut needed = HashSet::new();
+        for key in sampled {
+            for level in key.level..self.pinned_level {
+                let paged_level = &self.levels[level as usize];
+                let shift = level - key.level;
+                for (dx, dy) in (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy))) {
+                    // The sampler repeats the texture
+                    let x = ((key.x >> shift) as i64 + dx).rem_euclid(paged_level.pages_x as i64);
+                    let y = ((key.y >> shift) as i64 + dy).rem_euclid(paged_level.pages_y as i64);
+                    needed.insert(PageKey {
+                        level,
+                        x: x as u32,
+                        y: y as u32,
+                    });
+                }
+            }
+        }
+
+        // The coarsest pages are requested first, so the texture refines progressively
+        let mut requests = vec![];
+        for key in needed {
+            match self.pages.get_mut(&key) {
+                Some(PageState::Resident { last_seen, .. }) => *last_seen = self.frame_counter,
+                Some(PageState::Loading) => (),
+                None => requests.push(key),
+            }
+        }
+        requests.sort_by_key(|key| std::cmp::Reverse(key.level));
+
+        for key in requests {
+            if self.pending >= MAX_PENDING_PAGES {
+                break;
+            }
+            self.pages.insert(key, PageState::Loading);
+            self.pending += 1;
+
+            let source = self.source.clone();
+            let sender = self.sender.clone();
+            let (x, y, width, height) = self.page_area(key);
+            rayon::spawn(move || {
+                let mut texels = vec![0; (width * height * 4) as usize];
+                source.read(key.level, x, y, width, height, &mut texels);
+                // The texture may have been destroyed in the meantime
+                let _ = sender.send((key, texels));
+            });
+        }
+    }
+
+    fn receive_pages(&mut self) {
+        while let Ok(page) = self.receiver.try_recv() {
+            self.pending -= 1;
+            self.loaded.push(page);
+        }
+        self.loaded.sort_by_key(|(key, _)| key.level);
+    }
+
+    /// Gives a slot of the pool to the loaded pages, evicting the least recently sampled pages if
+    /// needed. Returns the binds to apply and the pages to upload.
+    fn allocate_pages(
+        &mut self,
+        frame_count: usize,
+    ) -> (Vec<vk::SparseImageMemoryBind>, Vec<(PageKey, Vec<u8>)>) {
+        let mut binds = vec![];
+        let mut uploads = vec![];
+        // A page that hasn't been sampled for that long isn't sampled by a frame in flight anymore
+        let evictable_before = self.frame_counter.saturating_sub(2 * frame_count as u64);
+
+        // The loaded pages are sorted from the most detailed level, the coarsest are uploaded first
+        while uploads.len() < MAX_PAGE_UPLOADS_PER_FRAME {
+            let Some((key, texels)) = self.loaded.pop() else {
+                break;
+            };
+
+            let slot = match self.free_slots.pop() {
+                Some(slot) => slot,
+                None => {
+                    let evicted = self
+                        .pages
+                        .iter()
+                        .filter_map(|(key, state)| match state {
+                            PageState::Resident { last_seen, .. }
+                                if *last_seen < evictable_before =>
+                            {
+                                Some((*key, *last_seen))
+                            }
+                            _ => None,
+                        })
+                        .min_by_key(|(_, last_seen)| *last_seen);
+                    let Some((evicted, _)) = evicted else {
+                        // Every page is in use, the page waits for one to become evictable
+                        self.loaded.push((key, texels));
+                        break;
+                    };
+
+                    let Some(PageState::Resident { slot, .. }) = self.pages.remove(&evicted) else {
+                        unreachable!()
+                    };
+                    binds.push(self.page_bind(evicted, vk::DeviceMemory::null(), 0));
+                    slot
+                }
+            };
+
+            binds.push(self.page_bind(key, self.pool, slot));
+            self.pages.insert(
+                key,
+                PageState::Resident {
+                    slot,
+                    last_seen: self.frame_counter,
+                },
+            );
+            uploads.push((key, texels));
+        }
+
+        if !binds.is_empty() {
+            self.update_page_table();
+        }
+
+        (binds, uploads)
+    }
+
+    /// Computes the most detailed level that can be sampled in each region, i.e. the first level
+    /// from which the pages covering the region and its neighbours are resident down to the
+    /// pinned levels
+    fn update_page_table(&mut self) {
+        let (regions_x, regions_y) = self.regions();
+        let mut resident_levels = vec![self.pinned_level as u8; self.page_table.len()];
+        for y in 0..regions_y {
+            for x in 0..regions_x {
+                let mut level = self.pinned_level;
+                while level > 0 {
+                    let key = PageKey {
+                        level: level - 1,
+                        x: x >> (level - 1),
+                        y: y >> (level - 1),
+                    };
+                    if !matches!(self.pages.get(&key), Some(PageState::Resident { .. })) {
+                        break;
+                    }
+                    level -= 1;
+                }
+                resident_levels[(y * regions_x + x) as usize] = level as u8;
+            }
+        }
+
+        let mut page_table = resident_levels.clone();
+        for y in 0..regions_y {
+            for x in 0..regions_x {
+                let mut level = 0;
+                for (dx, dy) in (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy))) {
+                    let nx = (x as i64 + dx).rem_euclid(regions_x as i64) as u32;
+                    let ny = (y as i64 + dy).rem_euclid(regions_y as i64) as u32;
+                    level = level.max(resident_levels[(ny * regions_x + nx) as usize]);
+                }
+                page_table[(y * regions_x + x) as usize] = level;
+            }
+        }
+
+        if page_table != self.page_table {
+            self.page_table = page_table;
+            self.page_table_dirty = true;
+        }
+    }
+
+    /// Records the copy of `uploads` and of the page table if it changed. The previous draws may
+    /// still sample the texture, the copies wait for them.
+    unsafe fn record_uploads(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        uploads: &[(PageKey, Vec<u8>)],
+        arena: &FrameArena,
+    ) {
+        let staging = &self.frames[frame].staging;
+        let staging_ptr = staging.memory_map as *mut u8;
+
+        let mut copies = arena.vec();
+        let mut offset = 0;
+        for (key, texels) in uploads {
+            std::ptr::copy(texels.as_ptr(), staging_ptr.add(offset), texels.len());
+            copies.push(self.page_copy(*key, offset as u64));
+            offset += texels.len();
+        }
+
+        let mut barriers = arena.vec();
+        if !copies.is_empty() {
+            barriers.push(self.image);
+        }
+        let page_table_copy = if self.page_table_dirty {
+            std::ptr::copy(
+                self.page_table.as_ptr(),
+                staging_ptr.add(offset),
+                self.page_table.len(),
+            );
+            barriers.push(self.page_table_image.image);
+            self.page_table_dirty = false;
+            Some(self.page_table_copy(offset as u64))
+        } else {
+            None
+        };
+
+        let level_count = |image| {
+            if image == self.image {
+                self.mip_levels
+            } else {
+                1
+            }
+        };
+        let to_transfer = arena.collect(barriers.iter().map(|&image| {
+            Self::image_barrier(
+                image,
+                level_count(image),
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::SHADER_READ,
+                vk::AccessFlags::TRANSFER_WRITE,
+            )
+        }));
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_transfer,
+        );
+
+        if !copies.is_empty() {
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging.buffer,
+                self.image,
+                vk::ImageLayout::GENERAL,
+                &copies,
+            );
+        }
+        if let Some(page_table_copy) = page_table_copy {
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging.buffer,
+                self.page_table_image.image,
+                vk::ImageLayout::GENERAL,
+                &[page_table_copy],
+            );
+        }
+
+        let to_shader = arena.collect(barriers.iter().map(|&image| {
+            Self::image_barrier(
+                image,
+                level_count(image),
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+            )
+        }));
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_shader,
+        );
+    }
+
+    fn resident_count(&self) -> usize {
+        self.pages
+            .values()
+            .filter(|state| matches!(state, PageState::Resident { .. }))
+            .count()
+    }
+
+    /// Size of the page table, i.e. the pages of level 0
+    fn regions(&self) -> (u32, u32) {
+        self.levels
+            .first()
+            .map_or((1, 1), |level| (level.pages_x, level.pages_y))
+    }
+
+    fn level_extent(&self, level: u32) -> (u32, u32) {
+        let (width, height) = self.source.extent();
+        ((width >> level).max(1), (height >> level).max(1))
+    }
+
+    /// Returns the area of the level covered by the page, the pages at the edges being smaller
+    fn page_area(&self, key: PageKey) -> (u32, u32, u32, u32) {
+        let paged_level = &self.levels[key.level as usize];
+        let x = key.x * self.page_extent.width;
+        let y = key.y * self.page_extent.height;
+        (
+            x,
+            y,
+            self.page_extent.width.min(paged_level.width - x),
+            self.page_extent.height.min(paged_level.height - y),
+        )
+    }
+
+    fn read_page(&self, key: PageKey) -> Vec<u8> {
+        let (x, y, width, height) = self.page_area(key);
+        let mut texels = vec![0; (width * height * 4) as usize];
+        self.source
+            .read(key.level, x, y, width, height, &mut texels);
+        texels
+    }
+
+    /// Binds `key` to the `slot` of `memory`, unbinds it if `memory` is null
+    fn page_bind(
+        &self,
+        key: PageKey,
+        memory: vk::DeviceMemory,
+        slot: u32,
+    ) -> vk::SparseImageMemoryBind {
+        let (x, y, width, height) = self.page_area(key);
+        vk::SparseImageMemoryBind {
+            subresource: vk::ImageSubresource {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: key.level,
+                array_layer: 0,
+            },
+            offset: vk::Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            },
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            memory,
+            memory_offset: slot as u64 * self.page_size,
+            ..Default::default()
+        }
+    }
+
+    fn page_copy(&self, key: PageKey, buffer_offset: u64) -> vk::BufferImageCopy {
+        let (x, y, width, height) = self.page_area(key);
+        Self::copy_region(key.level, x, y, width, height, buffer_offset)
+    }
+
+    fn page_table_copy(&self, buffer_offset: u64) -> vk::BufferImageCopy {
+        let (regions_x, regions_y) = self.regions();
+        Self::copy_region(0, 0, 0, regions_x, regions_y, buffer_offset)
+    }
+
+    fn copy_region(
+        level: u32,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        buffer_offset: u64,
+    ) -> vk::BufferImageCopy {
+        vk::BufferImageCopy {
+            buffer_offset,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: level,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            },
+            image_extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Barrier on every level of `image`, left in the general layout
+    fn image_barrier(
+        image: vk::Image,
+        level_count: u32,
+        old_layout: vk::ImageLayout,
+        src_access_mask: vk::AccessFlags,
+        dst_access_mask: vk::AccessFlags,
+    ) -> vk::ImageMemoryBarrier<'static> {
+        vk::ImageMemoryBarrier {
+            src_access_mask,
+            dst_access_mask,
+            old_layout,
+            new_layout: vk::ImageLayout::GENERAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        }
+    }
+
+    unsafe fn destroy(self, device: &Device) {
+        for frame in self.frames.iter() {
+            for buffer in [&frame.feedback, &frame.staging] {
+                handle_registry::unregister(buffer.buffer);
+                device.destroy_buffer(buffer.buffer, None);
+                handle_registry::unregister(buffer.memory);
+                device.free_memory(buffer.memory, None);
+            }
+        }
+        handle_registry::unregister(self.descriptor_pool);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+
+        handle_registry::unregister(self.page_table_view);
+        device.destroy_image_view(self.page_table_view, None);
+        handle_registry::unregister(self.page_table_image.image);
+        device.destroy_image(self.page_table_image.image, None);
+        handle_registry::unregister(self.page_table_image.memory);
+        device.free_memory(self.page_table_image.memory, None);
+
+        handle_registry::unregister(self.view);
+        device.destroy_image_view(self.view, None);
+        handle_registry::unregister(self.image);
+        device.destroy_image(self.image, None);
+        for memory in [Some(self.pool), self.mip_tail].into_iter().flatten() {
+            handle_registry::unregister(memory);
+            device.free_memory(memory, None);
+        }
+    }
+}